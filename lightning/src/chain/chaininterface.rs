@@ -5,7 +5,7 @@
 //! disconnections, transaction broadcasting, and feerate information requests.
 
 use bitcoin::blockdata::block::{Block, BlockHeader};
-use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::blockdata::transaction::{Transaction, TxOut, OutPoint};
 use bitcoin::blockdata::script::Script;
 use bitcoin::blockdata::constants::genesis_block;
 use bitcoin::util::hash::BitcoinHash;
@@ -47,11 +47,12 @@ pub trait ChainWatchInterface: Sync + Send {
 	/// Indicates that a listener needs to see all transactions.
 	fn watch_all_txn(&self);
 
-	/// Gets the script and value in satoshis for a given unspent transaction output given a
-	/// short_channel_id (aka unspent_tx_output_identier). For BTC/tBTC channels the top three
-	/// bytes are the block height, the next 3 the transaction index within the block, and the
-	/// final two the output within the transaction.
-	fn get_chain_utxo(&self, genesis_hash: BlockHash, unspent_tx_output_identifier: u64) -> Result<(Script, u64), ChainError>;
+	/// Gets the txid of the transaction containing a given unspent transaction output, along with
+	/// the output's script and value in satoshis, given a short_channel_id (aka
+	/// unspent_tx_output_identier). For BTC/tBTC channels the top three bytes are the block
+	/// height, the next 3 the transaction index within the block, and the final two the output
+	/// within the transaction.
+	fn get_chain_utxo(&self, genesis_hash: BlockHash, unspent_tx_output_identifier: u64) -> Result<(Txid, Script, u64), ChainError>;
 
 	/// Gets the list of transaction indices within a given block that the ChainWatchInterface is
 	/// watching for.
@@ -69,6 +70,39 @@ pub trait BroadcasterInterface: Sync + Send {
 	fn broadcast_transaction(&self, tx: &Transaction);
 }
 
+/// An unspent output controlled by the user's on-chain wallet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Utxo {
+	/// The outpoint of the unspent output.
+	pub outpoint: OutPoint,
+	/// The output itself, used to compute the fee of transactions spending it.
+	pub output: TxOut,
+	/// The weight of the script_sig and witness which will be required to spend the output, once
+	/// signed, excluding the rest of the input.
+	pub satisfaction_weight: u64,
+}
+
+/// An interface to the user's on-chain wallet, used to fund the child-pays-for-parent
+/// transactions which bump the fee of our commitment transactions via their anchor output in
+/// channels which negotiated option_anchor_outputs.
+///
+/// Note that all of the functions implemented here *must* be reentrant-safe (they're called from
+/// inside the library in response to ChainListener events).
+pub trait UtxoPool: Sync + Send {
+	/// Selects wallet outputs with a total value of at least target_value_sat, which will be
+	/// spent by a transaction bumping the fee of one of our commitment transactions.
+	///
+	/// Outputs which were previously returned for the same channel may be returned again, as a
+	/// bumped transaction replaces the one it bumps. An empty Vec may be returned if the wallet
+	/// cannot cover target_value_sat, in which case the commitment transaction is broadcast alone.
+	fn select_utxos(&self, target_value_sat: u64) -> Vec<Utxo>;
+	/// Gets the script to which any change left over after paying fees is sent.
+	fn get_change_script(&self) -> Script;
+	/// Signs the inputs of the given transaction which spend outputs previously returned by
+	/// select_utxos, leaving all other inputs as they are.
+	fn sign_tx(&self, tx: &mut Transaction) -> Result<(), ()>;
+}
+
 /// A trait indicating a desire to listen for events from the chain
 pub trait ChainListener: Sync + Send {
 	/// Notifies a listener that a block was connected.
@@ -354,7 +388,7 @@ impl ChainWatchInterface for ChainWatchInterfaceUtil {
 		}
 	}
 
-	fn get_chain_utxo(&self, genesis_hash: BlockHash, _unspent_tx_output_identifier: u64) -> Result<(Script, u64), ChainError> {
+	fn get_chain_utxo(&self, genesis_hash: BlockHash, _unspent_tx_output_identifier: u64) -> Result<(Txid, Script, u64), ChainError> {
 		if genesis_hash != genesis_block(self.network).header.bitcoin_hash() {
 			return Err(ChainError::NotWatched);
 		}
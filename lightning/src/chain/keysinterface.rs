@@ -109,6 +109,27 @@ pub enum SpendableOutputDescriptor {
 		/// The channel keys state used to proceed to derivation of signing key. Must
 		/// be pass to KeysInterface::derive_channel_keys.
 		key_derivation_params: (u64, u64),
+	},
+	/// An output to a P2WSH script which can be spent by our payment key (ie the private key which
+	/// corresponds to the public key in ChannelKeys::pubkeys().payment_point) after one
+	/// confirmation.
+	///
+	/// The witness in the spending input should be:
+	/// <BIP 143 signature> <witnessScript>
+	/// where the witness script may be regenerated by passing our payment point to
+	/// chan_utils::get_to_countersignatory_with_anchors_redeemscript. The nSequence field in the
+	/// spending input must be set to 1.
+	///
+	/// These are the equivalent of StaticOutputRemotePayment for channels which negotiated
+	/// option_anchor_outputs.
+	StaticOutputRemotePaymentWithAnchors {
+		/// The outpoint which is spendable
+		outpoint: OutPoint,
+		/// The output which is reference by the given outpoint
+		output: TxOut,
+		/// The channel keys state used to proceed to derivation of signing key. Must
+		/// be pass to KeysInterface::derive_channel_keys.
+		key_derivation_params: (u64, u64),
 	}
 }
 
@@ -137,6 +158,13 @@ impl Writeable for SpendableOutputDescriptor {
 				key_derivation_params.0.write(writer)?;
 				key_derivation_params.1.write(writer)?;
 			},
+			&SpendableOutputDescriptor::StaticOutputRemotePaymentWithAnchors { ref outpoint, ref output, ref key_derivation_params } => {
+				3u8.write(writer)?;
+				outpoint.write(writer)?;
+				output.write(writer)?;
+				key_derivation_params.0.write(writer)?;
+				key_derivation_params.1.write(writer)?;
+			},
 		}
 		Ok(())
 	}
@@ -162,6 +190,11 @@ impl Readable for SpendableOutputDescriptor {
 				output: Readable::read(reader)?,
 				key_derivation_params: (Readable::read(reader)?, Readable::read(reader)?),
 			}),
+			3u8 => Ok(SpendableOutputDescriptor::StaticOutputRemotePaymentWithAnchors {
+				outpoint: Readable::read(reader)?,
+				output: Readable::read(reader)?,
+				key_derivation_params: (Readable::read(reader)?, Readable::read(reader)?),
+			}),
 			_ => Err(DecodeError::InvalidValue),
 		}
 	}
@@ -206,12 +239,15 @@ pub trait ChannelKeys : Send+Clone {
 
 	/// Create a signature for a remote commitment transaction and associated HTLC transactions.
 	///
+	/// If opt_anchors is set, the channel negotiated option_anchor_outputs, and the HTLC
+	/// transactions must be signed with SIGHASH_SINGLE | SIGHASH_ANYONECANPAY.
+	///
 	/// Note that if signing fails or is rejected, the channel will be force-closed.
 	//
 	// TODO: Document the things someone using this interface should enforce before signing.
 	// TODO: Add more input vars to enable better checking (preferably removing commitment_tx and
 	// making the callee generate it via some util function we expose)!
-	fn sign_remote_commitment<T: secp256k1::Signing + secp256k1::Verification>(&self, feerate_per_kw: u32, commitment_tx: &Transaction, keys: &TxCreationKeys, htlcs: &[&HTLCOutputInCommitment], to_self_delay: u16, secp_ctx: &Secp256k1<T>) -> Result<(Signature, Vec<Signature>), ()>;
+	fn sign_remote_commitment<T: secp256k1::Signing + secp256k1::Verification>(&self, feerate_per_kw: u32, commitment_tx: &Transaction, keys: &TxCreationKeys, htlcs: &[&HTLCOutputInCommitment], to_self_delay: u16, opt_anchors: bool, secp_ctx: &Secp256k1<T>) -> Result<(Signature, Vec<Signature>), ()>;
 
 	/// Create a signature for a local commitment transaction. This will only ever be called with
 	/// the same local_commitment_tx (or a copy thereof), though there are currently no guarantees
@@ -265,7 +301,10 @@ pub trait ChannelKeys : Send+Clone {
 	/// on_remote_tx_csv is the relative lock-time that that our counterparty would have to set on
 	/// their transaction were they to spend the same output. It is included in the witness script
 	/// and thus committed to in the BIP 143 signature.
-	fn sign_justice_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, justice_tx: &Transaction, input: usize, amount: u64, per_commitment_key: &SecretKey, htlc: &Option<HTLCOutputInCommitment>, on_remote_tx_csv: u16, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
+	///
+	/// opt_anchors indicates whether the channel negotiated option_anchor_outputs, which changes
+	/// the format of HTLC witness scripts.
+	fn sign_justice_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, justice_tx: &Transaction, input: usize, amount: u64, per_commitment_key: &SecretKey, htlc: &Option<HTLCOutputInCommitment>, on_remote_tx_csv: u16, opt_anchors: bool, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
 
 	/// Create a signature for a claiming transaction for a HTLC output on a remote commitment
 	/// transaction, either offered or received.
@@ -284,7 +323,19 @@ pub trait ChannelKeys : Send+Clone {
 	/// detected onchain. It has been generated by our counterparty and is used to derive
 	/// channel state keys, which are then included in the witness script and committed to in the
 	/// BIP 143 signature.
-	fn sign_remote_htlc_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, htlc_tx: &Transaction, input: usize, amount: u64, per_commitment_point: &PublicKey, htlc: &HTLCOutputInCommitment, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
+	///
+	/// opt_anchors indicates whether the channel negotiated option_anchor_outputs, which changes
+	/// the format of HTLC witness scripts.
+	fn sign_remote_htlc_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, htlc_tx: &Transaction, input: usize, amount: u64, per_commitment_point: &PublicKey, htlc: &HTLCOutputInCommitment, opt_anchors: bool, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
+
+	/// Create a signature for the input at index `input` of a transaction spending the anchor
+	/// output of one of our local commitment transactions, in order to bump its fee via
+	/// child-pays-for-parent. Only called for channels which negotiated option_anchor_outputs.
+	///
+	/// The anchor output is encumbered by chan_utils::get_anchor_redeemscript with our funding
+	/// pubkey and has a value of chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI, which are committed to
+	/// in the BIP 143 signature.
+	fn sign_local_anchor_input<T: secp256k1::Signing>(&self, anchor_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
 
 	/// Create a signature for a (proposed) closing transaction.
 	///
@@ -292,6 +343,14 @@ pub trait ChannelKeys : Send+Clone {
 	/// chosen to forgo their output as dust.
 	fn sign_closing_transaction<T: secp256k1::Signing>(&self, closing_tx: &Transaction, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
 
+	/// Create a signature for the input at index `input` of a splice transaction, which spends the
+	/// channel's current funding output into a new one.
+	///
+	/// The funding output is encumbered by the same 2-of-2 multisig script as for a closing
+	/// transaction and has the current channel value, which are committed to in the BIP 143
+	/// signature.
+	fn sign_splice_funding_input<T: secp256k1::Signing>(&self, splice_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
+
 	/// Signs a channel announcement message with our funding key, proving it comes from one
 	/// of the channel participants.
 	///
@@ -305,6 +364,11 @@ pub trait ChannelKeys : Send+Clone {
 	///
 	/// Will be called before any signatures are applied.
 	fn set_remote_channel_pubkeys(&mut self, channel_points: &ChannelPublicKeys);
+
+	/// Set the total value of the channel. This is called for dual-funded channels, whose value is
+	/// only known once our counterparty has told us its contribution, before any signatures are
+	/// applied, and once a splice of the channel's funding output is locked in.
+	fn set_channel_value_satoshis(&mut self, channel_value_satoshis: u64);
 }
 
 /// A trait to describe an object which can get user secrets and key material.
@@ -327,6 +391,13 @@ pub trait KeysInterface: Send + Sync {
 	/// transaction is created, at which point they will use the outpoint in the funding
 	/// transaction.
 	fn get_channel_id(&self) -> [u8; 32];
+	/// Get 32 secure random bytes, eg for use as the preimage of a spontaneous payment. These
+	/// MUST be unique even if you restarted with some stale data!
+	fn get_secure_random_bytes(&self) -> [u8; 32];
+	/// Get a secret from which the keys used to create and verify the payment secrets (and
+	/// preimages) of ChannelManager::create_inbound_payment are derived. This MUST be the same
+	/// every time you restart, or payments created before the restart will be failed.
+	fn get_inbound_payment_key_material(&self) -> [u8; 32];
 }
 
 #[derive(Clone)]
@@ -408,7 +479,7 @@ impl ChannelKeys for InMemoryChannelKeys {
 	fn pubkeys(&self) -> &ChannelPublicKeys { &self.local_channel_pubkeys }
 	fn key_derivation_params(&self) -> (u64, u64) { self.key_derivation_params }
 
-	fn sign_remote_commitment<T: secp256k1::Signing + secp256k1::Verification>(&self, feerate_per_kw: u32, commitment_tx: &Transaction, keys: &TxCreationKeys, htlcs: &[&HTLCOutputInCommitment], to_self_delay: u16, secp_ctx: &Secp256k1<T>) -> Result<(Signature, Vec<Signature>), ()> {
+	fn sign_remote_commitment<T: secp256k1::Signing + secp256k1::Verification>(&self, feerate_per_kw: u32, commitment_tx: &Transaction, keys: &TxCreationKeys, htlcs: &[&HTLCOutputInCommitment], to_self_delay: u16, opt_anchors: bool, secp_ctx: &Secp256k1<T>) -> Result<(Signature, Vec<Signature>), ()> {
 		if commitment_tx.input.len() != 1 { return Err(()); }
 
 		let funding_pubkey = PublicKey::from_secret_key(secp_ctx, &self.funding_key);
@@ -423,9 +494,9 @@ impl ChannelKeys for InMemoryChannelKeys {
 		let mut htlc_sigs = Vec::with_capacity(htlcs.len());
 		for ref htlc in htlcs {
 			if let Some(_) = htlc.transaction_output_index {
-				let htlc_tx = chan_utils::build_htlc_transaction(&commitment_txid, feerate_per_kw, to_self_delay, htlc, &keys.a_delayed_payment_key, &keys.revocation_key);
-				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &keys);
-				let htlc_sighash = hash_to_message!(&bip143::SighashComponents::new(&htlc_tx).sighash_all(&htlc_tx.input[0], &htlc_redeemscript, htlc.amount_msat / 1000)[..]);
+				let htlc_tx = chan_utils::build_htlc_transaction(&commitment_txid, feerate_per_kw, to_self_delay, htlc, &keys.a_delayed_payment_key, &keys.revocation_key, opt_anchors);
+				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &keys, opt_anchors);
+				let htlc_sighash = hash_to_message!(&chan_utils::htlc_tx_sighash(&htlc_tx, 0, &htlc_redeemscript, htlc.amount_msat / 1000, chan_utils::htlc_counterparty_sighash_type(opt_anchors))[..]);
 				let our_htlc_key = match chan_utils::derive_private_key(&secp_ctx, &keys.per_commitment_point, &self.htlc_base_key) {
 					Ok(s) => s,
 					Err(_) => return Err(()),
@@ -458,7 +529,7 @@ impl ChannelKeys for InMemoryChannelKeys {
 		local_commitment_tx.get_htlc_sigs(&self.htlc_base_key, local_csv, secp_ctx)
 	}
 
-	fn sign_justice_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, justice_tx: &Transaction, input: usize, amount: u64, per_commitment_key: &SecretKey, htlc: &Option<HTLCOutputInCommitment>, on_remote_tx_csv: u16, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+	fn sign_justice_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, justice_tx: &Transaction, input: usize, amount: u64, per_commitment_key: &SecretKey, htlc: &Option<HTLCOutputInCommitment>, on_remote_tx_csv: u16, opt_anchors: bool, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
 		let revocation_key = match chan_utils::derive_private_revocation_key(&secp_ctx, &per_commitment_key, &self.revocation_base_key) {
 			Ok(revocation_key) => revocation_key,
 			Err(_) => return Err(())
@@ -477,7 +548,7 @@ impl ChannelKeys for InMemoryChannelKeys {
 				Ok(local_htlcpubkey) => local_htlcpubkey,
 				Err(_) => return Err(())
 			};
-			chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &remote_htlcpubkey, &local_htlcpubkey, &revocation_pubkey)
+			chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &remote_htlcpubkey, &local_htlcpubkey, &revocation_pubkey, opt_anchors)
 		} else {
 			let remote_delayedpubkey = match chan_utils::derive_public_key(&secp_ctx, &per_commitment_point, &self.remote_pubkeys().delayed_payment_basepoint) {
 				Ok(remote_delayedpubkey) => remote_delayedpubkey,
@@ -490,12 +561,12 @@ impl ChannelKeys for InMemoryChannelKeys {
 		return Ok(secp_ctx.sign(&sighash, &revocation_key))
 	}
 
-	fn sign_remote_htlc_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, htlc_tx: &Transaction, input: usize, amount: u64, per_commitment_point: &PublicKey, htlc: &HTLCOutputInCommitment, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+	fn sign_remote_htlc_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, htlc_tx: &Transaction, input: usize, amount: u64, per_commitment_point: &PublicKey, htlc: &HTLCOutputInCommitment, opt_anchors: bool, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
 		if let Ok(htlc_key) = chan_utils::derive_private_key(&secp_ctx, &per_commitment_point, &self.htlc_base_key) {
 			let witness_script = if let Ok(revocation_pubkey) = chan_utils::derive_public_revocation_key(&secp_ctx, &per_commitment_point, &self.pubkeys().revocation_basepoint) {
 				if let Ok(remote_htlcpubkey) = chan_utils::derive_public_key(&secp_ctx, &per_commitment_point, &self.remote_pubkeys().htlc_basepoint) {
 					if let Ok(local_htlcpubkey) = chan_utils::derive_public_key(&secp_ctx, &per_commitment_point, &self.pubkeys().htlc_basepoint) {
-						chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &remote_htlcpubkey, &local_htlcpubkey, &revocation_pubkey)
+						chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &remote_htlcpubkey, &local_htlcpubkey, &revocation_pubkey, opt_anchors)
 					} else { return Err(()) }
 				} else { return Err(()) }
 			} else { return Err(()) };
@@ -506,6 +577,14 @@ impl ChannelKeys for InMemoryChannelKeys {
 		Err(())
 	}
 
+	fn sign_local_anchor_input<T: secp256k1::Signing>(&self, anchor_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		if input >= anchor_tx.input.len() { return Err(()); }
+		let anchor_redeemscript = chan_utils::get_anchor_redeemscript(&self.pubkeys().funding_pubkey);
+		let sighash = hash_to_message!(&bip143::SighashComponents::new(anchor_tx)
+			.sighash_all(&anchor_tx.input[input], &anchor_redeemscript, chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI)[..]);
+		Ok(secp_ctx.sign(&sighash, &self.funding_key))
+	}
+
 	fn sign_closing_transaction<T: secp256k1::Signing>(&self, closing_tx: &Transaction, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
 		if closing_tx.input.len() != 1 { return Err(()); }
 		if closing_tx.input[0].witness.len() != 0 { return Err(()); }
@@ -520,6 +599,18 @@ impl ChannelKeys for InMemoryChannelKeys {
 		Ok(secp_ctx.sign(&sighash, &self.funding_key))
 	}
 
+	fn sign_splice_funding_input<T: secp256k1::Signing>(&self, splice_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		if input >= splice_tx.input.len() { return Err(()); }
+
+		let remote_channel_pubkeys = self.remote_channel_pubkeys.as_ref().expect("must set remote channel pubkeys before signing");
+		let funding_pubkey = PublicKey::from_secret_key(secp_ctx, &self.funding_key);
+		let channel_funding_redeemscript = make_funding_redeemscript(&funding_pubkey, &remote_channel_pubkeys.funding_pubkey);
+
+		let sighash = hash_to_message!(&bip143::SighashComponents::new(splice_tx)
+			.sighash_all(&splice_tx.input[input], &channel_funding_redeemscript, self.channel_value_satoshis)[..]);
+		Ok(secp_ctx.sign(&sighash, &self.funding_key))
+	}
+
 	fn sign_channel_announcement<T: secp256k1::Signing>(&self, msg: &msgs::UnsignedChannelAnnouncement, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
 		let msghash = hash_to_message!(&Sha256dHash::hash(&msg.encode()[..])[..]);
 		Ok(secp_ctx.sign(&msghash, &self.funding_key))
@@ -529,6 +620,10 @@ impl ChannelKeys for InMemoryChannelKeys {
 		assert!(self.remote_channel_pubkeys.is_none(), "Already set remote channel pubkeys");
 		self.remote_channel_pubkeys = Some(channel_pubkeys.clone());
 	}
+
+	fn set_channel_value_satoshis(&mut self, channel_value_satoshis: u64) {
+		self.channel_value_satoshis = channel_value_satoshis;
+	}
 }
 
 impl Writeable for InMemoryChannelKeys {
@@ -588,6 +683,8 @@ impl Readable for InMemoryChannelKeys {
 /// ChannelMonitor closes may use seed/1'
 /// Cooperative closes may use seed/2'
 /// The two close keys may be needed to claim on-chain funds!
+/// Inbound payments created with ChannelManager::create_inbound_payment are verified using
+/// seed/7'
 pub struct KeysManager {
 	secp_ctx: Secp256k1<secp256k1::SignOnly>,
 	node_secret: SecretKey,
@@ -599,6 +696,9 @@ pub struct KeysManager {
 	session_child_index: AtomicUsize,
 	channel_id_master_key: ExtendedPrivKey,
 	channel_id_child_index: AtomicUsize,
+	rand_bytes_master_key: ExtendedPrivKey,
+	rand_bytes_child_index: AtomicUsize,
+	inbound_payment_key: SecretKey,
 
 	seed: [u8; 32],
 	starting_time_secs: u64,
@@ -646,6 +746,8 @@ impl KeysManager {
 				let channel_master_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(3).unwrap()).expect("Your RNG is busted");
 				let session_master_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(4).unwrap()).expect("Your RNG is busted");
 				let channel_id_master_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(5).unwrap()).expect("Your RNG is busted");
+				let rand_bytes_master_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(6).unwrap()).expect("Your RNG is busted");
+				let inbound_payment_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(7).unwrap()).expect("Your RNG is busted").private_key.key;
 
 				KeysManager {
 					secp_ctx,
@@ -658,6 +760,9 @@ impl KeysManager {
 					session_child_index: AtomicUsize::new(0),
 					channel_id_master_key,
 					channel_id_child_index: AtomicUsize::new(0),
+					rand_bytes_master_key,
+					rand_bytes_child_index: AtomicUsize::new(0),
+					inbound_payment_key,
 
 					seed: *seed,
 					starting_time_secs,
@@ -775,4 +880,20 @@ impl KeysInterface for KeysManager {
 
 		Sha256::from_engine(sha).into_inner()
 	}
+
+	fn get_secure_random_bytes(&self) -> [u8; 32] {
+		let mut sha = self.derive_unique_start();
+
+		let child_ix = self.rand_bytes_child_index.fetch_add(1, Ordering::AcqRel);
+		let child_privkey = self.rand_bytes_master_key.ckd_priv(&self.secp_ctx, ChildNumber::from_hardened_idx(child_ix as u32).expect("key space exhausted")).expect("Your RNG is busted");
+		sha.input(&child_privkey.private_key.key[..]);
+
+		Sha256::from_engine(sha).into_inner()
+	}
+
+	fn get_inbound_payment_key_material(&self) -> [u8; 32] {
+		let mut key_material = [0; 32];
+		key_material.copy_from_slice(&self.inbound_payment_key[..]);
+		key_material
+	}
 }
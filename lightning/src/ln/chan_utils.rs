@@ -12,11 +12,11 @@ use bitcoin::util::bip143;
 use bitcoin::hashes::{Hash, HashEngine};
 use bitcoin::hashes::sha256::Hash as Sha256;
 use bitcoin::hashes::ripemd160::Hash as Ripemd160;
-use bitcoin::hash_types::{Txid, PubkeyHash};
+use bitcoin::hash_types::{Txid, PubkeyHash, SigHash};
 
 use ln::channelmanager::{PaymentHash, PaymentPreimage};
 use ln::msgs::DecodeError;
-use util::ser::{Readable, Writeable, Writer, WriterWriteAdaptor};
+use util::ser::{Readable, ReadableArgs, Writeable, Writer, WriterWriteAdaptor};
 use util::byte_utils;
 
 use bitcoin::secp256k1::key::{SecretKey, PublicKey};
@@ -29,6 +29,26 @@ const MAX_ALLOC_SIZE: usize = 64*1024;
 
 pub(super) const HTLC_SUCCESS_TX_WEIGHT: u64 = 703;
 pub(super) const HTLC_TIMEOUT_TX_WEIGHT: u64 = 663;
+const HTLC_SUCCESS_ANCHOR_TX_WEIGHT: u64 = 706;
+const HTLC_TIMEOUT_ANCHOR_TX_WEIGHT: u64 = 666;
+
+/// The value, in satoshis, of each of the two anchor outputs included in commitment transactions
+/// of channels which negotiated option_anchor_outputs.
+pub const ANCHOR_OUTPUT_VALUE_SATOSHI: u64 = 330;
+
+/// Gets the weight of an HTLC-Success transaction, which depends on whether the channel uses
+/// anchor outputs.
+#[inline]
+pub(crate) fn htlc_success_tx_weight(opt_anchors: bool) -> u64 {
+	if opt_anchors { HTLC_SUCCESS_ANCHOR_TX_WEIGHT } else { HTLC_SUCCESS_TX_WEIGHT }
+}
+
+/// Gets the weight of an HTLC-Timeout transaction, which depends on whether the channel uses
+/// anchor outputs.
+#[inline]
+pub(crate) fn htlc_timeout_tx_weight(opt_anchors: bool) -> u64 {
+	if opt_anchors { HTLC_TIMEOUT_ANCHOR_TX_WEIGHT } else { HTLC_TIMEOUT_TX_WEIGHT }
+}
 
 #[derive(PartialEq)]
 pub(crate) enum HTLCType {
@@ -38,15 +58,26 @@ pub(crate) enum HTLCType {
 
 impl HTLCType {
 	/// Check if a given tx witnessScript len matchs one of a pre-signed HTLC
-	pub(crate) fn scriptlen_to_htlctype(witness_script_len: usize) ->  Option<HTLCType> {
-		if witness_script_len == 133 {
+	pub(crate) fn scriptlen_to_htlctype(witness_script_len: usize, opt_anchors: bool) ->  Option<HTLCType> {
+		// Anchor outputs HTLC scripts carry an additional `1 OP_CSV OP_DROP`
+		let anchors_len = if opt_anchors { 3 } else { 0 };
+		if witness_script_len == 133 + anchors_len {
 			Some(HTLCType::OfferedHTLC)
-		} else if witness_script_len >= 136 && witness_script_len <= 139 {
+		} else if witness_script_len >= 136 + anchors_len && witness_script_len <= 139 + anchors_len {
 			Some(HTLCType::AcceptedHTLC)
 		} else {
 			None
 		}
 	}
+
+	/// Check if a given tx witnessScript matchs one of a pre-signed HTLC, with or without anchor
+	/// outputs
+	pub(crate) fn witness_script_to_htlctype(witness_script: &[u8]) -> Option<HTLCType> {
+		// Anchor outputs HTLC scripts end in `1 OP_CSV OP_DROP OP_ENDIF` rather than
+		// `OP_ENDIF OP_ENDIF`
+		let opt_anchors = witness_script.ends_with(&[opcodes::all::OP_PUSHNUM_1.into_u8(), opcodes::all::OP_CSV.into_u8(), opcodes::all::OP_DROP.into_u8(), opcodes::all::OP_ENDIF.into_u8()]);
+		Self::scriptlen_to_htlctype(witness_script.len(), opt_anchors)
+	}
 }
 
 // Various functions for key derivation and transaction creation for use within channels. Primarily
@@ -374,9 +405,9 @@ impl_writeable!(HTLCOutputInCommitment, 1 + 8 + 4 + 32 + 5, {
 });
 
 #[inline]
-pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommitment, a_htlc_key: &PublicKey, b_htlc_key: &PublicKey, revocation_key: &PublicKey) -> Script {
+pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommitment, a_htlc_key: &PublicKey, b_htlc_key: &PublicKey, revocation_key: &PublicKey, opt_anchors: bool) -> Script {
 	let payment_hash160 = Ripemd160::hash(&htlc.payment_hash.0[..]).into_inner();
-	if htlc.offered {
+	let builder = if htlc.offered {
 		Builder::new().push_opcode(opcodes::all::OP_DUP)
 		              .push_opcode(opcodes::all::OP_HASH160)
 		              .push_slice(&PubkeyHash::hash(&revocation_key.serialize())[..])
@@ -402,8 +433,6 @@ pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommit
 		              .push_opcode(opcodes::all::OP_EQUALVERIFY)
 		              .push_opcode(opcodes::all::OP_CHECKSIG)
 		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .into_script()
 	} else {
 		Builder::new().push_opcode(opcodes::all::OP_DUP)
 		              .push_opcode(opcodes::all::OP_HASH160)
@@ -433,16 +462,84 @@ pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommit
 		              .push_opcode(opcodes::all::OP_DROP)
 		              .push_opcode(opcodes::all::OP_CHECKSIG)
 		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .into_script()
-	}
+	};
+	// With anchor outputs, the non-revocation paths are delayed by one block so that they can't be
+	// used to pin the commitment transaction in the mempool.
+	let builder = if opt_anchors {
+		builder.push_int(1)
+		       .push_opcode(opcodes::all::OP_CSV)
+		       .push_opcode(opcodes::all::OP_DROP)
+	} else { builder };
+	builder.push_opcode(opcodes::all::OP_ENDIF).into_script()
 }
 
 /// note here that 'a_revocation_key' is generated using b_revocation_basepoint and a's
 /// commitment secret. 'htlc' does *not* need to have its previous_output_index filled.
 #[inline]
-pub fn get_htlc_redeemscript(htlc: &HTLCOutputInCommitment, keys: &TxCreationKeys) -> Script {
-	get_htlc_redeemscript_with_explicit_keys(htlc, &keys.a_htlc_key, &keys.b_htlc_key, &keys.revocation_key)
+pub fn get_htlc_redeemscript(htlc: &HTLCOutputInCommitment, keys: &TxCreationKeys, opt_anchors: bool) -> Script {
+	get_htlc_redeemscript_with_explicit_keys(htlc, &keys.a_htlc_key, &keys.b_htlc_key, &keys.revocation_key, opt_anchors)
+}
+
+/// Gets the witness script for an anchor output, spendable by the given funding key or by anyone
+/// once the commitment transaction has 16 confirmations.
+pub fn get_anchor_redeemscript(funding_pubkey: &PublicKey) -> Script {
+	Builder::new().push_slice(&funding_pubkey.serialize()[..])
+	              .push_opcode(opcodes::all::OP_CHECKSIG)
+	              .push_opcode(opcodes::all::OP_IFDUP)
+	              .push_opcode(opcodes::all::OP_NOTIF)
+	              .push_int(16)
+	              .push_opcode(opcodes::all::OP_CSV)
+	              .push_opcode(opcodes::all::OP_ENDIF)
+	              .into_script()
+}
+
+/// Gets the witness script for the to_remote output of a commitment transaction of a channel which
+/// negotiated option_anchor_outputs, spendable by the given payment key after one confirmation.
+pub fn get_to_countersignatory_with_anchors_redeemscript(payment_point: &PublicKey) -> Script {
+	Builder::new().push_slice(&payment_point.serialize()[..])
+	              .push_opcode(opcodes::all::OP_CHECKSIGVERIFY)
+	              .push_int(1)
+	              .push_opcode(opcodes::all::OP_CSV)
+	              .into_script()
+}
+
+/// Gets the sighash type with which the counterparty signs our HTLC transactions. With anchor
+/// outputs, these are signed with SIGHASH_SINGLE | SIGHASH_ANYONECANPAY so that fee-paying inputs
+/// and outputs may be added before broadcast.
+#[inline]
+pub fn htlc_counterparty_sighash_type(opt_anchors: bool) -> SigHashType {
+	if opt_anchors { SigHashType::SinglePlusAnyoneCanPay } else { SigHashType::All }
+}
+
+/// Computes the BIP 143 sighash of the given HTLC transaction input for the given sighash type,
+/// which must be either SIGHASH_ALL or SIGHASH_SINGLE | SIGHASH_ANYONECANPAY.
+pub fn htlc_tx_sighash(htlc_tx: &Transaction, input: usize, htlc_redeemscript: &Script, amount: u64, sighash_type: SigHashType) -> SigHash {
+	match sighash_type {
+		SigHashType::All => bip143::SighashComponents::new(htlc_tx).sighash_all(&htlc_tx.input[input], htlc_redeemscript, amount),
+		SigHashType::SinglePlusAnyoneCanPay => {
+			let mut enc = SigHash::engine();
+			htlc_tx.version.consensus_encode(&mut enc).unwrap();
+			// With ANYONECANPAY, hashPrevouts and hashSequence are zero
+			[0u8; 32].consensus_encode(&mut enc).unwrap();
+			[0u8; 32].consensus_encode(&mut enc).unwrap();
+			htlc_tx.input[input].previous_output.consensus_encode(&mut enc).unwrap();
+			htlc_redeemscript.consensus_encode(&mut enc).unwrap();
+			amount.consensus_encode(&mut enc).unwrap();
+			htlc_tx.input[input].sequence.consensus_encode(&mut enc).unwrap();
+			// With SINGLE, hashOutputs commits to only the output at the same index
+			if input < htlc_tx.output.len() {
+				let mut out_enc = SigHash::engine();
+				htlc_tx.output[input].consensus_encode(&mut out_enc).unwrap();
+				SigHash::from_engine(out_enc).consensus_encode(&mut enc).unwrap();
+			} else {
+				[0u8; 32].consensus_encode(&mut enc).unwrap();
+			}
+			htlc_tx.lock_time.consensus_encode(&mut enc).unwrap();
+			(SigHashType::SinglePlusAnyoneCanPay as u32).consensus_encode(&mut enc).unwrap();
+			SigHash::from_engine(enc)
+		},
+		_ => panic!("HTLC transactions are only signed with SIGHASH_ALL or SIGHASH_SINGLE | SIGHASH_ANYONECANPAY"),
+	}
 }
 
 /// Gets the redeemscript for a funding output from the two funding public keys.
@@ -462,7 +559,7 @@ pub fn make_funding_redeemscript(a: &PublicKey, b: &PublicKey) -> Script {
 }
 
 /// panics if htlc.transaction_output_index.is_none()!
-pub fn build_htlc_transaction(prev_hash: &Txid, feerate_per_kw: u32, to_self_delay: u16, htlc: &HTLCOutputInCommitment, a_delayed_payment_key: &PublicKey, revocation_key: &PublicKey) -> Transaction {
+pub fn build_htlc_transaction(prev_hash: &Txid, feerate_per_kw: u32, to_self_delay: u16, htlc: &HTLCOutputInCommitment, a_delayed_payment_key: &PublicKey, revocation_key: &PublicKey, opt_anchors: bool) -> Transaction {
 	let mut txins: Vec<TxIn> = Vec::new();
 	txins.push(TxIn {
 		previous_output: OutPoint {
@@ -470,14 +567,14 @@ pub fn build_htlc_transaction(prev_hash: &Txid, feerate_per_kw: u32, to_self_del
 			vout: htlc.transaction_output_index.expect("Can't build an HTLC transaction for a dust output"),
 		},
 		script_sig: Script::new(),
-		sequence: 0,
+		sequence: if opt_anchors { 1 } else { 0 },
 		witness: Vec::new(),
 	});
 
 	let total_fee = if htlc.offered {
-			feerate_per_kw as u64 * HTLC_TIMEOUT_TX_WEIGHT / 1000
+			feerate_per_kw as u64 * htlc_timeout_tx_weight(opt_anchors) / 1000
 		} else {
-			feerate_per_kw as u64 * HTLC_SUCCESS_TX_WEIGHT / 1000
+			feerate_per_kw as u64 * htlc_success_tx_weight(opt_anchors) / 1000
 		};
 
 	let mut txouts: Vec<TxOut> = Vec::new();
@@ -514,6 +611,10 @@ pub struct LocalCommitmentTransaction {
 	/// The feerate paid per 1000-weight-unit in this commitment transaction. This value is
 	/// controlled by the channel initiator.
 	pub feerate_per_kw: u32,
+	/// Whether the channel negotiated option_anchor_outputs, in which case this commitment
+	/// transaction includes anchor outputs and its HTLC transactions use the anchor outputs
+	/// formats and counterparty signatures.
+	pub opt_anchors: bool,
 	/// The HTLCs and remote htlc signatures which were included in this commitment transaction.
 	///
 	/// Note that this includes all HTLCs, including ones which were considered dust and not
@@ -555,13 +656,14 @@ impl LocalCommitmentTransaction {
 					a_delayed_payment_key: dummy_key.clone(),
 				},
 			feerate_per_kw: 0,
+			opt_anchors: false,
 			per_htlc: Vec::new()
 		}
 	}
 
 	/// Generate a new LocalCommitmentTransaction based on a raw commitment transaction,
 	/// remote signature and both parties keys
-	pub(crate) fn new_missing_local_sig(unsigned_tx: Transaction, their_sig: Signature, our_funding_key: &PublicKey, their_funding_key: &PublicKey, local_keys: TxCreationKeys, feerate_per_kw: u32, htlc_data: Vec<(HTLCOutputInCommitment, Option<Signature>)>) -> LocalCommitmentTransaction {
+	pub(crate) fn new_missing_local_sig(unsigned_tx: Transaction, their_sig: Signature, our_funding_key: &PublicKey, their_funding_key: &PublicKey, local_keys: TxCreationKeys, feerate_per_kw: u32, opt_anchors: bool, htlc_data: Vec<(HTLCOutputInCommitment, Option<Signature>)>) -> LocalCommitmentTransaction {
 		if unsigned_tx.input.len() != 1 { panic!("Tried to store a commitment transaction that had input count != 1!"); }
 		if unsigned_tx.input[0].witness.len() != 0 { panic!("Tried to store a signed commitment transaction?"); }
 
@@ -571,6 +673,7 @@ impl LocalCommitmentTransaction {
 			our_sig_first: our_funding_key.serialize()[..] < their_funding_key.serialize()[..],
 			local_keys,
 			feerate_per_kw,
+			opt_anchors,
 			per_htlc: htlc_data,
 		}
 	}
@@ -627,9 +730,9 @@ impl LocalCommitmentTransaction {
 
 		for this_htlc in self.per_htlc.iter() {
 			if this_htlc.0.transaction_output_index.is_some() {
-				let htlc_tx = build_htlc_transaction(&txid, self.feerate_per_kw, local_csv, &this_htlc.0, &self.local_keys.a_delayed_payment_key, &self.local_keys.revocation_key);
+				let htlc_tx = build_htlc_transaction(&txid, self.feerate_per_kw, local_csv, &this_htlc.0, &self.local_keys.a_delayed_payment_key, &self.local_keys.revocation_key, self.opt_anchors);
 
-				let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc.0, &self.local_keys.a_htlc_key, &self.local_keys.b_htlc_key, &self.local_keys.revocation_key);
+				let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc.0, &self.local_keys.a_htlc_key, &self.local_keys.b_htlc_key, &self.local_keys.revocation_key, self.opt_anchors);
 
 				let sighash = hash_to_message!(&bip143::SighashComponents::new(&htlc_tx).sighash_all(&htlc_tx.input[0], &htlc_redeemscript, this_htlc.0.amount_msat / 1000)[..]);
 				ret.push(Some(secp_ctx.sign(&sighash, &our_htlc_key)));
@@ -650,19 +753,19 @@ impl LocalCommitmentTransaction {
 		// Further, we should never be provided the preimage for an HTLC-Timeout transaction.
 		if  this_htlc.0.offered && preimage.is_some() { unreachable!(); }
 
-		let mut htlc_tx = build_htlc_transaction(&txid, self.feerate_per_kw, local_csv, &this_htlc.0, &self.local_keys.a_delayed_payment_key, &self.local_keys.revocation_key);
+		let mut htlc_tx = build_htlc_transaction(&txid, self.feerate_per_kw, local_csv, &this_htlc.0, &self.local_keys.a_delayed_payment_key, &self.local_keys.revocation_key, self.opt_anchors);
 		// Channel should have checked that we have a remote signature for this HTLC at
 		// creation, and we should have a sensible htlc transaction:
 		assert!(this_htlc.1.is_some());
 
-		let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc.0, &self.local_keys.a_htlc_key, &self.local_keys.b_htlc_key, &self.local_keys.revocation_key);
+		let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc.0, &self.local_keys.a_htlc_key, &self.local_keys.b_htlc_key, &self.local_keys.revocation_key, self.opt_anchors);
 
 		// First push the multisig dummy, note that due to BIP147 (NULLDUMMY) it must be a zero-length element.
 		htlc_tx.input[0].witness.push(Vec::new());
 
 		htlc_tx.input[0].witness.push(this_htlc.1.unwrap().serialize_der().to_vec());
 		htlc_tx.input[0].witness.push(signature.serialize_der().to_vec());
-		htlc_tx.input[0].witness[1].push(SigHashType::All as u8);
+		htlc_tx.input[0].witness[1].push(htlc_counterparty_sighash_type(self.opt_anchors) as u8);
 		htlc_tx.input[0].witness[2].push(SigHashType::All as u8);
 
 		if this_htlc.0.offered {
@@ -694,6 +797,7 @@ impl Writeable for LocalCommitmentTransaction {
 		self.our_sig_first.write(writer)?;
 		self.local_keys.write(writer)?;
 		self.feerate_per_kw.write(writer)?;
+		self.opt_anchors.write(writer)?;
 		writer.write_all(&byte_utils::be64_to_array(self.per_htlc.len() as u64))?;
 		for &(ref htlc, ref sig) in self.per_htlc.iter() {
 			htlc.write(writer)?;
@@ -704,6 +808,13 @@ impl Writeable for LocalCommitmentTransaction {
 }
 impl Readable for LocalCommitmentTransaction {
 	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		ReadableArgs::read(reader, true)
+	}
+}
+/// The argument is whether opt_anchors was serialized, which it wasn't in version 1
+/// ChannelMonitors.
+impl ReadableArgs<bool> for LocalCommitmentTransaction {
+	fn read<R: ::std::io::Read>(reader: &mut R, has_opt_anchors: bool) -> Result<Self, DecodeError> {
 		let unsigned_tx = match Transaction::consensus_decode(reader.by_ref()) {
 			Ok(tx) => tx,
 			Err(e) => match e {
@@ -715,6 +826,7 @@ impl Readable for LocalCommitmentTransaction {
 		let our_sig_first = Readable::read(reader)?;
 		let local_keys = Readable::read(reader)?;
 		let feerate_per_kw = Readable::read(reader)?;
+		let opt_anchors = if has_opt_anchors { Readable::read(reader)? } else { false };
 		let htlcs_count: u64 = Readable::read(reader)?;
 		let mut per_htlc = Vec::with_capacity(cmp::min(htlcs_count as usize, MAX_ALLOC_SIZE / mem::size_of::<(HTLCOutputInCommitment, Option<Signature>)>()));
 		for _ in 0..htlcs_count {
@@ -733,6 +845,7 @@ impl Readable for LocalCommitmentTransaction {
 			our_sig_first,
 			local_keys,
 			feerate_per_kw,
+			opt_anchors,
 			per_htlc,
 		})
 	}
@@ -740,9 +853,32 @@ impl Readable for LocalCommitmentTransaction {
 
 #[cfg(test)]
 mod tests {
-	use super::CounterpartyCommitmentSecrets;
+	use super::{CounterpartyCommitmentSecrets, HTLCOutputInCommitment, HTLCType, get_htlc_redeemscript_with_explicit_keys};
+	use ln::channelmanager::PaymentHash;
+	use bitcoin::secp256k1::Secp256k1;
+	use bitcoin::secp256k1::key::{PublicKey, SecretKey};
 	use hex;
 
+	#[test]
+	fn test_witness_script_to_htlctype() {
+		// HTLC scripts are told apart with and without anchor outputs, even though the offered
+		// script with anchors is as long as some accepted scripts without.
+		let secp_ctx = Secp256k1::new();
+		let key = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		for cltv_expiry in [0, 100, 500_000].iter() {
+			for opt_anchors in [false, true].iter() {
+				for offered in [false, true].iter() {
+					let htlc = HTLCOutputInCommitment {
+						offered: *offered, amount_msat: 1000, cltv_expiry: *cltv_expiry, payment_hash: PaymentHash([1; 32]), transaction_output_index: None,
+					};
+					let script = get_htlc_redeemscript_with_explicit_keys(&htlc, &key, &key, &key, *opt_anchors);
+					let expected_type = if *offered { HTLCType::OfferedHTLC } else { HTLCType::AcceptedHTLC };
+					assert!(HTLCType::witness_script_to_htlctype(&script[..]) == Some(expected_type));
+				}
+			}
+		}
+	}
+
 	#[test]
 	fn test_per_commitment_storage() {
 		// Test vectors from BOLT 3:
@@ -1,6 +1,6 @@
 use bitcoin::blockdata::block::BlockHeader;
 use bitcoin::blockdata::script::{Script,Builder};
-use bitcoin::blockdata::transaction::{TxIn, TxOut, Transaction, SigHashType};
+use bitcoin::blockdata::transaction::{TxIn, TxOut, Transaction, SigHashType, OutPoint as BitcoinOutPoint};
 use bitcoin::blockdata::opcodes;
 use bitcoin::util::hash::BitcoinHash;
 use bitcoin::util::bip143;
@@ -18,14 +18,16 @@ use ln::features::{ChannelFeatures, InitFeatures};
 use ln::msgs;
 use ln::msgs::{DecodeError, OptionalField, DataLossProtect};
 use ln::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateStep, HTLC_FAIL_BACK_BUFFER};
-use ln::channelmanager::{PendingHTLCStatus, HTLCSource, HTLCFailReason, HTLCFailureMsg, PendingHTLCInfo, RAACommitmentOrder, PaymentPreimage, PaymentHash, BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT};
-use ln::chan_utils::{CounterpartyCommitmentSecrets, LocalCommitmentTransaction, TxCreationKeys, HTLCOutputInCommitment, HTLC_SUCCESS_TX_WEIGHT, HTLC_TIMEOUT_TX_WEIGHT, make_funding_redeemscript, ChannelPublicKeys};
+use ln::channelmanager::{PendingHTLCStatus, HTLCSource, HTLCFailReason, HTLCFailureMsg, PendingHTLCInfo, RAACommitmentOrder, PaymentPreimage, PaymentHash, BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT, MIN_CLTV_EXPIRY_DELTA};
+use ln::chan_utils::{CounterpartyCommitmentSecrets, LocalCommitmentTransaction, TxCreationKeys, HTLCOutputInCommitment, ANCHOR_OUTPUT_VALUE_SATOSHI, htlc_success_tx_weight, htlc_timeout_tx_weight, make_funding_redeemscript, ChannelPublicKeys};
 use ln::chan_utils;
+use ln::interactivetxs;
+use ln::interactivetxs::{FundingContribution, FundingInput, FundingTxSigningState, InteractiveTxConstructor, InteractiveTxMessageSend, SharedInputSignatures};
 use chain::chaininterface::{FeeEstimator,ConfirmationTarget};
 use chain::transaction::OutPoint;
 use chain::keysinterface::{ChannelKeys, KeysInterface};
 use util::transaction_utils;
-use util::ser::{Readable, Writeable, Writer};
+use util::ser::{Readable, ReadableArgs, Writeable, Writer};
 use util::logger::Logger;
 use util::errors::APIError;
 use util::config::{UserConfig,ChannelConfig};
@@ -255,6 +257,9 @@ enum UpdateStatus {
 // inbound channel.
 pub(super) struct Channel<ChanSigner: ChannelKeys> {
 	config: ChannelConfig,
+	/// The config we had before our last call to update_config and the number of timer ticks since,
+	/// while we still accept HTLCs forwarded according to it.
+	prev_config: Option<(ChannelConfig, u8)>,
 
 	user_id: u64,
 
@@ -263,6 +268,9 @@ pub(super) struct Channel<ChanSigner: ChannelKeys> {
 	channel_outbound: bool,
 	secp_ctx: Secp256k1<secp256k1::All>,
 	channel_value_satoshis: u64,
+	/// Whether option_anchor_outputs was negotiated for this channel, fixing the format of its
+	/// commitment and HTLC transactions.
+	opt_anchors: bool,
 
 	latest_monitor_update_id: u64,
 
@@ -331,6 +339,30 @@ pub(super) struct Channel<ChanSigner: ChannelKeys> {
 	last_sent_closing_fee: Option<(u32, u64, Signature)>, // (feerate, fee, our_sig)
 
 	funding_txo: Option<OutPoint>,
+	/// Set while negotiating the funding transaction of a dual-funded channel, ie until we've
+	/// exchanged commitment_signed for the initial commitment transactions.
+	dual_funding: Option<DualFundingNegotiation>,
+	/// Set for dual-funded channels once their funding transaction has been constructed, tracking
+	/// the exchange of tx_signatures which makes it broadcastable.
+	funding_tx_signing: Option<FundingTxSigningState>,
+	/// Set while negotiating a splice transaction, ie until we've sent the commitment_signed for
+	/// our counterparty's commitment transaction spending it.
+	splice_negotiation: Option<SpliceNegotiation>,
+	/// Set once a splice transaction has been constructed, until it locks and replaces the
+	/// channel's funding transaction. Once commitment transactions spending it were exchanged, the
+	/// channel is updated as usual, with each commitment transaction signed for both funding
+	/// outputs.
+	pending_splice: Option<PendingSplice>,
+	/// The value of cur_local_commitment_transaction_number when our last splice locked. Until our
+	/// counterparty sends us a new commitment transaction, it may not have seen our splice_locked,
+	/// which we thus retransmit on reconnection.
+	splice_locked_commitment_number: Option<u64>,
+	/// The funding outpoint the channel was opened with, once it has been spliced. Our
+	/// ChannelMonitor remains keyed by it for the lifetime of the channel.
+	original_funding_txo: Option<OutPoint>,
+	/// The short_channel_ids the channel had before being spliced, which HTLCs we received before
+	/// the splice (or payers unaware of it) still refer to.
+	prev_short_channel_ids: Vec<u64>,
 
 	/// The hash of the block in which the funding transaction reached our CONF_TARGET. We use this
 	/// to detect unconfirmation after a serialize-unserialize roundtrip where we may not see a full
@@ -342,6 +374,10 @@ pub(super) struct Channel<ChanSigner: ChannelKeys> {
 	/// ChannelManager deserialization (hence pub(super))
 	pub(super) last_block_connected: BlockHash,
 	funding_tx_confirmations: u64,
+	/// The block height at which we received open_channel for an inbound channel, used to close it
+	/// if the funding transaction never confirms. Unused for outbound channels. None only for
+	/// channels read from data written before this was tracked, until ChannelManager fills it in.
+	channel_creation_height: Option<u32>,
 
 	their_dust_limit_satoshis: u64,
 	#[cfg(test)]
@@ -384,6 +420,56 @@ pub(super) struct Channel<ChanSigner: ChannelKeys> {
 	network_sync: UpdateStatus,
 }
 
+/// The parameters of a dual-funded channel's funding transaction, kept while it is negotiated.
+/// None of this is persisted as channels which haven't exchanged commitment_signed are forgotten
+/// on disconnection.
+struct DualFundingNegotiation {
+	funding_feerate_per_kw: u32,
+	locktime: u32,
+	counterparty_funding_satoshis: u64,
+	/// Our contribution, which is only known for inbound channels once the user provides it via
+	/// ChannelManager::contribute_channel_funding.
+	holder_contribution: Option<FundingContribution>,
+	/// Set once both sides have sent their open_channel2/accept_channel2.
+	constructor: Option<InteractiveTxConstructor>,
+}
+
+/// The parameters of a splice transaction, kept while it is negotiated. None of this is persisted
+/// as splices are abandoned on disconnection until we've sent our tx_signatures.
+struct SpliceNegotiation {
+	is_initiator: bool,
+	funding_feerate_per_kw: u32,
+	locktime: u32,
+	/// The change to our channel balance, which is negative when splicing funds out.
+	holder_contribution_satoshis: i64,
+	/// The change to our counterparty's channel balance, which is only known to the initiator once
+	/// it receives splice_ack.
+	counterparty_contribution_satoshis: i64,
+	/// The inputs and outputs the initiator adds to the splice transaction, until construction
+	/// begins.
+	holder_inputs: Vec<FundingInput>,
+	holder_outputs: Vec<TxOut>,
+	/// Set once both sides have sent their splice_init/splice_ack.
+	constructor: Option<InteractiveTxConstructor>,
+}
+
+/// A constructed splice transaction, along with the channel's funding parameters once it locks.
+struct PendingSplice {
+	funding_txo: OutPoint,
+	channel_value_satoshis: u64,
+	value_to_self_msat: u64,
+	local_channel_reserve_satoshis: u64,
+	signing: FundingTxSigningState,
+	/// Set once we've checked our counterparty's signature for our commitment transaction spending
+	/// the splice transaction and handed it to our ChannelMonitor.
+	received_commitment_signed: bool,
+	confirmations: u32,
+	confirmed_in: Option<BlockHash>,
+	short_channel_id: Option<u64>,
+	sent_splice_locked: bool,
+	received_splice_locked: bool,
+}
+
 pub const OUR_MAX_HTLCS: u16 = 50; //TODO
 /// Confirmation count threshold at which we close a channel. Ideally we'd keep the channel around
 /// on ice until the funding transaction gets more confirmations, but the LN protocol doesn't
@@ -400,11 +486,39 @@ pub const COMMITMENT_TX_BASE_WEIGHT: u64 = 724;
 const COMMITMENT_TX_WEIGHT_PER_HTLC: u64 = 172;
 #[cfg(test)]
 pub const COMMITMENT_TX_WEIGHT_PER_HTLC: u64 = 172;
+// The two anchor outputs add 2 * 43 bytes and the to_remote output becomes P2WSH (+ 12 bytes).
+const COMMITMENT_TX_BASE_ANCHOR_WEIGHT: u64 = 1124;
+
+/// Gets the weight of a commitment transaction without any HTLC outputs.
+#[inline]
+fn commitment_tx_base_weight(opt_anchors: bool) -> u64 {
+	if opt_anchors { COMMITMENT_TX_BASE_ANCHOR_WEIGHT } else { COMMITMENT_TX_BASE_WEIGHT }
+}
+
+/// Gets the total value of the anchor outputs which the funder pays for in each commitment
+/// transaction, on top of the commitment transaction fee.
+#[inline]
+fn anchor_outputs_value_satoshis(opt_anchors: bool) -> u64 {
+	if opt_anchors { 2 * ANCHOR_OUTPUT_VALUE_SATOSHI } else { 0 }
+}
 
-/// Maximmum `funding_satoshis` value, according to the BOLT #2 specification
+/// Maximmum `funding_satoshis` value for channels with peers which don't support
+/// option_support_large_channel (aka wumbo channels), according to the BOLT #2 specification
 /// it's 2^24.
 pub const MAX_FUNDING_SATOSHIS: u64 = 1 << 24;
 
+/// The total number of satoshis which will ever exist, which no channel can exceed.
+pub const TOTAL_BITCOIN_SUPPLY_SATOSHIS: u64 = 21_000_000 * 1_0000_0000;
+
+/// The number of times ChannelManager::timer_chan_freshness_every_min may be called after our
+/// forwarding policy changes before we stop accepting HTLCs which only satisfy the previous one,
+/// giving our channel_update time to propagate.
+pub(super) const EXPIRE_PREV_CONFIG_TICKS: u8 = 5;
+
+/// The number of blocks after we receive an inbound channel's open_channel within which its
+/// funding transaction must confirm, after which we give up on it and close the channel.
+pub const FUNDING_CONF_DEADLINE_BLOCKS: u32 = 2016;
+
 /// Used to return a simple Error back to ChannelManager. Will get converted to a
 /// msgs::ErrorAction::SendErrorMessage or msgs::ErrorAction::IgnoreError as appropriate with our
 /// channel_id in ChannelManager.
@@ -453,14 +567,17 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	}
 
 	// Constructors:
-	pub fn new_outbound<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, their_node_id: PublicKey, channel_value_satoshis: u64, push_msat: u64, user_id: u64, config: &UserConfig) -> Result<Channel<ChanSigner>, APIError>
+	pub fn new_outbound<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, their_node_id: PublicKey, their_features: &InitFeatures, channel_value_satoshis: u64, push_msat: u64, user_id: u64, config: &UserConfig) -> Result<Channel<ChanSigner>, APIError>
 	where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
 	      F::Target: FeeEstimator,
 	{
 		let chan_keys = keys_provider.get_channel_keys(false, channel_value_satoshis);
 
-		if channel_value_satoshis >= MAX_FUNDING_SATOSHIS {
-			return Err(APIError::APIMisuseError{err: "funding value > 2^24"});
+		if channel_value_satoshis > TOTAL_BITCOIN_SUPPLY_SATOSHIS {
+			return Err(APIError::APIMisuseError{err: "funding value > total bitcoin supply"});
+		}
+		if channel_value_satoshis >= MAX_FUNDING_SATOSHIS && !their_features.supports_wumbo() {
+			return Err(APIError::APIMisuseError{err: "funding value > 2^24 - 1 and peer does not support large channels"});
 		}
 
 		if push_msat > channel_value_satoshis * 1000 {
@@ -469,6 +586,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if config.own_channel_config.our_to_self_delay < BREAKDOWN_TIMEOUT {
 			return Err(APIError::APIMisuseError{err: "Configured with an unreasonable our_to_self_delay putting user funds at risks"});
 		}
+		if config.channel_options.cltv_expiry_delta < MIN_CLTV_EXPIRY_DELTA {
+			return Err(APIError::APIMisuseError{err: "Configured with a cltv_expiry_delta below MIN_CLTV_EXPIRY_DELTA putting user funds at risks"});
+		}
 
 
 		let background_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Background);
@@ -477,16 +597,19 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 
 		let feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+		let opt_anchors = config.own_channel_config.negotiate_anchor_outputs && their_features.supports_anchor_outputs();
 
 		Ok(Channel {
 			user_id: user_id,
 			config: config.channel_options.clone(),
+			prev_config: None,
 
 			channel_id: keys_provider.get_channel_id(),
 			channel_state: ChannelState::OurInitSent as u32,
 			channel_outbound: true,
 			secp_ctx: Secp256k1::new(),
 			channel_value_satoshis: channel_value_satoshis,
+			opt_anchors,
 
 			latest_monitor_update_id: 0,
 
@@ -523,10 +646,18 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			last_sent_closing_fee: None,
 
 			funding_txo: None,
+			dual_funding: None,
+			funding_tx_signing: None,
+			splice_negotiation: None,
+			pending_splice: None,
+			splice_locked_commitment_number: None,
+			original_funding_txo: None,
+			prev_short_channel_ids: Vec::new(),
 			funding_tx_confirmed_in: None,
 			short_channel_id: None,
 			last_block_connected: Default::default(),
 			funding_tx_confirmations: 0,
+			channel_creation_height: Some(0),
 
 			feerate_per_kw: feerate,
 			their_dust_limit_satoshis: 0,
@@ -567,9 +698,25 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		Ok(())
 	}
 
+	/// Checks the feerate in an update_fee against the same bounds as check_remote_fee, widened by
+	/// max_deviation_percent.
+	fn check_remote_update_fee<F: Deref>(fee_estimator: &F, feerate_per_kw: u32, max_deviation_percent: u16) -> Result<(), ChannelError>
+		where F::Target: FeeEstimator
+	{
+		let lower_bound = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Background) as u64;
+		if (feerate_per_kw as u64) * (100 + max_deviation_percent as u64) < lower_bound * 100 {
+			return Err(ChannelError::Close("Peer's feerate much too low"));
+		}
+		let upper_bound = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::HighPriority) as u64 * 2;
+		if (feerate_per_kw as u64) * 100 > upper_bound * (100 + max_deviation_percent as u64) {
+			return Err(ChannelError::Close("Peer's feerate much too high"));
+		}
+		Ok(())
+	}
+
 	/// Creates a new channel from a remote sides' request for one.
 	/// Assumes chain_hash has already been checked and corresponds with what we expect!
-	pub fn new_from_req<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, their_node_id: PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannel, user_id: u64, config: &UserConfig) -> Result<Channel<ChanSigner>, ChannelError>
+	pub fn new_from_req<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, their_node_id: PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannel, user_id: u64, current_chain_height: u32, config: &UserConfig) -> Result<Channel<ChanSigner>, ChannelError>
 		where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
           F::Target: FeeEstimator
 	{
@@ -587,10 +734,16 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if config.own_channel_config.our_to_self_delay < BREAKDOWN_TIMEOUT {
 			return Err(ChannelError::Close("Configured with an unreasonable our_to_self_delay putting user funds at risks"));
 		}
+		if config.channel_options.cltv_expiry_delta < MIN_CLTV_EXPIRY_DELTA {
+			return Err(ChannelError::Close("Configured with a cltv_expiry_delta below MIN_CLTV_EXPIRY_DELTA putting user funds at risks"));
+		}
 
 		// Check sanity of message fields:
-		if msg.funding_satoshis >= MAX_FUNDING_SATOSHIS {
-			return Err(ChannelError::Close("funding value > 2^24"));
+		if msg.funding_satoshis > TOTAL_BITCOIN_SUPPLY_SATOSHIS {
+			return Err(ChannelError::Close("funding value > total bitcoin supply"));
+		}
+		if msg.funding_satoshis >= MAX_FUNDING_SATOSHIS && !their_features.supports_wumbo() {
+			return Err(ChannelError::Close("funding value > 2^24 - 1 and large channels were not negotiated"));
 		}
 		if msg.channel_reserve_satoshis > msg.funding_satoshis {
 			return Err(ChannelError::Close("Bogus channel_reserve_satoshis"));
@@ -623,6 +776,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if msg.funding_satoshis < config.peer_channel_config_limits.min_funding_satoshis {
 			return Err(ChannelError::Close("funding satoshis is less than the user specified limit"));
 		}
+		if msg.funding_satoshis > config.peer_channel_config_limits.max_funding_satoshis {
+			return Err(ChannelError::Close("funding satoshis is greater than the user specified limit"));
+		}
 		if msg.htlc_minimum_msat > config.peer_channel_config_limits.max_htlc_minimum_msat {
 			return Err(ChannelError::Close("htlc minimum msat is higher than the user specified limit"));
 		}
@@ -667,15 +823,18 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			return Err(ChannelError::Close("Dust limit too high for the channel reserve we require the remote to keep"));
 		}
 
+		let opt_anchors = config.own_channel_config.negotiate_anchor_outputs && their_features.supports_anchor_outputs();
+
 		// check if the funder's amount for the initial commitment tx is sufficient
-		// for full fee payment
+		// for full fee payment (and the anchor outputs, if any)
 		let funders_amount_msat = msg.funding_satoshis * 1000 - msg.push_msat;
-		if funders_amount_msat < background_feerate as u64 * COMMITMENT_TX_BASE_WEIGHT {
+		let initial_commitment_cost_msat = background_feerate as u64 * commitment_tx_base_weight(opt_anchors) + anchor_outputs_value_satoshis(opt_anchors) * 1000;
+		if funders_amount_msat < initial_commitment_cost_msat {
 			return Err(ChannelError::Close("Insufficient funding amount for initial commitment"));
 		}
 
 		let to_local_msat = msg.push_msat;
-		let to_remote_msat = funders_amount_msat - background_feerate as u64 * COMMITMENT_TX_BASE_WEIGHT;
+		let to_remote_msat = funders_amount_msat - initial_commitment_cost_msat;
 		if to_local_msat <= msg.channel_reserve_satoshis * 1000 && to_remote_msat <= remote_channel_reserve_satoshis * 1000 {
 			return Err(ChannelError::Close("Insufficient funding amount for initial commitment"));
 		}
@@ -704,6 +863,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let chan = Channel {
 			user_id: user_id,
 			config: local_config,
+			prev_config: None,
 
 			channel_id: msg.temporary_channel_id,
 			channel_state: (ChannelState::OurInitSent as u32) | (ChannelState::TheirInitSent as u32),
@@ -745,13 +905,22 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			last_sent_closing_fee: None,
 
 			funding_txo: None,
+			dual_funding: None,
+			funding_tx_signing: None,
+			splice_negotiation: None,
+			pending_splice: None,
+			splice_locked_commitment_number: None,
+			original_funding_txo: None,
+			prev_short_channel_ids: Vec::new(),
 			funding_tx_confirmed_in: None,
 			short_channel_id: None,
 			last_block_connected: Default::default(),
 			funding_tx_confirmations: 0,
+			channel_creation_height: Some(current_chain_height),
 
 			feerate_per_kw: msg.feerate_per_kw,
 			channel_value_satoshis: msg.funding_satoshis,
+			opt_anchors,
 			their_dust_limit_satoshis: msg.dust_limit_satoshis,
 			our_dust_limit_satoshis: our_dust_limit_satoshis,
 			their_max_htlc_value_in_flight_msat: cmp::min(msg.max_htlc_value_in_flight_msat, msg.funding_satoshis * 1000),
@@ -780,6 +949,74 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		Ok(chan)
 	}
 
+	/// Creates a new outbound dual-funded channel, to which we contribute the given funds and
+	/// which our counterparty may add to in its accept_channel2.
+	pub fn new_outbound_v2<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, their_node_id: PublicKey, their_features: &InitFeatures, contribution: FundingContribution, locktime: u32, user_id: u64, config: &UserConfig) -> Result<Channel<ChanSigner>, APIError>
+	where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+	      F::Target: FeeEstimator,
+	{
+		if !their_features.supports_dual_fund() {
+			return Err(APIError::APIMisuseError{err: "Peer does not support dual-funded channels"});
+		}
+		let funding_feerate_per_kw = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+		interactivetxs::check_holder_contribution(&contribution, true, false, funding_feerate_per_kw).map_err(|err| APIError::APIMisuseError{err})?;
+
+		let mut chan = Channel::new_outbound(fee_estimator, keys_provider, their_node_id, their_features, contribution.funding_satoshis, 0, user_id, config)?;
+		chan.dual_funding = Some(DualFundingNegotiation {
+			funding_feerate_per_kw,
+			locktime,
+			counterparty_funding_satoshis: 0,
+			holder_contribution: Some(contribution),
+			constructor: None,
+		});
+		Ok(chan)
+	}
+
+	/// Creates a new dual-funded channel from a remote side's open_channel2. Our own contribution
+	/// is provided later, via accept_dual_funded_channel.
+	/// Assumes chain_hash has already been checked and corresponds with what we expect!
+	pub fn new_from_req_v2<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, their_node_id: PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannelV2, user_id: u64, current_chain_height: u32, config: &UserConfig) -> Result<Channel<ChanSigner>, ChannelError>
+		where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+          F::Target: FeeEstimator
+	{
+		Channel::<ChanSigner>::check_remote_fee(fee_estimator, msg.funding_feerate_per_kw)?;
+
+		// Apply the same checks as for an open_channel. Neither side may push funds to the other
+		// in a dual-funded channel and both sides' reserves are derived from the channel value.
+		let open_channel = msgs::OpenChannel {
+			chain_hash: msg.chain_hash,
+			temporary_channel_id: msg.temporary_channel_id,
+			funding_satoshis: msg.funding_satoshis,
+			push_msat: 0,
+			dust_limit_satoshis: msg.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: msg.max_htlc_value_in_flight_msat,
+			channel_reserve_satoshis: Channel::<ChanSigner>::get_remote_channel_reserve_satoshis(msg.funding_satoshis),
+			htlc_minimum_msat: msg.htlc_minimum_msat,
+			feerate_per_kw: msg.commitment_feerate_per_kw,
+			to_self_delay: msg.to_self_delay,
+			max_accepted_htlcs: msg.max_accepted_htlcs,
+			funding_pubkey: msg.funding_pubkey,
+			revocation_basepoint: msg.revocation_basepoint,
+			payment_point: msg.payment_point,
+			delayed_payment_basepoint: msg.delayed_payment_basepoint,
+			htlc_basepoint: msg.htlc_basepoint,
+			first_per_commitment_point: msg.first_per_commitment_point,
+			channel_flags: msg.channel_flags,
+			shutdown_scriptpubkey: msg.shutdown_scriptpubkey.clone(),
+		};
+		let mut chan = Channel::new_from_req(fee_estimator, keys_provider, their_node_id, their_features, &open_channel, user_id, current_chain_height, config)?;
+		// Capped to the channel value once we know our contribution.
+		chan.their_max_htlc_value_in_flight_msat = msg.max_htlc_value_in_flight_msat;
+		chan.dual_funding = Some(DualFundingNegotiation {
+			funding_feerate_per_kw: msg.funding_feerate_per_kw,
+			locktime: msg.locktime,
+			counterparty_funding_satoshis: msg.funding_satoshis,
+			holder_contribution: None,
+			constructor: None,
+		});
+		Ok(chan)
+	}
+
 	// Utilities to derive keys:
 
 	fn build_local_commitment_secret(&self, idx: u64) -> SecretKey {
@@ -868,10 +1105,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			($htlc: expr, $outbound: expr, $source: expr, $state_name: expr) => {
 				if $outbound == local { // "offered HTLC output"
 					let htlc_in_tx = get_htlc_in_commitment!($htlc, true);
-					if $htlc.amount_msat / 1000 >= dust_limit_satoshis + (feerate_per_kw as u64 * HTLC_TIMEOUT_TX_WEIGHT / 1000) {
+					if $htlc.amount_msat / 1000 >= dust_limit_satoshis + (feerate_per_kw as u64 * htlc_timeout_tx_weight(self.opt_anchors) / 1000) {
 						log_trace!(logger, "   ...including {} {} HTLC {} (hash {}) with value {}", if $outbound { "outbound" } else { "inbound" }, $state_name, $htlc.htlc_id, log_bytes!($htlc.payment_hash.0), $htlc.amount_msat);
 						txouts.push((TxOut {
-							script_pubkey: chan_utils::get_htlc_redeemscript(&htlc_in_tx, &keys).to_v0_p2wsh(),
+							script_pubkey: chan_utils::get_htlc_redeemscript(&htlc_in_tx, &keys, self.opt_anchors).to_v0_p2wsh(),
 							value: $htlc.amount_msat / 1000
 						}, Some((htlc_in_tx, $source))));
 					} else {
@@ -880,10 +1117,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 					}
 				} else {
 					let htlc_in_tx = get_htlc_in_commitment!($htlc, false);
-					if $htlc.amount_msat / 1000 >= dust_limit_satoshis + (feerate_per_kw as u64 * HTLC_SUCCESS_TX_WEIGHT / 1000) {
+					if $htlc.amount_msat / 1000 >= dust_limit_satoshis + (feerate_per_kw as u64 * htlc_success_tx_weight(self.opt_anchors) / 1000) {
 						log_trace!(logger, "   ...including {} {} HTLC {} (hash {}) with value {}", if $outbound { "outbound" } else { "inbound" }, $state_name, $htlc.htlc_id, log_bytes!($htlc.payment_hash.0), $htlc.amount_msat);
 						txouts.push((TxOut { // "received HTLC output"
-							script_pubkey: chan_utils::get_htlc_redeemscript(&htlc_in_tx, &keys).to_v0_p2wsh(),
+							script_pubkey: chan_utils::get_htlc_redeemscript(&htlc_in_tx, &keys, self.opt_anchors).to_v0_p2wsh(),
 							value: $htlc.amount_msat / 1000
 						}, Some((htlc_in_tx, $source))));
 					} else {
@@ -973,7 +1210,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			max_commitment_tx_output.1 = cmp::max(max_commitment_tx_output.1, value_to_remote_msat as u64);
 		}
 
-		let total_fee = feerate_per_kw as u64 * (COMMITMENT_TX_BASE_WEIGHT + (txouts.len() as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000;
+		let num_nondust_htlcs = txouts.len();
+		let total_fee = feerate_per_kw as u64 * (commitment_tx_base_weight(self.opt_anchors) + (num_nondust_htlcs as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000
+			+ anchor_outputs_value_satoshis(self.opt_anchors);
 		let (value_to_self, value_to_remote) = if self.channel_outbound {
 			(value_to_self_msat / 1000 - total_fee as i64, value_to_remote_msat / 1000)
 		} else {
@@ -999,15 +1238,44 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				self.their_pubkeys.as_ref().unwrap().payment_point
 			} else {
 				self.local_keys.pubkeys().payment_point
-			}.serialize();
+			};
+			let script_pubkey = if self.opt_anchors {
+				chan_utils::get_to_countersignatory_with_anchors_redeemscript(&static_payment_pk).to_v0_p2wsh()
+			} else {
+				Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0)
+				              .push_slice(&WPubkeyHash::hash(&static_payment_pk.serialize())[..])
+				              .into_script()
+			};
 			txouts.push((TxOut {
-				script_pubkey: Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0)
-				                             .push_slice(&WPubkeyHash::hash(&static_payment_pk)[..])
-				                             .into_script(),
+				script_pubkey,
 				value: value_to_b as u64
 			}, None));
 		}
 
+		if self.opt_anchors {
+			// Each party's anchor is only included if it has something at stake in the commitment
+			// transaction, ie a main output or any (non-dust) HTLC output.
+			let (a_funding_pubkey, b_funding_pubkey) = if local {
+				(self.local_keys.pubkeys().funding_pubkey, self.their_pubkeys.as_ref().unwrap().funding_pubkey)
+			} else {
+				(self.their_pubkeys.as_ref().unwrap().funding_pubkey, self.local_keys.pubkeys().funding_pubkey)
+			};
+			if value_to_a >= (dust_limit_satoshis as i64) || num_nondust_htlcs != 0 {
+				log_trace!(logger, "   ...including {} anchor output", if local { "local" } else { "remote" });
+				txouts.push((TxOut {
+					script_pubkey: chan_utils::get_anchor_redeemscript(&a_funding_pubkey).to_v0_p2wsh(),
+					value: ANCHOR_OUTPUT_VALUE_SATOSHI,
+				}, None));
+			}
+			if value_to_b >= (dust_limit_satoshis as i64) || num_nondust_htlcs != 0 {
+				log_trace!(logger, "   ...including {} anchor output", if local { "remote" } else { "local" });
+				txouts.push((TxOut {
+					script_pubkey: chan_utils::get_anchor_redeemscript(&b_funding_pubkey).to_v0_p2wsh(),
+					value: ANCHOR_OUTPUT_VALUE_SATOSHI,
+				}, None));
+			}
+		}
+
 		transaction_utils::sort_outputs(&mut txouts, |a, b| {
 			if let &Some(ref a_htlc) = a {
 				if let &Some(ref b_htlc) = b {
@@ -1131,13 +1399,20 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	/// will sign and send to our counterparty.
 	/// If an Err is returned, it is a ChannelError::Close (for get_outbound_funding_created)
 	fn build_remote_transaction_keys(&self) -> Result<TxCreationKeys, ChannelError> {
+		self.build_remote_transaction_keys_with_point(&self.their_cur_commitment_point.unwrap())
+	}
+
+	#[inline]
+	/// Creates a set of keys for build_commitment_transaction to generate one of our
+	/// counterparty's transactions with the given per-commitment point.
+	fn build_remote_transaction_keys_with_point(&self, per_commitment_point: &PublicKey) -> Result<TxCreationKeys, ChannelError> {
 		//TODO: Ensure that the payment_key derived here ends up in the library users' wallet as we
 		//may see payments to it!
 		let revocation_basepoint = &self.local_keys.pubkeys().revocation_basepoint;
 		let htlc_basepoint = &self.local_keys.pubkeys().htlc_basepoint;
 		let their_pubkeys = self.their_pubkeys.as_ref().unwrap();
 
-		Ok(secp_check!(TxCreationKeys::new(&self.secp_ctx, &self.their_cur_commitment_point.unwrap(), &their_pubkeys.delayed_payment_basepoint, &their_pubkeys.htlc_basepoint, revocation_basepoint, htlc_basepoint), "Remote tx keys generation got bogus keys"))
+		Ok(secp_check!(TxCreationKeys::new(&self.secp_ctx, per_commitment_point, &their_pubkeys.delayed_payment_basepoint, &their_pubkeys.htlc_basepoint, revocation_basepoint, htlc_basepoint), "Remote tx keys generation got bogus keys"))
 	}
 
 	/// Gets the redeemscript for the funding transaction output (ie the funding transaction output
@@ -1151,7 +1426,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	/// @local is used only to convert relevant internal structures which refer to remote vs local
 	/// to decide value of outputs and direction of HTLCs.
 	fn build_htlc_transaction(&self, prev_hash: &Txid, htlc: &HTLCOutputInCommitment, local: bool, keys: &TxCreationKeys, feerate_per_kw: u32) -> Transaction {
-		chan_utils::build_htlc_transaction(prev_hash, feerate_per_kw, if local { self.their_to_self_delay } else { self.our_to_self_delay }, htlc, &keys.a_delayed_payment_key, &keys.revocation_key)
+		chan_utils::build_htlc_transaction(prev_hash, feerate_per_kw, if local { self.their_to_self_delay } else { self.our_to_self_delay }, htlc, &keys.a_delayed_payment_key, &keys.revocation_key, self.opt_anchors)
 	}
 
 	/// Per HTLC, only one get_update_fail_htlc or get_update_fulfill_htlc call may be made.
@@ -1368,6 +1643,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state != ChannelState::OurInitSent as u32 {
 			return Err(ChannelError::Close("Got an accept_channel message at a strange time"));
 		}
+		if self.channel_value_satoshis >= MAX_FUNDING_SATOSHIS && !their_features.supports_wumbo() {
+			return Err(ChannelError::Close("Peer accepted a channel larger than 2^24 - 1 without supporting large channels"));
+		}
 		if msg.dust_limit_satoshis > 21000000 * 100000000 {
 			return Err(ChannelError::Close("Peer never wants payout outputs?"));
 		}
@@ -1478,11 +1756,11 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		log_trace!(logger, "Checking funding_created tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(sig.serialize_compact()[..]), log_bytes!(self.their_funding_pubkey().serialize()), encode::serialize_hex(&local_initial_commitment_tx), log_bytes!(local_sighash[..]), encode::serialize_hex(&funding_script));
 		secp_check!(self.secp_ctx.verify(&local_sighash, &sig, self.their_funding_pubkey()), "Invalid funding_created signature from peer");
 
-		let localtx = LocalCommitmentTransaction::new_missing_local_sig(local_initial_commitment_tx, sig.clone(), &self.local_keys.pubkeys().funding_pubkey, self.their_funding_pubkey(), local_keys, self.feerate_per_kw, Vec::new());
+		let localtx = LocalCommitmentTransaction::new_missing_local_sig(local_initial_commitment_tx, sig.clone(), &self.local_keys.pubkeys().funding_pubkey, self.their_funding_pubkey(), local_keys, self.feerate_per_kw, self.opt_anchors, Vec::new());
 
 		let remote_keys = self.build_remote_transaction_keys()?;
 		let remote_initial_commitment_tx = self.build_commitment_transaction(self.cur_remote_commitment_transaction_number, &remote_keys, false, false, self.feerate_per_kw, logger).0;
-		let remote_signature = self.local_keys.sign_remote_commitment(self.feerate_per_kw, &remote_initial_commitment_tx, &remote_keys, &Vec::new(), self.our_to_self_delay, &self.secp_ctx)
+		let remote_signature = self.local_keys.sign_remote_commitment(self.feerate_per_kw, &remote_initial_commitment_tx, &remote_keys, &Vec::new(), self.our_to_self_delay, self.opt_anchors, &self.secp_ctx)
 				.map_err(|_| ChannelError::Close("Failed to get signatures for new commitment_signed"))?.0;
 
 		// We sign the "remote" commitment transaction, allowing them to broadcast the tx if they wish.
@@ -1529,7 +1807,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			() => { {
 				let mut channel_monitor = ChannelMonitor::new(self.local_keys.clone(),
 				                                              &self.shutdown_pubkey, self.our_to_self_delay,
-				                                              &self.destination_script, (funding_txo, funding_txo_script.clone()),
+				                                              &self.destination_script, (funding_txo, funding_txo_script.clone()), funding_txo.to_channel_id(),
 				                                              &their_pubkeys.htlc_basepoint, &their_pubkeys.delayed_payment_basepoint,
 				                                              self.their_to_self_delay, funding_redeemscript.clone(), self.channel_value_satoshis,
 				                                              self.get_commitment_transaction_number_obscure_factor(),
@@ -1563,6 +1841,15 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & !(ChannelState::MonitorUpdateFailed as u32) != ChannelState::FundingCreated as u32 {
 			return Err(ChannelError::Close("Received funding_signed in strange state!"));
 		}
+		if self.funding_tx_signing.is_some() {
+			return Err(ChannelError::Close("Received funding_signed for a dual-funded channel"));
+		}
+		self.initial_commitment_monitor(&msg.signature, "Invalid funding_signed signature from peer", logger)
+	}
+
+	/// Checks our counterparty's signature for our initial commitment transaction, once we've sent
+	/// them the signature for theirs, and creates the ChannelMonitor for the channel.
+	fn initial_commitment_monitor<L: Deref>(&mut self, sig: &Signature, err: &'static str, logger: &L) -> Result<ChannelMonitor<ChanSigner>, ChannelError> where L::Target: Logger {
 		if self.commitment_secrets.get_min_seen_secret() != (1 << 48) ||
 				self.cur_remote_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER ||
 				self.cur_local_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER {
@@ -1581,8 +1868,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let their_funding_pubkey = &self.their_pubkeys.as_ref().unwrap().funding_pubkey;
 
 		// They sign the "local" commitment transaction, allowing us to broadcast the tx if we wish.
-		if let Err(_) = self.secp_ctx.verify(&local_sighash, &msg.signature, their_funding_pubkey) {
-			return Err(ChannelError::Close("Invalid funding_signed signature from peer"));
+		if let Err(_) = self.secp_ctx.verify(&local_sighash, sig, their_funding_pubkey) {
+			return Err(ChannelError::Close(err));
 		}
 
 		let their_pubkeys = self.their_pubkeys.as_ref().unwrap();
@@ -1591,10 +1878,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let funding_txo_script = funding_redeemscript.to_v0_p2wsh();
 		macro_rules! create_monitor {
 			() => { {
-				let local_commitment_tx = LocalCommitmentTransaction::new_missing_local_sig(local_initial_commitment_tx.clone(), msg.signature.clone(), &self.local_keys.pubkeys().funding_pubkey, their_funding_pubkey, local_keys.clone(), self.feerate_per_kw, Vec::new());
+				let local_commitment_tx = LocalCommitmentTransaction::new_missing_local_sig(local_initial_commitment_tx.clone(), sig.clone(), &self.local_keys.pubkeys().funding_pubkey, their_funding_pubkey, local_keys.clone(), self.feerate_per_kw, self.opt_anchors, Vec::new());
 				let mut channel_monitor = ChannelMonitor::new(self.local_keys.clone(),
 				                                              &self.shutdown_pubkey, self.our_to_self_delay,
-				                                              &self.destination_script, (funding_txo.clone(), funding_txo_script.clone()),
+				                                              &self.destination_script, (funding_txo.clone(), funding_txo_script.clone()), self.channel_id,
 				                                              &their_pubkeys.htlc_basepoint, &their_pubkeys.delayed_payment_basepoint,
 				                                              self.their_to_self_delay, funding_redeemscript.clone(), self.channel_value_satoshis,
 				                                              self.get_commitment_transaction_number_obscure_factor(),
@@ -1617,6 +1904,846 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		Ok(channel_monitor)
 	}
 
+	// Dual-funded channel establishment:
+
+	/// Derives the channel_id of a dual-funded channel, which is needed before its funding
+	/// transaction is known, from both sides' revocation basepoints.
+	fn get_v2_channel_id(&self) -> [u8; 32] {
+		let our_basepoint = self.local_keys.pubkeys().revocation_basepoint.serialize();
+		let their_basepoint = self.their_pubkeys.as_ref().unwrap().revocation_basepoint.serialize();
+		let mut sha = Sha256::engine();
+		if our_basepoint[..] < their_basepoint[..] {
+			sha.input(&our_basepoint);
+			sha.input(&their_basepoint);
+		} else {
+			sha.input(&their_basepoint);
+			sha.input(&our_basepoint);
+		}
+		Sha256::from_engine(sha).into_inner()
+	}
+
+	/// Returns true if this is an inbound dual-funded channel still waiting on the user to provide
+	/// our contribution via accept_dual_funded_channel.
+	pub fn is_awaiting_funding_contribution(&self) -> bool {
+		!self.channel_outbound && self.dual_funding.as_ref().map(|dual_funding| dual_funding.constructor.is_none()).unwrap_or(false)
+	}
+
+	/// Adds our contribution to an inbound dual-funded channel, returning the accept_channel2
+	/// message to send and beginning the construction of the funding transaction.
+	/// Note that channel_id changes during this call!
+	pub fn accept_dual_funded_channel(&mut self, contribution: FundingContribution, their_features: &InitFeatures) -> Result<msgs::AcceptChannelV2, APIError> {
+		if !self.is_awaiting_funding_contribution() {
+			return Err(APIError::APIMisuseError{err: "Channel is not awaiting a funding contribution"});
+		}
+		let funding_feerate_per_kw = self.dual_funding.as_ref().unwrap().funding_feerate_per_kw;
+		interactivetxs::check_holder_contribution(&contribution, false, false, funding_feerate_per_kw).map_err(|err| APIError::APIMisuseError{err})?;
+
+		let channel_value_satoshis = match self.channel_value_satoshis.checked_add(contribution.funding_satoshis) {
+			Some(value) if value <= TOTAL_BITCOIN_SUPPLY_SATOSHIS => value,
+			_ => return Err(APIError::APIMisuseError{err: "funding value > total bitcoin supply"}),
+		};
+		if channel_value_satoshis >= MAX_FUNDING_SATOSHIS && !their_features.supports_wumbo() {
+			return Err(APIError::APIMisuseError{err: "funding value > 2^24 - 1 and peer does not support large channels"});
+		}
+
+		self.channel_value_satoshis = channel_value_satoshis;
+		self.value_to_self_msat = contribution.funding_satoshis * 1000;
+		self.local_channel_reserve_satoshis = Channel::<ChanSigner>::get_remote_channel_reserve_satoshis(channel_value_satoshis);
+		self.their_max_htlc_value_in_flight_msat = cmp::min(self.their_max_htlc_value_in_flight_msat, channel_value_satoshis * 1000);
+		self.local_keys.set_channel_value_satoshis(channel_value_satoshis);
+
+		let accept_channel = self.get_accept_channel();
+		self.channel_id = self.get_v2_channel_id();
+		self.dual_funding.as_mut().unwrap().constructor = Some(InteractiveTxConstructor::new(self.channel_id, false, None, contribution.inputs, contribution.outputs));
+
+		Ok(msgs::AcceptChannelV2 {
+			temporary_channel_id: accept_channel.temporary_channel_id,
+			funding_satoshis: contribution.funding_satoshis,
+			dust_limit_satoshis: accept_channel.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: Channel::<ChanSigner>::get_our_max_htlc_value_in_flight_msat(channel_value_satoshis),
+			htlc_minimum_msat: accept_channel.htlc_minimum_msat,
+			minimum_depth: accept_channel.minimum_depth,
+			to_self_delay: accept_channel.to_self_delay,
+			max_accepted_htlcs: accept_channel.max_accepted_htlcs,
+			funding_pubkey: accept_channel.funding_pubkey,
+			revocation_basepoint: accept_channel.revocation_basepoint,
+			payment_point: accept_channel.payment_point,
+			delayed_payment_basepoint: accept_channel.delayed_payment_basepoint,
+			htlc_basepoint: accept_channel.htlc_basepoint,
+			first_per_commitment_point: accept_channel.first_per_commitment_point,
+			shutdown_scriptpubkey: accept_channel.shutdown_scriptpubkey,
+		})
+	}
+
+	/// Handles an accept_channel2 for an outbound dual-funded channel, beginning the construction
+	/// of the funding transaction. The first message to send our counterparty can then be fetched
+	/// with interactive_tx_next_message.
+	/// Note that channel_id changes during this call!
+	pub fn accept_channel_v2(&mut self, msg: &msgs::AcceptChannelV2, config: &UserConfig, their_features: InitFeatures) -> Result<(), ChannelError> {
+		if self.dual_funding.is_none() {
+			return Err(ChannelError::Close("Got an accept_channel2 message for a single-funded channel"));
+		}
+		let our_funding_satoshis = self.channel_value_satoshis;
+		let channel_value_satoshis = match our_funding_satoshis.checked_add(msg.funding_satoshis) {
+			Some(value) if value <= TOTAL_BITCOIN_SUPPLY_SATOSHIS => value,
+			_ => return Err(ChannelError::Close("funding value > total bitcoin supply")),
+		};
+
+		// Apply the same checks as for an accept_channel, against the combined channel value.
+		let accept_channel = msgs::AcceptChannel {
+			temporary_channel_id: msg.temporary_channel_id,
+			dust_limit_satoshis: msg.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: msg.max_htlc_value_in_flight_msat,
+			channel_reserve_satoshis: Channel::<ChanSigner>::get_remote_channel_reserve_satoshis(channel_value_satoshis),
+			htlc_minimum_msat: msg.htlc_minimum_msat,
+			minimum_depth: msg.minimum_depth,
+			to_self_delay: msg.to_self_delay,
+			max_accepted_htlcs: msg.max_accepted_htlcs,
+			funding_pubkey: msg.funding_pubkey,
+			revocation_basepoint: msg.revocation_basepoint,
+			payment_point: msg.payment_point,
+			delayed_payment_basepoint: msg.delayed_payment_basepoint,
+			htlc_basepoint: msg.htlc_basepoint,
+			first_per_commitment_point: msg.first_per_commitment_point,
+			shutdown_scriptpubkey: msg.shutdown_scriptpubkey.clone(),
+		};
+		self.channel_value_satoshis = channel_value_satoshis;
+		if let Err(e) = self.accept_channel(&accept_channel, config, their_features) {
+			self.channel_value_satoshis = our_funding_satoshis;
+			return Err(e);
+		}
+		self.local_keys.set_channel_value_satoshis(channel_value_satoshis);
+		self.channel_id = self.get_v2_channel_id();
+
+		let funding_output = TxOut {
+			value: channel_value_satoshis,
+			script_pubkey: self.get_funding_redeemscript().to_v0_p2wsh(),
+		};
+		let dual_funding = self.dual_funding.as_mut().unwrap();
+		let contribution = dual_funding.holder_contribution.take().unwrap();
+		dual_funding.counterparty_funding_satoshis = msg.funding_satoshis;
+		let mut outputs = vec![funding_output];
+		outputs.extend(contribution.outputs);
+		dual_funding.constructor = Some(InteractiveTxConstructor::new(self.channel_id, true, None, contribution.inputs, outputs));
+		Ok(())
+	}
+
+	fn interactive_tx_constructor(&mut self) -> Result<&mut InteractiveTxConstructor, ChannelError> {
+		let is_funded = self.channel_state & (ChannelState::ChannelFunded as u32) != 0;
+		let dual_funding_constructor = self.dual_funding.as_mut().and_then(|dual_funding| dual_funding.constructor.as_mut());
+		let splice_constructor = self.splice_negotiation.as_mut().and_then(|negotiation| negotiation.constructor.as_mut());
+		match dual_funding_constructor.or(splice_constructor) {
+			Some(constructor) => Ok(constructor),
+			// Our counterparty may still be sending messages for a splice we've just aborted.
+			None if is_funded => Err(ChannelError::Ignore("Received an interactive transaction message for a channel which isn't being spliced")),
+			None => Err(ChannelError::Close("Received an interactive transaction message for a channel which isn't constructing its funding transaction")),
+		}
+	}
+
+	/// Abandons the splice being negotiated, if any, upon an error in its negotiation, returning
+	/// the tx_abort to send our counterparty in place of the error. Errors constructing the funding
+	/// transaction of a dual-funded channel are returned as-is.
+	fn abort_splice_on_err(&mut self, res: Result<(Option<InteractiveTxMessageSend>, Option<msgs::CommitmentSigned>), ChannelError>) -> Result<(Option<InteractiveTxMessageSend>, Option<msgs::CommitmentSigned>), ChannelError> {
+		match res {
+			Err(e) => {
+				if self.splice_negotiation.take().is_none() {
+					return Err(e);
+				}
+				let data = match e {
+					ChannelError::Ignore(e) | ChannelError::Close(e) | ChannelError::CloseDelayBroadcast(e) => e,
+				};
+				Ok((Some(InteractiveTxMessageSend::TxAbort(msgs::TxAbort {
+					channel_id: self.channel_id,
+					data: data.as_bytes().to_vec(),
+				})), None))
+			},
+			Ok(res) => Ok(res),
+		}
+	}
+
+	/// Gets our next message in the construction of a dual-funded channel's funding transaction or
+	/// a splice transaction, if any, and, once construction is complete, the commitment_signed for
+	/// our counterparty's commitment transaction spending it.
+	pub fn interactive_tx_next_message<L: Deref>(&mut self, logger: &L) -> Result<(Option<InteractiveTxMessageSend>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		let res = self.get_interactive_tx_next_message(logger);
+		self.abort_splice_on_err(res)
+	}
+
+	fn get_interactive_tx_next_message<L: Deref>(&mut self, logger: &L) -> Result<(Option<InteractiveTxMessageSend>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		let (msg, complete) = {
+			let constructor = self.interactive_tx_constructor()?;
+			if constructor.is_complete() {
+				(None, true)
+			} else {
+				let msg = constructor.next_message();
+				(Some(msg), constructor.is_complete())
+			}
+		};
+		let commitment_signed = if !complete {
+			None
+		} else if self.splice_negotiation.is_some() {
+			Some(self.splice_tx_completed(logger)?)
+		} else {
+			Some(self.interactive_tx_completed(logger)?)
+		};
+		Ok((msg, commitment_signed))
+	}
+
+	pub fn tx_add_input<L: Deref>(&mut self, msg: &msgs::TxAddInput, logger: &L) -> Result<(Option<InteractiveTxMessageSend>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		let res = self.interactive_tx_constructor().and_then(|constructor| constructor.received_tx_add_input(msg))
+			.and_then(|_| self.get_interactive_tx_next_message(logger));
+		self.abort_splice_on_err(res)
+	}
+
+	pub fn tx_add_output<L: Deref>(&mut self, msg: &msgs::TxAddOutput, logger: &L) -> Result<(Option<InteractiveTxMessageSend>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		let res = self.interactive_tx_constructor().and_then(|constructor| constructor.received_tx_add_output(msg))
+			.and_then(|_| self.get_interactive_tx_next_message(logger));
+		self.abort_splice_on_err(res)
+	}
+
+	pub fn tx_complete<L: Deref>(&mut self, logger: &L) -> Result<(Option<InteractiveTxMessageSend>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		let res = self.interactive_tx_constructor().and_then(|constructor| constructor.received_tx_complete())
+			.and_then(|_| self.get_interactive_tx_next_message(logger));
+		self.abort_splice_on_err(res)
+	}
+
+	/// Builds the funding transaction once interactive construction completes, checking it pays
+	/// to our funding output and that our counterparty paid its share, and signs our
+	/// counterparty's initial commitment transaction.
+	fn interactive_tx_completed<L: Deref>(&mut self, logger: &L) -> Result<msgs::CommitmentSigned, ChannelError> where L::Target: Logger {
+		if self.commitment_secrets.get_min_seen_secret() != (1 << 48) ||
+				self.cur_remote_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER ||
+				self.cur_local_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER {
+			panic!("Should not have advanced channel commitment tx numbers prior to constructing the funding transaction");
+		}
+		let dual_funding = self.dual_funding.take().unwrap();
+		let constructor = dual_funding.constructor.unwrap();
+
+		let funding_output = TxOut {
+			value: self.channel_value_satoshis,
+			script_pubkey: self.get_funding_redeemscript().to_v0_p2wsh(),
+		};
+		constructor.check_counterparty_contribution(&funding_output, dual_funding.counterparty_funding_satoshis as i64, dual_funding.funding_feerate_per_kw)?;
+		let funding_tx = constructor.build_transaction(dual_funding.locktime);
+		let funding_output_index = funding_tx.output.iter().position(|output| *output == funding_output).unwrap();
+
+		self.funding_txo = Some(OutPoint { txid: funding_tx.txid(), index: funding_output_index as u16 });
+		let signature = match self.get_outbound_funding_created_signature(logger) {
+			Ok(res) => res,
+			Err(e) => {
+				self.funding_txo = None;
+				return Err(e);
+			}
+		};
+
+		let (our_inputs_value, their_inputs_value) = constructor.input_values();
+		let holder_sends_first = our_inputs_value < their_inputs_value || (our_inputs_value == their_inputs_value && self.channel_outbound);
+		self.funding_tx_signing = Some(FundingTxSigningState::new(funding_tx, constructor.holder_input_indices(), holder_sends_first, None));
+		self.channel_state = ChannelState::FundingCreated as u32;
+
+		Ok(msgs::CommitmentSigned {
+			channel_id: self.channel_id,
+			signature,
+			htlc_signatures: Vec::new(),
+			spliced_signatures: OptionalField::Absent,
+		})
+	}
+
+	/// Returns true if this is a dual-funded channel which has sent the commitment_signed for our
+	/// counterparty's initial commitment transaction and awaits theirs.
+	pub fn is_awaiting_initial_commitment_signed(&self) -> bool {
+		self.funding_tx_signing.is_some() && self.channel_state == ChannelState::FundingCreated as u32
+	}
+
+	/// Handles the commitment_signed for our initial commitment transaction in a dual-funded
+	/// channel, returning the ChannelMonitor to persist before signing the funding transaction.
+	pub fn initial_commitment_signed<L: Deref>(&mut self, msg: &msgs::CommitmentSigned, logger: &L) -> Result<ChannelMonitor<ChanSigner>, ChannelError> where L::Target: Logger {
+		if !self.is_awaiting_initial_commitment_signed() {
+			return Err(ChannelError::Close("Received initial commitment_signed in strange state!"));
+		}
+		if !msg.htlc_signatures.is_empty() {
+			return Err(ChannelError::Close("Received HTLC signatures for the initial commitment transaction"));
+		}
+		self.initial_commitment_monitor(&msg.signature, "Invalid initial commitment_signed signature from peer", logger)
+	}
+
+	/// Returns true if we may exchange tx_signatures for our pending splice transaction, ie we've
+	/// persisted the commitment transactions spending it.
+	fn is_splice_signing_ready(&self) -> bool {
+		self.pending_splice.as_ref().map(|splice| splice.received_commitment_signed).unwrap_or(false) &&
+			self.channel_state & (ChannelState::MonitorUpdateFailed as u32) == 0
+	}
+
+	/// Gets the funding transaction of a dual-funded channel, or our pending splice transaction, if
+	/// it still needs to be signed by the user, ie we contributed inputs and have yet to get their
+	/// witnesses.
+	pub fn get_unsigned_funding_tx(&self) -> Option<&Transaction> {
+		if let Some(ref splice) = self.pending_splice {
+			if !self.is_splice_signing_ready() {
+				return None;
+			}
+			return splice.signing.get_unsigned_funding_tx();
+		}
+		if !self.is_funding_initiated() {
+			return None;
+		}
+		self.funding_tx_signing.as_ref().and_then(|signing| signing.get_unsigned_funding_tx())
+	}
+
+	/// Gets our tx_signatures for a dual-funded channel or a pending splice, if they are ready to
+	/// be sent for the first time, and the funding transaction, if it is now fully signed and
+	/// should be broadcast.
+	pub fn get_tx_signatures(&mut self) -> (Option<msgs::TxSignatures>, Option<Transaction>) {
+		let signing_ready = if self.pending_splice.is_some() { self.is_splice_signing_ready() } else { self.is_funding_initiated() };
+		if !signing_ready {
+			return (None, None);
+		}
+		let channel_id = self.channel_id;
+		let signing = match self.pending_splice {
+			Some(ref mut splice) => Some(&mut splice.signing),
+			None => self.funding_tx_signing.as_mut(),
+		};
+		match signing {
+			Some(signing) => (signing.get_tx_signatures(channel_id), signing.get_signed_funding_tx().cloned()),
+			None => (None, None),
+		}
+	}
+
+	/// Provides the user-signed funding transaction of a dual-funded channel, or our pending splice
+	/// transaction, returning our tx_signatures, if they may now be sent, and the transaction, if
+	/// it is now fully signed and should be broadcast.
+	pub fn funding_transaction_signed(&mut self, signed_tx: &Transaction) -> Result<(Option<msgs::TxSignatures>, Option<Transaction>), APIError> {
+		if self.pending_splice.is_some() {
+			if !self.is_splice_signing_ready() {
+				return Err(APIError::APIMisuseError{err: "Channel is not awaiting splice transaction signatures"});
+			}
+			self.pending_splice.as_mut().unwrap().signing.provide_holder_witnesses(signed_tx).map_err(|err| APIError::APIMisuseError{err})?;
+			return Ok(self.get_tx_signatures());
+		}
+		if !self.is_funding_initiated() {
+			return Err(APIError::APIMisuseError{err: "Channel is not awaiting funding transaction signatures"});
+		}
+		match self.funding_tx_signing.as_mut() {
+			Some(signing) => signing.provide_holder_witnesses(signed_tx).map_err(|err| APIError::APIMisuseError{err})?,
+			None => return Err(APIError::APIMisuseError{err: "Channel is not dual-funded"}),
+		}
+		Ok(self.get_tx_signatures())
+	}
+
+	/// Handles our counterparty's tx_signatures, returning our own, if they may now be sent, and
+	/// the funding (or splice) transaction, if it is now fully signed and should be broadcast.
+	pub fn tx_signatures(&mut self, msg: &msgs::TxSignatures) -> Result<(Option<msgs::TxSignatures>, Option<Transaction>), ChannelError> {
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent tx_signatures when we needed a channel_reestablish"));
+		}
+		if self.pending_splice.is_some() {
+			return self.splice_tx_signatures(msg);
+		}
+		if msg.shared_input_signature.is_some() && self.channel_state & (ChannelState::ChannelFunded as u32) != 0 {
+			// We may have abandoned the splice after our counterparty sent these.
+			return Err(ChannelError::Ignore("Peer sent tx_signatures for a splice which was abandoned"));
+		}
+		if !self.is_funding_initiated() {
+			return Err(ChannelError::Close("Peer sent tx_signatures before exchanging commitment_signed"));
+		}
+		match self.funding_tx_signing.as_mut() {
+			Some(signing) => signing.received_tx_signatures(msg)?,
+			None => return Err(ChannelError::Close("Peer sent tx_signatures for a single-funded channel")),
+		}
+		Ok(self.get_tx_signatures())
+	}
+
+	fn splice_tx_signatures(&mut self, msg: &msgs::TxSignatures) -> Result<(Option<msgs::TxSignatures>, Option<Transaction>), ChannelError> {
+		{
+			let splice = self.pending_splice.as_ref().unwrap();
+			if !splice.received_commitment_signed {
+				return Err(ChannelError::Close("Peer sent tx_signatures before exchanging commitment_signed for the splice transaction"));
+			}
+			let splice_tx = splice.signing.get_funding_tx();
+			if let (Some(idx), Some(sig), true) = (splice.signing.shared_input_index(), msg.shared_input_signature.as_ref(), msg.txid == splice_tx.txid()) {
+				// The shared input spends our current funding output.
+				let sighash = hash_to_message!(&bip143::SighashComponents::new(splice_tx).sighash_all(&splice_tx.input[idx as usize], &self.get_funding_redeemscript(), self.channel_value_satoshis)[..]);
+				secp_check!(self.secp_ctx.verify(&sighash, sig, self.their_funding_pubkey()), "Invalid shared input signature in tx_signatures from peer");
+			}
+		}
+		self.pending_splice.as_mut().unwrap().signing.received_tx_signatures(msg)?;
+		Ok(self.get_tx_signatures())
+	}
+
+	/// Gets the tx_signatures we previously sent for a dual-funded channel or a pending splice, to
+	/// be retransmitted on reconnection until our counterparty tells us the transaction confirmed.
+	pub fn get_tx_signatures_to_resend(&self) -> Option<msgs::TxSignatures> {
+		if let Some(ref splice) = self.pending_splice {
+			if splice.received_splice_locked {
+				return None;
+			}
+			return splice.signing.get_sent_tx_signatures(self.channel_id);
+		}
+		if self.channel_state & !MULTI_STATE_FLAGS != ChannelState::FundingSent as u32 &&
+				self.channel_state & !MULTI_STATE_FLAGS != ChannelState::FundingSent as u32 | ChannelState::OurFundingLocked as u32 {
+			return None;
+		}
+		self.funding_tx_signing.as_ref().and_then(|signing| signing.get_sent_tx_signatures(self.channel_id))
+	}
+
+	// Splicing:
+
+	/// Returns true if the channel has no pending updates and isn't already being spliced, ie it
+	/// may be spliced.
+	fn is_quiescent(&self) -> bool {
+		self.channel_state == ChannelState::ChannelFunded as u32 &&
+			self.pending_inbound_htlcs.is_empty() && self.pending_outbound_htlcs.is_empty() &&
+			self.holding_cell_htlc_updates.is_empty() &&
+			self.pending_update_fee.is_none() && self.holding_cell_update_fee.is_none() &&
+			self.splice_negotiation.is_none() && self.pending_splice.is_none()
+	}
+
+	/// Returns true if the channel is being spliced, ie until the splice transaction locks or the
+	/// splice is abandoned.
+	pub fn is_splicing(&self) -> bool {
+		self.splice_negotiation.is_some() || self.pending_splice.is_some()
+	}
+
+	/// Returns true while a splice is being negotiated, ie until both we and our counterparty have
+	/// sent tx_signatures for the splice transaction, during which the channel must remain
+	/// quiescent.
+	fn is_negotiating_splice(&self) -> bool {
+		self.splice_negotiation.is_some() || (self.pending_splice.is_some() && !self.is_splice_signed())
+	}
+
+	/// Returns true once both we and our counterparty have sent tx_signatures for our pending
+	/// splice transaction, after which every new commitment transaction must be signed for its
+	/// funding output as well until it locks.
+	fn is_splice_signed(&self) -> bool {
+		self.pending_splice.as_ref().map(|splice| splice.received_commitment_signed &&
+			splice.signing.has_sent_tx_signatures() && splice.signing.get_signed_funding_tx().is_some()).unwrap_or(false)
+	}
+
+	/// Gets the channel value and our balance, in msat, once a splice with the given contributions
+	/// locks, checking that whichever side splices funds out of the channel can afford to.
+	fn get_spliced_balances(&self, holder_contribution_satoshis: i64, counterparty_contribution_satoshis: i64) -> Result<(u64, u64), &'static str> {
+		let channel_value_satoshis = self.channel_value_satoshis as i128 + holder_contribution_satoshis as i128 + counterparty_contribution_satoshis as i128;
+		if channel_value_satoshis > TOTAL_BITCOIN_SUPPLY_SATOSHIS as i128 {
+			return Err("Spliced channel value > total bitcoin supply");
+		}
+		let value_to_self_msat = self.value_to_self_msat as i128 + holder_contribution_satoshis as i128 * 1000;
+		let value_to_remote_msat = channel_value_satoshis * 1000 - value_to_self_msat;
+		if value_to_self_msat < 0 || value_to_remote_msat < 0 {
+			return Err("Splice-out amount exceeds channel balance");
+		}
+		// Whoever splices funds out must keep the reserve for the new channel value, as well as the
+		// commitment transaction fee if it's the funder.
+		let reserve_msat = Channel::<ChanSigner>::get_remote_channel_reserve_satoshis(channel_value_satoshis as u64) as i128 * 1000;
+		let commit_tx_fee_msat = self.commit_tx_fee_msat(0) as i128;
+		if holder_contribution_satoshis < 0 && value_to_self_msat < reserve_msat + if self.channel_outbound { commit_tx_fee_msat } else { 0 } {
+			return Err("Splicing out would leave our balance below the channel reserve");
+		}
+		if counterparty_contribution_satoshis < 0 && value_to_remote_msat < reserve_msat + if self.channel_outbound { 0 } else { commit_tx_fee_msat } {
+			return Err("Splicing out would leave the counterparty's balance below the channel reserve");
+		}
+		Ok((channel_value_satoshis as u64, value_to_self_msat as u64))
+	}
+
+	/// Gets the channel's current funding output, which is the shared input of a splice
+	/// transaction.
+	fn get_splice_shared_input(&self) -> (BitcoinOutPoint, TxOut) {
+		(self.funding_txo.unwrap().into_bitcoin_outpoint(), TxOut {
+			value: self.channel_value_satoshis,
+			script_pubkey: self.get_funding_redeemscript().to_v0_p2wsh(),
+		})
+	}
+
+	fn begin_splice(&mut self, holder_contribution_satoshis: i64, inputs: Vec<FundingInput>, outputs: Vec<TxOut>, funding_feerate_per_kw: u32, locktime: u32, their_features: &InitFeatures) -> Result<msgs::SpliceInit, APIError> {
+		if !their_features.supports_splicing() {
+			return Err(APIError::APIMisuseError{err: "Peer does not support splicing"});
+		}
+		if !self.is_quiescent() {
+			return Err(APIError::ChannelUnavailable{err: "Channel cannot be spliced while it has pending updates or is already being spliced"});
+		}
+		let (channel_value_satoshis, _) = self.get_spliced_balances(holder_contribution_satoshis, 0).map_err(|err| APIError::APIMisuseError{err})?;
+		if channel_value_satoshis >= MAX_FUNDING_SATOSHIS && !their_features.supports_wumbo() {
+			return Err(APIError::APIMisuseError{err: "Spliced channel value > 2^24 - 1 and peer does not support large channels"});
+		}
+
+		self.splice_negotiation = Some(SpliceNegotiation {
+			is_initiator: true,
+			funding_feerate_per_kw,
+			locktime,
+			holder_contribution_satoshis,
+			counterparty_contribution_satoshis: 0,
+			holder_inputs: inputs,
+			holder_outputs: outputs,
+			constructor: None,
+		});
+		Ok(msgs::SpliceInit {
+			channel_id: self.channel_id,
+			funding_contribution_satoshis: holder_contribution_satoshis,
+			funding_feerate_per_kw,
+			locktime,
+			funding_pubkey: self.local_keys.pubkeys().funding_pubkey,
+		})
+	}
+
+	/// Begins splicing the given contribution into the channel, returning the splice_init message
+	/// to send. The contribution's inputs must pay for its outputs and their fees, as well as for
+	/// the common fields of the splice transaction, its shared input and the new funding output.
+	pub fn splice_in(&mut self, contribution: FundingContribution, funding_feerate_per_kw: u32, locktime: u32, their_features: &InitFeatures) -> Result<msgs::SpliceInit, APIError> {
+		interactivetxs::check_holder_contribution(&contribution, true, true, funding_feerate_per_kw).map_err(|err| APIError::APIMisuseError{err})?;
+		if contribution.funding_satoshis == 0 || contribution.funding_satoshis > TOTAL_BITCOIN_SUPPLY_SATOSHIS {
+			return Err(APIError::APIMisuseError{err: "Splice-in amount must be non-zero and no greater than the total bitcoin supply"});
+		}
+		self.begin_splice(contribution.funding_satoshis as i64, contribution.inputs, contribution.outputs, funding_feerate_per_kw, locktime, their_features)
+	}
+
+	/// Begins splicing the given amount out of the channel to the given script, returning the
+	/// splice_init message to send. The splice transaction's fees are paid from our channel
+	/// balance along with the amount.
+	pub fn splice_out(&mut self, amount_satoshis: u64, script_pubkey: Script, funding_feerate_per_kw: u32, locktime: u32, their_features: &InitFeatures) -> Result<msgs::SpliceInit, APIError> {
+		if amount_satoshis < interactivetxs::MIN_OUTPUT_VALUE_SATOSHIS || amount_satoshis > TOTAL_BITCOIN_SUPPLY_SATOSHIS {
+			return Err(APIError::APIMisuseError{err: "Splice-out amount must be above the dust limit and no greater than the total bitcoin supply"});
+		}
+		let output = TxOut { value: amount_satoshis, script_pubkey };
+		let fee_satoshis = interactivetxs::splice_out_fee_satoshis(funding_feerate_per_kw, &output);
+		self.begin_splice(-((amount_satoshis + fee_satoshis) as i64), Vec::new(), vec![output], funding_feerate_per_kw, locktime, their_features)
+	}
+
+	/// Handles a splice_init from our counterparty. We don't contribute to splices we didn't
+	/// initiate, so we accept it as long as the channel may be spliced, returning the splice_ack to
+	/// send. A ChannelError::Ignore indicates the splice should be rejected with a tx_abort.
+	pub fn splice_init<F: Deref>(&mut self, msg: &msgs::SpliceInit, fee_estimator: &F, their_features: &InitFeatures) -> Result<msgs::SpliceAck, ChannelError> where F::Target: FeeEstimator {
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent splice_init when we needed a channel_reestablish"));
+		}
+		if self.channel_state & (ChannelState::ChannelFunded as u32) == 0 {
+			return Err(ChannelError::Close("Peer sent splice_init for a channel which isn't funded"));
+		}
+		if msg.funding_pubkey != *self.their_funding_pubkey() {
+			return Err(ChannelError::Close("Peer sent splice_init with a different funding_pubkey"));
+		}
+		if !self.is_quiescent() {
+			return Err(ChannelError::Ignore("Peer sent splice_init while the channel has pending updates or is already being spliced"));
+		}
+		if Channel::<ChanSigner>::check_remote_fee(fee_estimator, msg.funding_feerate_per_kw).is_err() {
+			return Err(ChannelError::Ignore("Peer sent splice_init with a funding_feerate_per_kw out of range"));
+		}
+		let (channel_value_satoshis, _) = self.get_spliced_balances(0, msg.funding_contribution_satoshis).map_err(|e| ChannelError::Ignore(e))?;
+		if channel_value_satoshis >= MAX_FUNDING_SATOSHIS && !their_features.supports_wumbo() {
+			return Err(ChannelError::Ignore("Spliced channel value > 2^24 - 1 and peer does not support large channels"));
+		}
+
+		let shared_input = self.get_splice_shared_input();
+		self.splice_negotiation = Some(SpliceNegotiation {
+			is_initiator: false,
+			funding_feerate_per_kw: msg.funding_feerate_per_kw,
+			locktime: msg.locktime,
+			holder_contribution_satoshis: 0,
+			counterparty_contribution_satoshis: msg.funding_contribution_satoshis,
+			holder_inputs: Vec::new(),
+			holder_outputs: Vec::new(),
+			constructor: Some(InteractiveTxConstructor::new(self.channel_id, false, Some(shared_input), Vec::new(), Vec::new())),
+		});
+		Ok(msgs::SpliceAck {
+			channel_id: self.channel_id,
+			funding_contribution_satoshis: 0,
+			funding_pubkey: self.local_keys.pubkeys().funding_pubkey,
+		})
+	}
+
+	/// Handles a splice_ack for a splice we initiated, beginning the construction of the splice
+	/// transaction with our first message.
+	pub fn splice_ack<L: Deref>(&mut self, msg: &msgs::SpliceAck, logger: &L) -> Result<(Option<InteractiveTxMessageSend>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent splice_ack when we needed a channel_reestablish"));
+		}
+		let holder_contribution_satoshis = match self.splice_negotiation {
+			Some(ref negotiation) if negotiation.is_initiator && negotiation.constructor.is_none() => negotiation.holder_contribution_satoshis,
+			_ => return Err(ChannelError::Ignore("Peer sent splice_ack for a splice we didn't initiate")),
+		};
+		if msg.funding_pubkey != *self.their_funding_pubkey() {
+			return Err(ChannelError::Close("Peer sent splice_ack with a different funding_pubkey"));
+		}
+		let channel_value_satoshis = match self.get_spliced_balances(holder_contribution_satoshis, msg.funding_contribution_satoshis) {
+			Ok((channel_value_satoshis, _)) => channel_value_satoshis,
+			Err(e) => return self.abort_splice_on_err(Err(ChannelError::Ignore(e))),
+		};
+
+		let funding_output = TxOut {
+			value: channel_value_satoshis,
+			script_pubkey: self.get_funding_redeemscript().to_v0_p2wsh(),
+		};
+		let shared_input = self.get_splice_shared_input();
+		let channel_id = self.channel_id;
+		let negotiation = self.splice_negotiation.as_mut().unwrap();
+		negotiation.counterparty_contribution_satoshis = msg.funding_contribution_satoshis;
+		let mut outputs = vec![funding_output];
+		outputs.extend(negotiation.holder_outputs.drain(..));
+		let inputs = mem::replace(&mut negotiation.holder_inputs, Vec::new());
+		negotiation.constructor = Some(InteractiveTxConstructor::new(channel_id, true, Some(shared_input), inputs, outputs));
+		self.interactive_tx_next_message(logger)
+	}
+
+	/// Builds the splice transaction once interactive construction completes, checking that it
+	/// pays to the new funding output and that our counterparty paid its share, and signs our
+	/// counterparty's commitment transaction spending it.
+	fn splice_tx_completed<L: Deref>(&mut self, logger: &L) -> Result<msgs::CommitmentSigned, ChannelError> where L::Target: Logger {
+		let (channel_value_satoshis, value_to_self_msat) = {
+			let negotiation = self.splice_negotiation.as_ref().unwrap();
+			self.get_spliced_balances(negotiation.holder_contribution_satoshis, negotiation.counterparty_contribution_satoshis).map_err(|e| ChannelError::Ignore(e))?
+		};
+		let funding_redeemscript = self.get_funding_redeemscript();
+		let funding_output = TxOut {
+			value: channel_value_satoshis,
+			script_pubkey: funding_redeemscript.to_v0_p2wsh(),
+		};
+
+		let (splice_txid, funding_output_index, signing) = {
+			let negotiation = self.splice_negotiation.as_ref().unwrap();
+			let constructor = negotiation.constructor.as_ref().unwrap();
+			constructor.check_counterparty_contribution(&funding_output, negotiation.counterparty_contribution_satoshis, negotiation.funding_feerate_per_kw)?;
+			let splice_tx = constructor.build_transaction(negotiation.locktime);
+			let funding_output_index = splice_tx.output.iter().position(|output| *output == funding_output).unwrap();
+
+			let shared_input_index = constructor.shared_input_index().unwrap();
+			let holder_signature = self.local_keys.sign_splice_funding_input(&splice_tx, shared_input_index as usize, &self.secp_ctx)
+				.map_err(|_| ChannelError::Close("Failed to sign the splice transaction's shared input"))?;
+			let shared_input = SharedInputSignatures {
+				index: shared_input_index,
+				witness_script: funding_redeemscript,
+				holder_sig_first: self.local_keys.pubkeys().funding_pubkey.serialize()[..] < self.their_funding_pubkey().serialize()[..],
+				holder_signature,
+				counterparty_signature: None,
+			};
+
+			let (our_inputs_value, their_inputs_value) = constructor.input_values();
+			let holder_sends_first = our_inputs_value < their_inputs_value || (our_inputs_value == their_inputs_value && negotiation.is_initiator);
+			(splice_tx.txid(), funding_output_index, FundingTxSigningState::new(splice_tx, constructor.holder_input_indices(), holder_sends_first, Some(shared_input)))
+		};
+
+		self.pending_splice = Some(PendingSplice {
+			funding_txo: OutPoint { txid: splice_txid, index: funding_output_index as u16 },
+			channel_value_satoshis,
+			value_to_self_msat,
+			local_channel_reserve_satoshis: Channel::<ChanSigner>::get_remote_channel_reserve_satoshis(channel_value_satoshis),
+			signing,
+			received_commitment_signed: false,
+			confirmations: 0,
+			confirmed_in: None,
+			short_channel_id: None,
+			sent_splice_locked: false,
+			received_splice_locked: false,
+		});
+		let signature = match self.with_pending_splice_funding(|chan| chan.sign_spliced_remote_commitment(logger)) {
+			Ok(signature) => signature,
+			Err(e) => {
+				self.pending_splice = None;
+				return Err(e);
+			}
+		};
+		self.splice_negotiation = None;
+
+		Ok(msgs::CommitmentSigned {
+			channel_id: self.channel_id,
+			signature,
+			htlc_signatures: Vec::new(),
+			spliced_signatures: OptionalField::Absent,
+		})
+	}
+
+	/// Swaps the channel's funding parameters with those of the pending splice.
+	fn swap_pending_splice_funding(&mut self) {
+		let splice = self.pending_splice.as_mut().unwrap();
+		let funding_txo = self.funding_txo.replace(splice.funding_txo);
+		splice.funding_txo = funding_txo.unwrap();
+		mem::swap(&mut self.channel_value_satoshis, &mut splice.channel_value_satoshis);
+		mem::swap(&mut self.value_to_self_msat, &mut splice.value_to_self_msat);
+		mem::swap(&mut self.local_channel_reserve_satoshis, &mut splice.local_channel_reserve_satoshis);
+		self.local_keys.set_channel_value_satoshis(self.channel_value_satoshis);
+		#[cfg(debug_assertions)]
+		{
+			// Our balances move relative to the reserves, so start tracking them afresh.
+			*self.max_commitment_tx_output_local.lock().unwrap() = (0, 0);
+			*self.max_commitment_tx_output_remote.lock().unwrap() = (0, 0);
+		}
+	}
+
+	/// Calls the given closure with the channel's funding parameters temporarily replaced by those
+	/// of the pending splice, eg to build the commitment transactions spending its funding output.
+	fn with_pending_splice_funding<R, F: FnOnce(&mut Self) -> R>(&mut self, f: F) -> R {
+		self.swap_pending_splice_funding();
+		let res = f(self);
+		self.swap_pending_splice_funding();
+		res
+	}
+
+	/// Builds our counterparty's current commitment transaction. As the channel is quiescent while
+	/// negotiating a splice, we re-sign it (and they re-sign ours) for the splice transaction's
+	/// funding output.
+	fn build_current_remote_commitment_tx<L: Deref>(&self, logger: &L) -> Result<(Transaction, TxCreationKeys), ChannelError> where L::Target: Logger {
+		let remote_keys = self.build_remote_transaction_keys_with_point(&self.their_prev_commitment_point.unwrap())?;
+		let remote_commitment_tx = self.build_commitment_transaction(self.cur_remote_commitment_transaction_number + 1, &remote_keys, false, true, self.feerate_per_kw, logger).0;
+		Ok((remote_commitment_tx, remote_keys))
+	}
+
+	fn sign_spliced_remote_commitment<L: Deref>(&self, logger: &L) -> Result<Signature, ChannelError> where L::Target: Logger {
+		let (remote_commitment_tx, remote_keys) = self.build_current_remote_commitment_tx(logger)?;
+		Ok(self.local_keys.sign_remote_commitment(self.feerate_per_kw, &remote_commitment_tx, &remote_keys, &Vec::new(), self.our_to_self_delay, self.opt_anchors, &self.secp_ctx)
+			.map_err(|_| ChannelError::Close("Failed to get signatures for splice commitment_signed"))?.0)
+	}
+
+	/// Checks our counterparty's signature for our current commitment transaction, re-built for the
+	/// splice transaction's funding output, returning it along with their commitment transaction.
+	fn check_spliced_commitment_signature<L: Deref>(&self, sig: &Signature, logger: &L) -> Result<(LocalCommitmentTransaction, Transaction), ChannelError> where L::Target: Logger {
+		let funding_script = self.get_funding_redeemscript();
+		let local_keys = self.build_local_transaction_keys(self.cur_local_commitment_transaction_number + 1)?;
+		let local_commitment_tx = self.build_commitment_transaction(self.cur_local_commitment_transaction_number + 1, &local_keys, true, false, self.feerate_per_kw, logger).0;
+		let local_sighash = hash_to_message!(&bip143::SighashComponents::new(&local_commitment_tx).sighash_all(&local_commitment_tx.input[0], &funding_script, self.channel_value_satoshis)[..]);
+		log_trace!(logger, "Checking splice commitment tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(sig.serialize_compact()[..]), log_bytes!(self.their_funding_pubkey().serialize()), encode::serialize_hex(&local_commitment_tx), log_bytes!(local_sighash[..]), encode::serialize_hex(&funding_script));
+		secp_check!(self.secp_ctx.verify(&local_sighash, sig, self.their_funding_pubkey()), "Invalid splice commitment_signed signature from peer");
+
+		let local_commitment_tx = LocalCommitmentTransaction::new_missing_local_sig(local_commitment_tx, sig.clone(), &self.local_keys.pubkeys().funding_pubkey, self.their_funding_pubkey(), local_keys, self.feerate_per_kw, self.opt_anchors, Vec::new());
+		let (remote_commitment_tx, _) = self.build_current_remote_commitment_tx(logger)?;
+		Ok((local_commitment_tx, remote_commitment_tx))
+	}
+
+	/// Returns true if we've sent the commitment_signed for our counterparty's commitment
+	/// transaction spending a pending splice transaction and await theirs.
+	pub fn is_awaiting_splice_commitment_signed(&self) -> bool {
+		self.pending_splice.as_ref().map(|splice| !splice.received_commitment_signed).unwrap_or(false)
+	}
+
+	/// Handles the commitment_signed for our commitment transaction spending a pending splice
+	/// transaction, returning the ChannelMonitorUpdate to persist before signing the splice
+	/// transaction.
+	pub fn splice_commitment_signed<L: Deref>(&mut self, msg: &msgs::CommitmentSigned, logger: &L) -> Result<ChannelMonitorUpdate, ChannelError> where L::Target: Logger {
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent commitment_signed when we needed a channel_reestablish"));
+		}
+		if !self.is_awaiting_splice_commitment_signed() {
+			return Err(ChannelError::Close("Received splice commitment_signed in strange state!"));
+		}
+		if !msg.htlc_signatures.is_empty() {
+			return Err(ChannelError::Close("Received HTLC signatures for a splice commitment transaction"));
+		}
+		let (local_commitment_tx, unsigned_remote_commitment_tx) = self.with_pending_splice_funding(|chan| chan.check_spliced_commitment_signature(&msg.signature, logger))?;
+
+		let splice = self.pending_splice.as_mut().unwrap();
+		splice.received_commitment_signed = true;
+		let funding_txo = splice.funding_txo;
+		let channel_value_satoshis = splice.channel_value_satoshis;
+		self.latest_monitor_update_id += 1;
+		let monitor_update = ChannelMonitorUpdate {
+			update_id: self.latest_monitor_update_id,
+			updates: vec![ChannelMonitorUpdateStep::SpliceNegotiated {
+				funding_txo,
+				channel_value_satoshis,
+				local_commitment_tx,
+				unsigned_remote_commitment_tx,
+			}],
+		};
+		self.channel_monitor.as_mut().unwrap().update_monitor_ooo(monitor_update.clone(), logger).unwrap();
+		Ok(monitor_update)
+	}
+
+	/// Handles a tx_abort from our counterparty, abandoning the splice being negotiated, if we
+	/// haven't yet sent our tx_signatures for it. Returns the tx_abort to echo back.
+	pub fn tx_abort(&mut self) -> Result<msgs::TxAbort, ChannelError> {
+		if self.dual_funding.is_some() {
+			return Err(ChannelError::Close("Peer aborted the construction of the funding transaction"));
+		}
+		if self.splice_negotiation.take().is_none() {
+			match self.pending_splice {
+				Some(ref splice) if !splice.signing.has_sent_tx_signatures() => {},
+				Some(_) => return Err(ChannelError::Close("Peer sent tx_abort for a splice we've already signed")),
+				None => return Err(ChannelError::Ignore("Peer sent tx_abort for a channel which isn't being spliced")),
+			}
+			self.pending_splice = None;
+		}
+		Ok(msgs::TxAbort {
+			channel_id: self.channel_id,
+			data: b"Splice aborted".to_vec(),
+		})
+	}
+
+	/// Gets the funding outpoint of a pending splice transaction, spends of which close the
+	/// channel as well.
+	pub fn get_pending_splice_funding_txo(&self) -> Option<OutPoint> {
+		self.pending_splice.as_ref().map(|splice| splice.funding_txo)
+	}
+
+	/// Returns true if the given transaction is our pending splice transaction, which spends the
+	/// channel's funding output without closing it.
+	pub fn is_pending_splice_tx(&self, txid: &Txid) -> bool {
+		self.pending_splice.as_ref().map(|splice| splice.funding_txo.txid == *txid).unwrap_or(false)
+	}
+
+	/// Handles a splice_locked from our counterparty.
+	pub fn splice_locked(&mut self, msg: &msgs::SpliceLocked) -> Result<(), ChannelError> {
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent splice_locked when we needed a channel_reestablish"));
+		}
+		match self.pending_splice {
+			Some(ref mut splice) if splice.funding_txo.txid == msg.splice_txid && splice.signing.has_sent_tx_signatures() => {
+				splice.received_splice_locked = true;
+				Ok(())
+			},
+			// They may retransmit their splice_locked on reconnection after the splice completed.
+			None if self.funding_txo.map(|funding_txo| funding_txo.txid) == Some(msg.splice_txid) => Ok(()),
+			_ => Err(ChannelError::Close("Peer sent splice_locked for an unknown splice transaction")),
+		}
+	}
+
+	/// Gets the splice_locked to retransmit on reconnection, if our counterparty may not have
+	/// received it.
+	pub fn get_splice_locked_to_resend(&self) -> Option<msgs::SpliceLocked> {
+		match self.pending_splice {
+			Some(ref splice) if splice.sent_splice_locked => Some(msgs::SpliceLocked {
+				channel_id: self.channel_id,
+				splice_txid: splice.funding_txo.txid,
+			}),
+			Some(_) => None,
+			None if self.splice_locked_commitment_number == Some(self.cur_local_commitment_transaction_number) => Some(msgs::SpliceLocked {
+				channel_id: self.channel_id,
+				splice_txid: self.funding_txo.unwrap().txid,
+			}),
+			None => None,
+		}
+	}
+
+	/// Completes the pending splice once both sides have sent splice_locked, switching the channel
+	/// over to the splice transaction's funding output. Returns the ChannelMonitorUpdate to
+	/// provide, which, as for any other update, is keyed by get_original_funding_txo.
+	pub fn complete_splice<L: Deref>(&mut self, logger: &L) -> Option<ChannelMonitorUpdate> where L::Target: Logger {
+		match self.pending_splice {
+			Some(ref splice) if splice.sent_splice_locked && splice.received_splice_locked => {},
+			_ => return None,
+		}
+		if self.channel_state & (ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32) != 0 {
+			return None;
+		}
+		self.swap_pending_splice_funding();
+		let splice = self.pending_splice.take().unwrap();
+		log_info!(logger, "Splice transaction {} locked for channel {}", splice.signing.get_funding_tx().txid(), log_bytes!(self.channel_id()));
+		if self.original_funding_txo.is_none() {
+			self.original_funding_txo = Some(splice.funding_txo);
+		}
+		if let Some(short_id) = self.short_channel_id {
+			self.prev_short_channel_ids.push(short_id);
+		}
+		self.funding_tx_confirmations = splice.confirmations as u64;
+		self.funding_tx_confirmed_in = splice.confirmed_in;
+		self.short_channel_id = splice.short_channel_id;
+		self.splice_locked_commitment_number = Some(self.cur_local_commitment_transaction_number);
+		self.update_time_counter += 1;
+
+		self.latest_monitor_update_id += 1;
+		let monitor_update = ChannelMonitorUpdate {
+			update_id: self.latest_monitor_update_id,
+			updates: vec![ChannelMonitorUpdateStep::SpliceLocked],
+		};
+		self.channel_monitor.as_mut().unwrap().update_monitor_ooo(monitor_update.clone(), logger).unwrap();
+		Some(monitor_update)
+	}
+
 	pub fn funding_locked(&mut self, msg: &msgs::FundingLocked) -> Result<(), ChannelError> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent funding_locked when we needed a channel_reestablish"));
@@ -1683,9 +2810,19 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	/// if-we-removed-it-already-but-haven't-fully-resolved-they-can-still-send-an-inbound-HTLC
 	/// corner case properly.
 	pub fn get_inbound_outbound_available_balance_msat(&self) -> (u64, u64) {
+		let htlc_inbound_value_msat = self.get_inbound_pending_htlc_stats().1 as i64;
+		let htlc_outbound_value_msat = self.get_outbound_pending_htlc_stats().1 as i64;
 		// Note that we have to handle overflow due to the above case.
-		(cmp::min(self.channel_value_satoshis as i64 * 1000 - self.value_to_self_msat as i64 - self.get_inbound_pending_htlc_stats().1 as i64, 0) as u64,
-		cmp::min(self.value_to_self_msat as i64 - self.get_outbound_pending_htlc_stats().1 as i64, 0) as u64)
+		let mut inbound_msat = cmp::max(self.channel_value_satoshis as i64 * 1000 - self.value_to_self_msat as i64 - htlc_inbound_value_msat, 0) as u64;
+		let mut outbound_msat = cmp::max(self.value_to_self_msat as i64 - htlc_outbound_value_msat, 0) as u64;
+		if self.is_splice_signed() {
+			// HTLCs are added to the commitment transactions spending our pending splice transaction
+			// as well, so must fit in our balances there too.
+			let splice = self.pending_splice.as_ref().unwrap();
+			inbound_msat = cmp::min(inbound_msat, cmp::max(splice.channel_value_satoshis as i64 * 1000 - splice.value_to_self_msat as i64 - htlc_inbound_value_msat, 0) as u64);
+			outbound_msat = cmp::min(outbound_msat, cmp::max(splice.value_to_self_msat as i64 - htlc_outbound_value_msat, 0) as u64);
+		}
+		(inbound_msat, outbound_msat)
 	}
 
 	// Get the fee cost of a commitment tx with a given number of HTLC outputs.
@@ -1693,7 +2830,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	fn commit_tx_fee_msat(&self, num_htlcs: usize) -> u64 {
 		// Note that we need to divide before multiplying to round properly,
 		// since the lowest denomination of bitcoin on-chain is the satoshi.
-		(COMMITMENT_TX_BASE_WEIGHT + num_htlcs as u64 * COMMITMENT_TX_WEIGHT_PER_HTLC) * self.feerate_per_kw as u64 / 1000 * 1000
+		(commitment_tx_base_weight(self.opt_anchors) + num_htlcs as u64 * COMMITMENT_TX_WEIGHT_PER_HTLC) * self.feerate_per_kw as u64 / 1000 * 1000
+			+ anchor_outputs_value_satoshis(self.opt_anchors) * 1000
 	}
 
 	// Get the commitment tx fee for the local (i.e our) next commitment transaction
@@ -1755,37 +2893,16 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.commit_tx_fee_msat(their_acked_htlcs + addl_htlcs)
 	}
 
-	pub fn update_add_htlc<F, L: Deref>(&mut self, msg: &msgs::UpdateAddHTLC, mut pending_forward_status: PendingHTLCStatus, create_pending_htlc_status: F, logger: &L) -> Result<(), ChannelError>
-	where F: for<'a> Fn(&'a Self, PendingHTLCStatus, u16) -> PendingHTLCStatus, L::Target: Logger {
-		// We can't accept HTLCs sent after we've sent a shutdown.
-		let local_sent_shutdown = (self.channel_state & (ChannelState::ChannelFunded as u32 | ChannelState::LocalShutdownSent as u32)) != (ChannelState::ChannelFunded as u32);
-		if local_sent_shutdown {
-			pending_forward_status = create_pending_htlc_status(self, pending_forward_status, 0x1000|20);
-		}
-		// If the remote has sent a shutdown prior to adding this HTLC, then they are in violation of the spec.
-		let remote_sent_shutdown = (self.channel_state & (ChannelState::ChannelFunded as u32 | ChannelState::RemoteShutdownSent as u32)) != (ChannelState::ChannelFunded as u32);
-		if remote_sent_shutdown {
-			return Err(ChannelError::Close("Got add HTLC message when channel was not in an operational state"));
-		}
-		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
-			return Err(ChannelError::Close("Peer sent update_add_htlc when we needed a channel_reestablish"));
-		}
-		if msg.amount_msat > self.channel_value_satoshis * 1000 {
-			return Err(ChannelError::Close("Remote side tried to send more than the total value of the channel"));
-		}
-		if msg.amount_msat == 0 {
-			return Err(ChannelError::Close("Remote side tried to send a 0-msat HTLC"));
-		}
-		if msg.amount_msat < self.our_htlc_minimum_msat {
-			return Err(ChannelError::Close("Remote side tried to send less than our minimum HTLC value"));
-		}
-
+	/// Checks our counterparty can afford to add an HTLC of the given value to our channel,
+	/// returning whether it would eat into the fee spike buffer we keep for them, in which case it
+	/// should be failed back.
+	fn check_remote_htlc_add_affordable(&self, amount_msat: u64) -> Result<bool, ChannelError> {
 		let (inbound_htlc_count, htlc_inbound_value_msat) = self.get_inbound_pending_htlc_stats();
 		if inbound_htlc_count + 1 > OUR_MAX_HTLCS as u32 {
 			return Err(ChannelError::Close("Remote tried to push more than our max accepted HTLCs"));
 		}
 		// Check our_max_htlc_value_in_flight_msat
-		if htlc_inbound_value_msat + msg.amount_msat > Channel::<ChanSigner>::get_our_max_htlc_value_in_flight_msat(self.channel_value_satoshis) {
+		if htlc_inbound_value_msat + amount_msat > Channel::<ChanSigner>::get_our_max_htlc_value_in_flight_msat(self.channel_value_satoshis) {
 			return Err(ChannelError::Close("Remote HTLC add would put them over our max HTLC value"));
 		}
 		// Check remote_channel_reserve_satoshis (we're getting paid, so they have to at least meet
@@ -1813,7 +2930,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			self.value_to_self_msat + htlc_inbound_value_msat - removed_outbound_total_msat;
 		let pending_remote_value_msat =
 			self.channel_value_satoshis * 1000 - pending_value_to_self_msat;
-		if pending_remote_value_msat < msg.amount_msat {
+		if pending_remote_value_msat < amount_msat {
 			return Err(ChannelError::Close("Remote HTLC add would overdraw remaining funds"));
 		}
 
@@ -1823,13 +2940,13 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			// +1 for this HTLC.
 			self.next_remote_commit_tx_fee_msat(1)
 		};
-		if pending_remote_value_msat - msg.amount_msat < remote_commit_tx_fee_msat {
+		if pending_remote_value_msat - amount_msat < remote_commit_tx_fee_msat {
 			return Err(ChannelError::Close("Remote HTLC add would not leave enough to pay for fees"));
 		};
 
 		let chan_reserve_msat =
 			Channel::<ChanSigner>::get_remote_channel_reserve_satoshis(self.channel_value_satoshis) * 1000;
-		if pending_remote_value_msat - msg.amount_msat - remote_commit_tx_fee_msat < chan_reserve_msat {
+		if pending_remote_value_msat - amount_msat - remote_commit_tx_fee_msat < chan_reserve_msat {
 			return Err(ChannelError::Close("Remote HTLC add would put them under remote reserve value"));
 		}
 
@@ -1842,11 +2959,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			// as we should still be able to afford adding this HTLC plus one more future HTLC, regardless of
 			// being sensitive to fee spikes.
 			let remote_fee_cost_incl_stuck_buffer_msat = 2 * self.next_remote_commit_tx_fee_msat(1 + 1);
-			if pending_remote_value_msat - msg.amount_msat - chan_reserve_msat < remote_fee_cost_incl_stuck_buffer_msat {
-				// Note that if the pending_forward_status is not updated here, then it's because we're already failing
-				// the HTLC, i.e. its status is already set to failing.
-				log_info!(logger, "Attempting to fail HTLC due to fee spike buffer violation");
-				pending_forward_status = create_pending_htlc_status(self, pending_forward_status, 0x1000|7);
+			if pending_remote_value_msat - amount_msat - chan_reserve_msat < remote_fee_cost_incl_stuck_buffer_msat {
+				return Ok(true);
 			}
 		} else {
 			// Check that they won't violate our local required channel reserve by adding this HTLC.
@@ -1857,6 +2971,52 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				return Err(ChannelError::Close("Cannot receive value that would put us under local channel reserve value"));
 			}
 		}
+		Ok(false)
+	}
+
+	pub fn update_add_htlc<F, L: Deref>(&mut self, msg: &msgs::UpdateAddHTLC, mut pending_forward_status: PendingHTLCStatus, create_pending_htlc_status: F, logger: &L) -> Result<(), ChannelError>
+	where F: for<'a> Fn(&'a Self, PendingHTLCStatus, u16) -> PendingHTLCStatus, L::Target: Logger {
+		// We can't accept HTLCs sent after we've sent a shutdown.
+		let local_sent_shutdown = (self.channel_state & (ChannelState::ChannelFunded as u32 | ChannelState::LocalShutdownSent as u32)) != (ChannelState::ChannelFunded as u32);
+		if local_sent_shutdown {
+			pending_forward_status = create_pending_htlc_status(self, pending_forward_status, 0x1000|20);
+		}
+		// If the remote has sent a shutdown prior to adding this HTLC, then they are in violation of the spec.
+		let remote_sent_shutdown = (self.channel_state & (ChannelState::ChannelFunded as u32 | ChannelState::RemoteShutdownSent as u32)) != (ChannelState::ChannelFunded as u32);
+		if remote_sent_shutdown {
+			return Err(ChannelError::Close("Got add HTLC message when channel was not in an operational state"));
+		}
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent update_add_htlc when we needed a channel_reestablish"));
+		}
+		if self.pending_splice.is_some() && !self.is_splice_signed() {
+			return Err(ChannelError::Close("Peer sent update_add_htlc before the splice transaction was signed"));
+		}
+		// Our splice_init may have crossed this message, in which case our counterparty will reject
+		// it, so abandon the splice.
+		self.splice_negotiation = None;
+		if msg.amount_msat > self.channel_value_satoshis * 1000 {
+			return Err(ChannelError::Close("Remote side tried to send more than the total value of the channel"));
+		}
+		if msg.amount_msat == 0 {
+			return Err(ChannelError::Close("Remote side tried to send a 0-msat HTLC"));
+		}
+		if msg.amount_msat < self.our_htlc_minimum_msat {
+			return Err(ChannelError::Close("Remote side tried to send less than our minimum HTLC value"));
+		}
+
+		let mut fee_spike_buffer_violated = self.check_remote_htlc_add_affordable(msg.amount_msat)?;
+		if self.is_splice_signed() {
+			// The HTLC is added to the commitment transactions spending our pending splice
+			// transaction as well.
+			fee_spike_buffer_violated |= self.with_pending_splice_funding(|chan| chan.check_remote_htlc_add_affordable(msg.amount_msat))?;
+		}
+		if fee_spike_buffer_violated {
+			// Note that if the pending_forward_status is not updated here, then it's because we're already failing
+			// the HTLC, i.e. its status is already set to failing.
+			log_info!(logger, "Attempting to fail HTLC due to fee spike buffer violation");
+			pending_forward_status = create_pending_htlc_status(self, pending_forward_status, 0x1000|7);
+		}
 
 		if self.next_remote_htlc_id != msg.htlc_id {
 			return Err(ChannelError::Close("Remote skipped HTLC ID"));
@@ -1885,7 +3045,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	/// Marks an outbound HTLC which we have received update_fail/fulfill/malformed
 	#[inline]
-	fn mark_outbound_htlc_removed(&mut self, htlc_id: u64, check_preimage: Option<PaymentHash>, fail_reason: Option<HTLCFailReason>) -> Result<&HTLCSource, ChannelError> {
+	fn mark_outbound_htlc_removed(&mut self, htlc_id: u64, check_preimage: Option<PaymentHash>, fail_reason: Option<HTLCFailReason>) -> Result<&OutboundHTLCOutput, ChannelError> {
 		for htlc in self.pending_outbound_htlcs.iter_mut() {
 			if htlc.htlc_id == htlc_id {
 				match check_preimage {
@@ -1904,13 +3064,14 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 					OutboundHTLCState::AwaitingRemoteRevokeToRemove(_) | OutboundHTLCState::AwaitingRemovedRemoteRevoke(_) | OutboundHTLCState::RemoteRemoved(_) =>
 						return Err(ChannelError::Close("Remote tried to fulfill/fail HTLC that they'd already fulfilled/failed")),
 				}
-				return Ok(&htlc.source);
+				return Ok(htlc);
 			}
 		}
 		Err(ChannelError::Close("Remote tried to fulfill/fail an HTLC we couldn't find"))
 	}
 
-	pub fn update_fulfill_htlc(&mut self, msg: &msgs::UpdateFulfillHTLC) -> Result<HTLCSource, ChannelError> {
+	/// Returns the source of the fulfilled HTLC and its value in msat.
+	pub fn update_fulfill_htlc(&mut self, msg: &msgs::UpdateFulfillHTLC) -> Result<(HTLCSource, u64), ChannelError> {
 		if (self.channel_state & (ChannelState::ChannelFunded as u32)) != (ChannelState::ChannelFunded as u32) {
 			return Err(ChannelError::Close("Got fulfill HTLC message when channel was not in an operational state"));
 		}
@@ -1919,7 +3080,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 
 		let payment_hash = PaymentHash(Sha256::hash(&msg.payment_preimage.0[..]).into_inner());
-		self.mark_outbound_htlc_removed(msg.htlc_id, Some(payment_hash), None).map(|source| source.clone())
+		self.mark_outbound_htlc_removed(msg.htlc_id, Some(payment_hash), None).map(|htlc| (htlc.source.clone(), htlc.amount_msat))
 	}
 
 	pub fn update_fail_htlc(&mut self, msg: &msgs::UpdateFailHTLC, fail_reason: HTLCFailReason) -> Result<(), ChannelError> {
@@ -1944,59 +3105,39 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 		self.mark_outbound_htlc_removed(msg.htlc_id, None, Some(fail_reason))?;
 		Ok(())
-	}
-
-	pub fn commitment_signed<F: Deref, L: Deref>(&mut self, msg: &msgs::CommitmentSigned, fee_estimator: &F, logger: &L) -> Result<(msgs::RevokeAndACK, Option<msgs::CommitmentSigned>, Option<msgs::ClosingSigned>, ChannelMonitorUpdate), (Option<ChannelMonitorUpdate>, ChannelError)>
-	where F::Target: FeeEstimator,
-				L::Target: Logger
-	{
-		if (self.channel_state & (ChannelState::ChannelFunded as u32)) != (ChannelState::ChannelFunded as u32) {
-			return Err((None, ChannelError::Close("Got commitment signed message when channel was not in an operational state")));
-		}
-		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
-			return Err((None, ChannelError::Close("Peer sent commitment_signed when we needed a channel_reestablish")));
-		}
-		if self.channel_state & BOTH_SIDES_SHUTDOWN_MASK == BOTH_SIDES_SHUTDOWN_MASK && self.last_sent_closing_fee.is_some() {
-			return Err((None, ChannelError::Close("Peer sent commitment_signed after we'd started exchanging closing_signeds")));
-		}
-
-		let funding_script = self.get_funding_redeemscript();
-
-		let local_keys = self.build_local_transaction_keys(self.cur_local_commitment_transaction_number).map_err(|e| (None, e))?;
-
-		let mut update_fee = false;
-		let feerate_per_kw = if !self.channel_outbound && self.pending_update_fee.is_some() {
-			update_fee = true;
-			self.pending_update_fee.unwrap()
-		} else {
-			self.feerate_per_kw
-		};
+	}
+
+	/// Checks our counterparty's signatures for our next commitment transaction, returning it along
+	/// with its HTLCs, as provided to our ChannelMonitor.
+	fn check_next_local_commitment_signatures<L: Deref>(&self, signature: &Signature, htlc_signatures: &Vec<Signature>, local_keys: &TxCreationKeys, feerate_per_kw: u32, update_fee: bool, logger: &L) -> Result<(Transaction, Vec<(HTLCOutputInCommitment, Option<Signature>)>, Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>), ChannelError> where L::Target: Logger {
+		let funding_script = self.get_funding_redeemscript();
 
 		let mut local_commitment_tx = {
-			let mut commitment_tx = self.build_commitment_transaction(self.cur_local_commitment_transaction_number, &local_keys, true, false, feerate_per_kw, logger);
+			let mut commitment_tx = self.build_commitment_transaction(self.cur_local_commitment_transaction_number, local_keys, true, false, feerate_per_kw, logger);
 			let htlcs_cloned: Vec<_> = commitment_tx.2.drain(..).map(|htlc| (htlc.0, htlc.1.map(|h| h.clone()))).collect();
 			(commitment_tx.0, commitment_tx.1, htlcs_cloned)
 		};
 		let local_commitment_txid = local_commitment_tx.0.txid();
 		let local_sighash = hash_to_message!(&bip143::SighashComponents::new(&local_commitment_tx.0).sighash_all(&local_commitment_tx.0.input[0], &funding_script, self.channel_value_satoshis)[..]);
-		log_trace!(logger, "Checking commitment tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(msg.signature.serialize_compact()[..]), log_bytes!(self.their_funding_pubkey().serialize()), encode::serialize_hex(&local_commitment_tx.0), log_bytes!(local_sighash[..]), encode::serialize_hex(&funding_script));
-		if let Err(_) = self.secp_ctx.verify(&local_sighash, &msg.signature, &self.their_funding_pubkey()) {
-			return Err((None, ChannelError::Close("Invalid commitment tx signature from peer")));
+		log_trace!(logger, "Checking commitment tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(signature.serialize_compact()[..]), log_bytes!(self.their_funding_pubkey().serialize()), encode::serialize_hex(&local_commitment_tx.0), log_bytes!(local_sighash[..]), encode::serialize_hex(&funding_script));
+		if let Err(_) = self.secp_ctx.verify(&local_sighash, signature, &self.their_funding_pubkey()) {
+			return Err(ChannelError::Close("Invalid commitment tx signature from peer"));
 		}
 
 		//If channel fee was updated by funder confirm funder can afford the new fee rate when applied to the current local commitment transaction
 		if update_fee {
 			let num_htlcs = local_commitment_tx.1;
-			let total_fee = feerate_per_kw as u64 * (COMMITMENT_TX_BASE_WEIGHT + (num_htlcs as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000;
+			let total_fee = feerate_per_kw as u64 * (commitment_tx_base_weight(self.opt_anchors) + (num_htlcs as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000
+				+ anchor_outputs_value_satoshis(self.opt_anchors);
 
 			let remote_reserve_we_require = Channel::<ChanSigner>::get_remote_channel_reserve_satoshis(self.channel_value_satoshis);
 			if self.channel_value_satoshis - self.value_to_self_msat / 1000 < total_fee + remote_reserve_we_require {
-				return Err((None, ChannelError::Close("Funding remote cannot afford proposed new fee")));
+				return Err(ChannelError::Close("Funding remote cannot afford proposed new fee"));
 			}
 		}
 
-		if msg.htlc_signatures.len() != local_commitment_tx.1 {
-			return Err((None, ChannelError::Close("Got wrong number of HTLC signatures from remote")));
+		if htlc_signatures.len() != local_commitment_tx.1 {
+			return Err(ChannelError::Close("Got wrong number of HTLC signatures from remote"));
 		}
 
 		// TODO: Merge these two, sadly they are currently both required to be passed separately to
@@ -2005,21 +3146,66 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let mut htlcs_and_sigs = Vec::with_capacity(local_commitment_tx.2.len());
 		for (idx, (htlc, source)) in local_commitment_tx.2.drain(..).enumerate() {
 			if let Some(_) = htlc.transaction_output_index {
-				let htlc_tx = self.build_htlc_transaction(&local_commitment_txid, &htlc, true, &local_keys, feerate_per_kw);
-				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &local_keys);
-				let htlc_sighash = hash_to_message!(&bip143::SighashComponents::new(&htlc_tx).sighash_all(&htlc_tx.input[0], &htlc_redeemscript, htlc.amount_msat / 1000)[..]);
-				log_trace!(logger, "Checking HTLC tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(msg.htlc_signatures[idx].serialize_compact()[..]), log_bytes!(local_keys.b_htlc_key.serialize()), encode::serialize_hex(&htlc_tx), log_bytes!(htlc_sighash[..]), encode::serialize_hex(&htlc_redeemscript));
-				if let Err(_) = self.secp_ctx.verify(&htlc_sighash, &msg.htlc_signatures[idx], &local_keys.b_htlc_key) {
-					return Err((None, ChannelError::Close("Invalid HTLC tx signature from peer")));
+				let htlc_tx = self.build_htlc_transaction(&local_commitment_txid, &htlc, true, local_keys, feerate_per_kw);
+				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, local_keys, self.opt_anchors);
+				let htlc_sighash = hash_to_message!(&chan_utils::htlc_tx_sighash(&htlc_tx, 0, &htlc_redeemscript, htlc.amount_msat / 1000, chan_utils::htlc_counterparty_sighash_type(self.opt_anchors))[..]);
+				log_trace!(logger, "Checking HTLC tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(htlc_signatures[idx].serialize_compact()[..]), log_bytes!(local_keys.b_htlc_key.serialize()), encode::serialize_hex(&htlc_tx), log_bytes!(htlc_sighash[..]), encode::serialize_hex(&htlc_redeemscript));
+				if let Err(_) = self.secp_ctx.verify(&htlc_sighash, &htlc_signatures[idx], &local_keys.b_htlc_key) {
+					return Err(ChannelError::Close("Invalid HTLC tx signature from peer"));
 				}
-				htlcs_without_source.push((htlc.clone(), Some(msg.htlc_signatures[idx])));
-				htlcs_and_sigs.push((htlc, Some(msg.htlc_signatures[idx]), source));
+				htlcs_without_source.push((htlc.clone(), Some(htlc_signatures[idx])));
+				htlcs_and_sigs.push((htlc, Some(htlc_signatures[idx]), source));
 			} else {
 				htlcs_without_source.push((htlc.clone(), None));
 				htlcs_and_sigs.push((htlc, None, source));
 			}
 		}
 
+		Ok((local_commitment_tx.0, htlcs_without_source, htlcs_and_sigs))
+	}
+
+	pub fn commitment_signed<F: Deref, L: Deref>(&mut self, msg: &msgs::CommitmentSigned, fee_estimator: &F, logger: &L) -> Result<(msgs::RevokeAndACK, Option<msgs::CommitmentSigned>, Option<msgs::ClosingSigned>, ChannelMonitorUpdate), (Option<ChannelMonitorUpdate>, ChannelError)>
+	where F::Target: FeeEstimator,
+				L::Target: Logger
+	{
+		if (self.channel_state & (ChannelState::ChannelFunded as u32)) != (ChannelState::ChannelFunded as u32) {
+			return Err((None, ChannelError::Close("Got commitment signed message when channel was not in an operational state")));
+		}
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err((None, ChannelError::Close("Peer sent commitment_signed when we needed a channel_reestablish")));
+		}
+		if self.channel_state & BOTH_SIDES_SHUTDOWN_MASK == BOTH_SIDES_SHUTDOWN_MASK && self.last_sent_closing_fee.is_some() {
+			return Err((None, ChannelError::Close("Peer sent commitment_signed after we'd started exchanging closing_signeds")));
+		}
+
+		let local_keys = self.build_local_transaction_keys(self.cur_local_commitment_transaction_number).map_err(|e| (None, e))?;
+
+		let mut update_fee = false;
+		let feerate_per_kw = if !self.channel_outbound && self.pending_update_fee.is_some() {
+			update_fee = true;
+			self.pending_update_fee.unwrap()
+		} else {
+			self.feerate_per_kw
+		};
+
+		// If our counterparty signed for our pending splice transaction's funding output before
+		// receiving our splice_locked, that is now our channel's funding output.
+		let (signature, htlc_signatures) = match msg.spliced_signatures {
+			OptionalField::Present(ref spliced) if !self.is_splice_signed() &&
+					self.splice_locked_commitment_number == Some(self.cur_local_commitment_transaction_number) =>
+				(&spliced.signature, &spliced.htlc_signatures),
+			_ => (&msg.signature, &msg.htlc_signatures),
+		};
+		let (local_commitment_tx, htlcs_without_source, htlcs_and_sigs) = self.check_next_local_commitment_signatures(signature, htlc_signatures, &local_keys, feerate_per_kw, update_fee, logger).map_err(|e| (None, e))?;
+		let spliced_commitment_tx = if self.is_splice_signed() {
+			let spliced = match msg.spliced_signatures {
+				OptionalField::Present(ref spliced) => spliced,
+				OptionalField::Absent => return Err((None, ChannelError::Close("Peer didn't sign our commitment transaction spending the pending splice transaction"))),
+			};
+			let spliced_local_keys = local_keys.clone();
+			Some((spliced, self.with_pending_splice_funding(|chan| chan.check_next_local_commitment_signatures(&spliced.signature, &spliced.htlc_signatures, &spliced_local_keys, feerate_per_kw, update_fee, logger)).map_err(|e| (None, e))?, spliced_local_keys))
+		} else { None };
+
 		let next_per_commitment_point = PublicKey::from_secret_key(&self.secp_ctx, &self.build_local_commitment_secret(self.cur_local_commitment_transaction_number - 1));
 		let per_commitment_secret = chan_utils::build_commitment_secret(self.local_keys.commitment_seed(), self.cur_local_commitment_transaction_number + 1);
 
@@ -2044,10 +3230,16 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let mut monitor_update = ChannelMonitorUpdate {
 			update_id: self.latest_monitor_update_id,
 			updates: vec![ChannelMonitorUpdateStep::LatestLocalCommitmentTXInfo {
-				commitment_tx: LocalCommitmentTransaction::new_missing_local_sig(local_commitment_tx.0, msg.signature.clone(), &self.local_keys.pubkeys().funding_pubkey, &their_funding_pubkey, local_keys, self.feerate_per_kw, htlcs_without_source),
+				commitment_tx: LocalCommitmentTransaction::new_missing_local_sig(local_commitment_tx, signature.clone(), &self.local_keys.pubkeys().funding_pubkey, &their_funding_pubkey, local_keys, self.feerate_per_kw, self.opt_anchors, htlcs_without_source),
 				htlc_outputs: htlcs_and_sigs
 			}]
 		};
+		if let Some((spliced, (commitment_tx, htlcs_without_source, htlcs_and_sigs), spliced_local_keys)) = spliced_commitment_tx {
+			monitor_update.updates.push(ChannelMonitorUpdateStep::LatestSplicedLocalCommitmentTXInfo {
+				commitment_tx: LocalCommitmentTransaction::new_missing_local_sig(commitment_tx, spliced.signature.clone(), &self.local_keys.pubkeys().funding_pubkey, &their_funding_pubkey, spliced_local_keys, self.feerate_per_kw, self.opt_anchors, htlcs_without_source),
+				htlc_outputs: htlcs_and_sigs
+			});
+		}
 		self.channel_monitor.as_mut().unwrap().update_monitor_ooo(monitor_update.clone(), logger).unwrap();
 
 		for htlc in self.pending_inbound_htlcs.iter_mut() {
@@ -2383,6 +3575,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			}
 		}
 		self.value_to_self_msat = (self.value_to_self_msat as i64 + value_to_self_msat_diff) as u64;
+		if let Some(ref mut splice) = self.pending_splice {
+			splice.value_to_self_msat = (splice.value_to_self_msat as i64 + value_to_self_msat_diff) as u64;
+		}
 
 		if self.channel_outbound {
 			if let Some(feerate) = self.pending_update_fee.take() {
@@ -2520,6 +3715,12 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		// will be retransmitted.
 		self.last_sent_closing_fee = None;
 
+		// Splices are abandoned if we disconnect before we've sent our tx_signatures for them.
+		self.splice_negotiation = None;
+		if self.pending_splice.as_ref().map(|splice| !splice.signing.has_sent_tx_signatures()).unwrap_or(false) {
+			self.pending_splice = None;
+		}
+
 		let mut inbound_drop_count = 0;
 		self.pending_inbound_htlcs.retain(|htlc| {
 			match htlc.state {
@@ -2595,7 +3796,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		assert_eq!(self.channel_state & ChannelState::MonitorUpdateFailed as u32, ChannelState::MonitorUpdateFailed as u32);
 		self.channel_state &= !(ChannelState::MonitorUpdateFailed as u32);
 
-		let needs_broadcast_safe = self.channel_state & (ChannelState::FundingSent as u32) != 0 && self.channel_outbound;
+		// Dual-funded channels' funding transactions are instead broadcast upon exchanging
+		// tx_signatures.
+		let needs_broadcast_safe = self.channel_state & (ChannelState::FundingSent as u32) != 0 && self.channel_outbound && self.funding_tx_signing.is_none();
 
 		// Because we will never generate a FundingBroadcastSafe event when we're in
 		// MonitorUpdateFailed, if we assume the user only broadcast the funding transaction when
@@ -2604,7 +3807,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		// monitor on funding_created, and we even got the funding transaction confirmed before the
 		// monitor was persisted.
 		let funding_locked = if self.monitor_pending_funding_locked {
-			assert!(!self.channel_outbound, "Funding transaction broadcast without FundingBroadcastSafe!");
+			assert!(!self.channel_outbound || self.funding_tx_signing.is_some(), "Funding transaction broadcast without FundingBroadcastSafe!");
 			self.monitor_pending_funding_locked = false;
 			let next_per_commitment_secret = self.build_local_commitment_secret(self.cur_local_commitment_transaction_number);
 			let next_per_commitment_point = PublicKey::from_secret_key(&self.secp_ctx, &next_per_commitment_secret);
@@ -2643,7 +3846,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		(raa, commitment_update, order, forwards, failures, needs_broadcast_safe, funding_locked)
 	}
 
-	pub fn update_fee<F: Deref>(&mut self, fee_estimator: &F, msg: &msgs::UpdateFee) -> Result<(), ChannelError>
+	/// Handles an update_fee from our counterparty, closing the channel if its feerate is out of
+	/// the bounds we accept, widened by max_deviation_percent (see
+	/// UserConfig::max_inbound_update_fee_deviation_percent).
+	pub fn update_fee<F: Deref>(&mut self, fee_estimator: &F, msg: &msgs::UpdateFee, max_deviation_percent: u16) -> Result<(), ChannelError>
 		where F::Target: FeeEstimator
 	{
 		if self.channel_outbound {
@@ -2652,7 +3858,11 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fee when we needed a channel_reestablish"));
 		}
-		Channel::<ChanSigner>::check_remote_fee(fee_estimator, msg.feerate_per_kw)?;
+		if self.pending_splice.is_some() && !self.is_splice_signed() {
+			return Err(ChannelError::Close("Peer sent update_fee before the splice transaction was signed"));
+		}
+		Channel::<ChanSigner>::check_remote_update_fee(fee_estimator, msg.feerate_per_kw, max_deviation_percent)?;
+		self.splice_negotiation = None;
 		self.pending_update_fee = Some(msg.feerate_per_kw);
 		self.update_time_counter += 1;
 		Ok(())
@@ -2668,7 +3878,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 	}
 
-	fn get_last_commitment_update<L: Deref>(&self, logger: &L) -> msgs::CommitmentUpdate where L::Target: Logger {
+	fn get_last_commitment_update<L: Deref>(&mut self, logger: &L) -> msgs::CommitmentUpdate where L::Target: Logger {
 		let mut update_add_htlcs = Vec::new();
 		let mut update_fulfill_htlcs = Vec::new();
 		let mut update_fail_htlcs = Vec::new();
@@ -2716,12 +3926,26 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			}
 		}
 
-		log_trace!(logger, "Regenerated latest commitment update with {} update_adds, {} update_fulfills, {} update_fails, and {} update_fail_malformeds",
-				update_add_htlcs.len(), update_fulfill_htlcs.len(), update_fail_htlcs.len(), update_fail_malformed_htlcs.len());
+		// An update_fee we sent is only cleared once our counterparty revokes the commitment
+		// transaction it applies to, so if it's still pending it goes along with this one.
+		let update_fee = if self.channel_outbound && self.pending_update_fee.is_some() {
+			Some(msgs::UpdateFee {
+				channel_id: self.channel_id(),
+				feerate_per_kw: self.pending_update_fee.unwrap(),
+			})
+		} else { None };
+
+		let mut commitment_signed = self.send_commitment_no_state_update(logger).expect("It looks like we failed to re-generate a commitment_signed we had previously sent?").0;
+		if let Some((spliced_signatures, _, _)) = self.send_spliced_commitment_no_state_update(logger).expect("It looks like we failed to re-generate a commitment_signed we had previously sent?") {
+			commitment_signed.spliced_signatures = OptionalField::Present(spliced_signatures);
+		}
+
+		log_trace!(logger, "Regenerated latest commitment update with {} update_adds, {} update_fulfills, {} update_fails, {} update_fail_malformeds and {} update_fee",
+				update_add_htlcs.len(), update_fulfill_htlcs.len(), update_fail_htlcs.len(), update_fail_malformed_htlcs.len(), if update_fee.is_some() { "an" } else { "no" });
 		msgs::CommitmentUpdate {
 			update_add_htlcs, update_fulfill_htlcs, update_fail_htlcs, update_fail_malformed_htlcs,
-			update_fee: None,
-			commitment_signed: self.send_commitment_no_state_update(logger).expect("It looks like we failed to re-generate a commitment_signed we had previously sent?").0,
+			update_fee,
+			commitment_signed,
 		}
 	}
 
@@ -2899,6 +4123,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			// can do that via error message without getting a connection fail anyway...
 			return Err(ChannelError::Close("Peer sent shutdown pre-funding generation"));
 		}
+		if self.pending_splice.is_some() {
+			return Err(ChannelError::Close("Peer sent shutdown while the channel was being spliced"));
+		}
+		self.splice_negotiation = None;
 		for htlc in self.pending_inbound_htlcs.iter() {
 			if let InboundHTLCState::RemoteAnnounced(_) = htlc.state {
 				return Err(ChannelError::Close("Got shutdown with remote pending HTLCs"));
@@ -3111,12 +4339,23 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.short_channel_id
 	}
 
+	/// Gets the short_channel_ids the channel had before being spliced, which still refer to it.
+	pub fn get_prev_short_channel_ids(&self) -> &Vec<u64> {
+		&self.prev_short_channel_ids
+	}
+
 	/// Returns the funding_txo we either got from our peer, or were given by
 	/// get_outbound_funding_created.
 	pub fn get_funding_txo(&self) -> Option<OutPoint> {
 		self.funding_txo
 	}
 
+	/// Returns the funding outpoint the channel was opened with, by which our ChannelMonitor is
+	/// keyed, even once the channel has been spliced and get_funding_txo returns the spliced one.
+	pub fn get_original_funding_txo(&self) -> Option<OutPoint> {
+		self.original_funding_txo.or(self.funding_txo)
+	}
+
 	/// Allowed in any state (including after shutdown)
 	pub fn get_their_node_id(&self) -> PublicKey {
 		self.their_node_id
@@ -3136,8 +4375,63 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.channel_value_satoshis
 	}
 
-	pub fn get_fee_proportional_millionths(&self) -> u32 {
-		self.config.fee_proportional_millionths
+	/// Gets the value of the inbound HTLC with the given id, if it is still pending.
+	pub fn get_pending_inbound_htlc_value_msat(&self, htlc_id: u64) -> Option<u64> {
+		self.pending_inbound_htlcs.iter().find(|htlc| htlc.htlc_id == htlc_id).map(|htlc| htlc.amount_msat)
+	}
+
+	/// Gets the CLTV expiry of the inbound HTLC with the given id, if it is still pending.
+	pub fn get_pending_inbound_htlc_cltv_expiry(&self, htlc_id: u64) -> Option<u32> {
+		self.pending_inbound_htlcs.iter().find(|htlc| htlc.htlc_id == htlc_id).map(|htlc| htlc.cltv_expiry)
+	}
+
+	/// Allowed in any state (including after shutdown)
+	pub fn config(&self) -> ChannelConfig {
+		self.config
+	}
+
+	/// The config we had before the last call to update_config, if HTLCs forwarded according to it
+	/// should still be accepted.
+	pub fn prev_config(&self) -> Option<ChannelConfig> {
+		self.prev_config.map(|(config, _)| config)
+	}
+
+	/// Updates our forwarding policy to that in the given config, returning whether it changed (and
+	/// a new channel_update should be generated). The fields of the config which may only be set at
+	/// channel creation are ignored. HTLCs forwarded according to the previous policy continue to
+	/// be accepted for EXPIRE_PREV_CONFIG_TICKS calls to maybe_expire_prev_config.
+	pub fn update_config(&mut self, config: &ChannelConfig) -> bool {
+		let old_config = self.config;
+		self.config.fee_proportional_millionths = config.fee_proportional_millionths;
+		self.config.fee_base_msat = config.fee_base_msat;
+		self.config.cltv_expiry_delta = config.cltv_expiry_delta;
+		self.config.forwarding_htlc_minimum_msat = config.forwarding_htlc_minimum_msat;
+		self.config.forwarding_htlc_maximum_msat = config.forwarding_htlc_maximum_msat;
+		if self.config.fee_proportional_millionths == old_config.fee_proportional_millionths &&
+				self.config.fee_base_msat == old_config.fee_base_msat &&
+				self.config.cltv_expiry_delta == old_config.cltv_expiry_delta &&
+				self.config.forwarding_htlc_minimum_msat == old_config.forwarding_htlc_minimum_msat &&
+				self.config.forwarding_htlc_maximum_msat == old_config.forwarding_htlc_maximum_msat {
+			return false;
+		}
+		self.prev_config = Some((old_config, 0));
+		self.update_time_counter += 1;
+		true
+	}
+
+	/// Called on each timer tick to stop accepting HTLCs forwarded according to our previous
+	/// forwarding policy once it has had time to propagate.
+	pub fn maybe_expire_prev_config(&mut self) {
+		let expired = match self.prev_config {
+			Some((_, ref mut ticks)) => {
+				*ticks += 1;
+				*ticks >= EXPIRE_PREV_CONFIG_TICKS
+			},
+			None => false,
+		};
+		if expired {
+			self.prev_config = None;
+		}
 	}
 
 	#[cfg(test)]
@@ -3145,6 +4439,29 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.feerate_per_kw
 	}
 
+	/// Gets the feerate our commitment transactions will have once any update_fee we've sent or
+	/// queued in our holding cell is committed. Only valid for outbound channels.
+	pub fn get_latest_outbound_feerate(&self) -> u32 {
+		debug_assert!(self.channel_outbound);
+		self.holding_cell_update_fee.or(self.pending_update_fee).unwrap_or(self.feerate_per_kw)
+	}
+
+	/// Gets the highest feerate we can pay for our commitment transactions while keeping the
+	/// reserve our counterparty requires of us, counting every pending HTLC as non-dust and any
+	/// HTLCs we may yet be paid as not ours. Only valid for outbound channels.
+	pub fn get_max_affordable_outbound_feerate(&self) -> u32 {
+		debug_assert!(self.channel_outbound);
+		let (outbound_htlc_count, outbound_htlc_value_msat) = self.get_outbound_pending_htlc_stats();
+		let num_htlcs = self.pending_inbound_htlcs.len() as u64 + outbound_htlc_count as u64;
+		let available_sat = (self.value_to_self_msat as i64 - outbound_htlc_value_msat as i64) / 1000
+			- self.local_channel_reserve_satoshis as i64 - anchor_outputs_value_satoshis(self.opt_anchors) as i64;
+		if available_sat <= 0 {
+			return 0;
+		}
+		let weight = commitment_tx_base_weight(self.opt_anchors) + num_htlcs * COMMITMENT_TX_WEIGHT_PER_HTLC;
+		cmp::min(available_sat as u64 * 1000 / weight, u32::max_value() as u64) as u32
+	}
+
 	pub fn get_cur_local_commitment_transaction_number(&self) -> u64 {
 		self.cur_local_commitment_transaction_number + 1
 	}
@@ -3213,7 +4530,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		// output value back into a transaction with the regular channel output:
 
 		// the fee cost of the HTLC-Success/HTLC-Timeout transaction:
-		let mut res = self.feerate_per_kw as u64 * cmp::max(HTLC_TIMEOUT_TX_WEIGHT, HTLC_SUCCESS_TX_WEIGHT) / 1000;
+		let mut res = self.feerate_per_kw as u64 * cmp::max(htlc_timeout_tx_weight(self.opt_anchors), htlc_success_tx_weight(self.opt_anchors)) / 1000;
 
 		if self.channel_outbound {
 			// + the marginal fee increase cost to us in the commitment transaction:
@@ -3242,7 +4559,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	/// is_usable() and considers things like the channel being temporarily disabled.
 	/// Allowed in any state (including after shutdown)
 	pub fn is_live(&self) -> bool {
-		self.is_usable() && (self.channel_state & (ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32) == 0)
+		self.is_usable() && (self.channel_state & (ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32) == 0) &&
+			!self.is_negotiating_splice()
 	}
 
 	/// Returns true if this channel has been marked as awaiting a monitor update to move forward.
@@ -3256,6 +4574,23 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.channel_state >= ChannelState::FundingSent as u32
 	}
 
+	/// Returns true if this is an inbound channel whose funding transaction has still not
+	/// confirmed FUNDING_CONF_DEADLINE_BLOCKS after we received open_channel, at which point it
+	/// should be closed.
+	pub fn is_funding_timed_out(&self, height: u32) -> bool {
+		!self.channel_outbound && self.funding_tx_confirmations == 0 &&
+			(self.channel_state & !MULTI_STATE_FLAGS) < ChannelState::ChannelFunded as u32 &&
+			height >= self.channel_creation_height.unwrap_or(height) + FUNDING_CONF_DEADLINE_BLOCKS
+	}
+
+	/// Sets the height at which we consider this channel to have been created if it was read from
+	/// data written before we tracked it, so that its funding deadline starts counting from now.
+	pub(super) fn set_channel_creation_height_if_unknown(&mut self, height: u32) {
+		if self.channel_creation_height.is_none() {
+			self.channel_creation_height = Some(height);
+		}
+	}
+
 	/// Returns true if this channel is fully shut down. True here implies that no further actions
 	/// may/will be taken on this channel, and thus this object should be freed. Any future changes
 	/// will be handled appropriately by the chain monitor.
@@ -3300,7 +4635,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	///
 	/// May return some HTLCs (and their payment_hash) which have timed out and should be failed
 	/// back.
-	pub fn block_connected(&mut self, header: &BlockHeader, height: u32, txn_matched: &[&Transaction], indexes_of_txn_matched: &[usize]) -> Result<(Option<msgs::FundingLocked>, Vec<(HTLCSource, PaymentHash)>), msgs::ErrorMessage> {
+	pub fn block_connected(&mut self, header: &BlockHeader, height: u32, txn_matched: &[&Transaction], indexes_of_txn_matched: &[usize]) -> Result<(Option<msgs::FundingLocked>, Option<msgs::SpliceLocked>, Vec<(HTLCSource, PaymentHash)>), msgs::ErrorMessage> {
 		let mut timed_out_htlcs = Vec::new();
 		self.holding_cell_htlc_updates.retain(|htlc_update| {
 			match htlc_update {
@@ -3363,6 +4698,33 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				}
 			}
 		}
+		let mut splice_locked = None;
+		if let Some(ref mut splice) = self.pending_splice {
+			if header.bitcoin_hash() != self.last_block_connected && splice.confirmations > 0 {
+				splice.confirmations += 1;
+			}
+			for (ref tx, index_in_block) in txn_matched.iter().zip(indexes_of_txn_matched) {
+				if tx.txid() == splice.funding_txo.txid {
+					if height > 0xff_ff_ff || (*index_in_block) > 0xff_ff_ff {
+						panic!("Block was bogus - either height 16 million or had > 16 million transactions");
+					}
+					splice.confirmations = 1;
+					splice.short_channel_id = Some(((height as u64)                     << (5*8)) |
+					                               ((*index_in_block as u64)            << (2*8)) |
+					                               ((splice.funding_txo.index as u64)   << (0*8)));
+				}
+			}
+			if splice.confirmations == self.minimum_depth && header.bitcoin_hash() != self.last_block_connected {
+				splice.confirmed_in = Some(header.bitcoin_hash());
+				if !splice.sent_splice_locked {
+					splice.sent_splice_locked = true;
+					splice_locked = Some(msgs::SpliceLocked {
+						channel_id: self.channel_id,
+						splice_txid: splice.funding_txo.txid,
+					});
+				}
+			}
+		}
 		if header.bitcoin_hash() != self.last_block_connected {
 			self.last_block_connected = header.bitcoin_hash();
 			self.update_time_counter = cmp::max(self.update_time_counter, header.time);
@@ -3402,16 +4764,16 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 							return Ok((Some(msgs::FundingLocked {
 								channel_id: self.channel_id,
 								next_per_commitment_point: next_per_commitment_point,
-							}), timed_out_htlcs));
+							}), splice_locked, timed_out_htlcs));
 						} else {
 							self.monitor_pending_funding_locked = true;
-							return Ok((None, timed_out_htlcs));
+							return Ok((None, splice_locked, timed_out_htlcs));
 						}
 					}
 				}
 			}
 		}
-		Ok((None, timed_out_htlcs))
+		Ok((None, splice_locked, timed_out_htlcs))
 	}
 
 	/// Called by channelmanager based on chain blocks being disconnected.
@@ -3427,6 +4789,17 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if Some(header.bitcoin_hash()) == self.funding_tx_confirmed_in {
 			self.funding_tx_confirmations = self.minimum_depth as u64 - 1;
 		}
+		if let Some(ref mut splice) = self.pending_splice {
+			if splice.confirmations > 0 {
+				splice.confirmations -= 1;
+				if splice.confirmations == 0 {
+					splice.short_channel_id = None;
+				}
+			}
+			if Some(header.bitcoin_hash()) == splice.confirmed_in {
+				splice.confirmations = self.minimum_depth - 1;
+			}
+		}
 		self.last_block_connected = header.bitcoin_hash();
 		if let Some(channel_monitor) = self.channel_monitor.as_mut() {
 			channel_monitor.last_block_hash = self.last_block_connected;
@@ -3475,6 +4848,33 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 	}
 
+	pub fn get_open_channel_v2(&self, chain_hash: BlockHash) -> msgs::OpenChannelV2 {
+		let dual_funding = self.dual_funding.as_ref().expect("Tried to send open_channel2 for a single-funded channel");
+		let open_channel = self.get_open_channel(chain_hash);
+
+		msgs::OpenChannelV2 {
+			chain_hash,
+			temporary_channel_id: open_channel.temporary_channel_id,
+			funding_feerate_per_kw: dual_funding.funding_feerate_per_kw,
+			commitment_feerate_per_kw: open_channel.feerate_per_kw,
+			funding_satoshis: open_channel.funding_satoshis,
+			dust_limit_satoshis: open_channel.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: open_channel.max_htlc_value_in_flight_msat,
+			htlc_minimum_msat: open_channel.htlc_minimum_msat,
+			to_self_delay: open_channel.to_self_delay,
+			max_accepted_htlcs: open_channel.max_accepted_htlcs,
+			locktime: dual_funding.locktime,
+			funding_pubkey: open_channel.funding_pubkey,
+			revocation_basepoint: open_channel.revocation_basepoint,
+			payment_point: open_channel.payment_point,
+			delayed_payment_basepoint: open_channel.delayed_payment_basepoint,
+			htlc_basepoint: open_channel.htlc_basepoint,
+			first_per_commitment_point: open_channel.first_per_commitment_point,
+			channel_flags: open_channel.channel_flags,
+			shutdown_scriptpubkey: open_channel.shutdown_scriptpubkey,
+		}
+	}
+
 	pub fn get_accept_channel(&self) -> msgs::AcceptChannel {
 		if self.channel_outbound {
 			panic!("Tried to send accept_channel for an outbound channel?");
@@ -3512,7 +4912,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	fn get_outbound_funding_created_signature<L: Deref>(&mut self, logger: &L) -> Result<Signature, ChannelError> where L::Target: Logger {
 		let remote_keys = self.build_remote_transaction_keys()?;
 		let remote_initial_commitment_tx = self.build_commitment_transaction(self.cur_remote_commitment_transaction_number, &remote_keys, false, false, self.feerate_per_kw, logger).0;
-		Ok(self.local_keys.sign_remote_commitment(self.feerate_per_kw, &remote_initial_commitment_tx, &remote_keys, &Vec::new(), self.our_to_self_delay, &self.secp_ctx)
+		Ok(self.local_keys.sign_remote_commitment(self.feerate_per_kw, &remote_initial_commitment_tx, &remote_keys, &Vec::new(), self.our_to_self_delay, self.opt_anchors, &self.secp_ctx)
 				.map_err(|_| ChannelError::Close("Failed to get signatures for new commitment_signed"))?.0)
 	}
 
@@ -3653,40 +5053,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	// Send stuff to our remote peers:
 
-	/// Adds a pending outbound HTLC to this channel, note that you probably want
-	/// send_htlc_and_commit instead cause you'll want both messages at once.
-	/// This returns an option instead of a pure UpdateAddHTLC as we may be in a state where we are
-	/// waiting on the remote peer to send us a revoke_and_ack during which time we cannot add new
-	/// HTLCs on the wire or we wouldn't be able to determine what they actually ACK'ed.
-	/// You MUST call send_commitment prior to any other calls on this Channel
-	/// If an Err is returned, it's a ChannelError::Ignore!
-	pub fn send_htlc(&mut self, amount_msat: u64, payment_hash: PaymentHash, cltv_expiry: u32, source: HTLCSource, onion_routing_packet: msgs::OnionPacket) -> Result<Option<msgs::UpdateAddHTLC>, ChannelError> {
-		if (self.channel_state & (ChannelState::ChannelFunded as u32 | BOTH_SIDES_SHUTDOWN_MASK)) != (ChannelState::ChannelFunded as u32) {
-			return Err(ChannelError::Ignore("Cannot send HTLC until channel is fully established and we haven't started shutting down"));
-		}
-
-		if amount_msat > self.channel_value_satoshis * 1000 {
-			return Err(ChannelError::Ignore("Cannot send more than the total value of the channel"));
-		}
-
-		if amount_msat == 0 {
-			return Err(ChannelError::Ignore("Cannot send 0-msat HTLC"));
-		}
-
-		if amount_msat < self.their_htlc_minimum_msat {
-			return Err(ChannelError::Ignore("Cannot send less than their minimum HTLC value"));
-		}
-
-		if (self.channel_state & (ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32)) != 0 {
-			// Note that this should never really happen, if we're !is_live() on receipt of an
-			// incoming HTLC for relay will result in us rejecting the HTLC and we won't allow
-			// the user to send directly into a !is_live() channel. However, if we
-			// disconnected during the time the previous hop was doing the commitment dance we may
-			// end up getting here after the forwarding delay. In any case, returning an
-			// IgnoreError will get ChannelManager to do the right thing and fail backwards now.
-			return Err(ChannelError::Ignore("Cannot send an HTLC while disconnected/frozen for channel monitor update"));
-		}
-
+	/// Checks we can afford to add an HTLC of the given value to our channel.
+	fn check_holder_htlc_add_affordable(&self, amount_msat: u64) -> Result<(), ChannelError> {
 		let (outbound_htlc_count, htlc_outbound_value_msat) = self.get_outbound_pending_htlc_stats();
 		if outbound_htlc_count + 1 > self.their_max_accepted_htlcs as u32 {
 			return Err(ChannelError::Ignore("Cannot push more than their max accepted HTLCs"));
@@ -3728,6 +5096,52 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if pending_value_to_self_msat - amount_msat - local_commit_tx_fee_msat < chan_reserve_msat {
 			return Err(ChannelError::Ignore("Cannot send value that would put us under local channel reserve value"));
 		}
+		Ok(())
+	}
+
+	/// Adds a pending outbound HTLC to this channel, note that you probably want
+	/// send_htlc_and_commit instead cause you'll want both messages at once.
+	/// This returns an option instead of a pure UpdateAddHTLC as we may be in a state where we are
+	/// waiting on the remote peer to send us a revoke_and_ack during which time we cannot add new
+	/// HTLCs on the wire or we wouldn't be able to determine what they actually ACK'ed.
+	/// You MUST call send_commitment prior to any other calls on this Channel
+	/// If an Err is returned, it's a ChannelError::Ignore!
+	pub fn send_htlc(&mut self, amount_msat: u64, payment_hash: PaymentHash, cltv_expiry: u32, source: HTLCSource, onion_routing_packet: msgs::OnionPacket) -> Result<Option<msgs::UpdateAddHTLC>, ChannelError> {
+		if (self.channel_state & (ChannelState::ChannelFunded as u32 | BOTH_SIDES_SHUTDOWN_MASK)) != (ChannelState::ChannelFunded as u32) {
+			return Err(ChannelError::Ignore("Cannot send HTLC until channel is fully established and we haven't started shutting down"));
+		}
+
+		if amount_msat > self.channel_value_satoshis * 1000 {
+			return Err(ChannelError::Ignore("Cannot send more than the total value of the channel"));
+		}
+
+		if amount_msat == 0 {
+			return Err(ChannelError::Ignore("Cannot send 0-msat HTLC"));
+		}
+
+		if amount_msat < self.their_htlc_minimum_msat {
+			return Err(ChannelError::Ignore("Cannot send less than their minimum HTLC value"));
+		}
+
+		if (self.channel_state & (ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32)) != 0 {
+			// Note that this should never really happen, if we're !is_live() on receipt of an
+			// incoming HTLC for relay will result in us rejecting the HTLC and we won't allow
+			// the user to send directly into a !is_live() channel. However, if we
+			// disconnected during the time the previous hop was doing the commitment dance we may
+			// end up getting here after the forwarding delay. In any case, returning an
+			// IgnoreError will get ChannelManager to do the right thing and fail backwards now.
+			return Err(ChannelError::Ignore("Cannot send an HTLC while disconnected/frozen for channel monitor update"));
+		}
+		if self.is_negotiating_splice() {
+			return Err(ChannelError::Ignore("Cannot send an HTLC while a splice is being negotiated"));
+		}
+
+		self.check_holder_htlc_add_affordable(amount_msat)?;
+		if self.is_splice_signed() {
+			// The HTLC is added to the commitment transactions spending our pending splice
+			// transaction as well.
+			self.with_pending_splice_funding(|chan| chan.check_holder_htlc_add_affordable(amount_msat))?;
+		}
 
 		// Now update local state:
 		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32)) == (ChannelState::AwaitingRemoteRevoke as u32) {
@@ -3820,7 +5234,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 		self.resend_order = RAACommitmentOrder::RevokeAndACKFirst;
 
-		let (res, remote_commitment_tx, htlcs) = match self.send_commitment_no_state_update(logger) {
+		let (mut res, remote_commitment_tx, htlcs) = match self.send_commitment_no_state_update(logger) {
 			Ok((res, (remote_commitment_tx, mut htlcs))) => {
 				// Update state now that we've passed all the can-fail calls...
 				let htlcs_no_ref: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)> =
@@ -3829,9 +5243,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			},
 			Err(e) => return Err(e),
 		};
+		let spliced_commitment = self.send_spliced_commitment_no_state_update(logger)?;
 
 		self.latest_monitor_update_id += 1;
-		let monitor_update = ChannelMonitorUpdate {
+		let mut monitor_update = ChannelMonitorUpdate {
 			update_id: self.latest_monitor_update_id,
 			updates: vec![ChannelMonitorUpdateStep::LatestRemoteCommitmentTXInfo {
 				unsigned_commitment_tx: remote_commitment_tx.clone(),
@@ -3840,11 +5255,35 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				their_revocation_point: self.their_cur_commitment_point.unwrap()
 			}]
 		};
+		if let Some((spliced_signatures, unsigned_commitment_tx, htlc_outputs)) = spliced_commitment {
+			res.spliced_signatures = OptionalField::Present(spliced_signatures);
+			monitor_update.updates.push(ChannelMonitorUpdateStep::LatestSplicedRemoteCommitmentTXInfo {
+				unsigned_commitment_tx,
+				htlc_outputs,
+			});
+		}
 		self.channel_monitor.as_mut().unwrap().update_monitor_ooo(monitor_update.clone(), logger).unwrap();
 		self.channel_state |= ChannelState::AwaitingRemoteRevoke as u32;
 		Ok((res, monitor_update))
 	}
 
+	/// Signs our counterparty's next commitment transaction spending our pending splice
+	/// transaction, if we've exchanged commitment transactions spending it, returning the
+	/// signatures along with the commitment transaction and its HTLCs.
+	fn send_spliced_commitment_no_state_update<L: Deref>(&mut self, logger: &L) -> Result<Option<(msgs::SplicedCommitmentSignatures, Transaction, Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>)>, ChannelError> where L::Target: Logger {
+		if !self.is_splice_signed() {
+			return Ok(None);
+		}
+		self.with_pending_splice_funding(|chan| {
+			let (res, (remote_commitment_tx, htlcs)) = chan.send_commitment_no_state_update(logger)?;
+			let htlcs_no_ref = htlcs.into_iter().map(|(htlc, htlc_source)| (htlc, htlc_source.map(|source_ref| Box::new(source_ref.clone())))).collect();
+			Ok(Some((msgs::SplicedCommitmentSignatures {
+				signature: res.signature,
+				htlc_signatures: res.htlc_signatures,
+			}, remote_commitment_tx, htlcs_no_ref)))
+		})
+	}
+
 	/// Only fails in case of bad keys. Used for channel_reestablish commitment_signed generation
 	/// when we shouldn't change HTLC/channel state.
 	fn send_commitment_no_state_update<L: Deref>(&self, logger: &L) -> Result<(msgs::CommitmentSigned, (Transaction, Vec<(HTLCOutputInCommitment, Option<&HTLCSource>)>)), ChannelError> where L::Target: Logger {
@@ -3865,7 +5304,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				htlcs.push(htlc);
 			}
 
-			let res = self.local_keys.sign_remote_commitment(feerate_per_kw, &remote_commitment_tx.0, &remote_keys, &htlcs, self.our_to_self_delay, &self.secp_ctx)
+			let res = self.local_keys.sign_remote_commitment(feerate_per_kw, &remote_commitment_tx.0, &remote_keys, &htlcs, self.our_to_self_delay, self.opt_anchors, &self.secp_ctx)
 				.map_err(|_| ChannelError::Close("Failed to get signatures for new commitment_signed"))?;
 			signature = res.0;
 			htlc_signatures = res.1;
@@ -3877,8 +5316,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 			for (ref htlc_sig, ref htlc) in htlc_signatures.iter().zip(htlcs) {
 				log_trace!(logger, "Signed remote HTLC tx {} with redeemscript {} with pubkey {} -> {}",
-					encode::serialize_hex(&chan_utils::build_htlc_transaction(&remote_commitment_tx.0.txid(), feerate_per_kw, self.our_to_self_delay, htlc, &remote_keys.a_delayed_payment_key, &remote_keys.revocation_key)),
-					encode::serialize_hex(&chan_utils::get_htlc_redeemscript(&htlc, &remote_keys)),
+					encode::serialize_hex(&chan_utils::build_htlc_transaction(&remote_commitment_tx.0.txid(), feerate_per_kw, self.our_to_self_delay, htlc, &remote_keys.a_delayed_payment_key, &remote_keys.revocation_key, self.opt_anchors)),
+					encode::serialize_hex(&chan_utils::get_htlc_redeemscript(&htlc, &remote_keys, self.opt_anchors)),
 					log_bytes!(remote_keys.a_htlc_key.serialize()),
 					log_bytes!(htlc_sig.serialize_compact()[..]));
 			}
@@ -3888,6 +5327,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			channel_id: self.channel_id,
 			signature,
 			htlc_signatures,
+			spliced_signatures: OptionalField::Absent,
 		}, (remote_commitment_tx.0, remote_commitment_tx.2)))
 	}
 
@@ -3908,6 +5348,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	/// Begins the shutdown process, getting a message for the remote peer and returning all
 	/// holding cell HTLCs for payment failure.
 	pub fn get_shutdown(&mut self) -> Result<(msgs::Shutdown, Vec<(HTLCSource, PaymentHash)>), APIError> {
+		if self.is_splicing() {
+			return Err(APIError::ChannelUnavailable{err: "Cannot begin shutdown while the channel is being spliced"});
+		}
 		for htlc in self.pending_outbound_htlcs.iter() {
 			if let OutboundHTLCState::LocalAnnounced(_) = htlc.state {
 				return Err(APIError::APIMisuseError{err: "Cannot begin shutdown with pending HTLCs. Process pending events first"});
@@ -3985,15 +5428,15 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.channel_state = ChannelState::ShutdownComplete as u32;
 		self.update_time_counter += 1;
 		self.latest_monitor_update_id += 1;
-		(self.funding_txo.clone(), ChannelMonitorUpdate {
+		(self.get_original_funding_txo(), ChannelMonitorUpdate {
 			update_id: self.latest_monitor_update_id,
 			updates: vec![ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast }],
 		}, dropped_outbound_htlcs)
 	}
 }
 
-const SERIALIZATION_VERSION: u8 = 1;
-const MIN_SERIALIZATION_VERSION: u8 = 1;
+const SERIALIZATION_VERSION: u8 = 2;
+const MIN_SERIALIZATION_VERSION: u8 = 2;
 
 impl Writeable for InboundHTLCRemovalReason {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
@@ -4027,6 +5470,41 @@ impl Readable for InboundHTLCRemovalReason {
 	}
 }
 
+impl Writeable for PendingSplice {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		self.funding_txo.write(writer)?;
+		self.channel_value_satoshis.write(writer)?;
+		self.value_to_self_msat.write(writer)?;
+		self.local_channel_reserve_satoshis.write(writer)?;
+		self.signing.write(writer)?;
+		self.received_commitment_signed.write(writer)?;
+		self.confirmations.write(writer)?;
+		self.confirmed_in.write(writer)?;
+		self.short_channel_id.write(writer)?;
+		self.sent_splice_locked.write(writer)?;
+		self.received_splice_locked.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for PendingSplice {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		Ok(PendingSplice {
+			funding_txo: Readable::read(reader)?,
+			channel_value_satoshis: Readable::read(reader)?,
+			value_to_self_msat: Readable::read(reader)?,
+			local_channel_reserve_satoshis: Readable::read(reader)?,
+			signing: Readable::read(reader)?,
+			received_commitment_signed: Readable::read(reader)?,
+			confirmations: Readable::read(reader)?,
+			confirmed_in: Readable::read(reader)?,
+			short_channel_id: Readable::read(reader)?,
+			sent_splice_locked: Readable::read(reader)?,
+			received_splice_locked: Readable::read(reader)?,
+		})
+	}
+}
+
 impl<ChanSigner: ChannelKeys + Writeable> Writeable for Channel<ChanSigner> {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
 		// Note that we write out as if remove_uncommitted_htlcs_and_mark_paused had just been
@@ -4037,11 +5515,20 @@ impl<ChanSigner: ChannelKeys + Writeable> Writeable for Channel<ChanSigner> {
 
 		self.user_id.write(writer)?;
 		self.config.write(writer)?;
+		match self.prev_config {
+			Some((ref prev_config, ref ticks)) => {
+				1u8.write(writer)?;
+				prev_config.write(writer)?;
+				ticks.write(writer)?;
+			},
+			None => 0u8.write(writer)?,
+		}
 
 		self.channel_id.write(writer)?;
 		(self.channel_state | ChannelState::PeerDisconnected as u32).write(writer)?;
 		self.channel_outbound.write(writer)?;
 		self.channel_value_satoshis.write(writer)?;
+		self.opt_anchors.write(writer)?;
 
 		self.latest_monitor_update_id.write(writer)?;
 
@@ -4183,11 +5670,28 @@ impl<ChanSigner: ChannelKeys + Writeable> Writeable for Channel<ChanSigner> {
 		}
 
 		self.funding_txo.write(writer)?;
+		self.funding_tx_signing.write(writer)?;
+		// Splices we haven't sent tx_signatures for are abandoned on disconnection, see
+		// remove_uncommitted_htlcs_and_mark_paused.
+		match self.pending_splice {
+			Some(ref splice) if splice.signing.has_sent_tx_signatures() => {
+				1u8.write(writer)?;
+				splice.write(writer)?;
+			},
+			_ => 0u8.write(writer)?,
+		}
+		self.splice_locked_commitment_number.write(writer)?;
+		self.original_funding_txo.write(writer)?;
+		(self.prev_short_channel_ids.len() as u64).write(writer)?;
+		for short_id in self.prev_short_channel_ids.iter() {
+			short_id.write(writer)?;
+		}
 		self.funding_tx_confirmed_in.write(writer)?;
 		self.short_channel_id.write(writer)?;
 
 		self.last_block_connected.write(writer)?;
 		self.funding_tx_confirmations.write(writer)?;
+		self.channel_creation_height.unwrap_or(0).write(writer)?;
 
 		self.their_dust_limit_satoshis.write(writer)?;
 		self.our_dust_limit_satoshis.write(writer)?;
@@ -4217,19 +5721,35 @@ impl<ChanSigner: ChannelKeys + Writeable> Writeable for Channel<ChanSigner> {
 
 impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 	fn read<R : ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
-		let _ver: u8 = Readable::read(reader)?;
+		let ver: u8 = Readable::read(reader)?;
 		let min_ver: u8 = Readable::read(reader)?;
 		if min_ver > SERIALIZATION_VERSION {
 			return Err(DecodeError::UnknownVersion);
 		}
 
 		let user_id = Readable::read(reader)?;
-		let config: ChannelConfig = Readable::read(reader)?;
+		let config: ChannelConfig = if ver >= 2 { Readable::read(reader)? } else {
+			// Version 1 ChannelConfigs didn't include the forwarding parameters.
+			ChannelConfig {
+				fee_proportional_millionths: Readable::read(reader)?,
+				announced_channel: Readable::read(reader)?,
+				commit_upfront_shutdown_pubkey: Readable::read(reader)?,
+				..Default::default()
+			}
+		};
+		let prev_config = if ver < 2 { None } else {
+			match <u8 as Readable>::read(reader)? {
+				0 => None,
+				1 => Some((Readable::read(reader)?, Readable::read(reader)?)),
+				_ => return Err(DecodeError::InvalidValue),
+			}
+		};
 
 		let channel_id = Readable::read(reader)?;
 		let channel_state = Readable::read(reader)?;
 		let channel_outbound = Readable::read(reader)?;
 		let channel_value_satoshis = Readable::read(reader)?;
+		let opt_anchors = if ver >= 2 { Readable::read(reader)? } else { false };
 
 		let latest_monitor_update_id = Readable::read(reader)?;
 
@@ -4250,8 +5770,8 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 				cltv_expiry: Readable::read(reader)?,
 				payment_hash: Readable::read(reader)?,
 				state: match <u8 as Readable>::read(reader)? {
-					1 => InboundHTLCState::AwaitingRemoteRevokeToAnnounce(Readable::read(reader)?),
-					2 => InboundHTLCState::AwaitingAnnouncedRemoteRevoke(Readable::read(reader)?),
+					1 => InboundHTLCState::AwaitingRemoteRevokeToAnnounce(ReadableArgs::read(reader, ver)?),
+					2 => InboundHTLCState::AwaitingAnnouncedRemoteRevoke(ReadableArgs::read(reader, ver)?),
 					3 => InboundHTLCState::Committed,
 					4 => InboundHTLCState::LocalRemoved(Readable::read(reader)?),
 					_ => return Err(DecodeError::InvalidValue),
@@ -4315,7 +5835,7 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 		let monitor_pending_forwards_count: u64 = Readable::read(reader)?;
 		let mut monitor_pending_forwards = Vec::with_capacity(cmp::min(monitor_pending_forwards_count as usize, OUR_MAX_HTLCS as usize));
 		for _ in 0..monitor_pending_forwards_count {
-			monitor_pending_forwards.push((Readable::read(reader)?, Readable::read(reader)?));
+			monitor_pending_forwards.push((ReadableArgs::read(reader, ver)?, Readable::read(reader)?));
 		}
 
 		let monitor_pending_failures_count: u64 = Readable::read(reader)?;
@@ -4339,11 +5859,28 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 		};
 
 		let funding_txo = Readable::read(reader)?;
+		let funding_tx_signing = if ver >= 2 { Readable::read(reader)? } else { None };
+		let (pending_splice, splice_locked_commitment_number, original_funding_txo, prev_short_channel_ids) = if ver < 2 { (None, None, None, Vec::new()) } else {
+			let pending_splice = match <u8 as Readable>::read(reader)? {
+				0 => None,
+				1 => Some(Readable::read(reader)?),
+				_ => return Err(DecodeError::InvalidValue),
+			};
+			let splice_locked_commitment_number = Readable::read(reader)?;
+			let original_funding_txo = Readable::read(reader)?;
+			let prev_short_channel_id_count: u64 = Readable::read(reader)?;
+			let mut prev_short_channel_ids = Vec::with_capacity(cmp::min(prev_short_channel_id_count as usize, 16));
+			for _ in 0..prev_short_channel_id_count {
+				prev_short_channel_ids.push(Readable::read(reader)?);
+			}
+			(pending_splice, splice_locked_commitment_number, original_funding_txo, prev_short_channel_ids)
+		};
 		let funding_tx_confirmed_in = Readable::read(reader)?;
 		let short_channel_id = Readable::read(reader)?;
 
 		let last_block_connected = Readable::read(reader)?;
 		let funding_tx_confirmations = Readable::read(reader)?;
+		let channel_creation_height = if ver >= 2 { Some(Readable::read(reader)?) } else { None };
 
 		let their_dust_limit_satoshis = Readable::read(reader)?;
 		let our_dust_limit_satoshis = Readable::read(reader)?;
@@ -4376,11 +5913,13 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 			user_id,
 
 			config,
+			prev_config,
 			channel_id,
 			channel_state,
 			channel_outbound,
 			secp_ctx: Secp256k1::new(),
 			channel_value_satoshis,
+			opt_anchors,
 
 			latest_monitor_update_id,
 
@@ -4419,10 +5958,18 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 			last_sent_closing_fee,
 
 			funding_txo,
+			dual_funding: None,
+			funding_tx_signing,
+			splice_negotiation: None,
+			pending_splice,
+			splice_locked_commitment_number,
+			original_funding_txo,
+			prev_short_channel_ids,
 			funding_tx_confirmed_in,
 			short_channel_id,
 			last_block_connected,
 			funding_tx_confirmations,
+			channel_creation_height,
 
 			their_dust_limit_satoshis,
 			our_dust_limit_satoshis,
@@ -4465,7 +6012,7 @@ mod tests {
 	use hex;
 	use ln::channelmanager::{HTLCSource, PaymentPreimage, PaymentHash};
 	use ln::channel::{Channel,ChannelKeys,InboundHTLCOutput,OutboundHTLCOutput,InboundHTLCState,OutboundHTLCState,HTLCOutputInCommitment,TxCreationKeys};
-	use ln::channel::MAX_FUNDING_SATOSHIS;
+	use ln::channel::{ChannelError, MAX_FUNDING_SATOSHIS, TOTAL_BITCOIN_SUPPLY_SATOSHIS, EXPIRE_PREV_CONFIG_TICKS, FUNDING_CONF_DEADLINE_BLOCKS};
 	use ln::features::InitFeatures;
 	use ln::msgs::{OptionalField, DataLossProtect};
 	use ln::chan_utils;
@@ -4474,8 +6021,10 @@ mod tests {
 	use chain::keysinterface::{InMemoryChannelKeys, KeysInterface};
 	use chain::transaction::OutPoint;
 	use util::config::UserConfig;
+	use util::errors::APIError;
 	use util::enforcing_trait_impls::EnforcingChannelKeys;
 	use util::test_utils;
+	use util::ser::{Readable, Writeable};
 	use util::logger::Logger;
 	use bitcoin::secp256k1::{Secp256k1, Message, Signature, All};
 	use bitcoin::secp256k1::key::{SecretKey,PublicKey};
@@ -4495,7 +6044,7 @@ mod tests {
 
 	#[test]
 	fn test_max_funding_satoshis() {
-		assert!(MAX_FUNDING_SATOSHIS <= 21_000_000 * 100_000_000,
+		assert!(MAX_FUNDING_SATOSHIS <= TOTAL_BITCOIN_SUPPLY_SATOSHIS,
 		        "MAX_FUNDING_SATOSHIS is greater than all satoshis in existence");
 	}
 
@@ -4524,6 +6073,8 @@ mod tests {
 		}
 		fn get_onion_rand(&self) -> (SecretKey, [u8; 32]) { panic!(); }
 		fn get_channel_id(&self) -> [u8; 32] { [0; 32] }
+		fn get_secure_random_bytes(&self) -> [u8; 32] { [0; 32] }
+		fn get_inbound_payment_key_material(&self) -> [u8; 32] { panic!(); }
 	}
 
 	fn public_from_secret_hex(secp_ctx: &Secp256k1<All>, hex: &str) -> PublicKey {
@@ -4543,7 +6094,7 @@ mod tests {
 
 		let node_a_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
 		let config = UserConfig::default();
-		let node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&fee_est, &&keys_provider, node_a_node_id, 10000000, 100000, 42, &config).unwrap();
+		let node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&fee_est, &&keys_provider, node_a_node_id, &InitFeatures::known(), 10000000, 100000, 42, &config).unwrap();
 
 		// Now change the fee so we can check that the fee in the open_channel message is the
 		// same as the old fee.
@@ -4552,6 +6103,85 @@ mod tests {
 		assert_eq!(open_channel_msg.feerate_per_kw, original_fee);
 	}
 
+	// Check that channels of MAX_FUNDING_SATOSHIS or more are only opened and accepted if both
+	// peers support large channels, and only accepted up to our configured limit.
+	#[test]
+	fn test_large_channels() {
+		let fee_est = TestFeeEstimator{fee_est: 253 };
+		let secp_ctx = Secp256k1::new();
+		let seed = [42; 32];
+		let network = Network::Testnet;
+		let keys_provider = test_utils::TestKeysInterface::new(&seed, network);
+		let node_a_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		let node_b_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[7; 32]).unwrap());
+		let no_wumbo_features = InitFeatures::known().clear_wumbo();
+		let mut config = UserConfig::default();
+
+		// Channels just below the limit don't need the feature
+		let node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&fee_est, &&keys_provider, node_b_node_id, &no_wumbo_features, MAX_FUNDING_SATOSHIS - 1, 0, 42, &config).unwrap();
+		let open_channel_msg = node_a_chan.get_open_channel(genesis_block(network).header.bitcoin_hash());
+		assert!(Channel::<EnforcingChannelKeys>::new_from_req(&&fee_est, &&keys_provider, node_a_node_id, no_wumbo_features.clone(), &open_channel_msg, 7, 0, &config).is_ok());
+
+		// Larger ones can't be opened to a peer which doesn't support them...
+		match Channel::<EnforcingChannelKeys>::new_outbound(&&fee_est, &&keys_provider, node_b_node_id, &no_wumbo_features, MAX_FUNDING_SATOSHIS, 0, 42, &config) {
+			Err(APIError::APIMisuseError { err }) => assert_eq!(err, "funding value > 2^24 - 1 and peer does not support large channels"),
+			_ => panic!("Unexpected result"),
+		}
+		let mut node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&fee_est, &&keys_provider, node_b_node_id, &InitFeatures::known(), MAX_FUNDING_SATOSHIS, 0, 42, &config).unwrap();
+		let open_channel_msg = node_a_chan.get_open_channel(genesis_block(network).header.bitcoin_hash());
+
+		// ...nor accepted from one...
+		match Channel::<EnforcingChannelKeys>::new_from_req(&&fee_est, &&keys_provider, node_a_node_id, no_wumbo_features.clone(), &open_channel_msg, 7, 0, &config) {
+			Err(ChannelError::Close(err)) => assert_eq!(err, "funding value > 2^24 - 1 and large channels were not negotiated"),
+			_ => panic!("Unexpected result"),
+		}
+		// ...and are only accepted once we've raised our limit
+		match Channel::<EnforcingChannelKeys>::new_from_req(&&fee_est, &&keys_provider, node_a_node_id, InitFeatures::known(), &open_channel_msg, 7, 0, &config) {
+			Err(ChannelError::Close(err)) => assert_eq!(err, "funding satoshis is greater than the user specified limit"),
+			_ => panic!("Unexpected result"),
+		}
+		config.peer_channel_config_limits.max_funding_satoshis = MAX_FUNDING_SATOSHIS;
+		let node_b_chan = Channel::<EnforcingChannelKeys>::new_from_req(&&fee_est, &&keys_provider, node_a_node_id, InitFeatures::known(), &open_channel_msg, 7, 0, &config).unwrap();
+
+		// The opener also checks that the peer which accepted it supports them
+		let accept_channel_msg = node_b_chan.get_accept_channel();
+		match node_a_chan.accept_channel(&accept_channel_msg, &config, no_wumbo_features) {
+			Err(ChannelError::Close(err)) => assert_eq!(err, "Peer accepted a channel larger than 2^24 - 1 without supporting large channels"),
+			_ => panic!("Unexpected result"),
+		}
+		node_a_chan.accept_channel(&accept_channel_msg, &config, InitFeatures::known()).unwrap();
+	}
+
+	#[test]
+	fn test_funding_deadline_from_unknown_creation_height() {
+		// Channels read from data written before we tracked their creation height shouldn't be
+		// closed as timed out on load, only FUNDING_CONF_DEADLINE_BLOCKS after it.
+		let fee_est = TestFeeEstimator{fee_est: 253 };
+		let secp_ctx = Secp256k1::new();
+		let seed = [42; 32];
+		let network = Network::Testnet;
+		let keys_provider = test_utils::TestKeysInterface::new(&seed, network);
+		let node_a_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		let node_b_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[7; 32]).unwrap());
+		let config = UserConfig::default();
+
+		let node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&fee_est, &&keys_provider, node_b_node_id, &InitFeatures::known(), 10000000, 100000, 42, &config).unwrap();
+		let open_channel_msg = node_a_chan.get_open_channel(genesis_block(network).header.bitcoin_hash());
+		let mut node_b_chan = Channel::<EnforcingChannelKeys>::new_from_req(&&fee_est, &&keys_provider, node_a_node_id, InitFeatures::known(), &open_channel_msg, 7, 100, &config).unwrap();
+		assert!(!node_b_chan.is_funding_timed_out(100 + FUNDING_CONF_DEADLINE_BLOCKS - 1));
+		assert!(node_b_chan.is_funding_timed_out(100 + FUNDING_CONF_DEADLINE_BLOCKS));
+		assert!(!node_a_chan.is_funding_timed_out(100 + FUNDING_CONF_DEADLINE_BLOCKS));
+
+		node_b_chan.channel_creation_height = None;
+		assert!(!node_b_chan.is_funding_timed_out(5000));
+		node_b_chan.set_channel_creation_height_if_unknown(5000);
+		assert!(!node_b_chan.is_funding_timed_out(5000 + FUNDING_CONF_DEADLINE_BLOCKS - 1));
+		assert!(node_b_chan.is_funding_timed_out(5000 + FUNDING_CONF_DEADLINE_BLOCKS));
+		// A known creation height is never overwritten.
+		node_b_chan.set_channel_creation_height_if_unknown(10000);
+		assert!(node_b_chan.is_funding_timed_out(5000 + FUNDING_CONF_DEADLINE_BLOCKS));
+	}
+
 	#[test]
 	fn channel_reestablish_no_updates() {
 		let feeest = TestFeeEstimator{fee_est: 15000};
@@ -4566,12 +6196,12 @@ mod tests {
 		// Create Node A's channel
 		let node_a_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
 		let config = UserConfig::default();
-		let mut node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&feeest, &&keys_provider, node_a_node_id, 10000000, 100000, 42, &config).unwrap();
+		let mut node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&feeest, &&keys_provider, node_a_node_id, &InitFeatures::known(), 10000000, 100000, 42, &config).unwrap();
 
 		// Create Node B's channel by receiving Node A's open_channel message
 		let open_channel_msg = node_a_chan.get_open_channel(genesis_block(network).header.bitcoin_hash());
 		let node_b_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[7; 32]).unwrap());
-		let mut node_b_chan = Channel::<EnforcingChannelKeys>::new_from_req(&&feeest, &&keys_provider, node_b_node_id, InitFeatures::known(), &open_channel_msg, 7, &config).unwrap();
+		let mut node_b_chan = Channel::<EnforcingChannelKeys>::new_from_req(&&feeest, &&keys_provider, node_b_node_id, InitFeatures::known(), &open_channel_msg, 7, 0, &config).unwrap();
 
 		// Node B --> Node A: accept channel
 		let accept_channel_msg = node_b_chan.get_accept_channel();
@@ -4616,6 +6246,45 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn prev_config_serialization() {
+		// The previous forwarding policy, and how long we've been accepting it, survive reloads.
+		let feeest = TestFeeEstimator{fee_est: 15000};
+		let logger = test_utils::TestLogger::new();
+		let secp_ctx = Secp256k1::new();
+		let seed = [42; 32];
+		let network = Network::Testnet;
+		let keys_provider = test_utils::TestKeysInterface::new(&seed, network);
+
+		let node_a_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		let config = UserConfig::default();
+		let mut node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&feeest, &&keys_provider, node_a_node_id, &InitFeatures::known(), 10000000, 100000, 42, &config).unwrap();
+		let open_channel_msg = node_a_chan.get_open_channel(genesis_block(network).header.bitcoin_hash());
+		let node_b_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[7; 32]).unwrap());
+		let mut node_b_chan = Channel::<EnforcingChannelKeys>::new_from_req(&&feeest, &&keys_provider, node_b_node_id, InitFeatures::known(), &open_channel_msg, 7, 0, &config).unwrap();
+		node_a_chan.accept_channel(&node_b_chan.get_accept_channel(), &config, InitFeatures::known()).unwrap();
+		let tx = Transaction { version: 1, lock_time: 0, input: Vec::new(), output: vec![TxOut {
+			value: 10000000, script_pubkey: node_a_chan.get_funding_redeemscript(),
+		}]};
+		let funding_created_msg = node_a_chan.get_outbound_funding_created(OutPoint{ txid: tx.txid(), index: 0 }, &&logger).unwrap();
+		let _ = node_b_chan.funding_created(&funding_created_msg, &&logger).unwrap();
+
+		let mut new_config = config.channel_options;
+		new_config.fee_proportional_millionths += 1;
+		assert!(node_b_chan.update_config(&new_config));
+		node_b_chan.maybe_expire_prev_config();
+
+		let mut reloaded_chan: Channel<EnforcingChannelKeys> = Readable::read(&mut ::std::io::Cursor::new(node_b_chan.encode())).unwrap();
+		assert_eq!(reloaded_chan.config().fee_proportional_millionths, new_config.fee_proportional_millionths);
+		assert_eq!(reloaded_chan.prev_config().unwrap().fee_proportional_millionths, config.channel_options.fee_proportional_millionths);
+		for _ in 2..EXPIRE_PREV_CONFIG_TICKS {
+			reloaded_chan.maybe_expire_prev_config();
+		}
+		assert!(reloaded_chan.prev_config().is_some());
+		reloaded_chan.maybe_expire_prev_config();
+		assert!(reloaded_chan.prev_config().is_none());
+	}
+
 	#[test]
 	fn outbound_commitment_test() {
 		// Test vectors from BOLT 3 Appendix C:
@@ -4644,7 +6313,7 @@ mod tests {
 		let their_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
 		let mut config = UserConfig::default();
 		config.channel_options.announced_channel = false;
-		let mut chan = Channel::<InMemoryChannelKeys>::new_outbound(&&feeest, &&keys_provider, their_node_id, 10_000_000, 100000, 42, &config).unwrap(); // Nothing uses their network key in this test
+		let mut chan = Channel::<InMemoryChannelKeys>::new_outbound(&&feeest, &&keys_provider, their_node_id, &InitFeatures::known(), 10_000_000, 100000, 42, &config).unwrap(); // Nothing uses their network key in this test
 		chan.their_to_self_delay = 144;
 		chan.our_dust_limit_satoshis = 546;
 
@@ -4707,7 +6376,7 @@ mod tests {
 				})*
 				assert_eq!(unsigned_tx.1.len(), per_htlc.len());
 
-				localtx = LocalCommitmentTransaction::new_missing_local_sig(unsigned_tx.0.clone(), their_signature.clone(), &chan_keys.pubkeys().funding_pubkey, chan.their_funding_pubkey(), keys.clone(), chan.feerate_per_kw, per_htlc);
+				localtx = LocalCommitmentTransaction::new_missing_local_sig(unsigned_tx.0.clone(), their_signature.clone(), &chan_keys.pubkeys().funding_pubkey, chan.their_funding_pubkey(), keys.clone(), chan.feerate_per_kw, false, per_htlc);
 				let local_sig = chan_keys.sign_local_commitment(&localtx, &chan.secp_ctx).unwrap();
 				assert_eq!(Signature::from_der(&hex::decode($our_sig_hex).unwrap()[..]).unwrap(), local_sig);
 
@@ -4722,7 +6391,7 @@ mod tests {
 
 					let ref htlc = unsigned_tx.1[$htlc_idx];
 					let htlc_tx = chan.build_htlc_transaction(&unsigned_tx.0.txid(), &htlc, true, &keys, chan.feerate_per_kw);
-					let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &keys);
+					let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &keys, false);
 					let htlc_sighash = Message::from_slice(&bip143::SighashComponents::new(&htlc_tx).sighash_all(&htlc_tx.input[0], &htlc_redeemscript, htlc.amount_msat / 1000)[..]).unwrap();
 					secp_ctx.verify(&htlc_sighash, &remote_signature, &keys.b_htlc_key).unwrap();
 
@@ -10,6 +10,7 @@
 
 use bitcoin::blockdata::block::BlockHeader;
 use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::blockdata::script::Script;
 use bitcoin::blockdata::constants::genesis_block;
 use bitcoin::network::constants::Network;
 use bitcoin::util::hash::BitcoinHash;
@@ -26,25 +27,28 @@ use bitcoin::secp256k1::Secp256k1;
 use bitcoin::secp256k1::ecdh::SharedSecret;
 use bitcoin::secp256k1;
 
-use chain::chaininterface::{BroadcasterInterface,ChainListener,FeeEstimator};
+use chain::chaininterface::{BroadcasterInterface,ChainListener,FeeEstimator,ConfirmationTarget};
 use chain::transaction::OutPoint;
 use ln::channel::{Channel, ChannelError};
 use ln::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateErr, ManyChannelMonitor, HTLC_FAIL_BACK_BUFFER, CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS, ANTI_REORG_DELAY};
 use ln::features::{InitFeatures, NodeFeatures};
+use ln::interactivetxs::{FundingContribution, InteractiveTxMessageSend};
 use routing::router::{Route, RouteHop};
 use ln::msgs;
 use ln::onion_utils;
+use ln::inbound_payment;
 use ln::msgs::{ChannelMessageHandler, DecodeError, LightningError};
 use chain::keysinterface::{ChannelKeys, KeysInterface, KeysManager, InMemoryChannelKeys};
-use util::config::UserConfig;
+use util::config::{UserConfig, ChannelConfig};
 use util::{byte_utils, events};
+use util::events::ClosureReason;
 use util::ser::{Readable, ReadableArgs, MaybeReadable, Writeable, Writer};
 use util::chacha20::{ChaCha20, ChaChaReader};
 use util::logger::Logger;
-use util::errors::APIError;
+use util::errors::{APIError, OnionFailureCode};
 
 use std::{cmp, mem};
-use std::collections::{HashMap, hash_map, HashSet};
+use std::collections::{BTreeMap, HashMap, hash_map, HashSet};
 use std::io::{Cursor, Read};
 use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -77,6 +81,7 @@ enum PendingHTLCRouting {
 	},
 	Receive {
 		payment_data: Option<msgs::FinalOnionHopData>,
+		keysend_preimage: Option<PaymentPreimage>,
 		incoming_cltv_expiry: u32, // Used to track when we should expire pending HTLCs that go unclaimed
 	},
 }
@@ -86,6 +91,7 @@ pub(super) struct PendingHTLCInfo {
 	routing: PendingHTLCRouting,
 	incoming_shared_secret: [u8; 32],
 	payment_hash: PaymentHash,
+	incoming_amt_msat: u64,
 	pub(super) amt_to_forward: u64,
 	pub(super) outgoing_cltv_value: u32,
 }
@@ -115,12 +121,28 @@ pub(super) enum HTLCForwardInfo {
 	},
 }
 
+/// An HTLC to an intercepted short channel id, awaiting a call to
+/// ChannelManager::forward_intercepted_htlc or ChannelManager::fail_intercepted_htlc.
+struct InterceptedHTLC {
+	prev_short_channel_id: u64,
+	prev_htlc_id: u64,
+	/// The CLTV expiry of the inbound HTLC.
+	cltv_expiry: u32,
+	forward_info: PendingHTLCInfo,
+}
+
 /// Tracks the inbound corresponding to an outbound HTLC
 #[derive(Clone, PartialEq)]
 pub(super) struct HTLCPreviousHopData {
 	short_channel_id: u64,
 	htlc_id: u64,
 	incoming_packet_shared_secret: [u8; 32],
+	/// The channel_id of the inbound channel and the value, in msat, of the inbound HTLC, used to
+	/// generate an Event::PaymentForwarded once it's claimed even if the inbound channel has since
+	/// closed. Only set for HTLCs we forwarded, and not for those read from data written before
+	/// they were tracked.
+	prev_channel_id: Option<[u8; 32]>,
+	incoming_amt_msat: Option<u64>,
 }
 
 struct ClaimableHTLC {
@@ -132,6 +154,9 @@ struct ClaimableHTLC {
 	/// are part of the same payment.
 	payment_data: Option<msgs::FinalOnionHopData>,
 	cltv_expiry: u32,
+	/// The number of times timer_chan_freshness_every_min has been called since we received this
+	/// HTLC, used to time out incomplete multi-path payments.
+	timer_ticks: u8,
 }
 
 /// Tracks the inbound corresponding to an outbound HTLC
@@ -177,6 +202,10 @@ pub struct PaymentPreimage(pub [u8;32]);
 /// payment_secret type, use to authenticate sender to the receiver and tie MPP HTLCs together
 #[derive(Hash, Copy, Clone, PartialEq, Eq, Debug)]
 pub struct PaymentSecret(pub [u8;32]);
+/// intercept_id type, identifies an HTLC provided in Event::HTLCIntercepted until it is forwarded
+/// or failed
+#[derive(Hash, Copy, Clone, PartialEq, Eq, Debug)]
+pub struct InterceptId(pub [u8;32]);
 
 type ShutdownResult = (Option<OutPoint>, ChannelMonitorUpdate, Vec<(HTLCSource, PaymentHash)>);
 
@@ -285,6 +314,27 @@ pub(super) enum RAACommitmentOrder {
 	RevokeAndACKFirst,
 }
 
+/// An inbound channel request awaiting a call to ChannelManager::accept_inbound_channel or
+/// ChannelManager::reject_inbound_channel.
+struct PendingInboundChannel<ChanSigner: ChannelKeys> {
+	channel: Channel<ChanSigner>,
+	/// The number of times timer_chan_freshness_every_min has been called since we received the
+	/// request, used to time it out.
+	timer_ticks: u8,
+}
+
+/// The number of times timer_chan_freshness_every_min may be called after we receive an inbound
+/// channel request before it is rejected if the user hasn't answered it.
+const INBOUND_CHANNEL_REQUEST_TIMEOUT_TICKS: u8 = 2;
+
+/// The maximum number of unanswered inbound channel requests we hold for a single peer, beyond
+/// which further requests from it are rejected.
+const MAX_PENDING_INBOUND_CHANNELS_PER_PEER: usize = 2;
+
+/// The maximum number of unanswered inbound channel requests we hold across all peers, beyond
+/// which further requests are rejected.
+const MAX_PENDING_INBOUND_CHANNELS: usize = 100;
+
 // Note this is only exposed in cfg(test):
 pub(super) struct ChannelHolder<ChanSigner: ChannelKeys> {
 	pub(super) by_id: HashMap<[u8; 32], Channel<ChanSigner>>,
@@ -300,6 +350,22 @@ pub(super) struct ChannelHolder<ChanSigner: ChannelKeys> {
 	/// guarantees are made about the channels given here actually existing anymore by the time you
 	/// go to read them!
 	claimable_htlcs: HashMap<(PaymentHash, Option<PaymentSecret>), Vec<ClaimableHTLC>>,
+	/// temporary_channel_id -> inbound channel requests awaiting the user's decision, either
+	/// because UserConfig::manually_accept_inbound_channels is set or because they are dual-funded
+	/// and awaiting our contribution. These are never persisted.
+	pending_inbound_channels: HashMap<[u8; 32], PendingInboundChannel<ChanSigner>>,
+	/// Short channel ids we intercept HTLCs to, see ChannelManager::add_intercept_scid.
+	intercept_scids: HashSet<u64>,
+	/// intercept_id -> HTLCs to intercepted short channel ids awaiting the user's decision.
+	/// Note that, as with forward_htlcs, no consistency guarantees are made about the inbound
+	/// channel still existing by the time the user decides what to do with the HTLC.
+	pending_intercepted_htlcs: HashMap<InterceptId, InterceptedHTLC>,
+	/// outgoing_cltv_value -> hashes of the shared secrets of onions we've committed to forwarding
+	/// with that outgoing CLTV, used to reject replayed onion packets (see BOLT 4). Buckets are
+	/// pruned once the CLTV is too close for us to accept a forward with it anyway.
+	/// Replays are checked against every bucket, as an onion reusing a shared secret need not
+	/// carry the same outgoing CLTV.
+	onion_replay_cache: BTreeMap<u32, HashSet<[u8; 32]>>,
 	/// Messages to send to peers - pushed to in the same lock that they are generated in (except
 	/// for broadcast messages, where ordering isn't as strict).
 	pub(super) pending_msg_events: Vec<events::MessageSendEvent>,
@@ -394,11 +460,16 @@ pub struct ChannelManager<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref,
 	#[cfg(not(test))]
 	channel_state: Mutex<ChannelHolder<ChanSigner>>,
 	our_network_key: SecretKey,
+	inbound_payment_key: inbound_payment::ExpandedKey,
 
 	/// Used to track the last value sent in a node_announcement "timestamp" field. We ensure this
 	/// value increases strictly since we don't assume access to a time source.
 	last_node_announcement_serial: AtomicUsize,
 
+	/// The highest block timestamp we've seen, which is close to the current time, used to expire
+	/// inbound payments created with create_inbound_payment.
+	highest_seen_timestamp: AtomicUsize,
+
 	/// The bulk of our storage will eventually be here (channels and message queues and the like).
 	/// If we are connected to a peer we always at least have an entry here, even if no channels
 	/// are currently open with that peer.
@@ -429,7 +500,8 @@ pub(crate) const MAX_LOCAL_BREAKDOWN_TIMEOUT: u16 = 6 * 24 * 7;
 /// ie the node we forwarded the payment on to should always have enough room to reliably time out
 /// the HTLC via a full update_fail_htlc/commitment_signed dance before we hit the
 /// CLTV_CLAIM_BUFFER point (we static assert that it's at least 3 blocks more).
-const CLTV_EXPIRY_DELTA: u16 = 6 * 12; //TODO?
+/// Channels may be configured with a larger delta via ChannelConfig::cltv_expiry_delta.
+pub(crate) const MIN_CLTV_EXPIRY_DELTA: u16 = 6 * 12; //TODO?
 pub(super) const CLTV_FAR_FAR_AWAY: u32 = 6 * 24 * 7; //TODO?
 
 // Check that our CLTV_EXPIRY is at least CLTV_CLAIM_BUFFER + ANTI_REORG_DELAY + LATENCY_GRACE_PERIOD_BLOCKS,
@@ -440,13 +512,13 @@ pub(super) const CLTV_FAR_FAR_AWAY: u32 = 6 * 24 * 7; //TODO?
 // LATENCY_GRACE_PERIOD_BLOCKS.
 #[deny(const_err)]
 #[allow(dead_code)]
-const CHECK_CLTV_EXPIRY_SANITY: u32 = CLTV_EXPIRY_DELTA as u32 - LATENCY_GRACE_PERIOD_BLOCKS - CLTV_CLAIM_BUFFER - ANTI_REORG_DELAY - LATENCY_GRACE_PERIOD_BLOCKS;
+const CHECK_CLTV_EXPIRY_SANITY: u32 = MIN_CLTV_EXPIRY_DELTA as u32 - LATENCY_GRACE_PERIOD_BLOCKS - CLTV_CLAIM_BUFFER - ANTI_REORG_DELAY - LATENCY_GRACE_PERIOD_BLOCKS;
 
 // Check for ability of an attacker to make us fail on-chain by delaying inbound claim. See
 // ChannelMontior::would_broadcast_at_height for a description of why this is needed.
 #[deny(const_err)]
 #[allow(dead_code)]
-const CHECK_CLTV_EXPIRY_SANITY_2: u32 = CLTV_EXPIRY_DELTA as u32 - LATENCY_GRACE_PERIOD_BLOCKS - 2*CLTV_CLAIM_BUFFER;
+const CHECK_CLTV_EXPIRY_SANITY_2: u32 = MIN_CLTV_EXPIRY_DELTA as u32 - LATENCY_GRACE_PERIOD_BLOCKS - 2*CLTV_CLAIM_BUFFER;
 
 /// Details of a channel, as returned by ChannelManager::list_channels and ChannelManager::list_usable_channels
 pub struct ChannelDetails {
@@ -511,7 +583,7 @@ pub enum PaymentSendFailure {
 	///
 	/// The results here are ordered the same as the paths in the route object which was passed to
 	/// send_payment, and any Errs which are not APIError::MonitorUpdateFailed can be safely
-	/// retried (though there is currently no API with which to do so).
+	/// retried via ChannelManager::retry_payment.
 	///
 	/// Any entries which contain Err(APIError::MonitorUpdateFailed) or Ok(()) MUST NOT be retried
 	/// as they will result in over-/re-payment. These HTLCs all either successfully sent (in the
@@ -576,6 +648,10 @@ macro_rules! break_chan_entry {
 				if let Some(short_id) = chan.get_short_channel_id() {
 					$channel_state.short_to_id.remove(&short_id);
 				}
+				for short_id in chan.get_prev_short_channel_ids() {
+					$channel_state.short_to_id.remove(short_id);
+				}
+				$self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: msg.to_owned() });
 				break Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, chan.force_shutdown(true), $self.get_channel_update(&chan).ok()))
 			},
 			Err(ChannelError::CloseDelayBroadcast(_)) => { panic!("Wait is only generated on receipt of channel_reestablish, which is handled by try_chan_entry, we don't bother to support it here"); }
@@ -596,6 +672,10 @@ macro_rules! try_chan_entry {
 				if let Some(short_id) = chan.get_short_channel_id() {
 					$channel_state.short_to_id.remove(&short_id);
 				}
+				for short_id in chan.get_prev_short_channel_ids() {
+					$channel_state.short_to_id.remove(short_id);
+				}
+				$self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: msg.to_owned() });
 				return Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, chan.force_shutdown(true), $self.get_channel_update(&chan).ok()))
 			},
 			Err(ChannelError::CloseDelayBroadcast(msg)) => {
@@ -604,6 +684,10 @@ macro_rules! try_chan_entry {
 				if let Some(short_id) = chan.get_short_channel_id() {
 					$channel_state.short_to_id.remove(&short_id);
 				}
+				for short_id in chan.get_prev_short_channel_ids() {
+					$channel_state.short_to_id.remove(short_id);
+				}
+				$self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: msg.to_owned() });
 				let shutdown_res = chan.force_shutdown(false);
 				return Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, shutdown_res, $self.get_channel_update(&chan).ok()))
 			}
@@ -623,6 +707,10 @@ macro_rules! handle_monitor_err {
 				if let Some(short_id) = chan.get_short_channel_id() {
 					$channel_state.short_to_id.remove(&short_id);
 				}
+				for short_id in chan.get_prev_short_channel_ids() {
+					$channel_state.short_to_id.remove(short_id);
+				}
+				$self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: "ChannelMonitor storage failure".to_owned() });
 				// TODO: $failed_fails is dropped here, which will cause other channels to hit the
 				// chain in a confused state! We need to move them into the ChannelMonitor which
 				// will be responsible for failing backwards once things confirm on-chain.
@@ -696,8 +784,6 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	///
 	/// Non-proportional fees are fixed according to our risk using the provided fee estimator.
 	///
-	/// panics if channel_value_satoshis is >= `MAX_FUNDING_SATOSHIS`!
-	///
 	/// Users must provide the current blockchain height from which to track onchain channel
 	/// funding outpoints and send payments with reliable timelocks.
 	///
@@ -726,11 +812,17 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				short_to_id: HashMap::new(),
 				forward_htlcs: HashMap::new(),
 				claimable_htlcs: HashMap::new(),
+				pending_inbound_channels: HashMap::new(),
+				intercept_scids: HashSet::new(),
+				pending_intercepted_htlcs: HashMap::new(),
+				onion_replay_cache: BTreeMap::new(),
 				pending_msg_events: Vec::new(),
 			}),
 			our_network_key: keys_manager.get_node_secret(),
+			inbound_payment_key: inbound_payment::ExpandedKey::new(&keys_manager.get_inbound_payment_key_material()),
 
 			last_node_announcement_serial: AtomicUsize::new(0),
+			highest_seen_timestamp: AtomicUsize::new(0),
 
 			per_peer_state: RwLock::new(HashMap::new()),
 
@@ -753,15 +845,25 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// If successful, will generate a SendOpenChannel message event, so you should probably poll
 	/// PeerManager::process_events afterwards.
 	///
-	/// Raises APIError::APIMisuseError when channel_value_satoshis > 2**24 or push_msat is
-	/// greater than channel_value_satoshis * 1k or channel_value_satoshis is < 1000.
+	/// Channels larger than 2**24 - 1 satoshis (aka wumbo channels) can only be created with peers
+	/// which are currently connected and signaled support for them in their Init message.
+	///
+	/// Raises APIError::APIMisuseError when channel_value_satoshis > 2**24 - 1 and the peer does
+	/// not support large channels, or push_msat is greater than channel_value_satoshis * 1k or
+	/// channel_value_satoshis is < 1000.
 	pub fn create_channel(&self, their_network_key: PublicKey, channel_value_satoshis: u64, push_msat: u64, user_id: u64, override_config: Option<UserConfig>) -> Result<(), APIError> {
 		if channel_value_satoshis < 1000 {
 			return Err(APIError::APIMisuseError { err: "channel_value must be at least 1000 satoshis" });
 		}
 
-		let config = if override_config.is_some() { override_config.as_ref().unwrap() } else { &self.default_configuration };
-		let channel = Channel::new_outbound(&self.fee_estimator, &self.keys_manager, their_network_key, channel_value_satoshis, push_msat, user_id, config)?;
+		let their_features = match self.per_peer_state.read().unwrap().get(&their_network_key) {
+			Some(peer_state) => peer_state.lock().unwrap().latest_features.clone(),
+			None => InitFeatures::empty(),
+		};
+		let mut config = if let Some(override_config) = override_config { override_config } else { self.default_configuration.clone() };
+		// Anchor outputs support is advertised in our Init message, so it can't be set per-channel
+		config.own_channel_config.negotiate_anchor_outputs = self.default_configuration.own_channel_config.negotiate_anchor_outputs;
+		let channel = Channel::new_outbound(&self.fee_estimator, &self.keys_manager, their_network_key, &their_features, channel_value_satoshis, push_msat, user_id, &config)?;
 		let res = channel.get_open_channel(self.genesis_hash.clone());
 
 		let _ = self.total_consistency_lock.read().unwrap();
@@ -783,6 +885,54 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		Ok(())
 	}
 
+	/// Creates a new outbound dual-funded channel to the given remote node, to which we contribute
+	/// the given funds and our counterparty may add its own. The funding transaction is then
+	/// constructed interactively with our counterparty, resulting in an
+	/// Event::FundingTransactionReadyForSigning once our inputs must be signed.
+	///
+	/// user_id will be provided back in the FundingTransactionReadyForSigning event to allow
+	/// tracking of which events correspond with which create_dual_funded_channel call.
+	///
+	/// If successful, will generate a SendOpenChannelV2 message event, so you should probably poll
+	/// PeerManager::process_events afterwards.
+	///
+	/// Raises APIError::APIMisuseError when the peer is not connected or does not support
+	/// dual-funded channels, or when the contribution's inputs don't spend SegWit outputs or don't
+	/// cover its funding amount, outputs and fees.
+	pub fn create_dual_funded_channel(&self, their_network_key: PublicKey, contribution: FundingContribution, user_id: u64, override_config: Option<UserConfig>) -> Result<(), APIError> {
+		if contribution.funding_satoshis < 1000 {
+			return Err(APIError::APIMisuseError { err: "channel_value must be at least 1000 satoshis" });
+		}
+
+		let their_features = match self.per_peer_state.read().unwrap().get(&their_network_key) {
+			Some(peer_state) => peer_state.lock().unwrap().latest_features.clone(),
+			None => InitFeatures::empty(),
+		};
+		let mut config = if let Some(override_config) = override_config { override_config } else { self.default_configuration.clone() };
+		config.own_channel_config.negotiate_anchor_outputs = self.default_configuration.own_channel_config.negotiate_anchor_outputs;
+		let locktime = self.latest_block_height.load(Ordering::Acquire) as u32;
+		let channel = Channel::new_outbound_v2(&self.fee_estimator, &self.keys_manager, their_network_key, &their_features, contribution, locktime, user_id, &config)?;
+		let res = channel.get_open_channel_v2(self.genesis_hash.clone());
+
+		let _ = self.total_consistency_lock.read().unwrap();
+		let mut channel_state = self.channel_state.lock().unwrap();
+		match channel_state.by_id.entry(channel.channel_id()) {
+			hash_map::Entry::Occupied(_) => {
+				if cfg!(feature = "fuzztarget") {
+					return Err(APIError::APIMisuseError { err: "Fuzzy bad RNG" });
+				} else {
+					panic!("RNG is bad???");
+				}
+			},
+			hash_map::Entry::Vacant(entry) => { entry.insert(channel); }
+		}
+		channel_state.pending_msg_events.push(events::MessageSendEvent::SendOpenChannelV2 {
+			node_id: their_network_key,
+			msg: res,
+		});
+		Ok(())
+	}
+
 	fn list_channels_with_filter<Fn: FnMut(&(&[u8; 32], &Channel<ChanSigner>)) -> bool>(&self, f: Fn) -> Vec<ChannelDetails> {
 		let mut res = Vec::new();
 		{
@@ -830,6 +980,41 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		self.list_channels_with_filter(|&(_, ref channel)| channel.is_live())
 	}
 
+	/// Updates the forwarding policy of the given channel, ie its fee_base_msat,
+	/// fee_proportional_millionths, cltv_expiry_delta and forwarding HTLC limits, to those in the
+	/// given config. The remaining fields of the config can only be set at channel creation and are
+	/// ignored. The new policy is persisted with the channel.
+	///
+	/// If the policy changed and the channel has been confirmed, a fresh channel_update is
+	/// broadcast. HTLCs forwarded according to the previous policy continue to be accepted for a few
+	/// calls to timer_chan_freshness_every_min while the channel_update propagates.
+	///
+	/// Raises APIError::APIMisuseError if config.cltv_expiry_delta is below MIN_CLTV_EXPIRY_DELTA,
+	/// or APIError::ChannelUnavailable if the channel doesn't exist.
+	pub fn update_channel_config(&self, channel_id: &[u8; 32], config: &ChannelConfig) -> Result<(), APIError> {
+		if config.cltv_expiry_delta < MIN_CLTV_EXPIRY_DELTA {
+			return Err(APIError::APIMisuseError { err: "cltv_expiry_delta must be at least MIN_CLTV_EXPIRY_DELTA" });
+		}
+
+		let _ = self.total_consistency_lock.read().unwrap();
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		let chan = match channel_state.by_id.get_mut(channel_id) {
+			Some(chan) => chan,
+			None => return Err(APIError::ChannelUnavailable { err: "No such channel" }),
+		};
+		if chan.update_config(config) {
+			log_trace!(self.logger, "Updated forwarding policy of channel {}", log_bytes!(channel_id[..]));
+			if let Ok(update) = self.get_channel_update(chan) {
+				channel_state.pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
+					msg: update
+				});
+			}
+		}
+		Ok(())
+	}
+
 	/// Begins the process of closing a channel. After this call (plus some timeout), no new HTLCs
 	/// will be accepted on the given channel, and after additional timeout/the closing of all
 	/// pending HTLCs, the channel will be closed on chain.
@@ -852,6 +1037,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						if let Some(short_id) = chan_entry.get().get_short_channel_id() {
 							channel_state.short_to_id.remove(&short_id);
 						}
+						for short_id in chan_entry.get().get_prev_short_channel_ids() {
+							channel_state.short_to_id.remove(short_id);
+						}
+						self.issue_channel_close_events(chan_entry.get(), ClosureReason::CooperativeClosure);
 						(failed_htlcs, Some(chan_entry.remove_entry().1))
 					} else { (failed_htlcs, None) }
 				},
@@ -877,6 +1066,15 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		Ok(())
 	}
 
+	/// Queues an Event::ChannelClosed for a channel we've just removed from by_id.
+	fn issue_channel_close_events(&self, chan: &Channel<ChanSigner>, reason: ClosureReason) {
+		self.pending_events.lock().unwrap().push(events::Event::ChannelClosed {
+			channel_id: chan.channel_id(),
+			user_id: chan.get_user_id(),
+			reason,
+		});
+	}
+
 	#[inline]
 	fn finish_force_close_channel(&self, shutdown_res: ShutdownResult) {
 		let (funding_txo_option, monitor_update, mut failed_htlcs) = shutdown_res;
@@ -896,6 +1094,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// Force closes a channel, immediately broadcasting the latest local commitment transaction to
 	/// the chain and rejecting new HTLCs on the given channel.
 	pub fn force_close_channel(&self, channel_id: &[u8; 32]) {
+		self.force_close_channel_with_reason(channel_id, ClosureReason::HolderForceClosed);
+	}
+
+	fn force_close_channel_with_reason(&self, channel_id: &[u8; 32], reason: ClosureReason) {
 		let _ = self.total_consistency_lock.read().unwrap();
 
 		let mut chan = {
@@ -905,12 +1107,16 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				if let Some(short_id) = chan.get_short_channel_id() {
 					channel_state.short_to_id.remove(&short_id);
 				}
+				for short_id in chan.get_prev_short_channel_ids() {
+					channel_state.short_to_id.remove(short_id);
+				}
 				chan
 			} else {
 				return;
 			}
 		};
 		log_trace!(self.logger, "Force-closing channel {}", log_bytes!(channel_id[..]));
+		self.issue_channel_close_events(&chan, reason);
 		self.finish_force_close_channel(chan.force_shutdown(true));
 		if let Ok(update) = self.get_channel_update(&chan) {
 			let mut channel_state = self.channel_state.lock().unwrap();
@@ -1047,12 +1253,25 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					return_err!("Upstream node set CLTV to the wrong value", 18, &byte_utils::be32_to_array(msg.cltv_expiry));
 				}
 
-				let payment_data = match next_hop_data.format {
-					msgs::OnionHopDataFormat::Legacy { .. } => None,
+				let (payment_data, keysend_preimage) = match next_hop_data.format {
+					msgs::OnionHopDataFormat::Legacy { .. } => (None, None),
 					msgs::OnionHopDataFormat::NonFinalNode { .. } => return_err!("Got non final data with an HMAC of 0", 0x4000 | 22, &[0;0]),
-					msgs::OnionHopDataFormat::FinalNode { payment_data } => payment_data,
+					msgs::OnionHopDataFormat::FinalNode { payment_data, keysend_preimage } => (payment_data, keysend_preimage),
 				};
 
+				if let Some(ref preimage) = keysend_preimage {
+					// Unless we've opted in, treat spontaneous payments as we would any other
+					// payment for an unknown payment hash.
+					if !self.default_configuration.accept_spontaneous_payments {
+						let mut htlc_msat_height_data = byte_utils::be64_to_array(msg.amount_msat).to_vec();
+						htlc_msat_height_data.extend_from_slice(&byte_utils::be32_to_array(self.latest_block_height.load(Ordering::Acquire) as u32));
+						return_err!("We don't accept spontaneous payments", 0x4000 | 15, &htlc_msat_height_data);
+					}
+					if PaymentHash(Sha256::hash(&preimage.0).into_inner()) != msg.payment_hash {
+						return_err!("Spontaneous payment preimage didn't match the payment hash", 0x4000 | 22, &[0;0]);
+					}
+				}
+
 				// Note that we could obviously respond immediately with an update_fulfill_htlc
 				// message, however that would leak that we are the recipient of this payment, so
 				// instead we stay symmetric with the forwarding case, only responding (after a
@@ -1061,10 +1280,12 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				PendingHTLCStatus::Forward(PendingHTLCInfo {
 					routing: PendingHTLCRouting::Receive {
 						payment_data,
+						keysend_preimage,
 						incoming_cltv_expiry: msg.cltv_expiry,
 					},
 					payment_hash: msg.payment_hash.clone(),
 					incoming_shared_secret: shared_secret,
+					incoming_amt_msat: msg.amount_msat,
 					amt_to_forward: next_hop_data.amt_to_forward,
 					outgoing_cltv_value: next_hop_data.outgoing_cltv_value,
 				})
@@ -1119,6 +1340,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					},
 					payment_hash: msg.payment_hash.clone(),
 					incoming_shared_secret: shared_secret,
+					incoming_amt_msat: msg.amount_msat,
 					amt_to_forward: next_hop_data.amt_to_forward,
 					outgoing_cltv_value: next_hop_data.outgoing_cltv_value,
 				})
@@ -1126,10 +1348,35 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 
 		channel_state = Some(self.channel_state.lock().unwrap());
 		if let &PendingHTLCStatus::Forward(PendingHTLCInfo { ref routing, ref amt_to_forward, ref outgoing_cltv_value, .. }) = &pending_forward_info {
+			// Reject onions we've already forwarded, lest someone who saw it once re-send it to
+			// probe whether the next hop is (still) the payment's destination. Note that we only
+			// add onions to the cache once the HTLC is irrevocably committed in forward_htlcs, as
+			// our peer will legitimately re-send uncommitted update_add_htlcs on reconnection.
+			if let PendingHTLCRouting::Forward { .. } = routing {
+				let onion_hash = Sha256::hash(&shared_secret).into_inner();
+				if channel_state.as_ref().unwrap().onion_replay_cache.values().any(|onion_hashes| onion_hashes.contains(&onion_hash)) {
+					return_err!("Onion packet was replayed", 0x2000 | 2, &[0;0]);
+				}
+			}
 			// If short_channel_id is 0 here, we'll reject the HTLC as there cannot be a channel
 			// with a short_channel_id of 0. This is important as various things later assume
 			// short_channel_id is non-0 in any ::Forward.
 			if let &PendingHTLCRouting::Forward { ref short_channel_id, .. } = routing {
+				// HTLCs to intercepted short channel ids are provided to the user in forward_htlcs,
+				// who picks the channel (if any) to forward them over.
+				if *short_channel_id != 0 && channel_state.as_ref().unwrap().intercept_scids.contains(short_channel_id) {
+					// We don't know which channel the HTLC will be forwarded over yet, so require
+					// the minimum CLTV delta we'd accept for any, see forward_intercepted_htlc. As
+					// we have no channel_update to provide for intercepted short channel ids, we
+					// send temporary_node_failure instead of errors which require one.
+					if (msg.cltv_expiry as u64) < *outgoing_cltv_value as u64 + MIN_CLTV_EXPIRY_DELTA as u64 {
+						return_err!("Forwarding node has tampered with the intended HTLC values or origin node has an obsolete cltv_expiry_delta", 0x2000 | 2, &[0;0]);
+					}
+					if let Err((err, code)) = self.check_incoming_htlc_cltv(msg.cltv_expiry, *outgoing_cltv_value) {
+						return_err!(err, if code & 0x1000 == 0x1000 { 0x2000 | 2 } else { code }, &[0;0]);
+					}
+					return (pending_forward_info, channel_state.unwrap());
+				}
 				let id_option = channel_state.as_ref().unwrap().short_to_id.get(&short_channel_id).cloned();
 				let forwarding_id = match id_option {
 					None => { // unknown_next_peer
@@ -1148,29 +1395,18 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					if !chan.is_live() { // channel_disabled
 						break Some(("Forwarding channel is not in a ready state.", 0x1000 | 20, Some(self.get_channel_update(chan).unwrap())));
 					}
-					if *amt_to_forward < chan.get_their_htlc_minimum_msat() { // amount_below_minimum
-						break Some(("HTLC amount was below the htlc_minimum_msat", 0x1000 | 11, Some(self.get_channel_update(chan).unwrap())));
-					}
-					let fee = amt_to_forward.checked_mul(chan.get_fee_proportional_millionths() as u64).and_then(|prop_fee| { (prop_fee / 1000000).checked_add(chan.get_our_fee_base_msat(&self.fee_estimator) as u64) });
-					if fee.is_none() || msg.amount_msat < fee.unwrap() || (msg.amount_msat - fee.unwrap()) < *amt_to_forward { // fee_insufficient
-						break Some(("Prior hop has deviated from specified fees parameters or origin node has obsolete ones", 0x1000 | 12, Some(self.get_channel_update(chan).unwrap())));
-					}
-					if (msg.cltv_expiry as u64) < (*outgoing_cltv_value) as u64 + CLTV_EXPIRY_DELTA as u64 { // incorrect_cltv_expiry
-						break Some(("Forwarding node has tampered with the intended HTLC values or origin node has an obsolete cltv_expiry_delta", 0x1000 | 13, Some(self.get_channel_update(chan).unwrap())));
-					}
-					let cur_height = self.latest_block_height.load(Ordering::Acquire) as u32 + 1;
-					// Theoretically, channel counterparty shouldn't send us a HTLC expiring now, but we want to be robust wrt to counterparty
-					// packet sanitization (see HTLC_FAIL_BACK_BUFFER rational)
-					if msg.cltv_expiry <= cur_height + HTLC_FAIL_BACK_BUFFER as u32 { // expiry_too_soon
-						break Some(("CLTV expiry is too close", 0x1000 | 14, Some(self.get_channel_update(chan).unwrap())));
-					}
-					if msg.cltv_expiry > cur_height + CLTV_FAR_FAR_AWAY as u32 { // expiry_too_far
-						break Some(("CLTV expiry is too far in the future", 21, None));
+					// HTLCs sent according to our previous forwarding policy are accepted until the
+					// channel_update for the current one has had some time to propagate.
+					if let Err((err, code)) = self.check_forwarding_policy(chan, &chan.config(), msg, *amt_to_forward, *outgoing_cltv_value) {
+						match chan.prev_config() {
+							Some(prev_config) if self.check_forwarding_policy(chan, &prev_config, msg, *amt_to_forward, *outgoing_cltv_value).is_ok() => {},
+							_ => break Some((err, code, Some(self.get_channel_update(chan).unwrap()))),
+						}
 					}
-					// In theory, we would be safe against unitentional channel-closure, if we only required a margin of LATENCY_GRACE_PERIOD_BLOCKS.
-					// But, to be safe against policy reception, we use a longuer delay.
-					if (*outgoing_cltv_value) as u64 <= (cur_height + HTLC_FAIL_BACK_BUFFER) as u64 {
-						break Some(("Outgoing CLTV value is too soon", 0x1000 | 14, Some(self.get_channel_update(chan).unwrap())));
+					if let Err((err, code)) = self.check_incoming_htlc_cltv(msg.cltv_expiry, *outgoing_cltv_value) {
+						// expiry_too_far is the only error which doesn't come with a channel_update.
+						let chan_update = if code & 0x1000 == 0x1000 { Some(self.get_channel_update(chan).unwrap()) } else { None };
+						break Some((err, code, chan_update));
 					}
 
 					break None;
@@ -1197,6 +1433,56 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		(pending_forward_info, channel_state.unwrap())
 	}
 
+	/// Checks the CLTV expiry of an HTLC we've been asked to forward, and the CLTV expiry it asks
+	/// for the outbound HTLC, against the current block height, returning the failure message and
+	/// code if either is too soon or far.
+	fn check_incoming_htlc_cltv(&self, cltv_expiry: u32, outgoing_cltv_value: u32) -> Result<(), (&'static str, u16)> {
+		let cur_height = self.latest_block_height.load(Ordering::Acquire) as u32 + 1;
+		// Theoretically, channel counterparty shouldn't send us a HTLC expiring now, but we want to be robust wrt to counterparty
+		// packet sanitization (see HTLC_FAIL_BACK_BUFFER rational)
+		if cltv_expiry <= cur_height + HTLC_FAIL_BACK_BUFFER { // expiry_too_soon
+			return Err(("CLTV expiry is too close", 0x1000 | 14));
+		}
+		if cltv_expiry > cur_height + CLTV_FAR_FAR_AWAY { // expiry_too_far
+			return Err(("CLTV expiry is too far in the future", 21));
+		}
+		// In theory, we would be safe against unitentional channel-closure, if we only required a margin of LATENCY_GRACE_PERIOD_BLOCKS.
+		// But, to be safe against policy reception, we use a longuer delay.
+		if outgoing_cltv_value as u64 <= (cur_height + HTLC_FAIL_BACK_BUFFER) as u64 {
+			return Err(("Outgoing CLTV value is too soon", 0x1000 | 14));
+		}
+		Ok(())
+	}
+
+	/// Checks an HTLC we've been asked to forward over the given channel against the forwarding
+	/// policy in config, returning the failure message and code if it doesn't satisfy it.
+	fn check_forwarding_policy(&self, chan: &Channel<ChanSigner>, config: &ChannelConfig, msg: &msgs::UpdateAddHTLC, amt_to_forward: u64, outgoing_cltv_value: u32) -> Result<(), (&'static str, u16)> {
+		if amt_to_forward < cmp::max(config.forwarding_htlc_minimum_msat, chan.get_their_htlc_minimum_msat()) { // amount_below_minimum
+			return Err(("HTLC amount was below the htlc_minimum_msat", 0x1000 | 11));
+		}
+		if let Some(htlc_maximum_msat) = config.forwarding_htlc_maximum_msat {
+			if amt_to_forward > htlc_maximum_msat { // temporary_channel_failure
+				return Err(("HTLC amount was above the htlc_maximum_msat", 0x1000 | 7));
+			}
+		}
+		let fee = amt_to_forward.checked_mul(config.fee_proportional_millionths as u64).and_then(|prop_fee| { (prop_fee / 1000000).checked_add(self.get_fee_base_msat(chan, config) as u64) });
+		if fee.is_none() || msg.amount_msat < fee.unwrap() || (msg.amount_msat - fee.unwrap()) < amt_to_forward { // fee_insufficient
+			return Err(("Prior hop has deviated from specified fees parameters or origin node has obsolete ones", 0x1000 | 12));
+		}
+		if (msg.cltv_expiry as u64) < outgoing_cltv_value as u64 + config.cltv_expiry_delta as u64 { // incorrect_cltv_expiry
+			return Err(("Forwarding node has tampered with the intended HTLC values or origin node has an obsolete cltv_expiry_delta", 0x1000 | 13));
+		}
+		Ok(())
+	}
+
+	/// Gets the base fee we charge for forwarding over the given channel according to config.
+	fn get_fee_base_msat(&self, chan: &Channel<ChanSigner>, config: &ChannelConfig) -> u32 {
+		match config.fee_base_msat {
+			Some(fee_base_msat) => fee_base_msat,
+			None => chan.get_our_fee_base_msat(&self.fee_estimator),
+		}
+	}
+
 	/// only fails if the channel does not yet have an assigned short_id
 	/// May be called with channel_state already locked!
 	fn get_channel_update(&self, chan: &Channel<ChanSigner>) -> Result<msgs::ChannelUpdate, LightningError> {
@@ -1207,15 +1493,22 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 
 		let were_node_one = PublicKey::from_secret_key(&self.secp_ctx, &self.our_network_key).serialize()[..] < chan.get_their_node_id().serialize()[..];
 
+		let config = chan.config();
+		let htlc_maximum_msat = match config.forwarding_htlc_maximum_msat {
+			Some(htlc_maximum_msat) => msgs::OptionalField::Present(cmp::min(htlc_maximum_msat, chan.get_value_satoshis() * 1000)),
+			None => msgs::OptionalField::Absent,
+		};
 		let unsigned = msgs::UnsignedChannelUpdate {
 			chain_hash: self.genesis_hash,
 			short_channel_id: short_channel_id,
 			timestamp: chan.get_update_time_counter(),
-			flags: (!were_node_one) as u16 | ((!chan.is_live() as u16) << 1),
-			cltv_expiry_delta: CLTV_EXPIRY_DELTA,
-			htlc_minimum_msat: chan.get_our_htlc_minimum_msat(),
-			fee_base_msat: chan.get_our_fee_base_msat(&self.fee_estimator),
-			fee_proportional_millionths: chan.get_fee_proportional_millionths(),
+			flags: (!were_node_one) as u16 | ((!chan.is_live() as u16) << 1) |
+				if let msgs::OptionalField::Present(_) = htlc_maximum_msat { 1 << 8 } else { 0 },
+			cltv_expiry_delta: config.cltv_expiry_delta,
+			htlc_minimum_msat: cmp::max(config.forwarding_htlc_minimum_msat, chan.get_our_htlc_minimum_msat()),
+			fee_base_msat: self.get_fee_base_msat(chan, &config),
+			fee_proportional_millionths: config.fee_proportional_millionths,
+			htlc_maximum_msat,
 			excess_data: Vec::new(),
 		};
 
@@ -1229,13 +1522,13 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	}
 
 	// Only public for testing, this should otherwise never be called direcly
-	pub(crate) fn send_payment_along_path(&self, path: &Vec<RouteHop>, payment_hash: &PaymentHash, payment_secret: &Option<PaymentSecret>, total_value: u64, cur_height: u32) -> Result<(), APIError> {
+	pub(crate) fn send_payment_along_path(&self, path: &Vec<RouteHop>, payment_hash: &PaymentHash, payment_secret: &Option<PaymentSecret>, keysend_preimage: &Option<PaymentPreimage>, total_value: u64, cur_height: u32) -> Result<(), APIError> {
 		log_trace!(self.logger, "Attempting to send payment for path with next hop {}", path.first().unwrap().short_channel_id);
 		let (session_priv, prng_seed) = self.keys_manager.get_onion_rand();
 
 		let onion_keys = onion_utils::construct_onion_keys(&self.secp_ctx, &path, &session_priv)
 			.map_err(|_| APIError::RouteError{err: "Pubkey along hop was maliciously selected"})?;
-		let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(path, total_value, payment_secret, cur_height)?;
+		let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(path, total_value, payment_secret, keysend_preimage, cur_height)?;
 		if onion_utils::route_size_insane(&onion_payloads) {
 			return Err(APIError::RouteError{err: "Route size too large considering onion data"});
 		}
@@ -1266,7 +1559,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					}, onion_packet, &self.logger), channel_state, chan)
 				} {
 					Some((update_add, commitment_signed, monitor_update)) => {
-						if let Err(e) = self.monitor.update_monitor(chan.get().get_funding_txo().unwrap(), monitor_update) {
+						if let Err(e) = self.monitor.update_monitor(chan.get().get_original_funding_txo().unwrap(), monitor_update) {
 							maybe_break_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::CommitmentFirst, false, true);
 							// Note that MonitorUpdateFailed here indicates (per function docs)
 							// that we will resend the commitment update once monitor updating
@@ -1342,6 +1635,52 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// bit set (either as required or as available). If multiple paths are present in the Route,
 	/// we assume the invoice had the basic_mpp feature set.
 	pub fn send_payment(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>) -> Result<(), PaymentSendFailure> {
+		self.send_payment_internal(route, payment_hash, payment_secret, &None, None)
+	}
+
+	/// Sends the paths in the given route as additional parts of a payment for total_msat, some
+	/// parts of which may already be in flight. This allows you to retry the parts of a
+	/// multi-path payment which failed (either when sending, as indicated by a
+	/// PaymentSendFailure::PartialFailure, or later via Event::PaymentFailed) without re-sending
+	/// the parts which are still pending.
+	///
+	/// total_msat must be the same value as was sent in the original payment. As the recipient
+	/// must recognize the new parts as belonging to the same payment, payment_secret is required
+	/// unless the route pays the full total_msat.
+	///
+	/// See ChannelManager::send_payment for more details on the return value and the caveats
+	/// which apply.
+	pub fn retry_payment(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>, total_msat: u64) -> Result<(), PaymentSendFailure> {
+		self.send_payment_internal(route, payment_hash, payment_secret, &None, Some(total_msat))
+	}
+
+	/// Sends a spontaneous ("keysend") payment along a given route, ie a payment for which we did
+	/// not receive an invoice. The payment preimage is included in the onion for the recipient,
+	/// who must have opted in to accepting such payments (see
+	/// UserConfig::accept_spontaneous_payments).
+	///
+	/// If payment_preimage is None, we generate a random preimage using the KeysInterface. The
+	/// hash of the preimage used is returned on success. Note that, as the recipient learns the
+	/// preimage from the onion, it does not serve as proof-of-payment.
+	///
+	/// As no payment_secret is available for spontaneous payments, the route must contain exactly
+	/// one path, and the recipient must support variable-length onions.
+	///
+	/// See ChannelManager::send_payment for more details on the return value and the caveats
+	/// which apply.
+	pub fn send_spontaneous_payment(&self, route: &Route, payment_preimage: Option<PaymentPreimage>) -> Result<PaymentHash, PaymentSendFailure> {
+		if route.paths.len() != 1 {
+			return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "Spontaneous payments must be sent over exactly one path"}));
+		}
+		let payment_preimage = match payment_preimage {
+			Some(payment_preimage) => payment_preimage,
+			None => PaymentPreimage(self.keys_manager.get_secure_random_bytes()),
+		};
+		let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
+		self.send_payment_internal(route, payment_hash, &None, &Some(payment_preimage), None).map(|()| payment_hash)
+	}
+
+	fn send_payment_internal(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>, keysend_preimage: &Option<PaymentPreimage>, total_msat: Option<u64>) -> Result<(), PaymentSendFailure> {
 		if route.paths.len() < 1 {
 			return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "There must be at least one path to send over"}));
 		}
@@ -1371,11 +1710,20 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		if path_errs.iter().any(|e| e.is_err()) {
 			return Err(PaymentSendFailure::PathParameterError(path_errs));
 		}
+		if let Some(total_msat) = total_msat {
+			if total_value > total_msat {
+				return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "Route pays more than the total payment value"}));
+			}
+			if total_value < total_msat && payment_secret.is_none() {
+				return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "Cannot send part of a payment without a payment_secret"}));
+			}
+			total_value = total_msat;
+		}
 
 		let cur_height = self.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 		let mut results = Vec::new();
 		for path in route.paths.iter() {
-			results.push(self.send_payment_along_path(&path, &payment_hash, payment_secret, total_value, cur_height));
+			results.push(self.send_payment_along_path(&path, &payment_hash, payment_secret, keysend_preimage, total_value, cur_height));
 		}
 		let mut has_ok = false;
 		let mut has_err = false;
@@ -1426,7 +1774,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				Ok(funding_msg) => {
 					(chan, funding_msg)
 				},
-				Err(_) => { return; }
+				Err(e) => {
+					self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: e.err.to_owned() });
+					return;
+				}
 			}
 		};
 
@@ -1445,6 +1796,221 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 	}
 
+	/// Call this in response to an Event::FundingContributionRequired with the funds, if any, you
+	/// wish to add to the dual-funded channel our counterparty is opening. A contribution with a
+	/// funding_satoshis of 0 and no inputs or outputs accepts the channel without adding to it.
+	///
+	/// Note that ALL inputs MUST spend SegWit outputs and cover the contribution's funding amount,
+	/// outputs and fees at the event's funding_feerate_per_kw, or an APIError::APIMisuseError is
+	/// returned.
+	///
+	/// Requests are rejected with reject_inbound_channel, and time out, exactly as those which
+	/// generate an Event::OpenChannelRequest do.
+	pub fn contribute_channel_funding(&self, temporary_channel_id: &[u8; 32], contribution: FundingContribution) -> Result<(), APIError> {
+		let _ = self.total_consistency_lock.read().unwrap();
+
+		let their_node_id = match self.channel_state.lock().unwrap().pending_inbound_channels.get(temporary_channel_id) {
+			Some(pending) => pending.channel.get_their_node_id(),
+			None => return Err(APIError::ChannelUnavailable { err: "No such channel request" }),
+		};
+		let their_features = match self.per_peer_state.read().unwrap().get(&their_node_id) {
+			Some(peer_state) => peer_state.lock().unwrap().latest_features.clone(),
+			None => InitFeatures::empty(),
+		};
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		let (chan, msg) = match channel_state.pending_inbound_channels.entry(*temporary_channel_id) {
+			hash_map::Entry::Occupied(mut pending) => {
+				let msg = pending.get_mut().channel.accept_dual_funded_channel(contribution, &their_features)?;
+				(pending.remove().channel, msg)
+			},
+			hash_map::Entry::Vacant(_) => return Err(APIError::ChannelUnavailable { err: "No such channel request" }),
+		};
+		match channel_state.by_id.entry(chan.channel_id()) {
+			hash_map::Entry::Occupied(_) => {
+				return Err(APIError::ChannelUnavailable { err: "Already had channel with the new channel_id" });
+			},
+			hash_map::Entry::Vacant(e) => {
+				channel_state.pending_msg_events.push(events::MessageSendEvent::SendAcceptChannelV2 {
+					node_id: their_node_id,
+					msg,
+				});
+				e.insert(chan);
+			}
+		}
+		Ok(())
+	}
+
+	/// Call this in response to an Event::OpenChannelRequest to accept the channel our
+	/// counterparty requested, sending it our accept_channel.
+	///
+	/// Raises APIError::ChannelUnavailable if there is no such request pending, eg because it timed
+	/// out or the peer disconnected.
+	pub fn accept_inbound_channel(&self, temporary_channel_id: &[u8; 32]) -> Result<(), APIError> {
+		let _ = self.total_consistency_lock.read().unwrap();
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		let channel = match channel_state.pending_inbound_channels.remove(temporary_channel_id) {
+			Some(pending) => pending.channel,
+			None => return Err(APIError::ChannelUnavailable { err: "No such channel request" }),
+		};
+		match channel_state.by_id.entry(*temporary_channel_id) {
+			hash_map::Entry::Occupied(_) => return Err(APIError::ChannelUnavailable { err: "Already had channel with the same temporary_channel_id" }),
+			hash_map::Entry::Vacant(entry) => {
+				channel_state.pending_msg_events.push(events::MessageSendEvent::SendAcceptChannel {
+					node_id: channel.get_their_node_id(),
+					msg: channel.get_accept_channel(),
+				});
+				entry.insert(channel);
+			}
+		}
+		Ok(())
+	}
+
+	/// Call this in response to an Event::OpenChannelRequest or
+	/// Event::FundingContributionRequired to reject the channel our counterparty requested,
+	/// sending it an error message.
+	///
+	/// Raises APIError::ChannelUnavailable if there is no such request pending, eg because it timed
+	/// out or the peer disconnected.
+	pub fn reject_inbound_channel(&self, temporary_channel_id: &[u8; 32]) -> Result<(), APIError> {
+		let _ = self.total_consistency_lock.read().unwrap();
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.pending_inbound_channels.remove(temporary_channel_id) {
+			Some(pending) => {
+				channel_state.pending_msg_events.push(Self::inbound_channel_rejection(&pending, "Channel request rejected"));
+				Ok(())
+			},
+			None => Err(APIError::ChannelUnavailable { err: "No such channel request" }),
+		}
+	}
+
+	fn inbound_channel_rejection(pending: &PendingInboundChannel<ChanSigner>, err: &str) -> events::MessageSendEvent {
+		events::MessageSendEvent::HandleError {
+			node_id: pending.channel.get_their_node_id(),
+			action: msgs::ErrorAction::SendErrorMessage {
+				msg: msgs::ErrorMessage {
+					channel_id: pending.channel.channel_id(),
+					data: err.to_owned(),
+				},
+			},
+		}
+	}
+
+	/// Call this in response to an Event::FundingTransactionReadyForSigning with the event's
+	/// unsigned_transaction, with the witnesses for our inputs filled in. Our tx_signatures will
+	/// then be sent (once it is our turn to do so) and the funding transaction broadcast once our
+	/// counterparty has provided its own.
+	///
+	/// Raises APIError::APIMisuseError if the transaction is not the channel's funding transaction
+	/// or is missing witnesses for any of our inputs.
+	pub fn funding_transaction_signed(&self, channel_id: &[u8; 32], transaction: &Transaction) -> Result<(), APIError> {
+		let _ = self.total_consistency_lock.read().unwrap();
+
+		let funding_tx = {
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			match channel_state.by_id.get_mut(channel_id) {
+				Some(chan) => {
+					let (tx_signatures, funding_tx) = chan.funding_transaction_signed(transaction)?;
+					if let Some(msg) = tx_signatures {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+							node_id: chan.get_their_node_id(),
+							msg,
+						});
+					}
+					funding_tx
+				},
+				None => return Err(APIError::ChannelUnavailable { err: "No such channel" }),
+			}
+		};
+		if let Some(tx) = funding_tx {
+			self.tx_broadcaster.broadcast_transaction(&tx);
+		}
+		Ok(())
+	}
+
+	/// Splices funds into the given channel, growing its capacity by the contribution's
+	/// funding_satoshis without closing it. The splice transaction spending the current funding
+	/// output along with the contribution's inputs is constructed interactively with our
+	/// counterparty, resulting in an Event::FundingTransactionReadyForSigning once our inputs
+	/// must be signed.
+	///
+	/// The channel cannot be used to send or forward payments until the splice has been
+	/// negotiated. Once signed, the splice transaction replaces the channel's funding transaction
+	/// after reaching the channel's minimum depth. Until both sides have signed it, either may
+	/// abandon the splice, leaving the channel as it was.
+	///
+	/// Raises APIError::APIMisuseError when the peer does not support splicing or the
+	/// contribution's inputs don't spend SegWit outputs or don't cover its funding amount, outputs
+	/// and fees, and APIError::ChannelUnavailable if the channel isn't currently usable.
+	pub fn splice_in(&self, channel_id: &[u8; 32], contribution: FundingContribution) -> Result<(), APIError> {
+		let feerate_per_kw = self.fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+		self.begin_splice(channel_id, |chan, locktime, their_features| chan.splice_in(contribution, feerate_per_kw, locktime, their_features))
+	}
+
+	/// Splices funds out of the given channel to the given script_pubkey, shrinking its capacity
+	/// by amount_satoshis plus the fees for the output without closing it. The funds are taken
+	/// from our balance in the channel.
+	///
+	/// As with splice_in, the channel cannot be used until the splice has been negotiated. As we
+	/// contribute no inputs, the splice transaction is signed without any further action.
+	///
+	/// Raises APIError::APIMisuseError when the peer does not support splicing, the output is
+	/// dust or the amount is more than our balance less our channel reserve, and
+	/// APIError::ChannelUnavailable if the channel isn't currently usable.
+	pub fn splice_out(&self, channel_id: &[u8; 32], amount_satoshis: u64, script_pubkey: Script) -> Result<(), APIError> {
+		let feerate_per_kw = self.fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+		self.begin_splice(channel_id, |chan, locktime, their_features| chan.splice_out(amount_satoshis, script_pubkey, feerate_per_kw, locktime, their_features))
+	}
+
+	fn begin_splice<S: FnOnce(&mut Channel<ChanSigner>, u32, &InitFeatures) -> Result<msgs::SpliceInit, APIError>>(&self, channel_id: &[u8; 32], f: S) -> Result<(), APIError> {
+		let _ = self.total_consistency_lock.read().unwrap();
+
+		let their_node_id = match self.channel_state.lock().unwrap().by_id.get(channel_id) {
+			Some(chan) => chan.get_their_node_id(),
+			None => return Err(APIError::ChannelUnavailable { err: "No such channel" }),
+		};
+		let their_features = match self.per_peer_state.read().unwrap().get(&their_node_id) {
+			Some(peer_state) => peer_state.lock().unwrap().latest_features.clone(),
+			None => InitFeatures::empty(),
+		};
+		let locktime = self.latest_block_height.load(Ordering::Acquire) as u32;
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.get_mut(channel_id) {
+			Some(chan) => {
+				let msg = f(chan, locktime, &their_features)?;
+				channel_state.pending_msg_events.push(events::MessageSendEvent::SendSpliceInit {
+					node_id: their_node_id,
+					msg,
+				});
+			},
+			None => return Err(APIError::ChannelUnavailable { err: "No such channel" }),
+		}
+		Ok(())
+	}
+
+	/// Gets what needs doing once the initial ChannelMonitor of a dual-funded channel has been
+	/// persisted: the user signing our funding inputs or, if we didn't contribute any, sending our
+	/// tx_signatures.
+	fn get_funding_tx_signing_actions(chan: &mut Channel<ChanSigner>) -> (Option<events::Event>, Option<msgs::TxSignatures>, Option<Transaction>) {
+		if let Some(tx) = chan.get_unsigned_funding_tx() {
+			return (Some(events::Event::FundingTransactionReadyForSigning {
+				channel_id: chan.channel_id(),
+				user_channel_id: chan.get_user_id(),
+				unsigned_transaction: tx.clone(),
+			}), None, None);
+		}
+		let (tx_signatures, funding_tx) = chan.get_tx_signatures();
+		(None, tx_signatures, funding_tx)
+	}
+
 	fn get_announcement_sigs(&self, chan: &Channel<ChanSigner>) -> Option<msgs::AnnouncementSignatures> {
 		if !chan.should_announce() {
 			log_trace!(self.logger, "Can't send announcement_signatures for private channel {}", log_bytes!(chan.channel_id()));
@@ -1498,8 +2064,12 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			panic!("More than half the message size was taken up by public addresses!");
 		}
 
+		let mut features = NodeFeatures::known();
+		if !self.default_configuration.own_channel_config.negotiate_anchor_outputs {
+			features = features.clear_anchor_outputs();
+		}
 		let announcement = msgs::UnsignedNodeAnnouncement {
-			features: NodeFeatures::known(),
+			features,
 			timestamp: self.last_node_announcement_serial.fetch_add(1, Ordering::AcqRel) as u32,
 			node_id: self.get_our_node_id(),
 			rgb, alias, addresses,
@@ -1517,6 +2087,103 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		});
 	}
 
+	/// Intercepts HTLCs which we're asked to forward over the given short channel id, which need
+	/// not belong to any of our channels, providing them in Event::HTLCIntercepted instead of
+	/// forwarding or failing them. This allows, eg, opening a channel to the next hop before
+	/// forwarding the HTLC over it.
+	///
+	/// Intercepted short channel ids are persisted with the ChannelManager.
+	pub fn add_intercept_scid(&self, short_channel_id: u64) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		self.channel_state.lock().unwrap().intercept_scids.insert(short_channel_id);
+	}
+
+	/// Stops intercepting HTLCs to the given short channel id. HTLCs which were already intercepted
+	/// must still be forwarded or failed.
+	pub fn remove_intercept_scid(&self, short_channel_id: u64) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		self.channel_state.lock().unwrap().intercept_scids.remove(&short_channel_id);
+	}
+
+	/// Call this in response to an Event::HTLCIntercepted to forward the intercepted HTLC over the
+	/// given channel, sending amt_to_forward_msat to the next hop. The HTLC is then forwarded as
+	/// any other upon the next process_pending_htlc_forwards call, failing backwards if it cannot
+	/// be sent over the channel.
+	///
+	/// Note that amt_to_forward_msat is not checked against the value of the inbound HTLC, nor is
+	/// the channel's forwarding policy applied, so you may forward less than was requested, eg to
+	/// cover the cost of opening the channel. The CLTV expiry of the inbound HTLC must however
+	/// leave room for the channel's cltv_expiry_delta after the outbound one.
+	///
+	/// Raises APIError::ChannelUnavailable if the channel doesn't exist or doesn't have a short
+	/// channel id yet, or if the HTLC can't be safely forwarded over it given its CLTV expiry, in
+	/// which case it remains intercepted. Raises APIError::APIMisuseError if there is no such
+	/// intercepted HTLC, eg because it was already forwarded or failed, or timed out.
+	pub fn forward_intercepted_htlc(&self, intercept_id: InterceptId, next_hop_channel_id: &[u8; 32], amt_to_forward_msat: u64) -> Result<(), APIError> {
+		let _ = self.total_consistency_lock.read().unwrap();
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		let (next_hop_scid, cltv_expiry_delta) = match channel_state.by_id.get(next_hop_channel_id) {
+			Some(chan) => match chan.get_short_channel_id() {
+				Some(short_channel_id) => (short_channel_id, chan.config().cltv_expiry_delta),
+				None => return Err(APIError::ChannelUnavailable { err: "Channel is not yet confirmed" }),
+			},
+			None => return Err(APIError::ChannelUnavailable { err: "No such channel" }),
+		};
+		match channel_state.pending_intercepted_htlcs.get(&intercept_id) {
+			Some(htlc) => {
+				if (htlc.cltv_expiry as u64) < htlc.forward_info.outgoing_cltv_value as u64 + cltv_expiry_delta as u64 {
+					return Err(APIError::ChannelUnavailable { err: "The HTLC's CLTV expiry doesn't leave room for the channel's cltv_expiry_delta" });
+				}
+				if let Err((err, _)) = self.check_incoming_htlc_cltv(htlc.cltv_expiry, htlc.forward_info.outgoing_cltv_value) {
+					return Err(APIError::ChannelUnavailable { err });
+				}
+			},
+			None => return Err(APIError::APIMisuseError { err: "No such intercepted HTLC" }),
+		}
+		let InterceptedHTLC { prev_short_channel_id, prev_htlc_id, mut forward_info, .. } = channel_state.pending_intercepted_htlcs.remove(&intercept_id).unwrap();
+		if let PendingHTLCRouting::Forward { ref mut short_channel_id, .. } = forward_info.routing {
+			*short_channel_id = next_hop_scid;
+		}
+		forward_info.amt_to_forward = amt_to_forward_msat;
+
+		if channel_state.forward_htlcs.is_empty() {
+			self.pending_events.lock().unwrap().push(events::Event::PendingHTLCsForwardable {
+				time_forwardable: Duration::from_millis(MIN_HTLC_RELAY_HOLDING_CELL_MILLIS)
+			});
+		}
+		channel_state.forward_htlcs.entry(next_hop_scid).or_insert(Vec::new())
+			.push(HTLCForwardInfo::AddHTLC { prev_short_channel_id, prev_htlc_id, forward_info });
+		Ok(())
+	}
+
+	/// Call this in response to an Event::HTLCIntercepted to fail the intercepted HTLC backwards
+	/// as if its next hop were unknown to us. As with other HTLCs we fail backwards, the failure is
+	/// sent upon the next process_pending_htlc_forwards call.
+	///
+	/// Raises APIError::APIMisuseError if there is no such intercepted HTLC, eg because it was
+	/// already forwarded or failed, or timed out.
+	pub fn fail_intercepted_htlc(&self, intercept_id: InterceptId) -> Result<(), APIError> {
+		let _ = self.total_consistency_lock.read().unwrap();
+
+		let mut channel_state = self.channel_state.lock().unwrap();
+		let htlc = match channel_state.pending_intercepted_htlcs.remove(&intercept_id) {
+			Some(htlc) => htlc,
+			None => return Err(APIError::APIMisuseError { err: "No such intercepted HTLC" }),
+		};
+		let htlc_source = HTLCSource::PreviousHopData(HTLCPreviousHopData {
+			short_channel_id: htlc.prev_short_channel_id,
+			htlc_id: htlc.prev_htlc_id,
+			incoming_packet_shared_secret: htlc.forward_info.incoming_shared_secret,
+			prev_channel_id: None,
+			incoming_amt_msat: None,
+		});
+		self.fail_htlc_backwards_internal(channel_state, htlc_source, &htlc.forward_info.payment_hash,
+			HTLCFailReason::Reason { failure_code: 0x4000 | 10, data: Vec::new() });
+		Ok(())
+	}
+
 	/// Processes HTLCs which are pending waiting on random forward delay.
 	///
 	/// Should only really ever be called in response to a PendingHTLCsForwardable event.
@@ -1544,6 +2211,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 											short_channel_id: prev_short_channel_id,
 											htlc_id: prev_htlc_id,
 											incoming_packet_shared_secret: forward_info.incoming_shared_secret,
+											prev_channel_id: None,
+											incoming_amt_msat: None,
 										});
 										failed_forwards.push((htlc_source, forward_info.payment_hash,
 											HTLCFailReason::Reason { failure_code: 0x4000 | 10, data: Vec::new() }
@@ -1568,12 +2237,14 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 								HTLCForwardInfo::AddHTLC { prev_short_channel_id, prev_htlc_id, forward_info: PendingHTLCInfo {
 										routing: PendingHTLCRouting::Forward {
 											onion_packet, ..
-										}, incoming_shared_secret, payment_hash, amt_to_forward, outgoing_cltv_value }, } => {
+										}, incoming_shared_secret, payment_hash, incoming_amt_msat, amt_to_forward, outgoing_cltv_value, .. }, } => {
 									log_trace!(self.logger, "Adding HTLC from short id {} with payment_hash {} to channel with short id {} after delay", log_bytes!(payment_hash.0), prev_short_channel_id, short_chan_id);
 									let htlc_source = HTLCSource::PreviousHopData(HTLCPreviousHopData {
 										short_channel_id: prev_short_channel_id,
 										htlc_id: prev_htlc_id,
 										incoming_packet_shared_secret: incoming_shared_secret,
+										prev_channel_id: channel_state.short_to_id.get(&prev_short_channel_id).cloned(),
+										incoming_amt_msat: Some(incoming_amt_msat),
 									});
 									match chan.get_mut().send_htlc(amt_to_forward, payment_hash, outgoing_cltv_value, htlc_source.clone(), onion_packet) {
 										Err(e) => {
@@ -1654,6 +2325,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 											if let Some(short_id) = channel.get_short_channel_id() {
 												channel_state.short_to_id.remove(&short_id);
 											}
+											for short_id in channel.get_prev_short_channel_ids() {
+												channel_state.short_to_id.remove(short_id);
+											}
+											self.issue_channel_close_events(&channel, ClosureReason::ProcessingError { err: msg.to_owned() });
 											Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, channel.force_shutdown(true), self.get_channel_update(&channel).ok()))
 										},
 										ChannelError::CloseDelayBroadcast(_) => { panic!("Wait is only generated on receipt of channel_reestablish, which is handled by try_chan_entry, we don't bother to support it here"); }
@@ -1662,7 +2337,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 									continue;
 								}
 							};
-							if let Err(e) = self.monitor.update_monitor(chan.get().get_funding_txo().unwrap(), monitor_update) {
+							if let Err(e) = self.monitor.update_monitor(chan.get().get_original_funding_txo().unwrap(), monitor_update) {
 								handle_errors.push((chan.get().get_their_node_id(), handle_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::CommitmentFirst, false, true)));
 								continue;
 							}
@@ -1685,17 +2360,38 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					for forward_info in pending_forwards.drain(..) {
 						match forward_info {
 							HTLCForwardInfo::AddHTLC { prev_short_channel_id, prev_htlc_id, forward_info: PendingHTLCInfo {
-									routing: PendingHTLCRouting::Receive { payment_data, incoming_cltv_expiry },
+									routing: PendingHTLCRouting::Receive { payment_data, keysend_preimage, incoming_cltv_expiry },
 									incoming_shared_secret, payment_hash, amt_to_forward, .. }, } => {
 								let prev_hop = HTLCPreviousHopData {
 									short_channel_id: prev_short_channel_id,
 									htlc_id: prev_htlc_id,
 									incoming_packet_shared_secret: incoming_shared_secret,
+									prev_channel_id: None,
+									incoming_amt_msat: None,
 								};
 
-								let mut total_value = 0;
-								let payment_secret_opt =
-									if let &Some(ref data) = &payment_data { Some(data.payment_secret.clone()) } else { None };
+								// Payments to invoices from create_inbound_payment are checked here, with
+								// the payment preimage provided to the user if we generated it.
+								let mut payment_preimage = keysend_preimage;
+								if let &Some(ref data) = &payment_data {
+									match inbound_payment::verify(payment_hash, data, self.highest_seen_timestamp.load(Ordering::Acquire) as u64, &self.inbound_payment_key, &self.logger) {
+										Ok(derived_preimage) => {
+											if payment_preimage.is_none() { payment_preimage = derived_preimage; }
+										},
+										Err(()) => {
+											let mut htlc_msat_height_data = byte_utils::be64_to_array(amt_to_forward).to_vec();
+											htlc_msat_height_data.extend_from_slice(&byte_utils::be32_to_array(self.latest_block_height.load(Ordering::Acquire) as u32));
+											failed_forwards.push((HTLCSource::PreviousHopData(prev_hop), payment_hash,
+												HTLCFailReason::Reason { failure_code: 0x4000 | 15, data: htlc_msat_height_data }
+											));
+											continue;
+										},
+									}
+								}
+
+								let mut total_value = 0;
+								let payment_secret_opt =
+									if let &Some(ref data) = &payment_data { Some(data.payment_secret.clone()) } else { None };
 								let htlcs = channel_state.claimable_htlcs.entry((payment_hash, payment_secret_opt))
 									.or_insert(Vec::new());
 								htlcs.push(ClaimableHTLC {
@@ -1703,6 +2399,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 									value: amt_to_forward,
 									payment_data: payment_data.clone(),
 									cltv_expiry: incoming_cltv_expiry,
+									timer_ticks: 0,
 								});
 								if let &Some(ref data) = &payment_data {
 									for htlc in htlcs.iter() {
@@ -1721,11 +2418,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 														as u32,
 												),
 											);
-											failed_forwards.push((HTLCSource::PreviousHopData(HTLCPreviousHopData {
-													short_channel_id: htlc.prev_hop.short_channel_id,
-													htlc_id: htlc.prev_hop.htlc_id,
-													incoming_packet_shared_secret: htlc.prev_hop.incoming_packet_shared_secret,
-												}), payment_hash,
+											failed_forwards.push((HTLCSource::PreviousHopData(htlc.prev_hop.clone()), payment_hash,
 												HTLCFailReason::Reason { failure_code: 0x4000 | 15, data: htlc_msat_height_data }
 											));
 										}
@@ -1733,6 +2426,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 										new_events.push(events::Event::PaymentReceived {
 											payment_hash: payment_hash,
 											payment_secret: Some(data.payment_secret),
+											payment_preimage,
 											amt: total_value,
 										});
 									}
@@ -1740,6 +2434,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 									new_events.push(events::Event::PaymentReceived {
 										payment_hash: payment_hash,
 										payment_secret: None,
+										payment_preimage,
 										amt: amt_to_forward,
 									});
 								}
@@ -1774,22 +2469,98 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// to inform the network about the uselessness of these channels.
 	///
 	/// This method handles all the details, and must be called roughly once per minute.
+	///
+	/// It also fails back the parts of any multi-path payments we've received which have not been
+	/// completed in time, see UserConfig::mpp_timeout_ticks.
+	///
+	/// Finally, it sends an update_fee on any channel we funded whose commitment transaction
+	/// feerate has drifted too far from our current fee estimate, see
+	/// UserConfig::update_fee_increase_threshold_percent and
+	/// UserConfig::update_fee_decrease_threshold_percent. Feerates are only raised as far as we
+	/// can afford while keeping the channel reserve.
 	pub fn timer_chan_freshness_every_min(&self) {
 		let _ = self.total_consistency_lock.read().unwrap();
-		let mut channel_state_lock = self.channel_state.lock().unwrap();
-		let channel_state = &mut *channel_state_lock;
-		for (_, chan) in channel_state.by_id.iter_mut() {
-			if chan.is_disabled_staged() && !chan.is_live() {
-				if let Ok(update) = self.get_channel_update(&chan) {
-					channel_state.pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
-						msg: update
-					});
+		let mut timed_out_mpp_htlcs = Vec::new();
+		let mut fee_updates = Vec::new();
+		{
+			let new_feerate = self.fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+			let increase_threshold_percent = self.default_configuration.update_fee_increase_threshold_percent as u64;
+			let decrease_threshold_percent = self.default_configuration.update_fee_decrease_threshold_percent as u64;
+
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			for (channel_id, chan) in channel_state.by_id.iter_mut() {
+				chan.maybe_expire_prev_config();
+				if chan.is_outbound() && chan.is_live() {
+					let feerate = chan.get_latest_outbound_feerate() as u64;
+					if (new_feerate as u64) * 100 > feerate * (100 + increase_threshold_percent) {
+						// Our counterparty would close the channel if we raised the feerate beyond
+						// what we can pay for while keeping our reserve, so raise it as far as we can.
+						let max_feerate = chan.get_max_affordable_outbound_feerate();
+						if max_feerate as u64 > feerate {
+							fee_updates.push((*channel_id, cmp::min(new_feerate, max_feerate)));
+						} else {
+							log_debug!(self.logger, "Cannot afford to raise feerate of channel {} above {} sat per 1000 weight", log_bytes!(channel_id[..]), feerate);
+						}
+					} else if decrease_threshold_percent < 100 && (new_feerate as u64) * 100 < feerate * (100 - decrease_threshold_percent) {
+						fee_updates.push((*channel_id, new_feerate));
+					}
+				}
+				if chan.is_disabled_staged() && !chan.is_live() {
+					if let Ok(update) = self.get_channel_update(&chan) {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
+							msg: update
+						});
+					}
+					chan.to_fresh();
+				} else if chan.is_disabled_staged() && chan.is_live() {
+					chan.to_fresh();
+				} else if chan.is_disabled_marked() {
+					chan.to_disabled_staged();
 				}
-				chan.to_fresh();
-			} else if chan.is_disabled_staged() && chan.is_live() {
-				chan.to_fresh();
-			} else if chan.is_disabled_marked() {
-				chan.to_disabled_staged();
+			}
+
+			let pending_msg_events = &mut channel_state.pending_msg_events;
+			channel_state.pending_inbound_channels.retain(|_, pending| {
+				pending.timer_ticks = pending.timer_ticks.saturating_add(1);
+				if pending.timer_ticks >= INBOUND_CHANNEL_REQUEST_TIMEOUT_TICKS {
+					pending_msg_events.push(Self::inbound_channel_rejection(pending, "Channel request timed out"));
+					false
+				} else { true }
+			});
+
+			let mpp_timeout_ticks = self.default_configuration.mpp_timeout_ticks;
+			channel_state.claimable_htlcs.retain(|&(ref payment_hash, _), htlcs| {
+				// Only multi-path payments we're still waiting on parts of can time out, anything
+				// else is waiting on the user to claim or fail it.
+				let total_msat = match htlcs[0].payment_data {
+					Some(ref data) => data.total_msat,
+					None => return true,
+				};
+				if htlcs.iter().map(|htlc| htlc.value).sum::<u64>() >= total_msat {
+					return true;
+				}
+				for htlc in htlcs.iter_mut() {
+					htlc.timer_ticks = htlc.timer_ticks.saturating_add(1);
+				}
+				if htlcs[0].timer_ticks >= mpp_timeout_ticks {
+					for htlc in htlcs.drain(..) {
+						timed_out_mpp_htlcs.push((HTLCSource::PreviousHopData(htlc.prev_hop), payment_hash.clone()));
+					}
+					false
+				} else { true }
+			});
+		}
+
+		for (source, payment_hash) in timed_out_mpp_htlcs.drain(..) {
+			self.fail_htlc_backwards_internal(self.channel_state.lock().unwrap(), source, &payment_hash,
+				HTLCFailReason::Reason { failure_code: 23, data: Vec::new() });
+		}
+
+		for (channel_id, feerate_per_kw) in fee_updates.drain(..) {
+			log_debug!(self.logger, "Updating feerate of channel {} to {} sat per 1000 weight", log_bytes!(channel_id), feerate_per_kw);
+			if let Err(e) = self.update_fee(channel_id, feerate_per_kw) {
+				log_debug!(self.logger, "Failed to update feerate of channel {}: {:?}", log_bytes!(channel_id), e);
 			}
 		}
 	}
@@ -1836,17 +2607,14 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				mem::drop(channel_state_lock);
 				match &onion_error {
 					&HTLCFailReason::LightningError { ref err } => {
-#[cfg(test)]
-						let (channel_update, payment_retryable, onion_error_code, onion_error_data) = onion_utils::process_onion_failure(&self.secp_ctx, &self.logger, &source, err.data.clone());
-#[cfg(not(test))]
-						let (channel_update, payment_retryable, _, _) = onion_utils::process_onion_failure(&self.secp_ctx, &self.logger, &source, err.data.clone());
+						let (channel_update, payment_retryable, failure) = onion_utils::process_onion_failure(&self.secp_ctx, &self.logger, &source, err.data.clone());
 						// TODO: If we decided to blame ourselves (or one of our channels) in
 						// process_onion_failure we should close that channel as it implies our
 						// next-hop is needlessly blaming us!
-						if let Some(update) = channel_update {
+						if let Some(ref update) = channel_update {
 							self.channel_state.lock().unwrap().pending_msg_events.push(
 								events::MessageSendEvent::PaymentFailureNetworkUpdate {
-									update,
+									update: update.clone(),
 								}
 							);
 						}
@@ -1854,19 +2622,13 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 							events::Event::PaymentFailed {
 								payment_hash: payment_hash.clone(),
 								rejected_by_dest: !payment_retryable,
-#[cfg(test)]
-								error_code: onion_error_code,
-#[cfg(test)]
-								error_data: onion_error_data
+								path: path.clone(),
+								network_update: channel_update,
+								failure,
 							}
 						);
 					},
-					&HTLCFailReason::Reason {
-#[cfg(test)]
-							ref failure_code,
-#[cfg(test)]
-							ref data,
-							.. } => {
+					&HTLCFailReason::Reason { ref failure_code, ref data } => {
 						// we get a fail_malformed_htlc from the first hop
 						// TODO: We'd like to generate a PaymentFailureNetworkUpdate for temporary
 						// failures here, but that would be insufficient as get_route
@@ -1874,20 +2636,26 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						// ChannelDetails.
 						// TODO: For non-temporary failures, we really should be closing the
 						// channel here as we apparently can't relay through them anyway.
+						let failure_code = OnionFailureCode::from_u16(*failure_code);
 						self.pending_events.lock().unwrap().push(
 							events::Event::PaymentFailed {
 								payment_hash: payment_hash.clone(),
 								rejected_by_dest: path.len() == 1,
-#[cfg(test)]
-								error_code: Some(*failure_code),
-#[cfg(test)]
-								error_data: Some(data.clone()),
+								path: path.clone(),
+								network_update: None,
+								failure: Some(events::PaymentFailureDetails {
+									failing_node_id: None,
+									short_channel_id: Some(path[0].short_channel_id),
+									failure_code,
+									failure_data: data.clone(),
+									channel_update: None,
+								}),
 							}
 						);
 					}
 				}
 			},
-			HTLCSource::PreviousHopData(HTLCPreviousHopData { short_channel_id, htlc_id, incoming_packet_shared_secret }) => {
+			HTLCSource::PreviousHopData(HTLCPreviousHopData { short_channel_id, htlc_id, incoming_packet_shared_secret, .. }) => {
 				let err_packet = match onion_error {
 					HTLCFailReason::Reason { failure_code, data } => {
 						log_trace!(self.logger, "Failing HTLC with payment_hash {} backwards from us with code {}", log_bytes!(payment_hash.0), failure_code);
@@ -1923,6 +2691,46 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 	}
 
+	/// Gets a payment hash and secret to include in an invoice for an inbound payment, which we can
+	/// verify upon receipt without storing anything: payments which pay less than min_value_msat
+	/// (if set) or which arrive more than invoice_expiry_delta_secs from now are failed backwards,
+	/// while the payment preimage of any others is provided in Event::PaymentReceived for you to
+	/// pass to claim_funds.
+	///
+	/// Note that expiry is checked against the timestamps of the blocks we've seen, so we allow an
+	/// extra two hours for them to catch up with the current time. If you need exact expiry
+	/// semantics, check the invoice's expiry upon PaymentReceived.
+	///
+	/// Raises APIError::APIMisuseError if min_value_msat exceeds the total bitcoin supply, or if
+	/// no block has been connected yet, as we then have no notion of the current time.
+	pub fn create_inbound_payment(&self, min_value_msat: Option<u64>, invoice_expiry_delta_secs: u32) -> Result<(PaymentHash, PaymentSecret), APIError> {
+		let expiry_timestamp = self.inbound_payment_expiry(min_value_msat, invoice_expiry_delta_secs)?;
+		Ok(inbound_payment::create(&self.inbound_payment_key, min_value_msat, expiry_timestamp, self.keys_manager.get_secure_random_bytes()))
+	}
+
+	/// Gets a payment secret for an inbound payment to the given payment hash, for which you know
+	/// the preimage, as for create_inbound_payment but without the preimage being provided in
+	/// Event::PaymentReceived.
+	///
+	/// Raises APIError::APIMisuseError if min_value_msat exceeds the total bitcoin supply, or if
+	/// no block has been connected yet, as we then have no notion of the current time.
+	pub fn create_inbound_payment_for_hash(&self, payment_hash: PaymentHash, min_value_msat: Option<u64>, invoice_expiry_delta_secs: u32) -> Result<PaymentSecret, APIError> {
+		let expiry_timestamp = self.inbound_payment_expiry(min_value_msat, invoice_expiry_delta_secs)?;
+		Ok(inbound_payment::create_from_hash(&self.inbound_payment_key, min_value_msat, payment_hash, expiry_timestamp))
+	}
+
+	fn inbound_payment_expiry(&self, min_value_msat: Option<u64>, invoice_expiry_delta_secs: u32) -> Result<u64, APIError> {
+		if min_value_msat.unwrap_or(0) > msgs::MAX_VALUE_MSAT {
+			return Err(APIError::APIMisuseError { err: "min_value_msat must not exceed the total bitcoin supply" });
+		}
+		let highest_seen_timestamp = self.highest_seen_timestamp.load(Ordering::Acquire) as u64;
+		if highest_seen_timestamp == 0 {
+			// Otherwise the payment would already have expired by the time we see a block.
+			return Err(APIError::APIMisuseError { err: "A block must be connected before creating inbound payments" });
+		}
+		Ok(highest_seen_timestamp + invoice_expiry_delta_secs as u64 + inbound_payment::EXPIRY_TIMESTAMP_GRACE_SECS)
+	}
+
 	/// Provides a payment preimage in response to a PaymentReceived event, returning true and
 	/// generating message events for the net layer to claim the payment, if possible. Thus, you
 	/// should probably kick the net layer to go send messages if this returns true!
@@ -2001,7 +2809,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						Err(None) => {
 							log_warn!(self.logger, "Channel we expected to claim an HTLC from was closed.");
 						},
-						Ok(()) => claimed_any_htlcs = true,
+						Ok(_) => claimed_any_htlcs = true,
 					}
 				}
 			}
@@ -2019,7 +2827,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		} else { false }
 	}
 
-	fn claim_funds_from_hop(&self, channel_state_lock: &mut MutexGuard<ChannelHolder<ChanSigner>>, prev_hop: HTLCPreviousHopData, payment_preimage: PaymentPreimage) -> Result<(), Option<(PublicKey, MsgHandleErrInternal)>> {
+	/// Claims the given inbound HTLC, returning whether this call fulfilled it, rather than it
+	/// having been claimed already (eg as we see the same preimage on-chain again).
+	fn claim_funds_from_hop(&self, channel_state_lock: &mut MutexGuard<ChannelHolder<ChanSigner>>, prev_hop: HTLCPreviousHopData, payment_preimage: PaymentPreimage) -> Result<bool, Option<(PublicKey, MsgHandleErrInternal)>> {
 		//TODO: Delay the claimed_funds relaying just like we do outbound relay!
 		let channel_state = &mut **channel_state_lock;
 		let chan_id = match channel_state.short_to_id.get(&prev_hop.short_channel_id) {
@@ -2033,8 +2843,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			let was_frozen_for_monitor = chan.get().is_awaiting_monitor_update();
 			match chan.get_mut().get_update_fulfill_htlc_and_commit(prev_hop.htlc_id, payment_preimage, &self.logger) {
 				Ok((msgs, monitor_option)) => {
+					// Claiming an HTLC which was already claimed doesn't generate a monitor update.
+					let fulfilled = monitor_option.is_some();
 					if let Some(monitor_update) = monitor_option {
-						if let Err(e) = self.monitor.update_monitor(chan.get().get_funding_txo().unwrap(), monitor_update) {
+						if let Err(e) = self.monitor.update_monitor(chan.get().get_original_funding_txo().unwrap(), monitor_update) {
 							if was_frozen_for_monitor {
 								assert!(msgs.is_none());
 							} else {
@@ -2055,7 +2867,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 							}
 						});
 					}
-					return Ok(())
+					return Ok(fulfilled)
 				},
 				Err(e) => {
 					// TODO: Do something with e?
@@ -2072,26 +2884,49 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		} else { unreachable!(); }
 	}
 
-	fn claim_funds_internal(&self, mut channel_state_lock: MutexGuard<ChannelHolder<ChanSigner>>, source: HTLCSource, payment_preimage: PaymentPreimage) {
+	/// Claims the source of an HTLC we sent over next_channel_id which was claimed with the given
+	/// payment_preimage. If it was forwarded and this call claimed the inbound HTLC, or the inbound
+	/// channel has already closed, generates an Event::PaymentForwarded given the value of the
+	/// claimed HTLC (if known).
+	fn claim_funds_internal(&self, mut channel_state_lock: MutexGuard<ChannelHolder<ChanSigner>>, source: HTLCSource, payment_preimage: PaymentPreimage, next_channel_id: [u8; 32], amt_out_msat: Option<u64>, from_onchain: bool) {
 		match source {
-			HTLCSource::OutboundRoute { .. } => {
+			HTLCSource::OutboundRoute { path, .. } => {
 				mem::drop(channel_state_lock);
 				let mut pending_events = self.pending_events.lock().unwrap();
 				pending_events.push(events::Event::PaymentSent {
-					payment_preimage
+					payment_preimage,
+					path,
 				});
 			},
 			HTLCSource::PreviousHopData(hop_data) => {
-				if let Err((their_node_id, err)) = match self.claim_funds_from_hop(&mut channel_state_lock, hop_data, payment_preimage) {
-					Ok(()) => Ok(()),
+				// HTLCs forwarded before we tracked the inbound channel and value can only be
+				// matched up with them while the inbound channel is still open.
+				let prev_channel_id = hop_data.prev_channel_id
+					.or_else(|| channel_state_lock.short_to_id.get(&hop_data.short_channel_id).cloned());
+				let amt_in_msat = hop_data.incoming_amt_msat
+					.or_else(|| prev_channel_id.and_then(|id| channel_state_lock.by_id.get(&id))
+						.and_then(|chan| chan.get_pending_inbound_htlc_value_msat(hop_data.htlc_id)));
+				let (claimed, res) = match self.claim_funds_from_hop(&mut channel_state_lock, hop_data, payment_preimage) {
+					Ok(fulfilled) => (fulfilled, Ok(())),
 					Err(None) => {
 						// TODO: There is probably a channel monitor somewhere that needs to
 						// learn the preimage as the channel already hit the chain and that's
 						// why it's missing.
-						Ok(())
+						(true, Ok(()))
 					},
-					Err(Some(res)) => Err(res),
-				} {
+					Err(Some(res)) => (true, Err(res)),
+				};
+				if let (true, Some(prev_channel_id), Some(amt_in_msat), Some(amt_out_msat)) = (claimed, prev_channel_id, amt_in_msat, amt_out_msat) {
+					self.pending_events.lock().unwrap().push(events::Event::PaymentForwarded {
+						prev_channel_id,
+						next_channel_id,
+						amt_in_msat,
+						amt_out_msat,
+						fee_earned_msat: amt_in_msat.saturating_sub(amt_out_msat),
+						claim_from_onchain_tx: from_onchain,
+					});
+				}
+				if let Err((their_node_id, err)) = res {
 					mem::drop(channel_state_lock);
 					let res: Result<(), _> = Err(err);
 					let _ = handle_error!(self, res, their_node_id);
@@ -2138,10 +2973,16 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			let channel_state = &mut *channel_lock;
 			let short_to_id = &mut channel_state.short_to_id;
 			let pending_msg_events = &mut channel_state.pending_msg_events;
-			let channel = match channel_state.by_id.get_mut(&funding_txo.to_channel_id()) {
-				Some(chan) => chan,
-				None => return,
+			// Dual-funded channels' ids aren't derived from their funding outpoint.
+			let channel_id = if channel_state.by_id.contains_key(&funding_txo.to_channel_id()) {
+				funding_txo.to_channel_id()
+			} else {
+				match channel_state.by_id.iter().find(|&(_, chan)| chan.get_original_funding_txo() == Some(*funding_txo)) {
+					Some((channel_id, _)) => *channel_id,
+					None => return,
+				}
 			};
+			let channel = channel_state.by_id.get_mut(&channel_id).unwrap();
 			if !channel.is_awaiting_monitor_update() || channel.get_latest_monitor_update_id() != highest_applied_update_id {
 				return;
 			}
@@ -2184,6 +3025,19 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					user_channel_id: channel.get_user_id(),
 				});
 			}
+			let (signing_event, tx_signatures, funding_tx) = Self::get_funding_tx_signing_actions(channel);
+			if let Some(event) = signing_event {
+				pending_events.push(event);
+			}
+			if let Some(msg) = tx_signatures {
+				pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+					node_id: channel.get_their_node_id(),
+					msg,
+				});
+			}
+			if let Some(tx) = funding_tx {
+				self.tx_broadcaster.broadcast_transaction(&tx);
+			}
 			if let Some(msg) = funding_locked {
 				pending_msg_events.push(events::MessageSendEvent::SendFundingLocked {
 					node_id: channel.get_their_node_id(),
@@ -2197,6 +3051,26 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				}
 				short_to_id.insert(channel.get_short_channel_id().unwrap(), channel.channel_id());
 			}
+			match self.complete_splice(channel, short_to_id, pending_msg_events) {
+				Ok(()) => {},
+				Err(ChannelMonitorUpdateErr::TemporaryFailure) => channel.monitor_update_failed(false, false, Vec::new(), Vec::new()),
+				Err(ChannelMonitorUpdateErr::PermanentFailure) => {
+					log_error!(self.logger, "Closing channel {} due to monitor update PermanentFailure", log_bytes!(channel_id));
+					if let Some(short_id) = channel.get_short_channel_id() {
+						short_to_id.remove(&short_id);
+					}
+					for short_id in channel.get_prev_short_channel_ids() {
+						short_to_id.remove(short_id);
+					}
+					close_results.push(channel.force_shutdown(true));
+					pending_events.push(events::Event::ChannelClosed {
+						channel_id,
+						user_id: channel.get_user_id(),
+						reason: ClosureReason::ProcessingError { err: "ChannelMonitor storage failure".to_owned() },
+					});
+					channel_state.by_id.remove(&channel_id);
+				},
+			}
 		}
 
 		self.pending_events.lock().unwrap().append(&mut pending_events);
@@ -2211,18 +3085,54 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 	}
 
+	/// Each request awaiting the user's decision holds a Channel until it is answered, so don't let
+	/// peers queue up an unbounded number of them.
+	fn check_pending_inbound_channel_limits(&self, their_node_id: &PublicKey, temporary_channel_id: [u8; 32]) -> Result<(), MsgHandleErrInternal> {
+		let channel_state = self.channel_state.lock().unwrap();
+		if channel_state.pending_inbound_channels.len() >= MAX_PENDING_INBOUND_CHANNELS {
+			return Err(MsgHandleErrInternal::send_err_msg_no_close("Too many pending channel requests", temporary_channel_id));
+		}
+		if channel_state.pending_inbound_channels.values().filter(|pending| pending.channel.get_their_node_id() == *their_node_id).count() >= MAX_PENDING_INBOUND_CHANNELS_PER_PEER {
+			return Err(MsgHandleErrInternal::send_err_msg_no_close("Too many pending channel requests from this peer", temporary_channel_id));
+		}
+		Ok(())
+	}
+
 	fn internal_open_channel(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannel) -> Result<(), MsgHandleErrInternal> {
 		if msg.chain_hash != self.genesis_hash {
 			return Err(MsgHandleErrInternal::send_err_msg_no_close("Unknown genesis block hash", msg.temporary_channel_id.clone()));
 		}
+		if self.default_configuration.manually_accept_inbound_channels {
+			self.check_pending_inbound_channel_limits(their_node_id, msg.temporary_channel_id)?;
+		}
 
-		let channel = Channel::new_from_req(&self.fee_estimator, &self.keys_manager, their_node_id.clone(), their_features, msg, 0, &self.default_configuration)
+		let channel = Channel::new_from_req(&self.fee_estimator, &self.keys_manager, their_node_id.clone(), their_features.clone(), msg, 0, self.latest_block_height.load(Ordering::Acquire) as u32, &self.default_configuration)
 			.map_err(|e| MsgHandleErrInternal::from_chan_no_close(e, msg.temporary_channel_id))?;
 		let mut channel_state_lock = self.channel_state.lock().unwrap();
 		let channel_state = &mut *channel_state_lock;
+		if channel_state.pending_inbound_channels.contains_key(&channel.channel_id()) {
+			return Err(MsgHandleErrInternal::send_err_msg_no_close("temporary_channel_id collision!", msg.temporary_channel_id.clone()));
+		}
 		match channel_state.by_id.entry(channel.channel_id()) {
 			hash_map::Entry::Occupied(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("temporary_channel_id collision!", msg.temporary_channel_id.clone())),
 			hash_map::Entry::Vacant(entry) => {
+				if self.default_configuration.manually_accept_inbound_channels {
+					self.pending_events.lock().unwrap().push(events::Event::OpenChannelRequest {
+						temporary_channel_id: msg.temporary_channel_id,
+						counterparty_node_id: their_node_id.clone(),
+						counterparty_features: their_features,
+						funding_satoshis: msg.funding_satoshis,
+						push_msat: msg.push_msat,
+						to_self_delay: msg.to_self_delay,
+						channel_reserve_satoshis: msg.channel_reserve_satoshis,
+						htlc_minimum_msat: msg.htlc_minimum_msat,
+						max_accepted_htlcs: msg.max_accepted_htlcs,
+						feerate_per_kw: msg.feerate_per_kw,
+						announce_channel: (msg.channel_flags & 1) == 1,
+					});
+					channel_state.pending_inbound_channels.insert(channel.channel_id(), PendingInboundChannel { channel, timer_ticks: 0 });
+					return Ok(());
+				}
 				channel_state.pending_msg_events.push(events::MessageSendEvent::SendAcceptChannel {
 					node_id: their_node_id.clone(),
 					msg: channel.get_accept_channel(),
@@ -2338,6 +3248,284 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		Ok(())
 	}
 
+	fn internal_open_channel_v2(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannelV2) -> Result<(), MsgHandleErrInternal> {
+		if msg.chain_hash != self.genesis_hash {
+			return Err(MsgHandleErrInternal::send_err_msg_no_close("Unknown genesis block hash", msg.temporary_channel_id.clone()));
+		}
+		// Dual-funded requests always await our contribution, so are always held as pending.
+		self.check_pending_inbound_channel_limits(their_node_id, msg.temporary_channel_id)?;
+
+		let channel = Channel::new_from_req_v2(&self.fee_estimator, &self.keys_manager, their_node_id.clone(), their_features, msg, 0, self.latest_block_height.load(Ordering::Acquire) as u32, &self.default_configuration)
+			.map_err(|e| MsgHandleErrInternal::from_chan_no_close(e, msg.temporary_channel_id))?;
+		{
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			if channel_state.by_id.contains_key(&channel.channel_id()) {
+				return Err(MsgHandleErrInternal::send_err_msg_no_close("temporary_channel_id collision!", msg.temporary_channel_id.clone()));
+			}
+			match channel_state.pending_inbound_channels.entry(channel.channel_id()) {
+				hash_map::Entry::Occupied(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("temporary_channel_id collision!", msg.temporary_channel_id.clone())),
+				hash_map::Entry::Vacant(entry) => { entry.insert(PendingInboundChannel { channel, timer_ticks: 0 }); }
+			}
+		}
+		let mut pending_events = self.pending_events.lock().unwrap();
+		pending_events.push(events::Event::FundingContributionRequired {
+			temporary_channel_id: msg.temporary_channel_id,
+			counterparty_node_id: their_node_id.clone(),
+			counterparty_funding_satoshis: msg.funding_satoshis,
+			funding_feerate_per_kw: msg.funding_feerate_per_kw,
+		});
+		Ok(())
+	}
+
+	/// Queues the messages a channel returned while interactively constructing its funding
+	/// transaction.
+	fn push_interactive_tx_msgs(pending_msg_events: &mut Vec<events::MessageSendEvent>, their_node_id: &PublicKey, msg: Option<InteractiveTxMessageSend>, commitment_signed: Option<msgs::CommitmentSigned>) {
+		match msg {
+			Some(InteractiveTxMessageSend::TxAddInput(msg)) => {
+				pending_msg_events.push(events::MessageSendEvent::SendTxAddInput { node_id: their_node_id.clone(), msg });
+			},
+			Some(InteractiveTxMessageSend::TxAddOutput(msg)) => {
+				pending_msg_events.push(events::MessageSendEvent::SendTxAddOutput { node_id: their_node_id.clone(), msg });
+			},
+			Some(InteractiveTxMessageSend::TxComplete(msg)) => {
+				pending_msg_events.push(events::MessageSendEvent::SendTxComplete { node_id: their_node_id.clone(), msg });
+			},
+			Some(InteractiveTxMessageSend::TxAbort(msg)) => {
+				pending_msg_events.push(events::MessageSendEvent::SendTxAbort { node_id: their_node_id.clone(), msg });
+			},
+			None => {},
+		}
+		if let Some(msg) = commitment_signed {
+			pending_msg_events.push(events::MessageSendEvent::UpdateHTLCs {
+				node_id: their_node_id.clone(),
+				updates: msgs::CommitmentUpdate {
+					update_add_htlcs: Vec::new(),
+					update_fulfill_htlcs: Vec::new(),
+					update_fail_htlcs: Vec::new(),
+					update_fail_malformed_htlcs: Vec::new(),
+					update_fee: None,
+					commitment_signed: msg,
+				},
+			});
+		}
+	}
+
+	fn internal_accept_channel_v2(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::AcceptChannelV2) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_lock;
+		let (chan, tx_msg, commitment_signed) = match channel_state.by_id.entry(msg.temporary_channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_their_node_id() != *their_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.temporary_channel_id));
+				}
+				try_chan_entry!(self, chan.get_mut().accept_channel_v2(&msg, &self.default_configuration, their_features), channel_state, chan);
+				let (tx_msg, commitment_signed) = try_chan_entry!(self, chan.get_mut().interactive_tx_next_message(&self.logger), channel_state, chan);
+				(chan.remove(), tx_msg, commitment_signed)
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.temporary_channel_id))
+		};
+		match channel_state.by_id.entry(chan.channel_id()) {
+			hash_map::Entry::Occupied(_) => {
+				return Err(MsgHandleErrInternal::send_err_msg_no_close("Already had channel with the new channel_id", chan.channel_id()))
+			},
+			hash_map::Entry::Vacant(e) => {
+				Self::push_interactive_tx_msgs(&mut channel_state.pending_msg_events, their_node_id, tx_msg, commitment_signed);
+				e.insert(chan);
+			}
+		}
+		Ok(())
+	}
+
+	fn internal_tx_add_input(&self, their_node_id: &PublicKey, msg: &msgs::TxAddInput) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_their_node_id() != *their_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.channel_id));
+				}
+				let (tx_msg, commitment_signed) = try_chan_entry!(self, chan.get_mut().tx_add_input(&msg, &self.logger), channel_state, chan);
+				Self::push_interactive_tx_msgs(&mut channel_state.pending_msg_events, their_node_id, tx_msg, commitment_signed);
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
+		}
+	}
+
+	fn internal_tx_add_output(&self, their_node_id: &PublicKey, msg: &msgs::TxAddOutput) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_their_node_id() != *their_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.channel_id));
+				}
+				let (tx_msg, commitment_signed) = try_chan_entry!(self, chan.get_mut().tx_add_output(&msg, &self.logger), channel_state, chan);
+				Self::push_interactive_tx_msgs(&mut channel_state.pending_msg_events, their_node_id, tx_msg, commitment_signed);
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
+		}
+	}
+
+	fn internal_tx_complete(&self, their_node_id: &PublicKey, msg: &msgs::TxComplete) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_their_node_id() != *their_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.channel_id));
+				}
+				let (tx_msg, commitment_signed) = try_chan_entry!(self, chan.get_mut().tx_complete(&self.logger), channel_state, chan);
+				Self::push_interactive_tx_msgs(&mut channel_state.pending_msg_events, their_node_id, tx_msg, commitment_signed);
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
+		}
+	}
+
+	fn internal_tx_signatures(&self, their_node_id: &PublicKey, msg: &msgs::TxSignatures) -> Result<(), MsgHandleErrInternal> {
+		let funding_tx = {
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			match channel_state.by_id.entry(msg.channel_id) {
+				hash_map::Entry::Occupied(mut chan) => {
+					if chan.get().get_their_node_id() != *their_node_id {
+						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.channel_id));
+					}
+					let (tx_signatures, funding_tx) = try_chan_entry!(self, chan.get_mut().tx_signatures(&msg), channel_state, chan);
+					if let Some(msg) = tx_signatures {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+							node_id: their_node_id.clone(),
+							msg,
+						});
+					}
+					funding_tx
+				},
+				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
+			}
+		};
+		if let Some(tx) = funding_tx {
+			self.tx_broadcaster.broadcast_transaction(&tx);
+		}
+		Ok(())
+	}
+
+	fn internal_tx_abort(&self, their_node_id: &PublicKey, msg: &msgs::TxAbort) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_their_node_id() != *their_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.channel_id));
+				}
+				log_debug!(self.logger, "Peer aborted splice of channel {}: {}", log_bytes!(msg.channel_id), String::from_utf8_lossy(&msg.data));
+				let tx_abort = try_chan_entry!(self, chan.get_mut().tx_abort(), channel_state, chan);
+				channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxAbort {
+					node_id: their_node_id.clone(),
+					msg: tx_abort,
+				});
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
+		}
+	}
+
+	fn internal_splice_init(&self, their_node_id: &PublicKey, msg: &msgs::SpliceInit) -> Result<(), MsgHandleErrInternal> {
+		let their_features = match self.per_peer_state.read().unwrap().get(their_node_id) {
+			Some(peer_state) => peer_state.lock().unwrap().latest_features.clone(),
+			None => InitFeatures::empty(),
+		};
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_their_node_id() != *their_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.channel_id));
+				}
+				match chan.get_mut().splice_init(&msg, &self.fee_estimator, &their_features) {
+					Ok(splice_ack) => {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendSpliceAck {
+							node_id: their_node_id.clone(),
+							msg: splice_ack,
+						});
+					},
+					Err(ChannelError::Ignore(err)) => {
+						// We can't splice right now, but the channel is fine as it is, so just tell our
+						// counterparty to abandon the splice.
+						log_debug!(self.logger, "Rejecting splice of channel {}: {}", log_bytes!(msg.channel_id), err);
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxAbort {
+							node_id: their_node_id.clone(),
+							msg: msgs::TxAbort {
+								channel_id: msg.channel_id,
+								data: err.as_bytes().to_vec(),
+							},
+						});
+					},
+					Err(e) => try_chan_entry!(self, Err(e), channel_state, chan),
+				}
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
+		}
+	}
+
+	fn internal_splice_ack(&self, their_node_id: &PublicKey, msg: &msgs::SpliceAck) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_their_node_id() != *their_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.channel_id));
+				}
+				let (tx_msg, commitment_signed) = try_chan_entry!(self, chan.get_mut().splice_ack(&msg, &self.logger), channel_state, chan);
+				Self::push_interactive_tx_msgs(&mut channel_state.pending_msg_events, their_node_id, tx_msg, commitment_signed);
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
+		}
+	}
+
+	fn internal_splice_locked(&self, their_node_id: &PublicKey, msg: &msgs::SpliceLocked) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_their_node_id() != *their_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.channel_id));
+				}
+				try_chan_entry!(self, chan.get_mut().splice_locked(&msg), channel_state, chan);
+				if let Err(e) = self.complete_splice(chan.get_mut(), &mut channel_state.short_to_id, &mut channel_state.pending_msg_events) {
+					return handle_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::CommitmentFirst, false, false);
+				}
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
+		}
+	}
+
+	/// Switches the given channel over to its pending splice transaction's funding output if both
+	/// we and our counterparty have now sent splice_locked, sending new announcement_signatures
+	/// for the new short_channel_id.
+	fn complete_splice(&self, chan: &mut Channel<ChanSigner>, short_to_id: &mut HashMap<u64, [u8; 32]>, pending_msg_events: &mut Vec<events::MessageSendEvent>) -> Result<(), ChannelMonitorUpdateErr> {
+		if let Some(monitor_update) = chan.complete_splice(&self.logger) {
+			// We keep the previous short_channel_id mapped to the channel until it closes, as HTLCs we
+			// received before the splice still refer to it.
+			if let Some(short_id) = chan.get_short_channel_id() {
+				short_to_id.insert(short_id, chan.channel_id());
+			}
+			if let Some(announcement_sigs) = self.get_announcement_sigs(chan) {
+				pending_msg_events.push(events::MessageSendEvent::SendAnnouncementSignatures {
+					node_id: chan.get_their_node_id(),
+					msg: announcement_sigs,
+				});
+			}
+			self.monitor.update_monitor(chan.get_original_funding_txo().unwrap(), monitor_update)?;
+		}
+		Ok(())
+	}
+
 	fn internal_funding_locked(&self, their_node_id: &PublicKey, msg: &msgs::FundingLocked) -> Result<(), MsgHandleErrInternal> {
 		let mut channel_state_lock = self.channel_state.lock().unwrap();
 		let channel_state = &mut *channel_state_lock;
@@ -2396,6 +3584,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						if let Some(short_id) = chan_entry.get().get_short_channel_id() {
 							channel_state.short_to_id.remove(&short_id);
 						}
+						for short_id in chan_entry.get().get_prev_short_channel_ids() {
+							channel_state.short_to_id.remove(short_id);
+						}
+						self.issue_channel_close_events(chan_entry.get(), ClosureReason::CooperativeClosure);
 						(dropped_htlcs, Some(chan_entry.remove_entry().1))
 					} else { (dropped_htlcs, None) }
 				},
@@ -2441,6 +3633,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						if let Some(short_id) = chan_entry.get().get_short_channel_id() {
 							channel_state.short_to_id.remove(&short_id);
 						}
+						for short_id in chan_entry.get().get_prev_short_channel_ids() {
+							channel_state.short_to_id.remove(short_id);
+						}
+						self.issue_channel_close_events(chan_entry.get(), ClosureReason::CooperativeClosure);
 						(tx, Some(chan_entry.remove_entry().1))
 					} else { (tx, None) }
 				},
@@ -2540,7 +3736,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
 			}
 		};
-		self.claim_funds_internal(channel_lock, htlc_source, msg.payment_preimage.clone());
+		let (htlc_source, htlc_value_msat) = htlc_source;
+		self.claim_funds_internal(channel_lock, htlc_source, msg.payment_preimage.clone(), msg.channel_id, Some(htlc_value_msat), false);
 		Ok(())
 	}
 
@@ -2586,18 +3783,46 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				if chan.get().get_their_node_id() != *their_node_id {
 					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.channel_id));
 				}
+				let awaiting_splice_commitment_signed = chan.get().is_awaiting_splice_commitment_signed();
+				if chan.get().is_awaiting_initial_commitment_signed() || awaiting_splice_commitment_signed {
+					if awaiting_splice_commitment_signed {
+						let monitor_update = try_chan_entry!(self, chan.get_mut().splice_commitment_signed(&msg, &self.logger), channel_state, chan);
+						if let Err(e) = self.monitor.update_monitor(chan.get().get_original_funding_txo().unwrap(), monitor_update) {
+							return_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::RevokeAndACKFirst, false, false);
+						}
+					} else {
+						let monitor = try_chan_entry!(self, chan.get_mut().initial_commitment_signed(&msg, &self.logger), channel_state, chan);
+						if let Err(e) = self.monitor.add_monitor(chan.get().get_funding_txo().unwrap(), monitor) {
+							return_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::RevokeAndACKFirst, false, false);
+						}
+					}
+					let (signing_event, tx_signatures, funding_tx) = Self::get_funding_tx_signing_actions(chan.get_mut());
+					if let Some(msg) = tx_signatures {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+							node_id: their_node_id.clone(),
+							msg,
+						});
+					}
+					if let Some(tx) = funding_tx {
+						self.tx_broadcaster.broadcast_transaction(&tx);
+					}
+					if let Some(event) = signing_event {
+						self.pending_events.lock().unwrap().push(event);
+					}
+					return Ok(());
+				}
 				let (revoke_and_ack, commitment_signed, closing_signed, monitor_update) =
 					match chan.get_mut().commitment_signed(&msg, &self.fee_estimator, &self.logger) {
 						Err((None, e)) => try_chan_entry!(self, Err(e), channel_state, chan),
 						Err((Some(update), e)) => {
 							assert!(chan.get().is_awaiting_monitor_update());
-							let _ = self.monitor.update_monitor(chan.get().get_funding_txo().unwrap(), update);
+							let _ = self.monitor.update_monitor(chan.get().get_original_funding_txo().unwrap(), update);
 							try_chan_entry!(self, Err(e), channel_state, chan);
 							unreachable!();
 						},
 						Ok(res) => res
 					};
-				if let Err(e) = self.monitor.update_monitor(chan.get().get_funding_txo().unwrap(), monitor_update) {
+				if let Err(e) = self.monitor.update_monitor(chan.get().get_original_funding_txo().unwrap(), monitor_update) {
 					return_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::RevokeAndACKFirst, true, commitment_signed.is_some());
 					//TODO: Rebroadcast closing_signed if present on monitor update restoration
 				}
@@ -2630,16 +3855,63 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 	}
 
+	/// Forgets all the onions we've forwarded, for tests which deliberately reuse onions.
+	#[cfg(test)]
+	pub(super) fn clear_onion_replay_cache(&self) {
+		self.channel_state.lock().unwrap().onion_replay_cache.clear();
+	}
+
+	/// Gets the outgoing CLTVs for which we're remembering forwarded onions.
+	#[cfg(test)]
+	pub(super) fn onion_replay_cache_cltvs(&self) -> Vec<u32> {
+		self.channel_state.lock().unwrap().onion_replay_cache.keys().cloned().collect()
+	}
+
 	#[inline]
 	fn forward_htlcs(&self, per_source_pending_forwards: &mut [(u64, Vec<(PendingHTLCInfo, u64)>)]) {
 		for &mut (prev_short_channel_id, ref mut pending_forwards) in per_source_pending_forwards {
 			let mut forward_event = None;
+			let mut intercepted_events = Vec::new();
+			let mut replayed_htlcs = Vec::new();
 			if !pending_forwards.is_empty() {
 				let mut channel_state = self.channel_state.lock().unwrap();
-				if channel_state.forward_htlcs.is_empty() {
-					forward_event = Some(Duration::from_millis(MIN_HTLC_RELAY_HOLDING_CELL_MILLIS))
-				}
+				let forward_htlcs_empty = channel_state.forward_htlcs.is_empty();
 				for (forward_info, prev_htlc_id) in pending_forwards.drain(..) {
+					if let PendingHTLCRouting::Forward { short_channel_id, .. } = forward_info.routing {
+						let onion_hash = Sha256::hash(&forward_info.incoming_shared_secret).into_inner();
+						// decode_update_add_htlc_onion rejects onions we've already forwarded, but
+						// two copies of the same onion may have been in flight at once.
+						if channel_state.onion_replay_cache.values().any(|onion_hashes| onion_hashes.contains(&onion_hash)) {
+							replayed_htlcs.push((HTLCSource::PreviousHopData(HTLCPreviousHopData {
+								short_channel_id: prev_short_channel_id,
+								htlc_id: prev_htlc_id,
+								incoming_packet_shared_secret: forward_info.incoming_shared_secret,
+								prev_channel_id: None,
+								incoming_amt_msat: None,
+							}), forward_info.payment_hash));
+							continue;
+						}
+						channel_state.onion_replay_cache.entry(forward_info.outgoing_cltv_value).or_insert(HashSet::new()).insert(onion_hash);
+						let intercept_id = InterceptId(onion_hash);
+						// If the inbound channel was closed in the meantime there's no HTLC left to
+						// intercept, so we let process_pending_htlc_forwards fail it as any other to
+						// an unknown short channel id.
+						let cltv_expiry = channel_state.short_to_id.get(&prev_short_channel_id)
+							.and_then(|chan_id| channel_state.by_id.get(chan_id))
+							.and_then(|chan| chan.get_pending_inbound_htlc_cltv_expiry(prev_htlc_id));
+						if let (true, Some(cltv_expiry)) = (channel_state.intercept_scids.contains(&short_channel_id), cltv_expiry) {
+							intercepted_events.push(events::Event::HTLCIntercepted {
+								intercept_id,
+								requested_next_hop_scid: short_channel_id,
+								payment_hash: forward_info.payment_hash,
+								inbound_amount_msat: forward_info.incoming_amt_msat,
+								expected_outbound_amount_msat: forward_info.amt_to_forward,
+								outgoing_cltv_value: forward_info.outgoing_cltv_value,
+							});
+							channel_state.pending_intercepted_htlcs.insert(intercept_id, InterceptedHTLC { prev_short_channel_id, prev_htlc_id, cltv_expiry, forward_info });
+							continue;
+						}
+					}
 					match channel_state.forward_htlcs.entry(match forward_info.routing {
 							PendingHTLCRouting::Forward { short_channel_id, .. } => short_channel_id,
 							PendingHTLCRouting::Receive { .. } => 0,
@@ -2652,6 +3924,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						}
 					}
 				}
+				if forward_htlcs_empty && !channel_state.forward_htlcs.is_empty() {
+					forward_event = Some(Duration::from_millis(MIN_HTLC_RELAY_HOLDING_CELL_MILLIS))
+				}
 			}
 			match forward_event {
 				Some(time) => {
@@ -2662,6 +3937,13 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				}
 				None => {},
 			}
+			if !intercepted_events.is_empty() {
+				self.pending_events.lock().unwrap().append(&mut intercepted_events);
+			}
+			for (source, payment_hash) in replayed_htlcs.drain(..) {
+				log_info!(self.logger, "Failing replayed onion packet with payment_hash {}", log_bytes!(payment_hash.0));
+				self.fail_htlc_backwards_internal(self.channel_state.lock().unwrap(), source, &payment_hash, HTLCFailReason::Reason { failure_code: 0x2000 | 2, data: Vec::new() });
+			}
 		}
 	}
 
@@ -2677,7 +3959,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					let was_frozen_for_monitor = chan.get().is_awaiting_monitor_update();
 					let (commitment_update, pending_forwards, pending_failures, closing_signed, monitor_update) =
 						try_chan_entry!(self, chan.get_mut().revoke_and_ack(&msg, &self.fee_estimator, &self.logger), channel_state, chan);
-					if let Err(e) = self.monitor.update_monitor(chan.get().get_funding_txo().unwrap(), monitor_update) {
+					if let Err(e) = self.monitor.update_monitor(chan.get().get_original_funding_txo().unwrap(), monitor_update) {
 						if was_frozen_for_monitor {
 							assert!(commitment_update.is_none() && closing_signed.is_none() && pending_forwards.is_empty() && pending_failures.is_empty());
 							return Err(MsgHandleErrInternal::ignore_no_close("Previous monitor update failure prevented responses to RAA"));
@@ -2718,7 +4000,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				if chan.get().get_their_node_id() != *their_node_id {
 					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!", msg.channel_id));
 				}
-				try_chan_entry!(self, chan.get_mut().update_fee(&self.fee_estimator, &msg), channel_state, chan);
+				try_chan_entry!(self, chan.get_mut().update_fee(&self.fee_estimator, &msg, self.default_configuration.max_inbound_update_fee_deviation_percent), channel_state, chan);
 			},
 			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
 		}
@@ -2779,8 +4061,14 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				}
 				let (funding_locked, revoke_and_ack, commitment_update, monitor_update_opt, mut order, shutdown) =
 					try_chan_entry!(self, chan.get_mut().channel_reestablish(msg, &self.logger), channel_state, chan);
+				if let Some(msg) = chan.get().get_tx_signatures_to_resend() {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+						node_id: their_node_id.clone(),
+						msg
+					});
+				}
 				if let Some(monitor_update) = monitor_update_opt {
-					if let Err(e) = self.monitor.update_monitor(chan.get().get_funding_txo().unwrap(), monitor_update) {
+					if let Err(e) = self.monitor.update_monitor(chan.get().get_original_funding_txo().unwrap(), monitor_update) {
 						// channel_reestablish doesn't guarantee the order it returns is sensical
 						// for the messages it returns, but if we're setting what messages to
 						// re-transmit on monitor update success, we need to make sure it is sane.
@@ -2800,6 +4088,12 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						msg
 					});
 				}
+				if let Some(msg) = chan.get().get_splice_locked_to_resend() {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendSpliceLocked {
+						node_id: their_node_id.clone(),
+						msg
+					});
+				}
 				macro_rules! send_raa { () => {
 					if let Some(msg) = revoke_and_ack {
 						channel_state.pending_msg_events.push(events::MessageSendEvent::SendRevokeAndACK {
@@ -2832,6 +4126,11 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						msg,
 					});
 				}
+				// If both sides sent splice_locked while we were disconnected, we can now complete the
+				// splice.
+				if let Err(e) = self.complete_splice(chan.get_mut(), &mut channel_state.short_to_id, &mut channel_state.pending_msg_events) {
+					return handle_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::CommitmentFirst, false, false);
+				}
 				Ok(())
 			},
 			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel", msg.channel_id))
@@ -2866,8 +4165,11 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					if let Some((update_fee, commitment_signed, monitor_update)) =
 							break_chan_entry!(self, chan.get_mut().send_update_fee_and_commit(feerate_per_kw, &self.logger), channel_state, chan)
 					{
-						if let Err(_e) = self.monitor.update_monitor(chan.get().get_funding_txo().unwrap(), monitor_update) {
-							unimplemented!();
+						if let Err(e) = self.monitor.update_monitor(chan.get().get_original_funding_txo().unwrap(), monitor_update) {
+							maybe_break_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::CommitmentFirst, false, true);
+							// The update_fee will be resent along with the commitment_signed once
+							// monitor updating is restored.
+							return Err(APIError::MonitorUpdateFailed);
 						}
 						channel_state.pending_msg_events.push(events::MessageSendEvent::UpdateHTLCs {
 							node_id: chan.get().get_their_node_id(),
@@ -2909,7 +4211,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			for htlc_update in self.monitor.get_and_clear_pending_htlcs_updated() {
 				if let Some(preimage) = htlc_update.payment_preimage {
 					log_trace!(self.logger, "Claiming HTLC with preimage {} from our monitor", log_bytes!(preimage.0));
-					self.claim_funds_internal(self.channel_state.lock().unwrap(), htlc_update.source, preimage);
+					self.claim_funds_internal(self.channel_state.lock().unwrap(), htlc_update.source, preimage, htlc_update.channel_id, htlc_update.htlc_value_msat, true);
 				} else {
 					log_trace!(self.logger, "Failing HTLC with hash {} from our monitor", log_bytes!(htlc_update.payment_hash.0));
 					self.fail_htlc_backwards_internal(self.channel_state.lock().unwrap(), htlc_update.source, &htlc_update.payment_hash, HTLCFailReason::Reason { failure_code: 0x4000 | 8, data: Vec::new() });
@@ -2940,7 +4242,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			for htlc_update in self.monitor.get_and_clear_pending_htlcs_updated() {
 				if let Some(preimage) = htlc_update.payment_preimage {
 					log_trace!(self.logger, "Claiming HTLC with preimage {} from our monitor", log_bytes!(preimage.0));
-					self.claim_funds_internal(self.channel_state.lock().unwrap(), htlc_update.source, preimage);
+					self.claim_funds_internal(self.channel_state.lock().unwrap(), htlc_update.source, preimage, htlc_update.channel_id, htlc_update.htlc_value_msat, true);
 				} else {
 					log_trace!(self.logger, "Failing HTLC with hash {} from our monitor", log_bytes!(htlc_update.payment_hash.0));
 					self.fail_htlc_backwards_internal(self.channel_state.lock().unwrap(), htlc_update.source, &htlc_update.payment_hash, HTLCFailReason::Reason { failure_code: 0x4000 | 8, data: Vec::new() });
@@ -2976,7 +4278,7 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 			let pending_msg_events = &mut channel_state.pending_msg_events;
 			channel_state.by_id.retain(|_, channel| {
 				let res = channel.block_connected(header, height, txn_matched, indexes_of_txn_matched);
-				if let Ok((chan_res, mut timed_out_pending_htlcs)) = res {
+				if let Ok((chan_res, splice_locked, mut timed_out_pending_htlcs)) = res {
 					for (source, payment_hash) in timed_out_pending_htlcs.drain(..) {
 						let chan_update = self.get_channel_update(&channel).map(|u| u.encode_with_len()).unwrap(); // Cannot add/recv HTLCs before we have a short_id so unwrap is safe
 						timed_out_htlcs.push((source, payment_hash,  HTLCFailReason::Reason {
@@ -3000,25 +4302,72 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 						}
 						short_to_id.insert(channel.get_short_channel_id().unwrap(), channel.channel_id());
 					}
+					if let Some(msg) = splice_locked {
+						log_trace!(self.logger, "Sending splice_locked for {}", log_bytes!(channel.channel_id()));
+						pending_msg_events.push(events::MessageSendEvent::SendSpliceLocked {
+							node_id: channel.get_their_node_id(),
+							msg,
+						});
+						match self.complete_splice(channel, short_to_id, pending_msg_events) {
+							Ok(()) => {},
+							Err(ChannelMonitorUpdateErr::TemporaryFailure) => channel.monitor_update_failed(false, false, Vec::new(), Vec::new()),
+							Err(ChannelMonitorUpdateErr::PermanentFailure) => {
+								log_error!(self.logger, "Closing channel {} due to monitor update PermanentFailure", log_bytes!(channel.channel_id()));
+								if let Some(short_id) = channel.get_short_channel_id() {
+									short_to_id.remove(&short_id);
+								}
+								for short_id in channel.get_prev_short_channel_ids() {
+									short_to_id.remove(short_id);
+								}
+								failed_channels.push(channel.force_shutdown(true));
+								self.issue_channel_close_events(channel, ClosureReason::ProcessingError { err: "ChannelMonitor storage failure".to_owned() });
+								return false;
+							},
+						}
+					}
 				} else if let Err(e) = res {
+					self.issue_channel_close_events(channel, ClosureReason::ProcessingError { err: e.data.clone() });
 					pending_msg_events.push(events::MessageSendEvent::HandleError {
 						node_id: channel.get_their_node_id(),
 						action: msgs::ErrorAction::SendErrorMessage { msg: e },
 					});
 					return false;
 				}
+				if channel.is_funding_timed_out(height) {
+					log_info!(self.logger, "Closing channel {} as its funding transaction was not confirmed in time", log_bytes!(channel.channel_id()));
+					failed_channels.push(channel.force_shutdown(true));
+					self.issue_channel_close_events(channel, ClosureReason::FundingTimedOut);
+					pending_msg_events.push(events::MessageSendEvent::HandleError {
+						node_id: channel.get_their_node_id(),
+						action: msgs::ErrorAction::SendErrorMessage {
+							msg: msgs::ErrorMessage {
+								channel_id: channel.channel_id(),
+								data: "Funding transaction was not confirmed in time".to_owned(),
+							},
+						},
+					});
+					return false;
+				}
 				if let Some(funding_txo) = channel.get_funding_txo() {
+					let splice_funding_txo = channel.get_pending_splice_funding_txo();
 					for tx in txn_matched {
+						// Our splice transaction spends the funding output without closing the channel.
+						if channel.is_pending_splice_tx(&tx.txid()) { continue; }
 						for inp in tx.input.iter() {
-							if inp.previous_output == funding_txo.into_bitcoin_outpoint() {
+							if inp.previous_output == funding_txo.into_bitcoin_outpoint() ||
+									splice_funding_txo.map(|txo| inp.previous_output == txo.into_bitcoin_outpoint()).unwrap_or(false) {
 								log_trace!(self.logger, "Detected channel-closing tx {} spending {}:{}, closing channel {}", tx.txid(), inp.previous_output.txid, inp.previous_output.vout, log_bytes!(channel.channel_id()));
 								if let Some(short_id) = channel.get_short_channel_id() {
 									short_to_id.remove(&short_id);
 								}
+								for short_id in channel.get_prev_short_channel_ids() {
+									short_to_id.remove(short_id);
+								}
 								// It looks like our counterparty went on-chain. We go ahead and
 								// broadcast our latest local state as well here, just in case its
 								// some kind of SPV attack, though we expect these to be dropped.
 								failed_channels.push(channel.force_shutdown(true));
+								self.issue_channel_close_events(channel, ClosureReason::CommitmentTxConfirmed);
 								if let Ok(update) = self.get_channel_update(&channel) {
 									pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
 										msg: update
@@ -3033,10 +4382,14 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 					if let Some(short_id) = channel.get_short_channel_id() {
 						short_to_id.remove(&short_id);
 					}
+					for short_id in channel.get_prev_short_channel_ids() {
+						short_to_id.remove(short_id);
+					}
 					// If would_broadcast_at_height() is true, the channel_monitor will broadcast
 					// the latest local tx for us, so we should skip that here (it doesn't really
 					// hurt anything, but does make tests a bit simpler).
 					failed_channels.push(channel.force_shutdown(false));
+					self.issue_channel_close_events(channel, ClosureReason::ProcessingError { err: "Pending HTLC must be resolved on-chain before it expires".to_owned() });
 					if let Ok(update) = self.get_channel_update(&channel) {
 						pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
 							msg: update
@@ -3065,6 +4418,30 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 				});
 				!htlcs.is_empty() // Only retain this entry if htlcs has at least one entry.
 			});
+
+			channel_state.pending_intercepted_htlcs.retain(|_, htlc| {
+				// Once the inbound HTLC gets close to expiring, or the outbound HTLC could no longer
+				// be safely forwarded, fail the inbound one instead of waiting on the user until our
+				// counterparty closes the channel. Note that the outbound HTLC always expires first,
+				// as we only intercept HTLCs with enough of a CLTV delta between the two.
+				if height + HTLC_FAIL_BACK_BUFFER >= cmp::min(htlc.cltv_expiry, htlc.forward_info.outgoing_cltv_value) {
+					timed_out_htlcs.push((HTLCSource::PreviousHopData(HTLCPreviousHopData {
+						short_channel_id: htlc.prev_short_channel_id,
+						htlc_id: htlc.prev_htlc_id,
+						incoming_packet_shared_secret: htlc.forward_info.incoming_shared_secret,
+						prev_channel_id: None,
+						incoming_amt_msat: None,
+					}), htlc.forward_info.payment_hash, HTLCFailReason::Reason {
+						failure_code: 0x4000 | 10,
+						data: Vec::new()
+					}));
+					false
+				} else { true }
+			});
+
+			// We never accept a forward whose outgoing CLTV is this close, so there's no need to
+			// remember the onions we forwarded with it any longer.
+			channel_state.onion_replay_cache = channel_state.onion_replay_cache.split_off(&(height + 1));
 		}
 		for failure in failed_channels.drain(..) {
 			self.finish_force_close_channel(failure);
@@ -3087,6 +4464,15 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 				break;
 			}
 		}
+		loop {
+			// Similarly, update highest_seen_timestamp to be the max of its current value and the
+			// block timestamp, though it is never incremented otherwise.
+			let old_timestamp = self.highest_seen_timestamp.load(Ordering::Acquire);
+			if old_timestamp >= header.time as usize { break; }
+			if self.highest_seen_timestamp.compare_exchange(old_timestamp, header.time as usize, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+				break;
+			}
+		}
 	}
 
 	/// We force-close the channel without letting our counterparty participate in the shutdown
@@ -3103,7 +4489,11 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 					if let Some(short_id) = v.get_short_channel_id() {
 						short_to_id.remove(&short_id);
 					}
+					for short_id in v.get_prev_short_channel_ids() {
+						short_to_id.remove(short_id);
+					}
 					failed_channels.push(v.force_shutdown(true));
+					self.issue_channel_close_events(v, ClosureReason::ProcessingError { err: "Funding transaction was un-confirmed".to_owned() });
 					if let Ok(update) = self.get_channel_update(&v) {
 						pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
 							msg: update
@@ -3156,6 +4546,56 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 		let _ = handle_error!(self, self.internal_funding_locked(their_node_id, msg), *their_node_id);
 	}
 
+	fn handle_open_channel_v2(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannelV2) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_open_channel_v2(their_node_id, their_features, msg), *their_node_id);
+	}
+
+	fn handle_accept_channel_v2(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::AcceptChannelV2) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_accept_channel_v2(their_node_id, their_features, msg), *their_node_id);
+	}
+
+	fn handle_tx_add_input(&self, their_node_id: &PublicKey, msg: &msgs::TxAddInput) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_tx_add_input(their_node_id, msg), *their_node_id);
+	}
+
+	fn handle_tx_add_output(&self, their_node_id: &PublicKey, msg: &msgs::TxAddOutput) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_tx_add_output(their_node_id, msg), *their_node_id);
+	}
+
+	fn handle_tx_complete(&self, their_node_id: &PublicKey, msg: &msgs::TxComplete) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_tx_complete(their_node_id, msg), *their_node_id);
+	}
+
+	fn handle_tx_signatures(&self, their_node_id: &PublicKey, msg: &msgs::TxSignatures) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_tx_signatures(their_node_id, msg), *their_node_id);
+	}
+
+	fn handle_tx_abort(&self, their_node_id: &PublicKey, msg: &msgs::TxAbort) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_tx_abort(their_node_id, msg), *their_node_id);
+	}
+
+	fn handle_splice_init(&self, their_node_id: &PublicKey, msg: &msgs::SpliceInit) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_splice_init(their_node_id, msg), *their_node_id);
+	}
+
+	fn handle_splice_ack(&self, their_node_id: &PublicKey, msg: &msgs::SpliceAck) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_splice_ack(their_node_id, msg), *their_node_id);
+	}
+
+	fn handle_splice_locked(&self, their_node_id: &PublicKey, msg: &msgs::SpliceLocked) {
+		let _ = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_splice_locked(their_node_id, msg), *their_node_id);
+	}
+
 	fn handle_shutdown(&self, their_node_id: &PublicKey, msg: &msgs::Shutdown) {
 		let _ = self.total_consistency_lock.read().unwrap();
 		let _ = handle_error!(self, self.internal_shutdown(their_node_id, msg), *their_node_id);
@@ -3228,7 +4668,11 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 						if let Some(short_id) = chan.get_short_channel_id() {
 							short_to_id.remove(&short_id);
 						}
+						for short_id in chan.get_prev_short_channel_ids() {
+							short_to_id.remove(short_id);
+						}
 						failed_channels.push(chan.force_shutdown(true));
+						self.issue_channel_close_events(chan, ClosureReason::DisconnectedPeer);
 						if let Ok(update) = self.get_channel_update(&chan) {
 							pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
 								msg: update
@@ -3253,6 +4697,10 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 							if let Some(short_id) = chan.get_short_channel_id() {
 								short_to_id.remove(&short_id);
 							}
+							for short_id in chan.get_prev_short_channel_ids() {
+								short_to_id.remove(short_id);
+							}
+							self.issue_channel_close_events(chan, ClosureReason::DisconnectedPeer);
 							return false;
 						} else {
 							no_channels_remain = false;
@@ -3261,10 +4709,21 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 					true
 				})
 			}
+			channel_state.pending_inbound_channels.retain(|_, pending| pending.channel.get_their_node_id() != *their_node_id);
 			pending_msg_events.retain(|msg| {
 				match msg {
 					&events::MessageSendEvent::SendAcceptChannel { ref node_id, .. } => node_id != their_node_id,
 					&events::MessageSendEvent::SendOpenChannel { ref node_id, .. } => node_id != their_node_id,
+					&events::MessageSendEvent::SendOpenChannelV2 { ref node_id, .. } => node_id != their_node_id,
+					&events::MessageSendEvent::SendAcceptChannelV2 { ref node_id, .. } => node_id != their_node_id,
+					&events::MessageSendEvent::SendTxAddInput { ref node_id, .. } => node_id != their_node_id,
+					&events::MessageSendEvent::SendTxAddOutput { ref node_id, .. } => node_id != their_node_id,
+					&events::MessageSendEvent::SendTxComplete { ref node_id, .. } => node_id != their_node_id,
+					&events::MessageSendEvent::SendTxSignatures { ref node_id, .. } => node_id != their_node_id,
+					&events::MessageSendEvent::SendTxAbort { ref node_id, .. } => node_id != their_node_id,
+					&events::MessageSendEvent::SendSpliceInit { ref node_id, .. } => node_id != their_node_id,
+					&events::MessageSendEvent::SendSpliceAck { ref node_id, .. } => node_id != their_node_id,
+					&events::MessageSendEvent::SendSpliceLocked { ref node_id, .. } => node_id != their_node_id,
 					&events::MessageSendEvent::SendFundingCreated { ref node_id, .. } => node_id != their_node_id,
 					&events::MessageSendEvent::SendFundingSigned { ref node_id, .. } => node_id != their_node_id,
 					&events::MessageSendEvent::SendFundingLocked { ref node_id, .. } => node_id != their_node_id,
@@ -3279,6 +4738,11 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 					&events::MessageSendEvent::BroadcastChannelUpdate { .. } => true,
 					&events::MessageSendEvent::HandleError { ref node_id, .. } => node_id != their_node_id,
 					&events::MessageSendEvent::PaymentFailureNetworkUpdate { .. } => true,
+					&events::MessageSendEvent::SendChannelRangeQuery { .. } => false,
+					&events::MessageSendEvent::SendReplyChannelRange { .. } => false,
+					&events::MessageSendEvent::SendShortIdsQuery { .. } => false,
+					&events::MessageSendEvent::SendShortIdsReply { .. } => false,
+					&events::MessageSendEvent::SendGossipTimestampFilter { .. } => false,
 				}
 			});
 		}
@@ -3296,6 +4760,15 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 		}
 	}
 
+	fn provided_init_features(&self) -> InitFeatures {
+		let features = InitFeatures::known();
+		if self.default_configuration.own_channel_config.negotiate_anchor_outputs {
+			features
+		} else {
+			features.clear_anchor_outputs()
+		}
+	}
+
 	fn peer_connected(&self, their_node_id: &PublicKey, init_msg: &msgs::Init) {
 		log_debug!(self.logger, "Generating channel_reestablish events for {}", log_pubkey!(their_node_id));
 
@@ -3325,6 +4798,7 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 					// peer we probably failed to send the open_channel message, which is now
 					// lost. We can't have had anything pending related to this channel, so we just
 					// drop it.
+					self.issue_channel_close_events(chan, ClosureReason::DisconnectedPeer);
 					false
 				} else {
 					pending_msg_events.push(events::MessageSendEvent::SendChannelReestablish {
@@ -3344,17 +4818,18 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 		if msg.channel_id == [0; 32] {
 			for chan in self.list_channels() {
 				if chan.remote_network_id == *their_node_id {
-					self.force_close_channel(&chan.channel_id);
+					self.force_close_channel_with_reason(&chan.channel_id, ClosureReason::CounterpartyForceClosed { peer_msg: msg.data.clone() });
 				}
 			}
 		} else {
-			self.force_close_channel(&msg.channel_id);
+			self.force_close_channel_with_reason(&msg.channel_id, ClosureReason::CounterpartyForceClosed { peer_msg: msg.data.clone() });
 		}
 	}
 }
 
-const SERIALIZATION_VERSION: u8 = 1;
-const MIN_SERIALIZATION_VERSION: u8 = 1;
+// Fields added in version 2 are read with defaults from version 1 data.
+const SERIALIZATION_VERSION: u8 = 2;
+const MIN_SERIALIZATION_VERSION: u8 = 2;
 
 impl Writeable for PendingHTLCInfo {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
@@ -3364,37 +4839,49 @@ impl Writeable for PendingHTLCInfo {
 				onion_packet.write(writer)?;
 				short_channel_id.write(writer)?;
 			},
-			&PendingHTLCRouting::Receive { ref payment_data, ref incoming_cltv_expiry } => {
+			&PendingHTLCRouting::Receive { ref payment_data, ref keysend_preimage, ref incoming_cltv_expiry } => {
 				1u8.write(writer)?;
 				payment_data.write(writer)?;
+				keysend_preimage.write(writer)?;
 				incoming_cltv_expiry.write(writer)?;
 			},
 		}
 		self.incoming_shared_secret.write(writer)?;
 		self.payment_hash.write(writer)?;
+		self.incoming_amt_msat.write(writer)?;
 		self.amt_to_forward.write(writer)?;
 		self.outgoing_cltv_value.write(writer)?;
 		Ok(())
 	}
 }
 
-impl Readable for PendingHTLCInfo {
-	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<PendingHTLCInfo, DecodeError> {
-		Ok(PendingHTLCInfo {
-			routing: match Readable::read(reader)? {
-				0u8 => PendingHTLCRouting::Forward {
-					onion_packet: Readable::read(reader)?,
-					short_channel_id: Readable::read(reader)?,
-				},
-				1u8 => PendingHTLCRouting::Receive {
-					payment_data: Readable::read(reader)?,
-					incoming_cltv_expiry: Readable::read(reader)?,
-				},
-				_ => return Err(DecodeError::InvalidValue),
+/// PendingHTLCInfo, PendingHTLCStatus and HTLCForwardInfo are read given the serialization version
+/// of the Channel or ChannelManager containing them.
+impl ReadableArgs<u8> for PendingHTLCInfo {
+	fn read<R: ::std::io::Read>(reader: &mut R, ver: u8) -> Result<PendingHTLCInfo, DecodeError> {
+		let routing = match Readable::read(reader)? {
+			0u8 => PendingHTLCRouting::Forward {
+				onion_packet: Readable::read(reader)?,
+				short_channel_id: Readable::read(reader)?,
+			},
+			1u8 => PendingHTLCRouting::Receive {
+				payment_data: Readable::read(reader)?,
+				keysend_preimage: if ver >= 2 { Readable::read(reader)? } else { None },
+				incoming_cltv_expiry: Readable::read(reader)?,
 			},
-			incoming_shared_secret: Readable::read(reader)?,
-			payment_hash: Readable::read(reader)?,
-			amt_to_forward: Readable::read(reader)?,
+			_ => return Err(DecodeError::InvalidValue),
+		};
+		let incoming_shared_secret = Readable::read(reader)?;
+		let payment_hash = Readable::read(reader)?;
+		let incoming_amt_msat = if ver >= 2 { Some(Readable::read(reader)?) } else { None };
+		let amt_to_forward = Readable::read(reader)?;
+		Ok(PendingHTLCInfo {
+			routing,
+			incoming_shared_secret,
+			payment_hash,
+			// We didn't track the inbound amount before version 2, so assume we charged no fee.
+			incoming_amt_msat: incoming_amt_msat.unwrap_or(amt_to_forward),
+			amt_to_forward,
 			outgoing_cltv_value: Readable::read(reader)?,
 		})
 	}
@@ -3442,34 +4929,67 @@ impl Writeable for PendingHTLCStatus {
 	}
 }
 
-impl Readable for PendingHTLCStatus {
-	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<PendingHTLCStatus, DecodeError> {
+impl ReadableArgs<u8> for PendingHTLCStatus {
+	fn read<R: ::std::io::Read>(reader: &mut R, ver: u8) -> Result<PendingHTLCStatus, DecodeError> {
 		match <u8 as Readable>::read(reader)? {
-			0 => Ok(PendingHTLCStatus::Forward(Readable::read(reader)?)),
+			0 => Ok(PendingHTLCStatus::Forward(ReadableArgs::read(reader, ver)?)),
 			1 => Ok(PendingHTLCStatus::Fail(Readable::read(reader)?)),
 			_ => Err(DecodeError::InvalidValue),
 		}
 	}
 }
 
-impl_writeable!(HTLCPreviousHopData, 0, {
-	short_channel_id,
-	htlc_id,
-	incoming_packet_shared_secret
-});
+impl Writeable for HTLCPreviousHopData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		self.short_channel_id.write(writer)?;
+		self.htlc_id.write(writer)?;
+		self.incoming_packet_shared_secret.write(writer)?;
+		self.prev_channel_id.write(writer)?;
+		self.incoming_amt_msat.write(writer)?;
+		Ok(())
+	}
+}
 
-impl_writeable!(ClaimableHTLC, 0, {
-	prev_hop,
-	value,
-	payment_data,
-	cltv_expiry
-});
+impl ReadableArgs<u8> for HTLCPreviousHopData {
+	fn read<R: ::std::io::Read>(reader: &mut R, ver: u8) -> Result<Self, DecodeError> {
+		Ok(HTLCPreviousHopData {
+			short_channel_id: Readable::read(reader)?,
+			htlc_id: Readable::read(reader)?,
+			incoming_packet_shared_secret: Readable::read(reader)?,
+			prev_channel_id: if ver >= 2 { Readable::read(reader)? } else { None },
+			incoming_amt_msat: if ver >= 2 { Readable::read(reader)? } else { None },
+		})
+	}
+}
+
+impl Writeable for ClaimableHTLC {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		self.prev_hop.write(writer)?;
+		self.value.write(writer)?;
+		self.payment_data.write(writer)?;
+		self.cltv_expiry.write(writer)?;
+		self.timer_ticks.write(writer)?;
+		Ok(())
+	}
+}
+
+impl ReadableArgs<u8> for ClaimableHTLC {
+	fn read<R: ::std::io::Read>(reader: &mut R, ver: u8) -> Result<Self, DecodeError> {
+		Ok(ClaimableHTLC {
+			prev_hop: ReadableArgs::read(reader, ver)?,
+			value: Readable::read(reader)?,
+			payment_data: Readable::read(reader)?,
+			cltv_expiry: Readable::read(reader)?,
+			timer_ticks: if ver >= 2 { Readable::read(reader)? } else { 0 },
+		})
+	}
+}
 
 impl Writeable for HTLCSource {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
 		match self {
 			&HTLCSource::PreviousHopData(ref hop_data) => {
-				0u8.write(writer)?;
+				2u8.write(writer)?;
 				hop_data.write(writer)?;
 			},
 			&HTLCSource::OutboundRoute { ref path, ref session_priv, ref first_hop_htlc_msat } => {
@@ -3486,12 +5006,15 @@ impl Writeable for HTLCSource {
 impl Readable for HTLCSource {
 	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<HTLCSource, DecodeError> {
 		match <u8 as Readable>::read(reader)? {
-			0 => Ok(HTLCSource::PreviousHopData(Readable::read(reader)?)),
+			// Sources written with a type of 0 predate our tracking the inbound channel and value
+			// of forwarded HTLCs.
+			0 => Ok(HTLCSource::PreviousHopData(ReadableArgs::read(reader, 1)?)),
 			1 => Ok(HTLCSource::OutboundRoute {
 				path: Readable::read(reader)?,
 				session_priv: Readable::read(reader)?,
 				first_hop_htlc_msat: Readable::read(reader)?,
 			}),
+			2 => Ok(HTLCSource::PreviousHopData(ReadableArgs::read(reader, 2)?)),
 			_ => Err(DecodeError::InvalidValue),
 		}
 	}
@@ -3546,13 +5069,13 @@ impl Writeable for HTLCForwardInfo {
 	}
 }
 
-impl Readable for HTLCForwardInfo {
-	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<HTLCForwardInfo, DecodeError> {
+impl ReadableArgs<u8> for HTLCForwardInfo {
+	fn read<R: ::std::io::Read>(reader: &mut R, ver: u8) -> Result<HTLCForwardInfo, DecodeError> {
 		match <u8 as Readable>::read(reader)? {
 			0 => Ok(HTLCForwardInfo::AddHTLC {
 				prev_short_channel_id: Readable::read(reader)?,
 				prev_htlc_id: Readable::read(reader)?,
-				forward_info: Readable::read(reader)?,
+				forward_info: ReadableArgs::read(reader, ver)?,
 			}),
 			1 => Ok(HTLCForwardInfo::FailHTLC {
 				htlc_id: Readable::read(reader)?,
@@ -3612,6 +5135,27 @@ impl<ChanSigner: ChannelKeys + Writeable, M: Deref, T: Deref, K: Deref, F: Deref
 			}
 		}
 
+		(channel_state.intercept_scids.len() as u64).write(writer)?;
+		for scid in channel_state.intercept_scids.iter() {
+			scid.write(writer)?;
+		}
+		(channel_state.pending_intercepted_htlcs.len() as u64).write(writer)?;
+		for (intercept_id, htlc) in channel_state.pending_intercepted_htlcs.iter() {
+			intercept_id.write(writer)?;
+			htlc.prev_short_channel_id.write(writer)?;
+			htlc.prev_htlc_id.write(writer)?;
+			htlc.cltv_expiry.write(writer)?;
+			htlc.forward_info.write(writer)?;
+		}
+		(channel_state.onion_replay_cache.len() as u64).write(writer)?;
+		for (outgoing_cltv_value, onion_hashes) in channel_state.onion_replay_cache.iter() {
+			outgoing_cltv_value.write(writer)?;
+			(onion_hashes.len() as u64).write(writer)?;
+			for onion_hash in onion_hashes.iter() {
+				onion_hash.write(writer)?;
+			}
+		}
+
 		let per_peer_state = self.per_peer_state.write().unwrap();
 		(per_peer_state.len() as u64).write(writer)?;
 		for (peer_pubkey, peer_state_mutex) in per_peer_state.iter() {
@@ -3627,6 +5171,7 @@ impl<ChanSigner: ChannelKeys + Writeable, M: Deref, T: Deref, K: Deref, F: Deref
 		}
 
 		(self.last_node_announcement_serial.load(Ordering::Acquire) as u32).write(writer)?;
+		(self.highest_seen_timestamp.load(Ordering::Acquire) as u32).write(writer)?;
 
 		Ok(())
 	}
@@ -3682,7 +5227,7 @@ pub struct ChannelManagerReadArgs<'a, ChanSigner: 'a + ChannelKeys, M: Deref, T:
 	pub default_config: UserConfig,
 
 	/// A map from channel funding outpoints to ChannelMonitors for those channels (ie
-	/// value.get_funding_txo() should be the key).
+	/// value.get_original_funding_txo() should be the key).
 	///
 	/// If a monitor is inconsistent with the channel state during deserialization the channel will
 	/// be force-closed using the data in the ChannelMonitor and the channel will be dropped. This
@@ -3719,7 +5264,7 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
         L::Target: Logger,
 {
 	fn read<R: ::std::io::Read>(reader: &mut R, args: ChannelManagerReadArgs<'a, ChanSigner, M, T, K, F, L>) -> Result<Self, DecodeError> {
-		let _ver: u8 = Readable::read(reader)?;
+		let ver: u8 = Readable::read(reader)?;
 		let min_ver: u8 = Readable::read(reader)?;
 		if min_ver > SERIALIZATION_VERSION {
 			return Err(DecodeError::UnknownVersion);
@@ -3730,6 +5275,7 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 		let last_block_hash: BlockHash = Readable::read(reader)?;
 
 		let mut failed_htlcs = Vec::new();
+		let mut closed_channel_events = Vec::new();
 
 		let channel_count: u64 = Readable::read(reader)?;
 		let mut funding_txo_set = HashSet::with_capacity(cmp::min(channel_count as usize, 128));
@@ -3737,11 +5283,12 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 		let mut short_to_id = HashMap::with_capacity(cmp::min(channel_count as usize, 128));
 		for _ in 0..channel_count {
 			let mut channel: Channel<ChanSigner> = Readable::read(reader)?;
+			channel.set_channel_creation_height_if_unknown(latest_block_height);
 			if channel.last_block_connected != Default::default() && channel.last_block_connected != last_block_hash {
 				return Err(DecodeError::InvalidValue);
 			}
 
-			let funding_txo = channel.get_funding_txo().ok_or(DecodeError::InvalidValue)?;
+			let funding_txo = channel.get_original_funding_txo().ok_or(DecodeError::InvalidValue)?;
 			funding_txo_set.insert(funding_txo.clone());
 			if let Some(ref mut monitor) = args.channel_monitors.get_mut(&funding_txo) {
 				if channel.get_cur_local_commitment_transaction_number() < monitor.get_cur_local_commitment_number() ||
@@ -3758,10 +5305,18 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 					let (_, _, mut new_failed_htlcs) = channel.force_shutdown(true);
 					failed_htlcs.append(&mut new_failed_htlcs);
 					monitor.broadcast_latest_local_commitment_txn(&args.tx_broadcaster, &args.logger);
+					closed_channel_events.push(events::Event::ChannelClosed {
+						channel_id: channel.channel_id(),
+						user_id: channel.get_user_id(),
+						reason: ClosureReason::ProcessingError { err: "ChannelManager is stale compared to its ChannelMonitor".to_owned() },
+					});
 				} else {
 					if let Some(short_channel_id) = channel.get_short_channel_id() {
 						short_to_id.insert(short_channel_id, channel.channel_id());
 					}
+					for short_channel_id in channel.get_prev_short_channel_ids() {
+						short_to_id.insert(*short_channel_id, channel.channel_id());
+					}
 					by_id.insert(channel.channel_id(), channel);
 				}
 			} else {
@@ -3783,7 +5338,7 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 			let pending_forwards_count: u64 = Readable::read(reader)?;
 			let mut pending_forwards = Vec::with_capacity(cmp::min(pending_forwards_count as usize, MAX_ALLOC_SIZE/mem::size_of::<HTLCForwardInfo>()));
 			for _ in 0..pending_forwards_count {
-				pending_forwards.push(Readable::read(reader)?);
+				pending_forwards.push(ReadableArgs::read(reader, ver)?);
 			}
 			forward_htlcs.insert(short_channel_id, pending_forwards);
 		}
@@ -3795,11 +5350,45 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 			let previous_hops_len: u64 = Readable::read(reader)?;
 			let mut previous_hops = Vec::with_capacity(cmp::min(previous_hops_len as usize, MAX_ALLOC_SIZE/mem::size_of::<ClaimableHTLC>()));
 			for _ in 0..previous_hops_len {
-				previous_hops.push(Readable::read(reader)?);
+				previous_hops.push(ReadableArgs::read(reader, ver)?);
 			}
 			claimable_htlcs.insert(payment_hash, previous_hops);
 		}
 
+		let mut intercept_scids = HashSet::new();
+		let mut pending_intercepted_htlcs = HashMap::new();
+		if ver >= 2 {
+			let intercept_scids_count: u64 = Readable::read(reader)?;
+			intercept_scids.reserve(cmp::min(intercept_scids_count as usize, 128));
+			for _ in 0..intercept_scids_count {
+				intercept_scids.insert(Readable::read(reader)?);
+			}
+			let pending_intercepted_htlcs_count: u64 = Readable::read(reader)?;
+			pending_intercepted_htlcs.reserve(cmp::min(pending_intercepted_htlcs_count as usize, 128));
+			for _ in 0..pending_intercepted_htlcs_count {
+				let intercept_id = Readable::read(reader)?;
+				pending_intercepted_htlcs.insert(intercept_id, InterceptedHTLC {
+					prev_short_channel_id: Readable::read(reader)?,
+					prev_htlc_id: Readable::read(reader)?,
+					cltv_expiry: Readable::read(reader)?,
+					forward_info: ReadableArgs::read(reader, ver)?,
+				});
+			}
+		}
+		let mut onion_replay_cache = BTreeMap::new();
+		if ver >= 2 {
+			let onion_replay_cache_count: u64 = Readable::read(reader)?;
+			for _ in 0..onion_replay_cache_count {
+				let outgoing_cltv_value = Readable::read(reader)?;
+				let onion_hashes_count: u64 = Readable::read(reader)?;
+				let mut onion_hashes = HashSet::with_capacity(cmp::min(onion_hashes_count as usize, 128));
+				for _ in 0..onion_hashes_count {
+					onion_hashes.insert(Readable::read(reader)?);
+				}
+				onion_replay_cache.insert(outgoing_cltv_value, onion_hashes);
+			}
+		}
+
 		let peer_count: u64 = Readable::read(reader)?;
 		let mut per_peer_state = HashMap::with_capacity(cmp::min(peer_count as usize, MAX_ALLOC_SIZE/mem::size_of::<(PublicKey, Mutex<PeerState>)>()));
 		for _ in 0..peer_count {
@@ -3813,13 +5402,18 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 		let event_count: u64 = Readable::read(reader)?;
 		let mut pending_events_read: Vec<events::Event> = Vec::with_capacity(cmp::min(event_count as usize, MAX_ALLOC_SIZE/mem::size_of::<events::Event>()));
 		for _ in 0..event_count {
-			match MaybeReadable::read(reader)? {
+			let event = if ver >= 2 { MaybeReadable::read(reader)? } else { events::Event::read_legacy(reader)? };
+			match event {
 				Some(event) => pending_events_read.push(event),
 				None => continue,
 			}
 		}
+		pending_events_read.append(&mut closed_channel_events);
 
 		let last_node_announcement_serial: u32 = Readable::read(reader)?;
+		// Version 1 didn't track block timestamps, so inbound payments can't be created until the
+		// next block is connected.
+		let highest_seen_timestamp: u32 = if ver >= 2 { Readable::read(reader)? } else { 0 };
 
 		let channel_manager = ChannelManager {
 			genesis_hash,
@@ -3836,11 +5430,17 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 				short_to_id,
 				forward_htlcs,
 				claimable_htlcs,
+				pending_inbound_channels: HashMap::new(),
+				intercept_scids,
+				pending_intercepted_htlcs,
+				onion_replay_cache,
 				pending_msg_events: Vec::new(),
 			}),
 			our_network_key: args.keys_manager.get_node_secret(),
+			inbound_payment_key: inbound_payment::ExpandedKey::new(&args.keys_manager.get_inbound_payment_key_material()),
 
 			last_node_announcement_serial: AtomicUsize::new(last_node_announcement_serial as usize),
+			highest_seen_timestamp: AtomicUsize::new(highest_seen_timestamp as usize),
 
 			per_peer_state: RwLock::new(per_peer_state),
 
@@ -32,11 +32,11 @@ use ln::chan_utils;
 use ln::chan_utils::{CounterpartyCommitmentSecrets, HTLCOutputInCommitment, LocalCommitmentTransaction, HTLCType};
 use ln::channelmanager::{HTLCSource, PaymentPreimage, PaymentHash};
 use ln::onchaintx::{OnchainTxHandler, InputDescriptors};
-use chain::chaininterface::{ChainListener, ChainWatchInterface, BroadcasterInterface, FeeEstimator};
+use chain::chaininterface::{ChainListener, ChainWatchInterface, BroadcasterInterface, FeeEstimator, UtxoPool};
 use chain::transaction::OutPoint;
 use chain::keysinterface::{SpendableOutputDescriptor, ChannelKeys};
 use util::logger::Logger;
-use util::ser::{Readable, MaybeReadable, Writer, Writeable, U48};
+use util::ser::{Readable, ReadableArgs, MaybeReadable, Writer, Writeable, U48};
 use util::{byte_utils, events};
 
 use std::collections::{HashMap, hash_map};
@@ -145,9 +145,13 @@ pub struct MonitorUpdateError(pub &'static str);
 pub struct HTLCUpdate {
 	pub(super) payment_hash: PaymentHash,
 	pub(super) payment_preimage: Option<PaymentPreimage>,
-	pub(super) source: HTLCSource
+	pub(super) source: HTLCSource,
+	/// The channel the HTLC was resolved on-chain in.
+	pub(super) channel_id: [u8; 32],
+	/// The value of the HTLC, set when it was claimed with the payment_preimage.
+	pub(super) htlc_value_msat: Option<u64>,
 }
-impl_writeable!(HTLCUpdate, 0, { payment_hash, payment_preimage, source });
+impl_writeable!(HTLCUpdate, 0, { payment_hash, payment_preimage, source, channel_id, htlc_value_msat });
 
 /// A simple implementation of a ManyChannelMonitor and ChainListener. Can be used to create a
 /// watchtower or watch our own channels.
@@ -160,11 +164,15 @@ impl_writeable!(HTLCUpdate, 0, { payment_hash, payment_preimage, source });
 ///
 /// If you're using this for local monitoring of your own channels, you probably want to use
 /// `OutPoint` as the key, which will give you a ManyChannelMonitor implementation.
-pub struct SimpleManyChannelMonitor<Key, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref, C: Deref>
+///
+/// The UtxoPool is used to fund the fee-bumping of our own commitment transactions in channels
+/// which negotiated option_anchor_outputs.
+pub struct SimpleManyChannelMonitor<Key, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref, C: Deref, U: Deref>
 	where T::Target: BroadcasterInterface,
         F::Target: FeeEstimator,
         L::Target: Logger,
         C::Target: ChainWatchInterface,
+        U::Target: UtxoPool,
 {
 	#[cfg(test)] // Used in ChannelManager tests to manipulate channels directly
 	pub monitors: Mutex<HashMap<Key, ChannelMonitor<ChanSigner>>>,
@@ -173,22 +181,24 @@ pub struct SimpleManyChannelMonitor<Key, ChanSigner: ChannelKeys, T: Deref, F: D
 	chain_monitor: C,
 	broadcaster: T,
 	logger: L,
-	fee_estimator: F
+	fee_estimator: F,
+	utxo_pool: U,
 }
 
-impl<Key : Send + cmp::Eq + hash::Hash, ChanSigner: ChannelKeys, T: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send, C: Deref + Sync + Send>
-	ChainListener for SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C>
+impl<Key : Send + cmp::Eq + hash::Hash, ChanSigner: ChannelKeys, T: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send, C: Deref + Sync + Send, U: Deref + Sync + Send>
+	ChainListener for SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C, U>
 	where T::Target: BroadcasterInterface,
 	      F::Target: FeeEstimator,
 	      L::Target: Logger,
         C::Target: ChainWatchInterface,
+        U::Target: UtxoPool,
 {
 	fn block_connected(&self, header: &BlockHeader, height: u32, txn_matched: &[&Transaction], _indexes_of_txn_matched: &[usize]) {
 		let block_hash = header.bitcoin_hash();
 		{
 			let mut monitors = self.monitors.lock().unwrap();
 			for monitor in monitors.values_mut() {
-				let txn_outputs = monitor.block_connected(txn_matched, height, &block_hash, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+				let txn_outputs = monitor.block_connected(txn_matched, height, &block_hash, &*self.broadcaster, &*self.fee_estimator, &*self.utxo_pool, &*self.logger);
 
 				for (ref txid, ref outputs) in txn_outputs {
 					for (idx, output) in outputs.iter().enumerate() {
@@ -203,26 +213,28 @@ impl<Key : Send + cmp::Eq + hash::Hash, ChanSigner: ChannelKeys, T: Deref + Sync
 		let block_hash = header.bitcoin_hash();
 		let mut monitors = self.monitors.lock().unwrap();
 		for monitor in monitors.values_mut() {
-			monitor.block_disconnected(disconnected_height, &block_hash, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+			monitor.block_disconnected(disconnected_height, &block_hash, &*self.broadcaster, &*self.fee_estimator, &*self.utxo_pool, &*self.logger);
 		}
 	}
 }
 
-impl<Key : Send + cmp::Eq + hash::Hash + 'static, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref, C: Deref> SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C>
+impl<Key : Send + cmp::Eq + hash::Hash + 'static, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref, C: Deref, U: Deref> SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C, U>
 	where T::Target: BroadcasterInterface,
 	      F::Target: FeeEstimator,
 	      L::Target: Logger,
         C::Target: ChainWatchInterface,
+        U::Target: UtxoPool,
 {
 	/// Creates a new object which can be used to monitor several channels given the chain
 	/// interface with which to register to receive notifications.
-	pub fn new(chain_monitor: C, broadcaster: T, logger: L, feeest: F) -> SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C> {
+	pub fn new(chain_monitor: C, broadcaster: T, logger: L, feeest: F, utxo_pool: U) -> SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C, U> {
 		let res = SimpleManyChannelMonitor {
 			monitors: Mutex::new(HashMap::new()),
 			chain_monitor,
 			broadcaster,
 			logger,
 			fee_estimator: feeest,
+			utxo_pool,
 		};
 
 		res
@@ -240,6 +252,14 @@ impl<Key : Send + cmp::Eq + hash::Hash + 'static, ChanSigner: ChannelKeys, T: De
 			log_trace!(self.logger, "Got new Channel Monitor for channel {}", log_bytes!(funding_txo.0.to_channel_id()[..]));
 			self.chain_monitor.install_watch_tx(&funding_txo.0.txid, &funding_txo.1);
 			self.chain_monitor.install_watch_outpoint((funding_txo.0.txid, funding_txo.0.index as u32), &funding_txo.1);
+			if let Some(spliced_funding_txo) = monitor.get_spliced_funding_txo() {
+				self.chain_monitor.install_watch_tx(&spliced_funding_txo.txid, &funding_txo.1);
+				self.chain_monitor.install_watch_outpoint((spliced_funding_txo.txid, spliced_funding_txo.index as u32), &funding_txo.1);
+			}
+			for prev_funding_txo in monitor.get_prev_funding_txos().iter() {
+				self.chain_monitor.install_watch_tx(&prev_funding_txo.txid, &funding_txo.1);
+				self.chain_monitor.install_watch_outpoint((prev_funding_txo.txid, prev_funding_txo.index as u32), &funding_txo.1);
+			}
 			for (txid, outputs) in monitor.get_outputs_to_watch().iter() {
 				for (idx, script) in outputs.iter().enumerate() {
 					self.chain_monitor.install_watch_outpoint((*txid, idx as u32), script);
@@ -256,18 +276,25 @@ impl<Key : Send + cmp::Eq + hash::Hash + 'static, ChanSigner: ChannelKeys, T: De
 		match monitors.get_mut(&key) {
 			Some(orig_monitor) => {
 				log_trace!(self.logger, "Updating Channel Monitor for channel {}", log_funding_info!(orig_monitor));
-				orig_monitor.update_monitor(update, &self.broadcaster, &self.logger)
+				orig_monitor.update_monitor(update, &self.broadcaster, &self.logger)?;
+				if let Some(spliced_funding_txo) = orig_monitor.get_spliced_funding_txo() {
+					let funding_txo = orig_monitor.get_funding_txo();
+					self.chain_monitor.install_watch_tx(&spliced_funding_txo.txid, &funding_txo.1);
+					self.chain_monitor.install_watch_outpoint((spliced_funding_txo.txid, spliced_funding_txo.index as u32), &funding_txo.1);
+				}
+				Ok(())
 			},
 			None => Err(MonitorUpdateError("No such monitor registered"))
 		}
 	}
 }
 
-impl<ChanSigner: ChannelKeys, T: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send, C: Deref + Sync + Send> ManyChannelMonitor for SimpleManyChannelMonitor<OutPoint, ChanSigner, T, F, L, C>
+impl<ChanSigner: ChannelKeys, T: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send, C: Deref + Sync + Send, U: Deref + Sync + Send> ManyChannelMonitor for SimpleManyChannelMonitor<OutPoint, ChanSigner, T, F, L, C, U>
 	where T::Target: BroadcasterInterface,
 	      F::Target: FeeEstimator,
 	      L::Target: Logger,
         C::Target: ChainWatchInterface,
+        U::Target: UtxoPool,
 {
 	type Keys = ChanSigner;
 
@@ -294,11 +321,12 @@ impl<ChanSigner: ChannelKeys, T: Deref + Sync + Send, F: Deref + Sync + Send, L:
 	}
 }
 
-impl<Key : Send + cmp::Eq + hash::Hash, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref, C: Deref> events::EventsProvider for SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C>
+impl<Key : Send + cmp::Eq + hash::Hash, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref, C: Deref, U: Deref> events::EventsProvider for SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C, U>
 	where T::Target: BroadcasterInterface,
 	      F::Target: FeeEstimator,
 	      L::Target: Logger,
         C::Target: ChainWatchInterface,
+        U::Target: UtxoPool,
 {
 	fn get_and_clear_pending_events(&self) -> Vec<events::Event> {
 		let mut pending_events = Vec::new();
@@ -322,7 +350,7 @@ pub(crate) const CLTV_CLAIM_BUFFER: u32 = 6;
 /// copies of ChannelMonitors, including watchtowers). We could enforce the contract by failing
 /// at CLTV expiration height but giving a grace period to our peer may be profitable for us if he
 /// can provide an over-late preimage. Nevertheless, grace period has to be accounted in our
-/// CLTV_EXPIRY_DELTA to be secure. Following this policy we may decrease the rate of channel failures
+/// MIN_CLTV_EXPIRY_DELTA to be secure. Following this policy we may decrease the rate of channel failures
 /// due to expiration but increase the cost of funds being locked longuer in case of failure.
 /// This delay also cover a low-power peer being slow to process blocks and so being behind us on
 /// accurate block height.
@@ -373,6 +401,21 @@ struct LocalSignedTx {
 	htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>,
 }
 
+impl LocalSignedTx {
+	fn new(commitment_tx: &LocalCommitmentTransaction, htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>) -> Self {
+		LocalSignedTx {
+			txid: commitment_tx.txid(),
+			revocation_key: commitment_tx.local_keys.revocation_key,
+			a_htlc_key: commitment_tx.local_keys.a_htlc_key,
+			b_htlc_key: commitment_tx.local_keys.b_htlc_key,
+			delayed_payment_key: commitment_tx.local_keys.a_delayed_payment_key,
+			per_commitment_point: commitment_tx.local_keys.per_commitment_point,
+			feerate_per_kw: commitment_tx.feerate_per_kw,
+			htlc_outputs,
+		}
+	}
+}
+
 /// We use this to track remote commitment transactions and htlcs outputs and
 /// use it to generate any justice or 2nd-stage preimage/timeout transactions.
 #[derive(PartialEq)]
@@ -590,8 +633,8 @@ enum OnchainEvent {
 	},
 }
 
-const SERIALIZATION_VERSION: u8 = 1;
-const MIN_SERIALIZATION_VERSION: u8 = 1;
+const SERIALIZATION_VERSION: u8 = 2;
+const MIN_SERIALIZATION_VERSION: u8 = 2;
 
 #[cfg_attr(test, derive(PartialEq))]
 #[derive(Clone)]
@@ -620,6 +663,34 @@ pub(super) enum ChannelMonitorUpdateStep {
 		/// think we've fallen behind!
 		should_broadcast: bool,
 	},
+	/// Used to indicate that a splice transaction was negotiated, providing the commitment
+	/// transactions spending its funding output, which replace the current ones once it locks.
+	SpliceNegotiated {
+		funding_txo: OutPoint,
+		channel_value_satoshis: u64,
+		local_commitment_tx: LocalCommitmentTransaction,
+		unsigned_remote_commitment_tx: Transaction,
+	},
+	/// Used to indicate that the negotiated splice transaction locked, ie that the channel is now
+	/// funded by its funding output.
+	///
+	/// Once this step is applied, the ChannelMonitor's funding outpoint is the spliced one, though
+	/// the channel keeps referring to the monitor by its original funding outpoint.
+	SpliceLocked,
+	/// Used to indicate that our commitment transaction spending the funding output of the
+	/// negotiated splice transaction was updated along with the one spending the current funding
+	/// output, provided in the LatestLocalCommitmentTXInfo step preceding it.
+	LatestSplicedLocalCommitmentTXInfo {
+		commitment_tx: LocalCommitmentTransaction,
+		htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>,
+	},
+	/// Used to indicate that our counterparty's commitment transaction spending the funding output
+	/// of the negotiated splice transaction was updated along with the one spending the current
+	/// funding output, provided in the LatestRemoteCommitmentTXInfo step preceding it.
+	LatestSplicedRemoteCommitmentTXInfo {
+		unsigned_commitment_tx: Transaction,
+		htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>,
+	},
 }
 
 impl Writeable for ChannelMonitorUpdateStep {
@@ -659,6 +730,35 @@ impl Writeable for ChannelMonitorUpdateStep {
 				4u8.write(w)?;
 				should_broadcast.write(w)?;
 			},
+			&ChannelMonitorUpdateStep::SpliceNegotiated { ref funding_txo, ref channel_value_satoshis, ref local_commitment_tx, ref unsigned_remote_commitment_tx } => {
+				5u8.write(w)?;
+				funding_txo.write(w)?;
+				channel_value_satoshis.write(w)?;
+				local_commitment_tx.write(w)?;
+				unsigned_remote_commitment_tx.write(w)?;
+			},
+			&ChannelMonitorUpdateStep::SpliceLocked => {
+				6u8.write(w)?;
+			},
+			&ChannelMonitorUpdateStep::LatestSplicedLocalCommitmentTXInfo { ref commitment_tx, ref htlc_outputs } => {
+				7u8.write(w)?;
+				commitment_tx.write(w)?;
+				(htlc_outputs.len() as u64).write(w)?;
+				for &(ref output, ref signature, ref source) in htlc_outputs.iter() {
+					output.write(w)?;
+					signature.write(w)?;
+					source.write(w)?;
+				}
+			},
+			&ChannelMonitorUpdateStep::LatestSplicedRemoteCommitmentTXInfo { ref unsigned_commitment_tx, ref htlc_outputs } => {
+				8u8.write(w)?;
+				unsigned_commitment_tx.write(w)?;
+				(htlc_outputs.len() as u64).write(w)?;
+				for &(ref output, ref source) in htlc_outputs.iter() {
+					output.write(w)?;
+					source.as_ref().map(|b| b.as_ref()).write(w)?;
+				}
+			},
 		}
 		Ok(())
 	}
@@ -710,11 +810,59 @@ impl Readable for ChannelMonitorUpdateStep {
 					should_broadcast: Readable::read(r)?
 				})
 			},
+			5u8 => {
+				Ok(ChannelMonitorUpdateStep::SpliceNegotiated {
+					funding_txo: Readable::read(r)?,
+					channel_value_satoshis: Readable::read(r)?,
+					local_commitment_tx: Readable::read(r)?,
+					unsigned_remote_commitment_tx: Readable::read(r)?,
+				})
+			},
+			6u8 => Ok(ChannelMonitorUpdateStep::SpliceLocked),
+			7u8 => {
+				Ok(ChannelMonitorUpdateStep::LatestSplicedLocalCommitmentTXInfo {
+					commitment_tx: Readable::read(r)?,
+					htlc_outputs: {
+						let len: u64 = Readable::read(r)?;
+						let mut res = Vec::new();
+						for _ in 0..len {
+							res.push((Readable::read(r)?, Readable::read(r)?, Readable::read(r)?));
+						}
+						res
+					},
+				})
+			},
+			8u8 => {
+				Ok(ChannelMonitorUpdateStep::LatestSplicedRemoteCommitmentTXInfo {
+					unsigned_commitment_tx: Readable::read(r)?,
+					htlc_outputs: {
+						let len: u64 = Readable::read(r)?;
+						let mut res = Vec::new();
+						for _ in 0..len {
+							res.push((Readable::read(r)?, <Option<HTLCSource> as Readable>::read(r)?.map(|o| Box::new(o))));
+						}
+						res
+					},
+				})
+			},
 			_ => Err(DecodeError::InvalidValue),
 		}
 	}
 }
 
+/// The funding output of a negotiated splice transaction and the commitment transactions spending
+/// it, which are updated along with the current ones, kept until the splice transaction locks and
+/// they replace the current ones.
+#[derive(Clone, PartialEq)]
+struct SplicedFunding {
+	funding_txo: OutPoint,
+	channel_value_satoshis: u64,
+	local_commitment_tx: LocalCommitmentTransaction,
+	local_signed_tx: LocalSignedTx,
+	current_remote_commitment_txid: Txid,
+	prev_remote_commitment_txid: Option<Txid>,
+}
+
 /// A ChannelMonitor handles chain events (blocks connected and disconnected) and generates
 /// on-chain transactions to ensure no loss of funds occurs.
 ///
@@ -736,12 +884,14 @@ pub struct ChannelMonitor<ChanSigner: ChannelKeys> {
 
 	keys: ChanSigner,
 	funding_info: (OutPoint, Script),
+	channel_id: [u8; 32],
 	current_remote_commitment_txid: Option<Txid>,
 	prev_remote_commitment_txid: Option<Txid>,
 
 	remote_tx_cache: RemoteCommitmentTransaction,
 	funding_redeemscript: Script,
 	channel_value_satoshis: u64,
+	opt_anchors: bool,
 	// first is the idx of the first of the two revocation points
 	their_cur_revocation_points: Option<(u64, PublicKey, Option<PublicKey>)>,
 
@@ -806,6 +956,15 @@ pub struct ChannelMonitor<ChanSigner: ChannelKeys> {
 	// may occur, and we fail any such monitor updates.
 	local_tx_signed: bool,
 
+	// Set when the channel was force-closed and we broadcast our latest local commitment transaction
+	// in a channel with anchor outputs, so that we start bumping its fee at the next block.
+	pending_funding_claim: bool,
+
+	spliced_funding: Option<SplicedFunding>,
+	// The funding outpoints the channel was funded by before being spliced, which we keep watching
+	// in case the splice transaction is reorged out.
+	prev_funding_txos: Vec<OutPoint>,
+
 	// We simply modify last_block_hash in Channel's block_connected so that serialization is
 	// consistent but hopefully the users' copy handles block_connected in a consistent way.
 	// (we do *not*, however, update them in update_monitor to ensure any local user copies keep
@@ -848,6 +1007,11 @@ pub trait ManyChannelMonitor: Send + Sync {
 	/// any relevant ChainWatchInterfaces such that the provided monitor receives block_connected
 	/// callbacks with the funding transaction, or any spends of it.
 	///
+	/// If the channel has been spliced, `funding_txo` is still the outpoint it was opened with (ie
+	/// monitor.get_original_funding_txo()), and the txids *and* outpoints returned by
+	/// monitor.get_funding_txo(), monitor.get_spliced_funding_txo() and
+	/// monitor.get_prev_funding_txos() must be registered as well.
+	///
 	/// Further, the implementer must also ensure that each output returned in
 	/// monitor.get_outputs_to_watch() is registered to ensure that the provided monitor learns about
 	/// any spends of any of the outputs.
@@ -862,6 +1026,12 @@ pub trait ManyChannelMonitor: Send + Sync {
 	/// any relevant ChainWatchInterfaces such that the provided monitor receives block_connected
 	/// callbacks with the funding transaction, or any spends of it.
 	///
+	/// If the channel is being spliced, the txid *and* outpoint returned by
+	/// monitor.get_spliced_funding_txo() must be registered as well. Note that `funding_txo` is
+	/// always the outpoint the channel was opened with (ie monitor.get_original_funding_txo()),
+	/// even once an update containing a SpliceLocked step has been applied and
+	/// monitor.get_funding_txo() returns the spliced funding outpoint.
+	///
 	/// Further, the implementer must also ensure that each output returned in
 	/// monitor.get_watch_outputs() is registered to ensure that the provided monitor learns about
 	/// any spends of any of the outputs.
@@ -891,11 +1061,13 @@ impl<ChanSigner: ChannelKeys> PartialEq for ChannelMonitor<ChanSigner> {
 			self.remote_payment_script != other.remote_payment_script ||
 			self.keys.pubkeys() != other.keys.pubkeys() ||
 			self.funding_info != other.funding_info ||
+			self.channel_id != other.channel_id ||
 			self.current_remote_commitment_txid != other.current_remote_commitment_txid ||
 			self.prev_remote_commitment_txid != other.prev_remote_commitment_txid ||
 			self.remote_tx_cache != other.remote_tx_cache ||
 			self.funding_redeemscript != other.funding_redeemscript ||
 			self.channel_value_satoshis != other.channel_value_satoshis ||
+			self.opt_anchors != other.opt_anchors ||
 			self.their_cur_revocation_points != other.their_cur_revocation_points ||
 			self.on_local_tx_csv != other.on_local_tx_csv ||
 			self.commitment_secrets != other.commitment_secrets ||
@@ -912,7 +1084,10 @@ impl<ChanSigner: ChannelKeys> PartialEq for ChannelMonitor<ChanSigner> {
 			self.onchain_events_waiting_threshold_conf != other.onchain_events_waiting_threshold_conf ||
 			self.outputs_to_watch != other.outputs_to_watch ||
 			self.lockdown_from_offchain != other.lockdown_from_offchain ||
-			self.local_tx_signed != other.local_tx_signed
+			self.local_tx_signed != other.local_tx_signed ||
+			self.pending_funding_claim != other.pending_funding_claim ||
+			self.spliced_funding != other.spliced_funding ||
+			self.prev_funding_txos != other.prev_funding_txos
 		{
 			false
 		} else {
@@ -957,12 +1132,14 @@ impl<ChanSigner: ChannelKeys + Writeable> ChannelMonitor<ChanSigner> {
 		writer.write_all(&self.funding_info.0.txid[..])?;
 		writer.write_all(&byte_utils::be16_to_array(self.funding_info.0.index))?;
 		self.funding_info.1.write(writer)?;
+		writer.write_all(&self.channel_id)?;
 		self.current_remote_commitment_txid.write(writer)?;
 		self.prev_remote_commitment_txid.write(writer)?;
 
 		self.remote_tx_cache.write(writer)?;
 		self.funding_redeemscript.write(writer)?;
 		self.channel_value_satoshis.write(writer)?;
+		self.opt_anchors.write(writer)?;
 
 		match self.their_cur_revocation_points {
 			Some((idx, pubkey, second_option)) => {
@@ -1106,6 +1283,23 @@ impl<ChanSigner: ChannelKeys + Writeable> ChannelMonitor<ChanSigner> {
 
 		self.lockdown_from_offchain.write(writer)?;
 		self.local_tx_signed.write(writer)?;
+		self.pending_funding_claim.write(writer)?;
+
+		if let Some(ref spliced_funding) = self.spliced_funding {
+			writer.write_all(&[1; 1])?;
+			spliced_funding.funding_txo.write(writer)?;
+			spliced_funding.channel_value_satoshis.write(writer)?;
+			spliced_funding.local_commitment_tx.write(writer)?;
+			serialize_local_tx!(spliced_funding.local_signed_tx);
+			spliced_funding.current_remote_commitment_txid.write(writer)?;
+			spliced_funding.prev_remote_commitment_txid.write(writer)?;
+		} else {
+			writer.write_all(&[0; 1])?;
+		}
+		writer.write_all(&byte_utils::be64_to_array(self.prev_funding_txos.len() as u64))?;
+		for funding_txo in self.prev_funding_txos.iter() {
+			funding_txo.write(writer)?;
+		}
 
 		Ok(())
 	}
@@ -1113,7 +1307,7 @@ impl<ChanSigner: ChannelKeys + Writeable> ChannelMonitor<ChanSigner> {
 
 impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 	pub(super) fn new(keys: ChanSigner, shutdown_pubkey: &PublicKey,
-			on_remote_tx_csv: u16, destination_script: &Script, funding_info: (OutPoint, Script),
+			on_remote_tx_csv: u16, destination_script: &Script, funding_info: (OutPoint, Script), channel_id: [u8; 32],
 			remote_htlc_base_key: &PublicKey, remote_delayed_payment_base_key: &PublicKey,
 			on_local_tx_csv: u16, funding_redeemscript: Script, channel_value_satoshis: u64,
 			commitment_transaction_number_obscure_factor: u64,
@@ -1122,25 +1316,17 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		assert!(commitment_transaction_number_obscure_factor <= (1 << 48));
 		let our_channel_close_key_hash = WPubkeyHash::hash(&shutdown_pubkey.serialize());
 		let shutdown_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&our_channel_close_key_hash[..]).into_script();
-		let payment_key_hash = WPubkeyHash::hash(&keys.pubkeys().payment_point.serialize());
-		let remote_payment_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&payment_key_hash[..]).into_script();
+		let opt_anchors = initial_local_commitment_tx.opt_anchors;
+		let remote_payment_script = get_remote_payment_script(&keys.pubkeys().payment_point, opt_anchors);
 
 		let remote_tx_cache = RemoteCommitmentTransaction { remote_delayed_payment_base_key: *remote_delayed_payment_base_key, remote_htlc_base_key: *remote_htlc_base_key, on_remote_tx_csv, per_htlc: HashMap::new() };
 
-		let mut onchain_tx_handler = OnchainTxHandler::new(destination_script.clone(), keys.clone(), on_local_tx_csv);
+		let mut onchain_tx_handler = OnchainTxHandler::new(destination_script.clone(), keys.clone(), on_local_tx_csv, opt_anchors);
 
 		let local_tx_sequence = initial_local_commitment_tx.unsigned_tx.input[0].sequence as u64;
 		let local_tx_locktime = initial_local_commitment_tx.unsigned_tx.lock_time as u64;
-		let local_commitment_tx = LocalSignedTx {
-			txid: initial_local_commitment_tx.txid(),
-			revocation_key: initial_local_commitment_tx.local_keys.revocation_key,
-			a_htlc_key: initial_local_commitment_tx.local_keys.a_htlc_key,
-			b_htlc_key: initial_local_commitment_tx.local_keys.b_htlc_key,
-			delayed_payment_key: initial_local_commitment_tx.local_keys.a_delayed_payment_key,
-			per_commitment_point: initial_local_commitment_tx.local_keys.per_commitment_point,
-			feerate_per_kw: initial_local_commitment_tx.feerate_per_kw,
-			htlc_outputs: Vec::new(), // There are never any HTLCs in the initial commitment transactions
-		};
+		// There are never any HTLCs in the initial commitment transactions
+		let local_commitment_tx = LocalSignedTx::new(&initial_local_commitment_tx, Vec::new());
 		// Returning a monitor error before updating tracking points means in case of using
 		// a concurrent watchtower implementation for same channel, if this one doesn't
 		// reject update as we do, you MAY have the latest local valid commitment tx onchain
@@ -1159,12 +1345,14 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 
 			keys,
 			funding_info,
+			channel_id,
 			current_remote_commitment_txid: None,
 			prev_remote_commitment_txid: None,
 
 			remote_tx_cache,
 			funding_redeemscript,
 			channel_value_satoshis: channel_value_satoshis,
+			opt_anchors,
 			their_cur_revocation_points: None,
 
 			on_local_tx_csv,
@@ -1190,6 +1378,10 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 
 			lockdown_from_offchain: false,
 			local_tx_signed: false,
+			pending_funding_claim: false,
+
+			spliced_funding: None,
+			prev_funding_txos: Vec::new(),
 
 			last_block_hash: Default::default(),
 			secp_ctx: Secp256k1::new(),
@@ -1211,6 +1403,11 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 				*source = None;
 			}
 		}
+		if let Some(txid) = self.spliced_funding.as_mut().and_then(|spliced_funding| spliced_funding.prev_remote_commitment_txid.take()) {
+			for &mut (_, ref mut source) in self.remote_claimable_outpoints.get_mut(&txid).unwrap() {
+				*source = None;
+			}
+		}
 
 		if !self.payment_preimages.is_empty() {
 			let cur_local_signed_commitment_tx = &self.current_local_commitment_tx;
@@ -1331,6 +1528,84 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		Ok(())
 	}
 
+	/// Informs this monitor of a negotiated splice transaction, providing the commitment
+	/// transactions spending its funding output. As the channel is quiescent while splicing, these
+	/// re-sign the current commitment transactions without any HTLCs. The remote one is watched for
+	/// right away while the local one only replaces ours once the splice transaction locks.
+	fn provide_spliced_funding<L: Deref>(&mut self, funding_txo: OutPoint, channel_value_satoshis: u64, local_commitment_tx: LocalCommitmentTransaction, unsigned_remote_commitment_tx: &Transaction, logger: &L) where L::Target: Logger {
+		let remote_commitment_txid = unsigned_remote_commitment_tx.txid();
+		log_trace!(logger, "Tracking spliced funding output {}:{} with remote commitment transaction {}", funding_txo.txid, funding_txo.index, remote_commitment_txid);
+		self.remote_claimable_outpoints.insert(remote_commitment_txid, Vec::new());
+		self.remote_tx_cache.per_htlc.insert(remote_commitment_txid, Vec::new());
+		self.spliced_funding = Some(SplicedFunding {
+			funding_txo,
+			channel_value_satoshis,
+			local_signed_tx: LocalSignedTx::new(&local_commitment_tx, Vec::new()),
+			local_commitment_tx,
+			current_remote_commitment_txid: remote_commitment_txid,
+			prev_remote_commitment_txid: None,
+		});
+	}
+
+	/// Informs this monitor of our latest commitment transaction spending the funding output of the
+	/// negotiated splice transaction, which replaces ours if it locks.
+	fn provide_latest_spliced_local_commitment_tx_info(&mut self, commitment_tx: LocalCommitmentTransaction, htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>) -> Result<(), MonitorUpdateError> {
+		match self.spliced_funding {
+			Some(ref mut spliced_funding) => {
+				spliced_funding.local_signed_tx = LocalSignedTx::new(&commitment_tx, htlc_outputs);
+				spliced_funding.local_commitment_tx = commitment_tx;
+				Ok(())
+			},
+			None => Err(MonitorUpdateError("Got a spliced local commitment transaction without a negotiated splice")),
+		}
+	}
+
+	/// Informs this monitor of our counterparty's latest commitment transaction spending the
+	/// funding output of the negotiated splice transaction, which we watch for as we do for the one
+	/// spending the current funding output.
+	fn provide_latest_spliced_remote_commitment_tx_info<L: Deref>(&mut self, unsigned_commitment_tx: &Transaction, htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>, logger: &L) -> Result<(), MonitorUpdateError> where L::Target: Logger {
+		let spliced_funding = match self.spliced_funding {
+			Some(ref mut spliced_funding) => spliced_funding,
+			None => return Err(MonitorUpdateError("Got a spliced remote commitment transaction without a negotiated splice")),
+		};
+		let new_txid = unsigned_commitment_tx.txid();
+		log_trace!(logger, "Tracking new remote commitment transaction with txid {} spending spliced funding output {}:{} with {} HTLC outputs", new_txid, spliced_funding.funding_txo.txid, spliced_funding.funding_txo.index, htlc_outputs.len());
+		spliced_funding.prev_remote_commitment_txid = Some(spliced_funding.current_remote_commitment_txid);
+		spliced_funding.current_remote_commitment_txid = new_txid;
+		let mut htlcs = Vec::with_capacity(htlc_outputs.len());
+		for htlc in htlc_outputs.iter() {
+			if htlc.0.transaction_output_index.is_some() {
+				htlcs.push(htlc.0.clone());
+			}
+		}
+		self.remote_claimable_outpoints.insert(new_txid, htlc_outputs);
+		self.remote_tx_cache.per_htlc.insert(new_txid, htlcs);
+		Ok(())
+	}
+
+	/// Switches over to the funding output of the negotiated splice transaction, if any.
+	fn promote_spliced_funding<L: Deref>(&mut self, logger: &L) where L::Target: Logger {
+		if let Some(spliced_funding) = self.spliced_funding.take() {
+			log_info!(logger, "Channel {} is now funded by spliced funding output {}:{}", log_bytes!(self.funding_info.0.to_channel_id()[..]), spliced_funding.funding_txo.txid, spliced_funding.funding_txo.index);
+			self.prev_funding_txos.push(self.funding_info.0);
+			self.funding_info.0 = spliced_funding.funding_txo;
+			self.channel_value_satoshis = spliced_funding.channel_value_satoshis;
+			self.keys.set_channel_value_satoshis(spliced_funding.channel_value_satoshis);
+			// Our counterparty's commitment transactions spending the previous funding output can no
+			// longer confirm, so stop tracking the HTLCs in them as we do once they're revoked.
+			for txid in self.current_remote_commitment_txid.iter().chain(self.prev_remote_commitment_txid.iter()) {
+				for &mut (_, ref mut source) in self.remote_claimable_outpoints.get_mut(txid).unwrap() {
+					*source = None;
+				}
+			}
+			self.current_remote_commitment_txid = Some(spliced_funding.current_remote_commitment_txid);
+			self.prev_remote_commitment_txid = spliced_funding.prev_remote_commitment_txid;
+			self.current_local_commitment_tx = spliced_funding.local_signed_tx;
+			self.prev_local_signed_commitment_tx = None;
+			self.onchain_tx_handler.provide_spliced_local_tx(spliced_funding.local_commitment_tx, spliced_funding.channel_value_satoshis);
+		}
+	}
+
 	/// Provides a payment_hash->payment_preimage mapping. Will be automatically pruned when all
 	/// commitment_tx_infos which contain the payment hash have been revoked.
 	pub(super) fn provide_payment_preimage(&mut self, payment_hash: &PaymentHash, payment_preimage: &PaymentPreimage) {
@@ -1361,6 +1636,16 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 				ChannelMonitorUpdateStep::CommitmentSecret { idx, secret } =>
 					self.provide_secret(idx, secret)?,
 				ChannelMonitorUpdateStep::ChannelForceClosed { .. } => {},
+				ChannelMonitorUpdateStep::SpliceNegotiated { funding_txo, channel_value_satoshis, local_commitment_tx, unsigned_remote_commitment_tx } =>
+					self.provide_spliced_funding(funding_txo, channel_value_satoshis, local_commitment_tx, &unsigned_remote_commitment_tx, logger),
+				ChannelMonitorUpdateStep::SpliceLocked =>
+					self.promote_spliced_funding(logger),
+				ChannelMonitorUpdateStep::LatestSplicedLocalCommitmentTXInfo { commitment_tx, htlc_outputs } => {
+					if self.lockdown_from_offchain { panic!(); }
+					self.provide_latest_spliced_local_commitment_tx_info(commitment_tx, htlc_outputs)?
+				},
+				ChannelMonitorUpdateStep::LatestSplicedRemoteCommitmentTXInfo { unsigned_commitment_tx, htlc_outputs } =>
+					self.provide_latest_spliced_remote_commitment_tx_info(&unsigned_commitment_tx, htlc_outputs, logger)?,
 			}
 		}
 		self.latest_update_id = updates.update_id;
@@ -1394,10 +1679,25 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 					self.lockdown_from_offchain = true;
 					if should_broadcast {
 						self.broadcast_latest_local_commitment_txn(broadcaster, logger);
+						if self.opt_anchors {
+							self.pending_funding_claim = true;
+						}
 					} else {
 						log_error!(logger, "You have a toxic local commitment transaction avaible in channel monitor, read comment in ChannelMonitor::get_latest_local_commitment_txn to be informed of manual action to take");
 					}
-				}
+				},
+				ChannelMonitorUpdateStep::SpliceNegotiated { funding_txo, channel_value_satoshis, local_commitment_tx, unsigned_remote_commitment_tx } => {
+					if self.lockdown_from_offchain { panic!(); }
+					self.provide_spliced_funding(funding_txo, channel_value_satoshis, local_commitment_tx, &unsigned_remote_commitment_tx, logger)
+				},
+				ChannelMonitorUpdateStep::SpliceLocked =>
+					self.promote_spliced_funding(logger),
+				ChannelMonitorUpdateStep::LatestSplicedLocalCommitmentTXInfo { commitment_tx, htlc_outputs } => {
+					if self.lockdown_from_offchain { panic!(); }
+					self.provide_latest_spliced_local_commitment_tx_info(commitment_tx, htlc_outputs)?
+				},
+				ChannelMonitorUpdateStep::LatestSplicedRemoteCommitmentTXInfo { unsigned_commitment_tx, htlc_outputs } =>
+					self.provide_latest_spliced_remote_commitment_tx_info(&unsigned_commitment_tx, htlc_outputs, logger)?,
 			}
 		}
 		self.latest_update_id = updates.update_id;
@@ -1415,6 +1715,25 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		&self.funding_info
 	}
 
+	/// Gets the funding outpoint the channel was opened with, by which the channel refers to this
+	/// ChannelMonitor even once it has been spliced.
+	pub fn get_original_funding_txo(&self) -> OutPoint {
+		self.prev_funding_txos.first().cloned().unwrap_or(self.funding_info.0)
+	}
+
+	/// Gets the funding outpoint of a negotiated splice transaction which hasn't yet replaced the
+	/// one returned by get_funding_txo. Spends of it (which pay to the same script) must be watched
+	/// for as well.
+	pub fn get_spliced_funding_txo(&self) -> Option<OutPoint> {
+		self.spliced_funding.as_ref().map(|spliced_funding| spliced_funding.funding_txo)
+	}
+
+	/// Gets the funding outpoints the channel was funded by before being spliced, spends of which
+	/// (which pay to the same script) should still be watched for in case of a reorg.
+	pub fn get_prev_funding_txos(&self) -> &Vec<OutPoint> {
+		&self.prev_funding_txos
+	}
+
 	/// Gets a list of txids, with their output scripts (in the order they appear in the
 	/// transaction), which we must learn about spends of via block_connected().
 	pub fn get_outputs_to_watch(&self) -> &HashMap<Txid, Vec<Script>> {
@@ -1641,12 +1960,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 						if revocation_points.0 == commitment_number + 1 { Some(point) } else { None }
 					} else { None };
 				if let Some(revocation_point) = revocation_point_option {
-					self.remote_payment_script = {
-						// Note that the Network here is ignored as we immediately drop the address for the
-						// script_pubkey version
-						let payment_hash160 = WPubkeyHash::hash(&self.keys.pubkeys().payment_point.serialize());
-						Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&payment_hash160[..]).into_script()
-					};
+					self.remote_payment_script = get_remote_payment_script(&self.keys.pubkeys().payment_point, self.opt_anchors);
 
 					// Then, try to find htlc outputs
 					for (_, &(ref htlc, _)) in per_commitment_data.iter().enumerate() {
@@ -1870,9 +2184,10 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 	/// Eventually this should be pub and, roughly, implement ChainListener, however this requires
 	/// &mut self, as well as returns new spendable outputs and outpoints to watch for spending of
 	/// on-chain.
-	fn block_connected<B: Deref, F: Deref, L: Deref>(&mut self, txn_matched: &[&Transaction], height: u32, block_hash: &BlockHash, broadcaster: B, fee_estimator: F, logger: L)-> Vec<(Txid, Vec<TxOut>)>
+	fn block_connected<B: Deref, F: Deref, U: Deref, L: Deref>(&mut self, txn_matched: &[&Transaction], height: u32, block_hash: &BlockHash, broadcaster: B, fee_estimator: F, utxo_pool: U, logger: L)-> Vec<(Txid, Vec<TxOut>)>
 		where B::Target: BroadcasterInterface,
 		      F::Target: FeeEstimator,
+		      U::Target: UtxoPool,
 					L::Target: Logger,
 	{
 		for tx in txn_matched {
@@ -1885,6 +2200,24 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		}
 
 		log_trace!(logger, "Block {} at height {} connected with {} txn matched", block_hash, height, txn_matched.len());
+		if let Some(spliced_funding_txo) = self.get_spliced_funding_txo() {
+			// Our previous commitment transactions can't confirm once the splice transaction has, so
+			// if the channel was already force-closed or our counterparty broadcasts a commitment
+			// transaction spending the spliced funding output we can't wait on the channel to tell us
+			// the splice locked.
+			let splice_confirmed = txn_matched.iter().any(|tx| tx.txid() == spliced_funding_txo.txid);
+			let spliced_funding_spent = txn_matched.iter().any(|tx| tx.input.iter().any(|input|
+				input.previous_output.txid == spliced_funding_txo.txid && input.previous_output.vout == spliced_funding_txo.index as u32));
+			if (splice_confirmed && self.lockdown_from_offchain) || spliced_funding_spent {
+				self.promote_spliced_funding(&logger);
+				if self.lockdown_from_offchain && !spliced_funding_spent {
+					self.broadcast_latest_local_commitment_txn(&broadcaster, &logger);
+					if self.opt_anchors {
+						self.pending_funding_claim = true;
+					}
+				}
+			}
+		}
 		let mut watch_outputs = Vec::new();
 		let mut claimable_outpoints = Vec::new();
 		for tx in txn_matched {
@@ -1927,7 +2260,10 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 			self.is_paying_spendable_output(&tx, height, &logger);
 		}
 		let should_broadcast = self.would_broadcast_at_height(height, &logger);
-		if should_broadcast {
+		// If we already broadcast our commitment transaction upon force-close, hand it over to the
+		// OnchainTxHandler too so that its fee gets bumped via our anchor output.
+		if should_broadcast || self.pending_funding_claim {
+			self.pending_funding_claim = false;
 			claimable_outpoints.push(ClaimRequest { absolute_timelock: height, aggregable: false, outpoint: BitcoinOutPoint { txid: self.funding_info.0.txid.clone(), vout: self.funding_info.0.index as u32 }, witness_data: InputMaterial::Funding { funding_redeemscript: self.funding_redeemscript.clone() }});
 		}
 		if should_broadcast {
@@ -1948,6 +2284,8 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 							payment_hash: htlc_update.1,
 							payment_preimage: None,
 							source: htlc_update.0,
+							channel_id: self.channel_id,
+							htlc_value_msat: None,
 						});
 					},
 					OnchainEvent::MaturingOutput { descriptor } => {
@@ -1959,7 +2297,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 				}
 			}
 		}
-		self.onchain_tx_handler.block_connected(txn_matched, claimable_outpoints, height, &*broadcaster, &*fee_estimator, &*logger);
+		self.onchain_tx_handler.block_connected(txn_matched, claimable_outpoints, height, &*broadcaster, &*fee_estimator, &*utxo_pool, &*logger);
 
 		self.last_block_hash = block_hash.clone();
 		for &(ref txid, ref output_scripts) in watch_outputs.iter() {
@@ -1969,9 +2307,10 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		watch_outputs
 	}
 
-	fn block_disconnected<B: Deref, F: Deref, L: Deref>(&mut self, height: u32, block_hash: &BlockHash, broadcaster: B, fee_estimator: F, logger: L)
+	fn block_disconnected<B: Deref, F: Deref, U: Deref, L: Deref>(&mut self, height: u32, block_hash: &BlockHash, broadcaster: B, fee_estimator: F, utxo_pool: U, logger: L)
 		where B::Target: BroadcasterInterface,
 		      F::Target: FeeEstimator,
+		      U::Target: UtxoPool,
 		      L::Target: Logger,
 	{
 		log_trace!(logger, "Block {} at height {} disconnected", block_hash, height);
@@ -1981,7 +2320,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 			//- maturing spendable output has transaction paying us has been disconnected
 		}
 
-		self.onchain_tx_handler.block_disconnected(height, broadcaster, fee_estimator, logger);
+		self.onchain_tx_handler.block_disconnected(height, broadcaster, fee_estimator, utxo_pool, logger);
 
 		self.last_block_hash = block_hash.clone();
 	}
@@ -2019,8 +2358,8 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 					//      inbound_cltv == height + CLTV_CLAIM_BUFFER
 					//      outbound_cltv + LATENCY_GRACE_PERIOD_BLOCKS + CLTV_CLAIM_BUFFER <= inbound_cltv - CLTV_CLAIM_BUFFER
 					//      LATENCY_GRACE_PERIOD_BLOCKS + 2*CLTV_CLAIM_BUFFER <= inbound_cltv - outbound_cltv
-					//      CLTV_EXPIRY_DELTA <= inbound_cltv - outbound_cltv (by check in ChannelManager::decode_update_add_htlc_onion)
-					//      LATENCY_GRACE_PERIOD_BLOCKS + 2*CLTV_CLAIM_BUFFER <= CLTV_EXPIRY_DELTA
+					//      MIN_CLTV_EXPIRY_DELTA <= inbound_cltv - outbound_cltv (by check in ChannelManager::decode_update_add_htlc_onion)
+					//      LATENCY_GRACE_PERIOD_BLOCKS + 2*CLTV_CLAIM_BUFFER <= MIN_CLTV_EXPIRY_DELTA
 					//  The final, above, condition is checked for statically in channelmanager
 					//  with CHECK_CLTV_EXPIRY_SANITY_2.
 					let htlc_outbound = $local_tx == htlc.offered;
@@ -2054,10 +2393,10 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 	fn is_resolving_htlc_output<L: Deref>(&mut self, tx: &Transaction, height: u32, logger: &L) where L::Target: Logger {
 		'outer_loop: for input in &tx.input {
 			let mut payment_data = None;
-			let revocation_sig_claim = (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len()) == Some(HTLCType::OfferedHTLC) && input.witness[1].len() == 33)
-				|| (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len()) == Some(HTLCType::AcceptedHTLC) && input.witness[1].len() == 33);
-			let accepted_preimage_claim = input.witness.len() == 5 && HTLCType::scriptlen_to_htlctype(input.witness[4].len()) == Some(HTLCType::AcceptedHTLC);
-			let offered_preimage_claim = input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len()) == Some(HTLCType::OfferedHTLC);
+			let revocation_sig_claim = (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len(), self.opt_anchors) == Some(HTLCType::OfferedHTLC) && input.witness[1].len() == 33)
+				|| (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len(), self.opt_anchors) == Some(HTLCType::AcceptedHTLC) && input.witness[1].len() == 33);
+			let accepted_preimage_claim = input.witness.len() == 5 && HTLCType::scriptlen_to_htlctype(input.witness[4].len(), self.opt_anchors) == Some(HTLCType::AcceptedHTLC);
+			let offered_preimage_claim = input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len(), self.opt_anchors) == Some(HTLCType::OfferedHTLC);
 
 			macro_rules! log_claim {
 				($tx_info: expr, $local_tx: expr, $htlc: expr, $source_avail: expr) => {
@@ -2088,7 +2427,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 							if pending_htlc.payment_hash == $htlc_output.payment_hash && pending_htlc.amount_msat == $htlc_output.amount_msat {
 								if let &Some(ref source) = pending_source {
 									log_claim!("revoked remote commitment tx", false, pending_htlc, true);
-									payment_data = Some(((**source).clone(), $htlc_output.payment_hash));
+									payment_data = Some(((**source).clone(), $htlc_output.payment_hash, $htlc_output.amount_msat));
 									break;
 								}
 							}
@@ -2108,7 +2447,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 								// transaction. This implies we either learned a preimage, the HTLC
 								// has timed out, or we screwed up. In any case, we should now
 								// resolve the source HTLC with the original sender.
-								payment_data = Some(((*source).clone(), htlc_output.payment_hash));
+								payment_data = Some(((*source).clone(), htlc_output.payment_hash, htlc_output.amount_msat));
 							} else if !$local_tx {
 									check_htlc_valid_remote!(self.current_remote_commitment_txid, htlc_output);
 								if payment_data.is_none() {
@@ -2141,7 +2480,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 
 			// Check that scan_commitment, above, decided there is some source worth relaying an
 			// HTLC resolution backwards to and figure out whether we learned a preimage from it.
-			if let Some((source, payment_hash)) = payment_data {
+			if let Some((source, payment_hash, amount_msat)) = payment_data {
 				let mut payment_preimage = PaymentPreimage([0; 32]);
 				if accepted_preimage_claim {
 					if !self.pending_htlcs_updated.iter().any(|update| update.source == source) {
@@ -2149,7 +2488,9 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 						self.pending_htlcs_updated.push(HTLCUpdate {
 							source,
 							payment_preimage: Some(payment_preimage),
-							payment_hash
+							payment_hash,
+							channel_id: self.channel_id,
+							htlc_value_msat: Some(amount_msat),
 						});
 					}
 				} else if offered_preimage_claim {
@@ -2158,7 +2499,9 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 						self.pending_htlcs_updated.push(HTLCUpdate {
 							source,
 							payment_preimage: Some(payment_preimage),
-							payment_hash
+							payment_hash,
+							channel_id: self.channel_id,
+							htlc_value_msat: Some(amount_msat),
 						});
 					}
 				} else {
@@ -2208,10 +2551,18 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 					break;
 				}
 			} else if self.remote_payment_script == outp.script_pubkey {
-				spendable_output = Some(SpendableOutputDescriptor::StaticOutputRemotePayment {
-					outpoint: BitcoinOutPoint { txid: tx.txid(), vout: i as u32 },
-					output: outp.clone(),
-					key_derivation_params: self.keys.key_derivation_params(),
+				spendable_output = Some(if self.opt_anchors {
+					SpendableOutputDescriptor::StaticOutputRemotePaymentWithAnchors {
+						outpoint: BitcoinOutPoint { txid: tx.txid(), vout: i as u32 },
+						output: outp.clone(),
+						key_derivation_params: self.keys.key_derivation_params(),
+					}
+				} else {
+					SpendableOutputDescriptor::StaticOutputRemotePayment {
+						outpoint: BitcoinOutPoint { txid: tx.txid(), vout: i as u32 },
+						output: outp.clone(),
+						key_derivation_params: self.keys.key_derivation_params(),
+					}
 				});
 				break;
 			} else if outp.script_pubkey == self.shutdown_script {
@@ -2238,6 +2589,17 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 
 const MAX_ALLOC_SIZE: usize = 64*1024;
 
+/// Gets the script_pubkey of the to_remote output in remote commitment transactions, which pays
+/// to our payment point.
+fn get_remote_payment_script(payment_point: &PublicKey, opt_anchors: bool) -> Script {
+	if opt_anchors {
+		chan_utils::get_to_countersignatory_with_anchors_redeemscript(payment_point).to_v0_p2wsh()
+	} else {
+		let payment_hash160 = WPubkeyHash::hash(&payment_point.serialize());
+		Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&payment_hash160[..]).into_script()
+	}
+}
+
 impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor<ChanSigner>) {
 	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
 		macro_rules! unwrap_obj {
@@ -2249,7 +2611,7 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 			}
 		}
 
-		let _ver: u8 = Readable::read(reader)?;
+		let ver: u8 = Readable::read(reader)?;
 		let min_ver: u8 = Readable::read(reader)?;
 		if min_ver > SERIALIZATION_VERSION {
 			return Err(DecodeError::UnknownVersion);
@@ -2280,12 +2642,16 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 			index: Readable::read(reader)?,
 		};
 		let funding_info = (outpoint, Readable::read(reader)?);
+		// Version 1 monitors were only ever written for channels which were never spliced, so the
+		// channel id is still derived from the funding outpoint.
+		let channel_id: [u8; 32] = if ver >= 2 { Readable::read(reader)? } else { funding_info.0.to_channel_id() };
 		let current_remote_commitment_txid = Readable::read(reader)?;
 		let prev_remote_commitment_txid = Readable::read(reader)?;
 
 		let remote_tx_cache = Readable::read(reader)?;
 		let funding_redeemscript = Readable::read(reader)?;
 		let channel_value_satoshis = Readable::read(reader)?;
+		let opt_anchors = if ver >= 2 { Readable::read(reader)? } else { false };
 
 		let their_cur_revocation_points = {
 			let first_idx = <U48 as Readable>::read(reader)?.0;
@@ -2418,7 +2784,15 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 		let pending_htlcs_updated_len: u64 = Readable::read(reader)?;
 		let mut pending_htlcs_updated = Vec::with_capacity(cmp::min(pending_htlcs_updated_len as usize, MAX_ALLOC_SIZE / (32 + 8*3)));
 		for _ in 0..pending_htlcs_updated_len {
-			pending_htlcs_updated.push(Readable::read(reader)?);
+			pending_htlcs_updated.push(if ver >= 2 { Readable::read(reader)? } else {
+				HTLCUpdate {
+					payment_hash: Readable::read(reader)?,
+					payment_preimage: Readable::read(reader)?,
+					source: Readable::read(reader)?,
+					channel_id,
+					htlc_value_msat: None,
+				}
+			});
 		}
 
 		let pending_events_len: u64 = Readable::read(reader)?;
@@ -2472,10 +2846,33 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 				return Err(DecodeError::InvalidValue);
 			}
 		}
-		let onchain_tx_handler = Readable::read(reader)?;
+		let onchain_tx_handler = ReadableArgs::read(reader, ver)?;
 
 		let lockdown_from_offchain = Readable::read(reader)?;
 		let local_tx_signed = Readable::read(reader)?;
+		let pending_funding_claim = if ver >= 2 { Readable::read(reader)? } else { false };
+
+		let mut spliced_funding = None;
+		let mut prev_funding_txos = Vec::new();
+		if ver >= 2 {
+			spliced_funding = match <u8 as Readable>::read(reader)? {
+				0 => None,
+				1 => Some(SplicedFunding {
+					funding_txo: Readable::read(reader)?,
+					channel_value_satoshis: Readable::read(reader)?,
+					local_commitment_tx: Readable::read(reader)?,
+					local_signed_tx: read_local_tx!(),
+					current_remote_commitment_txid: Readable::read(reader)?,
+					prev_remote_commitment_txid: Readable::read(reader)?,
+				}),
+				_ => return Err(DecodeError::InvalidValue),
+			};
+			let prev_funding_txos_len: u64 = Readable::read(reader)?;
+			prev_funding_txos.reserve(cmp::min(prev_funding_txos_len as usize, MAX_ALLOC_SIZE / 34));
+			for _ in 0..prev_funding_txos_len {
+				prev_funding_txos.push(Readable::read(reader)?);
+			}
+		}
 
 		Ok((last_block_hash.clone(), ChannelMonitor {
 			latest_update_id,
@@ -2488,12 +2885,14 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 
 			keys,
 			funding_info,
+			channel_id,
 			current_remote_commitment_txid,
 			prev_remote_commitment_txid,
 
 			remote_tx_cache,
 			funding_redeemscript,
 			channel_value_satoshis,
+			opt_anchors,
 			their_cur_revocation_points,
 
 			on_local_tx_csv,
@@ -2519,6 +2918,10 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 
 			lockdown_from_offchain,
 			local_tx_signed,
+			pending_funding_claim,
+
+			spliced_funding,
+			prev_funding_txos,
 
 			last_block_hash,
 			secp_ctx: Secp256k1::new(),
@@ -2618,7 +3021,7 @@ mod tests {
 		// old state.
 		let mut monitor = ChannelMonitor::new(keys,
 			&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap()), 0, &Script::new(),
-			(OutPoint { txid: Txid::from_slice(&[43; 32]).unwrap(), index: 0 }, Script::new()),
+			(OutPoint { txid: Txid::from_slice(&[43; 32]).unwrap(), index: 0 }, Script::new()), [43; 32],
 			&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[44; 32]).unwrap()),
 			&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[45; 32]).unwrap()),
 			10, Script::new(), 46, 0, LocalCommitmentTransaction::dummy());
@@ -2683,7 +3086,7 @@ mod tests {
 					payment_hash: PaymentHash([1; 32]),
 					transaction_output_index: Some($idx),
 				};
-				let redeem_script = if *$input_type == InputDescriptors::RevokedOutput { chan_utils::get_revokeable_redeemscript(&pubkey, 256, &pubkey) } else { chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &pubkey, &pubkey, &pubkey) };
+				let redeem_script = if *$input_type == InputDescriptors::RevokedOutput { chan_utils::get_revokeable_redeemscript(&pubkey, 256, &pubkey) } else { chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &pubkey, &pubkey, &pubkey, false) };
 				let sighash = hash_to_message!(&$sighash_parts.sighash_all(&$input, &redeem_script, $amount)[..]);
 				let sig = secp_ctx.sign(&sighash, &privkey);
 				$input.witness.push(sig.serialize_der().to_vec());
@@ -2731,7 +3134,7 @@ mod tests {
 		for (idx, inp) in claim_tx.input.iter_mut().zip(inputs_des.iter()).enumerate() {
 			sign_input!(sighash_parts, inp.0, idx as u32, 0, inp.1, sum_actual_sigs);
 		}
-		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..]),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
+		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..], false),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
 
 		// Claim tx with 1 offered HTLCs, 3 received HTLCs
 		claim_tx.input.clear();
@@ -2753,7 +3156,7 @@ mod tests {
 		for (idx, inp) in claim_tx.input.iter_mut().zip(inputs_des.iter()).enumerate() {
 			sign_input!(sighash_parts, inp.0, idx as u32, 0, inp.1, sum_actual_sigs);
 		}
-		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..]),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
+		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..], false),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
 
 		// Justice tx with 1 revoked HTLC-Success tx output
 		claim_tx.input.clear();
@@ -2773,7 +3176,7 @@ mod tests {
 		for (idx, inp) in claim_tx.input.iter_mut().zip(inputs_des.iter()).enumerate() {
 			sign_input!(sighash_parts, inp.0, idx as u32, 0, inp.1, sum_actual_sigs);
 		}
-		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..]), claim_tx.get_weight() + /* max_length_isg */ (73 * inputs_des.len() - sum_actual_sigs));
+		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..], false), claim_tx.get_weight() + /* max_length_isg */ (73 * inputs_des.len() - sum_actual_sigs));
 	}
 
 	// Further testing is done in the ChannelManager integration tests.
@@ -20,6 +20,8 @@ use std::{cmp, fmt};
 use std::result::Result;
 use std::marker::PhantomData;
 
+use bitcoin::bech32::u5;
+
 use ln::msgs::DecodeError;
 use util::ser::{Readable, Writeable, Writer};
 
@@ -92,14 +94,34 @@ mod sealed {
 			StaticRemoteKey,
 			// Byte 2
 			,
+			// Byte 3
+			,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			,
 		],
 		optional_features: [
 			// Byte 0
-			DataLossProtect | InitialRoutingSync | UpfrontShutdownScript,
+			DataLossProtect | InitialRoutingSync | UpfrontShutdownScript | GossipQueries,
 			// Byte 1
 			VariableLengthOnion | PaymentSecret,
 			// Byte 2
-			BasicMPP,
+			BasicMPP | Wumbo | AnchorOutputs,
+			// Byte 3
+			DualFund,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			Splicing,
 		],
 	});
 	define_context!(NodeContext {
@@ -110,20 +132,58 @@ mod sealed {
 			StaticRemoteKey,
 			// Byte 2
 			,
+			// Byte 3
+			,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			,
 		],
 		optional_features: [
 			// Byte 0
-			DataLossProtect | UpfrontShutdownScript,
+			DataLossProtect | UpfrontShutdownScript | GossipQueries,
 			// Byte 1
 			VariableLengthOnion | PaymentSecret,
 			// Byte 2
-			BasicMPP,
+			BasicMPP | Wumbo | AnchorOutputs,
+			// Byte 3
+			DualFund,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			Splicing,
 		],
 	});
 	define_context!(ChannelContext {
 		required_features: [],
 		optional_features: [],
 	});
+	define_context!(InvoiceContext {
+		required_features: [
+			// Byte 0
+			,
+			// Byte 1
+			,
+			// Byte 2
+			,
+		],
+		optional_features: [
+			// Byte 0
+			,
+			// Byte 1
+			VariableLengthOnion | PaymentSecret,
+			// Byte 2
+			BasicMPP,
+		],
+	});
 
 	/// Defines a feature with the given bits for the specified [`Context`]s. The generated trait is
 	/// useful for manipulating feature flags.
@@ -234,14 +294,24 @@ mod sealed {
 		"Feature flags for `initial_routing_sync`.");
 	define_feature!(5, UpfrontShutdownScript, [InitContext, NodeContext],
 		"Feature flags for `option_upfront_shutdown_script`.");
-	define_feature!(9, VariableLengthOnion, [InitContext, NodeContext],
+	define_feature!(7, GossipQueries, [InitContext, NodeContext],
+		"Feature flags for `gossip_queries`.");
+	define_feature!(9, VariableLengthOnion, [InitContext, NodeContext, InvoiceContext],
 		"Feature flags for `var_onion_optin`.");
 	define_feature!(13, StaticRemoteKey, [InitContext, NodeContext],
 		"Feature flags for `option_static_remotekey`.");
-	define_feature!(15, PaymentSecret, [InitContext, NodeContext],
+	define_feature!(15, PaymentSecret, [InitContext, NodeContext, InvoiceContext],
 		"Feature flags for `payment_secret`.");
-	define_feature!(17, BasicMPP, [InitContext, NodeContext],
+	define_feature!(17, BasicMPP, [InitContext, NodeContext, InvoiceContext],
 		"Feature flags for `basic_mpp`.");
+	define_feature!(19, Wumbo, [InitContext, NodeContext],
+		"Feature flags for `option_support_large_channel` (aka wumbo channels).");
+	define_feature!(21, AnchorOutputs, [InitContext, NodeContext],
+		"Feature flags for `option_anchor_outputs`.");
+	define_feature!(29, DualFund, [InitContext, NodeContext],
+		"Feature flags for `option_dual_fund`.");
+	define_feature!(63, Splicing, [InitContext, NodeContext],
+		"Feature flags for `option_splice`.");
 
 	#[cfg(test)]
 	define_context!(TestingContext {
@@ -301,6 +371,8 @@ pub type InitFeatures = Features<sealed::InitContext>;
 pub type NodeFeatures = Features<sealed::NodeContext>;
 /// Features used within a `channel_announcement` message.
 pub type ChannelFeatures = Features<sealed::ChannelContext>;
+/// Features used within an invoice.
+pub type InvoiceFeatures = Features<sealed::InvoiceContext>;
 
 impl InitFeatures {
 	/// Writes all features present up to, and including, 13.
@@ -337,6 +409,46 @@ impl InitFeatures {
 	}
 }
 
+impl InvoiceFeatures {
+	/// Encodes the features as the big-endian base32 words used in an invoice's feature field,
+	/// omitting any leading zero words.
+	pub(crate) fn to_base32(&self) -> Vec<u5> {
+		let word_count = (self.flags.len() * 8 + 4) / 5;
+		let mut words = Vec::with_capacity(word_count);
+		for word_idx in 0..word_count {
+			let mut word = 0u8;
+			for bit_idx in 0..5 {
+				let bit = word_idx * 5 + bit_idx;
+				if bit / 8 < self.flags.len() && self.flags[bit / 8] & (1 << (bit % 8)) != 0 {
+					word |= 1 << bit_idx;
+				}
+			}
+			words.push(u5::try_from_u8(word).unwrap());
+		}
+		while words.last().map(|word| word.to_u8() == 0).unwrap_or(false) {
+			words.pop();
+		}
+		words.reverse();
+		words
+	}
+
+	/// Decodes features from the big-endian base32 words of an invoice's feature field.
+	pub(crate) fn from_base32(words: &[u5]) -> InvoiceFeatures {
+		let mut flags = vec![0u8; (words.len() * 5 + 7) / 8];
+		for (word_idx, word) in words.iter().rev().enumerate() {
+			for bit_idx in 0..5 {
+				if word.to_u8() & (1 << bit_idx) != 0 {
+					let bit = word_idx * 5 + bit_idx;
+					flags[bit / 8] |= 1 << (bit % 8);
+				}
+			}
+		}
+		let last_non_zero_byte = flags.iter().rposition(|&byte| byte != 0);
+		flags.truncate(if let Some(offset) = last_non_zero_byte { offset + 1 } else { 0 });
+		InvoiceFeatures { flags, mark: PhantomData, }
+	}
+}
+
 impl<T: sealed::Context> Features<T> {
 	/// Create a blank Features with no features set
 	pub fn empty() -> Features<T> {
@@ -462,6 +574,21 @@ impl<T: sealed::UpfrontShutdownScript> Features<T> {
 	}
 }
 
+impl<T: sealed::GossipQueries> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_gossip_queries(&self) -> bool {
+		<T as sealed::GossipQueries>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_gossip_queries(&self) -> bool {
+		<T as sealed::GossipQueries>::supports_feature(&self.flags)
+	}
+	#[cfg(test)]
+	pub(crate) fn clear_gossip_queries(mut self) -> Self {
+		<T as sealed::GossipQueries>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
 impl<T: sealed::VariableLengthOnion> Features<T> {
 	#[cfg(test)]
 	pub(crate) fn requires_variable_length_onion(&self) -> bool {
@@ -517,6 +644,60 @@ impl<T: sealed::BasicMPP> Features<T> {
 	}
 }
 
+impl<T: sealed::Wumbo> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_wumbo(&self) -> bool {
+		<T as sealed::Wumbo>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_wumbo(&self) -> bool {
+		<T as sealed::Wumbo>::supports_feature(&self.flags)
+	}
+	#[cfg(test)]
+	pub(crate) fn clear_wumbo(mut self) -> Self {
+		<T as sealed::Wumbo>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
+impl<T: sealed::AnchorOutputs> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_anchor_outputs(&self) -> bool {
+		<T as sealed::AnchorOutputs>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_anchor_outputs(&self) -> bool {
+		<T as sealed::AnchorOutputs>::supports_feature(&self.flags)
+	}
+	pub(crate) fn clear_anchor_outputs(mut self) -> Self {
+		<T as sealed::AnchorOutputs>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
+impl<T: sealed::DualFund> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_dual_fund(&self) -> bool {
+		<T as sealed::DualFund>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_dual_fund(&self) -> bool {
+		<T as sealed::DualFund>::supports_feature(&self.flags)
+	}
+}
+
+impl<T: sealed::Splicing> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_splicing(&self) -> bool {
+		<T as sealed::Splicing>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_splicing(&self) -> bool {
+		<T as sealed::Splicing>::supports_feature(&self.flags)
+	}
+	#[cfg(test)]
+	pub(crate) fn clear_splicing(mut self) -> Self {
+		<T as sealed::Splicing>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
 impl<T: sealed::Context> Writeable for Features<T> {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
 		w.size_hint(self.flags.len() + 2);
@@ -541,7 +722,9 @@ impl<T: sealed::Context> Readable for Features<T> {
 
 #[cfg(test)]
 mod tests {
-	use super::{ChannelFeatures, InitFeatures, NodeFeatures};
+	use super::{ChannelFeatures, InitFeatures, InvoiceFeatures, NodeFeatures};
+
+	use bitcoin::bech32::u5;
 
 	#[test]
 	fn sanity_test_known_features() {
@@ -562,6 +745,11 @@ mod tests {
 		assert!(!InitFeatures::known().requires_data_loss_protect());
 		assert!(!NodeFeatures::known().requires_data_loss_protect());
 
+		assert!(InitFeatures::known().supports_gossip_queries());
+		assert!(NodeFeatures::known().supports_gossip_queries());
+		assert!(!InitFeatures::known().requires_gossip_queries());
+		assert!(!NodeFeatures::known().requires_gossip_queries());
+
 		assert!(InitFeatures::known().supports_variable_length_onion());
 		assert!(NodeFeatures::known().supports_variable_length_onion());
 		assert!(!InitFeatures::known().requires_variable_length_onion());
@@ -582,6 +770,33 @@ mod tests {
 		assert!(!InitFeatures::known().requires_basic_mpp());
 		assert!(!NodeFeatures::known().requires_basic_mpp());
 
+		assert!(InitFeatures::known().supports_wumbo());
+		assert!(NodeFeatures::known().supports_wumbo());
+		assert!(!InitFeatures::known().requires_wumbo());
+		assert!(!NodeFeatures::known().requires_wumbo());
+
+		assert!(InitFeatures::known().supports_anchor_outputs());
+		assert!(NodeFeatures::known().supports_anchor_outputs());
+		assert!(!InitFeatures::known().requires_anchor_outputs());
+		assert!(!NodeFeatures::known().requires_anchor_outputs());
+		assert!(!InitFeatures::known().clear_anchor_outputs().supports_anchor_outputs());
+
+		assert!(InitFeatures::known().supports_dual_fund());
+		assert!(NodeFeatures::known().supports_dual_fund());
+		assert!(!InitFeatures::known().requires_dual_fund());
+		assert!(!NodeFeatures::known().requires_dual_fund());
+
+		assert!(InitFeatures::known().supports_splicing());
+		assert!(NodeFeatures::known().supports_splicing());
+		assert!(!InitFeatures::known().requires_splicing());
+		assert!(!NodeFeatures::known().requires_splicing());
+		assert!(!InitFeatures::known().clear_splicing().supports_splicing());
+
+		assert!(!InvoiceFeatures::known().requires_unknown_bits());
+		assert!(InvoiceFeatures::known().supports_variable_length_onion());
+		assert!(InvoiceFeatures::known().supports_payment_secret());
+		assert!(InvoiceFeatures::known().supports_basic_mpp());
+
 		let mut init_features = InitFeatures::known();
 		assert!(init_features.initial_routing_sync());
 		init_features.clear_initial_routing_sync();
@@ -616,13 +831,20 @@ mod tests {
 		let node_features: NodeFeatures = init_features.to_context();
 		{
 			// Check that the flags are as expected:
-			// - option_data_loss_protect
+			// - option_data_loss_protect | gossip_queries
 			// - var_onion_optin | static_remote_key (req) | payment_secret
-			// - basic_mpp
-			assert_eq!(node_features.flags.len(), 3);
-			assert_eq!(node_features.flags[0], 0b00000010);
+			// - basic_mpp | wumbo | option_anchor_outputs
+			// - option_dual_fund
+			// - option_splice
+			assert_eq!(node_features.flags.len(), 8);
+			assert_eq!(node_features.flags[0], 0b10000010);
 			assert_eq!(node_features.flags[1], 0b10010010);
-			assert_eq!(node_features.flags[2], 0b00000010);
+			assert_eq!(node_features.flags[2], 0b00101010);
+			assert_eq!(node_features.flags[3], 0b00100000);
+			assert_eq!(node_features.flags[4], 0b00000000);
+			assert_eq!(node_features.flags[5], 0b00000000);
+			assert_eq!(node_features.flags[6], 0b00000000);
+			assert_eq!(node_features.flags[7], 0b10000000);
 		}
 
 		// Check that cleared flags are kept blank when converting back:
@@ -632,4 +854,18 @@ mod tests {
 		assert!(!features.initial_routing_sync());
 		assert!(!features.supports_upfront_shutdown_script());
 	}
+
+	#[test]
+	fn invoice_features_base32_round_trip() {
+		// var_onion_optin (bit 9), payment_secret (bit 15) and basic_mpp (bit 17), all optional.
+		let features = InvoiceFeatures::known();
+		let words: Vec<u8> = features.to_base32().iter().map(|word| word.to_u8()).collect();
+		assert_eq!(words, vec![0b00101, 0, 0b10000, 0]);
+		assert_eq!(InvoiceFeatures::from_base32(&features.to_base32()), features);
+
+		// Leading zero words are ignored when decoding.
+		let padded: Vec<u5> = [0, 0, 0b00101, 0, 0b10000, 0].iter().map(|&word| u5::try_from_u8(word).unwrap()).collect();
+		assert_eq!(InvoiceFeatures::from_base32(&padded), features);
+		assert_eq!(InvoiceFeatures::empty().to_base32(), Vec::new());
+	}
 }
@@ -6,22 +6,24 @@ use chain::transaction::OutPoint;
 use chain::keysinterface::{ChannelKeys, KeysInterface, SpendableOutputDescriptor};
 use chain::chaininterface;
 use chain::chaininterface::{ChainListener, ChainWatchInterfaceUtil, BlockNotifier};
-use ln::channel::{COMMITMENT_TX_BASE_WEIGHT, COMMITMENT_TX_WEIGHT_PER_HTLC};
-use ln::channelmanager::{ChannelManager,ChannelManagerReadArgs,HTLCForwardInfo,RAACommitmentOrder, PaymentPreimage, PaymentHash, PaymentSecret, PaymentSendFailure, BREAKDOWN_TIMEOUT};
-use ln::channelmonitor::{ChannelMonitor, CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS, ManyChannelMonitor, ANTI_REORG_DELAY};
+use ln::channel::{COMMITMENT_TX_BASE_WEIGHT, COMMITMENT_TX_WEIGHT_PER_HTLC, EXPIRE_PREV_CONFIG_TICKS, FUNDING_CONF_DEADLINE_BLOCKS};
+use ln::channelmanager::{ChannelManager,ChannelManagerReadArgs,HTLCForwardInfo,RAACommitmentOrder, PaymentPreimage, PaymentHash, PaymentSecret, PaymentSendFailure, BREAKDOWN_TIMEOUT, MIN_CLTV_EXPIRY_DELTA};
+use ln::channelmonitor::{ChannelMonitor, CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS, ManyChannelMonitor, ANTI_REORG_DELAY, HTLC_FAIL_BACK_BUFFER};
 use ln::channelmonitor;
 use ln::channel::{Channel, ChannelError};
 use ln::{chan_utils, onion_utils};
+use ln::payment_retry::PaymentRetryManager;
+use ln::interactivetxs::{FundingContribution, FundingInput};
 use routing::router::{Route, RouteHop, get_route};
 use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
 use ln::msgs;
 use ln::msgs::{ChannelMessageHandler,RoutingMessageHandler,HTLCFailChannelUpdate, ErrorAction};
 use util::enforcing_trait_impls::EnforcingChannelKeys;
 use util::{byte_utils, test_utils};
-use util::events::{Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
-use util::errors::APIError;
+use util::events::{ClosureReason, Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
+use util::errors::{APIError, OnionFailureCode};
 use util::ser::{Writeable, Writer, ReadableArgs, Readable};
-use util::config::UserConfig;
+use util::config::{UserConfig, ChannelConfig};
 
 use bitcoin::util::hash::BitcoinHash;
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
@@ -87,11 +89,11 @@ fn test_insane_channel_opens() {
 		} else { assert!(false); }
 	};
 
-	use ln::channel::MAX_FUNDING_SATOSHIS;
+	use ln::channel::TOTAL_BITCOIN_SUPPLY_SATOSHIS;
 	use ln::channelmanager::MAX_LOCAL_BREAKDOWN_TIMEOUT;
 
 	// Test all mutations that would make the channel open message insane
-	insane_open_helper("funding value > 2^24", |mut msg| { msg.funding_satoshis = MAX_FUNDING_SATOSHIS; msg });
+	insane_open_helper("funding value > total bitcoin supply", |mut msg| { msg.funding_satoshis = TOTAL_BITCOIN_SUPPLY_SATOSHIS + 1; msg });
 
 	insane_open_helper("Bogus channel_reserve_satoshis", |mut msg| { msg.channel_reserve_satoshis = msg.funding_satoshis + 1; msg });
 
@@ -110,6 +112,73 @@ fn test_insane_channel_opens() {
 	insane_open_helper("max_accpted_htlcs > 483", |mut msg| { msg.max_accepted_htlcs = 484; msg });
 }
 
+#[test]
+fn test_large_channel_negotiation() {
+	// Channels of 2^24 satoshis or more require both peers to support option_support_large_channel
+	// and the recipient to have raised its max_funding_satoshis limit.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let mut large_channel_config = UserConfig::default();
+	large_channel_config.channel_options.announced_channel = true;
+	large_channel_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	large_channel_config.peer_channel_config_limits.max_funding_satoshis = 1 << 24;
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, Some(large_channel_config)]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let channel_value_sat = 1 << 24;
+
+	// We can't open a large channel to a peer which hasn't told us it supports them
+	match nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), channel_value_sat, 0, 42, None) {
+		Err(APIError::APIMisuseError { err }) => assert_eq!(err, "funding value > 2^24 - 1 and peer does not support large channels"),
+		_ => panic!("Unexpected result"),
+	}
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), channel_value_sat, 0, 42, None).unwrap();
+	let open_channel_message = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+
+	let expect_open_channel_error = |node: &Node, their_features: InitFeatures, expected_error_str: &str| {
+		node.node.handle_open_channel(&nodes[0].node.get_our_node_id(), their_features, &open_channel_message);
+		let msg_events = node.node.get_and_clear_pending_msg_events();
+		assert_eq!(msg_events.len(), 1);
+		if let MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { .. }, .. } = msg_events[0] {
+			node.logger.assert_log("lightning::ln::channelmanager".to_string(), expected_error_str.to_string(), 1);
+		} else { panic!("Unexpected event"); }
+	};
+
+	// Large channels must be negotiated and within the recipient's configured limit
+	expect_open_channel_error(&nodes[1], InitFeatures::known(), "funding satoshis is greater than the user specified limit");
+	expect_open_channel_error(&nodes[2], InitFeatures::known().clear_wumbo(), "funding value > 2^24 - 1 and large channels were not negotiated");
+
+	nodes[2].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel_message);
+	let accept_channel_message = get_event_msg!(nodes[2], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
+	assert_eq!(accept_channel_message.temporary_channel_id, open_channel_message.temporary_channel_id);
+}
+
+#[test]
+fn test_channel_details_available_balances() {
+	// The inbound and outbound capacity in ChannelDetails used to be clamped with cmp::min rather
+	// than cmp::max, always reporting no available balance in either direction.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 10001, InitFeatures::known(), InitFeatures::known());
+
+	let check_balances = |outbound_msat: u64, inbound_msat: u64| {
+		let node_0_chans = nodes[0].node.list_channels();
+		assert_eq!(node_0_chans.len(), 1);
+		assert_eq!(node_0_chans[0].outbound_capacity_msat, outbound_msat);
+		assert_eq!(node_0_chans[0].inbound_capacity_msat, inbound_msat);
+	};
+	check_balances(100000000 - 10001, 10001);
+
+	// Pending outbound HTLCs are not available until they are resolved.
+	let (payment_preimage, _) = route_payment(&nodes[0], &[&nodes[1]], 1000000);
+	check_balances(100000000 - 10001 - 1000000, 10001);
+
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 1000000);
+	check_balances(100000000 - 10001 - 1000000, 10001 + 1000000);
+}
+
 #[test]
 fn test_async_inbound_update_fee() {
 	let chanmon_cfgs = create_chanmon_cfgs(2);
@@ -118,6 +187,7 @@ fn test_async_inbound_update_fee() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 	let channel_id = chan.2;
 
 	// balancing
@@ -158,7 +228,7 @@ fn test_async_inbound_update_fee() {
 	// ...but before it's delivered, nodes[1] starts to send a payment back to nodes[0]...
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger).unwrap(), our_payment_hash, &None).unwrap();
+	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger, &scorer).unwrap(), our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[1], 1);
 
 	let payment_event = {
@@ -234,6 +304,7 @@ fn test_update_fee_unordered_raa() {
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let channel_id = chan.2;
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	// balancing
 	send_payment(&nodes[0], &vec!(&nodes[1])[..], 8000000, 8_000_000);
@@ -256,7 +327,7 @@ fn test_update_fee_unordered_raa() {
 	// ...but before it's delivered, nodes[1] starts to send a payment back to nodes[0]...
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger).unwrap(), our_payment_hash, &None).unwrap();
+	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger, &scorer).unwrap(), our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[1], 1);
 
 	let payment_event = {
@@ -606,6 +677,7 @@ fn test_update_fee_that_funder_cannot_afford() {
 	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Funding remote cannot afford proposed new fee".to_string(), 1);
 	check_added_monitors!(nodes[1], 1);
 	check_closed_broadcast!(nodes[1], true);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Funding remote cannot afford proposed new fee".to_string() });
 }
 
 #[test]
@@ -617,6 +689,7 @@ fn test_update_fee_with_fundee_update_add_htlc() {
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let channel_id = chan.2;
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	// balancing
 	send_payment(&nodes[0], &vec!(&nodes[1])[..], 8000000, 8_000_000);
@@ -640,7 +713,7 @@ fn test_update_fee_with_fundee_update_add_htlc() {
 
 	let (our_payment_preimage, our_payment_hash) = get_payment_preimage_hash!(nodes[1]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 800000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 800000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 
 	// nothing happens since node[1] is in AwaitingRemoteRevoke
 	nodes[1].node.send_payment(&route, our_payment_hash, &None).unwrap();
@@ -836,6 +909,8 @@ fn pre_funding_lock_shutdown_test() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	assert!(nodes[1].node.list_channels().is_empty());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 }
 
 #[test]
@@ -848,6 +923,7 @@ fn updates_shutdown_wait() {
 	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (our_payment_preimage, _) = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 100000);
 
@@ -864,8 +940,8 @@ fn updates_shutdown_wait() {
 
 	let net_graph_msg_handler0 = &nodes[0].net_graph_msg_handler;
 	let net_graph_msg_handler1 = &nodes[1].net_graph_msg_handler;
-	let route_1 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler0.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-	let route_2 = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler1.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route_1 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler0.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let route_2 = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler1.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	unwrap_send_err!(nodes[0].node.send_payment(&route_1, payment_hash, &None), true, APIError::ChannelUnavailable {..}, {});
 	unwrap_send_err!(nodes[1].node.send_payment(&route_2, payment_hash, &None), true, APIError::ChannelUnavailable {..}, {});
 
@@ -878,6 +954,7 @@ fn updates_shutdown_wait() {
 	assert!(updates.update_fee.is_none());
 	assert_eq!(updates.update_fulfill_htlcs.len(), 1);
 	nodes[1].node.handle_update_fulfill_htlc(&nodes[2].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	expect_payment_forwarded!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
 	let updates_2 = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
 	commitment_signed_dance!(nodes[1], nodes[2], updates.commitment_signed, false);
@@ -893,7 +970,7 @@ fn updates_shutdown_wait() {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match events[0] {
-		Event::PaymentSent { ref payment_preimage } => {
+		Event::PaymentSent { ref payment_preimage, .. } => {
 			assert_eq!(our_payment_preimage, *payment_preimage);
 		},
 		_ => panic!("Unexpected event"),
@@ -905,6 +982,8 @@ fn updates_shutdown_wait() {
 	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 
 	assert!(nodes[0].node.list_channels().is_empty());
 
@@ -925,10 +1004,11 @@ fn htlc_fail_async_shutdown() {
 	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -983,6 +1063,8 @@ fn htlc_fail_async_shutdown() {
 	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 
 	assert!(nodes[0].node.list_channels().is_empty());
 
@@ -1051,6 +1133,7 @@ fn do_test_shutdown_rebroadcast(recv_count: u8) {
 	assert!(updates.update_fee.is_none());
 	assert_eq!(updates.update_fulfill_htlcs.len(), 1);
 	nodes[1].node.handle_update_fulfill_htlc(&nodes[2].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	expect_payment_forwarded!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
 	let updates_2 = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
 	commitment_signed_dance!(nodes[1], nodes[2], updates.commitment_signed, false);
@@ -1066,7 +1149,7 @@ fn do_test_shutdown_rebroadcast(recv_count: u8) {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match events[0] {
-		Event::PaymentSent { ref payment_preimage } => {
+		Event::PaymentSent { ref payment_preimage, .. } => {
 			assert_eq!(our_payment_preimage, *payment_preimage);
 		},
 		_ => panic!("Unexpected event"),
@@ -1109,6 +1192,7 @@ fn do_test_shutdown_rebroadcast(recv_count: u8) {
 		nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 		let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 		assert!(node_0_none.is_none());
+		check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
 	} else {
 		// If one node, however, received + responded with an identical closing_signed we end
 		// up erroring and node[0] will try to broadcast its own latest commitment transaction.
@@ -1137,7 +1221,9 @@ fn do_test_shutdown_rebroadcast(recv_count: u8) {
 		// closing_signed so we do it ourselves
 		check_closed_broadcast!(nodes[0], false);
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CounterpartyForceClosed { peer_msg: "Failed to find corresponding channel".to_string() });
 	}
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 
 	assert!(nodes[0].node.list_channels().is_empty());
 
@@ -1298,12 +1384,13 @@ fn holding_cell_htlc_counting() {
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let mut payments = Vec::new();
 	for _ in 0..::ln::channel::OUR_MAX_HTLCS {
 		let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[1].node.send_payment(&route, payment_hash, &None).unwrap();
 		payments.push((payment_preimage, payment_hash));
 	}
@@ -1320,7 +1407,7 @@ fn holding_cell_htlc_counting() {
 	let (_, payment_hash_1) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		unwrap_send_err!(nodes[1].node.send_payment(&route, payment_hash_1, &None), true, APIError::ChannelUnavailable { err },
 			assert_eq!(err, "Cannot push more than their max accepted HTLCs"));
 		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
@@ -1331,7 +1418,7 @@ fn holding_cell_htlc_counting() {
 	let (_, payment_hash_2) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[0].node.send_payment(&route, payment_hash_2, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 	}
@@ -1457,6 +1544,7 @@ fn test_duplicate_htlc_different_direction_onchain() {
 
 	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	// balancing
 	send_payment(&nodes[0], &vec!(&nodes[1])[..], 8000000, 8_000_000);
@@ -1464,7 +1552,7 @@ fn test_duplicate_htlc_different_direction_onchain() {
 	let (payment_preimage, payment_hash) = route_payment(&nodes[0], &vec!(&nodes[1])[..], 900_000);
 
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 800_000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 800_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	send_along_route_with_hash(&nodes[1], route, &vec!(&nodes[0])[..], 800_000, payment_hash);
 
 	// Provide preimage to node 0 by claiming payment
@@ -1488,6 +1576,7 @@ fn test_duplicate_htlc_different_direction_onchain() {
 	let header = BlockHeader { version: 0x2000_0000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![remote_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 
 	// Check we only broadcast 1 timeout tx
 	let claim_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clone();
@@ -1527,6 +1616,7 @@ fn test_basic_channel_reserve() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let chan_stat = get_channel_value_stat!(nodes[0], chan.2);
 	let channel_reserve = chan_stat.channel_reserve_msat;
@@ -1536,7 +1626,7 @@ fn test_basic_channel_reserve() {
 	let commit_tx_fee = 2 * commit_tx_fee_msat(get_feerate!(nodes[0], chan.2), 1 + 1);
 	let max_can_send = 5000000 - channel_reserve - commit_tx_fee;
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), max_can_send + 1, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), max_can_send + 1, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	let err = nodes[0].node.send_payment(&route, our_payment_hash, &None).err().unwrap();
 	match err {
 		PaymentSendFailure::AllFailedRetrySafe(ref fails) => {
@@ -1562,12 +1652,13 @@ fn test_fee_spike_violation_fails_htlc() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	macro_rules! get_route_and_payment_hash {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[1]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[0].node.get_our_node_id(), net_graph_msg_handler, &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1580,7 +1671,7 @@ fn test_fee_spike_violation_fails_htlc() {
 	let cur_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 
 	let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route.paths[0], &session_priv).unwrap();
-	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3460001, &None, cur_height).unwrap();
+	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3460001, &None, &None, cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &payment_hash);
 	let msg = msgs::UpdateAddHTLC {
 		channel_id: chan.2,
@@ -1653,7 +1744,7 @@ fn test_fee_spike_violation_fails_htlc() {
 	};
 
 	let htlc_output = TxOut {
-		script_pubkey: chan_utils::get_htlc_redeemscript(&accepted_htlc_info, &commit_tx_keys).to_v0_p2wsh(),
+		script_pubkey: chan_utils::get_htlc_redeemscript(&accepted_htlc_info, &commit_tx_keys, false).to_v0_p2wsh(),
 		value: 3460001 / 1000
 	};
 
@@ -1692,13 +1783,14 @@ fn test_fee_spike_violation_fails_htlc() {
 		let local_chan = local_chan_lock.by_id.get(&chan.2).unwrap();
 		let local_chan_keys = local_chan.get_local_keys();
 		local_chan_keys.sign_remote_commitment(feerate_per_kw, &commit_tx, &commit_tx_keys, &[&accepted_htlc_info],
-		BREAKDOWN_TIMEOUT, &secp_ctx).unwrap()
+		BREAKDOWN_TIMEOUT, false, &secp_ctx).unwrap()
 	};
 
 	let commit_signed_msg = msgs::CommitmentSigned {
 		channel_id: chan.2,
 		signature: res.0,
-		htlc_signatures: res.1
+		htlc_signatures: res.1,
+		spliced_signatures: msgs::OptionalField::Absent,
 	};
 
 	// Send the commitment_signed message to the nodes[1].
@@ -1740,12 +1832,13 @@ fn test_chan_reserve_violation_outbound_htlc_inbound_chan() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let _ = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	macro_rules! get_route_and_payment_hash {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[1]);
 			let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1772,12 +1865,13 @@ fn test_chan_reserve_violation_inbound_htlc_outbound_channel() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	macro_rules! get_route_and_payment_hash {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[1]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1788,7 +1882,7 @@ fn test_chan_reserve_violation_inbound_htlc_outbound_channel() {
 	let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 	let cur_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 	let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route.paths[0], &session_priv).unwrap();
-	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 1000, &None, cur_height).unwrap();
+	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 1000, &None, &None, cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &payment_hash);
 	let msg = msgs::UpdateAddHTLC {
 		channel_id: chan.2,
@@ -1804,6 +1898,7 @@ fn test_chan_reserve_violation_inbound_htlc_outbound_channel() {
 	nodes[0].logger.assert_log("lightning::ln::channelmanager".to_string(), "Cannot receive value that would put us under local channel reserve value".to_string(), 1);
 	assert_eq!(nodes[0].node.list_channels().len(), 0);
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Cannot receive value that would put us under local channel reserve value".to_string() });
 	assert_eq!(err_msg.data, "Cannot receive value that would put us under local channel reserve value");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -1817,12 +1912,13 @@ fn test_chan_reserve_violation_inbound_htlc_inbound_chan() {
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let _ = create_announced_chan_between_nodes_with_value(&nodes, 1, 2, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	macro_rules! get_route_and_payment_hash {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1860,7 +1956,7 @@ fn test_chan_reserve_violation_inbound_htlc_inbound_chan() {
 	let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 	let cur_height = nodes[0].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 	let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route_2.paths[0], &session_priv).unwrap();
-	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route_2.paths[0], recv_value_2, &None, cur_height).unwrap();
+	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route_2.paths[0], recv_value_2, &None, &None, cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &our_payment_hash_1);
 	let msg = msgs::UpdateAddHTLC {
 		channel_id: chan.2,
@@ -1876,6 +1972,7 @@ fn test_chan_reserve_violation_inbound_htlc_inbound_chan() {
 	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Remote HTLC add would put them under remote reserve value".to_string(), 1);
 	assert_eq!(nodes[1].node.list_channels().len(), 1);
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote HTLC add would put them under remote reserve value".to_string() });
 	assert_eq!(err_msg.data, "Remote HTLC add would put them under remote reserve value");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -1893,6 +1990,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 	let chan_1 = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 190000, 1001, InitFeatures::known(), InitFeatures::known());
 	let chan_2 = create_announced_chan_between_nodes_with_value(&nodes, 1, 2, 190000, 1001, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let mut stat01 = get_channel_value_stat!(nodes[0], chan_1.2);
 	let mut stat11 = get_channel_value_stat!(nodes[1], chan_1.2);
@@ -1904,7 +2002,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -2078,7 +2176,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 	let events = nodes[2].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 2);
 	match events[0] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt, .. } => {
 			assert_eq!(our_payment_hash_21, *payment_hash);
 			assert_eq!(*payment_secret, None);
 			assert_eq!(recv_value_21, amt);
@@ -2086,7 +2184,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 		_ => panic!("Unexpected event"),
 	}
 	match events[1] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt, .. } => {
 			assert_eq!(our_payment_hash_22, *payment_hash);
 			assert_eq!(None, *payment_secret);
 			assert_eq!(recv_value_22, amt);
@@ -2159,6 +2257,7 @@ fn channel_reserve_in_flight_removes() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let b_chan_values = get_channel_value_stat!(nodes[1], chan_1.2);
 	// Route the first two HTLCs.
@@ -2169,7 +2268,7 @@ fn channel_reserve_in_flight_removes() {
 	let (payment_preimage_3, payment_hash_3) = get_payment_preimage_hash!(nodes[0]);
 	let send_1 = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[0].node.send_payment(&route, payment_hash_3, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
@@ -2242,7 +2341,7 @@ fn channel_reserve_in_flight_removes() {
 	let (payment_preimage_4, payment_hash_4) = get_payment_preimage_hash!(nodes[1]);
 	let send_2 = {
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &[], 10000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &[], 10000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[1].node.send_payment(&route, payment_hash_4, &None).unwrap();
 		check_added_monitors!(nodes[1], 1);
 		let mut events = nodes[1].node.get_and_clear_pending_msg_events();
@@ -2314,6 +2413,8 @@ fn channel_monitor_network_test() {
 		test_txn_broadcast(&nodes[0], &chan_1, None, HTLCType::NONE);
 	}
 	get_announce_close_broadcast_events(&nodes, 0, 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
+	check_closed_event!(nodes[1], 1, ClosureReason::DisconnectedPeer);
 	assert_eq!(nodes[0].node.list_channels().len(), 0);
 	assert_eq!(nodes[1].node.list_channels().len(), 1);
 
@@ -2331,6 +2432,8 @@ fn channel_monitor_network_test() {
 		test_txn_broadcast(&nodes[2], &chan_2, None, HTLCType::NONE);
 	}
 	get_announce_close_broadcast_events(&nodes, 1, 2);
+	check_closed_event!(nodes[1], 1, ClosureReason::DisconnectedPeer);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	assert_eq!(nodes[1].node.list_channels().len(), 0);
 	assert_eq!(nodes[2].node.list_channels().len(), 1);
 
@@ -2373,6 +2476,8 @@ fn channel_monitor_network_test() {
 		check_preimage_claim(&nodes[3], &node_txn);
 	}
 	get_announce_close_broadcast_events(&nodes, 2, 3);
+	check_closed_event!(nodes[2], 1, ClosureReason::DisconnectedPeer);
+	check_closed_event!(nodes[3], 1, ClosureReason::CommitmentTxConfirmed);
 	assert_eq!(nodes[2].node.list_channels().len(), 0);
 	assert_eq!(nodes[3].node.list_channels().len(), 1);
 
@@ -2429,6 +2534,8 @@ fn channel_monitor_network_test() {
 		check_preimage_claim(&nodes[4], &node_txn);
 	}
 	get_announce_close_broadcast_events(&nodes, 3, 4);
+	check_closed_event!(nodes[3], 1, ClosureReason::ProcessingError { err: "Pending HTLC must be resolved on-chain before it expires".to_string() });
+	check_closed_event!(nodes[4], 1, ClosureReason::ProcessingError { err: "Pending HTLC must be resolved on-chain before it expires".to_string() });
 	assert_eq!(nodes[3].node.list_channels().len(), 0);
 	assert_eq!(nodes[4].node.list_channels().len(), 0);
 }
@@ -2479,6 +2586,7 @@ fn test_justice_tx() {
 			node_txn.truncate(1);
 		}
 		check_added_monitors!(nodes[1], 1);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 		test_txn_broadcast(&nodes[1], &chan_5, None, HTLCType::NONE);
 
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
@@ -2486,6 +2594,7 @@ fn test_justice_tx() {
 		let node_txn = test_txn_broadcast(&nodes[0], &chan_5, Some(revoked_local_txn[0].clone()), HTLCType::TIMEOUT);
 		header = BlockHeader { version: 0x20000000, prev_blockhash: header.bitcoin_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		// Broadcast revoked HTLC-timeout on node 1
 		nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![node_txn[1].clone()] }, 1);
 		test_revoked_htlc_claim_txn_broadcast(&nodes[1], node_txn[1].clone(), revoked_local_txn[0].clone());
@@ -2525,12 +2634,14 @@ fn test_justice_tx() {
 			node_txn.swap_remove(0);
 		}
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		test_txn_broadcast(&nodes[0], &chan_6, None, HTLCType::NONE);
 
 		nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 		let node_txn = test_txn_broadcast(&nodes[1], &chan_6, Some(revoked_local_txn[0].clone()), HTLCType::SUCCESS);
 		header = BlockHeader { version: 0x20000000, prev_blockhash: header.bitcoin_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 		check_added_monitors!(nodes[1], 1);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![node_txn[1].clone()] }, 1);
 		test_revoked_htlc_claim_txn_broadcast(&nodes[0], node_txn[1].clone(), revoked_local_txn[0].clone());
 	}
@@ -2560,6 +2671,7 @@ fn revoked_output_claim() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(node_txn.len(), 2); // ChannelMonitor: justice tx against revoked to_local output, ChannelManager: local commitment tx
 
@@ -2569,7 +2681,8 @@ fn revoked_output_claim() {
 	// Inform nodes[0] that a watchtower cheated on its behalf, so it will force-close the chan
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	get_announce_close_broadcast_events(&nodes, 0, 1);
-	check_added_monitors!(nodes[0], 1)
+	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 }
 
 #[test]
@@ -2606,8 +2719,10 @@ fn claim_htlc_outputs_shared_tx() {
 		let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 		check_added_monitors!(nodes[1], 1);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 		connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.bitcoin_hash());
 		expect_payment_failed!(nodes[1], payment_hash_2, true);
 
@@ -2669,9 +2784,21 @@ fn claim_htlc_outputs_single_tx() {
 		let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 200);
 		check_added_monitors!(nodes[0], 1);
+		let events = nodes[0].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 2);
+		match events[0] {
+			Event::ChannelClosed { ref reason, .. } => {
+				assert_eq!(*reason, ClosureReason::CommitmentTxConfirmed);
+			},
+			_ => panic!("Unexpected event"),
+		}
+		match events[1] {
+			Event::PendingHTLCsForwardable { .. } => { },
+			_ => panic!("Unexpected event"),
+		}
 		nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 200);
 		check_added_monitors!(nodes[1], 1);
-		expect_pending_htlcs_forwardable_ignore!(nodes[0]);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 
 		connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 200, true, header.bitcoin_hash());
 		expect_payment_failed!(nodes[1], payment_hash_2, true);
@@ -2764,6 +2891,7 @@ fn test_htlc_on_chain_success() {
 	nodes[2].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[2], false);
 	check_added_monitors!(nodes[2], 1);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 3 (commitment tx, 2*htlc-success tx), ChannelMonitor : 2 (2 * HTLC-Success tx)
 	assert_eq!(node_txn.len(), 5);
 	assert_eq!(node_txn[0], node_txn[3]);
@@ -2794,6 +2922,25 @@ fn test_htlc_on_chain_success() {
 		assert_eq!(added_monitors[1].0.txid, chan_1.3.txid());
 		added_monitors.clear();
 	}
+	let forwarded_events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(forwarded_events.len(), 3);
+	match forwarded_events[0] {
+		Event::ChannelClosed { channel_id, ref reason, .. } => {
+			assert_eq!(channel_id, chan_2.2);
+			assert_eq!(*reason, ClosureReason::CommitmentTxConfirmed);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	for event in forwarded_events[1..].iter() {
+		match event {
+			&Event::PaymentForwarded { ref prev_channel_id, ref next_channel_id, claim_from_onchain_tx, .. } => {
+				assert_eq!(*prev_channel_id, chan_1.2);
+				assert_eq!(*next_channel_id, chan_2.2);
+				assert!(claim_from_onchain_tx);
+			},
+			_ => panic!("Unexpected event"),
+		}
+	}
 	assert_eq!(events.len(), 2);
 	match events[0] {
 		MessageSendEvent::BroadcastChannelUpdate { .. } => {},
@@ -2855,6 +3002,7 @@ fn test_htlc_on_chain_success() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 3 (commitment tx + HTLC-Sucess * 2), ChannelMonitor : 1 (HTLC-Success)
 	assert_eq!(node_txn.len(), 4);
 	check_spends!(node_txn[0], commitment_tx[0]);
@@ -2875,11 +3023,11 @@ fn test_htlc_on_chain_success() {
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
 	let events = nodes[0].node.get_and_clear_pending_events();
-	assert_eq!(events.len(), 2);
+	assert_eq!(events.len(), 3);
 	let mut first_claimed = false;
 	for event in events {
 		match event {
-			Event::PaymentSent { payment_preimage } => {
+			Event::PaymentSent { payment_preimage, .. } => {
 				if payment_preimage == our_payment_preimage {
 					assert!(!first_claimed);
 					first_claimed = true;
@@ -2887,6 +3035,9 @@ fn test_htlc_on_chain_success() {
 					assert_eq!(payment_preimage, our_payment_preimage_2);
 				}
 			},
+			Event::ChannelClosed { reason, .. } => {
+				assert_eq!(reason, ClosureReason::CommitmentTxConfirmed);
+			},
 			_ => panic!("Unexpected event"),
 		}
 	}
@@ -2942,6 +3093,7 @@ fn test_htlc_on_chain_timeout() {
 	nodes[2].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[2], false);
 	check_added_monitors!(nodes[2], 1);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 1 (commitment tx)
 	assert_eq!(node_txn.len(), 1);
 	check_spends!(node_txn[0], chan_2.3);
@@ -2950,6 +3102,7 @@ fn test_htlc_on_chain_timeout() {
 	// Broadcast timeout transaction by B on received output from C's commitment tx on B's chain
 	// Verify that B's ChannelManager is able to detect that HTLC is timeout by its own tx and react backward in consequence
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 200);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let timeout_tx;
 	{
 		let mut node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -2998,6 +3151,7 @@ fn test_htlc_on_chain_timeout() {
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 200);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 2 (commitment tx, HTLC-Timeout tx), ChannelMonitor : 1 timeout tx
 	assert_eq!(node_txn.len(), 3);
 	check_spends!(node_txn[0], commitment_tx[0]);
@@ -3032,6 +3186,7 @@ fn test_simple_commitment_revoked_fail_backward() {
 
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42};
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.bitcoin_hash());
 	check_added_monitors!(nodes[1], 1);
 	check_closed_broadcast!(nodes[1], false);
@@ -3159,7 +3314,8 @@ fn do_test_commitment_revoked_fail_backward_exhaustive(deliver_bs_raa: bool, use
 	let (_, fourth_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = test_utils::TestScorer::new();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[1].node.send_payment(&route, fourth_payment_hash, &None).unwrap();
 	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
 	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
@@ -3189,15 +3345,21 @@ fn do_test_commitment_revoked_fail_backward_exhaustive(deliver_bs_raa: bool, use
 	connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.bitcoin_hash());
 
 	let events = nodes[1].node.get_and_clear_pending_events();
-	assert_eq!(events.len(), if deliver_bs_raa { 1 } else { 2 });
+	assert_eq!(events.len(), if deliver_bs_raa { 2 } else { 3 });
 	match events[0] {
+		Event::ChannelClosed { ref reason, .. } => {
+			assert_eq!(*reason, ClosureReason::CommitmentTxConfirmed);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
 		Event::PaymentFailed { ref payment_hash, .. } => {
 			assert_eq!(*payment_hash, fourth_payment_hash);
 		},
 		_ => panic!("Unexpected event"),
 	}
 	if !deliver_bs_raa {
-		match events[1] {
+		match events[2] {
 			Event::PendingHTLCsForwardable { .. } => { },
 			_ => panic!("Unexpected event"),
 		};
@@ -3300,12 +3462,13 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1_000_000, 500_000_000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	// Alice -> Bob: Route a payment but without Bob sending revoke_and_ack.
 	{
 		let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[0].node.send_payment(&route, payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
@@ -3322,7 +3485,7 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 	let (_, failed_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[0].node.send_payment(&route, failed_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 0);
 
@@ -3337,8 +3500,8 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 		let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 		let current_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger).unwrap();
-		let (onion_payloads, _amount_msat, cltv_expiry) = onion_utils::build_onion_payloads(&route.paths[0], 50_000, &None, current_height).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		let (onion_payloads, _amount_msat, cltv_expiry) = onion_utils::build_onion_payloads(&route.paths[0], 50_000, &None, &None, current_height).unwrap();
 		let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route.paths[0], &session_priv).unwrap();
 		let onion_routing_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &payment_hash);
 
@@ -3354,8 +3517,24 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 		nodes[0].node.handle_update_add_htlc(&nodes[1].node.get_our_node_id(), &update_add_htlc);
 	}
 
-	// Check that Alice fails backward the pending HTLC from the second payment.
-	expect_payment_failed!(nodes[0], failed_payment_hash, true);
+	// Check that Alice closes the channel and fails backward the pending HTLC from the second
+	// payment.
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::ChannelClosed { channel_id, ref reason, .. } => {
+			assert_eq!(channel_id, chan.2);
+			assert_eq!(*reason, ClosureReason::ProcessingError { err: "Remote side tried to send a 0-msat HTLC".to_string() });
+		},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::PaymentFailed { ref payment_hash, rejected_by_dest, .. } => {
+			assert_eq!(*payment_hash, failed_payment_hash);
+			assert!(rejected_by_dest);
+		},
+		_ => panic!("Unexpected event"),
+	}
 	check_closed_broadcast!(nodes[0], true);
 	check_added_monitors!(nodes[0], 1);
 }
@@ -3373,6 +3552,7 @@ fn test_htlc_ignore_latest_remote_commitment() {
 	route_payment(&nodes[0], &[&nodes[1]], 10000000);
 	nodes[0].node.force_close_channel(&nodes[0].node.list_channels()[0].channel_id);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
 	check_added_monitors!(nodes[0], 1);
 
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -3381,6 +3561,7 @@ fn test_htlc_ignore_latest_remote_commitment() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![node_txn[0].clone(), node_txn[1].clone()]}, 1);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	// Duplicate the block_connected call since this may happen due to other listeners
@@ -3398,12 +3579,13 @@ fn test_force_close_fail_back() {
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (our_payment_preimage, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 
 	let mut payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 1000000, 42, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 1000000, 42, &logger, &scorer).unwrap();
 		nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
@@ -3434,6 +3616,7 @@ fn test_force_close_fail_back() {
 
 	nodes[2].node.force_close_channel(&payment_event.commitment_msg.channel_id);
 	check_closed_broadcast!(nodes[2], false);
+	check_closed_event!(nodes[2], 1, ClosureReason::HolderForceClosed);
 	check_added_monitors!(nodes[2], 1);
 	let tx = {
 		let mut node_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -3449,6 +3632,7 @@ fn test_force_close_fail_back() {
 
 	// Note no UpdateHTLCs event here from nodes[1] to nodes[0]!
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	// Now check that if we add the preimage to ChannelMonitor it broadcasts our HTLC-Success..
@@ -3496,6 +3680,7 @@ fn test_unconf_chan() {
 	}
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Funding transaction was un-confirmed".to_string() });
 	let channel_state = nodes[0].node.channel_state.lock().unwrap();
 	assert_eq!(channel_state.by_id.len(), 0);
 	assert_eq!(channel_state.short_to_id.len(), 0);
@@ -3540,7 +3725,7 @@ fn test_simple_peer_disconnect() {
 		let events = nodes[0].node.get_and_clear_pending_events();
 		assert_eq!(events.len(), 2);
 		match events[0] {
-			Event::PaymentSent { payment_preimage } => {
+			Event::PaymentSent { payment_preimage, .. } => {
 				assert_eq!(payment_preimage, payment_preimage_3);
 			},
 			_ => panic!("Unexpected event"),
@@ -3574,9 +3759,10 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 	let (payment_preimage_1, payment_hash_1) = get_payment_preimage_hash!(nodes[0]);
 
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 	let payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), Some(&nodes[0].node.list_usable_channels()), &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), Some(&nodes[0].node.list_usable_channels()), &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[0].node.send_payment(&route, payment_hash_1, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
@@ -3652,7 +3838,7 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 	let events_2 = nodes[1].node.get_and_clear_pending_events();
 	assert_eq!(events_2.len(), 1);
 	match events_2[0] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt, .. } => {
 			assert_eq!(payment_hash_1, *payment_hash);
 			assert_eq!(*payment_secret, None);
 			assert_eq!(amt, 1000000);
@@ -3684,7 +3870,7 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 		let events_4 = nodes[0].node.get_and_clear_pending_events();
 		assert_eq!(events_4.len(), 1);
 		match events_4[0] {
-			Event::PaymentSent { ref payment_preimage } => {
+			Event::PaymentSent { ref payment_preimage, .. } => {
 				assert_eq!(payment_preimage_1, *payment_preimage);
 			},
 			_ => panic!("Unexpected event"),
@@ -3725,7 +3911,7 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 			let events_4 = nodes[0].node.get_and_clear_pending_events();
 			assert_eq!(events_4.len(), 1);
 			match events_4[0] {
-				Event::PaymentSent { ref payment_preimage } => {
+				Event::PaymentSent { ref payment_preimage, .. } => {
 					assert_eq!(payment_preimage_1, *payment_preimage);
 				},
 				_ => panic!("Unexpected event"),
@@ -3751,7 +3937,7 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 
 	// Channel should still work fine...
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), Some(&nodes[0].node.list_usable_channels()), &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), Some(&nodes[0].node.list_usable_channels()), &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	let payment_preimage_2 = send_along_route(&nodes[0], route, &[&nodes[1]], 1000000).0;
 	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage_2, 1_000_000);
 }
@@ -3852,7 +4038,8 @@ fn test_funding_peer_disconnect() {
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = test_utils::TestScorer::new();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	let (payment_preimage, _) = send_along_route(&nodes[0], route, &[&nodes[1]], 1000000);
 	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 1_000_000);
 }
@@ -3867,13 +4054,14 @@ fn test_drop_messages_peer_disconnect_dual_htlc() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (payment_preimage_1, _) = route_payment(&nodes[0], &[&nodes[1]], 1000000);
 
 	// Now try to send a second payment which will fail to send
 	let (payment_preimage_2, payment_hash_2) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, payment_hash_2, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 
@@ -3902,7 +4090,7 @@ fn test_drop_messages_peer_disconnect_dual_htlc() {
 			let events_3 = nodes[0].node.get_and_clear_pending_events();
 			assert_eq!(events_3.len(), 1);
 			match events_3[0] {
-				Event::PaymentSent { ref payment_preimage } => {
+				Event::PaymentSent { ref payment_preimage, .. } => {
 					assert_eq!(*payment_preimage, payment_preimage_1);
 				},
 				_ => panic!("Unexpected event"),
@@ -3987,7 +4175,7 @@ fn test_drop_messages_peer_disconnect_dual_htlc() {
 	let events_5 = nodes[1].node.get_and_clear_pending_events();
 	assert_eq!(events_5.len(), 1);
 	match events_5[0] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt: _ } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt: _, .. } => {
 			assert_eq!(payment_hash_2, *payment_hash);
 			assert_eq!(*payment_secret, None);
 		},
@@ -4011,15 +4199,16 @@ fn do_test_htlc_timeout(send_partial_mpp: bool) {
 
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let our_payment_hash = if send_partial_mpp {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		let (_, our_payment_hash) = get_payment_preimage_hash!(&nodes[0]);
 		let payment_secret = PaymentSecret([0xdb; 32]);
 		// Use the utility function send_payment_along_path to send the payment with MPP data which
 		// indicates there are more HTLCs coming.
-		nodes[0].node.send_payment_along_path(&route.paths[0], &our_payment_hash, &Some(payment_secret), 200000, CHAN_CONFIRM_DEPTH).unwrap();
+		nodes[0].node.send_payment_along_path(&route.paths[0], &our_payment_hash, &Some(payment_secret), &None, 200000, CHAN_CONFIRM_DEPTH).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
 		assert_eq!(events.len(), 1);
@@ -4072,12 +4261,13 @@ fn do_test_holding_cell_htlc_add_timeouts(forwarded_htlc: bool) {
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	// Route a first payment to get the 1 -> 2 channel in awaiting_raa...
 	let (_, first_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[1].node.send_payment(&route, first_payment_hash, &None).unwrap();
 	}
 	assert_eq!(nodes[1].node.get_and_clear_pending_msg_events().len(), 1);
@@ -4087,7 +4277,7 @@ fn do_test_holding_cell_htlc_add_timeouts(forwarded_htlc: bool) {
 	let (_, second_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	if forwarded_htlc {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[0].node.send_payment(&route, second_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let payment_event = SendEvent::from_event(nodes[0].node.get_and_clear_pending_msg_events().remove(0));
@@ -4097,7 +4287,7 @@ fn do_test_holding_cell_htlc_add_timeouts(forwarded_htlc: bool) {
 		check_added_monitors!(nodes[1], 0);
 	} else {
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[1].node.send_payment(&route, second_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[1], 0);
 	}
@@ -4257,7 +4447,7 @@ fn test_no_txn_manager_serialize_deserialize() {
 	keys_manager = test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet);
 	let (_, nodes_0_deserialized_tmp) = {
 		let mut channel_monitors = HashMap::new();
-		channel_monitors.insert(chan_0_monitor.get_funding_txo().0, &mut chan_0_monitor);
+		channel_monitors.insert(chan_0_monitor.get_original_funding_txo(), &mut chan_0_monitor);
 		<(BlockHash, ChannelManager<EnforcingChannelKeys, &test_utils::TestChannelMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestLogger>)>::read(&mut nodes_0_read, ChannelManagerReadArgs {
 			default_config: config,
 			keys_manager: &keys_manager,
@@ -4271,7 +4461,7 @@ fn test_no_txn_manager_serialize_deserialize() {
 	nodes_0_deserialized = nodes_0_deserialized_tmp;
 	assert!(nodes_0_read.is_empty());
 
-	assert!(nodes[0].chan_monitor.add_monitor(chan_0_monitor.get_funding_txo().0, chan_0_monitor).is_ok());
+	assert!(nodes[0].chan_monitor.add_monitor(chan_0_monitor.get_original_funding_txo(), chan_0_monitor).is_ok());
 	nodes[0].node = &nodes_0_deserialized;
 	nodes[0].block_notifier.register_listener(nodes[0].node);
 	assert_eq!(nodes[0].node.list_channels().len(), 1);
@@ -4365,7 +4555,7 @@ fn test_manager_serialize_deserialize_events() {
 	keys_manager = test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet);
 	let (_, nodes_0_deserialized_tmp) = {
 		let mut channel_monitors = HashMap::new();
-		channel_monitors.insert(chan_0_monitor.get_funding_txo().0, &mut chan_0_monitor);
+		channel_monitors.insert(chan_0_monitor.get_original_funding_txo(), &mut chan_0_monitor);
 		<(BlockHash, ChannelManager<EnforcingChannelKeys, &test_utils::TestChannelMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestLogger>)>::read(&mut nodes_0_read, ChannelManagerReadArgs {
 			default_config: config,
 			keys_manager: &keys_manager,
@@ -4381,7 +4571,7 @@ fn test_manager_serialize_deserialize_events() {
 
 	nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id(), false);
 
-	assert!(nodes[0].chan_monitor.add_monitor(chan_0_monitor.get_funding_txo().0, chan_0_monitor).is_ok());
+	assert!(nodes[0].chan_monitor.add_monitor(chan_0_monitor.get_original_funding_txo(), chan_0_monitor).is_ok());
 	nodes[0].node = &nodes_0_deserialized;
 
 	// After deserializing, make sure the FundingBroadcastSafe event is still held by the channel manager
@@ -4455,7 +4645,7 @@ fn test_simple_manager_serialize_deserialize() {
 	keys_manager = test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet);
 	let (_, nodes_0_deserialized_tmp) = {
 		let mut channel_monitors = HashMap::new();
-		channel_monitors.insert(chan_0_monitor.get_funding_txo().0, &mut chan_0_monitor);
+		channel_monitors.insert(chan_0_monitor.get_original_funding_txo(), &mut chan_0_monitor);
 		<(BlockHash, ChannelManager<EnforcingChannelKeys, &test_utils::TestChannelMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestLogger>)>::read(&mut nodes_0_read, ChannelManagerReadArgs {
 			default_config: UserConfig::default(),
 			keys_manager: &keys_manager,
@@ -4469,7 +4659,7 @@ fn test_simple_manager_serialize_deserialize() {
 	nodes_0_deserialized = nodes_0_deserialized_tmp;
 	assert!(nodes_0_read.is_empty());
 
-	assert!(nodes[0].chan_monitor.add_monitor(chan_0_monitor.get_funding_txo().0, chan_0_monitor).is_ok());
+	assert!(nodes[0].chan_monitor.add_monitor(chan_0_monitor.get_original_funding_txo(), chan_0_monitor).is_ok());
 	nodes[0].node = &nodes_0_deserialized;
 	check_added_monitors!(nodes[0], 1);
 
@@ -4553,7 +4743,7 @@ fn test_manager_serialize_deserialize_inconsistent_monitor() {
 		monitor: nodes[0].chan_monitor,
 		tx_broadcaster: nodes[0].tx_broadcaster.clone(),
 		logger: &logger,
-		channel_monitors: &mut node_0_stale_monitors.iter_mut().map(|monitor| { (monitor.get_funding_txo().0, monitor) }).collect(),
+		channel_monitors: &mut node_0_stale_monitors.iter_mut().map(|monitor| { (monitor.get_original_funding_txo(), monitor) }).collect(),
 	}) { } else {
 		panic!("If the monitor(s) are stale, this indicates a bug and we should get an Err return");
 	};
@@ -4567,7 +4757,7 @@ fn test_manager_serialize_deserialize_inconsistent_monitor() {
 		monitor: nodes[0].chan_monitor,
 		tx_broadcaster: nodes[0].tx_broadcaster.clone(),
 		logger: &logger,
-		channel_monitors: &mut node_0_monitors.iter_mut().map(|monitor| { (monitor.get_funding_txo().0, monitor) }).collect(),
+		channel_monitors: &mut node_0_monitors.iter_mut().map(|monitor| { (monitor.get_original_funding_txo(), monitor) }).collect(),
 	}).unwrap();
 	nodes_0_deserialized = nodes_0_deserialized_tmp;
 	assert!(nodes_0_read.is_empty());
@@ -4580,10 +4770,11 @@ fn test_manager_serialize_deserialize_inconsistent_monitor() {
 	}
 
 	for monitor in node_0_monitors.drain(..) {
-		assert!(nodes[0].chan_monitor.add_monitor(monitor.get_funding_txo().0, monitor).is_ok());
+		assert!(nodes[0].chan_monitor.add_monitor(monitor.get_original_funding_txo(), monitor).is_ok());
 		check_added_monitors!(nodes[0], 1);
 	}
 	nodes[0].node = &nodes_0_deserialized;
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "ChannelManager is stale compared to its ChannelMonitor".to_string() });
 
 	// nodes[1] and nodes[2] have no lost state with nodes[0]...
 	reconnect_nodes(&nodes[0], &nodes[1], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
@@ -4645,6 +4836,33 @@ macro_rules! check_spendable_outputs {
 									spend_tx.input[0].witness.push(remotepubkey.serialize().to_vec());
 									txn.push(spend_tx);
 								},
+								SpendableOutputDescriptor::StaticOutputRemotePaymentWithAnchors { ref outpoint, ref output, ref key_derivation_params } => {
+									let input = TxIn {
+										previous_output: outpoint.clone(),
+										script_sig: Script::new(),
+										sequence: 1,
+										witness: Vec::new(),
+									};
+									let outp = TxOut {
+										script_pubkey: Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script(),
+										value: output.value,
+									};
+									let mut spend_tx = Transaction {
+										version: 2,
+										lock_time: 0,
+										input: vec![input],
+										output: vec![outp],
+									};
+									let secp_ctx = Secp256k1::new();
+									let keys = $keysinterface.derive_channel_keys($chan_value, key_derivation_params.0, key_derivation_params.1);
+									let witness_script = chan_utils::get_to_countersignatory_with_anchors_redeemscript(&keys.pubkeys().payment_point);
+									let sighash = Message::from_slice(&bip143::SighashComponents::new(&spend_tx).sighash_all(&spend_tx.input[0], &witness_script, output.value)[..]).unwrap();
+									let remotesig = secp_ctx.sign(&sighash, &keys.inner.payment_key);
+									spend_tx.input[0].witness.push(remotesig.serialize_der().to_vec());
+									spend_tx.input[0].witness[0].push(SigHashType::All as u8);
+									spend_tx.input[0].witness.push(witness_script.into_bytes());
+									txn.push(spend_tx);
+								},
 								SpendableOutputDescriptor::DynamicOutputP2WSH { ref outpoint, ref per_commitment_point, ref to_self_delay, ref output, ref key_derivation_params, ref remote_revocation_pubkey } => {
 									let input = TxIn {
 										previous_output: outpoint.clone(),
@@ -4737,6 +4955,7 @@ fn test_claim_sizeable_push_msat() {
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 99000000, InitFeatures::known(), InitFeatures::known());
 	nodes[1].node.force_close_channel(&chan.2);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
 	check_added_monitors!(nodes[1], 1);
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(node_txn.len(), 1);
@@ -4764,6 +4983,7 @@ fn test_claim_on_remote_sizeable_push_msat() {
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 99000000, InitFeatures::known(), InitFeatures::known());
 	nodes[0].node.force_close_channel(&chan.2);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
 	check_added_monitors!(nodes[0], 1);
 
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -4774,6 +4994,7 @@ fn test_claim_on_remote_sizeable_push_msat() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![node_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 	connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.bitcoin_hash());
 
@@ -4783,6 +5004,138 @@ fn test_claim_on_remote_sizeable_push_msat() {
 	check_spends!(spend_txn[0], node_txn[0]);
 }
 
+#[test]
+fn test_anchor_outputs_force_close_cpfp() {
+	// With option_anchor_outputs, our commitment transaction carries an anchor output for each
+	// party and, once we force-close, we bump its fee by spending our anchor along with inputs
+	// from our UtxoPool.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut anchors_config = UserConfig::default();
+	anchors_config.channel_options.announced_channel = true;
+	anchors_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	anchors_config.own_channel_config.negotiate_anchor_outputs = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(anchors_config.clone()), Some(anchors_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	// Anchors are only negotiated once we've seen our peer's Init features
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 10000000, InitFeatures::known(), InitFeatures::known());
+	route_payment(&nodes[0], &vec!(&nodes[1])[..], 3000000);
+
+	nodes[0].node.force_close_channel(&chan.2);
+	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
+	check_added_monitors!(nodes[0], 1);
+
+	let commitment_tx = {
+		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
+		assert_eq!(node_txn.len(), 2);
+		check_spends!(node_txn[0], chan.3);
+		// to_local, to_remote, the HTLC and both anchors
+		assert_eq!(node_txn[0].output.len(), 5);
+		assert_eq!(node_txn[0].output.iter().filter(|outp| outp.value == chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI).count(), 2);
+		// The HTLC-Timeout transaction is valid with the counterparty's SIGHASH_SINGLE|ANYONECANPAY
+		// signature and spends the 1-CSV HTLC output.
+		check_spends!(node_txn[1], node_txn[0]);
+		assert_eq!(node_txn[1].input[0].sequence, 1);
+		let commitment_tx = node_txn[0].clone();
+		node_txn.clear();
+		commitment_tx
+	};
+
+	// On the next block, the commitment transaction is rebroadcast along with a child spending our
+	// anchor and a wallet input.
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![] }, 1);
+	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clone();
+	assert_eq!(node_txn.len(), 2);
+	assert_eq!(node_txn[0], commitment_tx);
+	let child_tx = &node_txn[1];
+	assert_eq!(child_tx.input.len(), 2);
+	check_spends!(child_tx, commitment_tx, nodes[0].chan_monitor.utxo_pool.wallet_tx);
+	assert_eq!(nodes[0].chan_monitor.utxo_pool.selections.load(Ordering::Acquire), 1);
+	let child_tx = child_tx.clone();
+	nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clear();
+
+	// The package feerate rises with each block the package doesn't confirm in, but the child
+	// pays at least the minimum relay fee on its own, so it is simply rebroadcast until the package
+	// feerate requires more than that...
+	let blockhash = connect_blocks(&nodes[0].block_notifier, 8, 1, true, header.bitcoin_hash());
+	{
+		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
+		assert_eq!(node_txn.len(), 16);
+		for txn in node_txn.chunks(2) {
+			assert_eq!(txn[0], commitment_tx);
+			assert_eq!(txn[1], child_tx);
+		}
+		node_txn.clear();
+	}
+
+	// ...at which point it is replaced by one spending the same anchor and wallet input at a
+	// higher feerate.
+	connect_blocks(&nodes[0].block_notifier, 1, 9, true, blockhash);
+	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clone();
+	assert_eq!(node_txn.len(), 2);
+	assert_eq!(node_txn[0], commitment_tx);
+	let bumped_child_tx = &node_txn[1];
+	assert_ne!(*bumped_child_tx, child_tx);
+	assert_eq!(bumped_child_tx.input.len(), 2);
+	assert_eq!(bumped_child_tx.input[0].previous_output, child_tx.input[0].previous_output);
+	assert_eq!(bumped_child_tx.input[1].previous_output, child_tx.input[1].previous_output);
+	check_spends!(bumped_child_tx, commitment_tx, nodes[0].chan_monitor.utxo_pool.wallet_tx);
+	// Both children return everything but their fee to the wallet
+	assert!(bumped_child_tx.output[0].value < child_tx.output[0].value);
+}
+
+#[test]
+fn test_anchor_outputs_cpfp_empty_wallet() {
+	// If our UtxoPool can't provide the inputs to bump our commitment transaction's fee, it is
+	// broadcast on its own, and bumped once the wallet has funds again.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut anchors_config = UserConfig::default();
+	anchors_config.channel_options.announced_channel = true;
+	anchors_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	anchors_config.own_channel_config.negotiate_anchor_outputs = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(anchors_config.clone()), Some(anchors_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 10000000, InitFeatures::known(), InitFeatures::known());
+
+	nodes[0].chan_monitor.utxo_pool.empty.store(true, Ordering::Release);
+	nodes[0].node.force_close_channel(&chan.2);
+	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
+	check_added_monitors!(nodes[0], 1);
+	let commitment_tx = {
+		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
+		assert_eq!(node_txn.len(), 1);
+		check_spends!(node_txn[0], chan.3);
+		node_txn.remove(0)
+	};
+
+	// Without wallet inputs, only the commitment transaction is rebroadcast
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![] }, 1);
+	{
+		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
+		assert_eq!(node_txn.len(), 1);
+		assert_eq!(node_txn[0], commitment_tx);
+		node_txn.clear();
+	}
+	assert_eq!(nodes[0].chan_monitor.utxo_pool.selections.load(Ordering::Acquire), 1);
+
+	// Once the wallet has funds again, the next bump attaches a child
+	nodes[0].chan_monitor.utxo_pool.empty.store(false, Ordering::Release);
+	connect_blocks(&nodes[0].block_notifier, 1, 1, true, header.bitcoin_hash());
+	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clone();
+	assert_eq!(node_txn.len(), 2);
+	assert_eq!(node_txn[0], commitment_tx);
+	check_spends!(node_txn[1], commitment_tx, nodes[0].chan_monitor.utxo_pool.wallet_tx);
+}
+
 #[test]
 fn test_claim_on_remote_revoked_sizeable_push_msat() {
 	// Same test as previous, just test on remote revoked commitment tx, as per_commitment_point registration changes following you're funder/fundee and
@@ -4803,6 +5156,7 @@ fn test_claim_on_remote_revoked_sizeable_push_msat() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -4839,6 +5193,7 @@ fn test_static_spendable_outputs_preimage_tx() {
 	check_added_monitors!(nodes[1], 1);
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()] }, 1);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[1].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::UpdateHTLCs { .. } => {},
@@ -4889,6 +5244,7 @@ fn test_static_spendable_outputs_timeout_tx() {
 	let header = BlockHeader { version: 0x2000_0000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42};
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()] }, 0);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[1].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::BroadcastChannelUpdate { .. } => {},
@@ -4933,6 +5289,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_commitment_tx() {
 	let  header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -4970,6 +5327,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_timeout_tx() {
 	// A will generate HTLC-Timeout from revoked commitment tx
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 
 	let revoked_htlc_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -4982,6 +5340,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_timeout_tx() {
 	// B will generate justice tx from A's revoked commitment/HTLC tx
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone(), revoked_htlc_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -5026,6 +5385,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_success_tx() {
 	// B will generate HTLC-Success from revoked commitment tx
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 	let revoked_htlc_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 
@@ -5037,6 +5397,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_success_tx() {
 	// A will generate justice tx from B's revoked commitment/HTLC tx
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone(), revoked_htlc_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -5096,6 +5457,7 @@ fn test_onchain_to_onchain_claim() {
 	nodes[2].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[2], false);
 	check_added_monitors!(nodes[2], 1);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let c_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 2 (commitment tx, HTLC-Success tx), ChannelMonitor : 1 (HTLC-Success tx)
 	assert_eq!(c_txn.len(), 3);
@@ -5128,6 +5490,20 @@ fn test_onchain_to_onchain_claim() {
 	check_added_monitors!(nodes[1], 1);
 	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
 	check_added_monitors!(nodes[1], 1);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::ChannelClosed { ref reason, .. } => {
+			assert_eq!(*reason, ClosureReason::CommitmentTxConfirmed);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::PaymentForwarded { claim_from_onchain_tx, .. } => {
+			assert!(claim_from_onchain_tx);
+		},
+		_ => panic!("Unexpected event"),
+	}
 	match msg_events[0] {
 		MessageSendEvent::BroadcastChannelUpdate {  .. } => {},
 		_ => panic!("Unexpected event"),
@@ -5157,6 +5533,7 @@ fn test_onchain_to_onchain_claim() {
 
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 }
 
 #[test]
@@ -5183,6 +5560,7 @@ fn test_duplicate_payment_hash_one_failure_one_success() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let htlc_timeout_tx;
 	{ // Extract one of the two HTLC-Timeout transaction
@@ -5205,6 +5583,7 @@ fn test_duplicate_payment_hash_one_failure_one_success() {
 	nodes[2].node.claim_funds(our_payment_preimage, &None, 900_000);
 	nodes[2].block_notifier.block_connected(&Block { header, txdata: vec![commitment_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[2], 3);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[2].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::UpdateHTLCs { .. } => {},
@@ -5263,13 +5642,14 @@ fn test_duplicate_payment_hash_one_failure_one_success() {
 	assert_eq!(updates.update_fulfill_htlcs[0].htlc_id, 0);
 	assert!(updates.update_fail_malformed_htlcs.is_empty());
 	check_added_monitors!(nodes[1], 1);
+	expect_payment_forwarded!(nodes[1], true);
 
 	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
 	commitment_signed_dance!(nodes[0], nodes[1], &updates.commitment_signed, false);
 
 	let events = nodes[0].node.get_and_clear_pending_events();
 	match events[0] {
-		Event::PaymentSent { ref payment_preimage } => {
+		Event::PaymentSent { ref payment_preimage, .. } => {
 			assert_eq!(*payment_preimage, our_payment_preimage);
 		}
 		_ => panic!("Unexpected event"),
@@ -5297,6 +5677,7 @@ fn test_dynamic_spendable_outputs_local_htlc_success_tx() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![local_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[1].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::UpdateHTLCs { .. } => {},
@@ -5343,6 +5724,7 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	let node_chanmgrs = create_node_chanmgrs(6, &node_cfgs, &[None, None, None, None, None, None]);
 	let nodes = create_network(6, &node_cfgs, &node_chanmgrs);
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	create_announced_chan_between_nodes(&nodes, 0, 2, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
@@ -5362,7 +5744,7 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	let (_, payment_hash_2) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], ds_dust_limit*1000); // not added < dust limit + HTLC tx fee
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
 	let our_node_id = &nodes[1].node.get_our_node_id();
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	// 2nd HTLC:
 	send_along_route_with_hash(&nodes[1], route.clone(), &[&nodes[2], &nodes[3], &nodes[5]], ds_dust_limit*1000, payment_hash_1); // not added < dust limit + HTLC tx fee
 	// 3rd HTLC:
@@ -5371,7 +5753,7 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	let (_, payment_hash_3) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], 1000000);
 	// 5th HTLC:
 	let (_, payment_hash_4) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], 1000000);
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	// 6th HTLC:
 	send_along_route_with_hash(&nodes[1], route.clone(), &[&nodes[2], &nodes[3], &nodes[5]], 1000000, payment_hash_3);
 	// 7th HTLC:
@@ -5380,13 +5762,13 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	// 8th HTLC:
 	let (_, payment_hash_5) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], 1000000);
 	// 9th HTLC:
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	send_along_route_with_hash(&nodes[1], route, &[&nodes[2], &nodes[3], &nodes[5]], ds_dust_limit*1000, payment_hash_5); // not added < dust limit + HTLC tx fee
 
 	// 10th HTLC:
 	let (_, payment_hash_6) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], ds_dust_limit*1000); // not added < dust limit + HTLC tx fee
 	// 11th HTLC:
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	send_along_route_with_hash(&nodes[1], route, &[&nodes[2], &nodes[3], &nodes[5]], 1000000, payment_hash_6);
 
 	// Double-check that six of the new HTLC were added
@@ -5462,7 +5844,18 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	}
 	connect_blocks(&nodes[2].block_notifier, ANTI_REORG_DELAY - 1, 1, true,  header.bitcoin_hash());
 	check_closed_broadcast!(nodes[2], false);
-	expect_pending_htlcs_forwardable!(nodes[2]);
+	// Depending on whether the final RAA was delivered, the PendingHTLCsForwardable event may
+	// have been generated before or after the channel was closed.
+	let events = nodes[2].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	for event in events {
+		match event {
+			Event::ChannelClosed { reason, .. } => assert_eq!(reason, ClosureReason::CommitmentTxConfirmed),
+			Event::PendingHTLCsForwardable { .. } => {},
+			_ => panic!("Unexpected event"),
+		}
+	}
+	nodes[2].node.process_pending_htlc_forwards();
 	check_added_monitors!(nodes[2], 3);
 
 	let cs_msgs = nodes[2].node.get_and_clear_pending_msg_events();
@@ -5601,6 +5994,7 @@ fn test_dynamic_spendable_outputs_local_htlc_timeout_tx() {
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![local_txn[0].clone()] }, 200);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let htlc_timeout = {
 		let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -5671,6 +6065,7 @@ fn test_key_derivation_params() {
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![local_txn_1[0].clone()] }, 200);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let htlc_timeout = {
 		let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -5741,7 +6136,7 @@ fn do_htlc_claim_local_commitment_only(use_dust: bool) {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match events[0] {
-		Event::PaymentSent { payment_preimage } => {
+		Event::PaymentSent { payment_preimage, .. } => {
 			assert_eq!(payment_preimage, our_payment_preimage);
 		},
 		_ => panic!("Unexpected event"),
@@ -5761,6 +6156,7 @@ fn do_htlc_claim_local_commitment_only(use_dust: bool) {
 	test_txn_broadcast(&nodes[1], &chan, None, if use_dust { HTLCType::NONE } else { HTLCType::SUCCESS });
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Pending HTLC must be resolved on-chain before it expires".to_string() });
 }
 
 fn do_htlc_claim_current_remote_commitment_only(use_dust: bool) {
@@ -5770,10 +6166,11 @@ fn do_htlc_claim_current_remote_commitment_only(use_dust: bool) {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), if use_dust { 50000 } else { 3000000 }, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), if use_dust { 50000 } else { 3000000 }, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 
@@ -5792,6 +6189,7 @@ fn do_htlc_claim_current_remote_commitment_only(use_dust: bool) {
 	test_txn_broadcast(&nodes[0], &chan, None, HTLCType::NONE);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Pending HTLC must be resolved on-chain before it expires".to_string() });
 }
 
 fn do_htlc_claim_previous_remote_commitment_only(use_dust: bool, check_revoke_no_close: bool) {
@@ -5836,6 +6234,7 @@ fn do_htlc_claim_previous_remote_commitment_only(use_dust: bool, check_revoke_no
 		test_txn_broadcast(&nodes[0], &chan, None, HTLCType::NONE);
 		check_closed_broadcast!(nodes[0], false);
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Pending HTLC must be resolved on-chain before it expires".to_string() });
 	} else {
 		expect_payment_failed!(nodes[0], our_payment_hash, true);
 	}
@@ -5893,6 +6292,8 @@ fn run_onion_failure_test_with_fail_intercept<F1,F2,F3>(_name: &str, test_case:
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	for ix in 0..nodes.len() {
 		nodes[ix].block_notifier.block_connected_checked(&header, 1, &[], &[]);
+		// Every test case reuses the same session key, so forget the onions we've already seen
+		nodes[ix].node.clear_onion_replay_cache();
 	}
 
 	macro_rules! expect_event {
@@ -5999,9 +6400,9 @@ fn run_onion_failure_test_with_fail_intercept<F1,F2,F3>(_name: &str, test_case:
 
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
-	if let &Event::PaymentFailed { payment_hash:_, ref rejected_by_dest, ref error_code, error_data: _ } = &events[0] {
+	if let &Event::PaymentFailed { payment_hash:_, ref rejected_by_dest, ref failure, .. } = &events[0] {
 		assert_eq!(*rejected_by_dest, !expected_retryable);
-		assert_eq!(*error_code, expected_error_code);
+		assert_eq!(failure.as_ref().map(|f| f.failure_code.to_u16()), expected_error_code);
 	} else {
 		panic!("Uexpected event");
 	}
@@ -6057,6 +6458,7 @@ impl msgs::ChannelUpdate {
 				htlc_minimum_msat: 0,
 				fee_base_msat: 0,
 				fee_proportional_millionths: 0,
+				htlc_maximum_msat: msgs::OptionalField::Absent,
 				excess_data: vec![],
 			}
 		}
@@ -6082,11 +6484,7 @@ fn test_onion_failure() {
 	use ln::msgs::ChannelUpdate;
 	use ln::channelmanager::CLTV_FAR_FAR_AWAY;
 	use bitcoin::secp256k1;
-
-	const BADONION: u16 = 0x8000;
-	const PERM: u16 = 0x4000;
-	const NODE: u16 = 0x2000;
-	const UPDATE: u16 = 0x1000;
+	use util::errors::{BADONION, PERM, NODE, UPDATE};
 
 	let chanmon_cfgs = create_chanmon_cfgs(3);
 	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
@@ -6099,7 +6497,8 @@ fn test_onion_failure() {
 	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = test_utils::TestScorer::new();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	// positve case
 	send_payment(&nodes[0], &vec!(&nodes[1], &nodes[2])[..], 40000, 40_000);
 
@@ -6108,7 +6507,7 @@ fn test_onion_failure() {
 		let session_priv = SecretKey::from_slice(&[3; 32]).unwrap();
 		let cur_height = nodes[0].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 		let onion_keys = onion_utils::construct_onion_keys(&Secp256k1::new(), &route.paths[0], &session_priv).unwrap();
-		let (mut onion_payloads, _htlc_msat, _htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 40000, &None, cur_height).unwrap();
+		let (mut onion_payloads, _htlc_msat, _htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 40000, &None, &None, cur_height).unwrap();
 		let mut new_payloads = Vec::new();
 		for payload in onion_payloads.drain(..) {
 			new_payloads.push(BogusOnionHopData::new(payload));
@@ -6124,7 +6523,7 @@ fn test_onion_failure() {
 		let session_priv = SecretKey::from_slice(&[3; 32]).unwrap();
 		let cur_height = nodes[0].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 		let onion_keys = onion_utils::construct_onion_keys(&Secp256k1::new(), &route.paths[0], &session_priv).unwrap();
-		let (mut onion_payloads, _htlc_msat, _htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 40000, &None, cur_height).unwrap();
+		let (mut onion_payloads, _htlc_msat, _htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 40000, &None, &None, cur_height).unwrap();
 		let mut new_payloads = Vec::new();
 		for payload in onion_payloads.drain(..) {
 			new_payloads.push(BogusOnionHopData::new(payload));
@@ -6309,7 +6708,7 @@ fn test_onion_failure() {
 		let height = 1;
 		route.paths[0][1].cltv_expiry_delta += CLTV_FAR_FAR_AWAY + route.paths[0][0].cltv_expiry_delta + 1;
 		let onion_keys = onion_utils::construct_onion_keys(&Secp256k1::new(), &route.paths[0], &session_priv).unwrap();
-		let (onion_payloads, _, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 40000, &None, height).unwrap();
+		let (onion_payloads, _, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 40000, &None, &None, height).unwrap();
 		let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &payment_hash);
 		msg.cltv_expiry = htlc_cltv;
 		msg.onion_routing_packet = onion_packet;
@@ -6400,7 +6799,8 @@ fn test_update_add_htlc_bolt2_sender_value_below_minimum_msat() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = test_utils::TestScorer::new();
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	route.paths[0][0].fee_msat = 100;
 
 	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { err },
@@ -6421,7 +6821,8 @@ fn test_update_add_htlc_bolt2_sender_zero_value_msat() {
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = test_utils::TestScorer::new();
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	route.paths[0][0].fee_msat = 0;
 	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { err },
 		assert_eq!(err, "Cannot send 0-msat HTLC"));
@@ -6442,7 +6843,8 @@ fn test_update_add_htlc_bolt2_receiver_zero_value_msat() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = test_utils::TestScorer::new();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6451,6 +6853,7 @@ fn test_update_add_htlc_bolt2_receiver_zero_value_msat() {
 	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
 	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Remote side tried to send a 0-msat HTLC".to_string(), 1);
 	check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote side tried to send a 0-msat HTLC".to_string() });
 	check_added_monitors!(nodes[1], 1);
 }
 
@@ -6464,11 +6867,12 @@ fn test_update_add_htlc_bolt2_sender_cltv_expiry_too_high() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let _chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 0, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000000, 500000001, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000000, 500000001, &logger, &scorer).unwrap();
 	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::RouteError { err },
 		assert_eq!(err, "Channel CLTV overflowed?!"));
 }
@@ -6486,11 +6890,12 @@ fn test_update_add_htlc_bolt2_sender_exceed_max_htlc_num_and_htlc_id_increment()
 	let max_accepted_htlcs = nodes[1].node.channel_state.lock().unwrap().by_id.get(&chan.2).unwrap().their_max_accepted_htlcs as u64;
 
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 	for i in 0..max_accepted_htlcs {
 		let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 		let payment_event = {
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 			check_added_monitors!(nodes[0], 1);
 
@@ -6512,7 +6917,7 @@ fn test_update_add_htlc_bolt2_sender_exceed_max_htlc_num_and_htlc_id_increment()
 	}
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { err },
 		assert_eq!(err, "Cannot push more than their max accepted HTLCs"));
 
@@ -6536,7 +6941,8 @@ fn test_update_add_htlc_bolt2_sender_exceed_max_htlc_value_in_flight() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_in_flight+1, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = test_utils::TestScorer::new();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_in_flight+1, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { err },
 		assert_eq!(err, "Cannot send value that would put us over the max HTLC value in flight our peer will accept"));
 
@@ -6565,7 +6971,8 @@ fn test_update_add_htlc_bolt2_receiver_check_amount_received_more_than_min() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], htlc_minimum_msat, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = test_utils::TestScorer::new();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], htlc_minimum_msat, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6573,6 +6980,7 @@ fn test_update_add_htlc_bolt2_receiver_check_amount_received_more_than_min() {
 	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote side tried to send less than our minimum HTLC value".to_string() });
 	assert_eq!(err_msg.data, "Remote side tried to send less than our minimum HTLC value");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6586,6 +6994,7 @@ fn test_update_add_htlc_bolt2_receiver_sender_can_afford_amount_sent() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let chan_stat = get_channel_value_stat!(nodes[0], chan.2);
 	let channel_reserve = chan_stat.channel_reserve_msat;
@@ -6596,7 +7005,7 @@ fn test_update_add_htlc_bolt2_receiver_sender_can_afford_amount_sent() {
 	let max_can_send = 5000000 - channel_reserve - commit_tx_fee_outbound;
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6609,6 +7018,7 @@ fn test_update_add_htlc_bolt2_receiver_sender_can_afford_amount_sent() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote HTLC add would put them under remote reserve value".to_string() });
 	assert_eq!(err_msg.data, "Remote HTLC add would put them under remote reserve value");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6623,16 +7033,17 @@ fn test_update_add_htlc_bolt2_receiver_check_max_htlc_limit() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 3999999, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 3999999, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 
 	let cur_height = nodes[0].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 	let onion_keys = onion_utils::construct_onion_keys(&Secp256k1::signing_only(), &route.paths[0], &session_priv).unwrap();
-	let (onion_payloads, _htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3999999, &None, cur_height).unwrap();
+	let (onion_payloads, _htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3999999, &None, &None, cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &our_payment_hash);
 
 	let mut msg = msgs::UpdateAddHTLC {
@@ -6653,6 +7064,7 @@ fn test_update_add_htlc_bolt2_receiver_check_max_htlc_limit() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote tried to push more than our max accepted HTLCs".to_string() });
 	assert_eq!(err_msg.data, "Remote tried to push more than our max accepted HTLCs");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6666,10 +7078,11 @@ fn test_update_add_htlc_bolt2_receiver_check_max_in_flight_msat() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 1000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6678,6 +7091,7 @@ fn test_update_add_htlc_bolt2_receiver_check_max_in_flight_msat() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote HTLC add would put them over our max HTLC value".to_string() });
 	assert_eq!(err_msg.data,"Remote HTLC add would put them over our max HTLC value");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6690,11 +7104,12 @@ fn test_update_add_htlc_bolt2_receiver_check_cltv_expiry() {
 	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6703,6 +7118,7 @@ fn test_update_add_htlc_bolt2_receiver_check_cltv_expiry() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote provided CLTV expiry in seconds instead of block height".to_string() });
 	assert_eq!(err_msg.data,"Remote provided CLTV expiry in seconds instead of block height");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6717,11 +7133,12 @@ fn test_update_add_htlc_bolt2_receiver_check_repeated_id_ignore() {
 	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6752,6 +7169,7 @@ fn test_update_add_htlc_bolt2_receiver_check_repeated_id_ignore() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote skipped HTLC ID".to_string() });
 	assert_eq!(err_msg.data, "Remote skipped HTLC ID");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6765,10 +7183,11 @@ fn test_update_fulfill_htlc_bolt2_update_fulfill_htlc_before_commitment() {
 	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let (our_payment_preimage, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 
 	check_added_monitors!(nodes[0], 1);
@@ -6785,6 +7204,7 @@ fn test_update_fulfill_htlc_bolt2_update_fulfill_htlc_before_commitment() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Remote tried to fulfill/fail HTLC before it had been committed".to_string() });
 	assert_eq!(err_msg.data, "Remote tried to fulfill/fail HTLC before it had been committed");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6799,10 +7219,11 @@ fn test_update_fulfill_htlc_bolt2_update_fail_htlc_before_commitment() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6818,6 +7239,7 @@ fn test_update_fulfill_htlc_bolt2_update_fail_htlc_before_commitment() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Remote tried to fulfill/fail HTLC before it had been committed".to_string() });
 	assert_eq!(err_msg.data, "Remote tried to fulfill/fail HTLC before it had been committed");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6832,10 +7254,11 @@ fn test_update_fulfill_htlc_bolt2_update_fail_malformed_htlc_before_commitment()
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6852,6 +7275,7 @@ fn test_update_fulfill_htlc_bolt2_update_fail_malformed_htlc_before_commitment()
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Remote tried to fulfill/fail HTLC before it had been committed".to_string() });
 	assert_eq!(err_msg.data, "Remote tried to fulfill/fail HTLC before it had been committed");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6893,6 +7317,7 @@ fn test_update_fulfill_htlc_bolt2_incorrect_htlc_id() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Remote tried to fulfill/fail an HTLC we couldn't find".to_string() });
 	assert_eq!(err_msg.data, "Remote tried to fulfill/fail an HTLC we couldn't find");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6934,6 +7359,7 @@ fn test_update_fulfill_htlc_bolt2_wrong_preimage() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Remote tried to fulfill HTLC with an incorrect preimage".to_string() });
 	assert_eq!(err_msg.data, "Remote tried to fulfill HTLC with an incorrect preimage");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6948,10 +7374,11 @@ fn test_update_fulfill_htlc_bolt2_missing_badonion_bit_for_malformed_htlc_messag
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 1000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 
@@ -6982,6 +7409,7 @@ fn test_update_fulfill_htlc_bolt2_missing_badonion_bit_for_malformed_htlc_messag
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Got update_fail_malformed_htlc with BADONION not set".to_string() });
 	assert_eq!(err_msg.data, "Got update_fail_malformed_htlc with BADONION not set");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6998,13 +7426,14 @@ fn test_update_fulfill_htlc_bolt2_after_malformed_htlc_message_must_forward_upda
 	create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 1000000, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes_with_value(&nodes, 1, 2, 1000000, 1000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 
 	//First hop
 	let mut payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
@@ -7127,8 +7556,8 @@ fn do_test_failure_delay_dust_htlc_local_commitment(announce_latest: bool) {
 
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 
-	assert_eq!(nodes[0].node.get_and_clear_pending_events().len(), 0);
 	connect_blocks(&nodes[0].block_notifier, ANTI_REORG_DELAY - 1, 1, true,  header.bitcoin_hash());
 	let events = nodes[0].node.get_and_clear_pending_events();
 	// Only 2 PaymentFailed events should show up, over-dust HTLC has to be failed by timeout tx
@@ -7244,7 +7673,7 @@ fn do_test_sweep_outbound_htlc_failure_update(revoked: bool, local: bool) {
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![as_commitment_tx[0].clone()]}, 1);
 		check_closed_broadcast!(nodes[0], false);
 		check_added_monitors!(nodes[0], 1);
-		assert_eq!(nodes[0].node.get_and_clear_pending_events().len(), 0);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		timeout_tx.push(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap()[0].clone());
 		let parent_hash  = connect_blocks(&nodes[0].block_notifier, ANTI_REORG_DELAY - 1, 2, true, header.bitcoin_hash());
 		expect_payment_failed!(nodes[0], dust_hash, true);
@@ -7261,7 +7690,7 @@ fn do_test_sweep_outbound_htlc_failure_update(revoked: bool, local: bool) {
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![bs_commitment_tx[0].clone()]}, 1);
 		check_closed_broadcast!(nodes[0], false);
 		check_added_monitors!(nodes[0], 1);
-		assert_eq!(nodes[0].node.get_and_clear_pending_events().len(), 0);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		timeout_tx.push(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap()[0].clone());
 		let parent_hash  = connect_blocks(&nodes[0].block_notifier, ANTI_REORG_DELAY - 1, 2, true, header.bitcoin_hash());
 		let header_2 = BlockHeader { version: 0x20000000, prev_blockhash: parent_hash, merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
@@ -7329,6 +7758,7 @@ fn test_upfront_shutdown_script() {
 	// Test we enforce upfront_scriptpbukey if by providing a diffrent one at closing that  we disconnect peer
 	nodes[2].node.handle_shutdown(&nodes[0].node.get_our_node_id(), &node_0_shutdown);
 	assert_eq!(check_closed_broadcast!(nodes[2], true).unwrap().data, "Got shutdown request with a scriptpubkey which did not match their previous scriptpubkey");
+	check_closed_event!(nodes[2], 1, ClosureReason::ProcessingError { err: "Got shutdown request with a scriptpubkey which did not match their previous scriptpubkey".to_string() });
 	check_added_monitors!(nodes[2], 1);
 
 	// We test that in case of peer committing upfront to a script, if it doesn't change at closing, we sign
@@ -7407,7 +7837,7 @@ fn test_user_configurable_csv_delay() {
 
 	// We test config.our_to_self > BREAKDOWN_TIMEOUT is enforced in Channel::new_outbound()
 	let keys_manager: Arc<KeysInterface<ChanKeySigner = EnforcingChannelKeys>> = Arc::new(test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet));
-	if let Err(error) = Channel::new_outbound(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), 1000000, 1000000, 0, &low_our_to_self_config) {
+	if let Err(error) = Channel::new_outbound(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), &InitFeatures::known(), 1000000, 1000000, 0, &low_our_to_self_config) {
 		match error {
 			APIError::APIMisuseError { err } => { assert_eq!(err, "Configured with an unreasonable our_to_self_delay putting user funds at risks"); },
 			_ => panic!("Unexpected event"),
@@ -7418,7 +7848,7 @@ fn test_user_configurable_csv_delay() {
 	nodes[1].node.create_channel(nodes[0].node.get_our_node_id(), 1000000, 1000000, 42, None).unwrap();
 	let mut open_channel = get_event_msg!(nodes[1], MessageSendEvent::SendOpenChannel, nodes[0].node.get_our_node_id());
 	open_channel.to_self_delay = 200;
-	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, &low_our_to_self_config) {
+	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, 0, &low_our_to_self_config) {
 		match error {
 			ChannelError::Close(err) => { assert_eq!(err, "Configured with an unreasonable our_to_self_delay putting user funds at risks"); },
 			_ => panic!("Unexpected event"),
@@ -7438,13 +7868,14 @@ fn test_user_configurable_csv_delay() {
 			},
 			_ => { assert!(false); }
 		}
+		check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "They wanted our payments to be delayed by a needlessly long period".to_string() });
 	} else { assert!(false); }
 
 	// We test msg.to_self_delay <= config.their_to_self_delay is enforced in Channel::new_from_req()
 	nodes[1].node.create_channel(nodes[0].node.get_our_node_id(), 1000000, 1000000, 42, None).unwrap();
 	let mut open_channel = get_event_msg!(nodes[1], MessageSendEvent::SendOpenChannel, nodes[0].node.get_our_node_id());
 	open_channel.to_self_delay = 200;
-	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, &high_their_to_self_config) {
+	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, 0, &high_their_to_self_config) {
 		match error {
 			ChannelError::Close(err) => { assert_eq!(err, "They wanted our payments to be delayed by a needlessly long period"); },
 			_ => panic!("Unexpected event"),
@@ -7546,11 +7977,13 @@ fn test_data_loss_protect() {
 			panic!("Unexpected event")
 		}
 	}
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "We have fallen behind - we have received proof that if we broadcast remote is going to claim our funds - we can't do any automated broadcasting".to_string() });
 
 	// Check we close channel detecting A is fallen-behind
 	nodes[1].node.handle_channel_reestablish(&nodes[0].node.get_our_node_id(), &reestablish_1[0]);
 	assert_eq!(check_closed_broadcast!(nodes[1], true).unwrap().data, "Peer attempted to reestablish channel with a very old local commitment transaction");
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Peer attempted to reestablish channel with a very old local commitment transaction".to_string() });
 
 
 	// Check A is able to claim to_remote output
@@ -7686,11 +8119,12 @@ fn test_bump_penalty_txn_on_revoked_commitment() {
 
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 59000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 
 	let payment_preimage = route_payment(&nodes[0], &vec!(&nodes[1])[..], 3000000).0;
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 3000000, 30, &logger).unwrap();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 3000000, 30, &logger, &scorer).unwrap();
 	send_along_route(&nodes[1], route, &vec!(&nodes[0])[..], 3000000);
 
 	let revoked_txn = get_local_commitment_txn!(nodes[0], chan.2);
@@ -7807,6 +8241,7 @@ fn test_bump_penalty_txn_on_revoked_htlcs() {
 	// B will generate both revoked HTLC-timeout/HTLC-preimage txn from revoked commitment tx
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	let revoked_htlc_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -7898,6 +8333,7 @@ fn test_bump_penalty_txn_on_revoked_htlcs() {
 	}
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 }
 
 #[test]
@@ -8048,6 +8484,7 @@ fn test_set_outpoints_partial_claiming() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: prev_header_100, merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![remote_txn[0].clone()] }, 101);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 	// Verify node A broadcast tx claiming both HTLCs
 	{
@@ -8065,6 +8502,7 @@ fn test_set_outpoints_partial_claiming() {
 	// Connect blocks on node B
 	connect_blocks(&nodes[1].block_notifier, 135, 0, false, Default::default());
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Pending HTLC must be resolved on-chain before it expires".to_string() });
 	check_added_monitors!(nodes[1], 1);
 	// Verify node B broadcast 2 HTLC-timeout txn
 	let partial_claim_tx = {
@@ -8134,6 +8572,7 @@ fn test_counterparty_raa_skip_no_crash() {
 	nodes[1].node.handle_revoke_and_ack(&nodes[0].node.get_our_node_id(),
 		&msgs::RevokeAndACK { channel_id, per_commitment_secret, next_per_commitment_point });
 	assert_eq!(check_closed_broadcast!(nodes[1], true).unwrap().data, "Received an unexpected revoke_and_ack");
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Received an unexpected revoke_and_ack".to_string() });
 	check_added_monitors!(nodes[1], 1);
 }
 
@@ -8166,6 +8605,7 @@ fn test_bump_txn_sanitize_tracking_maps() {
 	let header_129 = BlockHeader { version: 0x20000000, prev_blockhash: header_128, merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[0].block_notifier.block_connected(&Block { header: header_129, txdata: vec![revoked_local_txn[0].clone()] }, 129);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 	let penalty_txn = {
 		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -8238,11 +8678,12 @@ fn test_simple_payment_secret() {
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
 	let payment_secret = PaymentSecret([0xdb; 32]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	send_along_route_with_secret(&nodes[0], route, &[&[&nodes[1], &nodes[2]]], 100000, payment_hash, Some(payment_secret.clone()));
 	// Claiming with all the correct values but the wrong secret should result in nothing...
 	assert_eq!(nodes[2].node.claim_funds(payment_preimage, &None, 100_000), false);
@@ -8264,11 +8705,12 @@ fn test_simple_mpp() {
 	let chan_3_id = create_announced_chan_between_nodes(&nodes, 1, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
 	let chan_4_id = create_announced_chan_between_nodes(&nodes, 2, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
 	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
 	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
 	let payment_secret = PaymentSecret([0xdb; 32]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[3].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[3].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	let path = route.paths[0].clone();
 	route.paths.push(path);
 	route.paths[0][0].pubkey = nodes[1].node.get_our_node_id();
@@ -8286,62 +8728,1559 @@ fn test_simple_mpp() {
 }
 
 #[test]
-fn test_update_err_monitor_lockdown() {
-	// Our monitor will lock update of local commitment transaction if a broadcastion condition
-	// has been fulfilled (either force-close from Channel or block height requiring a HTLC-
-	// timeout). Trying to update monitor after lockdown should return a ChannelMonitorUpdateErr.
-	//
-	// This scenario may happen in a watchtower setup, where watchtower process a block height
-	// triggering a timeout while a slow-block-processing ChannelManager receives a local signed
-	// commitment at same time.
+fn test_payment_retry_manager() {
+	// Test that PaymentRetryManager retries the value of a failed path over a different path,
+	// avoiding the failed channel, and reports both attempts once the payment succeeds.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 0, 2, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 1, 3, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 2, 3, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
+	let retry_manager = PaymentRetryManager::new(3, &logger, &scorer);
+
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	retry_manager.send_payment(&nodes[0].node, &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(),
+		nodes[3].node.get_our_node_id(), payment_hash, None, &[], 100000, TEST_FINAL_CLTV).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	let (failing_hop, retry_hop) = if payment_event.node_id == nodes[1].node.get_our_node_id() { (1, 2) } else { (2, 1) };
+
+	// Disconnect the first path's intermediate node from the recipient so that it fails the HTLC
+	// back to us.
+	nodes[failing_hop].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[failing_hop], nodes[0], payment_event.commitment_msg, false);
+	nodes[failing_hop].node.peer_disconnected(&nodes[3].node.get_our_node_id(), false);
+	nodes[3].node.peer_disconnected(&nodes[failing_hop].node.get_our_node_id(), false);
+	expect_pending_htlcs_forwardable!(nodes[failing_hop]);
+	expect_pending_htlcs_forwardable!(nodes[failing_hop]);
+	check_added_monitors!(nodes[failing_hop], 1);
+	let updates = get_htlc_update_msgs!(nodes[failing_hop], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[failing_hop].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[failing_hop], updates.commitment_signed, false, true);
+
+	// The PaymentFailed event is consumed and the value is retried over the other path.
+	let events = retry_manager.process_events(&nodes[0].node, &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(),
+		nodes[0].node.get_and_clear_pending_events());
+	assert!(events.is_empty());
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		MessageSendEvent::PaymentFailureNetworkUpdate { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	pass_along_path(&nodes[0], &[&nodes[retry_hop], &nodes[3]], 100000, payment_hash, None, events.remove(1), true);
+	assert!(nodes[3].node.claim_funds(payment_preimage, &None, 100000));
+	check_added_monitors!(nodes[3], 1);
+	let updates = get_htlc_update_msgs!(nodes[3], nodes[retry_hop].node.get_our_node_id());
+	nodes[retry_hop].node.handle_update_fulfill_htlc(&nodes[3].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	expect_payment_forwarded!(nodes[retry_hop], false);
+	check_added_monitors!(nodes[retry_hop], 1);
+	let next_updates = get_htlc_update_msgs!(nodes[retry_hop], nodes[0].node.get_our_node_id());
+	commitment_signed_dance!(nodes[retry_hop], nodes[3], updates.commitment_signed, false);
+	nodes[0].node.handle_update_fulfill_htlc(&nodes[retry_hop].node.get_our_node_id(), &next_updates.update_fulfill_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[retry_hop], next_updates.commitment_signed, false);
+
+	let events = retry_manager.process_events(&nodes[0].node, &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(),
+		nodes[0].node.get_and_clear_pending_events());
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentResolved { payment_hash: ref hash, payment_preimage: ref preimage, ref attempts } => {
+			assert_eq!(*hash, payment_hash);
+			assert_eq!(*preimage, Some(payment_preimage));
+			assert_eq!(attempts.len(), 2);
+			assert!(attempts[0].failed);
+			assert_eq!(attempts[0].path[0].pubkey, nodes[failing_hop].node.get_our_node_id());
+			assert!(!attempts[1].failed);
+			assert_eq!(attempts[1].path[0].pubkey, nodes[retry_hop].node.get_our_node_id());
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
 
+#[test]
+fn test_mpp_receive_timeout() {
+	// If we only receive part of a multi-path payment, we should fail it back with mpp_timeout
+	// after mpp_timeout_ticks calls to timer_chan_freshness_every_min, but never time out a
+	// payment we've received in full.
 	let chanmon_cfgs = create_chanmon_cfgs(2);
 	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
 	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
-	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
-
-	// Create some initial channel
-	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
-	let outpoint = OutPoint { txid: chan_1.3.txid(), index: 0 };
-
-	// Rebalance the network to generate htlc in the two directions
-	send_payment(&nodes[0], &vec!(&nodes[1])[..], 10_000_000, 10_000_000);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
 
-	// Route a HTLC from node 0 to node 1 (but don't settle)
-	let preimage = route_payment(&nodes[0], &vec!(&nodes[1])[..], 9_000_000).0;
+	let (_, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	let payment_secret = PaymentSecret([0xdb; 32]);
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	// Send only one of two 100_000 msat parts
+	nodes[0].node.send_payment_along_path(&route.paths[0], &payment_hash, &Some(payment_secret), &None, 200000, CHAN_CONFIRM_DEPTH).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	pass_along_path(&nodes[0], &[&nodes[1]], 100000, payment_hash, Some(payment_secret), events.drain(..).next().unwrap(), false);
 
-	// Copy SimpleManyChannelMonitor to simulate a watchtower and update block height of node 0 until its ChannelMonitor timeout HTLC onchain
-	let logger = test_utils::TestLogger::with_id(format!("node {}", 0));
-	let chain_monitor = chaininterface::ChainWatchInterfaceUtil::new(Network::Testnet);
-	let watchtower = {
-		let monitors = nodes[0].chan_monitor.simple_monitor.monitors.lock().unwrap();
-		let monitor = monitors.get(&outpoint).unwrap();
-		let mut w = test_utils::TestVecWriter(Vec::new());
-		monitor.write_for_disk(&mut w).unwrap();
-		let new_monitor = <(BlockHash, channelmonitor::ChannelMonitor<EnforcingChannelKeys>)>::read(
-				&mut ::std::io::Cursor::new(&w.0)).unwrap().1;
-		assert!(new_monitor == *monitor);
-		let watchtower = test_utils::TestChannelMonitor::new(&chain_monitor, &chanmon_cfgs[0].tx_broadcaster, &logger, &chanmon_cfgs[0].fee_estimator);
-		assert!(watchtower.add_monitor(outpoint, new_monitor).is_ok());
-		watchtower
-	};
-	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
-	watchtower.simple_monitor.block_connected(&header, 200, &vec![], &vec![]);
+	// A complete payment which is yet to be claimed is left alone
+	let payment_preimage_2 = route_payment(&nodes[0], &[&nodes[1]], 100000).0;
 
-	// Try to update ChannelMonitor
-	assert!(nodes[1].node.claim_funds(preimage, &None, 9_000_000));
+	for _ in 1..UserConfig::default().mpp_timeout_ticks {
+		nodes[1].node.timer_chan_freshness_every_min();
+		assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	}
+	nodes[1].node.timer_chan_freshness_every_min();
+	expect_pending_htlcs_forwardable!(nodes[1]);
 	check_added_monitors!(nodes[1], 1);
 	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
-	assert_eq!(updates.update_fulfill_htlcs.len(), 1);
-	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
-	if let Some(ref mut channel) = nodes[0].node.channel_state.lock().unwrap().by_id.get_mut(&chan_1.2) {
-		if let Ok((_, _, _, update)) = channel.commitment_signed(&updates.commitment_signed, &node_cfgs[0].fee_estimator, &node_cfgs[0].logger) {
-			if let Err(_) =  watchtower.simple_monitor.update_monitor(outpoint, update.clone()) {} else { assert!(false); }
-			if let Ok(_) = nodes[0].chan_monitor.update_monitor(outpoint, update) {} else { assert!(false); }
-		} else { assert!(false); }
-	} else { assert!(false); };
-	// Our local monitor is in-sync and hasn't processed yet timeout
-	check_added_monitors!(nodes[0], 1);
-	let events = nodes[0].node.get_and_clear_pending_events();
-	assert_eq!(events.len(), 1);
+	assert!(updates.update_add_htlcs.is_empty());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	assert!(updates.update_fail_malformed_htlcs.is_empty());
+	assert!(updates.update_fee.is_none());
+
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+	expect_payment_failed!(nodes[0], payment_hash, false, 23, &[][..]);
+
+	nodes[1].node.timer_chan_freshness_every_min();
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage_2, 100000);
+}
+
+#[test]
+fn test_spontaneous_payment() {
+	// Spontaneous (keysend) payments carry their own preimage in the onion, which is surfaced in
+	// the PaymentReceived event if (and only if) the recipient opted in to receiving them.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let mut keysend_config = UserConfig::default();
+	keysend_config.channel_options.announced_channel = true;
+	keysend_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	keysend_config.accept_spontaneous_payments = true;
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, Some(keysend_config)]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
+
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let payment_hash = nodes[0].node.send_spontaneous_payment(&route, None).unwrap();
+	check_added_monitors!(nodes[0], 1);
+
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let mut payment_event = SendEvent::from_event(events.remove(0));
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let mut events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	payment_event = SendEvent::from_event(events.remove(0));
+	nodes[2].node.handle_update_add_htlc(&nodes[1].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[2], nodes[1], payment_event.commitment_msg, false);
+	expect_pending_htlcs_forwardable!(nodes[2]);
+
+	let events = nodes[2].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let payment_preimage = match events[0] {
+		Event::PaymentReceived { payment_hash: ref received_hash, payment_secret: None, payment_preimage: Some(preimage), amt } => {
+			assert_eq!(*received_hash, payment_hash);
+			assert_eq!(amt, 100000);
+			preimage
+		},
+		_ => panic!("Unexpected event"),
+	};
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 100000);
+
+	// A node which hasn't opted in fails spontaneous payments back as if the hash were unknown.
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let payment_hash = nodes[0].node.send_spontaneous_payment(&route, Some(PaymentPreimage([42; 32]))).unwrap();
+	assert_eq!(payment_hash, PaymentHash(Sha256::hash(&[42; 32]).into_inner()));
+	check_added_monitors!(nodes[0], 1);
+
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	// 100_000 msat as u64, followed by a height of 99 as u32
+	let mut expected_failure_data = byte_utils::be64_to_array(100_000).to_vec();
+	expected_failure_data.extend_from_slice(&byte_utils::be32_to_array(99));
+	expect_payment_failed!(nodes[0], payment_hash, true, 0x4000 | 15, &expected_failure_data[..]);
+}
+
+#[test]
+fn test_update_err_monitor_lockdown() {
+	// Our monitor will lock update of local commitment transaction if a broadcastion condition
+	// has been fulfilled (either force-close from Channel or block height requiring a HTLC-
+	// timeout). Trying to update monitor after lockdown should return a ChannelMonitorUpdateErr.
+	//
+	// This scenario may happen in a watchtower setup, where watchtower process a block height
+	// triggering a timeout while a slow-block-processing ChannelManager receives a local signed
+	// commitment at same time.
+
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	// Create some initial channel
+	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let outpoint = OutPoint { txid: chan_1.3.txid(), index: 0 };
+
+	// Rebalance the network to generate htlc in the two directions
+	send_payment(&nodes[0], &vec!(&nodes[1])[..], 10_000_000, 10_000_000);
+
+	// Route a HTLC from node 0 to node 1 (but don't settle)
+	let preimage = route_payment(&nodes[0], &vec!(&nodes[1])[..], 9_000_000).0;
+
+	// Copy SimpleManyChannelMonitor to simulate a watchtower and update block height of node 0 until its ChannelMonitor timeout HTLC onchain
+	let logger = test_utils::TestLogger::with_id(format!("node {}", 0));
+	let chain_monitor = chaininterface::ChainWatchInterfaceUtil::new(Network::Testnet);
+	let watchtower = {
+		let monitors = nodes[0].chan_monitor.simple_monitor.monitors.lock().unwrap();
+		let monitor = monitors.get(&outpoint).unwrap();
+		let mut w = test_utils::TestVecWriter(Vec::new());
+		monitor.write_for_disk(&mut w).unwrap();
+		let new_monitor = <(BlockHash, channelmonitor::ChannelMonitor<EnforcingChannelKeys>)>::read(
+				&mut ::std::io::Cursor::new(&w.0)).unwrap().1;
+		assert!(new_monitor == *monitor);
+		let watchtower = test_utils::TestChannelMonitor::new(&chain_monitor, &chanmon_cfgs[0].tx_broadcaster, &logger, &chanmon_cfgs[0].fee_estimator);
+		assert!(watchtower.add_monitor(outpoint, new_monitor).is_ok());
+		watchtower
+	};
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	watchtower.simple_monitor.block_connected(&header, 200, &vec![], &vec![]);
+
+	// Try to update ChannelMonitor
+	assert!(nodes[1].node.claim_funds(preimage, &None, 9_000_000));
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fulfill_htlcs.len(), 1);
+	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	if let Some(ref mut channel) = nodes[0].node.channel_state.lock().unwrap().by_id.get_mut(&chan_1.2) {
+		if let Ok((_, _, _, update)) = channel.commitment_signed(&updates.commitment_signed, &node_cfgs[0].fee_estimator, &node_cfgs[0].logger) {
+			if let Err(_) =  watchtower.simple_monitor.update_monitor(outpoint, update.clone()) {} else { assert!(false); }
+			if let Ok(_) = nodes[0].chan_monitor.update_monitor(outpoint, update) {} else { assert!(false); }
+		} else { assert!(false); }
+	} else { assert!(false); };
+	// Our local monitor is in-sync and hasn't processed yet timeout
+	check_added_monitors!(nodes[0], 1);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+}
+
+#[test]
+fn test_dual_funded_channel_open() {
+	// Open a channel to which both sides contribute funds, walking through the interactive
+	// construction of the funding transaction, the exchange of initial commitment signatures and
+	// of the funding transaction witnesses, then check the resulting channel is usable.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let op_true_script = Builder::new().push_opcode(opcodes::OP_TRUE).into_script();
+	let prevtx = |value, lock_time| Transaction { version: 2, lock_time, input: Vec::new(), output: vec![TxOut {
+		script_pubkey: op_true_script.to_v0_p2wsh(), value,
+	}]};
+	let change_script = |b| Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[b; 20]).into_script();
+	let as_prevtx = prevtx(1_000_000, 1);
+	let bs_prevtx = prevtx(600_000, 2);
+
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), FundingContribution {
+		funding_satoshis: 500_000,
+		inputs: vec![FundingInput { prevtx: as_prevtx.clone(), prevtx_out: 0, sequence: 0xfffffffd }],
+		outputs: vec![TxOut { script_pubkey: change_script(1), value: 400_000 }],
+	}, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannelV2, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel_v2(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel);
+
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let temporary_channel_id = match events[0] {
+		Event::FundingContributionRequired { temporary_channel_id, counterparty_node_id, counterparty_funding_satoshis, .. } => {
+			assert_eq!(counterparty_node_id, nodes[0].node.get_our_node_id());
+			assert_eq!(counterparty_funding_satoshis, 500_000);
+			temporary_channel_id
+		},
+		_ => panic!("Unexpected event"),
+	};
+	nodes[1].node.contribute_channel_funding(&temporary_channel_id, FundingContribution {
+		funding_satoshis: 300_000,
+		inputs: vec![FundingInput { prevtx: bs_prevtx.clone(), prevtx_out: 0, sequence: 0xfffffffd }],
+		outputs: vec![TxOut { script_pubkey: change_script(2), value: 250_000 }],
+	}).unwrap();
+	let accept_channel = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannelV2, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_accept_channel_v2(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_channel);
+
+	// Shuttle interactive construction messages and the initial commitment_signed messages back
+	// and forth until both sides go quiet.
+	loop {
+		let mut delivered = false;
+		for (sender, receiver) in [(0, 1), (1, 0)].iter() {
+			let sender_id = nodes[*sender].node.get_our_node_id();
+			for event in nodes[*sender].node.get_and_clear_pending_msg_events() {
+				delivered = true;
+				match event {
+					MessageSendEvent::SendTxAddInput { ref msg, .. } => nodes[*receiver].node.handle_tx_add_input(&sender_id, msg),
+					MessageSendEvent::SendTxAddOutput { ref msg, .. } => nodes[*receiver].node.handle_tx_add_output(&sender_id, msg),
+					MessageSendEvent::SendTxComplete { ref msg, .. } => nodes[*receiver].node.handle_tx_complete(&sender_id, msg),
+					MessageSendEvent::UpdateHTLCs { ref updates, .. } => {
+						assert!(updates.update_add_htlcs.is_empty());
+						nodes[*receiver].node.handle_commitment_signed(&sender_id, &updates.commitment_signed);
+					},
+					_ => panic!("Unexpected event"),
+				}
+			}
+		}
+		if !delivered { break; }
+	}
+	check_added_monitors!(nodes[0], 1);
+	check_added_monitors!(nodes[1], 1);
+
+	let mut unsigned_txn = Vec::new();
+	for (node, prevtx) in [(&nodes[0], &as_prevtx), (&nodes[1], &bs_prevtx)].iter() {
+		let events = node.node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::FundingTransactionReadyForSigning { channel_id, ref unsigned_transaction, .. } => {
+				assert_eq!(unsigned_transaction.input.len(), 2);
+				assert_eq!(unsigned_transaction.output.len(), 3);
+				let mut signed_tx = unsigned_transaction.clone();
+				for input in signed_tx.input.iter_mut() {
+					if input.previous_output.txid == prevtx.txid() {
+						input.witness = vec![op_true_script.clone().into_bytes()];
+					}
+				}
+				unsigned_txn.push((channel_id, signed_tx));
+			},
+			_ => panic!("Unexpected event"),
+		}
+	}
+	assert_eq!(unsigned_txn[0].0, unsigned_txn[1].0);
+	let channel_id = unsigned_txn[0].0;
+
+	// nodes[1] contributed the lower input value so sends its witnesses first, nodes[0] holding
+	// on to its own until it has received them.
+	nodes[0].node.funding_transaction_signed(&channel_id, &unsigned_txn[0].1).unwrap();
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	nodes[1].node.funding_transaction_signed(&channel_id, &unsigned_txn[1].1).unwrap();
+	let bs_tx_signatures = get_event_msg!(nodes[1], MessageSendEvent::SendTxSignatures, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_tx_signatures(&nodes[1].node.get_our_node_id(), &bs_tx_signatures);
+	let as_tx_signatures = get_event_msg!(nodes[0], MessageSendEvent::SendTxSignatures, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_tx_signatures(&nodes[0].node.get_our_node_id(), &as_tx_signatures);
+
+	let funding_tx = {
+		let as_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		let bs_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		assert_eq!(as_txn.len(), 1);
+		assert_eq!(as_txn, bs_txn);
+		as_txn[0].clone()
+	};
+	assert_eq!(funding_tx.output.iter().map(|o| o.value).filter(|v| *v == 800_000).count(), 1);
+	check_spends!(funding_tx, as_prevtx, bs_prevtx);
+
+	let (funding_locked, _) = create_chan_between_nodes_with_value_confirm(&nodes[0], &nodes[1], &funding_tx);
+	let (announcement, as_update, bs_update) = create_chan_between_nodes_with_value_b(&nodes[0], &nodes[1], &funding_locked);
+	for node in nodes.iter() {
+		assert!(node.net_graph_msg_handler.handle_channel_announcement(&announcement).unwrap());
+		node.net_graph_msg_handler.handle_channel_update(&as_update).unwrap();
+		node.net_graph_msg_handler.handle_channel_update(&bs_update).unwrap();
+	}
+	assert_eq!(nodes[0].node.list_usable_channels().len(), 1);
+	assert_eq!(nodes[1].node.list_usable_channels().len(), 1);
+
+	send_payment(&nodes[0], &[&nodes[1]], 40_000_000, 40_000_000);
+	send_payment(&nodes[1], &[&nodes[0]], 40_000_000, 40_000_000);
+}
+
+#[test]
+fn test_splice_in_and_out() {
+	// Splice funds into a channel and then back out of it, walking through the interactive
+	// construction and signing of each splice transaction, then check the channel remains usable
+	// while the splice confirms and switches over to the new funding output once it does.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100_000, 10_000_000, InitFeatures::known(), InitFeatures::known());
+	let channel_id = chan.2;
+	let original_funding_txo = nodes[0].node.channel_state.lock().unwrap().by_id.get(&channel_id).unwrap().get_funding_txo().unwrap();
+
+	let deliver_msgs = || {
+		loop {
+			let mut delivered = false;
+			for (sender, receiver) in [(0, 1), (1, 0)].iter() {
+				let sender_id = nodes[*sender].node.get_our_node_id();
+				for event in nodes[*sender].node.get_and_clear_pending_msg_events() {
+					delivered = true;
+					match event {
+						MessageSendEvent::SendSpliceInit { ref msg, .. } => nodes[*receiver].node.handle_splice_init(&sender_id, msg),
+						MessageSendEvent::SendSpliceAck { ref msg, .. } => nodes[*receiver].node.handle_splice_ack(&sender_id, msg),
+						MessageSendEvent::SendTxAddInput { ref msg, .. } => nodes[*receiver].node.handle_tx_add_input(&sender_id, msg),
+						MessageSendEvent::SendTxAddOutput { ref msg, .. } => nodes[*receiver].node.handle_tx_add_output(&sender_id, msg),
+						MessageSendEvent::SendTxComplete { ref msg, .. } => nodes[*receiver].node.handle_tx_complete(&sender_id, msg),
+						MessageSendEvent::SendTxSignatures { ref msg, .. } => nodes[*receiver].node.handle_tx_signatures(&sender_id, msg),
+						MessageSendEvent::SendSpliceLocked { ref msg, .. } => nodes[*receiver].node.handle_splice_locked(&sender_id, msg),
+						MessageSendEvent::SendAnnouncementSignatures { ref msg, .. } => nodes[*receiver].node.handle_announcement_signatures(&sender_id, msg),
+						MessageSendEvent::UpdateHTLCs { ref updates, .. } => {
+							assert!(updates.update_add_htlcs.is_empty());
+							nodes[*receiver].node.handle_commitment_signed(&sender_id, &updates.commitment_signed);
+						},
+						MessageSendEvent::BroadcastChannelAnnouncement { ref msg, ref update_msg } => {
+							// Both nodes broadcast the new announcement, so we'll see it twice
+							for node in nodes.iter() {
+								let _ = node.net_graph_msg_handler.handle_channel_announcement(msg);
+								node.net_graph_msg_handler.handle_channel_update(update_msg).unwrap();
+							}
+						},
+						_ => panic!("Unexpected event"),
+					}
+				}
+			}
+			if !delivered { break; }
+		}
+	};
+	let confirm_splice = |splice_tx: &Transaction, tx_index: u32| {
+		let prev_short_channel_id = nodes[0].node.list_channels()[0].short_channel_id.unwrap();
+		for node in nodes.iter() {
+			confirm_transaction(&node.block_notifier, &node.chain_monitor, splice_tx, tx_index);
+		}
+		deliver_msgs();
+		check_added_monitors!(nodes[0], 1);
+		check_added_monitors!(nodes[1], 1);
+		for node in nodes.iter() {
+			// Forget about the channel's previous funding output, now spent
+			node.net_graph_msg_handler.handle_htlc_fail_channel_update(&msgs::HTLCFailChannelUpdate::ChannelClosed { short_channel_id: prev_short_channel_id, is_permanent: true });
+		}
+		for node in nodes.iter() {
+			let channels = node.node.list_usable_channels();
+			assert_eq!(channels.len(), 1);
+			assert_ne!(channels[0].short_channel_id, Some(prev_short_channel_id));
+		}
+	};
+
+	// nodes[0] splices in 200_000 sat from an input of its own, getting some change back
+	let op_true_script = Builder::new().push_opcode(opcodes::OP_TRUE).into_script();
+	let as_prevtx = Transaction { version: 2, lock_time: 0, input: Vec::new(), output: vec![TxOut {
+		script_pubkey: op_true_script.to_v0_p2wsh(), value: 300_000,
+	}]};
+	let change_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[1; 20]).into_script();
+	nodes[0].node.splice_in(&channel_id, FundingContribution {
+		funding_satoshis: 200_000,
+		inputs: vec![FundingInput { prevtx: as_prevtx.clone(), prevtx_out: 0, sequence: 0xfffffffd }],
+		outputs: vec![TxOut { script_pubkey: change_script, value: 90_000 }],
+	}).unwrap();
+	// The channel can't be used while it's being spliced
+	assert!(nodes[0].node.list_usable_channels().is_empty());
+	deliver_msgs();
+	check_added_monitors!(nodes[0], 1);
+	check_added_monitors!(nodes[1], 1);
+
+	// nodes[1] contributed no inputs and so sent its tx_signatures already
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let signed_tx = match events[0] {
+		Event::FundingTransactionReadyForSigning { channel_id: event_channel_id, ref unsigned_transaction, .. } => {
+			assert_eq!(event_channel_id, channel_id);
+			assert_eq!(unsigned_transaction.input.len(), 2);
+			let mut signed_tx = unsigned_transaction.clone();
+			for input in signed_tx.input.iter_mut() {
+				if input.previous_output.txid == as_prevtx.txid() {
+					input.witness = vec![op_true_script.clone().into_bytes()];
+				}
+			}
+			signed_tx
+		},
+		_ => panic!("Unexpected event"),
+	};
+	nodes[0].node.funding_transaction_signed(&channel_id, &signed_tx).unwrap();
+	deliver_msgs();
+
+	let splice_in_tx = {
+		let as_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		let bs_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		assert_eq!(as_txn.len(), 1);
+		assert_eq!(as_txn, bs_txn);
+		as_txn[0].clone()
+	};
+	assert_eq!(splice_in_tx.output.iter().filter(|o| o.value == 300_000).count(), 1);
+	check_spends!(splice_in_tx, chan.3, as_prevtx);
+
+	confirm_splice(&splice_in_tx, 2);
+	assert_eq!(nodes[0].node.list_usable_channels()[0].channel_value_satoshis, 300_000);
+	// nodes[0] can now send more than its balance before the splice
+	for _ in 0..12 {
+		send_payment(&nodes[0], &[&nodes[1]], 9_000_000, 9_000_000);
+	}
+
+	// nodes[1] then splices 100_000 sat out of its balance, which contains no inputs at all
+	let splice_out_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[2; 20]).into_script();
+	nodes[1].node.splice_out(&channel_id, 100_000, splice_out_script.clone()).unwrap();
+	deliver_msgs();
+	check_added_monitors!(nodes[0], 1);
+	check_added_monitors!(nodes[1], 1);
+	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+
+	let splice_out_tx = {
+		let as_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		let bs_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		assert_eq!(as_txn.len(), 1);
+		assert_eq!(as_txn, bs_txn);
+		as_txn[0].clone()
+	};
+	assert_eq!(splice_out_tx.input.len(), 1);
+	assert_eq!(splice_out_tx.output.iter().filter(|o| o.script_pubkey == splice_out_script && o.value == 100_000).count(), 1);
+	check_spends!(splice_out_tx, splice_in_tx);
+
+	// Once the splice transaction is signed, the channel remains usable until it confirms, with
+	// each commitment transaction signed for both funding outputs. Leave one HTLC pending across
+	// the switch to the new funding output.
+	assert_eq!(nodes[0].node.list_usable_channels().len(), 1);
+	assert_eq!(nodes[1].node.list_usable_channels().len(), 1);
+	send_payment(&nodes[0], &[&nodes[1]], 5_000_000, 5_000_000);
+	send_payment(&nodes[1], &[&nodes[0]], 5_000_000, 5_000_000);
+	let (payment_preimage, _) = route_payment(&nodes[1], &[&nodes[0]], 3_000_000);
+
+	confirm_splice(&splice_out_tx, 3);
+	assert!(nodes[0].node.list_usable_channels()[0].channel_value_satoshis < 200_000);
+	// Our latest commitment transaction now spends the splice transaction, still including the
+	// pending HTLC
+	let local_txn = get_local_commitment_txn!(nodes[0], channel_id);
+	assert_eq!(local_txn.len(), 1);
+	check_spends!(local_txn[0], splice_out_tx);
+	assert_eq!(local_txn[0].output.len(), 3);
+	claim_payment(&nodes[1], &[&nodes[0]], payment_preimage, 3_000_000);
+
+	// The ChannelMonitors remain keyed by the channel's original funding outpoint
+	for node in nodes.iter() {
+		let monitors = node.chan_monitor.simple_monitor.monitors.lock().unwrap();
+		assert_eq!(monitors.len(), 1);
+		let monitor = monitors.get(&original_funding_txo).unwrap();
+		assert_eq!(monitor.get_funding_txo().0.txid, splice_out_tx.txid());
+		assert_eq!(monitor.get_original_funding_txo(), original_funding_txo);
+	}
+	send_payment(&nodes[0], &[&nodes[1]], 10_000_000, 10_000_000);
+	send_payment(&nodes[1], &[&nodes[0]], 10_000_000, 10_000_000);
+}
+
+#[test]
+fn test_manually_accept_inbound_channels() {
+	// With manually_accept_inbound_channels set, inbound channel requests generate an
+	// OpenChannelRequest event and wait for the user to accept or reject them, being rejected
+	// automatically if they aren't answered in time.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut manual_accept_config = UserConfig::default();
+	manual_accept_config.channel_options.announced_channel = true;
+	manual_accept_config.manually_accept_inbound_channels = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(manual_accept_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let open_channel_request = || {
+		nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+		let open_channel_message = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+		nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel_message);
+		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+		let events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::OpenChannelRequest { temporary_channel_id, counterparty_node_id, funding_satoshis, push_msat, .. } => {
+				assert_eq!(temporary_channel_id, open_channel_message.temporary_channel_id);
+				assert_eq!(counterparty_node_id, nodes[0].node.get_our_node_id());
+				assert_eq!(funding_satoshis, 100000);
+				assert_eq!(push_msat, 10001);
+			},
+			_ => panic!("Unexpected event"),
+		}
+		open_channel_message.temporary_channel_id
+	};
+	let expect_error_message = |temporary_channel_id: [u8; 32], expected_data: &str| {
+		let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+		assert_eq!(msg_events.len(), 1);
+		match msg_events[0] {
+			MessageSendEvent::HandleError { ref node_id, action: ErrorAction::SendErrorMessage { ref msg } } => {
+				assert_eq!(*node_id, nodes[0].node.get_our_node_id());
+				assert_eq!(msg.channel_id, temporary_channel_id);
+				assert_eq!(msg.data, expected_data);
+			},
+			_ => panic!("Unexpected event"),
+		}
+	};
+
+	// A rejected request is answered with an error message, after which it can't be accepted
+	let rejected_channel_id = open_channel_request();
+	nodes[1].node.reject_inbound_channel(&rejected_channel_id).unwrap();
+	expect_error_message(rejected_channel_id, "Channel request rejected");
+	match nodes[1].node.accept_inbound_channel(&rejected_channel_id) {
+		Err(APIError::ChannelUnavailable { err }) => assert_eq!(err, "No such channel request"),
+		_ => panic!("Unexpected result"),
+	}
+
+	// An unanswered request is rejected after two timer ticks
+	let timed_out_channel_id = open_channel_request();
+	nodes[1].node.timer_chan_freshness_every_min();
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	nodes[1].node.timer_chan_freshness_every_min();
+	expect_error_message(timed_out_channel_id, "Channel request timed out");
+
+	// An accepted request proceeds as usual
+	let accepted_channel_id = open_channel_request();
+	nodes[1].node.accept_inbound_channel(&accepted_channel_id).unwrap();
+	let accept_channel_message = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
+	assert_eq!(accept_channel_message.temporary_channel_id, accepted_channel_id);
+	nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_channel_message);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::FundingGenerationReady { temporary_channel_id, channel_value_satoshis, .. } => {
+			assert_eq!(temporary_channel_id, accepted_channel_id);
+			assert_eq!(channel_value_satoshis, 100000);
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_update_channel_config() {
+	// Updating a channel's forwarding policy broadcasts a fresh channel_update, after which HTLCs
+	// forwarded according to the previous policy are only accepted for EXPIRE_PREV_CONFIG_TICKS
+	// timer ticks.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let chan = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let old_route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+
+	let mut config = ChannelConfig::default();
+	config.fee_base_msat = Some(10_000);
+	config.fee_proportional_millionths = 1000;
+	config.forwarding_htlc_maximum_msat = Some(1_000_000);
+	config.cltv_expiry_delta = MIN_CLTV_EXPIRY_DELTA - 1;
+	match nodes[1].node.update_channel_config(&chan.2, &config) {
+		Err(APIError::APIMisuseError { err }) => assert_eq!(err, "cltv_expiry_delta must be at least MIN_CLTV_EXPIRY_DELTA"),
+		_ => panic!("Unexpected result"),
+	}
+	config.cltv_expiry_delta = MIN_CLTV_EXPIRY_DELTA + 6;
+	nodes[1].node.update_channel_config(&chan.2, &config).unwrap();
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let channel_update = match events[0] {
+		MessageSendEvent::BroadcastChannelUpdate { ref msg } => {
+			assert_eq!(msg.contents.short_channel_id, chan.0.contents.short_channel_id);
+			assert!(msg.contents.timestamp > chan.0.contents.timestamp);
+			assert_eq!(msg.contents.fee_base_msat, 10_000);
+			assert_eq!(msg.contents.fee_proportional_millionths, 1000);
+			assert_eq!(msg.contents.cltv_expiry_delta, MIN_CLTV_EXPIRY_DELTA + 6);
+			assert_eq!(msg.contents.htlc_maximum_msat, msgs::OptionalField::Present(1_000_000));
+			msg.clone()
+		},
+		_ => panic!("Unexpected event"),
+	};
+
+	// An update which doesn't change anything doesn't generate a new channel_update
+	nodes[1].node.update_channel_config(&chan.2, &config).unwrap();
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+	// Payments following the old policy are still forwarded for a grace period...
+	for _ in 1..EXPIRE_PREV_CONFIG_TICKS {
+		nodes[1].node.timer_chan_freshness_every_min();
+	}
+	let payment_preimage = send_along_route(&nodes[0], old_route.clone(), &[&nodes[1], &nodes[2]], 100000).0;
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 100000);
+
+	// ...but are rejected once it has expired
+	nodes[1].node.timer_chan_freshness_every_min();
+	let (_, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	nodes[0].node.send_payment(&old_route, payment_hash, &None).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	nodes[0].node.get_and_clear_pending_msg_events();
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { payment_hash: ref failed_hash, rejected_by_dest, ref failure, .. } => {
+			assert_eq!(*failed_hash, payment_hash);
+			assert!(!rejected_by_dest);
+			let failure = failure.as_ref().unwrap();
+			assert_eq!(failure.failure_code, OnionFailureCode::FeeInsufficient);
+			assert_eq!(failure.failing_node_id, Some(nodes[1].node.get_our_node_id()));
+			assert_eq!(failure.short_channel_id, Some(chan.0.contents.short_channel_id));
+			assert_eq!(failure.channel_update.as_ref().unwrap().contents.fee_base_msat, 10_000);
+			assert!(!failure.is_permanent());
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	// Once the new policy is known, payments following it are forwarded
+	nodes[0].net_graph_msg_handler.handle_channel_update(&channel_update).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	assert_eq!(route.paths[0][0].fee_msat, 10_000 + 100);
+	assert_eq!(route.paths[0][0].cltv_expiry_delta, (MIN_CLTV_EXPIRY_DELTA + 6) as u32);
+	let payment_preimage = send_along_route(&nodes[0], route, &[&nodes[1], &nodes[2]], 100000).0;
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 100000);
+}
+
+#[test]
+fn test_payment_forwarded() {
+	// Test that an intermediate node generates a PaymentForwarded event when it claims an HTLC
+	// off-chain, reporting the fee it earned along with the channels the HTLC was forwarded over.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
+
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let fee_msat = route.paths[0][0].fee_msat;
+	assert!(fee_msat > 0);
+	let payment_preimage = send_along_route(&nodes[0], route, &[&nodes[1], &nodes[2]], 100000).0;
+
+	assert!(nodes[2].node.claim_funds(payment_preimage, &None, 100000));
+	check_added_monitors!(nodes[2], 1);
+	let updates = get_htlc_update_msgs!(nodes[2], nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_update_fulfill_htlc(&nodes[2].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	check_added_monitors!(nodes[1], 1);
+
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentForwarded { prev_channel_id, next_channel_id, amt_in_msat, amt_out_msat, fee_earned_msat, claim_from_onchain_tx } => {
+			assert_eq!(prev_channel_id, chan_1.2);
+			assert_eq!(next_channel_id, chan_2.2);
+			assert_eq!(amt_in_msat, 100000 + fee_msat);
+			assert_eq!(amt_out_msat, 100000);
+			assert_eq!(fee_earned_msat, fee_msat);
+			assert!(!claim_from_onchain_tx);
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	let prev_updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	commitment_signed_dance!(nodes[1], nodes[2], updates.commitment_signed, false);
+	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &prev_updates.update_fulfill_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], prev_updates.commitment_signed, false);
+	expect_payment_sent!(nodes[0], payment_preimage);
+}
+
+#[test]
+fn test_payment_forwarded_inbound_channel_closed() {
+	// Test that an intermediate node still generates a PaymentForwarded event when the outbound
+	// HTLC is claimed after the inbound channel has been closed, in which case the inbound HTLC is
+	// claimed on-chain.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
+
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let fee_msat = route.paths[0][0].fee_msat;
+	let payment_preimage = send_along_route(&nodes[0], route, &[&nodes[1], &nodes[2]], 100000).0;
+
+	nodes[1].node.force_close_channel(&chan_1.2);
+	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
+	check_added_monitors!(nodes[1], 1);
+	assert!(nodes[1].node.list_channels().iter().all(|chan| chan.channel_id != chan_1.2));
+
+	assert!(nodes[2].node.claim_funds(payment_preimage, &None, 100000));
+	check_added_monitors!(nodes[2], 1);
+	let updates = get_htlc_update_msgs!(nodes[2], nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_update_fulfill_htlc(&nodes[2].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentForwarded { prev_channel_id, next_channel_id, amt_in_msat, amt_out_msat, fee_earned_msat, claim_from_onchain_tx } => {
+			assert_eq!(prev_channel_id, chan_1.2);
+			assert_eq!(next_channel_id, chan_2.2);
+			assert_eq!(amt_in_msat, 100000 + fee_msat);
+			assert_eq!(amt_out_msat, 100000);
+			assert_eq!(fee_earned_msat, fee_msat);
+			assert!(!claim_from_onchain_tx);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	commitment_signed_dance!(nodes[1], nodes[2], updates.commitment_signed, false);
+}
+
+#[test]
+fn test_funding_timeout_closes_inbound_channel() {
+	// Test that an inbound channel whose funding transaction never confirms is closed once
+	// FUNDING_CONF_DEADLINE_BLOCKS have passed, and that both sides are told why via ChannelClosed.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	create_chan_between_nodes_with_value_init(&nodes[0], &nodes[1], 100000, 10001, InitFeatures::known(), InitFeatures::known());
+	let channel_id = nodes[1].node.list_channels()[0].channel_id;
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let block_hash = connect_blocks(&nodes[1].block_notifier, FUNDING_CONF_DEADLINE_BLOCKS - 1, 0, false, header.bitcoin_hash());
+	assert_eq!(nodes[1].node.list_channels().len(), 1);
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+
+	connect_blocks(&nodes[1].block_notifier, 1, FUNDING_CONF_DEADLINE_BLOCKS - 1, true, block_hash);
+	assert!(nodes[1].node.list_channels().is_empty());
+	check_added_monitors!(nodes[1], 1);
+	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+	let err_msg = match msg_events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { ref msg }, ref node_id } => {
+			assert_eq!(*node_id, nodes[0].node.get_our_node_id());
+			assert_eq!(msg.channel_id, channel_id);
+			msg.clone()
+		},
+		_ => panic!("Unexpected event"),
+	};
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::ChannelClosed { channel_id: closed_channel_id, user_id, ref reason } => {
+			assert_eq!(closed_channel_id, channel_id);
+			assert_eq!(user_id, 0);
+			assert_eq!(*reason, ClosureReason::FundingTimedOut);
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	// The funder learns of the closure from the error message we send it.
+	nodes[0].node.handle_error(&nodes[1].node.get_our_node_id(), &err_msg);
+	assert!(nodes[0].node.list_channels().is_empty());
+	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CounterpartyForceClosed { peer_msg: "Funding transaction was not confirmed in time".to_string() });
+}
+
+#[test]
+fn test_htlc_interception() {
+	// HTLCs to an intercepted short channel id which doesn't belong to any of our channels are
+	// held until the user forwards them over a channel of their choosing or fails them, or until
+	// they get close to expiring.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let chan = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let intercept_scid = 42;
+	route.paths[0][1].short_channel_id = intercept_scid;
+	nodes[1].node.add_intercept_scid(intercept_scid);
+
+	let intercept_payment = |payment_hash: PaymentHash| {
+		nodes[0].node.send_payment(&route, payment_hash, &None).unwrap();
+		check_added_monitors!(nodes[0], 1);
+		let payment_event = SendEvent::from_node(&nodes[0]);
+		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+		commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+		let events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::HTLCIntercepted { intercept_id, requested_next_hop_scid, payment_hash: intercepted_hash, inbound_amount_msat, expected_outbound_amount_msat, outgoing_cltv_value } => {
+				assert_eq!(requested_next_hop_scid, intercept_scid);
+				assert_eq!(intercepted_hash, payment_hash);
+				assert_eq!(inbound_amount_msat, 100000 + route.paths[0][0].fee_msat);
+				assert_eq!(expected_outbound_amount_msat, 100000);
+				(intercept_id, outgoing_cltv_value)
+			},
+			_ => panic!("Unexpected event"),
+		}
+	};
+
+	// Forward an intercepted HTLC over the channel to nodes[2]
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	let (intercept_id, _) = intercept_payment(payment_hash);
+	match nodes[1].node.forward_intercepted_htlc(intercept_id, &[0; 32], 100000) {
+		Err(APIError::ChannelUnavailable { err }) => assert_eq!(err, "No such channel"),
+		_ => panic!("Unexpected result"),
+	}
+	// The HTLC stays intercepted if the channel's cltv_expiry_delta doesn't fit in its CLTV expiry
+	let mut config = ChannelConfig::default();
+	config.cltv_expiry_delta = route.paths[0][0].cltv_expiry_delta as u16 + 1;
+	nodes[1].node.update_channel_config(&chan.2, &config).unwrap();
+	match nodes[1].node.forward_intercepted_htlc(intercept_id, &chan.2, 100000) {
+		Err(APIError::ChannelUnavailable { err }) => assert_eq!(err, "The HTLC's CLTV expiry doesn't leave room for the channel's cltv_expiry_delta"),
+		_ => panic!("Unexpected result"),
+	}
+	config.cltv_expiry_delta = route.paths[0][0].cltv_expiry_delta as u16;
+	nodes[1].node.update_channel_config(&chan.2, &config).unwrap();
+	assert_eq!(nodes[1].node.get_and_clear_pending_msg_events().len(), 2);
+	nodes[1].node.forward_intercepted_htlc(intercept_id, &chan.2, 100000).unwrap();
+	match nodes[1].node.forward_intercepted_htlc(intercept_id, &chan.2, 100000) {
+		Err(APIError::APIMisuseError { err }) => assert_eq!(err, "No such intercepted HTLC"),
+		_ => panic!("Unexpected result"),
+	}
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let mut events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	pass_along_path(&nodes[1], &[&nodes[2]], 100000, payment_hash, None, events.remove(0), true);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 100000);
+
+	// Fail an intercepted HTLC back, which looks to the sender as if we didn't know the next hop
+	let (_, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	let (intercept_id, _) = intercept_payment(payment_hash);
+	nodes[1].node.fail_intercepted_htlc(intercept_id).unwrap();
+	assert!(nodes[1].node.fail_intercepted_htlc(intercept_id).is_err());
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	nodes[0].node.get_and_clear_pending_msg_events();
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { payment_hash: ref failed_hash, rejected_by_dest, ref failure, .. } => {
+			assert_eq!(*failed_hash, payment_hash);
+			assert!(!rejected_by_dest);
+			let failure = failure.as_ref().unwrap();
+			assert_eq!(failure.failure_code, OnionFailureCode::UnknownNextPeer);
+			assert_eq!(failure.failing_node_id, Some(nodes[1].node.get_our_node_id()));
+			assert_eq!(failure.short_channel_id, Some(intercept_scid));
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	// An intercepted HTLC the user doesn't act on is failed back once it gets close to expiring
+	let (_, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	let (intercept_id, outgoing_cltv_value) = intercept_payment(payment_hash);
+	let cur_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32;
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let block_hash = connect_blocks(&nodes[1].block_notifier, outgoing_cltv_value - HTLC_FAIL_BACK_BUFFER - cur_height - 1, cur_height, true, header.bitcoin_hash());
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	connect_blocks(&nodes[1].block_notifier, 1, outgoing_cltv_value - HTLC_FAIL_BACK_BUFFER - 1, true, block_hash);
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	nodes[0].node.get_and_clear_pending_msg_events();
+	expect_payment_failed!(nodes[0], payment_hash, false, 0x4000 | 10, &[0; 0]);
+	assert!(nodes[1].node.fail_intercepted_htlc(intercept_id).is_err());
+
+	// HTLCs to an intercepted short channel id are subject to the same CLTV checks as any other
+	// forward, and failed with temporary_node_failure as there's no channel_update to provide
+	let (_, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	let mut route = route.clone();
+	route.paths[0][0].cltv_expiry_delta = MIN_CLTV_EXPIRY_DELTA as u32 - 1;
+	nodes[0].node.send_payment(&route, payment_hash, &None).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let payment_event = SendEvent::from_node(&nodes[0]);
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	nodes[0].node.get_and_clear_pending_msg_events();
+	expect_payment_failed!(nodes[0], payment_hash, false, 0x2000 | 2, &[0; 0]);
+}
+
+#[test]
+fn test_stateless_inbound_payments() {
+	// Payments to invoices from create_inbound_payment are verified without any per-payment state,
+	// with the preimage provided in PaymentReceived if we generated the payment hash and payments
+	// which underpay or arrive after the expiry failed backwards automatically.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	// Until we've seen a block we can't tell when payments should expire
+	match nodes[1].node.create_inbound_payment(None, 3600) {
+		Err(APIError::APIMisuseError { err }) => assert_eq!(err, "A block must be connected before creating inbound payments"),
+		_ => panic!("Unexpected result"),
+	}
+
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = test_utils::TestScorer::new();
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+
+	match nodes[1].node.create_inbound_payment(Some(2_100_000_000_000_000_001), 3600) {
+		Err(APIError::APIMisuseError { err }) => assert_eq!(err, "min_value_msat must not exceed the total bitcoin supply"),
+		_ => panic!("Unexpected result"),
+	}
+
+	let send_payment = |payment_hash: PaymentHash, payment_secret: PaymentSecret, amt_msat: u64| {
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), amt_msat, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[0].node.send_payment(&route, payment_hash, &Some(payment_secret)).unwrap();
+		check_added_monitors!(nodes[0], 1);
+		let payment_event = SendEvent::from_node(&nodes[0]);
+		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+		commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+		expect_pending_htlcs_forwardable!(nodes[1]);
+	};
+	let expect_failed_back = |payment_hash: PaymentHash, amt_msat: u64| {
+		expect_pending_htlcs_forwardable!(nodes[1]);
+		check_added_monitors!(nodes[1], 1);
+		let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+		assert_eq!(updates.update_fail_htlcs.len(), 1);
+		nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+		commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+		let mut expected_failure_data = byte_utils::be64_to_array(amt_msat).to_vec();
+		expected_failure_data.extend_from_slice(&byte_utils::be32_to_array(nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32));
+		expect_payment_failed!(nodes[0], payment_hash, true, 0x4000 | 15, &expected_failure_data[..]);
+	};
+
+	// We provide the preimage of payments to hashes we generated
+	let (payment_hash, payment_secret) = nodes[1].node.create_inbound_payment(Some(100000), 3600).unwrap();
+	send_payment(payment_hash, payment_secret, 100000);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let payment_preimage = match events[0] {
+		Event::PaymentReceived { payment_hash: received_hash, payment_secret: received_secret, payment_preimage: Some(preimage), amt } => {
+			assert_eq!(received_hash, payment_hash);
+			assert_eq!(received_secret, Some(payment_secret));
+			assert_eq!(amt, 100000);
+			preimage
+		},
+		_ => panic!("Unexpected event"),
+	};
+	assert_eq!(PaymentHash(Sha256::hash(&payment_preimage.0).into_inner()), payment_hash);
+	claim_payment_along_route_with_secret(&nodes[0], &[&[&nodes[1]]], false, payment_preimage, Some(payment_secret), 100000);
+
+	// Payments which underpay are failed backwards without the user seeing them
+	let (payment_hash, payment_secret) = nodes[1].node.create_inbound_payment(Some(100000), 3600).unwrap();
+	send_payment(payment_hash, payment_secret, 99999);
+	expect_failed_back(payment_hash, 99999);
+
+	// Payments to hashes the user provided are verified without a preimage being provided
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	let payment_secret = nodes[1].node.create_inbound_payment_for_hash(payment_hash, None, 3600).unwrap();
+	send_payment(payment_hash, payment_secret, 100000);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentReceived { payment_hash: received_hash, payment_preimage: None, amt, .. } => {
+			assert_eq!(received_hash, payment_hash);
+			assert_eq!(amt, 100000);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	claim_payment_along_route_with_secret(&nodes[0], &[&[&nodes[1]]], false, payment_preimage, Some(payment_secret), 100000);
+
+	// Payments are failed backwards once a block past their expiry (plus the grace period) is seen
+	let (payment_hash, payment_secret) = nodes[1].node.create_inbound_payment(None, 3600).unwrap();
+	let cur_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32;
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42 + 3600 + 7200 + 1, bits: 42, nonce: 42 };
+	nodes[1].block_notifier.block_connected_checked(&header, cur_height + 1, &[], &[]);
+	send_payment(payment_hash, payment_secret, 100000);
+	expect_failed_back(payment_hash, 100000);
+
+	// Payment secrets which we didn't create are left for the user to check
+	let (_, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	send_payment(payment_hash, PaymentSecret([42; 32]), 100000);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentReceived { payment_hash: received_hash, payment_secret, payment_preimage: None, .. } => {
+			assert_eq!(received_hash, payment_hash);
+			assert_eq!(payment_secret, Some(PaymentSecret([42; 32])));
+		},
+		_ => panic!("Unexpected event"),
+	}
+	assert!(nodes[1].node.fail_htlc_backwards(&payment_hash, &Some(PaymentSecret([42; 32]))));
+	expect_failed_back(payment_hash, 100000);
+}
+
+#[test]
+fn test_onion_replay_protection() {
+	// Onion packets we've already forwarded are rejected if they're sent to us again, even across
+	// a restart, lest they be used to probe whether the next hop is the payment's destination.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let logger: test_utils::TestLogger;
+	let fee_estimator: test_utils::TestFeeEstimator;
+	let new_chan_monitor: test_utils::TestChannelMonitor;
+	let keys_manager: test_utils::TestKeysInterface;
+	let nodes_1_deserialized: ChannelManager<EnforcingChannelKeys, &test_utils::TestChannelMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestLogger>;
+	let mut nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+
+	// Fix the session key so that sending the same payment twice results in the same onion
+	*nodes[0].keys_manager.override_session_priv.lock().unwrap() = Some(SecretKey::from_slice(&[3; 32]).unwrap());
+	let route = {
+		let route_logger = test_utils::TestLogger::new();
+		let scorer = test_utils::TestScorer::new();
+		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+		get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &route_logger, &scorer).unwrap()
+	};
+	let (payment_preimage, payment_hash) = send_along_route(&nodes[0], route.clone(), &[&nodes[1], &nodes[2]], 100000);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 100000);
+
+	// Restart nodes[1]
+	nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id(), false);
+	nodes[2].node.peer_disconnected(&nodes[1].node.get_our_node_id(), false);
+
+	let nodes_1_serialized = nodes[1].node.encode();
+	let mut node_1_monitors_serialized = Vec::new();
+	for monitor in nodes[1].chan_monitor.simple_monitor.monitors.lock().unwrap().iter() {
+		let mut writer = test_utils::TestVecWriter(Vec::new());
+		monitor.1.write_for_disk(&mut writer).unwrap();
+		node_1_monitors_serialized.push(writer.0);
+	}
+
+	logger = test_utils::TestLogger::new();
+	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 253 };
+	new_chan_monitor = test_utils::TestChannelMonitor::new(nodes[1].chain_monitor.clone(), nodes[1].tx_broadcaster.clone(), &logger, &fee_estimator);
+	nodes[1].chan_monitor = &new_chan_monitor;
+
+	let mut node_1_monitors = Vec::new();
+	for serialized in node_1_monitors_serialized.iter() {
+		let mut read = &serialized[..];
+		let (_, monitor) = <(BlockHash, ChannelMonitor<EnforcingChannelKeys>)>::read(&mut read).unwrap();
+		assert!(read.is_empty());
+		node_1_monitors.push(monitor);
+	}
+
+	keys_manager = test_utils::TestKeysInterface::new(&nodes[1].node_seed, Network::Testnet);
+	let mut nodes_1_read = &nodes_1_serialized[..];
+	let (_, nodes_1_deserialized_tmp) =
+		<(BlockHash, ChannelManager<EnforcingChannelKeys, &test_utils::TestChannelMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestLogger>)>::read(&mut nodes_1_read, ChannelManagerReadArgs {
+		default_config: UserConfig::default(),
+		keys_manager: &keys_manager,
+		fee_estimator: &fee_estimator,
+		monitor: nodes[1].chan_monitor,
+		tx_broadcaster: nodes[1].tx_broadcaster.clone(),
+		logger: &logger,
+		channel_monitors: &mut node_1_monitors.iter_mut().map(|monitor| { (monitor.get_original_funding_txo(), monitor) }).collect(),
+	}).unwrap();
+	nodes_1_deserialized = nodes_1_deserialized_tmp;
+	assert!(nodes_1_read.is_empty());
+
+	for monitor in node_1_monitors.drain(..) {
+		assert!(nodes[1].chan_monitor.add_monitor(monitor.get_original_funding_txo(), monitor).is_ok());
+		check_added_monitors!(nodes[1], 1);
+	}
+	nodes[1].node = &nodes_1_deserialized;
+	reconnect_nodes(&nodes[0], &nodes[1], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
+	reconnect_nodes(&nodes[1], &nodes[2], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
+
+	// Sending the same payment again, at the same height, replays the onion to nodes[1]
+	nodes[0].node.send_payment(&route, payment_hash, &None).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let payment_event = SendEvent::from_node(&nodes[0]);
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	nodes[0].node.get_and_clear_pending_msg_events();
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { payment_hash: ref failed_hash, rejected_by_dest, ref failure, .. } => {
+			assert_eq!(*failed_hash, payment_hash);
+			assert!(!rejected_by_dest);
+			let failure = failure.as_ref().unwrap();
+			assert_eq!(failure.failure_code, OnionFailureCode::TemporaryNodeFailure);
+			assert_eq!(failure.failing_node_id, Some(nodes[1].node.get_our_node_id()));
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_onion_replay_cache_pruning() {
+	// Forwarded onions are remembered until their outgoing CLTV is too close for us to accept a
+	// forward with it anyway, catching replays which reuse the shared secret with a later outgoing
+	// CLTV in the meantime.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+
+	// Fix the session key so that sending the same payment twice results in the same shared secret
+	*nodes[0].keys_manager.override_session_priv.lock().unwrap() = Some(SecretKey::from_slice(&[3; 32]).unwrap());
+	let route = {
+		let route_logger = test_utils::TestLogger::new();
+		let scorer = test_utils::TestScorer::new();
+		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+		get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &route_logger, &scorer).unwrap()
+	};
+	let (payment_preimage, payment_hash) = send_along_route(&nodes[0], route.clone(), &[&nodes[1], &nodes[2]], 100000);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 100000);
+	let cached_cltvs = nodes[1].node.onion_replay_cache_cltvs();
+	assert_eq!(cached_cltvs.len(), 1);
+	let outgoing_cltv_value = cached_cltvs[0];
+
+	// A few blocks later the same session key results in an onion with a later outgoing CLTV
+	for node in nodes.iter() {
+		let cur_height = node.node.latest_block_height.load(Ordering::Acquire) as u32;
+		connect_blocks(&node.block_notifier, 5, cur_height, false, Default::default());
+	}
+	nodes[0].node.send_payment(&route, payment_hash, &None).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let payment_event = SendEvent::from_node(&nodes[0]);
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	nodes[0].node.get_and_clear_pending_msg_events();
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { payment_hash: ref failed_hash, ref failure, .. } => {
+			assert_eq!(*failed_hash, payment_hash);
+			assert_eq!(failure.as_ref().unwrap().failure_code, OnionFailureCode::TemporaryNodeFailure);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	assert_eq!(nodes[1].node.onion_replay_cache_cltvs(), vec![outgoing_cltv_value]);
+
+	// The onion is forgotten once a block at its outgoing CLTV is connected
+	let cur_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32;
+	let block_hash = connect_blocks(&nodes[1].block_notifier, outgoing_cltv_value - 1 - cur_height, cur_height, false, Default::default());
+	assert_eq!(nodes[1].node.onion_replay_cache_cltvs(), vec![outgoing_cltv_value]);
+	connect_blocks(&nodes[1].block_notifier, 1, outgoing_cltv_value - 1, true, block_hash);
+	assert!(nodes[1].node.onion_replay_cache_cltvs().is_empty());
+}
+
+#[test]
+fn test_automatic_update_fee() {
+	// Channels we funded have their feerate brought back in line with our fee estimate once it
+	// drifts past the configured thresholds, and update_fees which deviate too far from our fee
+	// estimate are rejected.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.max_inbound_update_fee_deviation_percent = 300;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let channel_id = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known()).2;
+	let fee_estimate = get_feerate!(nodes[0], channel_id);
+
+	let set_feerate = |feerate: u32| {
+		nodes[0].node.update_fee(channel_id, feerate).unwrap();
+		check_added_monitors!(nodes[0], 1);
+		let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+		nodes[1].node.handle_update_fee(&nodes[0].node.get_our_node_id(), updates.update_fee.as_ref().unwrap());
+		commitment_signed_dance!(nodes[1], nodes[0], updates.commitment_signed, false);
+		assert_eq!(get_feerate!(nodes[0], channel_id), feerate);
+	};
+	let expect_fee_update = || {
+		nodes[0].node.timer_chan_freshness_every_min();
+		check_added_monitors!(nodes[0], 1);
+		let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+		assert_eq!(updates.update_fee.as_ref().unwrap().feerate_per_kw, fee_estimate);
+		nodes[1].node.handle_update_fee(&nodes[0].node.get_our_node_id(), updates.update_fee.as_ref().unwrap());
+		commitment_signed_dance!(nodes[1], nodes[0], updates.commitment_signed, false);
+		assert_eq!(get_feerate!(nodes[0], channel_id), fee_estimate);
+	};
+
+	// Nothing to do while our feerate is in line with our fee estimate...
+	nodes[0].node.timer_chan_freshness_every_min();
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	// ...or within the thresholds of it...
+	set_feerate(fee_estimate - 20);
+	nodes[0].node.timer_chan_freshness_every_min();
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	set_feerate(fee_estimate * 2 - 20);
+	nodes[0].node.timer_chan_freshness_every_min();
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	// ...but once our fee estimate rises or falls far enough we update the feerate to match it
+	set_feerate(fee_estimate - 40);
+	expect_fee_update();
+	set_feerate(fee_estimate * 2 + 20);
+	expect_fee_update();
+
+	// The fundee never sends update_fee
+	nodes[1].node.timer_chan_freshness_every_min();
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+	// nodes[1] accepts feerates of up to twice its fee estimate, widened by 300%, but no more
+	nodes[0].node.update_fee(channel_id, fee_estimate * 8 + 1).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_update_fee(&nodes[0].node.get_our_node_id(), updates.update_fee.as_ref().unwrap());
+	check_added_monitors!(nodes[1], 1);
+	check_closed_broadcast!(nodes[1], true);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Peer's feerate much too high".to_string() });
+}
+
+#[test]
+fn test_inbound_update_fee_default_bounds() {
+	// By default, update_fees are held to the same bounds as the feerate in open_channel, ie
+	// between our Background estimate and twice our HighPriority estimate.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let fee_estimate = node_cfgs[1].fee_estimator.sat_per_kw;
+
+	let send_update_fee = |channel_id: [u8; 32], feerate: u32| {
+		nodes[0].node.update_fee(channel_id, feerate).unwrap();
+		check_added_monitors!(nodes[0], 1);
+		let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+		nodes[1].node.handle_update_fee(&nodes[0].node.get_our_node_id(), updates.update_fee.as_ref().unwrap());
+		updates
+	};
+
+	// Feerates at both edges are accepted...
+	let channel_id = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known()).2;
+	for feerate in [fee_estimate, fee_estimate * 2].iter() {
+		let updates = send_update_fee(channel_id, *feerate);
+		commitment_signed_dance!(nodes[1], nodes[0], updates.commitment_signed, false);
+		assert_eq!(get_feerate!(nodes[1], channel_id), *feerate);
+	}
+
+	// ...but not beyond them
+	send_update_fee(channel_id, fee_estimate - 1);
+	check_added_monitors!(nodes[1], 1);
+	check_closed_broadcast!(nodes[1], true);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Peer's feerate much too low".to_string() });
+
+	let channel_id = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known()).2;
+	send_update_fee(channel_id, fee_estimate * 2 + 1);
+	check_added_monitors!(nodes[1], 1);
+	check_closed_broadcast!(nodes[1], true);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Peer's feerate much too high".to_string() });
+}
+
+#[test]
+fn test_automatic_update_fee_affordability() {
+	// The feerate of a channel we funded is only raised as far as we can pay for while keeping
+	// the reserve our counterparty requires of us.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.max_inbound_update_fee_deviation_percent = 1000;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	// Leave nodes[0] with only 1500 sat, 500 sat above its 1000 sat reserve
+	let channel_id = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 98_500_000, InitFeatures::known(), InitFeatures::known()).2;
+	let fee_estimate = get_feerate!(nodes[0], channel_id);
+
+	// Lower the feerate so that we can spend most of it despite the fee spike buffer
+	nodes[0].node.update_fee(channel_id, 30).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_update_fee(&nodes[0].node.get_our_node_id(), updates.update_fee.as_ref().unwrap());
+	commitment_signed_dance!(nodes[1], nodes[0], updates.commitment_signed, false);
+	// Spend down to 145 sat above our reserve, enough for a feerate of 145 * 1000 / 724 = 200
+	send_payment(&nodes[0], &[&nodes[1]], 355_000, 355_000);
+
+	// Our fee estimate is above what we can afford, so we only raise the feerate to 200...
+	nodes[0].node.timer_chan_freshness_every_min();
+	check_added_monitors!(nodes[0], 1);
+	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+	assert!(fee_estimate > 200);
+	assert_eq!(updates.update_fee.as_ref().unwrap().feerate_per_kw, 200);
+	nodes[1].node.handle_update_fee(&nodes[0].node.get_our_node_id(), updates.update_fee.as_ref().unwrap());
+	commitment_signed_dance!(nodes[1], nodes[0], updates.commitment_signed, false);
+	assert_eq!(get_feerate!(nodes[0], channel_id), 200);
+
+	// ...and don't try to raise it any further
+	nodes[0].node.timer_chan_freshness_every_min();
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+}
+
+#[test]
+fn test_pending_inbound_channel_requests_limits() {
+	// Unanswered inbound channel requests are capped per peer and across all peers, beyond which
+	// further requests are rejected.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut manual_accept_config = UserConfig::default();
+	manual_accept_config.channel_options.announced_channel = true;
+	manual_accept_config.manually_accept_inbound_channels = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(manual_accept_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let secp_ctx = Secp256k1::new();
+
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+	let mut open_channel_message = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+	let mut open_channel_request = |counterparty_node_id: &PublicKey, temporary_channel_id: [u8; 32]| {
+		open_channel_message.temporary_channel_id = temporary_channel_id;
+		nodes[1].node.handle_open_channel(counterparty_node_id, InitFeatures::known(), &open_channel_message);
+		let events = nodes[1].node.get_and_clear_pending_events();
+		let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+		match msg_events.get(0) {
+			Some(MessageSendEvent::HandleError { ref node_id, action: ErrorAction::SendErrorMessage { ref msg } }) => {
+				assert!(events.is_empty());
+				assert_eq!(*node_id, *counterparty_node_id);
+				assert_eq!(msg.channel_id, temporary_channel_id);
+				Some(msg.data.clone())
+			},
+			None => {
+				assert_eq!(events.len(), 1);
+				None
+			},
+			_ => panic!("Unexpected event"),
+		}
+	};
+
+	// A single peer may only have two requests pending at once
+	let node_a_id = nodes[0].node.get_our_node_id();
+	assert_eq!(open_channel_request(&node_a_id, [1; 32]), None);
+	assert_eq!(open_channel_request(&node_a_id, [2; 32]), None);
+	assert_eq!(open_channel_request(&node_a_id, [3; 32]).unwrap(), "Too many pending channel requests from this peer");
+
+	// Answering one of them makes room for another
+	nodes[1].node.reject_inbound_channel(&[1; 32]).unwrap();
+	nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(open_channel_request(&node_a_id, [3; 32]), None);
+
+	// Across all peers, at most 100 requests may be pending
+	for i in 0..98u8 {
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[i + 1; 32]).unwrap());
+		assert_eq!(open_channel_request(&node_id, [i + 4; 32]), None);
+	}
+	let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[200; 32]).unwrap());
+	assert_eq!(open_channel_request(&node_id, [200; 32]).unwrap(), "Too many pending channel requests");
+}
+
+#[test]
+fn test_pending_dual_funded_channel_requests() {
+	// Dual-funded channel requests awaiting our contribution can be rejected, time out and are
+	// capped per peer just like those awaiting manual acceptance.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	let node_a_id = nodes[0].node.get_our_node_id();
+
+	let op_true_script = Builder::new().push_opcode(opcodes::OP_TRUE).into_script();
+	let prevtx = Transaction { version: 2, lock_time: 0, input: Vec::new(), output: vec![TxOut {
+		script_pubkey: op_true_script.to_v0_p2wsh(), value: 1_000_000,
+	}]};
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), FundingContribution {
+		funding_satoshis: 500_000,
+		inputs: vec![FundingInput { prevtx, prevtx_out: 0, sequence: 0xfffffffd }],
+		outputs: Vec::new(),
+	}, 42, None).unwrap();
+	let mut open_channel_message = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannelV2, nodes[1].node.get_our_node_id());
+	let mut open_channel_request = |temporary_channel_id: [u8; 32]| {
+		open_channel_message.temporary_channel_id = temporary_channel_id;
+		nodes[1].node.handle_open_channel_v2(&node_a_id, InitFeatures::known(), &open_channel_message);
+		let events = nodes[1].node.get_and_clear_pending_events();
+		let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+		match msg_events.get(0) {
+			Some(MessageSendEvent::HandleError { ref node_id, action: ErrorAction::SendErrorMessage { ref msg } }) => {
+				assert!(events.is_empty());
+				assert_eq!(*node_id, node_a_id);
+				assert_eq!(msg.channel_id, temporary_channel_id);
+				Some(msg.data.clone())
+			},
+			None => {
+				assert_eq!(events.len(), 1);
+				match events[0] {
+					Event::FundingContributionRequired { temporary_channel_id: event_channel_id, .. } => assert_eq!(event_channel_id, temporary_channel_id),
+					_ => panic!("Unexpected event"),
+				}
+				None
+			},
+			_ => panic!("Unexpected event"),
+		}
+	};
+	let expect_rejection = |temporary_channel_id: [u8; 32], err: &str| {
+		let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+		assert_eq!(msg_events.len(), 1);
+		match msg_events[0] {
+			MessageSendEvent::HandleError { ref node_id, action: ErrorAction::SendErrorMessage { ref msg } } => {
+				assert_eq!(*node_id, node_a_id);
+				assert_eq!(msg.channel_id, temporary_channel_id);
+				assert_eq!(msg.data, err);
+			},
+			_ => panic!("Unexpected event"),
+		}
+	};
+
+	// Pending requests aren't channels yet, and count against the per-peer limit
+	assert_eq!(open_channel_request([1; 32]), None);
+	assert_eq!(open_channel_request([2; 32]), None);
+	assert!(nodes[1].node.list_channels().is_empty());
+	assert_eq!(open_channel_request([3; 32]).unwrap(), "Too many pending channel requests from this peer");
+
+	// They can be rejected...
+	nodes[1].node.reject_inbound_channel(&[1; 32]).unwrap();
+	expect_rejection([1; 32], "Channel request rejected");
+	match nodes[1].node.contribute_channel_funding(&[1; 32], FundingContribution { funding_satoshis: 0, inputs: Vec::new(), outputs: Vec::new() }) {
+		Err(APIError::ChannelUnavailable { err }) => assert_eq!(err, "No such channel request"),
+		_ => panic!("Unexpected result"),
+	}
+
+	// ...and time out if left unanswered
+	nodes[1].node.timer_chan_freshness_every_min();
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	nodes[1].node.timer_chan_freshness_every_min();
+	expect_rejection([2; 32], "Channel request timed out");
+	match nodes[1].node.contribute_channel_funding(&[2; 32], FundingContribution { funding_satoshis: 0, inputs: Vec::new(), outputs: Vec::new() }) {
+		Err(APIError::ChannelUnavailable { err }) => assert_eq!(err, "No such channel request"),
+		_ => panic!("Unexpected result"),
+	}
+
+	// Once answered, contributing moves the request into a channel
+	assert_eq!(open_channel_request([3; 32]), None);
+	nodes[1].node.contribute_channel_funding(&[3; 32], FundingContribution { funding_satoshis: 0, inputs: Vec::new(), outputs: Vec::new() }).unwrap();
+	get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannelV2, node_a_id);
+	assert_eq!(nodes[1].node.list_channels().len(), 1);
 }
@@ -0,0 +1,266 @@
+//! Stateless creation and verification of inbound payments.
+//!
+//! Rather than storing a payment's preimage and secret until it is paid, we encode the minimum
+//! amount we expect and the payment's expiry time in its payment secret, encrypted and
+//! authenticated with keys derived from KeysInterface::get_inbound_payment_key_material. Where we
+//! generate the payment hash ourselves, the preimage is in turn derived from the payment secret,
+//! allowing us to provide it upon receipt.
+
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::hashes::cmp::fixed_time_eq;
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::sha256::Hash as Sha256;
+
+use ln::channelmanager::{PaymentHash, PaymentPreimage, PaymentSecret};
+use ln::msgs;
+use util::byte_utils;
+use util::chacha20::ChaCha20;
+use util::logger::Logger;
+
+use std::ops::Deref;
+
+/// Block header timestamps, which we check payment expiry against, may run up to two hours ahead
+/// of real time, so we add this to the expiry time of each payment we create.
+pub(super) const EXPIRY_TIMESTAMP_GRACE_SECS: u64 = 7200;
+
+const IV_LEN: usize = 16;
+const METADATA_LEN: usize = 16;
+/// The top three bits of the metadata encode how the payment hash was generated.
+const METHOD_TYPE_OFFSET: usize = 5;
+
+/// Keys used to create and verify inbound payments, derived from
+/// KeysInterface::get_inbound_payment_key_material.
+pub(super) struct ExpandedKey {
+	/// Encrypts the metadata in payment secrets.
+	metadata_key: [u8; 32],
+	/// Derives payment preimages from the payment secrets of payments whose hash we generated.
+	ldk_pmt_hash_key: [u8; 32],
+	/// Authenticates the payment secrets of payments whose hash the user provided.
+	user_pmt_hash_key: [u8; 32],
+}
+
+impl ExpandedKey {
+	pub(super) fn new(key_material: &[u8; 32]) -> ExpandedKey {
+		let derive_key = |info: &[u8]| {
+			let mut hmac = HmacEngine::<Sha256>::new(key_material);
+			hmac.input(info);
+			Hmac::from_engine(hmac).into_inner()
+		};
+		ExpandedKey {
+			metadata_key: derive_key(b"LDK inbound payment metadata key"),
+			ldk_pmt_hash_key: derive_key(b"LDK inbound payment preimage key"),
+			user_pmt_hash_key: derive_key(b"LDK inbound payment user hash key"),
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Method {
+	LdkPaymentHash = 0,
+	UserPaymentHash = 1,
+}
+
+/// Creates a payment hash and secret for a payment of at least min_value_msat (if set) which
+/// expires at expiry_timestamp, using random_bytes to generate the payment preimage.
+pub(super) fn create(keys: &ExpandedKey, min_value_msat: Option<u64>, expiry_timestamp: u64, random_bytes: [u8; 32]) -> (PaymentHash, PaymentSecret) {
+	let metadata_bytes = construct_metadata_bytes(min_value_msat, Method::LdkPaymentHash, expiry_timestamp);
+
+	let mut iv_bytes = [0; IV_LEN];
+	iv_bytes.copy_from_slice(&random_bytes[..IV_LEN]);
+
+	let payment_preimage = derive_ldk_payment_preimage(keys, &iv_bytes, &metadata_bytes);
+	let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
+	(payment_hash, construct_payment_secret(keys, &iv_bytes, &metadata_bytes))
+}
+
+/// Creates a payment secret for a payment with the given payment hash, of at least
+/// min_value_msat (if set), which expires at expiry_timestamp.
+pub(super) fn create_from_hash(keys: &ExpandedKey, min_value_msat: Option<u64>, payment_hash: PaymentHash, expiry_timestamp: u64) -> PaymentSecret {
+	let metadata_bytes = construct_metadata_bytes(min_value_msat, Method::UserPaymentHash, expiry_timestamp);
+	let iv_bytes = derive_user_payment_hash_iv(keys, &metadata_bytes, &payment_hash);
+	construct_payment_secret(keys, &iv_bytes, &metadata_bytes)
+}
+
+fn construct_metadata_bytes(min_value_msat: Option<u64>, payment_type: Method, expiry_timestamp: u64) -> [u8; METADATA_LEN] {
+	let min_value_msat = min_value_msat.unwrap_or(0);
+	// Callers are expected to have checked this, leaving the top bits free for the method.
+	debug_assert!(min_value_msat <= msgs::MAX_VALUE_MSAT);
+
+	let mut metadata_bytes = [0; METADATA_LEN];
+	metadata_bytes[..8].copy_from_slice(&byte_utils::be64_to_array(min_value_msat));
+	metadata_bytes[0] |= (payment_type as u8) << METHOD_TYPE_OFFSET;
+	metadata_bytes[8..].copy_from_slice(&byte_utils::be64_to_array(expiry_timestamp));
+	metadata_bytes
+}
+
+fn construct_payment_secret(keys: &ExpandedKey, iv_bytes: &[u8; IV_LEN], metadata_bytes: &[u8; METADATA_LEN]) -> PaymentSecret {
+	let mut payment_secret_bytes = [0; 32];
+	payment_secret_bytes[..IV_LEN].copy_from_slice(iv_bytes);
+	let mut chacha = ChaCha20::new(&keys.metadata_key, &iv_bytes[..12]);
+	chacha.process(metadata_bytes, &mut payment_secret_bytes[IV_LEN..]);
+	PaymentSecret(payment_secret_bytes)
+}
+
+fn derive_user_payment_hash_iv(keys: &ExpandedKey, metadata_bytes: &[u8; METADATA_LEN], payment_hash: &PaymentHash) -> [u8; IV_LEN] {
+	let mut hmac = HmacEngine::<Sha256>::new(&keys.user_pmt_hash_key);
+	hmac.input(metadata_bytes);
+	hmac.input(&payment_hash.0);
+	let hmac_bytes = Hmac::from_engine(hmac).into_inner();
+	let mut iv_bytes = [0; IV_LEN];
+	iv_bytes.copy_from_slice(&hmac_bytes[..IV_LEN]);
+	iv_bytes
+}
+
+fn derive_ldk_payment_preimage(keys: &ExpandedKey, iv_bytes: &[u8; IV_LEN], metadata_bytes: &[u8; METADATA_LEN]) -> PaymentPreimage {
+	let mut hmac = HmacEngine::<Sha256>::new(&keys.ldk_pmt_hash_key);
+	hmac.input(iv_bytes);
+	hmac.input(metadata_bytes);
+	PaymentPreimage(Hmac::from_engine(hmac).into_inner())
+}
+
+/// Checks whether the payment secret of a payment we received was created by create or
+/// create_from_hash and, if so, that the payment pays at least its minimum amount and has not
+/// expired.
+///
+/// Returns Ok(None) if we didn't create the payment secret, leaving it to the user to decide what
+/// to do with the payment, Ok with the payment preimage if we generated the payment hash, and
+/// Err(()) if the payment must be failed.
+pub(super) fn verify<L: Deref>(payment_hash: PaymentHash, payment_data: &msgs::FinalOnionHopData, highest_seen_timestamp: u64, keys: &ExpandedKey, logger: &L) -> Result<Option<PaymentPreimage>, ()>
+	where L::Target: Logger
+{
+	let mut iv_bytes = [0; IV_LEN];
+	iv_bytes.copy_from_slice(&payment_data.payment_secret.0[..IV_LEN]);
+	let mut metadata_bytes = [0; METADATA_LEN];
+	let mut chacha = ChaCha20::new(&keys.metadata_key, &iv_bytes[..12]);
+	chacha.process(&payment_data.payment_secret.0[IV_LEN..], &mut metadata_bytes);
+
+	let payment_type = metadata_bytes[0] >> METHOD_TYPE_OFFSET;
+	let payment_preimage = if payment_type == Method::LdkPaymentHash as u8 {
+		let payment_preimage = derive_ldk_payment_preimage(keys, &iv_bytes, &metadata_bytes);
+		if !fixed_time_eq(&Sha256::hash(&payment_preimage.0).into_inner(), &payment_hash.0) {
+			return Ok(None);
+		}
+		Some(payment_preimage)
+	} else if payment_type == Method::UserPaymentHash as u8 {
+		if !fixed_time_eq(&derive_user_payment_hash_iv(keys, &metadata_bytes, &payment_hash), &iv_bytes) {
+			return Ok(None);
+		}
+		None
+	} else {
+		return Ok(None);
+	};
+
+	let mut min_amt_msat_bytes = [0; 8];
+	min_amt_msat_bytes.copy_from_slice(&metadata_bytes[..8]);
+	min_amt_msat_bytes[0] &= (1 << METHOD_TYPE_OFFSET) - 1;
+	let min_amt_msat = byte_utils::slice_to_be64(&min_amt_msat_bytes);
+	let expiry = byte_utils::slice_to_be64(&metadata_bytes[8..]);
+
+	if payment_data.total_msat < min_amt_msat {
+		log_trace!(logger, "Failing inbound payment with payment_hash {} as it paid {} msat of at least {} msat", log_bytes!(payment_hash.0), payment_data.total_msat, min_amt_msat);
+		return Err(());
+	}
+	if expiry < highest_seen_timestamp {
+		log_trace!(logger, "Failing inbound payment with payment_hash {} as it expired at {}", log_bytes!(payment_hash.0), expiry);
+		return Err(());
+	}
+	Ok(payment_preimage)
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::hashes::{Hash, sha256::Hash as Sha256};
+
+	use ln::channelmanager::{PaymentHash, PaymentSecret};
+	use ln::inbound_payment::{create, create_from_hash, verify, ExpandedKey};
+	use ln::msgs::FinalOnionHopData;
+	use util::test_utils::TestLogger;
+
+	use std::sync::Arc;
+
+	fn hop_data(payment_secret: PaymentSecret, total_msat: u64) -> FinalOnionHopData {
+		FinalOnionHopData { payment_secret, total_msat }
+	}
+
+	#[test]
+	fn test_create_verify_round_trip() {
+		let logger = Arc::new(TestLogger::new());
+		let keys = ExpandedKey::new(&[42; 32]);
+
+		// Payments to hashes we generated provide their preimage
+		let (payment_hash, payment_secret) = create(&keys, Some(1000), 100, [1; 32]);
+		let payment_preimage = verify(payment_hash, &hop_data(payment_secret, 1000), 100, &keys, &logger).unwrap().unwrap();
+		assert_eq!(PaymentHash(Sha256::hash(&payment_preimage.0).into_inner()), payment_hash);
+
+		// Payments to hashes the user provided are verified without a preimage
+		let payment_hash = PaymentHash([2; 32]);
+		let payment_secret = create_from_hash(&keys, None, payment_hash, 100);
+		assert_eq!(verify(payment_hash, &hop_data(payment_secret, 1), 100, &keys, &logger), Ok(None));
+		// ...and their payment secrets are deterministic
+		assert_eq!(create_from_hash(&keys, None, payment_hash, 100), payment_secret);
+
+		// Payment secrets from different keys, or for a different payment hash, aren't ours
+		let other_keys = ExpandedKey::new(&[43; 32]);
+		let (payment_hash, payment_secret) = create(&keys, None, 100, [1; 32]);
+		assert_eq!(verify(payment_hash, &hop_data(payment_secret, 1), 100, &other_keys, &logger), Ok(None));
+		assert_eq!(verify(PaymentHash([3; 32]), &hop_data(payment_secret, 1), 100, &keys, &logger), Ok(None));
+	}
+
+	#[test]
+	fn test_tampered_metadata() {
+		let logger = Arc::new(TestLogger::new());
+		let keys = ExpandedKey::new(&[42; 32]);
+
+		// Flipping any bit of the encrypted metadata, eg to lower the minimum amount or extend the
+		// expiry, means the payment secret is no longer recognized as ours.
+		let (payment_hash, payment_secret) = create(&keys, Some(1000), 100, [1; 32]);
+		let user_payment_hash = PaymentHash([2; 32]);
+		let user_payment_secret = create_from_hash(&keys, Some(1000), user_payment_hash, 100);
+		for byte in 16..32 {
+			for bit in 0..8 {
+				let mut tampered_secret = payment_secret;
+				tampered_secret.0[byte] ^= 1 << bit;
+				assert_eq!(verify(payment_hash, &hop_data(tampered_secret, 1000), 100, &keys, &logger), Ok(None));
+
+				let mut tampered_secret = user_payment_secret;
+				tampered_secret.0[byte] ^= 1 << bit;
+				assert_eq!(verify(user_payment_hash, &hop_data(tampered_secret, 1000), 100, &keys, &logger), Ok(None));
+			}
+		}
+	}
+
+	#[test]
+	fn test_min_amount() {
+		let logger = Arc::new(TestLogger::new());
+		let keys = ExpandedKey::new(&[42; 32]);
+
+		let (payment_hash, payment_secret) = create(&keys, Some(1000), 100, [1; 32]);
+		assert!(verify(payment_hash, &hop_data(payment_secret, 999), 100, &keys, &logger).is_err());
+		assert!(verify(payment_hash, &hop_data(payment_secret, 1000), 100, &keys, &logger).unwrap().is_some());
+
+		let payment_hash = PaymentHash([2; 32]);
+		let payment_secret = create_from_hash(&keys, Some(1000), payment_hash, 100);
+		assert!(verify(payment_hash, &hop_data(payment_secret, 999), 100, &keys, &logger).is_err());
+		assert_eq!(verify(payment_hash, &hop_data(payment_secret, 1000), 100, &keys, &logger), Ok(None));
+
+		// Without a minimum amount anything goes
+		let (payment_hash, payment_secret) = create(&keys, None, 100, [1; 32]);
+		assert!(verify(payment_hash, &hop_data(payment_secret, 1), 100, &keys, &logger).unwrap().is_some());
+	}
+
+	#[test]
+	fn test_expiry() {
+		let logger = Arc::new(TestLogger::new());
+		let keys = ExpandedKey::new(&[42; 32]);
+
+		// Payments are accepted up to and including their expiry timestamp
+		let (payment_hash, payment_secret) = create(&keys, None, 100, [1; 32]);
+		assert!(verify(payment_hash, &hop_data(payment_secret, 1), 100, &keys, &logger).unwrap().is_some());
+		assert!(verify(payment_hash, &hop_data(payment_secret, 1), 101, &keys, &logger).is_err());
+
+		let payment_hash = PaymentHash([2; 32]);
+		let payment_secret = create_from_hash(&keys, None, payment_hash, 100);
+		assert_eq!(verify(payment_hash, &hop_data(payment_secret, 1), 100, &keys, &logger), Ok(None));
+		assert!(verify(payment_hash, &hop_data(payment_secret, 1), 101, &keys, &logger).is_err());
+	}
+}
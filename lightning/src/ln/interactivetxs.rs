@@ -0,0 +1,883 @@
+//! Interactive construction of transactions which both channel peers contribute inputs and
+//! outputs to, as used to build the funding transaction of dual-funded channels and splice
+//! transactions.
+//!
+//! Peers take turns sending tx_add_input/tx_add_output messages, with the channel (or splice)
+//! initiator going first, until both consecutively send tx_complete. Each peer then signs the
+//! other's initial (or spliced) commitment transaction before tx_signatures messages carrying the
+//! witnesses for each peer's inputs are exchanged.
+//!
+//! A splice transaction additionally spends the channel's current funding output, the "shared
+//! input", which the splice initiator adds and both peers sign for in their tx_signatures.
+
+use bitcoin::blockdata::transaction::{Transaction, TxIn, TxOut, OutPoint as BitcoinOutPoint, SigHashType};
+use bitcoin::blockdata::script::Script;
+
+use bitcoin::secp256k1::Signature;
+
+use ln::msgs;
+use ln::msgs::DecodeError;
+use ln::channel::{ChannelError, TOTAL_BITCOIN_SUPPLY_SATOSHIS};
+use util::ser::{Readable, Writeable, Writer};
+
+use std::collections::VecDeque;
+use std::{cmp, iter};
+
+/// The maximum number of inputs or outputs either peer may add to an interactively-constructed
+/// transaction, per BOLT #2.
+const MAX_INPUTS_OUTPUTS_COUNT: usize = 4096;
+
+/// Outputs below this value are considered dust and may not be added to a funding transaction.
+pub(super) const MIN_OUTPUT_VALUE_SATOSHIS: u64 = 546;
+
+/// The highest nSequence an input to a funding transaction may have, so that it signals
+/// replaceability per BIP 125.
+const MAX_INPUT_SEQUENCE: u32 = 0xfffffffd;
+
+/// The weight of the version, locktime, input and output counts and SegWit marker and flag,
+/// which the initiator pays for.
+const TX_COMMON_FIELDS_WEIGHT: u64 = (4 + 4 + 1 + 1) * 4 + 2;
+
+/// The weight of the P2WSH funding output, which the initiator pays for.
+const FUNDING_OUTPUT_WEIGHT: u64 = (8 + 1 + 34) * 4;
+
+/// An estimate of the weight of an input, based on spending a P2WPKH output:
+/// prevout: 36, nSequence: 4, script len: 1, witness: (1 + 1 + 73 + 1 + 33)/4
+const INPUT_WEIGHT_ESTIMATE: u64 = (36 + 4 + 1) * 4 + 1 + 1 + 73 + 1 + 33;
+
+/// The weight of the channel's current funding output when spent by a splice transaction, which
+/// the splice initiator pays for:
+/// prevout: 36, nSequence: 4, script len: 1, witness: (1 + 1 + 1 + 73 + 1 + 73 + 1 + 71)/4
+const SHARED_INPUT_WEIGHT: u64 = (36 + 4 + 1) * 4 + 1 + 1 + 1 + 73 + 1 + 73 + 1 + 71;
+
+/// An input we contribute to the funding transaction of a dual-funded channel or to a splice
+/// transaction.
+#[derive(Clone)]
+pub struct FundingInput {
+	/// The transaction containing the output being spent. Our counterparty needs the full
+	/// transaction to learn the output's value and check that it is a SegWit output.
+	pub prevtx: Transaction,
+	/// The index of the output being spent in prevtx.
+	pub prevtx_out: u32,
+	/// The nSequence to set on the input, which must signal replaceability, ie be at most
+	/// 0xfffffffd.
+	pub sequence: u32,
+}
+
+/// Our contribution to the funding transaction of a dual-funded channel, provided to
+/// ChannelManager::create_dual_funded_channel or ChannelManager::contribute_channel_funding, or
+/// to a splice transaction, provided to ChannelManager::splice_in.
+#[derive(Clone)]
+pub struct FundingContribution {
+	/// The amount, in satoshis, we add to the channel's funding output, which will be our initial
+	/// balance in the channel (or is added to our balance, when splicing). May be 0 if we're
+	/// accepting a channel without contributing to it.
+	pub funding_satoshis: u64,
+	/// The inputs we add to the funding transaction. They must spend SegWit outputs and cover
+	/// funding_satoshis, our outputs and the fees for our inputs and outputs at the funding
+	/// feerate (plus the funding output and the common transaction fields if we're opening the
+	/// channel, and the channel's current funding output if we're splicing).
+	pub inputs: Vec<FundingInput>,
+	/// Any outputs, eg for change, we wish to add to the funding transaction.
+	pub outputs: Vec<TxOut>,
+}
+
+fn output_weight(script: &Script) -> u64 {
+	(8 + 1 + script.len() as u64) * 4
+}
+
+/// Gets the fee, in satoshis, which a party must pay for its inputs and outputs in a funding
+/// transaction at the given feerate. The initiator also pays for the common transaction fields
+/// and the funding output, as well as the shared input if the transaction is a splice.
+fn contribution_fee_satoshis<'a, I: Iterator<Item = &'a TxOut>>(feerate_per_kw: u32, is_initiator: bool, is_splice: bool, inputs_count: usize, outputs: I) -> u64 {
+	let mut weight = inputs_count as u64 * INPUT_WEIGHT_ESTIMATE;
+	for output in outputs {
+		weight += output_weight(&output.script_pubkey);
+	}
+	if is_initiator {
+		weight += TX_COMMON_FIELDS_WEIGHT + FUNDING_OUTPUT_WEIGHT;
+		if is_splice {
+			weight += SHARED_INPUT_WEIGHT;
+		}
+	}
+	feerate_per_kw as u64 * weight / 1000
+}
+
+/// Gets the fee, in satoshis, which the initiator of a splice pays when its only contribution to
+/// the splice transaction is the given output, ie when splicing funds out of the channel. The fee
+/// is taken from its channel balance along with the output's value.
+pub(super) fn splice_out_fee_satoshis(feerate_per_kw: u32, output: &TxOut) -> u64 {
+	contribution_fee_satoshis(feerate_per_kw, true, true, 0, iter::once(output))
+}
+
+fn prevout_value(prevtx: &Transaction, prevtx_out: u32) -> Option<&TxOut> {
+	prevtx.output.get(prevtx_out as usize)
+}
+
+/// Checks that our own contribution is well-formed and pays for itself at the given feerate.
+pub(super) fn check_holder_contribution(contribution: &FundingContribution, is_initiator: bool, is_splice: bool, feerate_per_kw: u32) -> Result<(), &'static str> {
+	if contribution.inputs.len() > MAX_INPUTS_OUTPUTS_COUNT || contribution.outputs.len() > MAX_INPUTS_OUTPUTS_COUNT {
+		return Err("Too many funding inputs or outputs");
+	}
+	let mut inputs_value: u64 = 0;
+	for (idx, input) in contribution.inputs.iter().enumerate() {
+		let prevout = match prevout_value(&input.prevtx, input.prevtx_out) {
+			Some(prevout) => prevout,
+			None => return Err("Funding input prevtx_out is not an output of prevtx"),
+		};
+		if !prevout.script_pubkey.is_witness_program() {
+			return Err("Funding inputs must spend SegWit outputs");
+		}
+		if input.sequence > MAX_INPUT_SEQUENCE {
+			return Err("Funding inputs must signal replaceability");
+		}
+		let prevtx_txid = input.prevtx.txid();
+		if contribution.inputs[..idx].iter().any(|prev| prev.prevtx_out == input.prevtx_out && prev.prevtx.txid() == prevtx_txid) {
+			return Err("Funding inputs must not spend the same output twice");
+		}
+		inputs_value = inputs_value.saturating_add(prevout.value);
+	}
+	let mut outputs_value: u64 = 0;
+	for output in contribution.outputs.iter() {
+		if output.value < MIN_OUTPUT_VALUE_SATOSHIS {
+			return Err("Funding transaction outputs must not be dust");
+		}
+		outputs_value = outputs_value.saturating_add(output.value);
+	}
+	let fee = contribution_fee_satoshis(feerate_per_kw, is_initiator, is_splice, contribution.inputs.len(), contribution.outputs.iter());
+	if inputs_value < contribution.funding_satoshis.saturating_add(outputs_value).saturating_add(fee) {
+		return Err("Funding inputs do not cover the funding amount, outputs and fees");
+	}
+	Ok(())
+}
+
+/// A message to send our counterparty during interactive transaction construction.
+pub(super) enum InteractiveTxMessageSend {
+	TxAddInput(msgs::TxAddInput),
+	TxAddOutput(msgs::TxAddOutput),
+	TxComplete(msgs::TxComplete),
+	/// Sent in place of an error when the negotiation of a splice fails, abandoning the splice
+	/// without closing the channel.
+	TxAbort(msgs::TxAbort),
+}
+
+struct ConstructedInput {
+	serial_id: u64,
+	previous_output: BitcoinOutPoint,
+	/// The transaction containing the output being spent, which is None for the shared input of a
+	/// splice transaction.
+	prevtx: Option<Transaction>,
+	prev_output: TxOut,
+	sequence: u32,
+	holder_contributed: bool,
+}
+
+impl ConstructedInput {
+	fn is_shared(&self) -> bool {
+		self.prevtx.is_none()
+	}
+	fn value(&self) -> u64 {
+		self.prev_output.value
+	}
+}
+
+struct ConstructedOutput {
+	serial_id: u64,
+	output: TxOut,
+	holder_contributed: bool,
+}
+
+/// Tracks the inputs and outputs both peers have added to a transaction and whose turn it is to
+/// send the next message.
+pub(super) struct InteractiveTxConstructor {
+	channel_id: [u8; 32],
+	is_initiator: bool,
+	/// The channel's current funding outpoint and output, when constructing a splice transaction.
+	shared_input: Option<(BitcoinOutPoint, TxOut)>,
+	inputs: Vec<ConstructedInput>,
+	outputs: Vec<ConstructedOutput>,
+	/// Our inputs and outputs which we have yet to send, in the order we'll send them.
+	pending_holder_inputs: VecDeque<ConstructedInput>,
+	pending_holder_outputs: VecDeque<ConstructedOutput>,
+	holder_turn: bool,
+	sent_complete: bool,
+	received_complete: bool,
+}
+
+impl InteractiveTxConstructor {
+	/// Creates a new constructor which will add the given inputs and outputs. The initiator sends
+	/// the first message and uses even serial_ids, while its counterparty uses odd ones.
+	///
+	/// When constructing a splice transaction, shared_input is the channel's current funding
+	/// outpoint and output, which the initiator adds before its other inputs.
+	pub(super) fn new(channel_id: [u8; 32], is_initiator: bool, shared_input: Option<(BitcoinOutPoint, TxOut)>, inputs: Vec<FundingInput>, outputs: Vec<TxOut>) -> Self {
+		let mut next_serial_id = if is_initiator { 0 } else { 1 };
+		let mut pending_holder_inputs = VecDeque::with_capacity(inputs.len() + 1);
+		if is_initiator {
+			if let Some((ref previous_output, ref prev_output)) = shared_input {
+				pending_holder_inputs.push_back(ConstructedInput {
+					serial_id: next_serial_id,
+					previous_output: *previous_output,
+					prevtx: None,
+					prev_output: prev_output.clone(),
+					sequence: MAX_INPUT_SEQUENCE,
+					holder_contributed: true,
+				});
+				next_serial_id += 2;
+			}
+		}
+		for input in inputs {
+			pending_holder_inputs.push_back(ConstructedInput {
+				serial_id: next_serial_id,
+				previous_output: BitcoinOutPoint { txid: input.prevtx.txid(), vout: input.prevtx_out },
+				prev_output: input.prevtx.output[input.prevtx_out as usize].clone(),
+				prevtx: Some(input.prevtx),
+				sequence: input.sequence,
+				holder_contributed: true,
+			});
+			next_serial_id += 2;
+		}
+		let mut pending_holder_outputs = VecDeque::with_capacity(outputs.len());
+		for output in outputs {
+			pending_holder_outputs.push_back(ConstructedOutput {
+				serial_id: next_serial_id,
+				output,
+				holder_contributed: true,
+			});
+			next_serial_id += 2;
+		}
+		InteractiveTxConstructor {
+			channel_id,
+			is_initiator,
+			shared_input,
+			inputs: Vec::new(),
+			outputs: Vec::new(),
+			pending_holder_inputs,
+			pending_holder_outputs,
+			holder_turn: is_initiator,
+			sent_complete: false,
+			received_complete: false,
+		}
+	}
+
+	/// Returns true once both peers have consecutively sent tx_complete.
+	pub(super) fn is_complete(&self) -> bool {
+		self.sent_complete && self.received_complete
+	}
+
+	/// Gets the next message to send our counterparty. Must only be called when it is our turn,
+	/// ie when we're the initiator and haven't sent anything yet or after receiving a message
+	/// which didn't complete construction.
+	pub(super) fn next_message(&mut self) -> InteractiveTxMessageSend {
+		assert!(self.holder_turn && !self.is_complete());
+		self.holder_turn = false;
+		if let Some(input) = self.pending_holder_inputs.pop_front() {
+			self.received_complete = false;
+			let msg = msgs::TxAddInput {
+				channel_id: self.channel_id,
+				serial_id: input.serial_id,
+				prevtx: input.prevtx.clone(),
+				prevtx_out: input.previous_output.vout,
+				sequence: input.sequence,
+				shared_input_txid: if input.is_shared() { Some(input.previous_output.txid) } else { None },
+			};
+			self.inputs.push(input);
+			InteractiveTxMessageSend::TxAddInput(msg)
+		} else if let Some(output) = self.pending_holder_outputs.pop_front() {
+			self.received_complete = false;
+			let msg = msgs::TxAddOutput {
+				channel_id: self.channel_id,
+				serial_id: output.serial_id,
+				sats: output.output.value,
+				script: output.output.script_pubkey.clone(),
+			};
+			self.outputs.push(output);
+			InteractiveTxMessageSend::TxAddOutput(msg)
+		} else {
+			self.sent_complete = true;
+			InteractiveTxMessageSend::TxComplete(msgs::TxComplete { channel_id: self.channel_id })
+		}
+	}
+
+	fn check_counterparty_serial_id(&self, serial_id: u64) -> Result<(), ChannelError> {
+		if self.holder_turn || self.is_complete() {
+			return Err(ChannelError::Close("Received an interactive transaction message out of turn"));
+		}
+		// Serial ids of the initiator's inputs and outputs are even, its counterparty's odd.
+		if (serial_id % 2 == 0) != !self.is_initiator {
+			return Err(ChannelError::Close("Received an interactive transaction message with a serial_id of the wrong parity"));
+		}
+		if self.inputs.iter().any(|input| input.serial_id == serial_id) || self.outputs.iter().any(|output| output.serial_id == serial_id) {
+			return Err(ChannelError::Close("Received an interactive transaction message with a duplicate serial_id"));
+		}
+		Ok(())
+	}
+
+	pub(super) fn received_tx_add_input(&mut self, msg: &msgs::TxAddInput) -> Result<(), ChannelError> {
+		self.check_counterparty_serial_id(msg.serial_id)?;
+		if self.inputs.iter().filter(|input| !input.holder_contributed).count() >= MAX_INPUTS_OUTPUTS_COUNT {
+			return Err(ChannelError::Close("Counterparty added too many inputs to the funding transaction"));
+		}
+		if msg.sequence > MAX_INPUT_SEQUENCE {
+			return Err(ChannelError::Close("Counterparty added a funding input which doesn't signal replaceability"));
+		}
+		let input = match (&msg.prevtx, msg.shared_input_txid) {
+			(&None, Some(shared_input_txid)) => {
+				match self.shared_input {
+					Some((ref previous_output, ref prev_output)) if !self.is_initiator && previous_output.txid == shared_input_txid && previous_output.vout == msg.prevtx_out => {
+						ConstructedInput {
+							serial_id: msg.serial_id,
+							previous_output: *previous_output,
+							prevtx: None,
+							prev_output: prev_output.clone(),
+							sequence: msg.sequence,
+							holder_contributed: false,
+						}
+					},
+					_ => return Err(ChannelError::Close("Counterparty added a shared input which isn't the channel's funding output")),
+				}
+			},
+			(&Some(ref prevtx), None) => {
+				let prev_output = match prevout_value(prevtx, msg.prevtx_out) {
+					Some(prevout) if prevout.script_pubkey.is_witness_program() => prevout.clone(),
+					Some(_) => return Err(ChannelError::Close("Counterparty added a funding input spending a non-SegWit output")),
+					None => return Err(ChannelError::Close("Counterparty added a funding input with an invalid prevtx_out")),
+				};
+				ConstructedInput {
+					serial_id: msg.serial_id,
+					previous_output: BitcoinOutPoint { txid: prevtx.txid(), vout: msg.prevtx_out },
+					prevtx: Some(prevtx.clone()),
+					prev_output,
+					sequence: msg.sequence,
+					holder_contributed: false,
+				}
+			},
+			_ => return Err(ChannelError::Close("Counterparty added a funding input with both or neither of a prevtx and shared input txid")),
+		};
+		let previous_output = input.previous_output;
+		if self.inputs.iter().chain(self.pending_holder_inputs.iter()).any(|other| other.previous_output == previous_output) {
+			return Err(ChannelError::Close("Counterparty added a funding input spending an output which is already spent"));
+		}
+		self.inputs.push(input);
+		self.sent_complete = false;
+		self.holder_turn = true;
+		Ok(())
+	}
+
+	pub(super) fn received_tx_add_output(&mut self, msg: &msgs::TxAddOutput) -> Result<(), ChannelError> {
+		self.check_counterparty_serial_id(msg.serial_id)?;
+		if self.outputs.iter().filter(|output| !output.holder_contributed).count() >= MAX_INPUTS_OUTPUTS_COUNT {
+			return Err(ChannelError::Close("Counterparty added too many outputs to the funding transaction"));
+		}
+		if msg.sats < MIN_OUTPUT_VALUE_SATOSHIS {
+			return Err(ChannelError::Close("Counterparty added a dust output to the funding transaction"));
+		}
+		if msg.sats > TOTAL_BITCOIN_SUPPLY_SATOSHIS {
+			return Err(ChannelError::Close("Counterparty added an output larger than the total bitcoin supply"));
+		}
+		if !(msg.script.is_p2pkh() || msg.script.is_p2sh() || msg.script.is_v0_p2wsh() || msg.script.is_v0_p2wpkh()) {
+			return Err(ChannelError::Close("Counterparty added an output with a non-standard script"));
+		}
+		self.outputs.push(ConstructedOutput {
+			serial_id: msg.serial_id,
+			output: TxOut { value: msg.sats, script_pubkey: msg.script.clone() },
+			holder_contributed: false,
+		});
+		self.sent_complete = false;
+		self.holder_turn = true;
+		Ok(())
+	}
+
+	pub(super) fn received_tx_complete(&mut self) -> Result<(), ChannelError> {
+		if self.holder_turn || self.is_complete() {
+			return Err(ChannelError::Close("Received an interactive transaction message out of turn"));
+		}
+		self.received_complete = true;
+		self.holder_turn = !self.is_complete();
+		Ok(())
+	}
+
+	/// Builds the constructed transaction, with inputs and outputs ordered by serial_id.
+	pub(super) fn build_transaction(&self, locktime: u32) -> Transaction {
+		let mut inputs: Vec<&ConstructedInput> = self.inputs.iter().collect();
+		inputs.sort_unstable_by_key(|input| input.serial_id);
+		let mut outputs: Vec<&ConstructedOutput> = self.outputs.iter().collect();
+		outputs.sort_unstable_by_key(|output| output.serial_id);
+		Transaction {
+			version: 2,
+			lock_time: locktime,
+			input: inputs.iter().map(|input| TxIn {
+				previous_output: input.previous_output,
+				script_sig: Script::new(),
+				sequence: input.sequence,
+				witness: Vec::new(),
+			}).collect(),
+			output: outputs.iter().map(|output| output.output.clone()).collect(),
+		}
+	}
+
+	/// Gets the indices, in the transaction returned by build_transaction, of the inputs we added,
+	/// other than the shared input.
+	pub(super) fn holder_input_indices(&self) -> Vec<u32> {
+		let mut inputs: Vec<&ConstructedInput> = self.inputs.iter().collect();
+		inputs.sort_unstable_by_key(|input| input.serial_id);
+		inputs.iter().enumerate().filter(|&(_, input)| input.holder_contributed && !input.is_shared()).map(|(idx, _)| idx as u32).collect()
+	}
+
+	/// Gets the index, in the transaction returned by build_transaction, of the shared input, if
+	/// any.
+	pub(super) fn shared_input_index(&self) -> Option<u32> {
+		let mut inputs: Vec<&ConstructedInput> = self.inputs.iter().collect();
+		inputs.sort_unstable_by_key(|input| input.serial_id);
+		inputs.iter().position(|input| input.is_shared()).map(|idx| idx as u32)
+	}
+
+	/// Gets the total value of the inputs we added and of those our counterparty added, other
+	/// than the shared input.
+	pub(super) fn input_values(&self) -> (u64, u64) {
+		let mut values = (0u64, 0u64);
+		for input in self.inputs.iter() {
+			if input.is_shared() {
+				continue;
+			} else if input.holder_contributed {
+				values.0 = values.0.saturating_add(input.value());
+			} else {
+				values.1 = values.1.saturating_add(input.value());
+			}
+		}
+		values
+	}
+
+	/// Checks that the constructed transaction contains the given funding output (and spends the
+	/// shared input, if any) and that our counterparty's inputs pay for its contribution to the
+	/// funding output, its own outputs and its share of the fees at the given feerate.
+	///
+	/// The counterparty's contribution is negative if it splices funds out of the channel, in
+	/// which case its outputs and fees may be paid for from its channel balance instead.
+	pub(super) fn check_counterparty_contribution(&self, funding_output: &TxOut, counterparty_contribution_satoshis: i64, feerate_per_kw: u32) -> Result<(), ChannelError> {
+		if self.outputs.iter().filter(|output| output.output == *funding_output).count() != 1 {
+			return Err(ChannelError::Close("Funding transaction does not contain exactly one funding output"));
+		}
+		if self.shared_input.is_some() && !self.inputs.iter().any(|input| input.is_shared()) {
+			return Err(ChannelError::Close("Splice transaction does not spend the channel's funding output"));
+		}
+		let mut counterparty_outputs_value: u64 = 0;
+		let mut counterparty_outputs = Vec::new();
+		for output in self.outputs.iter().filter(|output| !output.holder_contributed) {
+			if output.output == *funding_output {
+				continue;
+			}
+			counterparty_outputs_value = counterparty_outputs_value.saturating_add(output.output.value);
+			counterparty_outputs.push(&output.output);
+		}
+		let counterparty_inputs_count = self.inputs.iter().filter(|input| !input.holder_contributed && !input.is_shared()).count();
+		let fee = contribution_fee_satoshis(feerate_per_kw, !self.is_initiator, self.shared_input.is_some(), counterparty_inputs_count, counterparty_outputs.iter().map(|output| *output));
+		let (_, counterparty_inputs_value) = self.input_values();
+		if (counterparty_inputs_value as i128) < counterparty_contribution_satoshis as i128 + counterparty_outputs_value as i128 + fee as i128 {
+			return Err(ChannelError::Close("Counterparty's funding inputs do not cover its funding amount, outputs and fees"));
+		}
+		Ok(())
+	}
+}
+
+/// Tracks the exchange of tx_signatures for an interactively-constructed funding transaction,
+/// which only becomes broadcastable once both peers have provided the witnesses for their
+/// inputs.
+pub(super) struct FundingTxSigningState {
+	/// The funding transaction, with witnesses filled in as they are provided.
+	funding_tx: Transaction,
+	holder_input_indices: Vec<u32>,
+	/// The peer which contributed the lower total input value sends its tx_signatures first (the
+	/// initiator, if both contributed the same value), so that the other peer can't get the
+	/// funding transaction signed and then refuse to sign its own inputs.
+	holder_sends_first: bool,
+	holder_signed: bool,
+	counterparty_signed: bool,
+	sent_tx_signatures: bool,
+	shared_input: Option<SharedInputSignatures>,
+}
+
+/// The signatures spending the shared input of a splice transaction, ie the channel's previous
+/// funding output, which is spent with a 2-of-2 multisig witness.
+pub(super) struct SharedInputSignatures {
+	pub(super) index: u32,
+	pub(super) witness_script: Script,
+	/// Whether our signature comes first in the witness, ie our funding pubkey sorts first.
+	pub(super) holder_sig_first: bool,
+	pub(super) holder_signature: Signature,
+	pub(super) counterparty_signature: Option<Signature>,
+}
+
+impl FundingTxSigningState {
+	pub(super) fn new(funding_tx: Transaction, holder_input_indices: Vec<u32>, holder_sends_first: bool, shared_input: Option<SharedInputSignatures>) -> Self {
+		let holder_signed = holder_input_indices.is_empty();
+		let counterparty_signed = holder_input_indices.len() == funding_tx.input.len();
+		FundingTxSigningState {
+			funding_tx,
+			holder_input_indices,
+			holder_sends_first,
+			holder_signed,
+			counterparty_signed,
+			sent_tx_signatures: false,
+			shared_input,
+		}
+	}
+
+	/// Gets the funding transaction, which may not yet be signed.
+	pub(super) fn get_funding_tx(&self) -> &Transaction {
+		&self.funding_tx
+	}
+
+	/// Gets the index of the shared input, if the funding transaction is a splice transaction.
+	pub(super) fn shared_input_index(&self) -> Option<u32> {
+		self.shared_input.as_ref().map(|shared_input| shared_input.index)
+	}
+
+	pub(super) fn has_sent_tx_signatures(&self) -> bool {
+		self.sent_tx_signatures
+	}
+
+	/// Gets the funding transaction if it still requires witnesses for our inputs.
+	pub(super) fn get_unsigned_funding_tx(&self) -> Option<&Transaction> {
+		if self.holder_signed { None } else { Some(&self.funding_tx) }
+	}
+
+	/// Gets the fully-signed funding transaction, if both peers have provided their witnesses.
+	pub(super) fn get_signed_funding_tx(&self) -> Option<&Transaction> {
+		if self.holder_signed && self.counterparty_signed { Some(&self.funding_tx) } else { None }
+	}
+
+	/// Copies the witnesses for our inputs from the given signed copy of the funding transaction.
+	pub(super) fn provide_holder_witnesses(&mut self, signed_tx: &Transaction) -> Result<(), &'static str> {
+		if self.holder_signed {
+			return Err("Funding transaction was already signed");
+		}
+		if signed_tx.txid() != self.funding_tx.txid() {
+			return Err("Signed transaction does not match the funding transaction");
+		}
+		for idx in self.holder_input_indices.iter() {
+			if signed_tx.input[*idx as usize].witness.is_empty() {
+				return Err("Signed transaction is missing the witness for one of our inputs");
+			}
+		}
+		for idx in self.holder_input_indices.iter() {
+			self.funding_tx.input[*idx as usize].witness = signed_tx.input[*idx as usize].witness.clone();
+		}
+		self.holder_signed = true;
+		Ok(())
+	}
+
+	/// Fills in our counterparty's witnesses from their tx_signatures. Their signature for the
+	/// shared input, if any, must already have been checked by the caller.
+	pub(super) fn received_tx_signatures(&mut self, msg: &msgs::TxSignatures) -> Result<(), ChannelError> {
+		if self.counterparty_signed {
+			// Our counterparty retransmits its tx_signatures on reconnection until it sees the
+			// funding transaction confirm, so this isn't necessarily a protocol violation.
+			return Err(ChannelError::Ignore("Received duplicate tx_signatures"));
+		}
+		if self.holder_sends_first && !self.sent_tx_signatures {
+			return Err(ChannelError::Close("Received tx_signatures before we sent ours"));
+		}
+		if msg.txid != self.funding_tx.txid() {
+			return Err(ChannelError::Close("Received tx_signatures for the wrong transaction"));
+		}
+		if self.shared_input.is_some() != msg.shared_input_signature.is_some() {
+			return Err(ChannelError::Close("Received tx_signatures with an unexpected or missing shared input signature"));
+		}
+		let shared_input_index = self.shared_input_index();
+		let counterparty_input_count = self.funding_tx.input.len() - self.holder_input_indices.len() - if shared_input_index.is_some() { 1 } else { 0 };
+		if msg.witnesses.len() != counterparty_input_count {
+			return Err(ChannelError::Close("Received tx_signatures with the wrong number of witnesses"));
+		}
+		if msg.witnesses.iter().any(|witness| witness.is_empty()) {
+			return Err(ChannelError::Close("Received tx_signatures with an empty witness"));
+		}
+		let mut witnesses = msg.witnesses.iter();
+		for (idx, input) in self.funding_tx.input.iter_mut().enumerate() {
+			if !self.holder_input_indices.contains(&(idx as u32)) && Some(idx as u32) != shared_input_index {
+				input.witness = witnesses.next().unwrap().clone();
+			}
+		}
+		if let Some(ref mut shared_input) = self.shared_input {
+			let counterparty_signature = msg.shared_input_signature.unwrap();
+			shared_input.counterparty_signature = Some(counterparty_signature);
+
+			let witness = &mut self.funding_tx.input[shared_input.index as usize].witness;
+			// The multisig dummy element, then both signatures in funding pubkey order
+			witness.push(Vec::new());
+			let mut holder_sig = shared_input.holder_signature.serialize_der().to_vec();
+			holder_sig.push(SigHashType::All as u8);
+			let mut counterparty_sig = counterparty_signature.serialize_der().to_vec();
+			counterparty_sig.push(SigHashType::All as u8);
+			if shared_input.holder_sig_first {
+				witness.push(holder_sig);
+				witness.push(counterparty_sig);
+			} else {
+				witness.push(counterparty_sig);
+				witness.push(holder_sig);
+			}
+			witness.push(shared_input.witness_script.as_bytes().to_vec());
+		}
+		self.counterparty_signed = true;
+		Ok(())
+	}
+
+	/// Gets our tx_signatures if we have signed and it is our turn to send them, marking them sent.
+	pub(super) fn get_tx_signatures(&mut self, channel_id: [u8; 32]) -> Option<msgs::TxSignatures> {
+		if !self.holder_signed || self.sent_tx_signatures || !(self.holder_sends_first || self.counterparty_signed) {
+			return None;
+		}
+		self.sent_tx_signatures = true;
+		Some(self.build_tx_signatures(channel_id))
+	}
+
+	/// Gets the tx_signatures we previously sent, for retransmission upon reconnection.
+	pub(super) fn get_sent_tx_signatures(&self, channel_id: [u8; 32]) -> Option<msgs::TxSignatures> {
+		if self.sent_tx_signatures { Some(self.build_tx_signatures(channel_id)) } else { None }
+	}
+
+	fn build_tx_signatures(&self, channel_id: [u8; 32]) -> msgs::TxSignatures {
+		msgs::TxSignatures {
+			channel_id,
+			txid: self.funding_tx.txid(),
+			witnesses: self.holder_input_indices.iter().map(|idx| self.funding_tx.input[*idx as usize].witness.clone()).collect(),
+			shared_input_signature: self.shared_input.as_ref().map(|shared_input| shared_input.holder_signature),
+		}
+	}
+}
+
+impl Writeable for FundingTxSigningState {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		self.funding_tx.write(writer)?;
+		(self.holder_input_indices.len() as u64).write(writer)?;
+		for idx in self.holder_input_indices.iter() {
+			idx.write(writer)?;
+		}
+		self.holder_sends_first.write(writer)?;
+		self.holder_signed.write(writer)?;
+		self.counterparty_signed.write(writer)?;
+		self.sent_tx_signatures.write(writer)?;
+		match self.shared_input {
+			Some(ref shared_input) => {
+				1u8.write(writer)?;
+				shared_input.index.write(writer)?;
+				shared_input.witness_script.write(writer)?;
+				shared_input.holder_sig_first.write(writer)?;
+				shared_input.holder_signature.write(writer)?;
+				shared_input.counterparty_signature.write(writer)?;
+			},
+			None => 0u8.write(writer)?,
+		}
+		Ok(())
+	}
+}
+
+impl Readable for FundingTxSigningState {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		let funding_tx: Transaction = Readable::read(reader)?;
+		let indices_count: u64 = Readable::read(reader)?;
+		let mut holder_input_indices = Vec::with_capacity(cmp::min(indices_count as usize, funding_tx.input.len()));
+		for _ in 0..indices_count {
+			let idx: u32 = Readable::read(reader)?;
+			if idx as usize >= funding_tx.input.len() {
+				return Err(DecodeError::InvalidValue);
+			}
+			holder_input_indices.push(idx);
+		}
+		let holder_sends_first = Readable::read(reader)?;
+		let holder_signed = Readable::read(reader)?;
+		let counterparty_signed = Readable::read(reader)?;
+		let sent_tx_signatures = Readable::read(reader)?;
+		let shared_input = match <u8 as Readable>::read(reader)? {
+			0 => None,
+			1 => {
+				let index: u32 = Readable::read(reader)?;
+				if index as usize >= funding_tx.input.len() {
+					return Err(DecodeError::InvalidValue);
+				}
+				Some(SharedInputSignatures {
+					index,
+					witness_script: Readable::read(reader)?,
+					holder_sig_first: Readable::read(reader)?,
+					holder_signature: Readable::read(reader)?,
+					counterparty_signature: Readable::read(reader)?,
+				})
+			},
+			_ => return Err(DecodeError::InvalidValue),
+		};
+		Ok(FundingTxSigningState {
+			funding_tx,
+			holder_input_indices,
+			holder_sends_first,
+			holder_signed,
+			counterparty_signed,
+			sent_tx_signatures,
+			shared_input,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::opcodes;
+	use bitcoin::blockdata::script::{Builder, Script};
+	use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+
+	use ln::channel::ChannelError;
+	use ln::interactivetxs::{FundingInput, FundingTxSigningState, InteractiveTxConstructor, InteractiveTxMessageSend};
+	use ln::msgs;
+
+	const CHANNEL_ID: [u8; 32] = [42; 32];
+
+	fn witness_output(value: u64, b: u8) -> TxOut {
+		TxOut { value, script_pubkey: Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[b; 32]).into_script() }
+	}
+
+	fn prevtx(value: u64, lock_time: u32) -> Transaction {
+		Transaction { version: 2, lock_time, input: Vec::new(), output: vec![witness_output(value, 1)] }
+	}
+
+	fn tx_add_input(serial_id: u64, prevtx: Transaction, sequence: u32) -> msgs::TxAddInput {
+		msgs::TxAddInput { channel_id: CHANNEL_ID, serial_id, prevtx: Some(prevtx), prevtx_out: 0, sequence, shared_input_txid: None }
+	}
+
+	fn tx_add_output(serial_id: u64, output: TxOut) -> msgs::TxAddOutput {
+		msgs::TxAddOutput { channel_id: CHANNEL_ID, serial_id, sats: output.value, script: output.script_pubkey }
+	}
+
+	fn assert_close<T>(res: Result<T, ChannelError>, expected: &str) {
+		match res {
+			Err(ChannelError::Close(msg)) => assert_eq!(msg, expected),
+			Err(e) => panic!("Unexpected error {:?}", e),
+			Ok(_) => panic!("Expected an error"),
+		}
+	}
+
+	/// Creates a constructor for an initiator adding a single input, which has sent it.
+	fn initiator_after_first_input() -> InteractiveTxConstructor {
+		let mut constructor = InteractiveTxConstructor::new(CHANNEL_ID, true, None, vec![FundingInput {
+			prevtx: prevtx(200_000, 0), prevtx_out: 0, sequence: 0xfffffffd,
+		}], Vec::new());
+		match constructor.next_message() {
+			InteractiveTxMessageSend::TxAddInput(msg) => assert_eq!(msg.serial_id, 0),
+			_ => panic!("Unexpected message"),
+		}
+		constructor
+	}
+
+	#[test]
+	fn serial_id_parity_and_duplicates() {
+		// The initiator's counterparty must use odd serial_ids...
+		let mut constructor = initiator_after_first_input();
+		assert_close(constructor.received_tx_add_input(&tx_add_input(2, prevtx(100_000, 1), 0xfffffffd)),
+			"Received an interactive transaction message with a serial_id of the wrong parity");
+
+		// ...and may not reuse one, neither for an input nor an output
+		let mut constructor = initiator_after_first_input();
+		constructor.received_tx_add_input(&tx_add_input(1, prevtx(100_000, 1), 0xfffffffd)).unwrap();
+		match constructor.next_message() {
+			InteractiveTxMessageSend::TxComplete(_) => {},
+			_ => panic!("Unexpected message"),
+		}
+		assert_close(constructor.received_tx_add_output(&tx_add_output(1, witness_output(50_000, 2))),
+			"Received an interactive transaction message with a duplicate serial_id");
+
+		// The initiator itself must use even ones
+		let mut constructor = InteractiveTxConstructor::new(CHANNEL_ID, false, None, Vec::new(), Vec::new());
+		assert_close(constructor.received_tx_add_output(&tx_add_output(1, witness_output(50_000, 2))),
+			"Received an interactive transaction message with a serial_id of the wrong parity");
+	}
+
+	#[test]
+	fn out_of_turn_messages() {
+		// The initiator sends the first message...
+		let mut constructor = InteractiveTxConstructor::new(CHANNEL_ID, true, None, Vec::new(), Vec::new());
+		assert_close(constructor.received_tx_add_input(&tx_add_input(1, prevtx(100_000, 1), 0xfffffffd)),
+			"Received an interactive transaction message out of turn");
+		assert_close(constructor.received_tx_complete(), "Received an interactive transaction message out of turn");
+
+		// ...and peers then take turns
+		let mut constructor = initiator_after_first_input();
+		constructor.received_tx_add_output(&tx_add_output(1, witness_output(50_000, 2))).unwrap();
+		assert_close(constructor.received_tx_add_output(&tx_add_output(3, witness_output(50_000, 3))),
+			"Received an interactive transaction message out of turn");
+
+		// Nothing may be received once both peers have sent tx_complete
+		let mut constructor = initiator_after_first_input();
+		constructor.received_tx_complete().unwrap();
+		match constructor.next_message() {
+			InteractiveTxMessageSend::TxComplete(_) => {},
+			_ => panic!("Unexpected message"),
+		}
+		assert!(constructor.is_complete());
+		assert_close(constructor.received_tx_add_input(&tx_add_input(1, prevtx(100_000, 1), 0xfffffffd)),
+			"Received an interactive transaction message out of turn");
+		assert_close(constructor.received_tx_complete(), "Received an interactive transaction message out of turn");
+	}
+
+	#[test]
+	fn non_replaceable_input() {
+		for sequence in [0xfffffffe, 0xffffffff].iter() {
+			let mut constructor = initiator_after_first_input();
+			assert_close(constructor.received_tx_add_input(&tx_add_input(1, prevtx(100_000, 1), *sequence)),
+				"Counterparty added a funding input which doesn't signal replaceability");
+		}
+	}
+
+	#[test]
+	fn underfunded_counterparty_contribution() {
+		let funding_output = witness_output(300_000, 3);
+		let run = |counterparty_input_value: u64| {
+			let mut constructor = InteractiveTxConstructor::new(CHANNEL_ID, true, None, vec![FundingInput {
+				prevtx: prevtx(200_000, 0), prevtx_out: 0, sequence: 0xfffffffd,
+			}], vec![funding_output.clone()]);
+			constructor.next_message();
+			constructor.received_tx_add_input(&tx_add_input(1, prevtx(counterparty_input_value, 1), 0xfffffffd)).unwrap();
+			constructor.next_message();
+			constructor.received_tx_complete().unwrap();
+			constructor.next_message();
+			assert!(constructor.is_complete());
+			constructor.check_counterparty_contribution(&funding_output, 100_000, 1000)
+		};
+		// At 1000 sat/kW, the counterparty pays 273 sat for its input on top of its 100_000 sat.
+		run(100_273).unwrap();
+		assert_close(run(100_272), "Counterparty's funding inputs do not cover its funding amount, outputs and fees");
+	}
+
+	#[test]
+	fn tx_signatures_ordering() {
+		let mut funding_tx = Transaction { version: 2, lock_time: 0, input: Vec::new(), output: vec![witness_output(300_000, 3)] };
+		for lock_time in 0..2 {
+			funding_tx.input.push(TxIn {
+				previous_output: OutPoint { txid: prevtx(100_000, lock_time).txid(), vout: 0 },
+				script_sig: Script::new(), sequence: 0xfffffffd, witness: Vec::new(),
+			});
+		}
+		let mut signed_tx = funding_tx.clone();
+		signed_tx.input[0].witness = vec![vec![1]];
+		let counterparty_tx_signatures = |witnesses: Vec<Vec<Vec<u8>>>| msgs::TxSignatures {
+			channel_id: CHANNEL_ID, txid: funding_tx.txid(), witnesses, shared_input_signature: None,
+		};
+
+		// If we send first, we may not receive our counterparty's tx_signatures before sending
+		// ours...
+		let mut state = FundingTxSigningState::new(funding_tx.clone(), vec![0], true, None);
+		assert_close(state.received_tx_signatures(&counterparty_tx_signatures(vec![vec![vec![2]]])), "Received tx_signatures before we sent ours");
+		assert!(state.get_tx_signatures(CHANNEL_ID).is_none());
+		state.provide_holder_witnesses(&signed_tx).unwrap();
+		assert_eq!(state.get_tx_signatures(CHANNEL_ID).unwrap().witnesses, vec![vec![vec![1]]]);
+		// ...which must then carry one witness for each of their inputs
+		assert_close(state.received_tx_signatures(&counterparty_tx_signatures(vec![vec![vec![2]], vec![vec![3]]])), "Received tx_signatures with the wrong number of witnesses");
+		state.received_tx_signatures(&counterparty_tx_signatures(vec![vec![vec![2]]])).unwrap();
+		assert_eq!(state.get_signed_funding_tx().unwrap().input[1].witness, vec![vec![2]]);
+
+		// Otherwise, we hold on to ours until we've received theirs
+		let mut state = FundingTxSigningState::new(funding_tx.clone(), vec![0], false, None);
+		state.provide_holder_witnesses(&signed_tx).unwrap();
+		assert!(state.get_tx_signatures(CHANNEL_ID).is_none());
+		assert_close(state.received_tx_signatures(&counterparty_tx_signatures(Vec::new())), "Received tx_signatures with the wrong number of witnesses");
+		state.received_tx_signatures(&counterparty_tx_signatures(vec![vec![vec![2]]])).unwrap();
+		assert!(state.get_tx_signatures(CHANNEL_ID).is_some());
+		assert!(state.get_signed_funding_tx().is_some());
+	}
+}
@@ -0,0 +1,779 @@
+//! Utilities for generating and parsing BOLT 11 invoices, which communicate everything a payer
+//! needs to pay us (the payment hash, amount, private route hints, etc) as a bech32 string.
+//!
+//! An Invoice is created by filling in an UnsignedInvoice and signing it with our node key, as
+//! provided by KeysInterface::get_node_secret, and is encoded with to_string(). Invoices are
+//! parsed with str::parse, which checks the invoice's signature and recovers the payee's node id
+//! from it.
+//!
+//! See [BOLT #11] for details.
+//!
+//! [BOLT #11]: https://github.com/lightningnetwork/lightning-rfc/blob/master/11-payment-encoding.md
+
+use bitcoin::bech32;
+use bitcoin::bech32::{u5, FromBase32, ToBase32};
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::key::{PublicKey, SecretKey};
+use bitcoin::secp256k1::recovery::{RecoverableSignature, RecoveryId};
+use bitcoin::secp256k1::{Message, Secp256k1};
+
+use chain::keysinterface::KeysInterface;
+use ln::channelmanager::{PaymentHash, PaymentSecret};
+use ln::features::InvoiceFeatures;
+use routing::network_graph::RoutingFees;
+use routing::router::RouteHint;
+use util::byte_utils;
+
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// The number of seconds after its timestamp that an invoice which doesn't specify an expiry
+/// time expires.
+pub const DEFAULT_EXPIRY_TIME: u64 = 3600;
+/// The min_final_cltv_expiry of an invoice which doesn't specify one.
+pub const DEFAULT_MIN_FINAL_CLTV_EXPIRY: u32 = 18;
+
+/// Timestamps are encoded in 7 base32 words, ie 35 bits.
+const TIMESTAMP_WORDS: usize = 7;
+const MAX_TIMESTAMP: u64 = (1 << 35) - 1;
+/// Tagged field lengths are encoded in 2 base32 words, ie 10 bits.
+const MAX_FIELD_WORDS: usize = (1 << 10) - 1;
+/// A 64-byte compact signature followed by a 1-byte recovery id.
+const SIGNATURE_WORDS: usize = 104;
+/// Each hop in a route hint is a pubkey, short_channel_id, fee_base_msat,
+/// fee_proportional_millionths and cltv_expiry_delta.
+const ROUTE_HINT_HOP_LEN: usize = 33 + 8 + 4 + 4 + 2;
+/// No more than 21 million bitcoin may be requested.
+const MAX_AMOUNT_MSAT: u64 = 21_000_000 * 100_000_000 * 1000;
+
+const TAG_PAYMENT_HASH: u8 = 1;
+const TAG_ROUTE_HINT: u8 = 3;
+const TAG_FEATURES: u8 = 5;
+const TAG_EXPIRY_TIME: u8 = 6;
+const TAG_DESCRIPTION: u8 = 13;
+const TAG_PAYMENT_SECRET: u8 = 16;
+const TAG_PAYEE_PUB_KEY: u8 = 19;
+const TAG_DESCRIPTION_HASH: u8 = 23;
+const TAG_MIN_FINAL_CLTV_EXPIRY: u8 = 24;
+
+/// A description of what a payment is for, as included in an invoice.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvoiceDescription {
+	/// A short UTF-8 description of the payment.
+	Direct(String),
+	/// The SHA-256 hash of a longer description of the payment, which is communicated to the payer
+	/// out-of-band.
+	Hash(Sha256),
+}
+
+/// The contents of an invoice, before it is signed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnsignedInvoice {
+	/// The network the payment is to be made on.
+	pub network: Network,
+	/// The amount requested, in msat, if any. If None, the payer picks the amount to pay.
+	pub amount_msat: Option<u64>,
+	/// The time the invoice was created, in seconds since the UNIX epoch.
+	pub timestamp: u64,
+	/// The hash of the payment preimage which will be revealed once the payment is claimed.
+	pub payment_hash: PaymentHash,
+	/// The payment_secret the payer must include in the payment's onion, required for paying the
+	/// invoice over multiple paths. Should be set for all new invoices.
+	pub payment_secret: Option<PaymentSecret>,
+	/// What the payment is for.
+	pub description: InvoiceDescription,
+	/// The number of seconds after timestamp after which the invoice should no longer be paid. If
+	/// None, DEFAULT_EXPIRY_TIME applies.
+	pub expiry_time: Option<u64>,
+	/// The CLTV delta the payer must use for the final hop. If None,
+	/// DEFAULT_MIN_FINAL_CLTV_EXPIRY applies.
+	pub min_final_cltv_expiry: Option<u32>,
+	/// Paths through private channels which the payer may use to reach us. Each hint is a list of
+	/// hops, the last of which is a channel to us.
+	///
+	/// Note that htlc_minimum_msat is not communicated in invoices and is ignored.
+	pub route_hints: Vec<Vec<RouteHint>>,
+	/// The features the payer may use when paying the invoice. Should generally be set to
+	/// InvoiceFeatures::known().
+	pub features: Option<InvoiceFeatures>,
+}
+
+/// A signed BOLT 11 invoice. Use to_string() to get its encoding and str::parse to read one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invoice {
+	contents: UnsignedInvoice,
+	payee_pub_key: PublicKey,
+	/// The human-readable part and data part (less the signature) exactly as signed, so that
+	/// fields we don't understand are preserved when re-encoding a parsed invoice.
+	hrp: String,
+	data: Vec<u5>,
+	signature: RecoverableSignature,
+}
+
+/// An error in creating an invoice.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CreationError {
+	/// The amount was zero or more than 21 million bitcoin.
+	InvalidAmount,
+	/// The timestamp does not fit in the 35 bits available in an invoice.
+	TimestampOutOfBounds,
+	/// The description is too long to fit in an invoice (ie longer than 639 bytes).
+	DescriptionTooLong,
+	/// A route hint has too many hops to fit in an invoice (ie more than 12) or has no hops.
+	InvalidRouteHint,
+}
+
+/// An error in parsing an invoice.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+	/// The string was not valid bech32.
+	Bech32Error(bech32::Error),
+	/// The human-readable part did not start with "ln" followed by the prefix of a known network.
+	UnknownNetwork,
+	/// The amount was malformed, less than 1 msat or more than 21 million bitcoin.
+	InvalidAmount,
+	/// The data part ended in the middle of the timestamp, a tagged field or the signature.
+	TooShortDataPart,
+	/// An integer field was too large to represent.
+	IntegerOverflow,
+	/// The description was not valid UTF-8.
+	InvalidDescription,
+	/// The payee's public key field was not a valid public key.
+	InvalidPubKey,
+	/// A route hint field was malformed.
+	InvalidRouteHint,
+	/// The invoice requires features we don't understand.
+	UnknownRequiredFeature,
+	/// The invoice did not include a payment hash.
+	MissingPaymentHash,
+	/// The invoice included neither a description nor a description hash.
+	MissingDescription,
+	/// The invoice included both a description and a description hash.
+	MultipleDescriptions,
+	/// The signature was malformed or did not match the payee's public key.
+	InvalidSignature,
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ParseError::Bech32Error(ref e) => e.fmt(f),
+			ParseError::UnknownNetwork => f.write_str("Invoice is for an unknown network"),
+			ParseError::InvalidAmount => f.write_str("Invoice amount was malformed or out of range"),
+			ParseError::TooShortDataPart => f.write_str("Invoice data ended unexpectedly"),
+			ParseError::IntegerOverflow => f.write_str("Invoice integer field was too large"),
+			ParseError::InvalidDescription => f.write_str("Invoice description was not valid UTF-8"),
+			ParseError::InvalidPubKey => f.write_str("Invoice payee public key was invalid"),
+			ParseError::InvalidRouteHint => f.write_str("Invoice route hint was malformed"),
+			ParseError::UnknownRequiredFeature => f.write_str("Invoice requires unknown features"),
+			ParseError::MissingPaymentHash => f.write_str("Invoice did not include a payment hash"),
+			ParseError::MissingDescription => f.write_str("Invoice did not include a description"),
+			ParseError::MultipleDescriptions => f.write_str("Invoice included both a description and a description hash"),
+			ParseError::InvalidSignature => f.write_str("Invoice signature was invalid"),
+		}
+	}
+}
+
+fn network_prefix(network: Network) -> &'static str {
+	match network {
+		Network::Bitcoin => "bc",
+		Network::Testnet => "tb",
+		Network::Regtest => "bcrt",
+	}
+}
+
+/// Encodes an integer as big-endian base32 words, using exactly the given number of words.
+fn encode_int_words(value: u64, word_count: usize) -> Vec<u5> {
+	(0..word_count).rev().map(|idx| {
+		let word = if idx * 5 < 64 { (value >> (idx * 5)) & 0x1f } else { 0 };
+		u5::try_from_u8(word as u8).unwrap()
+	}).collect()
+}
+
+/// Encodes an integer as big-endian base32 words, omitting leading zero words.
+fn encode_int(value: u64) -> Vec<u5> {
+	let mut word_count = 0;
+	while word_count < 13 && value >> (word_count * 5) != 0 {
+		word_count += 1;
+	}
+	encode_int_words(value, word_count)
+}
+
+fn decode_int(words: &[u5]) -> Result<u64, ParseError> {
+	let mut value = 0u64;
+	for word in words {
+		if value >> 59 != 0 {
+			return Err(ParseError::IntegerOverflow);
+		}
+		value = (value << 5) | word.to_u8() as u64;
+	}
+	Ok(value)
+}
+
+fn push_field(data: &mut Vec<u5>, tag: u8, field: Vec<u5>) {
+	debug_assert!(field.len() <= MAX_FIELD_WORDS);
+	data.push(u5::try_from_u8(tag).unwrap());
+	data.extend_from_slice(&encode_int_words(field.len() as u64, 2));
+	data.extend_from_slice(&field);
+}
+
+/// Gets the message signed by the payee: the hash of the human-readable part followed by the data
+/// part, padded with zero bits to a whole number of bytes.
+fn signature_message(hrp: &str, data: &[u5]) -> Message {
+	let words: Vec<u8> = data.iter().map(|word| word.to_u8()).collect();
+	let mut preimage = hrp.as_bytes().to_vec();
+	preimage.extend_from_slice(&bech32::convert_bits(&words, 5, 8, true).unwrap());
+	Message::from_slice(&Sha256::hash(&preimage)[..]).unwrap()
+}
+
+impl UnsignedInvoice {
+	/// Signs the invoice with our node key, as provided by the given KeysInterface's
+	/// get_node_secret.
+	pub fn sign<K: Deref>(self, keys_manager: K) -> Result<Invoice, CreationError> where K::Target: KeysInterface {
+		self.sign_with_key(&keys_manager.get_node_secret())
+	}
+
+	fn sign_with_key(self, node_secret: &SecretKey) -> Result<Invoice, CreationError> {
+		let hrp = self.encode_hrp()?;
+		let data = self.encode_data()?;
+		let secp_ctx = Secp256k1::signing_only();
+		let signature = secp_ctx.sign_recoverable(&signature_message(&hrp, &data), node_secret);
+		let payee_pub_key = PublicKey::from_secret_key(&secp_ctx, node_secret);
+		Ok(Invoice {
+			contents: self,
+			payee_pub_key,
+			hrp,
+			data,
+			signature,
+		})
+	}
+
+	fn encode_hrp(&self) -> Result<String, CreationError> {
+		let amount = match self.amount_msat {
+			None => String::new(),
+			Some(amount_msat) if amount_msat == 0 || amount_msat > MAX_AMOUNT_MSAT => {
+				return Err(CreationError::InvalidAmount);
+			},
+			// Use the largest multiplier which represents the amount exactly.
+			Some(amount_msat) if amount_msat % 100_000_000_000 == 0 => format!("{}", amount_msat / 100_000_000_000),
+			Some(amount_msat) if amount_msat % 100_000_000 == 0 => format!("{}m", amount_msat / 100_000_000),
+			Some(amount_msat) if amount_msat % 100_000 == 0 => format!("{}u", amount_msat / 100_000),
+			Some(amount_msat) if amount_msat % 100 == 0 => format!("{}n", amount_msat / 100),
+			// 1 msat is 10 pico-bitcoin.
+			Some(amount_msat) => format!("{}0p", amount_msat),
+		};
+		Ok(format!("ln{}{}", network_prefix(self.network), amount))
+	}
+
+	fn encode_data(&self) -> Result<Vec<u5>, CreationError> {
+		if self.timestamp > MAX_TIMESTAMP {
+			return Err(CreationError::TimestampOutOfBounds);
+		}
+		let mut data = encode_int_words(self.timestamp, TIMESTAMP_WORDS);
+
+		push_field(&mut data, TAG_PAYMENT_HASH, self.payment_hash.0.to_base32());
+		if let Some(ref payment_secret) = self.payment_secret {
+			push_field(&mut data, TAG_PAYMENT_SECRET, payment_secret.0.to_base32());
+		}
+		match self.description {
+			InvoiceDescription::Direct(ref description) => {
+				let words = description.as_bytes().to_base32();
+				if words.len() > MAX_FIELD_WORDS {
+					return Err(CreationError::DescriptionTooLong);
+				}
+				push_field(&mut data, TAG_DESCRIPTION, words);
+			},
+			InvoiceDescription::Hash(ref hash) => push_field(&mut data, TAG_DESCRIPTION_HASH, hash.into_inner().to_base32()),
+		}
+		if let Some(expiry_time) = self.expiry_time {
+			push_field(&mut data, TAG_EXPIRY_TIME, encode_int(expiry_time));
+		}
+		if let Some(min_final_cltv_expiry) = self.min_final_cltv_expiry {
+			push_field(&mut data, TAG_MIN_FINAL_CLTV_EXPIRY, encode_int(min_final_cltv_expiry as u64));
+		}
+		for route_hint in self.route_hints.iter() {
+			let mut bytes = Vec::with_capacity(route_hint.len() * ROUTE_HINT_HOP_LEN);
+			for hop in route_hint.iter() {
+				bytes.extend_from_slice(&hop.src_node_id.serialize());
+				bytes.extend_from_slice(&byte_utils::be64_to_array(hop.short_channel_id));
+				bytes.extend_from_slice(&byte_utils::be32_to_array(hop.fees.base_msat));
+				bytes.extend_from_slice(&byte_utils::be32_to_array(hop.fees.proportional_millionths));
+				bytes.extend_from_slice(&byte_utils::be16_to_array(hop.cltv_expiry_delta));
+			}
+			let words = bytes.to_base32();
+			if words.is_empty() || words.len() > MAX_FIELD_WORDS {
+				return Err(CreationError::InvalidRouteHint);
+			}
+			push_field(&mut data, TAG_ROUTE_HINT, words);
+		}
+		if let Some(ref features) = self.features {
+			push_field(&mut data, TAG_FEATURES, features.to_base32());
+		}
+		Ok(data)
+	}
+}
+
+impl Invoice {
+	/// The network the payment is to be made on.
+	pub fn network(&self) -> Network {
+		self.contents.network
+	}
+
+	/// The amount requested, in msat, if any. If None, the payer picks the amount to pay.
+	pub fn amount_msat(&self) -> Option<u64> {
+		self.contents.amount_msat
+	}
+
+	/// The time the invoice was created, in seconds since the UNIX epoch.
+	pub fn timestamp(&self) -> u64 {
+		self.contents.timestamp
+	}
+
+	/// The hash of the payment preimage which will be revealed once the payment is claimed.
+	pub fn payment_hash(&self) -> &PaymentHash {
+		&self.contents.payment_hash
+	}
+
+	/// The payment_secret to provide when paying the invoice, if any.
+	pub fn payment_secret(&self) -> Option<&PaymentSecret> {
+		self.contents.payment_secret.as_ref()
+	}
+
+	/// What the payment is for.
+	pub fn description(&self) -> &InvoiceDescription {
+		&self.contents.description
+	}
+
+	/// The node id of the payee, which signed the invoice.
+	pub fn payee_pub_key(&self) -> &PublicKey {
+		&self.payee_pub_key
+	}
+
+	/// The number of seconds after timestamp after which the invoice should no longer be paid.
+	pub fn expiry_time(&self) -> u64 {
+		self.contents.expiry_time.unwrap_or(DEFAULT_EXPIRY_TIME)
+	}
+
+	/// Returns true if the invoice has expired as of the given time, in seconds since the UNIX
+	/// epoch.
+	pub fn is_expired(&self, now: u64) -> bool {
+		now >= self.timestamp().saturating_add(self.expiry_time())
+	}
+
+	/// The CLTV delta to use for the final hop when paying the invoice, eg as get_route's
+	/// final_cltv.
+	pub fn min_final_cltv_expiry(&self) -> u32 {
+		self.contents.min_final_cltv_expiry.unwrap_or(DEFAULT_MIN_FINAL_CLTV_EXPIRY)
+	}
+
+	/// Paths through private channels which may be used to reach the payee. Each hint is a list
+	/// of hops, the last of which is a channel to the payee.
+	pub fn route_hints(&self) -> &Vec<Vec<RouteHint>> {
+		&self.contents.route_hints
+	}
+
+	/// Gets the last hop of each route hint, for use as get_route's last_hops.
+	///
+	/// Note that get_route can only make use of the final hop of each hint, so hints whose earlier
+	/// hops are through private channels will not be usable.
+	pub fn last_hops(&self) -> Vec<RouteHint> {
+		self.contents.route_hints.iter().filter_map(|route_hint| route_hint.last().cloned()).collect()
+	}
+
+	/// The features the payer may use when paying the invoice, if any were given.
+	pub fn features(&self) -> Option<&InvoiceFeatures> {
+		self.contents.features.as_ref()
+	}
+}
+
+impl fmt::Display for Invoice {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let (recovery_id, signature) = self.signature.serialize_compact();
+		let mut signature_bytes = signature.to_vec();
+		signature_bytes.push(recovery_id.to_i32() as u8);
+
+		let mut data = self.data.clone();
+		data.extend_from_slice(&signature_bytes.to_base32());
+		f.write_str(&bech32::encode(&self.hrp, data).map_err(|_| fmt::Error)?)
+	}
+}
+
+/// Parses the network and amount out of an invoice's human-readable part.
+fn parse_hrp(hrp: &str) -> Result<(Network, Option<u64>), ParseError> {
+	if !hrp.starts_with("ln") {
+		return Err(ParseError::UnknownNetwork);
+	}
+	let hrp = &hrp[2..];
+	// Regtest must be checked first as its prefix begins with mainnet's.
+	let network = [Network::Regtest, Network::Bitcoin, Network::Testnet].iter()
+		.find(|network| hrp.starts_with(network_prefix(**network)))
+		.cloned().ok_or(ParseError::UnknownNetwork)?;
+	let amount = &hrp[network_prefix(network).len()..];
+	if amount.is_empty() {
+		return Ok((network, None));
+	}
+
+	let (digits, multiplier) = match amount.chars().last().unwrap() {
+		'm' | 'u' | 'n' | 'p' => amount.split_at(amount.len() - 1),
+		_ => (amount, ""),
+	};
+	if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+		return Err(ParseError::InvalidAmount);
+	}
+	let amount_msat = match multiplier {
+		"" => digits.parse::<u64>().ok().and_then(|value| value.checked_mul(100_000_000_000)),
+		"m" => digits.parse::<u64>().ok().and_then(|value| value.checked_mul(100_000_000)),
+		"u" => digits.parse::<u64>().ok().and_then(|value| value.checked_mul(100_000)),
+		"n" => digits.parse::<u64>().ok().and_then(|value| value.checked_mul(100)),
+		// 1 msat is 10 pico-bitcoin, so anything which isn't a multiple of 10 is a fraction of a msat.
+		_ if digits.ends_with('0') => digits[..digits.len() - 1].parse::<u64>().ok(),
+		_ => None,
+	};
+	match amount_msat {
+		Some(amount_msat) if amount_msat != 0 && amount_msat <= MAX_AMOUNT_MSAT => Ok((network, Some(amount_msat))),
+		_ => Err(ParseError::InvalidAmount),
+	}
+}
+
+fn parse_bytes(words: &[u5]) -> Result<Vec<u8>, ParseError> {
+	Vec::<u8>::from_base32(words).map_err(|e| ParseError::Bech32Error(e))
+}
+
+fn parse_route_hint(bytes: &[u8]) -> Result<Vec<RouteHint>, ParseError> {
+	debug_assert_eq!(bytes.len() % ROUTE_HINT_HOP_LEN, 0);
+	let mut route_hint = Vec::with_capacity(bytes.len() / ROUTE_HINT_HOP_LEN);
+	for hop in bytes.chunks(ROUTE_HINT_HOP_LEN) {
+		route_hint.push(RouteHint {
+			src_node_id: PublicKey::from_slice(&hop[0..33]).map_err(|_| ParseError::InvalidRouteHint)?,
+			short_channel_id: byte_utils::slice_to_be64(&hop[33..41]),
+			fees: RoutingFees {
+				base_msat: byte_utils::slice_to_be32(&hop[41..45]),
+				proportional_millionths: byte_utils::slice_to_be32(&hop[45..49]),
+			},
+			cltv_expiry_delta: byte_utils::slice_to_be16(&hop[49..51]),
+			htlc_minimum_msat: 0,
+		});
+	}
+	Ok(route_hint)
+}
+
+impl FromStr for Invoice {
+	type Err = ParseError;
+
+	fn from_str(s: &str) -> Result<Invoice, ParseError> {
+		let (hrp, data) = bech32::decode(s).map_err(|e| ParseError::Bech32Error(e))?;
+		let (network, amount_msat) = parse_hrp(&hrp)?;
+		if data.len() < TIMESTAMP_WORDS + SIGNATURE_WORDS {
+			return Err(ParseError::TooShortDataPart);
+		}
+		let (data, signature_words) = data.split_at(data.len() - SIGNATURE_WORDS);
+		let signature_bytes = parse_bytes(signature_words)?;
+		let recovery_id = RecoveryId::from_i32(signature_bytes[64] as i32).map_err(|_| ParseError::InvalidSignature)?;
+		let signature = RecoverableSignature::from_compact(&signature_bytes[..64], recovery_id)
+			.map_err(|_| ParseError::InvalidSignature)?;
+		let timestamp = decode_int(&data[..TIMESTAMP_WORDS])?;
+
+		let mut payment_hash = None;
+		let mut payment_secret = None;
+		let mut description = None;
+		let mut description_hash = None;
+		let mut payee_pub_key = None;
+		let mut expiry_time = None;
+		let mut min_final_cltv_expiry = None;
+		let mut route_hints = Vec::new();
+		let mut features = None;
+
+		let mut fields = &data[TIMESTAMP_WORDS..];
+		while !fields.is_empty() {
+			if fields.len() < 3 {
+				return Err(ParseError::TooShortDataPart);
+			}
+			let tag = fields[0].to_u8();
+			let field_len = decode_int(&fields[1..3])? as usize;
+			if fields.len() < 3 + field_len {
+				return Err(ParseError::TooShortDataPart);
+			}
+			let field = &fields[3..3 + field_len];
+			fields = &fields[3 + field_len..];
+
+			// Per BOLT 11, unknown fields and known fields with an unexpected length are skipped.
+			match tag {
+				TAG_PAYMENT_HASH if field.len() == 52 && payment_hash.is_none() => {
+					let mut hash = [0; 32];
+					hash.copy_from_slice(&parse_bytes(field)?);
+					payment_hash = Some(PaymentHash(hash));
+				},
+				TAG_PAYMENT_SECRET if field.len() == 52 && payment_secret.is_none() => {
+					let mut secret = [0; 32];
+					secret.copy_from_slice(&parse_bytes(field)?);
+					payment_secret = Some(PaymentSecret(secret));
+				},
+				TAG_DESCRIPTION if description.is_none() => {
+					let bytes = parse_bytes(field)?;
+					description = Some(String::from_utf8(bytes).map_err(|_| ParseError::InvalidDescription)?);
+				},
+				TAG_DESCRIPTION_HASH if field.len() == 52 && description_hash.is_none() => {
+					description_hash = Some(Sha256::from_slice(&parse_bytes(field)?).unwrap());
+				},
+				TAG_PAYEE_PUB_KEY if field.len() == 53 && payee_pub_key.is_none() => {
+					payee_pub_key = Some(PublicKey::from_slice(&parse_bytes(field)?).map_err(|_| ParseError::InvalidPubKey)?);
+				},
+				TAG_EXPIRY_TIME if expiry_time.is_none() => {
+					expiry_time = Some(decode_int(field)?);
+				},
+				TAG_MIN_FINAL_CLTV_EXPIRY if min_final_cltv_expiry.is_none() => {
+					let cltv_expiry = decode_int(field)?;
+					if cltv_expiry > ::std::u32::MAX as u64 {
+						return Err(ParseError::IntegerOverflow);
+					}
+					min_final_cltv_expiry = Some(cltv_expiry as u32);
+				},
+				TAG_ROUTE_HINT => {
+					let bytes = parse_bytes(field)?;
+					if !bytes.is_empty() && bytes.len() % ROUTE_HINT_HOP_LEN == 0 {
+						route_hints.push(parse_route_hint(&bytes)?);
+					}
+				},
+				TAG_FEATURES if features.is_none() => {
+					let invoice_features = InvoiceFeatures::from_base32(field);
+					if invoice_features.requires_unknown_bits() {
+						return Err(ParseError::UnknownRequiredFeature);
+					}
+					features = Some(invoice_features);
+				},
+				_ => {},
+			}
+		}
+
+		let payment_hash = payment_hash.ok_or(ParseError::MissingPaymentHash)?;
+		let description = match (description, description_hash) {
+			(Some(description), None) => InvoiceDescription::Direct(description),
+			(None, Some(hash)) => InvoiceDescription::Hash(hash),
+			(None, None) => return Err(ParseError::MissingDescription),
+			(Some(_), Some(_)) => return Err(ParseError::MultipleDescriptions),
+		};
+
+		// If the payee's public key was given we verify the signature against it, otherwise it is
+		// recovered from the signature.
+		let secp_ctx = Secp256k1::verification_only();
+		let message = signature_message(&hrp, data);
+		let payee_pub_key = match payee_pub_key {
+			Some(pubkey) => {
+				secp_ctx.verify(&message, &signature.to_standard(), &pubkey).map_err(|_| ParseError::InvalidSignature)?;
+				pubkey
+			},
+			None => secp_ctx.recover(&message, &signature).map_err(|_| ParseError::InvalidSignature)?,
+		};
+
+		Ok(Invoice {
+			contents: UnsignedInvoice {
+				network,
+				amount_msat,
+				timestamp,
+				payment_hash,
+				payment_secret,
+				description,
+				expiry_time,
+				min_final_cltv_expiry,
+				route_hints,
+				features,
+			},
+			payee_pub_key,
+			hrp,
+			data: data.to_vec(),
+			signature,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use chain::keysinterface::KeysInterface;
+	use ln::channelmanager::{PaymentHash, PaymentSecret};
+	use ln::features::InvoiceFeatures;
+	use ln::invoice::{parse_hrp, Invoice, InvoiceDescription, ParseError, UnsignedInvoice};
+	use routing::network_graph::RoutingFees;
+	use routing::router::RouteHint;
+	use util::test_utils;
+
+	use bitcoin::bech32::{u5, FromBase32, ToBase32};
+	use bitcoin::bech32;
+	use bitcoin::hashes::Hash;
+	use bitcoin::hashes::sha256::Hash as Sha256;
+	use bitcoin::network::constants::Network;
+	use bitcoin::secp256k1::key::{PublicKey, SecretKey};
+	use bitcoin::secp256k1::Secp256k1;
+
+	use hex;
+
+	fn spec_node_secret() -> SecretKey {
+		SecretKey::from_slice(&hex::decode("e126f68f7eafcc8b74f54d269fe206be715000f94dac067d1c04a8ca3b2db734").unwrap()[..]).unwrap()
+	}
+
+	fn spec_payment_hash() -> PaymentHash {
+		let mut payment_hash = [0; 32];
+		payment_hash.copy_from_slice(&hex::decode("0001020304050607080900010203040506070809000102030405060708090102").unwrap());
+		PaymentHash(payment_hash)
+	}
+
+	#[test]
+	fn bolt11_test_vectors() {
+		let payee_pub_key = PublicKey::from_slice(&hex::decode("03e7156ae33b0a208d0744199163177e909e80176e55d97a2f221ede0f934dd9ad").unwrap()[..]).unwrap();
+
+		// A donation of any amount.
+		let encoded = "lnbc1pvjluezpp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpl2pkx2ctnv5sxxmmwwd5kgetjypeh2ursdae8g6twvus8g6rfwvs8qun0dfjkxaq8rkx3yf5tcsyz3d73gafnh3cax9rn449d9p5uxz9ezhhypd0elx87sjle52x86fux2ypatgddc6k63n7erqz25le42c4u4ecky03ylcqca784w";
+		let invoice: Invoice = encoded.parse().unwrap();
+		assert_eq!(invoice.network(), Network::Bitcoin);
+		assert_eq!(invoice.amount_msat(), None);
+		assert_eq!(invoice.timestamp(), 1496314658);
+		assert_eq!(*invoice.payment_hash(), spec_payment_hash());
+		assert_eq!(*invoice.description(), InvoiceDescription::Direct("Please consider supporting this project".to_owned()));
+		assert_eq!(*invoice.payee_pub_key(), payee_pub_key);
+		assert_eq!(invoice.expiry_time(), 3600);
+		assert_eq!(invoice.min_final_cltv_expiry(), 18);
+		assert_eq!(invoice.to_string(), encoded);
+
+		// Signing the same contents with the same key gives the same (deterministic) signature.
+		let unsigned = UnsignedInvoice {
+			network: Network::Bitcoin,
+			amount_msat: None,
+			timestamp: 1496314658,
+			payment_hash: spec_payment_hash(),
+			payment_secret: None,
+			description: InvoiceDescription::Direct("Please consider supporting this project".to_owned()),
+			expiry_time: None,
+			min_final_cltv_expiry: None,
+			route_hints: Vec::new(),
+			features: None,
+		};
+		assert_eq!(unsigned.sign_with_key(&spec_node_secret()).unwrap().to_string(), encoded);
+
+		// $3 for a cup of coffee, within one minute.
+		let encoded = "lnbc2500u1pvjluezpp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpuaztrnwngzn3kdzw5hydlzf03qdgm2hdq27cqv3agm2awhz5se903vruatfhq77w3ls4evs3ch9zw97j25emudupq63nyw24cg27h2rspfj9srp";
+		let invoice: Invoice = encoded.parse().unwrap();
+		assert_eq!(invoice.amount_msat(), Some(250_000_000));
+		assert_eq!(*invoice.description(), InvoiceDescription::Direct("1 cup coffee".to_owned()));
+		assert_eq!(invoice.expiry_time(), 60);
+		assert!(!invoice.is_expired(1496314658 + 59));
+		assert!(invoice.is_expired(1496314658 + 60));
+		assert_eq!(*invoice.payee_pub_key(), payee_pub_key);
+		assert_eq!(invoice.to_string(), encoded);
+	}
+
+	#[test]
+	fn invoice_round_trip() {
+		let secp_ctx = Secp256k1::new();
+		let hop_node = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		let route_hint = |short_channel_id| RouteHint {
+			src_node_id: hop_node,
+			short_channel_id,
+			fees: RoutingFees { base_msat: 1000, proportional_millionths: 100 },
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 0,
+		};
+		let keys_manager = test_utils::TestKeysInterface::new(&[7; 32], Network::Regtest);
+		let unsigned = UnsignedInvoice {
+			network: Network::Regtest,
+			amount_msat: Some(1_234_567),
+			timestamp: 1_600_000_000,
+			payment_hash: spec_payment_hash(),
+			payment_secret: Some(PaymentSecret([9; 32])),
+			description: InvoiceDescription::Hash(Sha256::hash(&[1; 1000])),
+			expiry_time: Some(24 * 3600),
+			min_final_cltv_expiry: Some(40),
+			route_hints: vec![vec![route_hint(1), route_hint(2)], vec![route_hint(3)]],
+			features: Some(InvoiceFeatures::known()),
+		};
+		let invoice = unsigned.clone().sign(&keys_manager).unwrap();
+		let encoded = invoice.to_string();
+		assert!(encoded.starts_with("lnbcrt12345670p1"));
+
+		let parsed: Invoice = encoded.parse().unwrap();
+		assert_eq!(parsed, invoice);
+		assert_eq!(*parsed.payee_pub_key(), PublicKey::from_secret_key(&secp_ctx, &keys_manager.get_node_secret()));
+		assert_eq!(parsed.payment_secret(), Some(&PaymentSecret([9; 32])));
+		assert_eq!(parsed.min_final_cltv_expiry(), 40);
+		assert_eq!(parsed.features(), Some(&InvoiceFeatures::known()));
+		assert_eq!(*parsed.route_hints(), unsigned.route_hints);
+		assert_eq!(parsed.last_hops(), vec![route_hint(2), route_hint(3)]);
+
+		// Any change to the signed data invalidates the signature (or, with a recovered key, the
+		// payee's identity).
+		let (hrp, mut data) = bech32::decode(&encoded).unwrap();
+		data[0] = u5::try_from_u8(data[0].to_u8() ^ 1).unwrap();
+		let tampered: Invoice = bech32::encode(&hrp, data).unwrap().parse().unwrap();
+		assert_ne!(tampered.payee_pub_key(), parsed.payee_pub_key());
+	}
+
+	#[test]
+	fn rejects_invalid_invoices() {
+		assert_eq!(parse_hrp("lnbc"), Ok((Network::Bitcoin, None)));
+		assert_eq!(parse_hrp("lntb2500u"), Ok((Network::Testnet, Some(250_000_000))));
+		assert_eq!(parse_hrp("lnbcrt10p"), Ok((Network::Regtest, Some(1))));
+		assert_eq!(parse_hrp("lnbc1p"), Err(ParseError::InvalidAmount));
+		assert_eq!(parse_hrp("lnbcm"), Err(ParseError::InvalidAmount));
+		assert_eq!(parse_hrp("lnbc0"), Err(ParseError::InvalidAmount));
+		assert_eq!(parse_hrp("lnbc21000001"), Err(ParseError::InvalidAmount));
+		assert_eq!(parse_hrp("lnxy"), Err(ParseError::UnknownNetwork));
+		assert_eq!(parse_hrp("bc"), Err(ParseError::UnknownNetwork));
+
+		let unsigned = UnsignedInvoice {
+			network: Network::Bitcoin,
+			amount_msat: None,
+			timestamp: 1496314658,
+			payment_hash: spec_payment_hash(),
+			payment_secret: None,
+			description: InvoiceDescription::Direct("test".to_owned()),
+			expiry_time: None,
+			min_final_cltv_expiry: None,
+			route_hints: Vec::new(),
+			features: None,
+		};
+		let invoice = unsigned.clone().sign_with_key(&spec_node_secret()).unwrap();
+		let (hrp, data) = bech32::decode(&invoice.to_string()).unwrap();
+		let (data, signature) = data.split_at(data.len() - 104);
+
+		// Invoices requiring unknown features are rejected.
+		let mut features_data = data.to_vec();
+		let mut unknown_features = vec![u5::try_from_u8(0).unwrap(); 20];
+		unknown_features[0] = u5::try_from_u8(2).unwrap();
+		features_data.extend_from_slice(&[u5::try_from_u8(5).unwrap(), u5::try_from_u8(0).unwrap(), u5::try_from_u8(20).unwrap()]);
+		features_data.extend_from_slice(&unknown_features);
+		let signature_bytes = Vec::<u8>::from_base32(signature).unwrap();
+		features_data.extend_from_slice(&signature_bytes.to_base32());
+		assert_eq!(bech32::encode(&hrp, features_data).unwrap().parse::<Invoice>(), Err(ParseError::UnknownRequiredFeature));
+
+		// Route hints which aren't a whole number of hops long are skipped.
+		let mut route_hint_data = data.to_vec();
+		route_hint_data.extend_from_slice(&[u5::try_from_u8(3).unwrap(), u5::try_from_u8(0).unwrap(), u5::try_from_u8(16).unwrap()]);
+		route_hint_data.extend_from_slice(&[u8::max_value(); 10].to_base32());
+		route_hint_data.extend_from_slice(&signature_bytes.to_base32());
+		let parsed = bech32::encode(&hrp, route_hint_data).unwrap().parse::<Invoice>().unwrap();
+		assert!(parsed.route_hints().is_empty());
+
+		// Invoices with both a description and a description hash are rejected.
+		let mut description_hash_data = data.to_vec();
+		description_hash_data.extend_from_slice(&[u5::try_from_u8(23).unwrap(), u5::try_from_u8(1).unwrap(), u5::try_from_u8(20).unwrap()]);
+		description_hash_data.extend_from_slice(&[1; 32].to_base32());
+		description_hash_data.extend_from_slice(&signature_bytes.to_base32());
+		assert_eq!(bech32::encode(&hrp, description_hash_data).unwrap().parse::<Invoice>(), Err(ParseError::MultipleDescriptions));
+
+		// Truncated invoices are rejected.
+		let mut truncated = data[..data.len() - 1].to_vec();
+		truncated.extend_from_slice(signature);
+		assert_eq!(bech32::encode(&hrp, truncated).unwrap().parse::<Invoice>(), Err(ParseError::TooShortDataPart));
+
+		// As are those missing a description.
+		let mut no_description = unsigned.clone();
+		no_description.description = InvoiceDescription::Direct(String::new());
+		let invoice = no_description.sign_with_key(&spec_node_secret()).unwrap();
+		assert!(invoice.to_string().parse::<Invoice>().is_ok());
+		let (hrp, data) = bech32::decode(&invoice.to_string()).unwrap();
+		let mut stripped = data[..data.len() - 104 - 3].to_vec();
+		stripped.extend_from_slice(&data[data.len() - 104..]);
+		assert_eq!(bech32::encode(&hrp, stripped).unwrap().parse::<Invoice>(), Err(ParseError::MissingDescription));
+	}
+}
@@ -15,6 +15,9 @@ pub mod msgs;
 pub mod peer_handler;
 pub mod chan_utils;
 pub mod features;
+pub mod invoice;
+pub mod payment_retry;
+pub mod interactivetxs;
 pub(crate) mod onchaintx;
 
 #[cfg(feature = "fuzztarget")]
@@ -23,6 +26,7 @@ pub mod peer_channel_encryptor;
 pub(crate) mod peer_channel_encryptor;
 
 mod channel;
+mod inbound_payment;
 mod onion_utils;
 mod wire;
 
@@ -19,6 +19,8 @@ use bitcoin::secp256k1::key::PublicKey;
 use bitcoin::secp256k1::Signature;
 use bitcoin::secp256k1;
 use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
 use bitcoin::hash_types::{Txid, BlockHash};
 
 use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
@@ -52,6 +54,8 @@ pub enum DecodeError {
 	BadLengthDescriptor,
 	/// Error from std::io
 	Io(::std::io::Error),
+	/// The message included zlib-compressed values, which we don't support.
+	UnsupportedCompression,
 }
 
 /// An init message to be sent or received from a peer
@@ -124,6 +128,136 @@ pub struct AcceptChannel {
 	pub(crate) shutdown_scriptpubkey: OptionalField<Script>
 }
 
+/// An open_channel2 message to be sent or received from a peer, opening a channel which both
+/// peers may contribute funds to.
+#[derive(Clone)]
+pub struct OpenChannelV2 {
+	pub(crate) chain_hash: BlockHash,
+	pub(crate) temporary_channel_id: [u8; 32],
+	pub(crate) funding_feerate_per_kw: u32,
+	pub(crate) commitment_feerate_per_kw: u32,
+	pub(crate) funding_satoshis: u64,
+	pub(crate) dust_limit_satoshis: u64,
+	pub(crate) max_htlc_value_in_flight_msat: u64,
+	pub(crate) htlc_minimum_msat: u64,
+	pub(crate) to_self_delay: u16,
+	pub(crate) max_accepted_htlcs: u16,
+	pub(crate) locktime: u32,
+	pub(crate) funding_pubkey: PublicKey,
+	pub(crate) revocation_basepoint: PublicKey,
+	pub(crate) payment_point: PublicKey,
+	pub(crate) delayed_payment_basepoint: PublicKey,
+	pub(crate) htlc_basepoint: PublicKey,
+	pub(crate) first_per_commitment_point: PublicKey,
+	pub(crate) channel_flags: u8,
+	pub(crate) shutdown_scriptpubkey: OptionalField<Script>,
+}
+
+/// An accept_channel2 message to be sent or received from a peer
+#[derive(Clone)]
+pub struct AcceptChannelV2 {
+	pub(crate) temporary_channel_id: [u8; 32],
+	pub(crate) funding_satoshis: u64,
+	pub(crate) dust_limit_satoshis: u64,
+	pub(crate) max_htlc_value_in_flight_msat: u64,
+	pub(crate) htlc_minimum_msat: u64,
+	pub(crate) minimum_depth: u32,
+	pub(crate) to_self_delay: u16,
+	pub(crate) max_accepted_htlcs: u16,
+	pub(crate) funding_pubkey: PublicKey,
+	pub(crate) revocation_basepoint: PublicKey,
+	pub(crate) payment_point: PublicKey,
+	pub(crate) delayed_payment_basepoint: PublicKey,
+	pub(crate) htlc_basepoint: PublicKey,
+	pub(crate) first_per_commitment_point: PublicKey,
+	pub(crate) shutdown_scriptpubkey: OptionalField<Script>,
+}
+
+/// A tx_add_input message to be sent or received from a peer, adding an input to a funding
+/// transaction under interactive construction.
+#[derive(Clone, PartialEq)]
+pub struct TxAddInput {
+	pub(crate) channel_id: [u8; 32],
+	pub(crate) serial_id: u64,
+	/// The transaction containing the output being spent, which is omitted for the channel's
+	/// funding output when splicing.
+	pub(crate) prevtx: Option<Transaction>,
+	pub(crate) prevtx_out: u32,
+	pub(crate) sequence: u32,
+	/// Set, instead of prevtx, when adding the channel's funding output as the shared input of a
+	/// splice transaction.
+	pub(crate) shared_input_txid: Option<Txid>,
+}
+
+/// A tx_add_output message to be sent or received from a peer, adding an output to a funding
+/// transaction under interactive construction.
+#[derive(Clone, PartialEq)]
+pub struct TxAddOutput {
+	pub(crate) channel_id: [u8; 32],
+	pub(crate) serial_id: u64,
+	pub(crate) sats: u64,
+	pub(crate) script: Script,
+}
+
+/// A tx_complete message to be sent or received from a peer, indicating the sender has nothing
+/// further to add to a funding transaction under interactive construction.
+#[derive(Clone, PartialEq)]
+pub struct TxComplete {
+	pub(crate) channel_id: [u8; 32],
+}
+
+/// A tx_signatures message to be sent or received from a peer, providing the witnesses for the
+/// sender's inputs to an interactively-constructed funding transaction.
+#[derive(Clone, PartialEq)]
+pub struct TxSignatures {
+	pub(crate) channel_id: [u8; 32],
+	pub(crate) txid: Txid,
+	/// The witness stack for each of the sender's inputs, in the order they appear in the
+	/// transaction.
+	pub(crate) witnesses: Vec<Vec<Vec<u8>>>,
+	/// The sender's signature for the channel's funding output when it is spent by a splice
+	/// transaction.
+	pub(crate) shared_input_signature: Option<Signature>,
+}
+
+/// A splice_init message to be sent or received from a peer, proposing to splice funds into or
+/// out of a channel.
+#[derive(Clone, PartialEq)]
+pub struct SpliceInit {
+	pub(crate) channel_id: [u8; 32],
+	/// The change, in satoshis, to the sender's channel balance, which is negative when splicing
+	/// funds out.
+	pub(crate) funding_contribution_satoshis: i64,
+	pub(crate) funding_feerate_per_kw: u32,
+	pub(crate) locktime: u32,
+	pub(crate) funding_pubkey: PublicKey,
+}
+
+/// A splice_ack message to be sent or received from a peer, accepting a proposed splice.
+#[derive(Clone, PartialEq)]
+pub struct SpliceAck {
+	pub(crate) channel_id: [u8; 32],
+	/// The change, in satoshis, to the sender's channel balance.
+	pub(crate) funding_contribution_satoshis: i64,
+	pub(crate) funding_pubkey: PublicKey,
+}
+
+/// A splice_locked message to be sent or received from a peer, indicating the splice transaction
+/// has reached the sender's minimum depth.
+#[derive(Clone, PartialEq)]
+pub struct SpliceLocked {
+	pub(crate) channel_id: [u8; 32],
+	pub(crate) splice_txid: Txid,
+}
+
+/// A tx_abort message to be sent or received from a peer, abandoning the construction of an
+/// interactively-constructed transaction.
+#[derive(Clone, PartialEq)]
+pub struct TxAbort {
+	pub(crate) channel_id: [u8; 32],
+	pub(crate) data: Vec<u8>,
+}
+
 /// A funding_created message to be sent or received from a peer
 #[derive(Clone)]
 pub struct FundingCreated {
@@ -199,12 +333,21 @@ pub struct UpdateFailMalformedHTLC {
 	pub(crate) failure_code: u16,
 }
 
+/// The signatures for the commitment transaction spending the funding output of a pending splice
+/// transaction, sent along with those for the one spending the current funding output.
+#[derive(Clone, PartialEq)]
+pub(crate) struct SplicedCommitmentSignatures {
+	pub(crate) signature: Signature,
+	pub(crate) htlc_signatures: Vec<Signature>,
+}
+
 /// A commitment_signed message to be sent or received from a peer
 #[derive(Clone, PartialEq)]
 pub struct CommitmentSigned {
 	pub(crate) channel_id: [u8; 32],
 	pub(crate) signature: Signature,
 	pub(crate) htlc_signatures: Vec<Signature>,
+	pub(crate) spliced_signatures: OptionalField<SplicedCommitmentSignatures>,
 }
 
 /// A revoke_and_ack message to be sent or received from a peer
@@ -432,6 +575,9 @@ pub(crate) struct UnsignedChannelUpdate {
 	pub(crate) htlc_minimum_msat: u64,
 	pub(crate) fee_base_msat: u32,
 	pub(crate) fee_proportional_millionths: u32,
+	/// The maximum value, in msat, which the channel will relay in a single HTLC. Present if and
+	/// only if bit 0 of message_flags (ie 1 << 8 in flags) is set.
+	pub(crate) htlc_maximum_msat: OptionalField<u64>,
 	pub(crate) excess_data: Vec<u8>,
 }
 /// A channel_update message to be sent or received from a peer
@@ -441,6 +587,78 @@ pub struct ChannelUpdate {
 	pub(crate) contents: UnsignedChannelUpdate,
 }
 
+/// A query_channel_range message to be sent to a peer, requesting the short_channel_ids of all
+/// channels whose funding transactions confirmed within a range of blocks. The peer should
+/// respond with one or more reply_channel_range messages.
+#[derive(PartialEq, Clone, Debug)]
+pub struct QueryChannelRange {
+	/// The genesis hash of the blockchain being queried
+	pub(crate) chain_hash: BlockHash,
+	/// The height of the first block for the channels being queried
+	pub(crate) first_blocknum: u32,
+	/// The number of blocks to include in the query
+	pub(crate) number_of_blocks: u32,
+}
+
+/// A reply_channel_range message to be sent or received from a peer in response to a
+/// query_channel_range. A query may be answered by several replies, which together cover the
+/// queried block range.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ReplyChannelRange {
+	/// The genesis hash of the blockchain being queried
+	pub(crate) chain_hash: BlockHash,
+	/// The height of the first block covered by this reply
+	pub(crate) first_blocknum: u32,
+	/// The number of blocks covered by this reply
+	pub(crate) number_of_blocks: u32,
+	/// False if the replying node does not maintain up-to-date channel information for chain_hash
+	pub(crate) sync_complete: bool,
+	/// The short_channel_ids of the channels in the covered block range
+	pub(crate) short_channel_ids: Vec<u64>,
+}
+
+/// A query_short_channel_ids message to be sent to a peer, requesting the channel_announcement
+/// and channel_update messages (as well as the relevant node_announcement messages) for a set of
+/// channels. The peer should respond with the requested gossip followed by a
+/// reply_short_channel_ids_end message.
+#[derive(PartialEq, Clone, Debug)]
+pub struct QueryShortChannelIds {
+	/// The genesis hash of the blockchain being queried
+	pub(crate) chain_hash: BlockHash,
+	/// The short_channel_ids of the channels being queried
+	pub(crate) short_channel_ids: Vec<u64>,
+}
+
+/// A reply_short_channel_ids_end message to be sent or received from a peer once all gossip
+/// requested in a query_short_channel_ids has been sent.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ReplyShortChannelIdsEnd {
+	/// The genesis hash of the blockchain which was queried
+	pub(crate) chain_hash: BlockHash,
+	/// False if the replying node does not maintain up-to-date channel information for chain_hash
+	pub(crate) full_information: bool,
+}
+
+/// A gossip_timestamp_filter message to be sent or received from a peer which supports
+/// gossip_queries, limiting the gossip relayed to the sender to messages whose timestamp lies
+/// within the given range.
+#[derive(PartialEq, Clone, Debug)]
+pub struct GossipTimestampFilter {
+	/// The genesis hash of the blockchain for which gossip is requested
+	pub(crate) chain_hash: BlockHash,
+	/// The lowest timestamp of gossip which should be relayed
+	pub(crate) first_timestamp: u32,
+	/// The number of seconds after first_timestamp for which gossip should be relayed
+	pub(crate) timestamp_range: u32,
+}
+
+impl GossipTimestampFilter {
+	/// Returns whether gossip with the given timestamp falls within this filter.
+	pub(crate) fn includes(&self, timestamp: u32) -> bool {
+		timestamp >= self.first_timestamp && (timestamp as u64) < self.first_timestamp as u64 + self.timestamp_range as u64
+	}
+}
+
 /// Used to put an error message in a LightningError
 #[derive(Clone)]
 pub enum ErrorAction {
@@ -517,7 +735,7 @@ pub enum HTLCFailChannelUpdate {
 /// As we wish to serialize these differently from Option<T>s (Options get a tag byte, but
 /// OptionalFeild simply gets Present if there are enough bytes to read into it), we have a
 /// separate enum type for them.
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum OptionalField<T> {
 	/// Optional field is included in message
 	Present(T),
@@ -542,6 +760,30 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 	/// Handle an incoming funding_locked message from the given peer.
 	fn handle_funding_locked(&self, their_node_id: &PublicKey, msg: &FundingLocked);
 
+	// Dual-funded channel init:
+	/// Handle an incoming open_channel2 message from the given peer.
+	fn handle_open_channel_v2(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &OpenChannelV2);
+	/// Handle an incoming accept_channel2 message from the given peer.
+	fn handle_accept_channel_v2(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &AcceptChannelV2);
+	/// Handle an incoming tx_add_input message from the given peer.
+	fn handle_tx_add_input(&self, their_node_id: &PublicKey, msg: &TxAddInput);
+	/// Handle an incoming tx_add_output message from the given peer.
+	fn handle_tx_add_output(&self, their_node_id: &PublicKey, msg: &TxAddOutput);
+	/// Handle an incoming tx_complete message from the given peer.
+	fn handle_tx_complete(&self, their_node_id: &PublicKey, msg: &TxComplete);
+	/// Handle an incoming tx_signatures message from the given peer.
+	fn handle_tx_signatures(&self, their_node_id: &PublicKey, msg: &TxSignatures);
+	/// Handle an incoming tx_abort message from the given peer.
+	fn handle_tx_abort(&self, their_node_id: &PublicKey, msg: &TxAbort);
+
+	// Splicing:
+	/// Handle an incoming splice_init message from the given peer.
+	fn handle_splice_init(&self, their_node_id: &PublicKey, msg: &SpliceInit);
+	/// Handle an incoming splice_ack message from the given peer.
+	fn handle_splice_ack(&self, their_node_id: &PublicKey, msg: &SpliceAck);
+	/// Handle an incoming splice_locked message from the given peer.
+	fn handle_splice_locked(&self, their_node_id: &PublicKey, msg: &SpliceLocked);
+
 	// Channl close:
 	/// Handle an incoming shutdown message from the given peer.
 	fn handle_shutdown(&self, their_node_id: &PublicKey, msg: &Shutdown);
@@ -576,6 +818,9 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 	/// and any outstanding channels should be failed.
 	fn peer_disconnected(&self, their_node_id: &PublicKey, no_connection_possible: bool);
 
+	/// Gets the features we advertise to peers in our Init message. The PeerManager may clear
+	/// some additional (eg routing-related) bits before sending it.
+	fn provided_init_features(&self) -> InitFeatures;
 	/// Handle a peer reconnecting, possibly generating channel_reestablish message(s).
 	fn peer_connected(&self, their_node_id: &PublicKey, msg: &Init);
 	/// Handle an incoming channel_reestablish message from the given peer.
@@ -587,7 +832,10 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 }
 
 /// A trait to describe an object which can receive routing messages.
-pub trait RoutingMessageHandler : Send + Sync {
+///
+/// Responses to gossip queries, as well as our own queries, are provided to the PeerManager via
+/// the MessageSendEventsProvider interface.
+pub trait RoutingMessageHandler : events::MessageSendEventsProvider + Send + Sync {
 	/// Handle an incoming node_announcement message, returning true if it should be forwarded on,
 	/// false or returning an Err otherwise.
 	fn handle_node_announcement(&self, msg: &NodeAnnouncement) -> Result<bool, LightningError>;
@@ -610,10 +858,28 @@ pub trait RoutingMessageHandler : Send + Sync {
 	fn get_next_node_announcements(&self, starting_point: Option<&PublicKey>, batch_amount: u8) -> Vec<NodeAnnouncement>;
 	/// Returns whether a full sync should be requested from a peer.
 	fn should_request_full_sync(&self, node_id: &PublicKey) -> bool;
+	/// Called when a connection is established with a peer, allowing us to sync our routing table
+	/// with it using gossip queries if the peer supports them.
+	fn sync_routing_table(&self, their_node_id: &PublicKey, init: &Init);
+	/// Indicates a connection to the peer failed/an existing connection was lost, allowing us to
+	/// forget any gossip queries we had outstanding with it.
+	fn peer_disconnected(&self, their_node_id: &PublicKey);
+	/// Handle an incoming reply_channel_range message from the given peer, generally querying it
+	/// for any channels we don't yet know about.
+	fn handle_reply_channel_range(&self, their_node_id: &PublicKey, msg: &ReplyChannelRange) -> Result<(), LightningError>;
+	/// Handle an incoming reply_short_channel_ids_end message from the given peer, indicating it
+	/// has finished replying to our previous query_short_channel_ids.
+	fn handle_reply_short_channel_ids_end(&self, their_node_id: &PublicKey, msg: &ReplyShortChannelIdsEnd) -> Result<(), LightningError>;
+	/// Handle an incoming query_channel_range message from the given peer, replying with the
+	/// short_channel_ids of the channels we know about in the queried block range.
+	fn handle_query_channel_range(&self, their_node_id: &PublicKey, msg: &QueryChannelRange) -> Result<(), LightningError>;
+	/// Handle an incoming query_short_channel_ids message from the given peer, replying with the
+	/// gossip we have for the queried channels.
+	fn handle_query_short_channel_ids(&self, their_node_id: &PublicKey, msg: &QueryShortChannelIds) -> Result<(), LightningError>;
 }
 
 mod fuzzy_internal_msgs {
-	use ln::channelmanager::PaymentSecret;
+	use ln::channelmanager::{PaymentPreimage, PaymentSecret};
 
 	// These types aren't intended to be pub, but are exposed for direct fuzzing (as we deserialize
 	// them from untrusted input):
@@ -634,6 +900,9 @@ mod fuzzy_internal_msgs {
 		},
 		FinalNode {
 			payment_data: Option<FinalOnionHopData>,
+			/// The preimage of a spontaneous (keysend) payment, chosen by the sender. This isn't part
+			/// of the BOLTs, but is sent in TLV type 5482373484 as other implementations do.
+			keysend_preimage: Option<PaymentPreimage>,
 		},
 	}
 
@@ -695,6 +964,7 @@ impl fmt::Display for DecodeError {
 			DecodeError::ShortRead => f.write_str("Packet extended beyond the provided bytes"),
 			DecodeError::BadLengthDescriptor => f.write_str("A length descriptor in the packet didn't describe the later data correctly"),
 			DecodeError::Io(ref e) => e.fmt(f),
+			DecodeError::UnsupportedCompression => f.write_str("We don't support receiving messages with zlib-compressed fields"),
 		}
 	}
 }
@@ -763,6 +1033,27 @@ impl_writeable_len_match!(AcceptChannel, {
 	shutdown_scriptpubkey
 });
 
+impl_writeable_len_match!(AcceptChannelV2, {
+		{AcceptChannelV2{ shutdown_scriptpubkey: OptionalField::Present(ref script), .. }, 270 + 2 + script.len()},
+		{_, 270}
+	}, {
+	temporary_channel_id,
+	funding_satoshis,
+	dust_limit_satoshis,
+	max_htlc_value_in_flight_msat,
+	htlc_minimum_msat,
+	minimum_depth,
+	to_self_delay,
+	max_accepted_htlcs,
+	funding_pubkey,
+	revocation_basepoint,
+	payment_point,
+	delayed_payment_basepoint,
+	htlc_basepoint,
+	first_per_commitment_point,
+	shutdown_scriptpubkey
+});
+
 impl_writeable!(AnnouncementSignatures, 32+8+64*2, {
 	channel_id,
 	short_channel_id,
@@ -814,13 +1105,43 @@ impl_writeable!(ClosingSigned, 32+8+64, {
 	signature
 });
 
-impl_writeable_len_match!(CommitmentSigned, {
-		{ CommitmentSigned { ref htlc_signatures, .. }, 32+64+2+htlc_signatures.len()*64 }
-	}, {
-	channel_id,
-	signature,
-	htlc_signatures
-});
+impl Writeable for CommitmentSigned {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32+64+2+self.htlc_signatures.len()*64 + if let OptionalField::Present(ref spliced_signatures) = self.spliced_signatures { 64+2+spliced_signatures.htlc_signatures.len()*64 } else { 0 });
+		self.channel_id.write(w)?;
+		self.signature.write(w)?;
+		self.htlc_signatures.write(w)?;
+		match self.spliced_signatures {
+			OptionalField::Present(ref spliced_signatures) => {
+				spliced_signatures.signature.write(w)?;
+				spliced_signatures.htlc_signatures.write(w)?;
+			},
+			OptionalField::Absent => {}
+		}
+		Ok(())
+	}
+}
+
+impl Readable for CommitmentSigned {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		Ok(Self {
+			channel_id: Readable::read(r)?,
+			signature: Readable::read(r)?,
+			htlc_signatures: Readable::read(r)?,
+			spliced_signatures: {
+				match <Signature as Readable>::read(r) {
+					Ok(signature) =>
+						OptionalField::Present(SplicedCommitmentSignatures {
+							signature,
+							htlc_signatures: Readable::read(r)?,
+						}),
+					Err(DecodeError::ShortRead) => OptionalField::Absent,
+					Err(e) => return Err(e)
+				}
+			}
+		})
+	}
+}
 
 impl_writeable_len_match!(DecodedOnionErrorPacket, {
 		{ DecodedOnionErrorPacket { ref failuremsg, ref pad, .. }, 32 + 4 + failuremsg.len() + pad.len() }
@@ -891,6 +1212,31 @@ impl_writeable_len_match!(OpenChannel, {
 	shutdown_scriptpubkey
 });
 
+impl_writeable_len_match!(OpenChannelV2, {
+		{ OpenChannelV2 { shutdown_scriptpubkey: OptionalField::Present(ref script), .. }, 311 + 2 + script.len() },
+		{ _, 311 }
+	}, {
+	chain_hash,
+	temporary_channel_id,
+	funding_feerate_per_kw,
+	commitment_feerate_per_kw,
+	funding_satoshis,
+	dust_limit_satoshis,
+	max_htlc_value_in_flight_msat,
+	htlc_minimum_msat,
+	to_self_delay,
+	max_accepted_htlcs,
+	locktime,
+	funding_pubkey,
+	revocation_basepoint,
+	payment_point,
+	delayed_payment_basepoint,
+	htlc_basepoint,
+	first_per_commitment_point,
+	channel_flags,
+	shutdown_scriptpubkey
+});
+
 impl_writeable!(RevokeAndACK, 32+32+33, {
 	channel_id,
 	per_commitment_secret,
@@ -904,6 +1250,169 @@ impl_writeable_len_match!(Shutdown, {
 	scriptpubkey
 });
 
+impl Writeable for TxAddInput {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		// The previous transaction is length-prefixed, unlike in our other uses of Transaction,
+		// and empty for the shared input of a splice transaction.
+		let prevtx = match self.prevtx {
+			Some(ref prevtx) => encode::serialize(prevtx),
+			None => Vec::new(),
+		};
+		w.size_hint(32 + 8 + 2 + prevtx.len() + 4 + 4 + if self.shared_input_txid.is_some() { 34 } else { 0 });
+		self.channel_id.write(w)?;
+		self.serial_id.write(w)?;
+		prevtx.write(w)?;
+		self.prevtx_out.write(w)?;
+		self.sequence.write(w)?;
+		if let Some(ref shared_input_txid) = self.shared_input_txid {
+			encode_tlv!(w, {
+				(0, shared_input_txid)
+			});
+		}
+		Ok(())
+	}
+}
+
+impl Readable for TxAddInput {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let serial_id = Readable::read(r)?;
+		let prevtx_bytes: Vec<u8> = Readable::read(r)?;
+		let prevtx = if prevtx_bytes.is_empty() {
+			None
+		} else {
+			Some(encode::deserialize(&prevtx_bytes).map_err(|_| DecodeError::InvalidValue)?)
+		};
+		let prevtx_out = Readable::read(r)?;
+		let sequence = Readable::read(r)?;
+		let mut shared_input_txid: Option<Txid> = None;
+		decode_tlv!(&mut *r, {}, {(0, shared_input_txid)});
+		if prevtx.is_some() == shared_input_txid.is_some() {
+			return Err(DecodeError::InvalidValue);
+		}
+		Ok(TxAddInput {
+			channel_id,
+			serial_id,
+			prevtx,
+			prevtx_out,
+			sequence,
+			shared_input_txid,
+		})
+	}
+}
+
+impl_writeable_len_match!(TxAddOutput, {
+		{ TxAddOutput { ref script, .. }, 32 + 8 + 8 + 2 + script.len() }
+	}, {
+	channel_id,
+	serial_id,
+	sats,
+	script
+});
+
+impl_writeable!(TxComplete, 32, {
+	channel_id
+});
+
+impl Writeable for TxSignatures {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		self.channel_id.write(w)?;
+		self.txid.write(w)?;
+		(self.witnesses.len() as u16).write(w)?;
+		for witness in self.witnesses.iter() {
+			(witness.len() as u16).write(w)?;
+			for element in witness.iter() {
+				element.write(w)?;
+			}
+		}
+		if let Some(ref shared_input_signature) = self.shared_input_signature {
+			encode_tlv!(w, {
+				(0, shared_input_signature)
+			});
+		}
+		Ok(())
+	}
+}
+
+impl Readable for TxSignatures {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let txid = Readable::read(r)?;
+		let witnesses_count: u16 = Readable::read(r)?;
+		let mut witnesses = Vec::with_capacity(cmp::min(witnesses_count as usize, 64));
+		for _ in 0..witnesses_count {
+			let elements_count: u16 = Readable::read(r)?;
+			let mut witness = Vec::with_capacity(cmp::min(elements_count as usize, 16));
+			for _ in 0..elements_count {
+				let element: Vec<u8> = Readable::read(r)?;
+				witness.push(element);
+			}
+			witnesses.push(witness);
+		}
+		let mut shared_input_signature: Option<Signature> = None;
+		decode_tlv!(&mut *r, {}, {(0, shared_input_signature)});
+		Ok(TxSignatures { channel_id, txid, witnesses, shared_input_signature })
+	}
+}
+
+impl Writeable for SpliceInit {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 8 + 4 + 4 + 33);
+		self.channel_id.write(w)?;
+		(self.funding_contribution_satoshis as u64).write(w)?;
+		self.funding_feerate_per_kw.write(w)?;
+		self.locktime.write(w)?;
+		self.funding_pubkey.write(w)
+	}
+}
+
+impl Readable for SpliceInit {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let funding_contribution_satoshis: u64 = Readable::read(r)?;
+		Ok(SpliceInit {
+			channel_id,
+			funding_contribution_satoshis: funding_contribution_satoshis as i64,
+			funding_feerate_per_kw: Readable::read(r)?,
+			locktime: Readable::read(r)?,
+			funding_pubkey: Readable::read(r)?,
+		})
+	}
+}
+
+impl Writeable for SpliceAck {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 8 + 33);
+		self.channel_id.write(w)?;
+		(self.funding_contribution_satoshis as u64).write(w)?;
+		self.funding_pubkey.write(w)
+	}
+}
+
+impl Readable for SpliceAck {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let funding_contribution_satoshis: u64 = Readable::read(r)?;
+		Ok(SpliceAck {
+			channel_id,
+			funding_contribution_satoshis: funding_contribution_satoshis as i64,
+			funding_pubkey: Readable::read(r)?,
+		})
+	}
+}
+
+impl_writeable!(SpliceLocked, 32 + 32, {
+	channel_id,
+	splice_txid
+});
+
+impl_writeable_len_match!(TxAbort, {
+		{ TxAbort { ref data, .. }, 32 + 2 + data.len() }
+	}, {
+	channel_id,
+	data
+});
+
 impl_writeable_len_match!(UpdateFailHTLC, {
 		{ UpdateFailHTLC { ref reason, .. }, 32 + 10 + reason.data.len() }
 	}, {
@@ -1012,7 +1521,7 @@ impl Writeable for OnionHopData {
 					(6, short_channel_id)
 				});
 			},
-			OnionHopDataFormat::FinalNode { payment_data: Some(ref final_data) } => {
+			OnionHopDataFormat::FinalNode { payment_data: Some(ref final_data), keysend_preimage: None } => {
 				if final_data.total_msat > MAX_VALUE_MSAT { panic!("We should never be sending infinite/overflow onion payments"); }
 				encode_varint_length_prefixed_tlv!(w, {
 					(2, HighZeroBytesDroppedVarInt(self.amt_to_forward)),
@@ -1020,12 +1529,28 @@ impl Writeable for OnionHopData {
 					(8, final_data)
 				});
 			},
-			OnionHopDataFormat::FinalNode { payment_data: None } => {
+			OnionHopDataFormat::FinalNode { payment_data: Some(ref final_data), keysend_preimage: Some(ref keysend_preimage) } => {
+				if final_data.total_msat > MAX_VALUE_MSAT { panic!("We should never be sending infinite/overflow onion payments"); }
+				encode_varint_length_prefixed_tlv!(w, {
+					(2, HighZeroBytesDroppedVarInt(self.amt_to_forward)),
+					(4, HighZeroBytesDroppedVarInt(self.outgoing_cltv_value)),
+					(8, final_data),
+					(5482373484, keysend_preimage)
+				});
+			},
+			OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: None } => {
 				encode_varint_length_prefixed_tlv!(w, {
 					(2, HighZeroBytesDroppedVarInt(self.amt_to_forward)),
 					(4, HighZeroBytesDroppedVarInt(self.outgoing_cltv_value))
 				});
 			},
+			OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: Some(ref keysend_preimage) } => {
+				encode_varint_length_prefixed_tlv!(w, {
+					(2, HighZeroBytesDroppedVarInt(self.amt_to_forward)),
+					(4, HighZeroBytesDroppedVarInt(self.outgoing_cltv_value)),
+					(5482373484, keysend_preimage)
+				});
+			},
 		}
 		Ok(())
 	}
@@ -1046,16 +1571,18 @@ impl Readable for OnionHopData {
 			let mut cltv_value = HighZeroBytesDroppedVarInt(0u32);
 			let mut short_id: Option<u64> = None;
 			let mut payment_data: Option<FinalOnionHopData> = None;
+			let mut keysend_preimage: Option<PaymentPreimage> = None;
 			decode_tlv!(&mut rd, {
 				(2, amt),
 				(4, cltv_value)
 			}, {
 				(6, short_id),
-				(8, payment_data)
+				(8, payment_data),
+				(5482373484, keysend_preimage)
 			});
 			rd.eat_remaining().map_err(|_| DecodeError::ShortRead)?;
 			let format = if let Some(short_channel_id) = short_id {
-				if payment_data.is_some() { return Err(DecodeError::InvalidValue); }
+				if payment_data.is_some() || keysend_preimage.is_some() { return Err(DecodeError::InvalidValue); }
 				OnionHopDataFormat::NonFinalNode {
 					short_channel_id,
 				}
@@ -1066,7 +1593,8 @@ impl Readable for OnionHopData {
 					}
 				}
 				OnionHopDataFormat::FinalNode {
-					payment_data
+					payment_data,
+					keysend_preimage,
 				}
 			};
 			(format, amt.0, cltv_value.0)
@@ -1189,6 +1717,9 @@ impl Writeable for UnsignedChannelUpdate {
 		self.htlc_minimum_msat.write(w)?;
 		self.fee_base_msat.write(w)?;
 		self.fee_proportional_millionths.write(w)?;
+		if let OptionalField::Present(htlc_maximum_msat) = self.htlc_maximum_msat {
+			htlc_maximum_msat.write(w)?;
+		}
 		w.write_all(&self.excess_data[..])?;
 		Ok(())
 	}
@@ -1196,15 +1727,24 @@ impl Writeable for UnsignedChannelUpdate {
 
 impl Readable for UnsignedChannelUpdate {
 	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let chain_hash = Readable::read(r)?;
+		let short_channel_id = Readable::read(r)?;
+		let timestamp = Readable::read(r)?;
+		let flags: u16 = Readable::read(r)?;
 		Ok(Self {
-			chain_hash: Readable::read(r)?,
-			short_channel_id: Readable::read(r)?,
-			timestamp: Readable::read(r)?,
-			flags: Readable::read(r)?,
+			chain_hash,
+			short_channel_id,
+			timestamp,
+			flags,
 			cltv_expiry_delta: Readable::read(r)?,
 			htlc_minimum_msat: Readable::read(r)?,
 			fee_base_msat: Readable::read(r)?,
 			fee_proportional_millionths: Readable::read(r)?,
+			htlc_maximum_msat: if flags & (1 << 8) != 0 {
+				OptionalField::Present(Readable::read(r)?)
+			} else {
+				OptionalField::Absent
+			},
 			excess_data: {
 				let mut excess_data = vec![];
 				r.read_to_end(&mut excess_data)?;
@@ -1222,6 +1762,47 @@ impl_writeable_len_match!(ChannelUpdate, {
 	contents
 });
 
+impl Writeable for HTLCFailChannelUpdate {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		match self {
+			&HTLCFailChannelUpdate::ChannelUpdateMessage { ref msg } => {
+				0u8.write(w)?;
+				msg.write(w)?;
+			},
+			&HTLCFailChannelUpdate::ChannelClosed { ref short_channel_id, ref is_permanent } => {
+				1u8.write(w)?;
+				short_channel_id.write(w)?;
+				is_permanent.write(w)?;
+			},
+			&HTLCFailChannelUpdate::NodeFailure { ref node_id, ref is_permanent } => {
+				2u8.write(w)?;
+				node_id.write(w)?;
+				is_permanent.write(w)?;
+			},
+		}
+		Ok(())
+	}
+}
+
+impl Readable for HTLCFailChannelUpdate {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		match <u8 as Readable>::read(r)? {
+			0 => Ok(HTLCFailChannelUpdate::ChannelUpdateMessage {
+				msg: Readable::read(r)?,
+			}),
+			1 => Ok(HTLCFailChannelUpdate::ChannelClosed {
+				short_channel_id: Readable::read(r)?,
+				is_permanent: Readable::read(r)?,
+			}),
+			2 => Ok(HTLCFailChannelUpdate::NodeFailure {
+				node_id: Readable::read(r)?,
+				is_permanent: Readable::read(r)?,
+			}),
+			_ => Err(DecodeError::InvalidValue),
+		}
+	}
+}
+
 impl Writeable for ErrorMessage {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
 		w.size_hint(32 + 2 + self.data.len());
@@ -1351,13 +1932,114 @@ impl_writeable_len_match!(NodeAnnouncement, {
 	contents
 });
 
+/// The encoding type byte of a short_channel_id list which is not compressed. zlib-compressed
+/// lists (encoding type 1) are never sent and are rejected with DecodeError::UnsupportedCompression.
+const ENCODING_TYPE_UNCOMPRESSED: u8 = 0;
+const ENCODING_TYPE_ZLIB: u8 = 1;
+
+/// The maximum number of short_channel_ids whose uncompressed encoding fits in its u16 length.
+pub(crate) const MAX_ENCODED_SHORT_CHANNEL_IDS: usize = (0xffff - 1) / 8;
+
+/// Writes a list of short_channel_ids in the uncompressed encoding. Fails with an
+/// ErrorKind::InvalidInput io::Error if more than MAX_ENCODED_SHORT_CHANNEL_IDS
+/// short_channel_ids are provided, as their encoding's length would not fit in a u16.
+fn write_encoded_short_ids<W: Writer>(short_channel_ids: &[u64], w: &mut W) -> Result<(), ::std::io::Error> {
+	if short_channel_ids.len() > MAX_ENCODED_SHORT_CHANNEL_IDS {
+		return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput, "Too many short_channel_ids to encode in one message"));
+	}
+	((short_channel_ids.len() * 8 + 1) as u16).write(w)?;
+	ENCODING_TYPE_UNCOMPRESSED.write(w)?;
+	for short_channel_id in short_channel_ids.iter() {
+		short_channel_id.write(w)?;
+	}
+	Ok(())
+}
+
+fn read_encoded_short_ids<R: Read>(r: &mut R) -> Result<Vec<u64>, DecodeError> {
+	let encoding_len: u16 = Readable::read(r)?;
+	if encoding_len == 0 {
+		return Err(DecodeError::InvalidValue);
+	}
+	let encoding_type: u8 = Readable::read(r)?;
+	if encoding_type == ENCODING_TYPE_ZLIB {
+		return Err(DecodeError::UnsupportedCompression);
+	} else if encoding_type != ENCODING_TYPE_UNCOMPRESSED {
+		return Err(DecodeError::InvalidValue);
+	}
+	if (encoding_len - 1) % 8 != 0 {
+		return Err(DecodeError::InvalidValue);
+	}
+	let mut short_channel_ids = Vec::with_capacity((encoding_len as usize - 1) / 8);
+	for _ in 0..(encoding_len - 1) / 8 {
+		short_channel_ids.push(Readable::read(r)?);
+	}
+	Ok(short_channel_ids)
+}
+
+impl_writeable!(QueryChannelRange, 32+4+4, {
+	chain_hash,
+	first_blocknum,
+	number_of_blocks
+});
+
+impl Writeable for ReplyChannelRange {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 4 + 4 + 1 + 2 + 1 + self.short_channel_ids.len() * 8);
+		self.chain_hash.write(w)?;
+		self.first_blocknum.write(w)?;
+		self.number_of_blocks.write(w)?;
+		self.sync_complete.write(w)?;
+		write_encoded_short_ids(&self.short_channel_ids, w)
+	}
+}
+
+impl Readable for ReplyChannelRange {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		Ok(ReplyChannelRange {
+			chain_hash: Readable::read(r)?,
+			first_blocknum: Readable::read(r)?,
+			number_of_blocks: Readable::read(r)?,
+			sync_complete: Readable::read(r)?,
+			short_channel_ids: read_encoded_short_ids(r)?,
+		})
+	}
+}
+
+impl Writeable for QueryShortChannelIds {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 2 + 1 + self.short_channel_ids.len() * 8);
+		self.chain_hash.write(w)?;
+		write_encoded_short_ids(&self.short_channel_ids, w)
+	}
+}
+
+impl Readable for QueryShortChannelIds {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		Ok(QueryShortChannelIds {
+			chain_hash: Readable::read(r)?,
+			short_channel_ids: read_encoded_short_ids(r)?,
+		})
+	}
+}
+
+impl_writeable!(ReplyShortChannelIdsEnd, 32+1, {
+	chain_hash,
+	full_information
+});
+
+impl_writeable!(GossipTimestampFilter, 32+4+4, {
+	chain_hash,
+	first_timestamp,
+	timestamp_range
+});
+
 #[cfg(test)]
 mod tests {
 	use hex;
 	use ln::msgs;
 	use ln::msgs::{ChannelFeatures, FinalOnionHopData, InitFeatures, NodeFeatures, OptionalField, OnionErrorPacket, OnionHopDataFormat};
 	use ln::channelmanager::{PaymentPreimage, PaymentHash, PaymentSecret};
-	use util::ser::{Writeable, Readable};
+	use util::ser::{Writeable, Readable, VecWriter};
 
 	use bitcoin::hashes::hex::FromHex;
 	use bitcoin::util::address::Address;
@@ -1610,7 +2292,8 @@ mod tests {
 			htlc_minimum_msat: 1000000,
 			fee_base_msat: 10000,
 			fee_proportional_millionths: 20,
-			excess_data: if htlc_maximum_msat { vec![0, 0, 0, 0, 59, 154, 202, 0] } else { Vec::new() }
+			htlc_maximum_msat: if htlc_maximum_msat { OptionalField::Present(1_000_000_000) } else { OptionalField::Absent },
+			excess_data: Vec::new()
 		};
 		let channel_update = msgs::ChannelUpdate {
 			signature: sig_1,
@@ -1771,6 +2454,106 @@ mod tests {
 		assert_eq!(encoded_value, target_value);
 	}
 
+	#[test]
+	fn encoding_tx_add_output() {
+		let tx_add_output = msgs::TxAddOutput {
+			channel_id: [2; 32],
+			serial_id: 4,
+			sats: 100_000,
+			script: Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[1; 20]).into_script(),
+		};
+		let encoded_value = tx_add_output.encode();
+		let target_value = hex::decode("0202020202020202020202020202020202020202020202020202020202020202000000000000000400000000000186a0001600140101010101010101010101010101010101010101").unwrap();
+		assert_eq!(encoded_value, target_value);
+		assert!(msgs::TxAddOutput::read(&mut Cursor::new(&target_value)).unwrap() == tx_add_output);
+	}
+
+	#[test]
+	fn encoding_tx_signatures() {
+		let tx_signatures = msgs::TxSignatures {
+			channel_id: [2; 32],
+			txid: Txid::from_hex("0303030303030303030303030303030303030303030303030303030303030303").unwrap(),
+			witnesses: vec![vec![vec![0x51]], vec![vec![1, 2], Vec::new()]],
+			shared_input_signature: None,
+		};
+		let encoded_value = tx_signatures.encode();
+		let target_value = hex::decode("02020202020202020202020202020202020202020202020202020202020202020303030303030303030303030303030303030303030303030303030303030303000200010001510002000201020000").unwrap();
+		assert_eq!(encoded_value, target_value);
+		assert!(msgs::TxSignatures::read(&mut Cursor::new(&target_value)).unwrap() == tx_signatures);
+	}
+
+	#[test]
+	fn encoding_tx_signatures_shared_input() {
+		let secp_ctx = Secp256k1::new();
+		let (privkey_1, _) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
+		let sig_1 = get_sig_on!(privkey_1, secp_ctx, String::from("01010101010101010101010101010101"));
+		let tx_signatures = msgs::TxSignatures {
+			channel_id: [2; 32],
+			txid: Txid::from_hex("0303030303030303030303030303030303030303030303030303030303030303").unwrap(),
+			witnesses: Vec::new(),
+			shared_input_signature: Some(sig_1),
+		};
+		let encoded_value = tx_signatures.encode();
+		let target_value = hex::decode("0202020202020202020202020202020202020202020202020202020202020202030303030303030303030303030303030303030303030303030303030303030300000040d977cb9b53d93a6ff64bb5f1e158b4094b66e798fb12911168a3ccdf80a83096340a6a95da0ae8d9f776528eecdbb747eb6b545495a4319ed5378e35b21e073a").unwrap();
+		assert_eq!(encoded_value, target_value);
+		assert!(msgs::TxSignatures::read(&mut Cursor::new(&target_value)).unwrap() == tx_signatures);
+	}
+
+	#[test]
+	fn encoding_splice_init() {
+		let secp_ctx = Secp256k1::new();
+		let (_, pubkey_1) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
+		let splice_init = msgs::SpliceInit {
+			channel_id: [2; 32],
+			funding_contribution_satoshis: -100_000,
+			funding_feerate_per_kw: 253,
+			locktime: 600_000,
+			funding_pubkey: pubkey_1,
+		};
+		let encoded_value = splice_init.encode();
+		let target_value = hex::decode("0202020202020202020202020202020202020202020202020202020202020202fffffffffffe7960000000fd000927c0031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f").unwrap();
+		assert_eq!(encoded_value, target_value);
+		assert!(msgs::SpliceInit::read(&mut Cursor::new(&target_value)).unwrap() == splice_init);
+	}
+
+	#[test]
+	fn encoding_splice_ack() {
+		let secp_ctx = Secp256k1::new();
+		let (_, pubkey_1) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
+		let splice_ack = msgs::SpliceAck {
+			channel_id: [2; 32],
+			funding_contribution_satoshis: 0,
+			funding_pubkey: pubkey_1,
+		};
+		let encoded_value = splice_ack.encode();
+		let target_value = hex::decode("02020202020202020202020202020202020202020202020202020202020202020000000000000000031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f").unwrap();
+		assert_eq!(encoded_value, target_value);
+		assert!(msgs::SpliceAck::read(&mut Cursor::new(&target_value)).unwrap() == splice_ack);
+	}
+
+	#[test]
+	fn encoding_splice_locked() {
+		let splice_locked = msgs::SpliceLocked {
+			channel_id: [2; 32],
+			splice_txid: Txid::from_hex("0303030303030303030303030303030303030303030303030303030303030303").unwrap(),
+		};
+		let encoded_value = splice_locked.encode();
+		let target_value = hex::decode("02020202020202020202020202020202020202020202020202020202020202020303030303030303030303030303030303030303030303030303030303030303").unwrap();
+		assert_eq!(encoded_value, target_value);
+	}
+
+	#[test]
+	fn encoding_tx_abort() {
+		let tx_abort = msgs::TxAbort {
+			channel_id: [2; 32],
+			data: vec![1, 2, 3],
+		};
+		let encoded_value = tx_abort.encode();
+		let target_value = hex::decode("02020202020202020202020202020202020202020202020202020202020202020003010203").unwrap();
+		assert_eq!(encoded_value, target_value);
+		assert!(msgs::TxAbort::read(&mut Cursor::new(&target_value)).unwrap() == tx_abort);
+	}
+
 	#[test]
 	fn encoding_funding_locked() {
 		let secp_ctx = Secp256k1::new();
@@ -1892,7 +2675,7 @@ mod tests {
 		assert_eq!(encoded_value, target_value);
 	}
 
-	fn do_encoding_commitment_signed(htlcs: bool) {
+	fn do_encoding_commitment_signed(htlcs: bool, spliced: bool) {
 		let secp_ctx = Secp256k1::new();
 		let (privkey_1, _) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
 		let (privkey_2, _) = get_keys_from!("0202020202020202020202020202020202020202020202020202020202020202", secp_ctx);
@@ -1906,6 +2689,10 @@ mod tests {
 			channel_id: [2; 32],
 			signature: sig_1,
 			htlc_signatures: if htlcs { vec![sig_2, sig_3, sig_4] } else { Vec::new() },
+			spliced_signatures: if spliced { OptionalField::Present(msgs::SplicedCommitmentSignatures {
+				signature: sig_4,
+				htlc_signatures: if htlcs { vec![sig_3, sig_2, sig_1] } else { Vec::new() },
+			}) } else { OptionalField::Absent },
 		};
 		let encoded_value = commitment_signed.encode();
 		let mut target_value = hex::decode("0202020202020202020202020202020202020202020202020202020202020202d977cb9b53d93a6ff64bb5f1e158b4094b66e798fb12911168a3ccdf80a83096340a6a95da0ae8d9f776528eecdbb747eb6b545495a4319ed5378e35b21e073a").unwrap();
@@ -1914,13 +2701,27 @@ mod tests {
 		} else {
 			target_value.append(&mut hex::decode("0000").unwrap());
 		}
+		if spliced {
+			target_value.append(&mut sig_4.serialize_compact().to_vec());
+			if htlcs {
+				target_value.append(&mut hex::decode("0003").unwrap());
+				for sig in [sig_3, sig_2, sig_1].iter() {
+					target_value.append(&mut sig.serialize_compact().to_vec());
+				}
+			} else {
+				target_value.append(&mut hex::decode("0000").unwrap());
+			}
+		}
 		assert_eq!(encoded_value, target_value);
+		assert!(msgs::CommitmentSigned::read(&mut Cursor::new(&target_value)).unwrap() == commitment_signed);
 	}
 
 	#[test]
 	fn encoding_commitment_signed() {
-		do_encoding_commitment_signed(true);
-		do_encoding_commitment_signed(false);
+		do_encoding_commitment_signed(true, false);
+		do_encoding_commitment_signed(false, false);
+		do_encoding_commitment_signed(true, true);
+		do_encoding_commitment_signed(false, true);
 	}
 
 	#[test]
@@ -2032,6 +2833,7 @@ mod tests {
 		let mut msg = msgs::OnionHopData {
 			format: OnionHopDataFormat::FinalNode {
 				payment_data: None,
+				keysend_preimage: None,
 			},
 			amt_to_forward: 0x0badf00d01020304,
 			outgoing_cltv_value: 0xffffffff,
@@ -2040,7 +2842,7 @@ mod tests {
 		let target_value = hex::decode("1002080badf00d010203040404ffffffff").unwrap();
 		assert_eq!(encoded_value, target_value);
 		msg = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
-		if let OnionHopDataFormat::FinalNode { payment_data: None } = msg.format { } else { panic!(); }
+		if let OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: None } = msg.format { } else { panic!(); }
 		assert_eq!(msg.amt_to_forward, 0x0badf00d01020304);
 		assert_eq!(msg.outgoing_cltv_value, 0xffffffff);
 	}
@@ -2054,6 +2856,7 @@ mod tests {
 					payment_secret: expected_payment_secret,
 					total_msat: 0x1badca1f
 				}),
+				keysend_preimage: None,
 			},
 			amt_to_forward: 0x0badf00d01020304,
 			outgoing_cltv_value: 0xffffffff,
@@ -2066,11 +2869,121 @@ mod tests {
 			payment_data: Some(FinalOnionHopData {
 				payment_secret,
 				total_msat: 0x1badca1f
-			})
+			}),
+			keysend_preimage: None,
 		} = msg.format {
 			assert_eq!(payment_secret, expected_payment_secret);
 		} else { panic!(); }
 		assert_eq!(msg.amt_to_forward, 0x0badf00d01020304);
 		assert_eq!(msg.outgoing_cltv_value, 0xffffffff);
 	}
+
+	#[test]
+	fn encoding_final_onion_hop_data_with_keysend_preimage() {
+		let expected_preimage = PaymentPreimage([0x42u8; 32]);
+		let mut msg = msgs::OnionHopData {
+			format: OnionHopDataFormat::FinalNode {
+				payment_data: None,
+				keysend_preimage: Some(expected_preimage),
+			},
+			amt_to_forward: 0x0badf00d01020304,
+			outgoing_cltv_value: 0xffffffff,
+		};
+		let encoded_value = msg.encode();
+		let target_value = hex::decode("3a02080badf00d010203040404ffffffffff0000000146c6616c204242424242424242424242424242424242424242424242424242424242424242").unwrap();
+		assert_eq!(encoded_value, target_value);
+		msg = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
+		if let OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: Some(preimage) } = msg.format {
+			assert_eq!(preimage, expected_preimage);
+		} else { panic!(); }
+		assert_eq!(msg.amt_to_forward, 0x0badf00d01020304);
+		assert_eq!(msg.outgoing_cltv_value, 0xffffffff);
+	}
+
+	#[test]
+	fn encoding_query_channel_range() {
+		let query_channel_range = msgs::QueryChannelRange {
+			chain_hash: BlockHash::from_hex("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f").unwrap(),
+			first_blocknum: 100000,
+			number_of_blocks: 1500,
+		};
+		let encoded_value = query_channel_range.encode();
+		let target_value = hex::decode("6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000000186a0000005dc").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let read_value: msgs::QueryChannelRange = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
+		assert_eq!(read_value, query_channel_range);
+	}
+
+	#[test]
+	fn encoding_reply_channel_range() {
+		let reply_channel_range = msgs::ReplyChannelRange {
+			chain_hash: BlockHash::from_hex("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f").unwrap(),
+			first_blocknum: 756230,
+			number_of_blocks: 1500,
+			sync_complete: true,
+			short_channel_ids: vec![0x000000000000008e, 0x0000000000003c69, 0x000000000045a6c4],
+		};
+		let encoded_value = reply_channel_range.encode();
+		let target_value = hex::decode("6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000000b8a06000005dc01001900000000000000008e0000000000003c69000000000045a6c4").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let read_value: msgs::ReplyChannelRange = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
+		assert_eq!(read_value, reply_channel_range);
+
+		// zlib-compressed short_channel_id lists are rejected, as are malformed lists.
+		let zlib_value = hex::decode("6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000000b8a06000005dc01001801789c").unwrap();
+		if let Err(msgs::DecodeError::UnsupportedCompression) = <msgs::ReplyChannelRange as Readable>::read(&mut Cursor::new(&zlib_value[..])) {} else { panic!(); }
+		let malformed_value = hex::decode("6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000000b8a06000005dc0100040000000000").unwrap();
+		if let Err(msgs::DecodeError::InvalidValue) = <msgs::ReplyChannelRange as Readable>::read(&mut Cursor::new(&malformed_value[..])) {} else { panic!(); }
+
+		// Lists too long for their encoding's length to fit in a u16 can't be written.
+		let mut too_long = reply_channel_range.clone();
+		too_long.short_channel_ids = vec![0; msgs::MAX_ENCODED_SHORT_CHANNEL_IDS + 1];
+		assert!(too_long.write(&mut VecWriter(Vec::new())).is_err());
+		too_long.short_channel_ids.pop();
+		let read_value: msgs::ReplyChannelRange = Readable::read(&mut Cursor::new(&too_long.encode()[..])).unwrap();
+		assert_eq!(read_value, too_long);
+	}
+
+	#[test]
+	fn encoding_query_short_channel_ids() {
+		let query_short_channel_ids = msgs::QueryShortChannelIds {
+			chain_hash: BlockHash::from_hex("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f").unwrap(),
+			short_channel_ids: vec![0x0000000000008e, 0x0000000000003c69, 0x000000000045a6c4],
+		};
+		let encoded_value = query_short_channel_ids.encode();
+		let target_value = hex::decode("6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000001900000000000000008e0000000000003c69000000000045a6c4").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let read_value: msgs::QueryShortChannelIds = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
+		assert_eq!(read_value, query_short_channel_ids);
+	}
+
+	#[test]
+	fn encoding_reply_short_channel_ids_end() {
+		let reply_short_channel_ids_end = msgs::ReplyShortChannelIdsEnd {
+			chain_hash: BlockHash::from_hex("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f").unwrap(),
+			full_information: true,
+		};
+		let encoded_value = reply_short_channel_ids_end.encode();
+		let target_value = hex::decode("6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d619000000000001").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let read_value: msgs::ReplyShortChannelIdsEnd = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
+		assert_eq!(read_value, reply_short_channel_ids_end);
+	}
+
+	#[test]
+	fn encoding_gossip_timestamp_filter() {
+		let gossip_timestamp_filter = msgs::GossipTimestampFilter {
+			chain_hash: BlockHash::from_hex("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f").unwrap(),
+			first_timestamp: 1590000000,
+			timestamp_range: 0xffff_ffff,
+		};
+		let encoded_value = gossip_timestamp_filter.encode();
+		let target_value = hex::decode("6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d61900000000005ec57980ffffffff").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let read_value: msgs::GossipTimestampFilter = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
+		assert_eq!(read_value, gossip_timestamp_filter);
+		assert!(gossip_timestamp_filter.includes(1590000000));
+		assert!(gossip_timestamp_filter.includes(0xffff_ffff));
+		assert!(!gossip_timestamp_filter.includes(1589999999));
+	}
 }
@@ -17,10 +17,10 @@ use ln::channelmonitor::{ANTI_REORG_DELAY, CLTV_SHARED_CLAIM_BUFFER, InputMateri
 use ln::channelmanager::PaymentPreimage;
 use ln::chan_utils;
 use ln::chan_utils::{TxCreationKeys, LocalCommitmentTransaction};
-use chain::chaininterface::{FeeEstimator, BroadcasterInterface, ConfirmationTarget, MIN_RELAY_FEE_SAT_PER_1000_WEIGHT};
+use chain::chaininterface::{FeeEstimator, BroadcasterInterface, ConfirmationTarget, UtxoPool, MIN_RELAY_FEE_SAT_PER_1000_WEIGHT};
 use chain::keysinterface::ChannelKeys;
 use util::logger::Logger;
-use util::ser::{Readable, Writer, Writeable};
+use util::ser::{Readable, ReadableArgs, Writer, Writeable};
 use util::byte_utils;
 
 use std::collections::{HashMap, hash_map};
@@ -29,6 +29,9 @@ use std::ops::Deref;
 
 const MAX_ALLOC_SIZE: usize = 64*1024;
 
+// number_of_witness_elements + sig_length + sig + witness_script_length + witness_script
+const ANCHOR_INPUT_WITNESS_WEIGHT: u64 = 1 + 1 + 73 + 1 + 40;
+
 /// Upon discovering of some classes of onchain tx by ChannelMonitor, we may have to take actions on it
 /// once they mature to enough confirmations (ANTI_REORG_DELAY)
 #[derive(Clone, PartialEq)]
@@ -241,6 +244,7 @@ pub struct OnchainTxHandler<ChanSigner: ChannelKeys> {
 	prev_local_commitment: Option<LocalCommitmentTransaction>,
 	prev_local_htlc_sigs: Option<Vec<Option<(usize, Signature)>>>,
 	on_local_tx_csv: u16,
+	opt_anchors: bool,
 
 	key_storage: ChanSigner,
 
@@ -273,6 +277,10 @@ pub struct OnchainTxHandler<ChanSigner: ChannelKeys> {
 
 	onchain_events_waiting_threshold_conf: HashMap<u32, Vec<OnchainEvent>>,
 
+	// The height at which a transaction spending the funding output, ie a commitment transaction,
+	// was seen. Once set we stop bumping the fee of our own commitment transaction via its anchor.
+	funding_spend_height: Option<u32>,
+
 	secp_ctx: Secp256k1<secp256k1::All>,
 }
 
@@ -285,6 +293,7 @@ impl<ChanSigner: ChannelKeys + Writeable> OnchainTxHandler<ChanSigner> {
 		self.prev_local_htlc_sigs.write(writer)?;
 
 		self.on_local_tx_csv.write(writer)?;
+		self.opt_anchors.write(writer)?;
 
 		self.key_storage.write(writer)?;
 
@@ -319,20 +328,23 @@ impl<ChanSigner: ChannelKeys + Writeable> OnchainTxHandler<ChanSigner> {
 				}
 			}
 		}
+		self.funding_spend_height.write(writer)?;
 		Ok(())
 	}
 }
 
-impl<ChanSigner: ChannelKeys + Readable> Readable for OnchainTxHandler<ChanSigner> {
-	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+/// The argument is the serialization version of the ChannelMonitor containing the handler.
+impl<ChanSigner: ChannelKeys + Readable> ReadableArgs<u8> for OnchainTxHandler<ChanSigner> {
+	fn read<R: ::std::io::Read>(reader: &mut R, ver: u8) -> Result<Self, DecodeError> {
 		let destination_script = Readable::read(reader)?;
 
-		let local_commitment = Readable::read(reader)?;
+		let local_commitment = ReadableArgs::read(reader, ver >= 2)?;
 		let local_htlc_sigs = Readable::read(reader)?;
-		let prev_local_commitment = Readable::read(reader)?;
+		let prev_local_commitment = ReadableArgs::read(reader, ver >= 2)?;
 		let prev_local_htlc_sigs = Readable::read(reader)?;
 
 		let on_local_tx_csv = Readable::read(reader)?;
+		let opt_anchors = if ver >= 2 { Readable::read(reader)? } else { false };
 
 		let key_storage = Readable::read(reader)?;
 
@@ -378,6 +390,7 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for OnchainTxHandler<ChanSigne
 			}
 			onchain_events_waiting_threshold_conf.insert(height_target, events);
 		}
+		let funding_spend_height = if ver >= 2 { Readable::read(reader)? } else { None };
 
 		Ok(OnchainTxHandler {
 			destination_script,
@@ -386,17 +399,19 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for OnchainTxHandler<ChanSigne
 			prev_local_commitment,
 			prev_local_htlc_sigs,
 			on_local_tx_csv,
+			opt_anchors,
 			key_storage,
 			claimable_outpoints,
 			pending_claim_requests,
 			onchain_events_waiting_threshold_conf,
+			funding_spend_height,
 			secp_ctx: Secp256k1::new(),
 		})
 	}
 }
 
 impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
-	pub(super) fn new(destination_script: Script, keys: ChanSigner, on_local_tx_csv: u16) -> Self {
+	pub(super) fn new(destination_script: Script, keys: ChanSigner, on_local_tx_csv: u16, opt_anchors: bool) -> Self {
 
 		let key_storage = keys;
 
@@ -407,35 +422,39 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 			prev_local_commitment: None,
 			prev_local_htlc_sigs: None,
 			on_local_tx_csv,
+			opt_anchors,
 			key_storage,
 			pending_claim_requests: HashMap::new(),
 			claimable_outpoints: HashMap::new(),
 			onchain_events_waiting_threshold_conf: HashMap::new(),
+			funding_spend_height: None,
 
 			secp_ctx: Secp256k1::new(),
 		}
 	}
 
-	pub(super) fn get_witnesses_weight(inputs: &[InputDescriptors]) -> usize {
+	pub(super) fn get_witnesses_weight(inputs: &[InputDescriptors], opt_anchors: bool) -> usize {
 		let mut tx_weight = 2; // count segwit flags
+		// HTLC witness scripts carry an additional `1 OP_CSV OP_DROP` with anchor outputs
+		let anchors_script_len = if opt_anchors { 3 } else { 0 };
 		for inp in inputs {
 			// We use expected weight (and not actual) as signatures and time lock delays may vary
 			tx_weight +=  match inp {
 				// number_of_witness_elements + sig_length + revocation_sig + pubkey_length + revocationpubkey + witness_script_length + witness_script
 				&InputDescriptors::RevokedOfferedHTLC => {
-					1 + 1 + 73 + 1 + 33 + 1 + 133
+					1 + 1 + 73 + 1 + 33 + 1 + 133 + anchors_script_len
 				},
 				// number_of_witness_elements + sig_length + revocation_sig + pubkey_length + revocationpubkey + witness_script_length + witness_script
 				&InputDescriptors::RevokedReceivedHTLC => {
-					1 + 1 + 73 + 1 + 33 + 1 + 139
+					1 + 1 + 73 + 1 + 33 + 1 + 139 + anchors_script_len
 				},
 				// number_of_witness_elements + sig_length + remotehtlc_sig  + preimage_length + preimage + witness_script_length + witness_script
 				&InputDescriptors::OfferedHTLC => {
-					1 + 1 + 73 + 1 + 32 + 1 + 133
+					1 + 1 + 73 + 1 + 32 + 1 + 133 + anchors_script_len
 				},
 				// number_of_witness_elements + sig_length + revocation_sig + pubkey_length + revocationpubkey + witness_script_length + witness_script
 				&InputDescriptors::ReceivedHTLC => {
-					1 + 1 + 73 + 1 + 1 + 1 + 139
+					1 + 1 + 73 + 1 + 1 + 1 + 139 + anchors_script_len
 				},
 				// number_of_witness_elements + sig_length + revocation_sig + true_length + op_true + witness_script_length + witness_script
 				&InputDescriptors::RevokedOutput => {
@@ -462,18 +481,28 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 
 	/// Lightning security model (i.e being able to redeem/timeout HTLC or penalize coutnerparty onchain) lays on the assumption of claim transactions getting confirmed before timelock expiration
 	/// (CSV or CLTV following cases). In case of high-fee spikes, claim tx may stuck in the mempool, so you need to bump its feerate quickly using Replace-By-Fee or Child-Pay-For-Parent.
-	fn generate_claim_tx<F: Deref, L: Deref>(&mut self, height: u32, cached_claim_datas: &ClaimTxBumpMaterial, fee_estimator: F, logger: L) -> Option<(Option<u32>, u32, Transaction)>
+	///
+	/// For our own commitment transactions in channels with anchor outputs, a child transaction
+	/// spending our anchor output is returned alongside the claim tx, and must be broadcast after it.
+	fn generate_claim_tx<F: Deref, U: Deref, L: Deref>(&mut self, height: u32, cached_claim_datas: &ClaimTxBumpMaterial, fee_estimator: F, utxo_pool: U, logger: L) -> Option<(Option<u32>, u32, Transaction, Option<Transaction>)>
 		where F::Target: FeeEstimator,
+		      U::Target: UtxoPool,
 					L::Target: Logger,
 	{
 		if cached_claim_datas.per_input_material.len() == 0 { return None } // But don't prune pending claiming request yet, we may have to resurrect HTLCs
 		let mut inputs = Vec::new();
-		for outp in cached_claim_datas.per_input_material.keys() {
+		for (outp, per_outp_material) in cached_claim_datas.per_input_material.iter() {
 			log_trace!(logger, "Outpoint {}:{}", outp.txid, outp.vout);
+			// With anchor outputs, remote HTLC outputs may only be spent one block after the
+			// commitment transaction confirmed.
+			let sequence = match per_outp_material {
+				&InputMaterial::RemoteHTLC { .. } if self.opt_anchors => 1,
+				_ => 0xfffffffd,
+			};
 			inputs.push(TxIn {
 				previous_output: *outp,
 				script_sig: Script::new(),
-				sequence: 0xfffffffd,
+				sequence,
 				witness: Vec::new(),
 			});
 		}
@@ -535,11 +564,11 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		for per_outp_material in cached_claim_datas.per_input_material.values() {
 			match per_outp_material {
 				&InputMaterial::Revoked { ref input_descriptor, ref amount, .. } => {
-					inputs_witnesses_weight += Self::get_witnesses_weight(&[*input_descriptor]);
+					inputs_witnesses_weight += Self::get_witnesses_weight(&[*input_descriptor], self.opt_anchors);
 					amt += *amount;
 				},
 				&InputMaterial::RemoteHTLC { ref preimage, ref htlc, .. } => {
-					inputs_witnesses_weight += Self::get_witnesses_weight(if preimage.is_some() { &[InputDescriptors::OfferedHTLC] } else { &[InputDescriptors::ReceivedHTLC] });
+					inputs_witnesses_weight += Self::get_witnesses_weight(if preimage.is_some() { &[InputDescriptors::OfferedHTLC] } else { &[InputDescriptors::ReceivedHTLC] }, self.opt_anchors);
 					amt += htlc.amount_msat / 1000;
 				},
 				&InputMaterial::LocalHTLC { .. } => {
@@ -577,12 +606,12 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 						if let Ok(chan_keys) = TxCreationKeys::new(&self.secp_ctx, &per_commitment_point, remote_delayed_payment_base_key, remote_htlc_base_key, &self.key_storage.pubkeys().revocation_basepoint, &self.key_storage.pubkeys().htlc_basepoint) {
 
 							let witness_script = if let Some(ref htlc) = *htlc {
-								chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &chan_keys.a_htlc_key, &chan_keys.b_htlc_key, &chan_keys.revocation_key)
+								chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &chan_keys.a_htlc_key, &chan_keys.b_htlc_key, &chan_keys.revocation_key, self.opt_anchors)
 							} else {
 								chan_utils::get_revokeable_redeemscript(&chan_keys.revocation_key, *on_remote_tx_csv, &chan_keys.a_delayed_payment_key)
 							};
 
-							if let Ok(sig) = self.key_storage.sign_justice_transaction(&bumped_tx, i, *amount, &per_commitment_key, htlc, *on_remote_tx_csv, &self.secp_ctx) {
+							if let Ok(sig) = self.key_storage.sign_justice_transaction(&bumped_tx, i, *amount, &per_commitment_key, htlc, *on_remote_tx_csv, self.opt_anchors, &self.secp_ctx) {
 								bumped_tx.input[i].witness.push(sig.serialize_der().to_vec());
 								bumped_tx.input[i].witness[0].push(SigHashType::All as u8);
 								if htlc.is_some() {
@@ -599,10 +628,10 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 					},
 					&InputMaterial::RemoteHTLC { ref per_commitment_point, ref remote_delayed_payment_base_key, ref remote_htlc_base_key, ref preimage, ref htlc } => {
 						if let Ok(chan_keys) = TxCreationKeys::new(&self.secp_ctx, &per_commitment_point, remote_delayed_payment_base_key, remote_htlc_base_key, &self.key_storage.pubkeys().revocation_basepoint, &self.key_storage.pubkeys().htlc_basepoint) {
-							let witness_script = chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &chan_keys.a_htlc_key, &chan_keys.b_htlc_key, &chan_keys.revocation_key);
+							let witness_script = chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &chan_keys.a_htlc_key, &chan_keys.b_htlc_key, &chan_keys.revocation_key, self.opt_anchors);
 
 							if !preimage.is_some() { bumped_tx.lock_time = htlc.cltv_expiry }; // Right now we don't aggregate time-locked transaction, if we do we should set lock_time before to avoid breaking hash computation
-							if let Ok(sig) = self.key_storage.sign_remote_htlc_transaction(&bumped_tx, i, &htlc.amount_msat / 1000, &per_commitment_point, htlc, &self.secp_ctx) {
+							if let Ok(sig) = self.key_storage.sign_remote_htlc_transaction(&bumped_tx, i, &htlc.amount_msat / 1000, &per_commitment_point, htlc, self.opt_anchors, &self.secp_ctx) {
 								bumped_tx.input[i].witness.push(sig.serialize_der().to_vec());
 								bumped_tx.input[i].witness[0].push(SigHashType::All as u8);
 								if let &Some(preimage) = preimage {
@@ -621,7 +650,7 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 			}
 			log_trace!(logger, "...with timer {}", new_timer.unwrap());
 			assert!(predicted_weight >= bumped_tx.get_weight() as u64);
-			return Some((new_timer, new_feerate as u32, bumped_tx))
+			return Some((new_timer, new_feerate as u32, bumped_tx, None))
 		} else {
 			for (_, (outp, per_outp_material)) in cached_claim_datas.per_input_material.iter().enumerate() {
 				match per_outp_material {
@@ -631,15 +660,29 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 							let feerate = (amount - htlc_tx.output[0].value) * 1000 / htlc_tx.get_weight() as u64;
 							// Timer set to $NEVER given we can't bump tx without anchor outputs
 							log_trace!(logger, "Going to broadcast Local HTLC-{} claiming HTLC output {} from {}...", if preimage.is_some() { "Success" } else { "Timeout" }, outp.vout, outp.txid);
-							return Some((None, feerate as u32, htlc_tx));
+							return Some((None, feerate as u32, htlc_tx, None));
 						}
 						return None;
 					},
 					&InputMaterial::Funding { ref funding_redeemscript } => {
 						let signed_tx = self.get_fully_signed_local_tx(funding_redeemscript).unwrap();
+						if self.opt_anchors && self.funding_spend_height.is_none() {
+							// The commitment transaction fee is fixed, but we can bump the feerate of
+							// the package by attaching a child spending our anchor output. Each bump
+							// replaces the previous child at a 25% higher feerate (or the current
+							// high priority feerate if it's higher).
+							let high_prio_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::HighPriority);
+							let package_feerate = if cached_claim_datas.feerate_previous != 0 {
+								cmp::max(high_prio_feerate, cached_claim_datas.feerate_previous * 125 / 100)
+							} else { high_prio_feerate };
+							let child_tx = self.build_anchor_child_tx(&signed_tx, package_feerate, &*utxo_pool, &*logger);
+							log_trace!(logger, "Going to broadcast Local Transaction {} claiming funding output {} from {} with a target package feerate {}...", signed_tx.txid(), outp.vout, outp.txid, package_feerate);
+							log_trace!(logger, "...with timer {}", new_timer.unwrap());
+							return Some((new_timer, package_feerate, signed_tx, child_tx));
+						}
 						// Timer set to $NEVER given we can't bump tx without anchor outputs
 						log_trace!(logger, "Going to broadcast Local Transaction {} claiming funding output {} from {}...", signed_tx.txid(), outp.vout, outp.txid);
-						return Some((None, self.local_commitment.as_ref().unwrap().feerate_per_kw, signed_tx));
+						return Some((None, self.local_commitment.as_ref().unwrap().feerate_per_kw, signed_tx, None));
 					}
 					_ => unreachable!()
 				}
@@ -648,9 +691,99 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		None
 	}
 
-	pub(super) fn block_connected<B: Deref, F: Deref, L: Deref>(&mut self, txn_matched: &[&Transaction], claimable_outpoints: Vec<ClaimRequest>, height: u32, broadcaster: B, fee_estimator: F, logger: L)
+	/// Builds a transaction spending the anchor output of our commitment transaction together with
+	/// inputs from the user's wallet, such that the two transactions together pay package_feerate.
+	///
+	/// Returns None if the commitment transaction has no anchor output of ours or if the wallet
+	/// failed to provide and sign enough inputs, in which case the commitment transaction has to
+	/// confirm with its own fee.
+	fn build_anchor_child_tx<U: Deref, L: Deref>(&self, commitment_tx: &Transaction, package_feerate: u32, utxo_pool: U, logger: L) -> Option<Transaction>
+		where U::Target: UtxoPool,
+		      L::Target: Logger,
+	{
+		let anchor_redeemscript = chan_utils::get_anchor_redeemscript(&self.key_storage.pubkeys().funding_pubkey);
+		let anchor_script_pubkey = anchor_redeemscript.to_v0_p2wsh();
+		let anchor_vout = match commitment_tx.output.iter().position(|outp| outp.script_pubkey == anchor_script_pubkey) {
+			Some(vout) => vout,
+			None => {
+				log_trace!(logger, "No anchor output of ours in local commitment transaction {}, can't bump its fee", commitment_tx.txid());
+				return None;
+			}
+		};
+		let commitment_weight = commitment_tx.get_weight() as u64;
+		let commitment_fee = self.local_commitment.as_ref().unwrap().feerate_per_kw as u64 * commitment_weight / 1000;
+
+		let mut child_tx = Transaction {
+			version: 2,
+			lock_time: 0,
+			input: vec![TxIn {
+				previous_output: BitcoinOutPoint { txid: commitment_tx.txid(), vout: anchor_vout as u32 },
+				script_sig: Script::new(),
+				sequence: 0xfffffffd,
+				witness: Vec::new(),
+			}],
+			output: vec![TxOut {
+				script_pubkey: utxo_pool.get_change_script(),
+				value: 0,
+			}],
+		};
+
+		// The weight of the wallet inputs is only known once they're selected, so we may have to
+		// select again with a higher target.
+		let mut selected_value = 0;
+		let mut satisfaction_weight = 0;
+		let mut selection_attempts = 0;
+		let child_fee = loop {
+			// Segwit marker and flag + anchor witness + wallet inputs witnesses
+			let child_weight = child_tx.get_weight() as u64 + 2 + ANCHOR_INPUT_WITNESS_WEIGHT + satisfaction_weight;
+			let package_fee = package_feerate as u64 * (commitment_weight + child_weight) / 1000;
+			let child_fee = cmp::max(package_fee.saturating_sub(commitment_fee), MIN_RELAY_FEE_SAT_PER_1000_WEIGHT * child_weight / 1000);
+			if selected_value >= child_fee { break child_fee; }
+			if selection_attempts == 3 {
+				log_error!(logger, "Wallet failed to provide {} sat to bump the fee of local commitment transaction {}", child_fee, commitment_tx.txid());
+				return None;
+			}
+			selection_attempts += 1;
+
+			let utxos = utxo_pool.select_utxos(child_fee);
+			if utxos.is_empty() {
+				log_error!(logger, "Wallet failed to provide {} sat to bump the fee of local commitment transaction {}", child_fee, commitment_tx.txid());
+				return None;
+			}
+			child_tx.input.truncate(1);
+			selected_value = 0;
+			satisfaction_weight = 0;
+			for utxo in utxos {
+				child_tx.input.push(TxIn {
+					previous_output: utxo.outpoint,
+					script_sig: Script::new(),
+					sequence: 0xfffffffd,
+					witness: Vec::new(),
+				});
+				selected_value += utxo.output.value;
+				satisfaction_weight += utxo.satisfaction_weight;
+			}
+		};
+		// Any value left over, including the anchor output value itself, goes back to the wallet
+		child_tx.output[0].value = selected_value + chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI - child_fee;
+
+		if let Ok(sig) = self.key_storage.sign_local_anchor_input(&child_tx, 0, &self.secp_ctx) {
+			child_tx.input[0].witness.push(sig.serialize_der().to_vec());
+			child_tx.input[0].witness[0].push(SigHashType::All as u8);
+			child_tx.input[0].witness.push(anchor_redeemscript.into_bytes());
+		} else { return None; }
+		if let Err(_) = utxo_pool.sign_tx(&mut child_tx) {
+			log_error!(logger, "Wallet failed to sign the fee-bumping child of local commitment transaction {}", commitment_tx.txid());
+			return None;
+		}
+		log_trace!(logger, "Built anchor child transaction {} paying {} sat to bump local commitment transaction {}", child_tx.txid(), child_fee, commitment_tx.txid());
+		Some(child_tx)
+	}
+
+	pub(super) fn block_connected<B: Deref, F: Deref, U: Deref, L: Deref>(&mut self, txn_matched: &[&Transaction], claimable_outpoints: Vec<ClaimRequest>, height: u32, broadcaster: B, fee_estimator: F, utxo_pool: U, logger: L)
 		where B::Target: BroadcasterInterface,
 		      F::Target: FeeEstimator,
+		      U::Target: UtxoPool,
 					L::Target: Logger,
 	{
 		log_trace!(logger, "Block at height {} connected with {} claim requests", height, claimable_outpoints.len());
@@ -682,7 +815,7 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		// height timer expiration (i.e in how many blocks we're going to take action).
 		for (soonest_timelock, claim) in new_claims.drain(..) {
 			let mut claim_material = ClaimTxBumpMaterial { height_timer: None, feerate_previous: 0, soonest_timelock, per_input_material: claim };
-			if let Some((new_timer, new_feerate, tx)) = self.generate_claim_tx(height, &claim_material, &*fee_estimator, &*logger) {
+			if let Some((new_timer, new_feerate, tx, child_tx)) = self.generate_claim_tx(height, &claim_material, &*fee_estimator, &*utxo_pool, &*logger) {
 				claim_material.height_timer = new_timer;
 				claim_material.feerate_previous = new_feerate;
 				let txid = tx.txid();
@@ -693,6 +826,10 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 				self.pending_claim_requests.insert(txid, claim_material);
 				log_trace!(logger, "Broadcast onchain {}", log_tx!(tx));
 				broadcaster.broadcast_transaction(&tx);
+				if let Some(child_tx) = child_tx {
+					log_trace!(logger, "Broadcast onchain {}", log_tx!(child_tx));
+					broadcaster.broadcast_transaction(&child_tx);
+				}
 			}
 		}
 
@@ -739,6 +876,11 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 						// ANTI_REORG_DELAY and clean the RBF tracking map.
 						if set_equality {
 							clean_claim_request_after_safety_delay!();
+							for input_material in claim_material.per_input_material.values() {
+								if let &InputMaterial::Funding { .. } = input_material {
+									self.funding_spend_height = Some(height);
+								}
+							}
 						} else { // If false, generate new claim request with update outpoint set
 							let mut at_least_one_drop = false;
 							for input in tx.input.iter() {
@@ -801,6 +943,11 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		for (first_claim_txid, ref claim_data) in self.pending_claim_requests.iter() {
 			if let Some(h) = claim_data.height_timer {
 				if h == height {
+					// Once a commitment transaction confirmed there's nothing left to bump via our anchor
+					let is_funding_claim = claim_data.per_input_material.values().any(|input_material| {
+						if let &InputMaterial::Funding { .. } = input_material { true } else { false }
+					});
+					if is_funding_claim && self.funding_spend_height.is_some() { continue; }
 					bump_candidates.insert(*first_claim_txid, (*claim_data).clone());
 				}
 			}
@@ -809,9 +956,13 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		// Build, bump and rebroadcast tx accordingly
 		log_trace!(logger, "Bumping {} candidates", bump_candidates.len());
 		for (first_claim_txid, claim_material) in bump_candidates.iter() {
-			if let Some((new_timer, new_feerate, bump_tx)) = self.generate_claim_tx(height, &claim_material, &*fee_estimator, &*logger) {
+			if let Some((new_timer, new_feerate, bump_tx, child_tx)) = self.generate_claim_tx(height, &claim_material, &*fee_estimator, &*utxo_pool, &*logger) {
 				log_trace!(logger, "Broadcast onchain {}", log_tx!(bump_tx));
 				broadcaster.broadcast_transaction(&bump_tx);
+				if let Some(child_tx) = child_tx {
+					log_trace!(logger, "Broadcast onchain {}", log_tx!(child_tx));
+					broadcaster.broadcast_transaction(&child_tx);
+				}
 				if let Some(claim_material) = self.pending_claim_requests.get_mut(first_claim_txid) {
 					claim_material.height_timer = new_timer;
 					claim_material.feerate_previous = new_feerate;
@@ -820,11 +971,15 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		}
 	}
 
-	pub(super) fn block_disconnected<B: Deref, F: Deref, L: Deref>(&mut self, height: u32, broadcaster: B, fee_estimator: F, logger: L)
+	pub(super) fn block_disconnected<B: Deref, F: Deref, U: Deref, L: Deref>(&mut self, height: u32, broadcaster: B, fee_estimator: F, utxo_pool: U, logger: L)
 		where B::Target: BroadcasterInterface,
 		      F::Target: FeeEstimator,
+		      U::Target: UtxoPool,
 					L::Target: Logger,
 	{
+		if self.funding_spend_height == Some(height) {
+			self.funding_spend_height = None;
+		}
 		let mut bump_candidates = HashMap::new();
 		if let Some(events) = self.onchain_events_waiting_threshold_conf.remove(&(height + ANTI_REORG_DELAY - 1)) {
 			//- our claim tx on a commitment tx output
@@ -846,10 +1001,13 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 			}
 		}
 		for (_, claim_material) in bump_candidates.iter_mut() {
-			if let Some((new_timer, new_feerate, bump_tx)) = self.generate_claim_tx(height, &claim_material, &*fee_estimator, &*logger) {
+			if let Some((new_timer, new_feerate, bump_tx, child_tx)) = self.generate_claim_tx(height, &claim_material, &*fee_estimator, &*utxo_pool, &*logger) {
 				claim_material.height_timer = new_timer;
 				claim_material.feerate_previous = new_feerate;
 				broadcaster.broadcast_transaction(&bump_tx);
+				if let Some(child_tx) = child_tx {
+					broadcaster.broadcast_transaction(&child_tx);
+				}
 			}
 		}
 		for (ancestor_claim_txid, claim_material) in bump_candidates.drain() {
@@ -882,6 +1040,17 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		Ok(())
 	}
 
+	/// Replaces our local commitment transactions with one spending the funding output of a splice
+	/// transaction. Unlike provide_latest_local_tx this is allowed once we've signed a local
+	/// commitment transaction, as those spend the previous funding output and can no longer confirm.
+	pub(super) fn provide_spliced_local_tx(&mut self, tx: LocalCommitmentTransaction, channel_value_satoshis: u64) {
+		self.key_storage.set_channel_value_satoshis(channel_value_satoshis);
+		self.local_commitment = Some(tx);
+		self.local_htlc_sigs = None;
+		self.prev_local_commitment = None;
+		self.prev_local_htlc_sigs = None;
+	}
+
 	fn sign_latest_local_htlcs(&mut self) {
 		if let Some(ref local_commitment) = self.local_commitment {
 			if let Ok(sigs) = self.key_storage.sign_local_commitment_htlc_transactions(local_commitment, self.on_local_tx_csv, &self.secp_ctx) {
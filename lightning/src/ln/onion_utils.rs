@@ -1,9 +1,10 @@
-use ln::channelmanager::{PaymentHash, PaymentSecret, HTLCSource};
+use ln::channelmanager::{PaymentHash, PaymentPreimage, PaymentSecret, HTLCSource};
 use ln::msgs;
 use routing::router::RouteHop;
 use util::byte_utils;
 use util::chacha20::ChaCha20;
-use util::errors::{self, APIError};
+use util::errors::{self, APIError, OnionFailureCode, PERM, NODE, UPDATE};
+use util::events::PaymentFailureDetails;
 use util::ser::{Readable, Writeable, LengthCalculatingWriter};
 use util::logger::Logger;
 
@@ -108,7 +109,7 @@ pub(super) fn construct_onion_keys<T: secp256k1::Signing>(secp_ctx: &Secp256k1<T
 }
 
 /// returns the hop data, as well as the first-hop value_msat and CLTV value we should send.
-pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, payment_secret_option: &Option<PaymentSecret>, starting_htlc_offset: u32) -> Result<(Vec<msgs::OnionHopData>, u64, u32), APIError> {
+pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, payment_secret_option: &Option<PaymentSecret>, keysend_preimage: &Option<PaymentPreimage>, starting_htlc_offset: u32) -> Result<(Vec<msgs::OnionHopData>, u64, u32), APIError> {
 	let mut cur_value_msat = 0u64;
 	let mut cur_cltv = starting_htlc_offset;
 	let mut last_short_channel_id = 0;
@@ -130,6 +131,7 @@ pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, paymen
 								total_msat,
 							})
 						} else { None },
+						keysend_preimage: *keysend_preimage,
 					}
 				} else {
 					msgs::OnionHopDataFormat::NonFinalNode {
@@ -137,6 +139,9 @@ pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, paymen
 					}
 				}
 			} else {
+				if idx == 0 && keysend_preimage.is_some() {
+					return Err(APIError::RouteError{err: "Keysend payments require the recipient to support variable-length onions"});
+				}
 				msgs::OnionHopDataFormat::Legacy {
 					short_channel_id: last_short_channel_id,
 				}
@@ -316,14 +321,13 @@ pub(super) fn build_first_hop_failure_packet(shared_secret: &[u8], failure_type:
 
 /// Process failure we got back from upstream on a payment we sent (implying htlc_source is an
 /// OutboundRoute).
-/// Returns update, a boolean indicating that the payment itself failed, and the error code.
+/// Returns update, a boolean indicating that the payment itself failed, and the decoded failure.
 #[inline]
-pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &Secp256k1<T>, logger: &L, htlc_source: &HTLCSource, mut packet_decrypted: Vec<u8>) -> (Option<msgs::HTLCFailChannelUpdate>, bool, Option<u16>, Option<Vec<u8>>) where L::Target: Logger {
+pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &Secp256k1<T>, logger: &L, htlc_source: &HTLCSource, mut packet_decrypted: Vec<u8>) -> (Option<msgs::HTLCFailChannelUpdate>, bool, Option<PaymentFailureDetails>) where L::Target: Logger {
 	if let &HTLCSource::OutboundRoute { ref path, ref session_priv, ref first_hop_htlc_msat } = htlc_source {
 		let mut res = None;
 		let mut htlc_msat = *first_hop_htlc_msat;
-		let mut error_code_ret = None;
-		let mut error_packet_ret = None;
+		let mut failure_details = None;
 		let mut next_route_hop_ix = 0;
 		let mut is_from_final_node = false;
 
@@ -352,13 +356,8 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 
 				if fixed_time_eq(&Hmac::from_engine(hmac).into_inner(), &err_packet.hmac) {
 					if let Some(error_code_slice) = err_packet.failuremsg.get(0..2) {
-						const PERM: u16 = 0x4000;
-						const NODE: u16 = 0x2000;
-						const UPDATE: u16 = 0x1000;
-
 						let error_code = byte_utils::slice_to_be16(&error_code_slice);
-						error_code_ret = Some(error_code);
-						error_packet_ret = Some(err_packet.failuremsg[2..].to_vec());
+						let mut embedded_chan_update = None;
 
 						let (debug_field, debug_field_size) = errors::get_onion_debug_field(error_code);
 
@@ -386,6 +385,7 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 								let update_len = byte_utils::slice_to_be16(&update_len_slice) as usize;
 								if let Some(update_slice) = err_packet.failuremsg.get(debug_field_size + 4..debug_field_size + 4 + update_len) {
 									if let Ok(chan_update) = msgs::ChannelUpdate::read(&mut Cursor::new(&update_slice)) {
+										embedded_chan_update = Some(chan_update.clone());
 										// if channel_update should NOT have caused the failure:
 										// MAY treat the channel_update as invalid.
 										let is_chan_update_invalid = match error_code & 0xff {
@@ -423,10 +423,13 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 									is_permanent: true,
 								});
 							}
-						} else if !payment_failed {
+						} else if !payment_failed && !(error_code == 23 && is_from_final_node) {
 							// We can't understand their error messages and they failed to
 							// forward...they probably can't understand our forwards so its
 							// really not worth trying any further.
+							// Note that an mpp_timeout from the destination only indicates it
+							// gave up waiting for the remaining parts of a multi-path payment,
+							// which tells us nothing about the nodes or channels along the path.
 							fail_channel_update = Some(msgs::HTLCFailChannelUpdate::NodeFailure {
 								node_id: route_hop.pubkey,
 								is_permanent: true,
@@ -437,6 +440,13 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 						// are always "sourced" from the node previous to the one which failed
 						// to decode the onion.
 						res = Some((fail_channel_update, !(error_code & PERM == PERM && is_from_final_node)));
+						failure_details = Some(PaymentFailureDetails {
+							failing_node_id: Some(route_hop.pubkey),
+							short_channel_id: path.get(next_route_hop_ix).map(|hop| hop.short_channel_id),
+							failure_code: OnionFailureCode::from_u16(error_code),
+							failure_data: err_packet.failuremsg[2..].to_vec(),
+							channel_update: embedded_chan_update,
+						});
 
 						let (description, title) = errors::get_onion_error_description(error_code);
 						if debug_field_size > 0 && err_packet.failuremsg.len() >= 4 + debug_field_size {
@@ -457,11 +467,11 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 			}
 		}).expect("Route that we sent via spontaneously grew invalid keys in the middle of it?");
 		if let Some((channel_update, payment_retryable)) = res {
-			(channel_update, payment_retryable, error_code_ret, error_packet_ret)
+			(channel_update, payment_retryable, failure_details)
 		} else {
 			// only not set either packet unparseable or hmac does not match with any
 			// payment not retryable only when garbage is from the final node
-			(None, !is_from_final_node, None, None)
+			(None, !is_from_final_node, None)
 		}
 	} else { unreachable!(); }
 }
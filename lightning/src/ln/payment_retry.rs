@@ -0,0 +1,347 @@
+//! A layer on top of ChannelManager which tracks outbound payments and automatically retries the
+//! parts of them which fail over new routes.
+//!
+//! Payments sent via PaymentRetryManager::send_payment are routed via get_multi_path_route. As
+//! ChannelManager generates PaymentFailed events for the payment's parts, the failed value is
+//! re-routed (avoiding the channels which failed) and sent via ChannelManager::retry_payment until
+//! the payment succeeds or the configured number of retries is exhausted. Events from the
+//! ChannelManager should be passed through PaymentRetryManager::process_events, which replaces
+//! the PaymentSent and PaymentFailed events for tracked payments with a single
+//! Event::PaymentResolved.
+//!
+//! Routes are selected using the provided Score, which is updated with the results of each part
+//! of a tracked payment.
+
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::secp256k1::key::PublicKey;
+
+use chain::chaininterface::{BroadcasterInterface, FeeEstimator};
+use chain::keysinterface::{ChannelKeys, KeysInterface};
+use ln::channelmanager::{ChannelManager, PaymentHash, PaymentPreimage, PaymentSecret, PaymentSendFailure};
+use ln::channelmonitor::ManyChannelMonitor;
+use ln::msgs::{DecodeError, HTLCFailChannelUpdate};
+use routing::network_graph::NetworkGraph;
+use routing::router::{get_multi_path_route_with_params, MAX_PATH_COUNT, Route, RouteHint, RouteHop, RouteParameters};
+use routing::scorer::{failed_channels, Score};
+use util::errors::APIError;
+use util::events::Event;
+use util::logger::Logger;
+use util::ser::{Readable, Writeable, Writer};
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::Mutex;
+
+/// A single part of a payment, sent over one path.
+#[derive(Clone, PartialEq)]
+pub struct PaymentAttempt {
+	/// The path the part was sent over. The last hop's fee_msat is the value of the part.
+	pub path: Vec<RouteHop>,
+	/// Whether the part failed, either when we tried to send it or later via
+	/// Event::PaymentFailed. If the payment succeeded, parts which did not fail can be assumed to
+	/// have been claimed by the recipient.
+	pub failed: bool,
+}
+
+impl Writeable for PaymentAttempt {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		self.path.write(writer)?;
+		self.failed.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for PaymentAttempt {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		Ok(PaymentAttempt {
+			path: Readable::read(reader)?,
+			failed: Readable::read(reader)?,
+		})
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PaymentState {
+	/// The payment has not yet succeeded and failed parts will be retried.
+	Retrying,
+	/// The recipient claimed (part of) the payment and we've generated an Event::PaymentResolved.
+	Succeeded,
+	/// The payment has failed and will not be retried. We'll generate an Event::PaymentResolved
+	/// once no parts remain in flight.
+	Abandoned,
+}
+
+struct PendingPayment {
+	payee: PublicKey,
+	payment_secret: Option<PaymentSecret>,
+	total_msat: u64,
+	final_cltv: u32,
+	last_hops: Vec<RouteHint>,
+	/// Channels which failed to carry a part of this payment and will not be used for retries.
+	excluded_channels: HashSet<u64>,
+	retries_remaining: usize,
+	/// The number of parts which were sent but have not yet resolved.
+	inflight_parts: usize,
+	attempts: Vec<PaymentAttempt>,
+	state: PaymentState,
+}
+
+impl PendingPayment {
+	/// Excludes the channel(s) a failure blamed from future routes for this payment.
+	fn exclude_failed_channels(&mut self, path: &Vec<RouteHop>, network_update: &Option<HTLCFailChannelUpdate>) {
+		self.excluded_channels.extend(failed_channels(path, network_update.as_ref()));
+	}
+
+	/// Marks the first not-yet-failed attempt over the given path as failed.
+	fn mark_failed(&mut self, path: &Vec<RouteHop>) {
+		if let Some(attempt) = self.attempts.iter_mut().find(|attempt| !attempt.failed && attempt.path == *path) {
+			attempt.failed = true;
+		}
+	}
+
+	fn resolved_event(&self, payment_hash: PaymentHash, payment_preimage: Option<PaymentPreimage>) -> Event {
+		Event::PaymentResolved {
+			payment_hash,
+			payment_preimage,
+			attempts: self.attempts.clone(),
+		}
+	}
+}
+
+/// Tracks outbound payments and retries their failed parts over new routes, see the module-level
+/// documentation for more.
+pub struct PaymentRetryManager<L: Deref, S: Deref> where L::Target: Logger, S::Target: Score {
+	pending_payments: Mutex<HashMap<PaymentHash, PendingPayment>>,
+	max_retries: usize,
+	logger: L,
+	scorer: S,
+}
+
+impl<L: Deref, S: Deref> PaymentRetryManager<L, S> where L::Target: Logger, S::Target: Score {
+	/// Creates a new PaymentRetryManager which will attempt to re-route failed value for each
+	/// payment at most max_retries times before giving up on it, selecting routes using scorer.
+	pub fn new(max_retries: usize, logger: L, scorer: S) -> Self {
+		PaymentRetryManager {
+			pending_payments: Mutex::new(HashMap::new()),
+			max_retries,
+			logger,
+			scorer,
+		}
+	}
+
+	/// Sends a payment of final_value_msat to payee, routing it over the given network graph and
+	/// the channels in channel_manager, and tracks it so that failed parts are retried as
+	/// process_events is called.
+	///
+	/// If payment_secret is None the payment is always sent over a single path, as multi-path
+	/// payments require a recipient-provided payment_secret.
+	///
+	/// If no route to the payee can be found, or no part of the payment could be sent, an Err is
+	/// returned and the payment is not tracked. Otherwise, an Event::PaymentResolved will
+	/// eventually be returned from process_events for the payment.
+	///
+	/// As with ChannelManager::send_payment, nothing prevents you from paying the same
+	/// payment_hash twice. Sending a payment for a payment_hash which is already being tracked
+	/// fails with an APIError::APIMisuseError.
+	pub fn send_payment<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, CL: Deref>(&self,
+			channel_manager: &ChannelManager<ChanSigner, M, T, K, F, CL>, network: &NetworkGraph, payee: PublicKey,
+			payment_hash: PaymentHash, payment_secret: Option<PaymentSecret>, last_hops: &[RouteHint],
+			final_value_msat: u64, final_cltv: u32) -> Result<(), PaymentSendFailure>
+		where M::Target: ManyChannelMonitor<Keys=ChanSigner>,
+		      T::Target: BroadcasterInterface,
+		      K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+		      F::Target: FeeEstimator,
+		      CL::Target: Logger,
+	{
+		let mut pending_payments = self.pending_payments.lock().unwrap();
+		if pending_payments.contains_key(&payment_hash) {
+			return Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError{err: "A payment with the same payment_hash is already pending"}));
+		}
+
+		let mut payment = PendingPayment {
+			payee,
+			payment_secret,
+			total_msat: final_value_msat,
+			final_cltv,
+			last_hops: last_hops.to_vec(),
+			excluded_channels: HashSet::new(),
+			retries_remaining: self.max_retries,
+			inflight_parts: 0,
+			attempts: Vec::new(),
+			state: PaymentState::Retrying,
+		};
+		let route = self.find_route(channel_manager, network, &payment, final_value_msat)
+			.map_err(|err| PaymentSendFailure::ParameterError(APIError::RouteError{err}))?;
+		let res = channel_manager.send_payment(&route, payment_hash, &payment.payment_secret);
+		let failed_value_msat = self.record_send_result(&mut payment, route, &res);
+		if failed_value_msat != 0 {
+			self.retry_value(channel_manager, network, payment_hash, &mut payment, failed_value_msat);
+		}
+		if payment.inflight_parts == 0 {
+			return res;
+		}
+		pending_payments.insert(payment_hash, payment);
+		Ok(())
+	}
+
+	/// Processes the events returned by ChannelManager::get_and_clear_pending_events, retrying
+	/// the failed parts of any payments sent via send_payment.
+	///
+	/// The PaymentSent and PaymentFailed events for tracked payments are consumed, with a single
+	/// Event::PaymentResolved generated once each payment succeeds or is given up on. The scorer
+	/// is updated with the results of the consumed events. All other events are returned
+	/// unmodified.
+	///
+	/// Retried parts may generate new messages for peers, so you should call
+	/// PeerManager::process_events after calling this.
+	pub fn process_events<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, CL: Deref>(&self,
+			channel_manager: &ChannelManager<ChanSigner, M, T, K, F, CL>, network: &NetworkGraph, events: Vec<Event>) -> Vec<Event>
+		where M::Target: ManyChannelMonitor<Keys=ChanSigner>,
+		      T::Target: BroadcasterInterface,
+		      K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+		      F::Target: FeeEstimator,
+		      CL::Target: Logger,
+	{
+		let mut pending_payments = self.pending_payments.lock().unwrap();
+		let mut res = Vec::with_capacity(events.len());
+		for event in events {
+			match event {
+				Event::PaymentSent { payment_preimage, path } => {
+					let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
+					let remove = match pending_payments.get_mut(&payment_hash) {
+						Some(payment) => {
+							self.scorer.payment_path_successful(&path);
+							payment.inflight_parts = payment.inflight_parts.saturating_sub(1);
+							if payment.state != PaymentState::Succeeded {
+								log_trace!(self.logger, "Payment with payment_hash {} succeeded", log_bytes!(payment_hash.0));
+								payment.state = PaymentState::Succeeded;
+								res.push(payment.resolved_event(payment_hash, Some(payment_preimage)));
+							}
+							payment.inflight_parts == 0
+						},
+						None => {
+							res.push(Event::PaymentSent { payment_preimage, path });
+							false
+						},
+					};
+					if remove {
+						pending_payments.remove(&payment_hash);
+					}
+				},
+				Event::PaymentFailed { payment_hash, rejected_by_dest, path, network_update, failure } => {
+					let remove = match pending_payments.get_mut(&payment_hash) {
+						Some(payment) => {
+							self.scorer.payment_path_failed(&path, network_update.as_ref());
+							payment.inflight_parts = payment.inflight_parts.saturating_sub(1);
+							payment.mark_failed(&path);
+							if payment.state == PaymentState::Retrying {
+								if rejected_by_dest {
+									log_trace!(self.logger, "Payment with payment_hash {} was rejected by the recipient", log_bytes!(payment_hash.0));
+									payment.state = PaymentState::Abandoned;
+								} else {
+									payment.exclude_failed_channels(&path, &network_update);
+									let failed_value_msat = path.last().map(|hop| hop.fee_msat).unwrap_or(0);
+									self.retry_value(channel_manager, network, payment_hash, payment, failed_value_msat);
+								}
+							}
+							if payment.inflight_parts == 0 && payment.state == PaymentState::Abandoned {
+								res.push(payment.resolved_event(payment_hash, None));
+							}
+							payment.inflight_parts == 0 && payment.state != PaymentState::Retrying
+						},
+						None => {
+							res.push(Event::PaymentFailed { payment_hash, rejected_by_dest, path, network_update, failure });
+							false
+						},
+					};
+					if remove {
+						pending_payments.remove(&payment_hash);
+					}
+				},
+				_ => res.push(event),
+			}
+		}
+		res
+	}
+
+	fn find_route<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, CL: Deref>(&self,
+			channel_manager: &ChannelManager<ChanSigner, M, T, K, F, CL>, network: &NetworkGraph, payment: &PendingPayment,
+			value_msat: u64) -> Result<Route, &'static str>
+		where M::Target: ManyChannelMonitor<Keys=ChanSigner>,
+		      T::Target: BroadcasterInterface,
+		      K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+		      F::Target: FeeEstimator,
+		      CL::Target: Logger,
+	{
+		let first_hops = channel_manager.list_usable_channels();
+		let params = RouteParameters {
+			max_path_count: if payment.payment_secret.is_some() { MAX_PATH_COUNT } else { 1 },
+			excluded_channels: payment.excluded_channels.clone(),
+			..Default::default()
+		};
+		get_multi_path_route_with_params(&channel_manager.get_our_node_id(), network, &payment.payee, Some(&first_hops),
+			&payment.last_hops, value_msat, payment.final_cltv, &params, &*self.logger, &*self.scorer)
+			.map_err(|e| e.err)
+	}
+
+	/// Records the paths in route as attempts, returning the value which failed to send. The
+	/// first hop of each path which failed to send is excluded from future routes.
+	fn record_send_result(&self, payment: &mut PendingPayment, mut route: Route, res: &Result<(), PaymentSendFailure>) -> u64 {
+		let results: Vec<bool> = match res {
+			&Ok(()) => vec![true; route.paths.len()],
+			&Err(PaymentSendFailure::ParameterError(_)) => vec![false; route.paths.len()],
+			&Err(PaymentSendFailure::PathParameterError(_)) => vec![false; route.paths.len()],
+			&Err(PaymentSendFailure::AllFailedRetrySafe(_)) => vec![false; route.paths.len()],
+			&Err(PaymentSendFailure::PartialFailure(ref results)) => results.iter().map(|res| match res {
+				// Parts which hit a monitor update failure will still be sent once the monitor
+				// update completes, so must be treated as in flight.
+				&Ok(()) | &Err(APIError::MonitorUpdateFailed) => true,
+				&Err(_) => false,
+			}).collect(),
+		};
+		let mut failed_value_msat = 0;
+		for (path, sent) in route.paths.drain(..).zip(results) {
+			if sent {
+				payment.inflight_parts += 1;
+			} else {
+				failed_value_msat += path.last().unwrap().fee_msat;
+				payment.excluded_channels.insert(path.first().unwrap().short_channel_id);
+			}
+			payment.attempts.push(PaymentAttempt { path, failed: !sent });
+		}
+		failed_value_msat
+	}
+
+	/// Re-routes and sends value_msat of the payment, using up retries until it is all in flight
+	/// or the payment is abandoned.
+	fn retry_value<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, CL: Deref>(&self,
+			channel_manager: &ChannelManager<ChanSigner, M, T, K, F, CL>, network: &NetworkGraph, payment_hash: PaymentHash,
+			payment: &mut PendingPayment, mut value_msat: u64)
+		where M::Target: ManyChannelMonitor<Keys=ChanSigner>,
+		      T::Target: BroadcasterInterface,
+		      K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+		      F::Target: FeeEstimator,
+		      CL::Target: Logger,
+	{
+		while value_msat != 0 {
+			if payment.retries_remaining == 0 {
+				log_trace!(self.logger, "Out of retries for payment with payment_hash {}", log_bytes!(payment_hash.0));
+				payment.state = PaymentState::Abandoned;
+				return;
+			}
+			payment.retries_remaining -= 1;
+			let route = match self.find_route(channel_manager, network, payment, value_msat) {
+				Ok(route) => route,
+				Err(err) => {
+					log_trace!(self.logger, "Failed to find a route to retry payment with payment_hash {}: {}", log_bytes!(payment_hash.0), err);
+					payment.state = PaymentState::Abandoned;
+					return;
+				},
+			};
+			log_trace!(self.logger, "Retrying {} msat of payment with payment_hash {}", value_msat, log_bytes!(payment_hash.0));
+			let res = channel_manager.retry_payment(&route, payment_hash, &payment.payment_secret, payment.total_msat);
+			value_msat = self.record_send_result(payment, route, &res);
+		}
+	}
+}
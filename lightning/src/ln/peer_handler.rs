@@ -21,7 +21,7 @@ use util::events::{MessageSendEvent, MessageSendEventsProvider};
 use util::logger::Logger;
 use routing::network_graph::NetGraphMsgHandler;
 
-use std::collections::{HashMap,hash_map,HashSet,LinkedList};
+use std::collections::{HashMap,hash_map,HashSet,LinkedList,VecDeque};
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{cmp,error,hash,fmt};
@@ -110,6 +110,13 @@ enum InitSyncTracker{
 	NodesSyncing(PublicKey),
 }
 
+/// The messages we have yet to send in reply to a peer's query_short_channel_ids.
+struct ShortIdsReplyTracker {
+	channel_announcements: VecDeque<(msgs::ChannelAnnouncement, Option<msgs::ChannelUpdate>, Option<msgs::ChannelUpdate>)>,
+	node_announcements: VecDeque<msgs::NodeAnnouncement>,
+	reply_end: msgs::ReplyShortChannelIdsEnd,
+}
+
 struct Peer {
 	channel_encryptor: PeerChannelEncryptor,
 	outbound: bool,
@@ -125,6 +132,11 @@ struct Peer {
 	pending_read_is_header: bool,
 
 	sync_status: InitSyncTracker,
+	/// The gossip_timestamp_filter most recently received from this peer, if any.
+	gossip_filter: Option<msgs::GossipTimestampFilter>,
+	/// Our reply to this peer's query_short_channel_ids, if we haven't finished sending it. Like
+	/// the initial routing table sync, it is only written as the outbound buffer drains.
+	pending_short_ids_reply: Option<ShortIdsReplyTracker>,
 
 	awaiting_pong: bool,
 }
@@ -152,6 +164,21 @@ impl Peer {
 			InitSyncTracker::NodesSyncing(pk) => pk < node_id,
 		}
 	}
+
+	/// Returns true if gossip with the given timestamp may be sent to this peer. Peers which
+	/// negotiated gossip_queries are only sent gossip which falls within the
+	/// gossip_timestamp_filter they provided, and none at all until they provide one.
+	fn gossip_filter_allows(&self, timestamp: u32) -> bool {
+		match self.their_features {
+			Some(ref features) if features.supports_gossip_queries() => {
+				match self.gossip_filter {
+					Some(ref filter) => filter.includes(timestamp),
+					None => false,
+				}
+			},
+			_ => true,
+		}
+	}
 }
 
 struct PeerHolder<Descriptor: SocketDescriptor> {
@@ -319,6 +346,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			pending_read_is_header: false,
 
 			sync_status: InitSyncTracker::NoSyncRequested,
+			gossip_filter: None,
+			pending_short_ids_reply: None,
 
 			awaiting_pong: false,
 		}).is_some() {
@@ -356,6 +385,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			pending_read_is_header: false,
 
 			sync_status: InitSyncTracker::NoSyncRequested,
+			gossip_filter: None,
+			pending_short_ids_reply: None,
 
 			awaiting_pong: false,
 		}).is_some() {
@@ -375,6 +406,29 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 		}
 		const MSG_BUFF_SIZE: usize = 10;
 		while !peer.awaiting_write_event {
+			// Replies to the peer's queries take priority over our own sync of our routing table.
+			if let Some(mut reply) = peer.pending_short_ids_reply.take() {
+				let mut reply_complete = false;
+				while !reply_complete && peer.pending_outbound_buffer.len() < MSG_BUFF_SIZE {
+					if let Some((announce, update_a_option, update_b_option)) = reply.channel_announcements.pop_front() {
+						encode_and_send_msg!(&announce);
+						if let Some(update_a) = update_a_option {
+							encode_and_send_msg!(&update_a);
+						}
+						if let Some(update_b) = update_b_option {
+							encode_and_send_msg!(&update_b);
+						}
+					} else if let Some(announce) = reply.node_announcements.pop_front() {
+						encode_and_send_msg!(&announce);
+					} else {
+						encode_and_send_msg!(&reply.reply_end);
+						reply_complete = true;
+					}
+				}
+				if !reply_complete {
+					peer.pending_short_ids_reply = Some(reply);
+				}
+			}
 			if peer.pending_outbound_buffer.len() < MSG_BUFF_SIZE {
 				match peer.sync_status {
 					InitSyncTracker::NoSyncRequested => {},
@@ -382,12 +436,22 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let steps = ((MSG_BUFF_SIZE - peer.pending_outbound_buffer.len() + 2) / 3) as u8;
 						let all_messages = self.message_handler.route_handler.get_next_channel_announcements(c, steps);
 						for &(ref announce, ref update_a_option, ref update_b_option) in all_messages.iter() {
-							encode_and_send_msg!(announce);
+							// A channel_announcement is filtered on the timestamp of its latest
+							// channel_update, as it has no timestamp of its own.
+							let update_a_timestamp = update_a_option.as_ref().map(|update_a| update_a.contents.timestamp);
+							let update_b_timestamp = update_b_option.as_ref().map(|update_b| update_b.contents.timestamp);
+							if peer.gossip_filter_allows(cmp::max(update_a_timestamp, update_b_timestamp).unwrap_or(0)) {
+								encode_and_send_msg!(announce);
+							}
 							if let &Some(ref update_a) = update_a_option {
-								encode_and_send_msg!(update_a);
+								if peer.gossip_filter_allows(update_a.contents.timestamp) {
+									encode_and_send_msg!(update_a);
+								}
 							}
 							if let &Some(ref update_b) = update_b_option {
-								encode_and_send_msg!(update_b);
+								if peer.gossip_filter_allows(update_b.contents.timestamp) {
+									encode_and_send_msg!(update_b);
+								}
 							}
 							peer.sync_status = InitSyncTracker::ChannelsSyncing(announce.contents.short_channel_id + 1);
 						}
@@ -399,7 +463,9 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let steps = (MSG_BUFF_SIZE - peer.pending_outbound_buffer.len()) as u8;
 						let all_messages = self.message_handler.route_handler.get_next_node_announcements(None, steps);
 						for msg in all_messages.iter() {
-							encode_and_send_msg!(msg);
+							if peer.gossip_filter_allows(msg.contents.timestamp) {
+								encode_and_send_msg!(msg);
+							}
 							peer.sync_status = InitSyncTracker::NodesSyncing(msg.contents.node_id);
 						}
 						if all_messages.is_empty() || all_messages.len() != steps as usize {
@@ -411,7 +477,9 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let steps = (MSG_BUFF_SIZE - peer.pending_outbound_buffer.len()) as u8;
 						let all_messages = self.message_handler.route_handler.get_next_node_announcements(Some(&key), steps);
 						for msg in all_messages.iter() {
-							encode_and_send_msg!(msg);
+							if peer.gossip_filter_allows(msg.contents.timestamp) {
+								encode_and_send_msg!(msg);
+							}
 							peer.sync_status = InitSyncTracker::NodesSyncing(msg.contents.node_id);
 						}
 						if all_messages.is_empty() || all_messages.len() != steps as usize {
@@ -423,7 +491,14 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 			if {
 				let next_buff = match peer.pending_outbound_buffer.front() {
-					None => return,
+					None => {
+						// If the peer's gossip filter excluded everything we just tried to sync,
+						// keep going until we either have something to send or are done syncing.
+						if let InitSyncTracker::NoSyncRequested = peer.sync_status {
+							return;
+						}
+						continue;
+					},
 					Some(buff) => buff,
 				};
 
@@ -575,7 +650,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 									peer.their_node_id = Some(their_node_id);
 									insert_node_id!();
-									let mut features = InitFeatures::known();
+									let mut features = self.message_handler.chan_handler.provided_init_features();
 									if !self.message_handler.route_handler.should_request_full_sync(&peer.their_node_id.unwrap()) {
 										features.clear_initial_routing_sync();
 									}
@@ -628,6 +703,10 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 													}
 													msgs::DecodeError::BadLengthDescriptor => return Err(PeerHandleError { no_connection_possible: false }),
 													msgs::DecodeError::Io(_) => return Err(PeerHandleError { no_connection_possible: false }),
+													msgs::DecodeError::UnsupportedCompression => {
+														log_debug!(self.logger, "We don't support zlib-compressed message fields, ignoring message");
+														continue;
+													}
 												}
 											}
 										};
@@ -685,15 +764,18 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				}
 
 				log_info!(
-					self.logger, "Received peer Init message: data_loss_protect: {}, initial_routing_sync: {}, upfront_shutdown_script: {}, static_remote_key: {}, unknown flags (local and global): {}",
+					self.logger, "Received peer Init message: data_loss_protect: {}, initial_routing_sync: {}, upfront_shutdown_script: {}, gossip_queries: {}, static_remote_key: {}, unknown flags (local and global): {}",
 					if msg.features.supports_data_loss_protect() { "supported" } else { "not supported"},
 					if msg.features.initial_routing_sync() { "requested" } else { "not requested" },
 					if msg.features.supports_upfront_shutdown_script() { "supported" } else { "not supported"},
+					if msg.features.supports_gossip_queries() { "supported" } else { "not supported" },
 					if msg.features.supports_static_remote_key() { "supported" } else { "not supported"},
 					if msg.features.supports_unknown_bits() { "present" } else { "none" }
 				);
 
-				if msg.features.initial_routing_sync() {
+				// Peers which support gossip_queries request gossip with a gossip_timestamp_filter
+				// instead of initial_routing_sync.
+				if msg.features.initial_routing_sync() && !msg.features.supports_gossip_queries() {
 					peer.sync_status = InitSyncTracker::ChannelsSyncing(0);
 					peers_needing_send.insert(peer_descriptor.clone());
 				}
@@ -703,7 +785,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				}
 
 				if !peer.outbound {
-					let mut features = InitFeatures::known();
+					let mut features = self.message_handler.chan_handler.provided_init_features();
 					if !self.message_handler.route_handler.should_request_full_sync(&peer.their_node_id.unwrap()) {
 						features.clear_initial_routing_sync();
 					}
@@ -713,6 +795,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				}
 
 				self.message_handler.chan_handler.peer_connected(&peer.their_node_id.unwrap(), &msg);
+				self.message_handler.route_handler.sync_routing_table(&peer.their_node_id.unwrap(), &msg);
 				peer.their_features = Some(msg.features);
 			},
 			wire::Message::Error(msg) => {
@@ -763,6 +846,37 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				self.message_handler.chan_handler.handle_funding_locked(&peer.their_node_id.unwrap(), &msg);
 			},
 
+			wire::Message::OpenChannelV2(msg) => {
+				self.message_handler.chan_handler.handle_open_channel_v2(&peer.their_node_id.unwrap(), peer.their_features.clone().unwrap(), &msg);
+			},
+			wire::Message::AcceptChannelV2(msg) => {
+				self.message_handler.chan_handler.handle_accept_channel_v2(&peer.their_node_id.unwrap(), peer.their_features.clone().unwrap(), &msg);
+			},
+			wire::Message::TxAddInput(msg) => {
+				self.message_handler.chan_handler.handle_tx_add_input(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxAddOutput(msg) => {
+				self.message_handler.chan_handler.handle_tx_add_output(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxComplete(msg) => {
+				self.message_handler.chan_handler.handle_tx_complete(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxSignatures(msg) => {
+				self.message_handler.chan_handler.handle_tx_signatures(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxAbort(msg) => {
+				self.message_handler.chan_handler.handle_tx_abort(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::SpliceInit(msg) => {
+				self.message_handler.chan_handler.handle_splice_init(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::SpliceAck(msg) => {
+				self.message_handler.chan_handler.handle_splice_ack(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::SpliceLocked(msg) => {
+				self.message_handler.chan_handler.handle_splice_locked(&peer.their_node_id.unwrap(), &msg);
+			},
+
 			wire::Message::Shutdown(msg) => {
 				self.message_handler.chan_handler.handle_shutdown(&peer.their_node_id.unwrap(), &msg);
 			},
@@ -831,6 +945,30 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 					// TODO: forward msg along to all our other peers!
 				}
 			},
+			wire::Message::QueryShortChannelIds(msg) => {
+				self.message_handler.route_handler.handle_query_short_channel_ids(&peer.their_node_id.unwrap(), &msg)?;
+			},
+			wire::Message::ReplyShortChannelIdsEnd(msg) => {
+				self.message_handler.route_handler.handle_reply_short_channel_ids_end(&peer.their_node_id.unwrap(), &msg)?;
+			},
+			wire::Message::QueryChannelRange(msg) => {
+				self.message_handler.route_handler.handle_query_channel_range(&peer.their_node_id.unwrap(), &msg)?;
+			},
+			wire::Message::ReplyChannelRange(msg) => {
+				self.message_handler.route_handler.handle_reply_channel_range(&peer.their_node_id.unwrap(), &msg)?;
+			},
+			wire::Message::GossipTimestampFilter(msg) => {
+				// Only start relaying gossip once the first filter is received, dumping any
+				// historical gossip which matches it.
+				let first_filter = peer.gossip_filter.is_none();
+				peer.gossip_filter = Some(msg);
+				if first_filter && peer.their_features.as_ref().unwrap().supports_gossip_queries() {
+					if let InitSyncTracker::NoSyncRequested = peer.sync_status {
+						peer.sync_status = InitSyncTracker::ChannelsSyncing(0);
+						peers_needing_send.insert(peer_descriptor.clone());
+					}
+				}
+			},
 
 			// Unknown messages:
 			wire::Message::Unknown(msg_type) if msg_type.is_even() => {
@@ -855,6 +993,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			// drop optional-ish messages when send buffers get full!
 
 			let mut events_generated = self.message_handler.chan_handler.get_and_clear_pending_msg_events();
+			events_generated.append(&mut self.message_handler.route_handler.get_and_clear_pending_msg_events());
 			let mut peers_lock = self.peers.lock().unwrap();
 			let peers = &mut *peers_lock;
 			for event in events_generated.drain(..) {
@@ -902,6 +1041,106 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
+					MessageSendEvent::SendOpenChannelV2 { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendOpenChannelV2 event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.temporary_channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendAcceptChannelV2 { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendAcceptChannelV2 event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.temporary_channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxAddInput { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxAddInput event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxAddOutput { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxAddOutput event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxComplete { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxComplete event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxSignatures { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxSignatures event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxAbort { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxAbort event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendSpliceInit { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendSpliceInit event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendSpliceAck { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendSpliceAck event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendSpliceLocked { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendSpliceLocked event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
 					MessageSendEvent::SendFundingCreated { ref node_id, ref msg } => {
 						log_trace!(self.logger, "Handling SendFundingCreated event in peer_handler for node {} for channel {} (which becomes {})",
 								log_pubkey!(node_id),
@@ -1022,7 +1261,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 							for (ref descriptor, ref mut peer) in peers.peers.iter_mut() {
 								if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() ||
-										!peer.should_forward_channel_announcement(msg.contents.short_channel_id) {
+										!peer.should_forward_channel_announcement(msg.contents.short_channel_id) ||
+										!peer.gossip_filter_allows(update_msg.contents.timestamp) {
 									continue
 								}
 								match peer.their_node_id {
@@ -1046,7 +1286,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 							for (ref descriptor, ref mut peer) in peers.peers.iter_mut() {
 								if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() ||
-										!peer.should_forward_node_announcement(msg.contents.node_id) {
+										!peer.should_forward_node_announcement(msg.contents.node_id) ||
+										!peer.gossip_filter_allows(msg.contents.timestamp) {
 									continue
 								}
 								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_msg[..]));
@@ -1061,7 +1302,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 							for (ref descriptor, ref mut peer) in peers.peers.iter_mut() {
 								if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() ||
-										!peer.should_forward_channel_announcement(msg.contents.short_channel_id)  {
+										!peer.should_forward_channel_announcement(msg.contents.short_channel_id) ||
+										!peer.gossip_filter_allows(msg.contents.timestamp) {
 									continue
 								}
 								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_msg[..]));
@@ -1072,6 +1314,56 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 					MessageSendEvent::PaymentFailureNetworkUpdate { ref update } => {
 						self.message_handler.route_handler.handle_htlc_fail_channel_update(update);
 					},
+					MessageSendEvent::SendChannelRangeQuery { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendChannelRangeQuery event in peer_handler for node {} for blocks {} through {}",
+								log_pubkey!(node_id),
+								msg.first_blocknum,
+								msg.first_blocknum as u64 + msg.number_of_blocks as u64);
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendReplyChannelRange { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendReplyChannelRange event in peer_handler for node {} with {} short_channel_ids",
+								log_pubkey!(node_id),
+								msg.short_channel_ids.len());
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendShortIdsQuery { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendShortIdsQuery event in peer_handler for node {} with {} short_channel_ids",
+								log_pubkey!(node_id),
+								msg.short_channel_ids.len());
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendShortIdsReply { ref node_id, ref channel_announcements, ref node_announcements, ref msg } => {
+						log_trace!(self.logger, "Handling SendShortIdsReply event in peer_handler for node {} with {} channel_announcements and {} node_announcements",
+								log_pubkey!(node_id),
+								channel_announcements.len(),
+								node_announcements.len());
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						if peer.pending_short_ids_reply.is_some() {
+							// Peers must wait for our reply_short_channel_ids_end before querying again.
+							log_debug!(self.logger, "Dropping reply to a query_short_channel_ids from {} sent before we finished replying to its previous query",
+									log_pubkey!(node_id));
+							continue;
+						}
+						peer.pending_short_ids_reply = Some(ShortIdsReplyTracker {
+							channel_announcements: channel_announcements.iter().cloned().collect(),
+							node_announcements: node_announcements.iter().cloned().collect(),
+							reply_end: msg.clone(),
+						});
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendGossipTimestampFilter { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendGossipTimestampFilter event in peer_handler for node {}", log_pubkey!(node_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
 					MessageSendEvent::HandleError { ref node_id, ref action } => {
 						match *action {
 							msgs::ErrorAction::DisconnectPeer { ref msg } => {
@@ -1092,6 +1384,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 									}
 									descriptor.disconnect_socket();
 									self.message_handler.chan_handler.peer_disconnected(&node_id, false);
+									self.message_handler.route_handler.peer_disconnected(&node_id);
 								}
 							},
 							msgs::ErrorAction::IgnoreError => {},
@@ -1142,6 +1435,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 					Some(node_id) => {
 						peers.node_id_to_descriptor.remove(&node_id);
 						self.message_handler.chan_handler.peer_disconnected(&node_id, no_connection_possible);
+						self.message_handler.route_handler.peer_disconnected(&node_id);
 					},
 					None => {}
 				}
@@ -1171,6 +1465,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 							log_trace!(self.logger, "Disconnecting peer with id {} due to ping timeout", node_id);
 							node_id_to_descriptor.remove(&node_id);
 							self.message_handler.chan_handler.peer_disconnected(&node_id, false);
+							self.message_handler.route_handler.peer_disconnected(&node_id);
 						}
 						None => {
 							// This can't actually happen as we should have hit
@@ -1210,20 +1505,26 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 mod tests {
 	use ln::peer_handler::{PeerManager, MessageHandler, SocketDescriptor};
 	use ln::msgs;
+	use ln::msgs::RoutingMessageHandler;
 	use util::events;
 	use util::test_utils;
 
+	use bitcoin::blockdata::constants::genesis_block;
+	use bitcoin::network::constants::Network;
+	use bitcoin::util::hash::BitcoinHash;
+
 	use bitcoin::secp256k1::Secp256k1;
 	use bitcoin::secp256k1::key::{SecretKey, PublicKey};
 
 	use std;
 	use std::sync::{Arc, Mutex};
-	use std::sync::atomic::Ordering;
+	use std::sync::atomic::{AtomicBool, Ordering};
 
 	#[derive(Clone)]
 	struct FileDescriptor {
 		fd: u16,
 		outbound_data: Arc<Mutex<Vec<u8>>>,
+		hang_writes: Arc<AtomicBool>,
 	}
 	impl PartialEq for FileDescriptor {
 		fn eq(&self, other: &Self) -> bool {
@@ -1239,6 +1540,7 @@ mod tests {
 
 	impl SocketDescriptor for FileDescriptor {
 		fn send_data(&mut self, data: &[u8], _resume_read: bool) -> usize {
+			if self.hang_writes.load(Ordering::Acquire) { return 0; }
 			self.outbound_data.lock().unwrap().extend_from_slice(data);
 			data.len()
 		}
@@ -1283,8 +1585,8 @@ mod tests {
 	fn establish_connection<'a>(peer_a: &PeerManager<FileDescriptor, &'a test_utils::TestChannelMessageHandler, &'a test_utils::TestRoutingMessageHandler, &'a test_utils::TestLogger>, peer_b: &PeerManager<FileDescriptor, &'a test_utils::TestChannelMessageHandler, &'a test_utils::TestRoutingMessageHandler, &'a test_utils::TestLogger>) -> (FileDescriptor, FileDescriptor) {
 		let secp_ctx = Secp256k1::new();
 		let a_id = PublicKey::from_secret_key(&secp_ctx, &peer_a.our_node_secret);
-		let mut fd_a = FileDescriptor { fd: 1, outbound_data: Arc::new(Mutex::new(Vec::new())) };
-		let mut fd_b = FileDescriptor { fd: 1, outbound_data: Arc::new(Mutex::new(Vec::new())) };
+		let mut fd_a = FileDescriptor { fd: 1, outbound_data: Arc::new(Mutex::new(Vec::new())), hang_writes: Arc::new(AtomicBool::new(false)) };
+		let mut fd_b = FileDescriptor { fd: 1, outbound_data: Arc::new(Mutex::new(Vec::new())), hang_writes: Arc::new(AtomicBool::new(false)) };
 		let initial_data = peer_b.new_outbound_connection(a_id, fd_b.clone()).unwrap();
 		peer_a.new_inbound_connection(fd_a.clone()).unwrap();
 		assert_eq!(peer_a.read_event(&mut fd_a, &initial_data).unwrap(), false);
@@ -1361,6 +1663,14 @@ mod tests {
 		peers[1].read_event(&mut fd_b, &fd_a.outbound_data.lock().unwrap().split_off(0)).unwrap();
 		peers[0].read_event(&mut fd_a, &fd_b.outbound_data.lock().unwrap().split_off(0)).unwrap();
 
+		// As both peers support gossip_queries, the routing table dump only starts once each peer
+		// has received the other's gossip_timestamp_filter.
+		peers[0].process_events();
+		peers[1].process_events();
+		peers[1].read_event(&mut fd_b, &fd_a.outbound_data.lock().unwrap().split_off(0)).unwrap();
+		peers[0].read_event(&mut fd_a, &fd_b.outbound_data.lock().unwrap().split_off(0)).unwrap();
+		peers[1].read_event(&mut fd_b, &fd_a.outbound_data.lock().unwrap().split_off(0)).unwrap();
+
 		// Check that each peer has received the expected number of channel updates and channel
 		// announcements.
 		assert_eq!(cfgs[0].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 100);
@@ -1369,6 +1679,50 @@ mod tests {
 		assert_eq!(cfgs[1].routing_handler.chan_anns_recvd.load(Ordering::Acquire), 50);
 	}
 
+	#[test]
+	fn test_short_ids_reply_backpressure() {
+		let cfgs = create_peermgr_cfgs(2);
+		let peers = create_network(2, &cfgs);
+		let (mut fd_a, mut fd_b) = establish_connection_and_read_events(&peers[0], &peers[1]);
+		peers[0].process_events();
+		peers[1].process_events();
+		peers[1].read_event(&mut fd_b, &fd_a.outbound_data.lock().unwrap().split_off(0)).unwrap();
+		peers[0].read_event(&mut fd_a, &fd_b.outbound_data.lock().unwrap().split_off(0)).unwrap();
+		let chan_anns_recvd = cfgs[1].routing_handler.chan_anns_recvd.load(Ordering::Acquire);
+		let chan_upds_recvd = cfgs[1].routing_handler.chan_upds_recvd.load(Ordering::Acquire);
+
+		// Reply to a query_short_channel_ids while peer 1 isn't reading from its socket.
+		let secp_ctx = Secp256k1::new();
+		let their_id = PublicKey::from_secret_key(&secp_ctx, &peers[1].our_node_secret);
+		let channel_announcements = test_utils::TestRoutingMessageHandler::new().get_next_channel_announcements(0, 50);
+		cfgs[0].routing_handler.pending_events.lock().unwrap().push(events::MessageSendEvent::SendShortIdsReply {
+			node_id: their_id,
+			channel_announcements,
+			node_announcements: Vec::new(),
+			msg: msgs::ReplyShortChannelIdsEnd {
+				chain_hash: genesis_block(Network::Testnet).header.bitcoin_hash(),
+				full_information: true,
+			},
+		});
+		fd_a.hang_writes.store(true, Ordering::Release);
+		peers[0].process_events();
+		{
+			// Only enough of the reply to fill the outbound buffer is encoded, here four channels.
+			let peers_lock = peers[0].peers.lock().unwrap();
+			let peer = peers_lock.peers.get(&fd_a).unwrap();
+			assert_eq!(peer.pending_outbound_buffer.len(), 12);
+			assert_eq!(peer.pending_short_ids_reply.as_ref().unwrap().channel_announcements.len(), 46);
+		}
+
+		// The rest of the reply is sent as peer 1 reads it.
+		fd_a.hang_writes.store(false, Ordering::Release);
+		peers[0].write_buffer_space_avail(&mut fd_a).unwrap();
+		assert!(peers[0].peers.lock().unwrap().peers.get(&fd_a).unwrap().pending_short_ids_reply.is_none());
+		peers[1].read_event(&mut fd_b, &fd_a.outbound_data.lock().unwrap().split_off(0)).unwrap();
+		assert_eq!(cfgs[1].routing_handler.chan_anns_recvd.load(Ordering::Acquire), chan_anns_recvd + 50);
+		assert_eq!(cfgs[1].routing_handler.chan_upds_recvd.load(Ordering::Acquire), chan_upds_recvd + 100);
+	}
+
 	#[test]
 	fn limit_initial_routing_sync_requests() {
 		// Inbound peer 0 requests initial_routing_sync, but outbound peer 1 does not.
@@ -32,6 +32,16 @@ pub enum Message {
 	FundingCreated(msgs::FundingCreated),
 	FundingSigned(msgs::FundingSigned),
 	FundingLocked(msgs::FundingLocked),
+	OpenChannelV2(msgs::OpenChannelV2),
+	AcceptChannelV2(msgs::AcceptChannelV2),
+	TxAddInput(msgs::TxAddInput),
+	TxAddOutput(msgs::TxAddOutput),
+	TxComplete(msgs::TxComplete),
+	TxSignatures(msgs::TxSignatures),
+	TxAbort(msgs::TxAbort),
+	SpliceInit(msgs::SpliceInit),
+	SpliceAck(msgs::SpliceAck),
+	SpliceLocked(msgs::SpliceLocked),
 	Shutdown(msgs::Shutdown),
 	ClosingSigned(msgs::ClosingSigned),
 	UpdateAddHTLC(msgs::UpdateAddHTLC),
@@ -46,6 +56,11 @@ pub enum Message {
 	ChannelAnnouncement(msgs::ChannelAnnouncement),
 	NodeAnnouncement(msgs::NodeAnnouncement),
 	ChannelUpdate(msgs::ChannelUpdate),
+	QueryShortChannelIds(msgs::QueryShortChannelIds),
+	ReplyShortChannelIdsEnd(msgs::ReplyShortChannelIdsEnd),
+	QueryChannelRange(msgs::QueryChannelRange),
+	ReplyChannelRange(msgs::ReplyChannelRange),
+	GossipTimestampFilter(msgs::GossipTimestampFilter),
 	/// A message that could not be decoded because its type is unknown.
 	Unknown(MessageType),
 }
@@ -67,6 +82,16 @@ impl Message {
 			&Message::FundingCreated(ref msg) => msg.type_id(),
 			&Message::FundingSigned(ref msg) => msg.type_id(),
 			&Message::FundingLocked(ref msg) => msg.type_id(),
+			&Message::OpenChannelV2(ref msg) => msg.type_id(),
+			&Message::AcceptChannelV2(ref msg) => msg.type_id(),
+			&Message::TxAddInput(ref msg) => msg.type_id(),
+			&Message::TxAddOutput(ref msg) => msg.type_id(),
+			&Message::TxComplete(ref msg) => msg.type_id(),
+			&Message::TxSignatures(ref msg) => msg.type_id(),
+			&Message::TxAbort(ref msg) => msg.type_id(),
+			&Message::SpliceInit(ref msg) => msg.type_id(),
+			&Message::SpliceAck(ref msg) => msg.type_id(),
+			&Message::SpliceLocked(ref msg) => msg.type_id(),
 			&Message::Shutdown(ref msg) => msg.type_id(),
 			&Message::ClosingSigned(ref msg) => msg.type_id(),
 			&Message::UpdateAddHTLC(ref msg) => msg.type_id(),
@@ -81,6 +106,11 @@ impl Message {
 			&Message::ChannelAnnouncement(ref msg) => msg.type_id(),
 			&Message::NodeAnnouncement(ref msg) => msg.type_id(),
 			&Message::ChannelUpdate(ref msg) => msg.type_id(),
+			&Message::QueryShortChannelIds(ref msg) => msg.type_id(),
+			&Message::ReplyShortChannelIdsEnd(ref msg) => msg.type_id(),
+			&Message::QueryChannelRange(ref msg) => msg.type_id(),
+			&Message::ReplyChannelRange(ref msg) => msg.type_id(),
+			&Message::GossipTimestampFilter(ref msg) => msg.type_id(),
 			&Message::Unknown(type_id) => type_id,
 		}
 	}
@@ -135,6 +165,36 @@ pub fn read<R: ::std::io::Read>(buffer: &mut R) -> Result<Message, msgs::DecodeE
 		msgs::FundingLocked::TYPE => {
 			Ok(Message::FundingLocked(Readable::read(buffer)?))
 		},
+		msgs::OpenChannelV2::TYPE => {
+			Ok(Message::OpenChannelV2(Readable::read(buffer)?))
+		},
+		msgs::AcceptChannelV2::TYPE => {
+			Ok(Message::AcceptChannelV2(Readable::read(buffer)?))
+		},
+		msgs::TxAddInput::TYPE => {
+			Ok(Message::TxAddInput(Readable::read(buffer)?))
+		},
+		msgs::TxAddOutput::TYPE => {
+			Ok(Message::TxAddOutput(Readable::read(buffer)?))
+		},
+		msgs::TxComplete::TYPE => {
+			Ok(Message::TxComplete(Readable::read(buffer)?))
+		},
+		msgs::TxSignatures::TYPE => {
+			Ok(Message::TxSignatures(Readable::read(buffer)?))
+		},
+		msgs::TxAbort::TYPE => {
+			Ok(Message::TxAbort(Readable::read(buffer)?))
+		},
+		msgs::SpliceInit::TYPE => {
+			Ok(Message::SpliceInit(Readable::read(buffer)?))
+		},
+		msgs::SpliceAck::TYPE => {
+			Ok(Message::SpliceAck(Readable::read(buffer)?))
+		},
+		msgs::SpliceLocked::TYPE => {
+			Ok(Message::SpliceLocked(Readable::read(buffer)?))
+		},
 		msgs::Shutdown::TYPE => {
 			Ok(Message::Shutdown(Readable::read(buffer)?))
 		},
@@ -177,6 +237,21 @@ pub fn read<R: ::std::io::Read>(buffer: &mut R) -> Result<Message, msgs::DecodeE
 		msgs::ChannelUpdate::TYPE => {
 			Ok(Message::ChannelUpdate(Readable::read(buffer)?))
 		},
+		msgs::QueryShortChannelIds::TYPE => {
+			Ok(Message::QueryShortChannelIds(Readable::read(buffer)?))
+		},
+		msgs::ReplyShortChannelIdsEnd::TYPE => {
+			Ok(Message::ReplyShortChannelIdsEnd(Readable::read(buffer)?))
+		},
+		msgs::QueryChannelRange::TYPE => {
+			Ok(Message::QueryChannelRange(Readable::read(buffer)?))
+		},
+		msgs::ReplyChannelRange::TYPE => {
+			Ok(Message::ReplyChannelRange(Readable::read(buffer)?))
+		},
+		msgs::GossipTimestampFilter::TYPE => {
+			Ok(Message::GossipTimestampFilter(Readable::read(buffer)?))
+		},
 		_ => {
 			Ok(Message::Unknown(MessageType(message_type)))
 		},
@@ -251,6 +326,46 @@ impl Encode for msgs::Shutdown {
 	const TYPE: u16 = 38;
 }
 
+impl Encode for msgs::OpenChannelV2 {
+	const TYPE: u16 = 64;
+}
+
+impl Encode for msgs::AcceptChannelV2 {
+	const TYPE: u16 = 65;
+}
+
+impl Encode for msgs::TxAddInput {
+	const TYPE: u16 = 66;
+}
+
+impl Encode for msgs::TxAddOutput {
+	const TYPE: u16 = 67;
+}
+
+impl Encode for msgs::TxComplete {
+	const TYPE: u16 = 70;
+}
+
+impl Encode for msgs::TxSignatures {
+	const TYPE: u16 = 71;
+}
+
+impl Encode for msgs::TxAbort {
+	const TYPE: u16 = 74;
+}
+
+impl Encode for msgs::SpliceLocked {
+	const TYPE: u16 = 77;
+}
+
+impl Encode for msgs::SpliceInit {
+	const TYPE: u16 = 80;
+}
+
+impl Encode for msgs::SpliceAck {
+	const TYPE: u16 = 81;
+}
+
 impl Encode for msgs::ClosingSigned {
 	const TYPE: u16 = 39;
 }
@@ -303,6 +418,26 @@ impl Encode for msgs::ChannelUpdate {
 	const TYPE: u16 = 258;
 }
 
+impl Encode for msgs::QueryShortChannelIds {
+	const TYPE: u16 = 261;
+}
+
+impl Encode for msgs::ReplyShortChannelIdsEnd {
+	const TYPE: u16 = 262;
+}
+
+impl Encode for msgs::QueryChannelRange {
+	const TYPE: u16 = 263;
+}
+
+impl Encode for msgs::ReplyChannelRange {
+	const TYPE: u16 = 264;
+}
+
+impl Encode for msgs::GossipTimestampFilter {
+	const TYPE: u16 = 265;
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -406,24 +541,25 @@ mod tests {
 	fn read_lnd_init_msg() {
 		// Taken from lnd v0.9.0-beta.
 		let buffer = vec![0, 16, 0, 2, 34, 0, 0, 3, 2, 162, 161];
-		check_init_msg(buffer);
+		check_init_msg(buffer, false);
 	}
 
 	#[test]
 	fn read_clightning_init_msg() {
 		// Taken from c-lightning v0.8.0.
 		let buffer = vec![0, 16, 0, 2, 34, 0, 0, 3, 2, 170, 162, 1, 32, 6, 34, 110, 70, 17, 26, 11, 89, 202, 175, 18, 96, 67, 235, 91, 191, 40, 195, 79, 58, 94, 51, 42, 31, 199, 178, 183, 60, 241, 136, 145, 15];
-		check_init_msg(buffer);
+		check_init_msg(buffer, true);
 	}
 
-	fn check_init_msg(buffer: Vec<u8>) {
+	fn check_init_msg(buffer: Vec<u8>, expect_unknown: bool) {
 		let mut reader = ::std::io::Cursor::new(buffer);
 		let decoded_msg = read(&mut reader).unwrap();
 		match decoded_msg {
 			Message::Init(msgs::Init { features }) => {
 				assert!(features.supports_variable_length_onion());
 				assert!(features.supports_upfront_shutdown_script());
-				assert!(features.supports_unknown_bits());
+				assert!(features.supports_gossip_queries());
+				assert_eq!(expect_unknown, features.supports_unknown_bits());
 				assert!(!features.requires_unknown_bits());
 				assert!(!features.initial_routing_sync());
 			},
@@ -441,7 +577,8 @@ mod tests {
 			Message::NodeAnnouncement(msgs::NodeAnnouncement { contents: msgs::UnsignedNodeAnnouncement { features, ..}, ..}) => {
 				assert!(features.supports_variable_length_onion());
 				assert!(features.supports_upfront_shutdown_script());
-				assert!(features.supports_unknown_bits());
+				assert!(features.supports_gossip_queries());
+				assert!(!features.supports_unknown_bits());
 				assert!(!features.requires_unknown_bits());
 			},
 			_ => panic!("Expected node announcement, found message type: {}", decoded_msg.type_id())
@@ -6,34 +6,278 @@ use bitcoin::secp256k1;
 
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 use bitcoin::hashes::Hash;
-use bitcoin::blockdata::script::Builder;
+use bitcoin::blockdata::script::{Builder, Script};
 use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::hash_types::{BlockHash, Txid};
 
-use chain::chaininterface::{ChainError, ChainWatchInterface};
+use chain::chaininterface::{ChainError, ChainListener, ChainWatchInterface};
 use ln::features::{ChannelFeatures, NodeFeatures};
-use ln::msgs::{DecodeError,ErrorAction,LightningError,RoutingMessageHandler,NetAddress};
+use ln::msgs::{DecodeError,ErrorAction,LightningError,RoutingMessageHandler,NetAddress,OptionalField};
+use ln::msgs::{QueryChannelRange,ReplyChannelRange,QueryShortChannelIds,ReplyShortChannelIdsEnd,GossipTimestampFilter};
 use ln::msgs;
-use util::ser::{Writeable, Readable, Writer};
+use util::events::{MessageSendEvent, MessageSendEventsProvider};
+use util::ser::{Writeable, Readable, ReadableArgs, Writer};
 use util::logger::Logger;
+use util::byte_utils;
 
-use std::{cmp, fmt};
-use std::sync::RwLock;
+use std::{cmp, fmt, mem};
+use std::sync::{Mutex, RwLock};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::collections::btree_map::Entry as BtreeEntry;
 use std::ops::Deref;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The maximum number of short_channel_ids we include in a single reply_channel_range or
+/// query_short_channel_ids message, keeping the message well within the 65535-byte limit.
+const MAX_SHORT_CHANNEL_IDS_PER_MESSAGE: usize = 8000;
+
+/// The maximum number of short_channel_ids we queue to be queried from a single peer. Any further
+/// short_channel_ids the peer tells us about are dropped, to be learned from another peer instead.
+const MAX_QUEUED_SHORT_CHANNEL_IDS_PER_PEER: usize = 100_000;
+
+/// Gets the block height in which the funding transaction of the given channel confirmed.
+fn block_from_scid(short_channel_id: u64) -> u32 {
+	(short_channel_id >> 40) as u32
+}
+
+/// Gets the lowest possible short_channel_id for a channel confirmed in the given block,
+/// saturating for blocks beyond the 3 bytes a short_channel_id can represent.
+fn scid_from_block(block: u64) -> u64 {
+	if block > 0xff_ffff { ::std::u64::MAX } else { block << 40 }
+}
+
+/// The age after which BOLT 7 allows a channel_update, and thus the direction of the channel
+/// it updates, to be considered stale.
+const STALE_CHANNEL_UPDATE_AGE_LIMIT_SECS: u64 = 60 * 60 * 24 * 14;
+
+/// The default number of confirmations a transaction spending a channel's funding output must
+/// have before we remove the channel from the network graph.
+const DEFAULT_FUNDING_SPEND_CONFIRMATIONS: u32 = 6;
+
+/// The total value of all bitcoin which will ever exist, in msat.
+const MAX_VALUE_MSAT: u64 = 21_000_000 * 1_0000_0000 * 1000;
+
+/// The funding outpoints of the channels we've checked against the chain, which we watch to
+/// learn when the channels close.
+#[derive(PartialEq)]
+struct FundingOutpoints {
+	/// The short_channel_id and funding output script of the channel at each funding outpoint.
+	channels: HashMap<(Txid, u32), (u64, Script)>,
+	/// The heights at which the funding outputs of channels were spent, for channels which we
+	/// haven't yet removed as the spend doesn't have enough confirmations.
+	spend_heights: HashMap<u64, u32>,
+}
+
+impl FundingOutpoints {
+	/// Forgets the funding outpoints of the channels for which is_removed returns true.
+	fn remove_channels<F: Fn(u64) -> bool>(&mut self, is_removed: F) {
+		self.channels.retain(|_, &mut (short_channel_id, _)| !is_removed(short_channel_id));
+		self.spend_heights.retain(|short_channel_id, _| !is_removed(*short_channel_id));
+	}
+}
+
+/// Gets the P2WSH script of the 2-of-2 multisig funding output of an announced channel.
+fn get_funding_script(msg: &msgs::UnsignedChannelAnnouncement) -> Script {
+	Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_2)
+	              .push_slice(&msg.bitcoin_key_1.serialize())
+	              .push_slice(&msg.bitcoin_key_2.serialize())
+	              .push_opcode(opcodes::all::OP_PUSHNUM_2)
+	              .push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script().to_v0_p2wsh()
+}
+
+/// The state of our gossip queries to a single peer. We only have one query_short_channel_ids
+/// outstanding with a peer at a time, queueing any further short_channel_ids until it has replied
+/// with a reply_short_channel_ids_end.
+struct PeerShortIdQueries {
+	/// The end of the block range of our outstanding query_channel_range, if any, which the
+	/// peer's reply_channel_range messages must cover before we consider the query answered.
+	channel_range_query_end: Option<u64>,
+	queued_short_channel_ids: Vec<u64>,
+	awaiting_reply: bool,
+}
+
+/// A single change applied to a NetworkGraph by a NetGraphMsgHandler, as appended to a
+/// NetworkUpdateLog.
+///
+/// Updates are serialized with a length prefix so that NetworkGraph::replay_update_log can read
+/// any number of them back to back and detect an update which was only partially written.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetworkGraphUpdate {
+	/// A node_announcement was applied to the graph.
+	NodeAnnouncement {
+		/// The node_announcement which was applied.
+		msg: msgs::NodeAnnouncement,
+	},
+	/// A channel_announcement was applied to the graph.
+	ChannelAnnouncement {
+		/// The channel_announcement which was applied.
+		msg: msgs::ChannelAnnouncement,
+		/// The value of the funding output of the channel, in satoshis, if it was checked against
+		/// the chain.
+		capacity_sats: Option<u64>,
+		/// The txid of the channel's funding transaction, if it was checked against the chain.
+		funding_txid: Option<Txid>,
+		/// The time, in seconds since the UNIX epoch, the channel_announcement was received at.
+		received_time_unix: u64,
+	},
+	/// A channel_update was applied to the graph.
+	ChannelUpdate {
+		/// The channel_update which was applied.
+		msg: msgs::ChannelUpdate,
+	},
+	/// A channel was closed or disabled following an HTLC failure.
+	ChannelClosed {
+		/// The short_channel_id of the closed channel.
+		short_channel_id: u64,
+		/// Whether the channel was removed from the graph, rather than only disabled.
+		is_permanent: bool,
+	},
+	/// Stale channels were removed from the graph.
+	StaleChannelsRemoved {
+		/// The time, in seconds since the UNIX epoch, the channels were considered stale at.
+		current_time_unix: u64,
+	},
+}
+
+impl Writeable for NetworkGraphUpdate {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		let mut contents = Vec::new();
+		match self {
+			&NetworkGraphUpdate::NodeAnnouncement { ref msg } => {
+				0u8.write(&mut contents)?;
+				msg.write(&mut contents)?;
+			},
+			&NetworkGraphUpdate::ChannelAnnouncement { ref msg, ref capacity_sats, ref funding_txid, ref received_time_unix } => {
+				// Type 1 is read for updates written before funding_txid and received_time_unix
+				// were added.
+				5u8.write(&mut contents)?;
+				// Messages read any excess data up to the end of the update, so must come last.
+				capacity_sats.write(&mut contents)?;
+				funding_txid.write(&mut contents)?;
+				received_time_unix.write(&mut contents)?;
+				msg.write(&mut contents)?;
+			},
+			&NetworkGraphUpdate::ChannelUpdate { ref msg } => {
+				2u8.write(&mut contents)?;
+				msg.write(&mut contents)?;
+			},
+			&NetworkGraphUpdate::ChannelClosed { ref short_channel_id, ref is_permanent } => {
+				3u8.write(&mut contents)?;
+				short_channel_id.write(&mut contents)?;
+				is_permanent.write(&mut contents)?;
+			},
+			&NetworkGraphUpdate::StaleChannelsRemoved { ref current_time_unix } => {
+				4u8.write(&mut contents)?;
+				current_time_unix.write(&mut contents)?;
+			},
+		}
+		(contents.len() as u32).write(writer)?;
+		writer.write_all(&contents)
+	}
+}
+
+impl Readable for NetworkGraphUpdate {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<NetworkGraphUpdate, DecodeError> {
+		let len: u32 = Readable::read(reader)?;
+		let mut contents = Vec::with_capacity(cmp::min(len as u64, MAX_ALLOC_SIZE) as usize);
+		reader.take(len as u64).read_to_end(&mut contents)?;
+		if contents.len() != len as usize {
+			return Err(DecodeError::ShortRead);
+		}
+		Self::read_contents(&mut ::std::io::Cursor::new(&contents[..]))
+	}
+}
+
+impl NetworkGraphUpdate {
+	fn read_contents<R: ::std::io::Read>(reader: &mut R) -> Result<NetworkGraphUpdate, DecodeError> {
+		let update_type: u8 = Readable::read(reader)?;
+		match update_type {
+			0 => Ok(NetworkGraphUpdate::NodeAnnouncement {
+				msg: Readable::read(reader)?,
+			}),
+			1 => {
+				let capacity_sats = Readable::read(reader)?;
+				Ok(NetworkGraphUpdate::ChannelAnnouncement {
+					msg: Readable::read(reader)?,
+					capacity_sats,
+					funding_txid: None,
+					// We don't know when the announcement was received, so let it be pruned as
+					// stale if no channel_update arrives.
+					received_time_unix: 0,
+				})
+			},
+			5 => {
+				let capacity_sats = Readable::read(reader)?;
+				let funding_txid = Readable::read(reader)?;
+				let received_time_unix = Readable::read(reader)?;
+				Ok(NetworkGraphUpdate::ChannelAnnouncement {
+					msg: Readable::read(reader)?,
+					capacity_sats,
+					funding_txid,
+					received_time_unix,
+				})
+			},
+			2 => Ok(NetworkGraphUpdate::ChannelUpdate {
+				msg: Readable::read(reader)?,
+			}),
+			3 => Ok(NetworkGraphUpdate::ChannelClosed {
+				short_channel_id: Readable::read(reader)?,
+				is_permanent: Readable::read(reader)?,
+			}),
+			4 => Ok(NetworkGraphUpdate::StaleChannelsRemoved {
+				current_time_unix: Readable::read(reader)?,
+			}),
+			_ => Err(DecodeError::InvalidValue),
+		}
+	}
+}
+
+/// An append-only log of the updates applied to a NetworkGraph, allowing the graph to be
+/// persisted without rewriting all of it every time it changes.
+///
+/// On startup, the latest snapshot should be read with NetworkGraph's Readable implementation,
+/// the updates appended since replayed with NetworkGraph::replay_update_log, and the result
+/// passed to NetGraphMsgHandler::from_net_graph_with_update_log.
+pub trait NetworkUpdateLog: Send + Sync {
+	/// Appends the given update (as serialized with its Writeable implementation) to the end of
+	/// the log. Called while the NetworkGraph is locked, so updates are appended in the order in
+	/// which they were applied.
+	fn append_update(&self, update: &NetworkGraphUpdate) -> Result<(), ::std::io::Error>;
+
+	/// Persists a snapshot of the full network graph (as serialized with its Writeable
+	/// implementation), after which all previously appended updates should be discarded. The
+	/// snapshot and truncated log should replace the previous ones atomically, as the graph
+	/// may otherwise fail to load on restart.
+	fn persist_snapshot(&self, network_graph: &NetworkGraph) -> Result<(), ::std::io::Error>;
+}
 
 /// Receives and validates network updates from peers,
 /// stores authentic and relevant data as a network graph.
 /// This network graph is then used for routing payments.
 /// Provides interface to help with initial routing sync by
 /// serving historical announcements.
+///
+/// Peers which support gossip_queries are synced with using query_channel_range and
+/// query_short_channel_ids, and are served the same queries from our network graph.
+///
+/// The funding outputs of channels which are checked against the chain are watched through the
+/// ChainWatchInterface, removing channels from the network graph once their funding output is
+/// spent. For this, NetGraphMsgHandler must be registered as a ChainListener.
 pub struct NetGraphMsgHandler<C: Deref, L: Deref> where C::Target: ChainWatchInterface, L::Target: Logger {
 	secp_ctx: Secp256k1<secp256k1::VerifyOnly>,
+	genesis_hash: BlockHash,
 	/// Representation of the payment channel network
 	pub network_graph: RwLock<NetworkGraph>,
 	chain_monitor: C,
 	full_syncs_requested: AtomicUsize,
+	short_id_queries: Mutex<HashMap<PublicKey, PeerShortIdQueries>>,
+	pending_events: Mutex<Vec<MessageSendEvent>>,
+	update_log: Option<Box<dyn NetworkUpdateLog>>,
+	funding_spend_confirmations: u32,
 	logger: L,
 }
 
@@ -43,30 +287,142 @@ impl<C: Deref, L: Deref> NetGraphMsgHandler<C, L> where C::Target: ChainWatchInt
 	/// Chain monitor is used to make sure announced channels exist on-chain,
 	/// channel data is correct, and that the announcement is signed with
 	/// channel owners' keys.
-	pub fn new(chain_monitor: C, logger: L) -> Self {
-		NetGraphMsgHandler {
-			secp_ctx: Secp256k1::verification_only(),
-			network_graph: RwLock::new(NetworkGraph {
-				channels: BTreeMap::new(),
-				nodes: BTreeMap::new(),
-			}),
-			full_syncs_requested: AtomicUsize::new(0),
-			chain_monitor,
-			logger,
-		}
+	/// The genesis hash identifies the chain for which we send and answer gossip queries.
+	pub fn new(genesis_hash: BlockHash, chain_monitor: C, logger: L) -> Self {
+		Self::from_net_graph(genesis_hash, chain_monitor, logger, NetworkGraph::new())
 	}
 
 	/// Creates a new tracker of the actual state of the network of channels and nodes,
 	/// assuming an existing Network Graph.
-	pub fn from_net_graph(chain_monitor: C, logger: L, network_graph: NetworkGraph) -> Self {
+	/// The funding outputs of the graph's channels which were checked against the chain are
+	/// watched again through the chain monitor.
+	pub fn from_net_graph(genesis_hash: BlockHash, chain_monitor: C, logger: L, network_graph: NetworkGraph) -> Self {
+		for (outpoint, &(_, ref script)) in network_graph.funding_outpoints.channels.iter() {
+			chain_monitor.install_watch_outpoint(*outpoint, script);
+		}
 		NetGraphMsgHandler {
 			secp_ctx: Secp256k1::verification_only(),
+			genesis_hash,
 			network_graph: RwLock::new(network_graph),
 			full_syncs_requested: AtomicUsize::new(0),
+			short_id_queries: Mutex::new(HashMap::new()),
+			pending_events: Mutex::new(Vec::new()),
+			update_log: None,
+			funding_spend_confirmations: DEFAULT_FUNDING_SPEND_CONFIRMATIONS,
 			chain_monitor,
 			logger,
 		}
 	}
+
+	/// Creates a new tracker of the actual state of the network of channels and nodes,
+	/// assuming an existing Network Graph, and appends every update applied to it to the given
+	/// update log.
+	/// The network graph should be the log's latest snapshot with any updates appended since
+	/// replayed into it, see NetworkUpdateLog.
+	pub fn from_net_graph_with_update_log(genesis_hash: BlockHash, chain_monitor: C, logger: L, network_graph: NetworkGraph, update_log: Box<dyn NetworkUpdateLog>) -> Self {
+		let mut net_graph_msg_handler = Self::from_net_graph(genesis_hash, chain_monitor, logger, network_graph);
+		net_graph_msg_handler.update_log = Some(update_log);
+		net_graph_msg_handler
+	}
+
+	/// Sets the number of confirmations a transaction spending a channel's funding output must
+	/// have before the channel is removed from the network graph. A value of 0 is treated as 1.
+	///
+	/// Default value: 6.
+	pub fn set_funding_spend_confirmations(&mut self, confirmations: u32) {
+		self.funding_spend_confirmations = cmp::max(confirmations, 1);
+	}
+
+	/// Persists a snapshot of the network graph to the update log, allowing it to discard the
+	/// updates appended so far. Should be called periodically (eg once an hour) to keep the log,
+	/// and thus the time it takes to load the graph on startup, from growing without bound.
+	/// Does nothing if this tracker was created without an update log.
+	pub fn compact_update_log(&self) -> Result<(), ::std::io::Error> {
+		if let Some(ref update_log) = self.update_log {
+			// Updates are only appended while holding the graph's write lock, so none may be
+			// appended between taking the snapshot and the log discarding previous updates.
+			let network_graph = self.network_graph.read().unwrap();
+			update_log.persist_snapshot(&network_graph)?;
+			log_trace!(self.logger, "Compacted network graph update log into a snapshot with {} channels", network_graph.channels.len());
+		}
+		Ok(())
+	}
+
+	/// Removes channels and nodes we haven't heard from in two weeks from the network graph, per
+	/// the BOLT 7 staleness rules, using the current system time.
+	/// Should be called regularly, eg once an hour, to avoid routing through channels which have
+	/// since closed.
+	pub fn remove_stale_channels(&self) {
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time must be > 1970").as_secs();
+		self.remove_stale_channels_with_time(now);
+	}
+
+	/// Removes channels and nodes we haven't heard from in two weeks from the network graph, per
+	/// the BOLT 7 staleness rules, as of the given time in seconds since the UNIX epoch.
+	///
+	/// A channel direction is stale once its latest channel_update is two weeks older than the
+	/// given time, and channels are removed once neither direction is left. Channels which never
+	/// received a channel_update are removed once their announcement was received two weeks
+	/// before the given time. Nodes are removed along with their last channel. Removed channels
+	/// may be re-learned from later announcements.
+	pub fn remove_stale_channels_with_time(&self, current_time_unix: u64) {
+		let mut network_graph = self.network_graph.write().unwrap();
+		let channels_before = network_graph.channels.len();
+		network_graph.remove_stale_channels_with_time(current_time_unix);
+		self.append_update(NetworkGraphUpdate::StaleChannelsRemoved { current_time_unix });
+		log_debug!(self.logger, "Removed {} stale channels from the network graph", channels_before - network_graph.channels.len());
+	}
+
+	/// Appends the given update to our update log, if any. Must be called while holding the
+	/// network graph's write lock, after the update was successfully applied.
+	fn append_update(&self, update: NetworkGraphUpdate) {
+		if let Some(ref update_log) = self.update_log {
+			if let Err(e) = update_log.append_update(&update) {
+				log_error!(self.logger, "Failed to append to the network graph update log: {}", e);
+			}
+		}
+	}
+
+	/// Queues the given short_channel_ids to be queried from the given peer, sending a
+	/// query_short_channel_ids immediately unless one is already outstanding.
+	fn query_short_channel_ids(&self, their_node_id: &PublicKey, mut short_channel_ids: Vec<u64>) {
+		let mut short_id_queries = self.short_id_queries.lock().unwrap();
+		let peer_queries = match short_id_queries.get_mut(their_node_id) {
+			Some(peer_queries) => peer_queries,
+			None => return,
+		};
+		let queue_space = MAX_QUEUED_SHORT_CHANNEL_IDS_PER_PEER - peer_queries.queued_short_channel_ids.len();
+		if short_channel_ids.len() > queue_space {
+			log_debug!(self.logger, "Dropping {} short_channel_ids from {} as too many are already queued to be queried",
+				short_channel_ids.len() - queue_space, log_pubkey!(their_node_id));
+			short_channel_ids.truncate(queue_space);
+		}
+		peer_queries.queued_short_channel_ids.append(&mut short_channel_ids);
+		if peer_queries.awaiting_reply || peer_queries.queued_short_channel_ids.is_empty() {
+			return;
+		}
+
+		let batch_len = cmp::min(peer_queries.queued_short_channel_ids.len(), MAX_SHORT_CHANNEL_IDS_PER_MESSAGE);
+		let batch: Vec<u64> = peer_queries.queued_short_channel_ids.drain(..batch_len).collect();
+		peer_queries.awaiting_reply = true;
+		log_trace!(self.logger, "Querying {} short_channel_ids from {}", batch.len(), log_pubkey!(their_node_id));
+		self.pending_events.lock().unwrap().push(MessageSendEvent::SendShortIdsQuery {
+			node_id: their_node_id.clone(),
+			msg: QueryShortChannelIds {
+				chain_hash: self.genesis_hash,
+				short_channel_ids: batch,
+			},
+		});
+	}
+}
+
+impl<C: Deref, L: Deref> MessageSendEventsProvider for NetGraphMsgHandler<C, L> where C::Target: ChainWatchInterface, L::Target: Logger {
+	fn get_and_clear_pending_msg_events(&self) -> Vec<MessageSendEvent> {
+		let mut ret = Vec::new();
+		let mut pending_events = self.pending_events.lock().unwrap();
+		::std::mem::swap(&mut ret, &mut pending_events);
+		ret
+	}
 }
 
 
@@ -81,7 +437,10 @@ macro_rules! secp_verify_sig {
 
 impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for NetGraphMsgHandler<C, L> where C::Target: ChainWatchInterface, L::Target: Logger {
 	fn handle_node_announcement(&self, msg: &msgs::NodeAnnouncement) -> Result<bool, LightningError> {
-		self.network_graph.write().unwrap().update_node_from_announcement(msg, Some(&self.secp_ctx))
+		let mut network_graph = self.network_graph.write().unwrap();
+		let should_relay = network_graph.update_node_from_announcement(msg, Some(&self.secp_ctx))?;
+		self.append_update(NetworkGraphUpdate::NodeAnnouncement { msg: msg.clone() });
+		Ok(should_relay)
 	}
 
 	fn handle_channel_announcement(&self, msg: &msgs::ChannelAnnouncement) -> Result<bool, LightningError> {
@@ -89,23 +448,16 @@ impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for N
 			return Err(LightningError{err: "Channel announcement node had a channel with itself", action: ErrorAction::IgnoreError});
 		}
 
-		let checked_utxo = match self.chain_monitor.get_chain_utxo(msg.contents.chain_hash, msg.contents.short_channel_id) {
-			Ok((script_pubkey, _value)) => {
-				let expected_script = Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_2)
-				                                    .push_slice(&msg.contents.bitcoin_key_1.serialize())
-				                                    .push_slice(&msg.contents.bitcoin_key_2.serialize())
-				                                    .push_opcode(opcodes::all::OP_PUSHNUM_2)
-				                                    .push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script().to_v0_p2wsh();
-				if script_pubkey != expected_script {
+		let funding_utxo = match self.chain_monitor.get_chain_utxo(msg.contents.chain_hash, msg.contents.short_channel_id) {
+			Ok((txid, script_pubkey, value)) => {
+				if script_pubkey != get_funding_script(&msg.contents) {
 					return Err(LightningError{err: "Channel announcement keys didn't match on-chain script", action: ErrorAction::IgnoreError});
 				}
-				//TODO: Check if value is worth storing, use it to inform routing, and compare it
-				//to the new HTLC max field in channel_update
-				true
+				Some((txid, script_pubkey, value))
 			},
 			Err(ChainError::NotSupported) => {
 				// Tentatively accept, potentially exposing us to DoS attacks
-				false
+				None
 			},
 			Err(ChainError::NotWatched) => {
 				return Err(LightningError{err: "Channel announced on an unknown chain", action: ErrorAction::IgnoreError});
@@ -114,7 +466,18 @@ impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for N
 				return Err(LightningError{err: "Channel announced without corresponding UTXO entry", action: ErrorAction::IgnoreError});
 			},
 		};
-		let result = self.network_graph.write().unwrap().update_channel_from_announcement(msg, checked_utxo, Some(&self.secp_ctx));
+		let capacity_sats = funding_utxo.as_ref().map(|&(_, _, value)| value);
+		let funding_txid = funding_utxo.as_ref().map(|&(txid, _, _)| txid);
+		let received_time_unix = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time must be > 1970").as_secs();
+		let mut network_graph = self.network_graph.write().unwrap();
+		let result = network_graph.update_channel_from_announcement(msg, capacity_sats, funding_txid, received_time_unix, Some(&self.secp_ctx));
+		if result.is_ok() {
+			// Only watch the funding output once the announcement's signatures were checked.
+			if let Some((txid, script_pubkey, _)) = funding_utxo {
+				self.chain_monitor.install_watch_outpoint((txid, (msg.contents.short_channel_id & 0xffff) as u32), &script_pubkey);
+			}
+			self.append_update(NetworkGraphUpdate::ChannelAnnouncement { msg: msg.clone(), capacity_sats, funding_txid, received_time_unix });
+		}
 		log_trace!(self.logger, "Added channel_announcement for {}{}", msg.contents.short_channel_id, if !msg.contents.excess_data.is_empty() { " with excess uninterpreted data!" } else { "" });
 		result
 	}
@@ -122,10 +485,12 @@ impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for N
 	fn handle_htlc_fail_channel_update(&self, update: &msgs::HTLCFailChannelUpdate) {
 		match update {
 			&msgs::HTLCFailChannelUpdate::ChannelUpdateMessage { ref msg } => {
-				let _ = self.network_graph.write().unwrap().update_channel(msg, Some(&self.secp_ctx));
+				let _ = self.handle_channel_update(msg);
 			},
 			&msgs::HTLCFailChannelUpdate::ChannelClosed { short_channel_id, is_permanent } => {
-				self.network_graph.write().unwrap().close_channel_from_update(short_channel_id, is_permanent);
+				let mut network_graph = self.network_graph.write().unwrap();
+				network_graph.close_channel_from_update(short_channel_id, is_permanent);
+				self.append_update(NetworkGraphUpdate::ChannelClosed { short_channel_id, is_permanent });
 			},
 			&msgs::HTLCFailChannelUpdate::NodeFailure { ref node_id, is_permanent } => {
 				self.network_graph.write().unwrap().fail_node(node_id, is_permanent);
@@ -134,7 +499,10 @@ impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for N
 	}
 
 	fn handle_channel_update(&self, msg: &msgs::ChannelUpdate) -> Result<bool, LightningError> {
-		self.network_graph.write().unwrap().update_channel(msg, Some(&self.secp_ctx))
+		let mut network_graph = self.network_graph.write().unwrap();
+		let should_relay = network_graph.update_channel(msg, Some(&self.secp_ctx))?;
+		self.append_update(NetworkGraphUpdate::ChannelUpdate { msg: msg.clone() });
+		Ok(should_relay)
 	}
 
 	fn get_next_channel_announcements(&self, starting_point: u64, batch_amount: u8) -> Vec<(msgs::ChannelAnnouncement, Option<msgs::ChannelUpdate>, Option<msgs::ChannelUpdate>)> {
@@ -199,6 +567,256 @@ impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for N
 			false
 		}
 	}
+
+	fn sync_routing_table(&self, their_node_id: &PublicKey, init: &msgs::Init) {
+		if !init.features.supports_gossip_queries() {
+			return;
+		}
+		let mut short_id_queries = self.short_id_queries.lock().unwrap();
+		short_id_queries.insert(their_node_id.clone(), PeerShortIdQueries {
+			channel_range_query_end: None,
+			queued_short_channel_ids: Vec::new(),
+			awaiting_reply: false,
+		});
+
+		let mut pending_events = self.pending_events.lock().unwrap();
+		// Peers which negotiated gossip_queries don't relay any gossip to us until we set a filter.
+		// Historical gossip is fetched with queries below, so we only ask for new gossip.
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time must be > 1970").as_secs();
+		pending_events.push(MessageSendEvent::SendGossipTimestampFilter {
+			node_id: their_node_id.clone(),
+			msg: GossipTimestampFilter {
+				chain_hash: self.genesis_hash,
+				first_timestamp: now as u32,
+				timestamp_range: 0xffff_ffff,
+			},
+		});
+
+		if !self.should_request_full_sync(their_node_id) {
+			return;
+		}
+		log_debug!(self.logger, "Querying all channels from {} to sync our routing table", log_pubkey!(their_node_id));
+		short_id_queries.get_mut(their_node_id).unwrap().channel_range_query_end = Some(0xffff_ffff);
+		pending_events.push(MessageSendEvent::SendChannelRangeQuery {
+			node_id: their_node_id.clone(),
+			msg: QueryChannelRange {
+				chain_hash: self.genesis_hash,
+				first_blocknum: 0,
+				number_of_blocks: 0xffff_ffff,
+			},
+		});
+	}
+
+	fn peer_disconnected(&self, their_node_id: &PublicKey) {
+		// Any queries still outstanding will never be answered, so drop them.
+		self.short_id_queries.lock().unwrap().remove(their_node_id);
+	}
+
+	fn handle_reply_channel_range(&self, their_node_id: &PublicKey, msg: &ReplyChannelRange) -> Result<(), LightningError> {
+		if msg.chain_hash != self.genesis_hash {
+			return Err(LightningError{err: "Received reply_channel_range for an unknown chain", action: ErrorAction::IgnoreError});
+		}
+		{
+			let mut short_id_queries = self.short_id_queries.lock().unwrap();
+			let peer_queries = match short_id_queries.get_mut(their_node_id) {
+				Some(peer_queries) => peer_queries,
+				None => return Err(LightningError{err: "Received reply_channel_range without an outstanding query", action: ErrorAction::IgnoreError}),
+			};
+			match peer_queries.channel_range_query_end {
+				Some(query_end) => {
+					// The final reply is the one covering the end of our queried range.
+					if msg.first_blocknum as u64 + msg.number_of_blocks as u64 >= query_end {
+						peer_queries.channel_range_query_end = None;
+					}
+				},
+				None => return Err(LightningError{err: "Received reply_channel_range without an outstanding query", action: ErrorAction::IgnoreError}),
+			}
+		}
+		if !msg.sync_complete {
+			return Err(LightningError{err: "Received reply_channel_range from a peer without up-to-date channel information", action: ErrorAction::IgnoreError});
+		}
+
+		let unknown_short_channel_ids: Vec<u64> = {
+			let network_graph = self.network_graph.read().unwrap();
+			msg.short_channel_ids.iter().filter(|scid| !network_graph.channels.contains_key(scid)).cloned().collect()
+		};
+		log_trace!(self.logger, "Received reply_channel_range from {} with {} short_channel_ids, {} of which are unknown",
+			log_pubkey!(their_node_id), msg.short_channel_ids.len(), unknown_short_channel_ids.len());
+		self.query_short_channel_ids(their_node_id, unknown_short_channel_ids);
+		Ok(())
+	}
+
+	fn handle_reply_short_channel_ids_end(&self, their_node_id: &PublicKey, msg: &ReplyShortChannelIdsEnd) -> Result<(), LightningError> {
+		{
+			let mut short_id_queries = self.short_id_queries.lock().unwrap();
+			match short_id_queries.get_mut(their_node_id) {
+				Some(ref mut peer_queries) if peer_queries.awaiting_reply => peer_queries.awaiting_reply = false,
+				_ => return Err(LightningError{err: "Received reply_short_channel_ids_end without an outstanding query", action: ErrorAction::IgnoreError}),
+			}
+		}
+		if msg.chain_hash != self.genesis_hash || !msg.full_information {
+			self.short_id_queries.lock().unwrap().remove(their_node_id);
+			return Err(LightningError{err: "Received reply_short_channel_ids_end from a peer without up-to-date channel information", action: ErrorAction::IgnoreError});
+		}
+		// Send the next batch of queued short_channel_ids, if any.
+		self.query_short_channel_ids(their_node_id, Vec::new());
+		Ok(())
+	}
+
+	fn handle_query_channel_range(&self, their_node_id: &PublicKey, msg: &QueryChannelRange) -> Result<(), LightningError> {
+		// Replies' number_of_blocks are u32s, so we can't cover a range ending beyond u32::MAX.
+		let query_end = cmp::min(msg.first_blocknum as u64 + msg.number_of_blocks as u64, ::std::u32::MAX as u64);
+		if msg.chain_hash != self.genesis_hash {
+			self.pending_events.lock().unwrap().push(MessageSendEvent::SendReplyChannelRange {
+				node_id: their_node_id.clone(),
+				msg: ReplyChannelRange {
+					chain_hash: msg.chain_hash,
+					first_blocknum: msg.first_blocknum,
+					number_of_blocks: msg.number_of_blocks,
+					sync_complete: false,
+					short_channel_ids: Vec::new(),
+				},
+			});
+			return Err(LightningError{err: "Received query_channel_range for an unknown chain", action: ErrorAction::IgnoreError});
+		}
+
+		// Replies must together cover the queried range, and may not split the channels of a
+		// single block across replies, so each batch ends at the block boundary after it fills.
+		// Only if a single block has more channels than fit in one message do we split it.
+		let mut replies = Vec::new();
+		let network_graph = self.network_graph.read().unwrap();
+		let mut batch_first_blocknum = msg.first_blocknum;
+		let mut batch = Vec::new();
+		let mut prev_block = None;
+		for (short_channel_id, _) in network_graph.channels.range(scid_from_block(msg.first_blocknum as u64)..scid_from_block(query_end)) {
+			let block = block_from_scid(*short_channel_id);
+			if (batch.len() >= MAX_SHORT_CHANNEL_IDS_PER_MESSAGE && prev_block != Some(block)) || batch.len() >= msgs::MAX_ENCODED_SHORT_CHANNEL_IDS {
+				replies.push(MessageSendEvent::SendReplyChannelRange {
+					node_id: their_node_id.clone(),
+					msg: ReplyChannelRange {
+						chain_hash: self.genesis_hash,
+						first_blocknum: batch_first_blocknum,
+						number_of_blocks: block - batch_first_blocknum,
+						sync_complete: true,
+						short_channel_ids: batch,
+					},
+				});
+				batch_first_blocknum = block;
+				batch = Vec::new();
+			}
+			batch.push(*short_channel_id);
+			prev_block = Some(block);
+		}
+		// pending_events is only ever taken after releasing the network graph, never while holding it.
+		mem::drop(network_graph);
+		log_trace!(self.logger, "Replying to query_channel_range from {} for blocks {} through {}", log_pubkey!(their_node_id), msg.first_blocknum, query_end);
+		replies.push(MessageSendEvent::SendReplyChannelRange {
+			node_id: their_node_id.clone(),
+			msg: ReplyChannelRange {
+				chain_hash: self.genesis_hash,
+				first_blocknum: batch_first_blocknum,
+				number_of_blocks: (query_end - batch_first_blocknum as u64) as u32,
+				sync_complete: true,
+				short_channel_ids: batch,
+			},
+		});
+		self.pending_events.lock().unwrap().append(&mut replies);
+		Ok(())
+	}
+
+	fn handle_query_short_channel_ids(&self, their_node_id: &PublicKey, msg: &QueryShortChannelIds) -> Result<(), LightningError> {
+		if msg.chain_hash != self.genesis_hash {
+			self.pending_events.lock().unwrap().push(MessageSendEvent::SendShortIdsReply {
+				node_id: their_node_id.clone(),
+				channel_announcements: Vec::new(),
+				node_announcements: Vec::new(),
+				msg: ReplyShortChannelIdsEnd {
+					chain_hash: msg.chain_hash,
+					full_information: false,
+				},
+			});
+			return Err(LightningError{err: "Received query_short_channel_ids for an unknown chain", action: ErrorAction::IgnoreError});
+		}
+
+		let network_graph = self.network_graph.read().unwrap();
+		let mut channel_announcements = Vec::new();
+		let mut node_announcements = Vec::new();
+		let mut nodes_announced = HashSet::new();
+		for short_channel_id in msg.short_channel_ids.iter() {
+			let chan = match network_graph.channels.get(short_channel_id) {
+				Some(chan) => chan,
+				None => continue,
+			};
+			let chan_announcement = match chan.announcement_message {
+				Some(ref announcement) => announcement.clone(),
+				None => continue,
+			};
+			let one_to_two_update = chan.one_to_two.as_ref().and_then(|one_to_two| one_to_two.last_update_message.clone());
+			let two_to_one_update = chan.two_to_one.as_ref().and_then(|two_to_one| two_to_one.last_update_message.clone());
+			channel_announcements.push((chan_announcement, one_to_two_update, two_to_one_update));
+
+			for node_id in [chan.node_one, chan.node_two].iter() {
+				if !nodes_announced.insert(*node_id) {
+					continue;
+				}
+				if let Some(node_announcement) = network_graph.nodes.get(node_id)
+						.and_then(|node| node.announcement_info.as_ref())
+						.and_then(|node_info| node_info.announcement_message.clone()) {
+					node_announcements.push(node_announcement);
+				}
+			}
+		}
+		mem::drop(network_graph);
+		log_trace!(self.logger, "Replying to query_short_channel_ids from {} with {} of {} channels", log_pubkey!(their_node_id), channel_announcements.len(), msg.short_channel_ids.len());
+		self.pending_events.lock().unwrap().push(MessageSendEvent::SendShortIdsReply {
+			node_id: their_node_id.clone(),
+			channel_announcements,
+			node_announcements,
+			msg: ReplyShortChannelIdsEnd {
+				chain_hash: self.genesis_hash,
+				full_information: true,
+			},
+		});
+		Ok(())
+	}
+}
+
+impl<C: Deref + Sync + Send, L: Deref + Sync + Send> ChainListener for NetGraphMsgHandler<C, L> where C::Target: ChainWatchInterface, L::Target: Logger {
+	fn block_connected(&self, _header: &BlockHeader, height: u32, txn_matched: &[&Transaction], _indexes_of_txn_matched: &[usize]) {
+		let mut network_graph = self.network_graph.write().unwrap();
+		let mut closed_channels = Vec::new();
+		{
+			let funding_outpoints = &mut network_graph.funding_outpoints;
+			for tx in txn_matched {
+				for input in tx.input.iter() {
+					let outpoint = (input.previous_output.txid, input.previous_output.vout);
+					if let Some(&(short_channel_id, _)) = funding_outpoints.channels.get(&outpoint) {
+						// Blocks may be re-scanned, so keep the height of the first spend we see.
+						funding_outpoints.spend_heights.entry(short_channel_id).or_insert(height);
+					}
+				}
+			}
+
+			let confirmations = self.funding_spend_confirmations;
+			for (short_channel_id, spend_height) in funding_outpoints.spend_heights.iter() {
+				if height + 1 >= *spend_height + confirmations {
+					closed_channels.push(*short_channel_id);
+				}
+			}
+		}
+
+		// Closing the channels also forgets their funding outpoints.
+		for short_channel_id in closed_channels {
+			log_debug!(self.logger, "Removing channel {} from the network graph as its funding output was spent", short_channel_id);
+			network_graph.close_channel_from_update(short_channel_id, true);
+			self.append_update(NetworkGraphUpdate::ChannelClosed { short_channel_id, is_permanent: true });
+		}
+	}
+
+	fn block_disconnected(&self, _header: &BlockHeader, disconnected_height: u32) {
+		// Spends in the disconnected block may not confirm again, so forget them.
+		self.network_graph.write().unwrap().funding_outpoints.spend_heights.retain(|_, spend_height| *spend_height < disconnected_height);
+	}
 }
 
 #[derive(PartialEq, Debug)]
@@ -214,6 +832,9 @@ pub struct DirectionalChannelInfo {
 	pub cltv_expiry_delta: u16,
 	/// The minimum value, which must be relayed to the next hop via the channel
 	pub htlc_minimum_msat: u64,
+	/// The maximum value which may be relayed to the next hop via the channel, if the update
+	/// announced one.
+	pub htlc_maximum_msat: Option<u64>,
 	/// Fees charged when the channel is used for routing
 	pub fees: RoutingFees,
 	/// Most recent update for the channel received from the network
@@ -225,7 +846,7 @@ pub struct DirectionalChannelInfo {
 
 impl fmt::Display for DirectionalChannelInfo {
 	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-		write!(f, "last_update {}, enabled {}, cltv_expiry_delta {}, htlc_minimum_msat {}, fees {:?}", self.last_update, self.enabled, self.cltv_expiry_delta, self.htlc_minimum_msat, self.fees)?;
+		write!(f, "last_update {}, enabled {}, cltv_expiry_delta {}, htlc_minimum_msat {}, htlc_maximum_msat {:?}, fees {:?}", self.last_update, self.enabled, self.cltv_expiry_delta, self.htlc_minimum_msat, self.htlc_maximum_msat, self.fees)?;
 		Ok(())
 	}
 }
@@ -235,10 +856,30 @@ impl_writeable!(DirectionalChannelInfo, 0, {
 	enabled,
 	cltv_expiry_delta,
 	htlc_minimum_msat,
+	htlc_maximum_msat,
 	fees,
 	last_update_message
 });
 
+/// The argument is the serialization version of the NetworkGraph containing the
+/// DirectionalChannelInfo.
+impl ReadableArgs<u8> for DirectionalChannelInfo {
+	fn read<R: ::std::io::Read>(reader: &mut R, ver: u8) -> Result<DirectionalChannelInfo, DecodeError> {
+		if ver >= 1 {
+			return Readable::read(reader);
+		}
+		Ok(DirectionalChannelInfo {
+			last_update: Readable::read(reader)?,
+			enabled: Readable::read(reader)?,
+			cltv_expiry_delta: Readable::read(reader)?,
+			htlc_minimum_msat: Readable::read(reader)?,
+			htlc_maximum_msat: None,
+			fees: Readable::read(reader)?,
+			last_update_message: Readable::read(reader)?,
+		})
+	}
+}
+
 #[derive(PartialEq)]
 /// Details about a channel (both directions).
 /// Received within a channel announcement.
@@ -253,6 +894,12 @@ pub struct ChannelInfo {
 	pub node_two: PublicKey,
 	/// Details about the second direction of a channel
 	pub two_to_one: Option<DirectionalChannelInfo>,
+	/// The value of the channel's funding output, in satoshis, if it was looked up on-chain when
+	/// the channel was announced.
+	pub capacity_sats: Option<u64>,
+	/// The time, in seconds since the UNIX epoch, we received the channel's announcement at.
+	/// Used to prune channels which never received a channel_update.
+	pub announcement_received_time: u64,
 	/// An initial announcement of the channel
 	/// Mostly redundant with the data we store in fields explicitly.
 	/// Everything else is useful only for sending out for initial routing sync.
@@ -262,8 +909,8 @@ pub struct ChannelInfo {
 
 impl fmt::Display for ChannelInfo {
 	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-		write!(f, "features: {}, node_one: {}, one_to_two: {:?}, node_two: {}, two_to_one: {:?}",
-		   log_bytes!(self.features.encode()), log_pubkey!(self.node_one), self.one_to_two, log_pubkey!(self.node_two), self.two_to_one)?;
+		write!(f, "features: {}, node_one: {}, one_to_two: {:?}, node_two: {}, two_to_one: {:?}, capacity_sats: {:?}",
+		   log_bytes!(self.features.encode()), log_pubkey!(self.node_one), self.one_to_two, log_pubkey!(self.node_two), self.two_to_one, self.capacity_sats)?;
 		Ok(())
 	}
 }
@@ -274,9 +921,32 @@ impl_writeable!(ChannelInfo, 0, {
 	one_to_two,
 	node_two,
 	two_to_one,
+	capacity_sats,
+	announcement_received_time,
 	announcement_message
 });
 
+/// The argument is the serialization version of the NetworkGraph containing the ChannelInfo.
+impl ReadableArgs<u8> for ChannelInfo {
+	fn read<R: ::std::io::Read>(reader: &mut R, ver: u8) -> Result<ChannelInfo, DecodeError> {
+		if ver >= 1 {
+			return Readable::read(reader);
+		}
+		Ok(ChannelInfo {
+			features: Readable::read(reader)?,
+			node_one: Readable::read(reader)?,
+			one_to_two: ReadableArgs::read(reader, ver)?,
+			node_two: Readable::read(reader)?,
+			two_to_one: ReadableArgs::read(reader, ver)?,
+			capacity_sats: None,
+			// We don't know when the announcement was received, so let the channel be pruned as
+			// stale if it has no channel_updates.
+			announcement_received_time: 0,
+			announcement_message: Readable::read(reader)?,
+		})
+	}
+}
+
 
 /// Fees for routing via a given channel or a node
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
@@ -432,10 +1102,17 @@ impl Readable for NodeInfo {
 pub struct NetworkGraph {
 	channels: BTreeMap<u64, ChannelInfo>,
 	nodes: BTreeMap<PublicKey, NodeInfo>,
+	funding_outpoints: FundingOutpoints,
 }
 
+const SERIALIZATION_VERSION: u8 = 1;
+const MIN_SERIALIZATION_VERSION: u8 = 1;
+
 impl Writeable for NetworkGraph {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		writer.write_all(&[SERIALIZATION_VERSION; 1])?;
+		writer.write_all(&[MIN_SERIALIZATION_VERSION; 1])?;
+
 		(self.channels.len() as u64).write(writer)?;
 		for (ref chan_id, ref chan_info) in self.channels.iter() {
 			(*chan_id).write(writer)?;
@@ -446,17 +1123,49 @@ impl Writeable for NetworkGraph {
 			node_id.write(writer)?;
 			node_info.write(writer)?;
 		}
+		(self.funding_outpoints.channels.len() as u64).write(writer)?;
+		for (&(txid, vout), &(short_channel_id, ref script)) in self.funding_outpoints.channels.iter() {
+			txid.write(writer)?;
+			vout.write(writer)?;
+			short_channel_id.write(writer)?;
+			script.write(writer)?;
+		}
+		(self.funding_outpoints.spend_heights.len() as u64).write(writer)?;
+		for (ref short_channel_id, ref spend_height) in self.funding_outpoints.spend_heights.iter() {
+			short_channel_id.write(writer)?;
+			spend_height.write(writer)?;
+		}
 		Ok(())
 	}
 }
 
+/// Graphs written before NetworkGraph serialization was versioned must instead be read with
+/// NetworkGraph::read_unversioned.
 impl Readable for NetworkGraph {
 	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<NetworkGraph, DecodeError> {
+		let ver: u8 = Readable::read(reader)?;
+		let min_ver: u8 = Readable::read(reader)?;
+		if min_ver > SERIALIZATION_VERSION {
+			return Err(DecodeError::UnknownVersion);
+		}
+		NetworkGraph::read_fields(reader, ver)
+	}
+}
+
+impl NetworkGraph {
+	/// Reads a NetworkGraph written before its serialization was versioned, ie by version 0.0.11
+	/// or earlier, filling in what it didn't track with defaults. Graphs written since must be
+	/// read with NetworkGraph's Readable implementation.
+	pub fn read_unversioned<R: ::std::io::Read>(reader: &mut R) -> Result<NetworkGraph, DecodeError> {
+		NetworkGraph::read_fields(reader, 0)
+	}
+
+	fn read_fields<R: ::std::io::Read>(reader: &mut R, ver: u8) -> Result<NetworkGraph, DecodeError> {
 		let channels_count: u64 = Readable::read(reader)?;
 		let mut channels = BTreeMap::new();
 		for _ in 0..channels_count {
 			let chan_id: u64 = Readable::read(reader)?;
-			let chan_info = Readable::read(reader)?;
+			let chan_info = ReadableArgs::read(reader, ver)?;
 			channels.insert(chan_id, chan_info);
 		}
 		let nodes_count: u64 = Readable::read(reader)?;
@@ -466,9 +1175,33 @@ impl Readable for NetworkGraph {
 			let node_info = Readable::read(reader)?;
 			nodes.insert(node_id, node_info);
 		}
+		let mut funding_outpoint_channels = HashMap::new();
+		let mut spend_heights = HashMap::new();
+		// Unversioned graphs didn't watch funding outpoints, so their channels are only removed once
+		// they go stale.
+		if ver >= 1 {
+			let funding_outpoints_count: u64 = Readable::read(reader)?;
+			for _ in 0..funding_outpoints_count {
+				let txid: Txid = Readable::read(reader)?;
+				let vout: u32 = Readable::read(reader)?;
+				let short_channel_id: u64 = Readable::read(reader)?;
+				let script: Script = Readable::read(reader)?;
+				funding_outpoint_channels.insert((txid, vout), (short_channel_id, script));
+			}
+			let spend_heights_count: u64 = Readable::read(reader)?;
+			for _ in 0..spend_heights_count {
+				let short_channel_id: u64 = Readable::read(reader)?;
+				let spend_height: u32 = Readable::read(reader)?;
+				spend_heights.insert(short_channel_id, spend_height);
+			}
+		}
 		Ok(NetworkGraph {
 			channels,
 			nodes,
+			funding_outpoints: FundingOutpoints {
+				channels: funding_outpoint_channels,
+				spend_heights,
+			},
 		})
 	}
 }
@@ -510,6 +1243,54 @@ impl NetworkGraph {
 		Self {
 			channels: BTreeMap::new(),
 			nodes: BTreeMap::new(),
+			funding_outpoints: FundingOutpoints {
+				channels: HashMap::new(),
+				spend_heights: HashMap::new(),
+			},
+		}
+	}
+
+	/// Applies the updates read from a NetworkUpdateLog to this graph, which should be the log's
+	/// latest snapshot.
+	/// Signatures are not re-checked, as updates are only logged once verified. Updates which no
+	/// longer apply (eg as they were already included in the snapshot) are skipped, as is a
+	/// final update which was only partially written, eg due to a crash while appending it.
+	pub fn replay_update_log<R: ::std::io::Read>(&mut self, update_log: &mut R) -> Result<(), DecodeError> {
+		loop {
+			let mut len_bytes = [0; 4];
+			let mut len_read = 0;
+			while len_read < len_bytes.len() {
+				match update_log.read(&mut len_bytes[len_read..]) {
+					Ok(0) => return Ok(()),
+					Ok(read) => len_read += read,
+					Err(ref e) if e.kind() == ::std::io::ErrorKind::Interrupted => {},
+					Err(e) => return Err(DecodeError::Io(e)),
+				}
+			}
+			let len = byte_utils::slice_to_be32(&len_bytes);
+			let mut contents = Vec::with_capacity(cmp::min(len as u64, MAX_ALLOC_SIZE) as usize);
+			update_log.take(len as u64).read_to_end(&mut contents)?;
+			if contents.len() != len as usize {
+				return Ok(());
+			}
+			let update = NetworkGraphUpdate::read_contents(&mut ::std::io::Cursor::new(&contents[..]))?;
+			let _ = self.apply_update(&update);
+		}
+	}
+
+	fn apply_update(&mut self, update: &NetworkGraphUpdate) -> Result<bool, LightningError> {
+		match update {
+			&NetworkGraphUpdate::NodeAnnouncement { ref msg } => self.update_node_from_announcement(msg, None),
+			&NetworkGraphUpdate::ChannelAnnouncement { ref msg, capacity_sats, funding_txid, received_time_unix } => self.update_channel_from_announcement(msg, capacity_sats, funding_txid, received_time_unix, None),
+			&NetworkGraphUpdate::ChannelUpdate { ref msg } => self.update_channel(msg, None),
+			&NetworkGraphUpdate::ChannelClosed { short_channel_id, is_permanent } => {
+				self.close_channel_from_update(short_channel_id, is_permanent);
+				Ok(true)
+			},
+			&NetworkGraphUpdate::StaleChannelsRemoved { current_time_unix } => {
+				self.remove_stale_channels_with_time(current_time_unix);
+				Ok(true)
+			},
 		}
 	}
 
@@ -549,9 +1330,11 @@ impl NetworkGraph {
 	/// Also store nodes (if not stored yet) the channel is between, and make node aware of this channel.
 	/// Checking utxo on-chain is useful if we receive an update for already known channel id,
 	/// which is probably result of a reorg. In that case, we update channel info only if the
-	/// utxo was checked, otherwise stick to the existing update, to prevent DoS risks.
+	/// utxo was checked (ie capacity_sats is Some), otherwise stick to the existing update, to
+	/// prevent DoS risks.
+	/// The channel's funding outpoint is recorded if its funding txid is provided.
 	/// Announcement signatures are checked here only if Secp256k1 object is provided.
-	fn update_channel_from_announcement(&mut self, msg: &msgs::ChannelAnnouncement, checked_utxo: bool, secp_ctx: Option<&Secp256k1<secp256k1::VerifyOnly>>) -> Result<bool, LightningError> {
+	fn update_channel_from_announcement(&mut self, msg: &msgs::ChannelAnnouncement, capacity_sats: Option<u64>, funding_txid: Option<Txid>, received_time_unix: u64, secp_ctx: Option<&Secp256k1<secp256k1::VerifyOnly>>) -> Result<bool, LightningError> {
 		if let Some(sig_verifier) = secp_ctx {
 			let msg_hash = hash_to_message!(&Sha256dHash::hash(&msg.contents.encode()[..])[..]);
 			secp_verify_sig!(sig_verifier, &msg_hash, &msg.node_signature_1, &msg.contents.node_id_1);
@@ -568,6 +1351,8 @@ impl NetworkGraph {
 				one_to_two: None,
 				node_two: msg.contents.node_id_2.clone(),
 				two_to_one: None,
+				capacity_sats,
+				announcement_received_time: received_time_unix,
 				announcement_message: if should_relay { Some(msg.clone()) } else { None },
 			};
 
@@ -576,7 +1361,7 @@ impl NetworkGraph {
 				//TODO: because asking the blockchain if short_channel_id is valid is only optional
 				//in the blockchain API, we need to handle it smartly here, though it's unclear
 				//exactly how...
-				if checked_utxo {
+				if capacity_sats.is_some() {
 					// Either our UTXO provider is busted, there was a reorg, or the UTXO provider
 					// only sometimes returns results. In any case remove the previous entry. Note
 					// that the spec expects us to "blacklist" the node_ids involved, but we can't
@@ -586,6 +1371,7 @@ impl NetworkGraph {
 					//    get reorg'd out.
 					// c) it's unclear how to do so without exposing ourselves to massive DoS risk.
 					Self::remove_channel_in_nodes(&mut self.nodes, &entry.get(), msg.contents.short_channel_id);
+					self.funding_outpoints.remove_channels(|short_channel_id| short_channel_id == msg.contents.short_channel_id);
 					*entry.get_mut() = chan_info;
 				} else {
 					return Err(LightningError{err: "Already have knowledge of channel", action: ErrorAction::IgnoreError})
@@ -616,6 +1402,11 @@ impl NetworkGraph {
 		add_channel_to_node!(msg.contents.node_id_1);
 		add_channel_to_node!(msg.contents.node_id_2);
 
+		if let Some(funding_txid) = funding_txid {
+			let outpoint = (funding_txid, (msg.contents.short_channel_id & 0xffff) as u32);
+			self.funding_outpoints.channels.insert(outpoint, (msg.contents.short_channel_id, get_funding_script(&msg.contents)));
+		}
+
 		Ok(should_relay)
 	}
 
@@ -628,6 +1419,7 @@ impl NetworkGraph {
 			if let Some(chan) = self.channels.remove(&short_channel_id) {
 				Self::remove_channel_in_nodes(&mut self.nodes, &chan, short_channel_id);
 			}
+			self.funding_outpoints.remove_channels(|chan_id| chan_id == short_channel_id);
 		} else {
 			if let Some(chan) = self.channels.get_mut(&short_channel_id) {
 				if let Some(one_to_two) = chan.one_to_two.as_mut() {
@@ -658,6 +1450,21 @@ impl NetworkGraph {
 		match self.channels.get_mut(&msg.contents.short_channel_id) {
 			None => return Err(LightningError{err: "Couldn't find channel for update", action: ErrorAction::IgnoreError}),
 			Some(channel) => {
+				let htlc_maximum_msat = match msg.contents.htlc_maximum_msat {
+					OptionalField::Present(htlc_maximum_msat) => {
+						if htlc_maximum_msat > MAX_VALUE_MSAT {
+							return Err(LightningError{err: "htlc_maximum_msat is larger than maximum possible msats", action: ErrorAction::IgnoreError});
+						}
+						if let Some(capacity_sats) = channel.capacity_sats {
+							if htlc_maximum_msat > capacity_sats.saturating_mul(1000) {
+								return Err(LightningError{err: "htlc_maximum_msat is larger than channel capacity", action: ErrorAction::IgnoreError});
+							}
+						}
+						Some(htlc_maximum_msat)
+					},
+					OptionalField::Absent => None,
+				};
+
 				macro_rules! maybe_update_channel_info {
 					( $target: expr, $src_node: expr) => {
 						if let Some(existing_chan_info) = $target.as_ref() {
@@ -680,6 +1487,7 @@ impl NetworkGraph {
 							last_update: msg.contents.timestamp,
 							cltv_expiry_delta: msg.contents.cltv_expiry_delta,
 							htlc_minimum_msat: msg.contents.htlc_minimum_msat,
+							htlc_maximum_msat,
 							fees: RoutingFees {
 								base_msat: msg.contents.fee_base_msat,
 								proportional_millionths: msg.contents.fee_proportional_millionths,
@@ -720,31 +1528,76 @@ impl NetworkGraph {
 				proportional_millionths
 			});
 		} else if chan_was_enabled {
-			let node = self.nodes.get_mut(&dest_node_id).unwrap();
-			let mut lowest_inbound_channel_fees = None;
+			self.recalculate_lowest_inbound_channel_fees(&dest_node_id);
+		}
 
-			for chan_id in node.channels.iter() {
-				let chan = self.channels.get(chan_id).unwrap();
-				let chan_info_opt;
-				if chan.node_one == dest_node_id {
-					chan_info_opt = chan.two_to_one.as_ref();
-				} else {
-					chan_info_opt = chan.one_to_two.as_ref();
-				}
-				if let Some(chan_info) = chan_info_opt {
-					if chan_info.enabled {
-						let fees = lowest_inbound_channel_fees.get_or_insert(RoutingFees {
-							base_msat: u32::max_value(), proportional_millionths: u32::max_value() });
-						fees.base_msat = cmp::min(fees.base_msat, chan_info.fees.base_msat);
-						fees.proportional_millionths = cmp::min(fees.proportional_millionths, chan_info.fees.proportional_millionths);
-					}
+		Ok(msg.contents.excess_data.is_empty())
+	}
+
+	/// Recalculates the lowest fees of the enabled channels into the given node, eg after one of
+	/// them was disabled or forgotten.
+	fn recalculate_lowest_inbound_channel_fees(&mut self, node_id: &PublicKey) {
+		let node = match self.nodes.get_mut(node_id) {
+			Some(node) => node,
+			None => return,
+		};
+		let mut lowest_inbound_channel_fees = None;
+
+		for chan_id in node.channels.iter() {
+			let chan = self.channels.get(chan_id).unwrap();
+			let chan_info_opt;
+			if chan.node_one == *node_id {
+				chan_info_opt = chan.two_to_one.as_ref();
+			} else {
+				chan_info_opt = chan.one_to_two.as_ref();
+			}
+			if let Some(chan_info) = chan_info_opt {
+				if chan_info.enabled {
+					let fees = lowest_inbound_channel_fees.get_or_insert(RoutingFees {
+						base_msat: u32::max_value(), proportional_millionths: u32::max_value() });
+					fees.base_msat = cmp::min(fees.base_msat, chan_info.fees.base_msat);
+					fees.proportional_millionths = cmp::min(fees.proportional_millionths, chan_info.fees.proportional_millionths);
 				}
 			}
-
-			node.lowest_inbound_channel_fees = lowest_inbound_channel_fees;
 		}
 
-		Ok(msg.contents.excess_data.is_empty())
+		node.lowest_inbound_channel_fees = lowest_inbound_channel_fees;
+	}
+
+	/// Forgets the directions of channels whose latest channel_update is more than two weeks
+	/// older than the given time, as BOLT 7 allows, and then the channels with no directions
+	/// left. Channels which never received a channel_update are only forgotten once their
+	/// announcement is as old. Nodes are forgotten along with their last channel.
+	fn remove_stale_channels_with_time(&mut self, current_time_unix: u64) {
+		let min_time_unix = current_time_unix.saturating_sub(STALE_CHANNEL_UPDATE_AGE_LIMIT_SECS);
+		let mut stale_channels = HashSet::new();
+		let mut updated_nodes = HashSet::new();
+		for (short_channel_id, chan) in self.channels.iter_mut() {
+			let mut had_stale_direction = false;
+			if chan.one_to_two.as_ref().map(|one_to_two| (one_to_two.last_update as u64) < min_time_unix).unwrap_or(false) {
+				chan.one_to_two = None;
+				updated_nodes.insert(chan.node_two);
+				had_stale_direction = true;
+			}
+			if chan.two_to_one.as_ref().map(|two_to_one| (two_to_one.last_update as u64) < min_time_unix).unwrap_or(false) {
+				chan.two_to_one = None;
+				updated_nodes.insert(chan.node_one);
+				had_stale_direction = true;
+			}
+			if chan.one_to_two.is_none() && chan.two_to_one.is_none() && (had_stale_direction || chan.announcement_received_time < min_time_unix) {
+				stale_channels.insert(*short_channel_id);
+			}
+		}
+		for short_channel_id in stale_channels.iter() {
+			let chan = self.channels.remove(short_channel_id).unwrap();
+			Self::remove_channel_in_nodes(&mut self.nodes, &chan, *short_channel_id);
+		}
+		if !stale_channels.is_empty() {
+			self.funding_outpoints.remove_channels(|short_channel_id| stale_channels.contains(&short_channel_id));
+		}
+		for node_id in updated_nodes.iter() {
+			self.recalculate_lowest_inbound_channel_fees(node_id);
+		}
 	}
 
 	fn remove_channel_in_nodes(nodes: &mut BTreeMap<PublicKey, NodeInfo>, chan: &ChannelInfo, short_channel_id: u64) {
@@ -771,13 +1624,18 @@ impl NetworkGraph {
 #[cfg(test)]
 mod tests {
 	use chain::chaininterface;
+	use chain::chaininterface::{ChainListener, ChainWatchInterface};
 	use ln::features::{ChannelFeatures, NodeFeatures};
-	use routing::network_graph::{NetGraphMsgHandler, NetworkGraph};
+	use routing::network_graph::{DirectionalChannelInfo, NetGraphMsgHandler, NetworkGraph, NetworkGraphUpdate, NetworkUpdateLog, RoutingFees,
+		MAX_QUEUED_SHORT_CHANNEL_IDS_PER_PEER, MAX_SHORT_CHANNEL_IDS_PER_MESSAGE};
+	use ln::features::InitFeatures;
 	use ln::msgs::{RoutingMessageHandler, UnsignedNodeAnnouncement, NodeAnnouncement,
-		UnsignedChannelAnnouncement, ChannelAnnouncement, UnsignedChannelUpdate, ChannelUpdate, HTLCFailChannelUpdate};
+		UnsignedChannelAnnouncement, ChannelAnnouncement, UnsignedChannelUpdate, ChannelUpdate, HTLCFailChannelUpdate,
+		Init, QueryChannelRange, ReplyChannelRange, QueryShortChannelIds, ReplyShortChannelIdsEnd, OptionalField};
+	use util::events::{MessageSendEvent, MessageSendEventsProvider};
 	use util::test_utils;
 	use util::logger::Logger;
-	use util::ser::{Readable, Writeable};
+	use util::ser::{BigSize, Readable, Writeable};
 
 	use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 	use bitcoin::hashes::Hash;
@@ -785,6 +1643,10 @@ mod tests {
 	use bitcoin::blockdata::constants::genesis_block;
 	use bitcoin::blockdata::script::Builder;
 	use bitcoin::blockdata::opcodes;
+	use bitcoin::blockdata::block::BlockHeader;
+	use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn};
+	use bitcoin::blockdata::script::Script;
+	use bitcoin::hash_types::Txid;
 	use bitcoin::util::hash::BitcoinHash;
 
 	use hex;
@@ -792,16 +1654,65 @@ mod tests {
 	use bitcoin::secp256k1::key::{PublicKey, SecretKey};
 	use bitcoin::secp256k1::{All, Secp256k1};
 
-	use std::sync::Arc;
+	use std::io::Cursor;
+	use std::ops::Deref;
+	use std::sync::{Arc, Mutex};
 
 	fn create_net_graph_msg_handler() -> (Secp256k1<All>, NetGraphMsgHandler<Arc<chaininterface::ChainWatchInterfaceUtil>, Arc<test_utils::TestLogger>>) {
 		let secp_ctx = Secp256k1::new();
 		let logger = Arc::new(test_utils::TestLogger::new());
 		let chain_monitor = Arc::new(chaininterface::ChainWatchInterfaceUtil::new(Network::Testnet));
-		let net_graph_msg_handler = NetGraphMsgHandler::new(chain_monitor, Arc::clone(&logger));
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.bitcoin_hash(), chain_monitor, Arc::clone(&logger));
 		(secp_ctx, net_graph_msg_handler)
 	}
 
+	fn announce_channel<C: Deref + Sync + Send>(secp_ctx: &Secp256k1<All>, net_graph_msg_handler: &NetGraphMsgHandler<C, Arc<test_utils::TestLogger>>, short_channel_id: u64) where C::Target: ChainWatchInterface {
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+		let node_1_btckey = &SecretKey::from_slice(&[40; 32]).unwrap();
+		let node_2_btckey = &SecretKey::from_slice(&[39; 32]).unwrap();
+		let unsigned_announcement = UnsignedChannelAnnouncement {
+			features: ChannelFeatures::empty(),
+			chain_hash: genesis_block(Network::Testnet).header.bitcoin_hash(),
+			short_channel_id,
+			node_id_1: PublicKey::from_secret_key(&secp_ctx, node_1_privkey),
+			node_id_2: PublicKey::from_secret_key(&secp_ctx, node_2_privkey),
+			bitcoin_key_1: PublicKey::from_secret_key(&secp_ctx, node_1_btckey),
+			bitcoin_key_2: PublicKey::from_secret_key(&secp_ctx, node_2_btckey),
+			excess_data: Vec::new(),
+		};
+		let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_announcement.encode()[..])[..]);
+		let valid_channel_announcement = ChannelAnnouncement {
+			node_signature_1: secp_ctx.sign(&msghash, node_1_privkey),
+			node_signature_2: secp_ctx.sign(&msghash, node_2_privkey),
+			bitcoin_signature_1: secp_ctx.sign(&msghash, node_1_btckey),
+			bitcoin_signature_2: secp_ctx.sign(&msghash, node_2_btckey),
+			contents: unsigned_announcement,
+		};
+		net_graph_msg_handler.handle_channel_announcement(&valid_channel_announcement).unwrap();
+	}
+
+	fn update_channel_direction(secp_ctx: &Secp256k1<All>, net_graph_msg_handler: &NetGraphMsgHandler<Arc<chaininterface::ChainWatchInterfaceUtil>, Arc<test_utils::TestLogger>>, short_channel_id: u64, flags: u16, timestamp: u32) {
+		let node_privkey = &SecretKey::from_slice(&[if flags & 1 == 0 { 42 } else { 41 }; 32]).unwrap();
+		let unsigned_channel_update = UnsignedChannelUpdate {
+			chain_hash: genesis_block(Network::Testnet).header.bitcoin_hash(),
+			short_channel_id,
+			timestamp,
+			flags,
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000000,
+			fee_base_msat: 10000,
+			fee_proportional_millionths: 20,
+			htlc_maximum_msat: OptionalField::Absent,
+			excess_data: Vec::new()
+		};
+		let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_channel_update.encode()[..])[..]);
+		net_graph_msg_handler.handle_channel_update(&ChannelUpdate {
+			signature: secp_ctx.sign(&msghash, node_privkey),
+			contents: unsigned_channel_update
+		}).unwrap();
+	}
+
 	#[test]
 	fn request_full_sync_finite_times() {
 		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
@@ -924,7 +1835,7 @@ mod tests {
 		let secp_ctx = Secp256k1::new();
 		let logger: Arc<Logger> = Arc::new(test_utils::TestLogger::new());
 		let chain_monitor = Arc::new(test_utils::TestChainWatcher::new());
-		let net_graph_msg_handler = NetGraphMsgHandler::new(chain_monitor.clone(), Arc::clone(&logger));
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.bitcoin_hash(), chain_monitor.clone(), Arc::clone(&logger));
 
 
 		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
@@ -1007,7 +1918,7 @@ mod tests {
 
 		// Now test if the transaction is found in the UTXO set and the script is correct.
 		unsigned_announcement.short_channel_id += 1;
-		*chain_monitor.utxo_ret.lock().unwrap() = Ok((good_script.clone(), 0));
+		*chain_monitor.utxo_ret.lock().unwrap() = Ok((Txid::default(), good_script.clone(), 0));
 
 		msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_announcement.encode()[..])[..]);
 		let valid_announcement = ChannelAnnouncement {
@@ -1039,7 +1950,7 @@ mod tests {
 		};
 
 		// But if it is confirmed, replace the channel
-		*chain_monitor.utxo_ret.lock().unwrap() = Ok((good_script, 0));
+		*chain_monitor.utxo_ret.lock().unwrap() = Ok((Txid::default(), good_script, 0));
 		unsigned_announcement.features = ChannelFeatures::empty();
 		msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_announcement.encode()[..])[..]);
 		let valid_announcement = ChannelAnnouncement {
@@ -1157,6 +2068,7 @@ mod tests {
 			htlc_minimum_msat: 1000000,
 			fee_base_msat: 10000,
 			fee_proportional_millionths: 20,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		};
 		let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_channel_update.encode()[..])[..]);
@@ -1290,6 +2202,7 @@ mod tests {
 				htlc_minimum_msat: 1000000,
 				fee_base_msat: 10000,
 				fee_proportional_millionths: 20,
+				htlc_maximum_msat: OptionalField::Absent,
 				excess_data: Vec::new()
 			};
 			let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_channel_update.encode()[..])[..]);
@@ -1417,6 +2330,7 @@ mod tests {
 				htlc_minimum_msat: 1000000,
 				fee_base_msat: 10000,
 				fee_proportional_millionths: 20,
+				htlc_maximum_msat: OptionalField::Absent,
 				excess_data: Vec::new()
 			};
 			let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_channel_update.encode()[..])[..]);
@@ -1453,6 +2367,7 @@ mod tests {
 				htlc_minimum_msat: 1000000,
 				fee_base_msat: 10000,
 				fee_proportional_millionths: 20,
+				htlc_maximum_msat: OptionalField::Absent,
 				excess_data: [1; 3].to_vec()
 			};
 			let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_channel_update.encode()[..])[..]);
@@ -1665,4 +2580,751 @@ mod tests {
 		network.write(&mut w).unwrap();
 		assert!(<NetworkGraph>::read(&mut ::std::io::Cursor::new(&w.0)).unwrap() == *network);
 	}
+
+	#[test]
+	fn syncing_routing_table_with_gossip_queries() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let chain_hash = genesis_block(Network::Testnet).header.bitcoin_hash();
+		let known_short_channel_id = (100 << 40) | (1 << 16);
+		announce_channel(&secp_ctx, &net_graph_msg_handler, known_short_channel_id);
+
+		// Peers without gossip_queries are synced via initial_routing_sync instead.
+		net_graph_msg_handler.sync_routing_table(&node_id, &Init { features: InitFeatures::known().clear_gossip_queries() });
+		assert!(net_graph_msg_handler.get_and_clear_pending_msg_events().is_empty());
+
+		net_graph_msg_handler.sync_routing_table(&node_id, &Init { features: InitFeatures::known() });
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 2);
+		match events[0] {
+			MessageSendEvent::SendGossipTimestampFilter { node_id: ref filter_node_id, ref msg } => {
+				assert_eq!(*filter_node_id, node_id);
+				assert_eq!(msg.chain_hash, chain_hash);
+				assert_eq!(msg.timestamp_range, 0xffff_ffff);
+			},
+			_ => panic!(),
+		}
+		match events[1] {
+			MessageSendEvent::SendChannelRangeQuery { ref msg, .. } => {
+				assert_eq!(*msg, QueryChannelRange { chain_hash, first_blocknum: 0, number_of_blocks: 0xffff_ffff });
+			},
+			_ => panic!(),
+		}
+
+		// Replies from peers we didn't query are ignored.
+		let other_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[3; 32]).unwrap());
+		let mut reply_channel_range = ReplyChannelRange {
+			chain_hash,
+			first_blocknum: 0,
+			number_of_blocks: 200,
+			sync_complete: true,
+			short_channel_ids: vec![known_short_channel_id + 1],
+		};
+		assert!(net_graph_msg_handler.handle_reply_channel_range(&other_node_id, &reply_channel_range).is_err());
+		assert!(net_graph_msg_handler.get_and_clear_pending_msg_events().is_empty());
+
+		// Replies from peers without complete information are ignored.
+		reply_channel_range = ReplyChannelRange {
+			chain_hash,
+			first_blocknum: 0,
+			number_of_blocks: 200,
+			sync_complete: false,
+			short_channel_ids: vec![known_short_channel_id, known_short_channel_id + 1, known_short_channel_id + 2],
+		};
+		assert!(net_graph_msg_handler.handle_reply_channel_range(&node_id, &reply_channel_range).is_err());
+		assert!(net_graph_msg_handler.get_and_clear_pending_msg_events().is_empty());
+
+		// Only the channels we don't yet know about are queried.
+		reply_channel_range.sync_complete = true;
+		net_graph_msg_handler.handle_reply_channel_range(&node_id, &reply_channel_range).unwrap();
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::SendShortIdsQuery { ref msg, .. } => {
+				assert_eq!(msg.short_channel_ids, vec![known_short_channel_id + 1, known_short_channel_id + 2]);
+			},
+			_ => panic!(),
+		}
+
+		// Further queries wait until the outstanding one is answered.
+		reply_channel_range.first_blocknum = 200;
+		reply_channel_range.number_of_blocks = 0xffff_ffff - 200;
+		reply_channel_range.short_channel_ids = vec![known_short_channel_id + 3];
+		net_graph_msg_handler.handle_reply_channel_range(&node_id, &reply_channel_range).unwrap();
+		assert!(net_graph_msg_handler.get_and_clear_pending_msg_events().is_empty());
+
+		// Once the queried range has been covered, further replies are unsolicited.
+		reply_channel_range.short_channel_ids = vec![known_short_channel_id + 4];
+		assert!(net_graph_msg_handler.handle_reply_channel_range(&node_id, &reply_channel_range).is_err());
+
+		let reply_short_channel_ids_end = ReplyShortChannelIdsEnd { chain_hash, full_information: true };
+		net_graph_msg_handler.handle_reply_short_channel_ids_end(&node_id, &reply_short_channel_ids_end).unwrap();
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::SendShortIdsQuery { ref msg, .. } => {
+				assert_eq!(msg.short_channel_ids, vec![known_short_channel_id + 3]);
+			},
+			_ => panic!(),
+		}
+
+		net_graph_msg_handler.handle_reply_short_channel_ids_end(&node_id, &reply_short_channel_ids_end).unwrap();
+		assert!(net_graph_msg_handler.get_and_clear_pending_msg_events().is_empty());
+
+		// A reply_short_channel_ids_end without an outstanding query is rejected.
+		assert!(net_graph_msg_handler.handle_reply_short_channel_ids_end(&node_id, &reply_short_channel_ids_end).is_err());
+	}
+
+	#[test]
+	fn capping_queued_short_channel_ids() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let chain_hash = genesis_block(Network::Testnet).header.bitcoin_hash();
+		net_graph_msg_handler.sync_routing_table(&node_id, &Init { features: InitFeatures::known() });
+		net_graph_msg_handler.get_and_clear_pending_msg_events();
+
+		// Replies covering part of the queried range at a time may tell us about more channels
+		// than we're willing to queue, the excess of which is dropped.
+		let mut first_blocknum = 0;
+		let mut queried_short_channel_ids = 0;
+		while queried_short_channel_ids <= MAX_QUEUED_SHORT_CHANNEL_IDS_PER_PEER + MAX_SHORT_CHANNEL_IDS_PER_MESSAGE {
+			let short_channel_ids = (0..MAX_SHORT_CHANNEL_IDS_PER_MESSAGE as u64).map(|i| ((first_blocknum as u64) << 40) | (i << 16)).collect();
+			net_graph_msg_handler.handle_reply_channel_range(&node_id, &ReplyChannelRange {
+				chain_hash, first_blocknum, number_of_blocks: 1, sync_complete: true, short_channel_ids,
+			}).unwrap();
+			first_blocknum += 1;
+			queried_short_channel_ids += MAX_SHORT_CHANNEL_IDS_PER_MESSAGE;
+		}
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::SendShortIdsQuery { ref msg, .. } => assert_eq!(msg.short_channel_ids.len(), MAX_SHORT_CHANNEL_IDS_PER_MESSAGE),
+			_ => panic!(),
+		}
+
+		let reply_short_channel_ids_end = ReplyShortChannelIdsEnd { chain_hash, full_information: true };
+		let mut queued_short_channel_ids = MAX_SHORT_CHANNEL_IDS_PER_MESSAGE;
+		loop {
+			net_graph_msg_handler.handle_reply_short_channel_ids_end(&node_id, &reply_short_channel_ids_end).unwrap();
+			let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+			if events.is_empty() { break; }
+			match events[0] {
+				MessageSendEvent::SendShortIdsQuery { ref msg, .. } => queued_short_channel_ids += msg.short_channel_ids.len(),
+				_ => panic!(),
+			}
+		}
+		assert_eq!(queued_short_channel_ids, MAX_QUEUED_SHORT_CHANNEL_IDS_PER_PEER + MAX_SHORT_CHANNEL_IDS_PER_MESSAGE);
+	}
+
+	#[test]
+	fn forgetting_queries_of_disconnected_peers() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let chain_hash = genesis_block(Network::Testnet).header.bitcoin_hash();
+		net_graph_msg_handler.sync_routing_table(&node_id, &Init { features: InitFeatures::known() });
+		net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert!(net_graph_msg_handler.short_id_queries.lock().unwrap().contains_key(&node_id));
+
+		// Our outstanding query is forgotten once the peer disconnects, so late replies are ignored
+		net_graph_msg_handler.peer_disconnected(&node_id);
+		assert!(net_graph_msg_handler.short_id_queries.lock().unwrap().is_empty());
+		match net_graph_msg_handler.handle_reply_channel_range(&node_id, &ReplyChannelRange {
+			chain_hash, first_blocknum: 0, number_of_blocks: 0xffff_ffff, sync_complete: true, short_channel_ids: vec![1 << 40],
+		}) {
+			Ok(_) => panic!(),
+			Err(e) => assert_eq!(e.err, "Received reply_channel_range without an outstanding query"),
+		}
+		assert!(net_graph_msg_handler.get_and_clear_pending_msg_events().is_empty());
+	}
+
+	#[test]
+	fn handling_query_channel_range() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let chain_hash = genesis_block(Network::Testnet).header.bitcoin_hash();
+		let short_channel_ids = vec![(100 << 40) | (1 << 16), (100 << 40) | (2 << 16), (101 << 40) | (1 << 16), (200 << 40) | (1 << 16)];
+		for short_channel_id in short_channel_ids.iter() {
+			announce_channel(&secp_ctx, &net_graph_msg_handler, *short_channel_id);
+		}
+
+		// Channels outside of the queried blocks are not included.
+		net_graph_msg_handler.handle_query_channel_range(&node_id, &QueryChannelRange { chain_hash, first_blocknum: 100, number_of_blocks: 100 }).unwrap();
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::SendReplyChannelRange { ref msg, .. } => {
+				assert_eq!(*msg, ReplyChannelRange {
+					chain_hash,
+					first_blocknum: 100,
+					number_of_blocks: 100,
+					sync_complete: true,
+					short_channel_ids: short_channel_ids[0..3].to_vec(),
+				});
+			},
+			_ => panic!(),
+		}
+
+		// Queries running to the end of the chain include every channel.
+		net_graph_msg_handler.handle_query_channel_range(&node_id, &QueryChannelRange { chain_hash, first_blocknum: 0, number_of_blocks: 0xffff_ffff }).unwrap();
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::SendReplyChannelRange { ref msg, .. } => {
+				assert_eq!(msg.short_channel_ids, short_channel_ids);
+			},
+			_ => panic!(),
+		}
+
+		// Replies to queries running past the last representable block end at it.
+		net_graph_msg_handler.handle_query_channel_range(&node_id, &QueryChannelRange { chain_hash, first_blocknum: 150, number_of_blocks: 0xffff_ffff }).unwrap();
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::SendReplyChannelRange { ref msg, .. } => {
+				assert_eq!(msg.first_blocknum, 150);
+				assert_eq!(msg.number_of_blocks, 0xffff_ffff - 150);
+				assert_eq!(msg.short_channel_ids, short_channel_ids[3..].to_vec());
+			},
+			_ => panic!(),
+		}
+
+		// Queries for an unknown chain are answered with an empty, incomplete reply.
+		let other_chain_hash = genesis_block(Network::Bitcoin).header.bitcoin_hash();
+		assert!(net_graph_msg_handler.handle_query_channel_range(&node_id, &QueryChannelRange { chain_hash: other_chain_hash, first_blocknum: 0, number_of_blocks: 0xffff_ffff }).is_err());
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::SendReplyChannelRange { ref msg, .. } => {
+				assert_eq!(msg.chain_hash, other_chain_hash);
+				assert!(!msg.sync_complete);
+				assert!(msg.short_channel_ids.is_empty());
+			},
+			_ => panic!(),
+		}
+	}
+
+	#[test]
+	fn handling_query_short_channel_ids() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let chain_hash = genesis_block(Network::Testnet).header.bitcoin_hash();
+		let short_channel_id = (100 << 40) | (1 << 16);
+		announce_channel(&secp_ctx, &net_graph_msg_handler, short_channel_id);
+
+		// Unknown channels are skipped.
+		net_graph_msg_handler.handle_query_short_channel_ids(&node_id, &QueryShortChannelIds { chain_hash, short_channel_ids: vec![short_channel_id, short_channel_id + 1] }).unwrap();
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::SendShortIdsReply { ref channel_announcements, ref node_announcements, ref msg, .. } => {
+				assert_eq!(channel_announcements.len(), 1);
+				assert_eq!(channel_announcements[0].0.contents.short_channel_id, short_channel_id);
+				assert_eq!(channel_announcements[0].1, None);
+				assert_eq!(channel_announcements[0].2, None);
+				assert!(node_announcements.is_empty());
+				assert_eq!(*msg, ReplyShortChannelIdsEnd { chain_hash, full_information: true });
+			},
+			_ => panic!(),
+		}
+
+		let other_chain_hash = genesis_block(Network::Bitcoin).header.bitcoin_hash();
+		assert!(net_graph_msg_handler.handle_query_short_channel_ids(&node_id, &QueryShortChannelIds { chain_hash: other_chain_hash, short_channel_ids: vec![short_channel_id] }).is_err());
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::SendShortIdsReply { ref channel_announcements, ref msg, .. } => {
+				assert!(channel_announcements.is_empty());
+				assert_eq!(*msg, ReplyShortChannelIdsEnd { chain_hash: other_chain_hash, full_information: false });
+			},
+			_ => panic!(),
+		}
+	}
+
+	struct TestUpdateLog {
+		snapshot: Arc<Mutex<Vec<u8>>>,
+		updates: Arc<Mutex<Vec<u8>>>,
+	}
+	impl NetworkUpdateLog for TestUpdateLog {
+		fn append_update(&self, update: &NetworkGraphUpdate) -> Result<(), ::std::io::Error> {
+			update.write(&mut *self.updates.lock().unwrap())
+		}
+		fn persist_snapshot(&self, network_graph: &NetworkGraph) -> Result<(), ::std::io::Error> {
+			*self.snapshot.lock().unwrap() = network_graph.encode();
+			self.updates.lock().unwrap().clear();
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn network_graph_update_log() {
+		let secp_ctx = Secp256k1::new();
+		let logger = Arc::new(test_utils::TestLogger::new());
+		let chain_monitor = Arc::new(chaininterface::ChainWatchInterfaceUtil::new(Network::Testnet));
+		let snapshot = Arc::new(Mutex::new(NetworkGraph::new().encode()));
+		let updates = Arc::new(Mutex::new(Vec::new()));
+		let update_log = Box::new(TestUpdateLog { snapshot: Arc::clone(&snapshot), updates: Arc::clone(&updates) });
+		let net_graph_msg_handler = NetGraphMsgHandler::from_net_graph_with_update_log(genesis_block(Network::Testnet).header.bitcoin_hash(),
+			chain_monitor, Arc::clone(&logger), NetworkGraph::new(), update_log);
+
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let chain_hash = genesis_block(Network::Testnet).header.bitcoin_hash();
+		let update_channel = |short_channel_id, timestamp| {
+			let unsigned_channel_update = UnsignedChannelUpdate {
+				chain_hash,
+				short_channel_id,
+				timestamp,
+				flags: 0,
+				cltv_expiry_delta: 144,
+				htlc_minimum_msat: 1000000,
+				fee_base_msat: 10000,
+				fee_proportional_millionths: 20,
+				htlc_maximum_msat: OptionalField::Absent,
+				excess_data: Vec::new()
+			};
+			let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_channel_update.encode()[..])[..]);
+			net_graph_msg_handler.handle_channel_update(&ChannelUpdate {
+				signature: secp_ctx.sign(&msghash, node_1_privkey),
+				contents: unsigned_channel_update
+			}).unwrap();
+		};
+
+		// Replaying the log into the latest snapshot results in the same graph.
+		let check_reload = || {
+			let mut network_graph: NetworkGraph = Readable::read(&mut Cursor::new(&snapshot.lock().unwrap()[..])).unwrap();
+			network_graph.replay_update_log(&mut Cursor::new(&updates.lock().unwrap()[..])).unwrap();
+			assert!(network_graph == *net_graph_msg_handler.network_graph.read().unwrap());
+		};
+
+		announce_channel(&secp_ctx, &net_graph_msg_handler, 1);
+		update_channel(1, 100);
+		check_reload();
+
+		net_graph_msg_handler.compact_update_log().unwrap();
+		assert!(updates.lock().unwrap().is_empty());
+		check_reload();
+
+		// Updates which fail to apply aren't logged.
+		assert!(net_graph_msg_handler.handle_channel_update(&{
+			let unsigned_channel_update = UnsignedChannelUpdate {
+				chain_hash,
+				short_channel_id: 1,
+				timestamp: 100,
+				flags: 0,
+				cltv_expiry_delta: 144,
+				htlc_minimum_msat: 1000000,
+				fee_base_msat: 10000,
+				fee_proportional_millionths: 20,
+				htlc_maximum_msat: OptionalField::Absent,
+				excess_data: Vec::new()
+			};
+			let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_channel_update.encode()[..])[..]);
+			ChannelUpdate { signature: secp_ctx.sign(&msghash, node_1_privkey), contents: unsigned_channel_update }
+		}).is_err());
+		assert!(updates.lock().unwrap().is_empty());
+
+		announce_channel(&secp_ctx, &net_graph_msg_handler, 2);
+		update_channel(1, 101);
+		update_channel(2, 101);
+		{
+			let unsigned_announcement = UnsignedNodeAnnouncement {
+				features: NodeFeatures::known(),
+				timestamp: 100,
+				node_id: PublicKey::from_secret_key(&secp_ctx, node_1_privkey),
+				rgb: [0; 3],
+				alias: [0; 32],
+				addresses: Vec::new(),
+				excess_address_data: Vec::new(),
+				excess_data: Vec::new(),
+			};
+			let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_announcement.encode()[..])[..]);
+			net_graph_msg_handler.handle_node_announcement(&NodeAnnouncement {
+				signature: secp_ctx.sign(&msghash, node_1_privkey),
+				contents: unsigned_announcement
+			}).unwrap();
+		}
+		net_graph_msg_handler.handle_htlc_fail_channel_update(&HTLCFailChannelUpdate::ChannelClosed { short_channel_id: 1, is_permanent: false });
+		net_graph_msg_handler.handle_htlc_fail_channel_update(&HTLCFailChannelUpdate::ChannelClosed { short_channel_id: 2, is_permanent: true });
+		check_reload();
+
+		// The snapshot alone only includes the updates from before compaction.
+		let network_graph: NetworkGraph = Readable::read(&mut Cursor::new(&snapshot.lock().unwrap()[..])).unwrap();
+		assert_eq!(network_graph.get_channels().len(), 1);
+		assert!(network_graph.get_channels().get(&1).unwrap().one_to_two.as_ref().unwrap().enabled);
+		assert_eq!(net_graph_msg_handler.network_graph.read().unwrap().get_channels().len(), 1);
+
+		net_graph_msg_handler.remove_stale_channels_with_time(101 + 60 * 60 * 24 * 14 + 1);
+		assert!(net_graph_msg_handler.network_graph.read().unwrap().get_channels().is_empty());
+		check_reload();
+
+		// A partially-written final update is ignored.
+		let update = NetworkGraphUpdate::ChannelClosed { short_channel_id: 1, is_permanent: true };
+		let encoded_update = update.encode();
+		let read_update: NetworkGraphUpdate = Readable::read(&mut Cursor::new(&encoded_update[..])).unwrap();
+		assert_eq!(read_update, update);
+		updates.lock().unwrap().extend_from_slice(&encoded_update[..encoded_update.len() - 1]);
+		check_reload();
+		let truncated_len = updates.lock().unwrap().len() - encoded_update.len() + 3;
+		updates.lock().unwrap().truncate(truncated_len);
+		check_reload();
+	}
+
+	#[test]
+	fn legacy_network_graph_deserialization() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		announce_channel(&secp_ctx, &net_graph_msg_handler, 1);
+		let network = net_graph_msg_handler.network_graph.read().unwrap();
+		let chan_info = network.get_channels().get(&1).unwrap();
+
+		// Graphs written before they were versioned, and before DirectionalChannelInfo gained
+		// htlc_maximum_msat, are read with read_unversioned.
+		let one_to_two = DirectionalChannelInfo {
+			last_update: 42,
+			enabled: true,
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000,
+			htlc_maximum_msat: None,
+			fees: RoutingFees { base_msat: 1000, proportional_millionths: 10 },
+			last_update_message: None,
+		};
+		let mut legacy_one_to_two = Vec::new();
+		one_to_two.last_update.write(&mut legacy_one_to_two).unwrap();
+		one_to_two.enabled.write(&mut legacy_one_to_two).unwrap();
+		one_to_two.cltv_expiry_delta.write(&mut legacy_one_to_two).unwrap();
+		one_to_two.htlc_minimum_msat.write(&mut legacy_one_to_two).unwrap();
+		one_to_two.fees.write(&mut legacy_one_to_two).unwrap();
+		one_to_two.last_update_message.write(&mut legacy_one_to_two).unwrap();
+
+		let mut w = test_utils::TestVecWriter(Vec::new());
+		1u64.write(&mut w).unwrap();
+		1u64.write(&mut w).unwrap();
+		chan_info.features.write(&mut w).unwrap();
+		chan_info.node_one.write(&mut w).unwrap();
+		BigSize(legacy_one_to_two.len() as u64 + 1).write(&mut w).unwrap();
+		w.0.extend_from_slice(&legacy_one_to_two);
+		chan_info.node_two.write(&mut w).unwrap();
+		None::<DirectionalChannelInfo>.write(&mut w).unwrap();
+		chan_info.announcement_message.write(&mut w).unwrap();
+		(network.get_nodes().len() as u64).write(&mut w).unwrap();
+		for (node_id, node_info) in network.get_nodes().iter() {
+			node_id.write(&mut w).unwrap();
+			node_info.write(&mut w).unwrap();
+		}
+		let legacy_network = NetworkGraph::read_unversioned(&mut Cursor::new(&w.0)).unwrap();
+		let legacy_chan_info = legacy_network.get_channels().get(&1).unwrap();
+		assert_eq!(legacy_chan_info.one_to_two, Some(one_to_two));
+		assert!(legacy_chan_info.two_to_one.is_none());
+		assert!(legacy_chan_info.capacity_sats.is_none());
+		assert_eq!(legacy_chan_info.announcement_received_time, 0);
+		assert!(legacy_chan_info.announcement_message == chan_info.announcement_message);
+		assert!(legacy_network.get_nodes() == network.get_nodes());
+	}
+
+	#[test]
+	fn legacy_network_graph_update_deserialization() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		announce_channel(&secp_ctx, &net_graph_msg_handler, 1);
+		let network = net_graph_msg_handler.network_graph.read().unwrap();
+		let chan_info = network.get_channels().get(&1).unwrap();
+
+		// Versioned graphs begin with an explicit version prefix, and are refused if they require a
+		// newer version.
+		let mut versioned = Vec::new();
+		network.write(&mut versioned).unwrap();
+		assert_eq!(&versioned[..2], &[1, 1]);
+		assert!(<NetworkGraph as Readable>::read(&mut Cursor::new(&[2u8, 2][..])).is_err());
+
+		// Channel_announcement updates logged before funding_txid and received_time_unix were added
+		// are still read.
+		let mut contents = Vec::new();
+		1u8.write(&mut contents).unwrap();
+		Some(1000u64).write(&mut contents).unwrap();
+		chan_info.announcement_message.as_ref().unwrap().write(&mut contents).unwrap();
+		let mut encoded_update = Vec::new();
+		(contents.len() as u32).write(&mut encoded_update).unwrap();
+		encoded_update.extend_from_slice(&contents);
+		match Readable::read(&mut Cursor::new(&encoded_update[..])).unwrap() {
+			NetworkGraphUpdate::ChannelAnnouncement { msg, capacity_sats, funding_txid, received_time_unix } => {
+				assert!(Some(msg) == chan_info.announcement_message);
+				assert_eq!(capacity_sats, Some(1000));
+				assert!(funding_txid.is_none());
+				assert_eq!(received_time_unix, 0);
+			},
+			_ => panic!(),
+		}
+	}
+
+	#[test]
+	fn removing_stale_channels() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let two_weeks = 60 * 60 * 24 * 14;
+		let node_id_1 = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		let node_id_2 = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[41; 32]).unwrap());
+
+		announce_channel(&secp_ctx, &net_graph_msg_handler, 1);
+		update_channel_direction(&secp_ctx, &net_graph_msg_handler, 1, 0, 1000);
+		update_channel_direction(&secp_ctx, &net_graph_msg_handler, 1, 1, 1100);
+		announce_channel(&secp_ctx, &net_graph_msg_handler, 2);
+		update_channel_direction(&secp_ctx, &net_graph_msg_handler, 2, 0, 1000 + two_weeks);
+		// Channels which never received a channel_update are kept until their announcement is
+		// stale, as their channel_updates may still be on their way.
+		announce_channel(&secp_ctx, &net_graph_msg_handler, 3);
+		net_graph_msg_handler.network_graph.write().unwrap().channels.get_mut(&3).unwrap().announcement_received_time = 1000 + two_weeks as u64;
+
+		net_graph_msg_handler.remove_stale_channels_with_time(1000 + two_weeks as u64);
+		{
+			let network_graph = net_graph_msg_handler.network_graph.read().unwrap();
+			assert_eq!(network_graph.get_channels().len(), 3);
+			assert!(network_graph.get_channels().get(&1).unwrap().one_to_two.is_some());
+			assert!(network_graph.get_channels().get(&3).is_some());
+			assert_eq!(network_graph.get_nodes().get(&node_id_2).unwrap().channels, vec![1, 2, 3]);
+		}
+
+		// Stale directions are forgotten individually.
+		net_graph_msg_handler.remove_stale_channels_with_time(1001 + two_weeks as u64);
+		{
+			let network_graph = net_graph_msg_handler.network_graph.read().unwrap();
+			let chan = network_graph.get_channels().get(&1).unwrap();
+			assert!(chan.one_to_two.is_none());
+			assert!(chan.two_to_one.is_some());
+		}
+
+		// Channels are removed along with their last direction.
+		net_graph_msg_handler.remove_stale_channels_with_time(1101 + two_weeks as u64);
+		{
+			let network_graph = net_graph_msg_handler.network_graph.read().unwrap();
+			assert!(network_graph.get_channels().get(&1).is_none());
+			assert_eq!(network_graph.get_nodes().get(&node_id_1).unwrap().channels, vec![2, 3]);
+			assert_eq!(network_graph.get_nodes().get(&node_id_2).unwrap().channels, vec![2, 3]);
+		}
+
+		// Channels without channel_updates are removed once their announcement is stale, and
+		// nodes are removed along with their last channel.
+		net_graph_msg_handler.remove_stale_channels_with_time(1001 + 2 * two_weeks as u64);
+		{
+			let network_graph = net_graph_msg_handler.network_graph.read().unwrap();
+			assert!(network_graph.get_channels().is_empty());
+			assert!(network_graph.get_nodes().is_empty());
+		}
+
+		// Removed channels may be announced again.
+		announce_channel(&secp_ctx, &net_graph_msg_handler, 1);
+		update_channel_direction(&secp_ctx, &net_graph_msg_handler, 1, 0, 2000 + 2 * two_weeks);
+		net_graph_msg_handler.remove_stale_channels_with_time(2000 + 2 * two_weeks as u64);
+		{
+			let network_graph = net_graph_msg_handler.network_graph.read().unwrap();
+			assert_eq!(network_graph.get_channels().get(&1).unwrap().one_to_two.as_ref().unwrap().last_update, 2000 + 2 * two_weeks);
+			assert_eq!(network_graph.get_nodes().get(&node_id_2).unwrap().lowest_inbound_channel_fees.unwrap().base_msat, 10000);
+		}
+	}
+
+	#[test]
+	fn removing_channels_with_spent_funding_outputs() {
+		let secp_ctx = Secp256k1::new();
+		let logger = Arc::new(test_utils::TestLogger::new());
+		let chain_monitor = Arc::new(test_utils::TestChainWatcher::new());
+		let mut net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.bitcoin_hash(), chain_monitor.clone(), Arc::clone(&logger));
+		net_graph_msg_handler.set_funding_spend_confirmations(3);
+
+		let funding_script = Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_2)
+		   .push_slice(&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[40; 32]).unwrap()).serialize())
+		   .push_slice(&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[39; 32]).unwrap()).serialize())
+		   .push_opcode(opcodes::all::OP_PUSHNUM_2)
+		   .push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script().to_v0_p2wsh();
+		let funding_txid = Txid::hash(&[42; 32]);
+		*chain_monitor.utxo_ret.lock().unwrap() = Ok((funding_txid, funding_script, 0));
+		let short_channel_id = (100 << 40) | (1 << 16) | 1;
+		announce_channel(&secp_ctx, &net_graph_msg_handler, short_channel_id);
+
+		let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+		let spend_tx = |vout| Transaction {
+			version: 2,
+			lock_time: 0,
+			input: vec![TxIn {
+				previous_output: OutPoint { txid: funding_txid, vout },
+				script_sig: Script::new(),
+				sequence: 0xffffffff,
+				witness: Vec::new(),
+			}],
+			output: Vec::new(),
+		};
+		let channel_known = || net_graph_msg_handler.network_graph.read().unwrap().get_channels().get(&short_channel_id).is_some();
+
+		// Spends of other outputs are ignored.
+		net_graph_msg_handler.block_connected(&header, 200, &[&spend_tx(0)], &[1]);
+		net_graph_msg_handler.block_connected(&header, 201, &[], &[]);
+		net_graph_msg_handler.block_connected(&header, 202, &[], &[]);
+		assert!(channel_known());
+
+		// Spends which are reorged out are forgotten.
+		net_graph_msg_handler.block_connected(&header, 203, &[&spend_tx(1)], &[1]);
+		net_graph_msg_handler.block_connected(&header, 204, &[], &[]);
+		assert!(channel_known());
+		net_graph_msg_handler.block_disconnected(&header, 204);
+		net_graph_msg_handler.block_disconnected(&header, 203);
+		net_graph_msg_handler.block_connected(&header, 203, &[], &[]);
+		net_graph_msg_handler.block_connected(&header, 204, &[], &[]);
+		net_graph_msg_handler.block_connected(&header, 205, &[], &[]);
+		assert!(channel_known());
+
+		// Once the spend has enough confirmations, the channel is removed.
+		net_graph_msg_handler.block_connected(&header, 206, &[&spend_tx(1)], &[1]);
+		net_graph_msg_handler.block_connected(&header, 206, &[&spend_tx(1)], &[1]);
+		net_graph_msg_handler.block_connected(&header, 207, &[], &[]);
+		assert!(channel_known());
+		net_graph_msg_handler.block_connected(&header, 208, &[], &[]);
+		assert!(!channel_known());
+		assert!(net_graph_msg_handler.network_graph.read().unwrap().get_nodes().is_empty());
+	}
+
+	#[test]
+	fn watching_funding_outpoints() {
+		let secp_ctx = Secp256k1::new();
+		let logger = Arc::new(test_utils::TestLogger::new());
+		let chain_monitor = Arc::new(test_utils::TestChainWatcher::new());
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.bitcoin_hash(), chain_monitor.clone(), Arc::clone(&logger));
+
+		let node_1_btckey = &SecretKey::from_slice(&[40; 32]).unwrap();
+		let node_2_btckey = &SecretKey::from_slice(&[39; 32]).unwrap();
+		let funding_script = Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_2)
+		   .push_slice(&PublicKey::from_secret_key(&secp_ctx, node_1_btckey).serialize())
+		   .push_slice(&PublicKey::from_secret_key(&secp_ctx, node_2_btckey).serialize())
+		   .push_opcode(opcodes::all::OP_PUSHNUM_2)
+		   .push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script().to_v0_p2wsh();
+		let funding_txid = Txid::hash(&[42; 32]);
+		*chain_monitor.utxo_ret.lock().unwrap() = Ok((funding_txid, funding_script, 0));
+		let short_channel_id = (100 << 40) | (1 << 16) | 1;
+		let funding_outpoint = (funding_txid, 1);
+
+		// Announcements with invalid signatures don't get their funding output watched.
+		{
+			let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+			let node_2_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+			let unsigned_announcement = UnsignedChannelAnnouncement {
+				features: ChannelFeatures::empty(),
+				chain_hash: genesis_block(Network::Testnet).header.bitcoin_hash(),
+				short_channel_id,
+				node_id_1: PublicKey::from_secret_key(&secp_ctx, node_1_privkey),
+				node_id_2: PublicKey::from_secret_key(&secp_ctx, node_2_privkey),
+				bitcoin_key_1: PublicKey::from_secret_key(&secp_ctx, node_1_btckey),
+				bitcoin_key_2: PublicKey::from_secret_key(&secp_ctx, node_2_btckey),
+				excess_data: Vec::new(),
+			};
+			let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_announcement.encode()[..])[..]);
+			assert!(net_graph_msg_handler.handle_channel_announcement(&ChannelAnnouncement {
+				node_signature_1: secp_ctx.sign(&msghash, node_2_privkey),
+				node_signature_2: secp_ctx.sign(&msghash, node_2_privkey),
+				bitcoin_signature_1: secp_ctx.sign(&msghash, node_1_btckey),
+				bitcoin_signature_2: secp_ctx.sign(&msghash, node_2_btckey),
+				contents: unsigned_announcement,
+			}).is_err());
+		}
+		assert!(chain_monitor.watched_outpoints.lock().unwrap().is_empty());
+		assert!(net_graph_msg_handler.network_graph.read().unwrap().funding_outpoints.channels.is_empty());
+
+		announce_channel(&secp_ctx, &net_graph_msg_handler, short_channel_id);
+		assert_eq!(*chain_monitor.watched_outpoints.lock().unwrap(), vec![funding_outpoint]);
+
+		// Funding outpoints are persisted with the graph and watched again once it is loaded.
+		let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+		let spend_tx = Transaction {
+			version: 2,
+			lock_time: 0,
+			input: vec![TxIn {
+				previous_output: OutPoint { txid: funding_txid, vout: 1 },
+				script_sig: Script::new(),
+				sequence: 0xffffffff,
+				witness: Vec::new(),
+			}],
+			output: Vec::new(),
+		};
+		net_graph_msg_handler.block_connected(&header, 200, &[&spend_tx], &[1]);
+		{
+			let encoded_graph = net_graph_msg_handler.network_graph.read().unwrap().encode();
+			let read_graph: NetworkGraph = Readable::read(&mut Cursor::new(&encoded_graph[..])).unwrap();
+			assert!(read_graph == *net_graph_msg_handler.network_graph.read().unwrap());
+			let reload_chain_monitor = Arc::new(test_utils::TestChainWatcher::new());
+			let reloaded_msg_handler = NetGraphMsgHandler::from_net_graph(genesis_block(Network::Testnet).header.bitcoin_hash(), reload_chain_monitor.clone(), Arc::clone(&logger), read_graph);
+			assert_eq!(*reload_chain_monitor.watched_outpoints.lock().unwrap(), vec![funding_outpoint]);
+			for height in 201..206 {
+				reloaded_msg_handler.block_connected(&header, height, &[], &[]);
+			}
+			let network_graph = reloaded_msg_handler.network_graph.read().unwrap();
+			assert!(network_graph.get_channels().is_empty());
+			assert!(network_graph.funding_outpoints.channels.is_empty());
+			assert!(network_graph.funding_outpoints.spend_heights.is_empty());
+		}
+
+		// Funding outpoints are forgotten when the channel is removed following an HTLC failure...
+		net_graph_msg_handler.handle_htlc_fail_channel_update(&HTLCFailChannelUpdate::ChannelClosed { short_channel_id, is_permanent: true });
+		{
+			let network_graph = net_graph_msg_handler.network_graph.read().unwrap();
+			assert!(network_graph.funding_outpoints.channels.is_empty());
+			assert!(network_graph.funding_outpoints.spend_heights.is_empty());
+		}
+
+		// ...or as it is stale.
+		announce_channel(&secp_ctx, &net_graph_msg_handler, short_channel_id);
+		assert_eq!(net_graph_msg_handler.network_graph.read().unwrap().funding_outpoints.channels.len(), 1);
+		let received_time = net_graph_msg_handler.network_graph.read().unwrap().get_channels().get(&short_channel_id).unwrap().announcement_received_time;
+		net_graph_msg_handler.remove_stale_channels_with_time(received_time + 60 * 60 * 24 * 14 + 1);
+		assert!(net_graph_msg_handler.network_graph.read().unwrap().funding_outpoints.channels.is_empty());
+	}
+
+	#[test]
+	fn handling_channel_update_htlc_maximum_msat() {
+		let secp_ctx = Secp256k1::new();
+		let logger = Arc::new(test_utils::TestLogger::new());
+		let chain_monitor = Arc::new(test_utils::TestChainWatcher::new());
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.bitcoin_hash(), chain_monitor.clone(), Arc::clone(&logger));
+
+		let funding_script = Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_2)
+		   .push_slice(&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[40; 32]).unwrap()).serialize())
+		   .push_slice(&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[39; 32]).unwrap()).serialize())
+		   .push_opcode(opcodes::all::OP_PUSHNUM_2)
+		   .push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script().to_v0_p2wsh();
+		*chain_monitor.utxo_ret.lock().unwrap() = Ok((Txid::hash(&[42; 32]), funding_script, 100_000));
+		let short_channel_id = (100 << 40) | (1 << 16);
+		announce_channel(&secp_ctx, &net_graph_msg_handler, short_channel_id);
+		assert_eq!(net_graph_msg_handler.network_graph.read().unwrap().get_channels().get(&short_channel_id).unwrap().capacity_sats, Some(100_000));
+
+		let update_channel = |timestamp, htlc_maximum_msat| {
+			let unsigned_channel_update = UnsignedChannelUpdate {
+				chain_hash: genesis_block(Network::Testnet).header.bitcoin_hash(),
+				short_channel_id,
+				timestamp,
+				flags: 1 << 8,
+				cltv_expiry_delta: 144,
+				htlc_minimum_msat: 1000,
+				fee_base_msat: 10000,
+				fee_proportional_millionths: 20,
+				htlc_maximum_msat: OptionalField::Present(htlc_maximum_msat),
+				excess_data: Vec::new()
+			};
+			let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_channel_update.encode()[..])[..]);
+			net_graph_msg_handler.handle_channel_update(&ChannelUpdate {
+				signature: secp_ctx.sign(&msghash, &SecretKey::from_slice(&[42; 32]).unwrap()),
+				contents: unsigned_channel_update
+			})
+		};
+
+		match update_channel(100, 100_000_001) {
+			Ok(_) => panic!(),
+			Err(e) => assert_eq!(e.err, "htlc_maximum_msat is larger than channel capacity")
+		};
+		match update_channel(100, 21_000_000 * 1_0000_0000 * 1000 + 1) {
+			Ok(_) => panic!(),
+			Err(e) => assert_eq!(e.err, "htlc_maximum_msat is larger than maximum possible msats")
+		};
+		assert!(update_channel(100, 100_000_000).unwrap());
+
+		// The limit and capacity survive serializing the graph
+		let network = net_graph_msg_handler.network_graph.read().unwrap();
+		let chan_info = network.get_channels().get(&short_channel_id).unwrap();
+		assert_eq!(chan_info.one_to_two.as_ref().unwrap().htlc_maximum_msat, Some(100_000_000));
+		let read_network = <NetworkGraph>::read(&mut ::std::io::Cursor::new(&network.encode())).unwrap();
+		assert!(read_network == *network);
+		let read_chan_info = read_network.get_channels().get(&short_channel_id).unwrap();
+		assert_eq!(read_chan_info.capacity_sats, Some(100_000));
+		assert_eq!(read_chan_info.one_to_two.as_ref().unwrap().htlc_maximum_msat, Some(100_000_000));
+	}
 }
@@ -8,12 +8,13 @@ use bitcoin::secp256k1::key::PublicKey;
 use ln::channelmanager;
 use ln::features::{ChannelFeatures, NodeFeatures};
 use ln::msgs::{DecodeError,ErrorAction,LightningError};
-use routing::network_graph::{NetworkGraph, RoutingFees};
+use routing::network_graph::{ChannelInfo, DirectionalChannelInfo, NetworkGraph, RoutingFees};
+use routing::scorer::Score;
 use util::ser::{Writeable, Readable};
 use util::logger::Logger;
 
 use std::cmp;
-use std::collections::{HashMap,BinaryHeap};
+use std::collections::{HashMap,HashSet,BinaryHeap};
 use std::ops::Deref;
 
 /// A hop in a route
@@ -104,6 +105,7 @@ impl Readable for Route {
 }
 
 /// A channel descriptor which provides a last-hop route to get_route
+#[derive(Clone, Debug, PartialEq)]
 pub struct RouteHint {
 	/// The node_id of the non-target end of the route
 	pub src_node_id: PublicKey,
@@ -117,11 +119,63 @@ pub struct RouteHint {
 	pub htlc_minimum_msat: u64,
 }
 
+/// Constraints on the routes found by get_route_with_params and get_multi_path_route_with_params.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteParameters {
+	/// The maximum total fee, in msat, to pay across all paths of the route.
+	///
+	/// Default value: u64::max_value() (ie no limit)
+	pub max_total_fee_msat: u64,
+	/// The maximum total CLTV delta of any path in the route, including the final_cltv for the
+	/// destination, ie the sum of the cltv_expiry_delta of each of its hops.
+	///
+	/// Default value: u32::max_value() (ie no limit)
+	pub max_total_cltv_expiry_delta: u32,
+	/// The maximum number of hops in any path in the route. Must be between 1 and 20, as an onion
+	/// can carry no more than 20 hops.
+	///
+	/// Default value: 20
+	pub max_path_length: u8,
+	/// The maximum number of paths a payment may be split across. Only considered by
+	/// get_multi_path_route_with_params, as get_route_with_params always returns a single path.
+	/// Must be between 1 and 10, as ChannelManager::send_payment doesn't accept more paths.
+	///
+	/// Default value: 10 (the limit ChannelManager::send_payment places on a Route)
+	pub max_path_count: usize,
+	/// Nodes which no path in the route may pass through.
+	///
+	/// Default value: empty
+	pub excluded_nodes: HashSet<PublicKey>,
+	/// Channels, by short_channel_id, which no path in the route may use.
+	///
+	/// Default value: empty
+	pub excluded_channels: HashSet<u64>,
+}
+
+impl Default for RouteParameters {
+	fn default() -> Self {
+		RouteParameters {
+			max_total_fee_msat: u64::max_value(),
+			max_total_cltv_expiry_delta: u32::max_value(),
+			max_path_length: MAX_PATH_LENGTH,
+			max_path_count: MAX_PATH_COUNT,
+			excluded_nodes: HashSet::new(),
+			excluded_channels: HashSet::new(),
+		}
+	}
+}
+
 #[derive(Eq, PartialEq)]
 struct RouteGraphNode {
 	pubkey: PublicKey,
 	lowest_fee_to_peer_through_node: u64,
 	lowest_fee_to_node: u64,
+	/// The sum of the penalties the scorer assigned to the channels from this node to the target.
+	path_penalty_msat: u64,
+	/// The total CLTV delta of the path from this node to the target, including the final_cltv.
+	cltv_to_target: u32,
+	/// The number of hops from this node to the target.
+	path_length: u8,
 }
 
 impl cmp::Ord for RouteGraphNode {
@@ -143,6 +197,91 @@ struct DummyDirectionalChannelInfo {
 	fees: RoutingFees,
 }
 
+/// The maximum number of paths get_multi_path_route will split a payment across. This matches the
+/// limit ChannelManager::send_payment places on the number of paths in a Route.
+pub(crate) const MAX_PATH_COUNT: usize = 10;
+
+/// The maximum number of hops in a path, as limited by the size of the onion. This matches the
+/// limit ChannelManager::send_payment places on the length of each path in a Route.
+const MAX_PATH_LENGTH: u8 = 20;
+
+/// The best hop we've found so far from a given node towards the target.
+struct PathBuildingHop {
+	/// The lowest total fee (including the minimum fee to reach this node) to reach the target
+	/// through this node.
+	lowest_fee_to_peer_through_node: u64,
+	/// The lowest fees of any channel into this node, used as our A* heuristic.
+	lowest_inbound_channel_fees: RoutingFees,
+	/// The hop from this node to the next node towards the target.
+	route_hop: RouteHop,
+	/// The fees this node charges to forward over the channel in route_hop.
+	channel_fees: RoutingFees,
+	/// The liquidity not yet used by other paths in the channel in route_hop.
+	available_liquidity_msat: u64,
+}
+
+/// A path as found by find_path, along with the fees charged by the previous node and the
+/// liquidity available in the channel used to reach each hop, allowing us to change the value sent
+/// over the path without re-running the search.
+struct PathCandidate {
+	hops: Vec<RouteHop>,
+	inbound_channel_fees: Vec<RoutingFees>,
+	available_liquidity_msat: Vec<u64>,
+}
+
+impl PathCandidate {
+	/// Gets the amount which will be sent over the channel into each hop if we deliver value_msat
+	/// to the destination, or None if the fees overflow.
+	fn hop_amounts_msat(&self, value_msat: u64) -> Option<Vec<u64>> {
+		let mut amounts_msat = vec![0; self.hops.len()];
+		let mut amount_msat = value_msat;
+		for idx in (0..self.hops.len()).rev() {
+			amounts_msat[idx] = amount_msat;
+			if idx != 0 {
+				let fees = &self.inbound_channel_fees[idx];
+				let fee_msat = amount_msat.checked_mul(fees.proportional_millionths as u64)
+					.and_then(|part| (fees.base_msat as u64).checked_add(part / 1000000))?;
+				amount_msat = amount_msat.checked_add(fee_msat)?;
+			}
+		}
+		Some(amounts_msat)
+	}
+
+	fn can_carry_value(&self, value_msat: u64, max_fee_msat: u64) -> bool {
+		match self.hop_amounts_msat(value_msat) {
+			Some(amounts_msat) => amounts_msat[0] - value_msat <= max_fee_msat &&
+				amounts_msat.iter().zip(self.available_liquidity_msat.iter())
+					.all(|(amount_msat, available_msat)| amount_msat <= available_msat),
+			None => false,
+		}
+	}
+
+	/// Gets the largest value in [min_value_msat, max_value_msat] which this path can carry given
+	/// the liquidity available at each hop, without paying more than max_fee_msat in fees.
+	/// min_value_msat must be the value the path was found for.
+	fn max_value_msat(&self, min_value_msat: u64, max_value_msat: u64, max_fee_msat: u64) -> u64 {
+		let (mut lowest_msat, mut highest_msat) = (min_value_msat, max_value_msat);
+		while lowest_msat < highest_msat {
+			let value_msat = lowest_msat + (highest_msat - lowest_msat + 1) / 2;
+			if self.can_carry_value(value_msat, max_fee_msat) {
+				lowest_msat = value_msat;
+			} else {
+				highest_msat = value_msat - 1;
+			}
+		}
+		lowest_msat
+	}
+
+	/// Converts this path into the hops for a Route path delivering value_msat to the destination.
+	fn into_route_hops(mut self, value_msat: u64) -> Vec<RouteHop> {
+		let amounts_msat = self.hop_amounts_msat(value_msat).unwrap();
+		for idx in 0..self.hops.len() - 1 {
+			self.hops[idx].fee_msat = amounts_msat[idx] - amounts_msat[idx + 1];
+		}
+		self.hops.last_mut().unwrap().fee_msat = value_msat;
+		self.hops
+	}
+}
 
 /// Gets a route from us (as specified in the provided NetworkGraph) to the given target node.
 ///
@@ -160,10 +299,138 @@ struct DummyDirectionalChannelInfo {
 /// The fees on channels from us to next-hops are ignored (as they are assumed to all be
 /// equal), however the enabled/disabled bit on such channels as well as the htlc_minimum_msat
 /// *is* checked as they may change based on the receiving node.
-pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, first_hops: Option<&[channelmanager::ChannelDetails]>,
-	last_hops: &[RouteHint], final_value_msat: u64, final_cltv: u32, logger: L) -> Result<Route, LightningError> where L::Target: Logger {
-	// TODO: Obviously *only* using total fee cost sucks. We should consider weighting by
-	// uptime/success in using a node in the past.
+///
+/// Channels in the network graph are never used to forward more than their htlc_maximum_msat
+/// or, if it was looked up on-chain, their capacity.
+///
+/// The returned Route always contains a single path and does not consider whether our channels
+/// have enough outbound capacity to send final_value_msat. See get_multi_path_route for that.
+///
+/// Paths are selected to minimize the sum of the fees paid and the penalties the given scorer
+/// assigns to each channel along them, though the penalties are never actually paid.
+pub fn get_route<L: Deref, S: Score + ?Sized>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, first_hops: Option<&[channelmanager::ChannelDetails]>,
+	last_hops: &[RouteHint], final_value_msat: u64, final_cltv: u32, logger: L, scorer: &S) -> Result<Route, LightningError> where L::Target: Logger {
+	get_route_with_params(our_node_id, network, target, first_hops, last_hops, final_value_msat, final_cltv, &RouteParameters::default(), logger, scorer)
+}
+
+/// Gets a route as get_route does, but only using paths which satisfy the given RouteParameters.
+///
+/// Note that the path is still selected greedily, so we may fail to find a route even though one
+/// satisfying the parameters exists, if a cheaper path to some intermediate node violates them.
+pub fn get_route_with_params<L: Deref, S: Score + ?Sized>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, first_hops: Option<&[channelmanager::ChannelDetails]>,
+	last_hops: &[RouteHint], final_value_msat: u64, final_cltv: u32, params: &RouteParameters, logger: L, scorer: &S) -> Result<Route, LightningError> where L::Target: Logger {
+	let path = find_path(our_node_id, network, target, first_hops, last_hops, final_value_msat, final_cltv, None, params, params.max_total_fee_msat, scorer)?;
+	let route = Route { paths: vec![path.hops] };
+	log_trace!(logger, "Got route: {}", log_route!(route));
+	Ok(route)
+}
+
+/// Gets a route from us (as specified in the provided NetworkGraph) to the given target node,
+/// splitting final_value_msat across several paths if no single path can carry all of it.
+///
+/// Paths are selected one at a time, each being the cheapest path which can carry the value not
+/// yet allocated to previous paths (or, if there is none, some fraction of it). The liquidity each
+/// path uses is then deducted from the channels along it before the next one is selected. The
+/// liquidity in our own channels is taken from ChannelDetails::outbound_capacity_msat, so you
+/// almost certainly want to fill in first_hops with the results of a local
+/// ChannelManager::list_usable_channels() call, otherwise we have no idea how much can be sent
+/// over each of our channels. Channels further along a path are currently assumed to have all
+/// of their capacity available (or unlimited liquidity, if their capacity is unknown), though
+/// no path will send more than a channel's htlc_maximum_msat over it.
+///
+/// At most 10 paths will be used (the limit ChannelManager::send_payment places on a Route),
+/// failing if they are not enough to carry final_value_msat.
+///
+/// Note that if the resulting Route contains more than one path, the payment can only be sent if
+/// the recipient's invoice provided a payment_secret and set the basic_mpp feature, see
+/// ChannelManager::send_payment.
+///
+/// The remaining parameters and caveats are the same as for get_route.
+pub fn get_multi_path_route<L: Deref, S: Score + ?Sized>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, first_hops: Option<&[channelmanager::ChannelDetails]>,
+	last_hops: &[RouteHint], final_value_msat: u64, final_cltv: u32, logger: L, scorer: &S) -> Result<Route, LightningError> where L::Target: Logger {
+	get_multi_path_route_with_params(our_node_id, network, target, first_hops, last_hops, final_value_msat, final_cltv, &RouteParameters::default(), logger, scorer)
+}
+
+/// Gets a route as get_multi_path_route does, but only using paths which satisfy the given
+/// RouteParameters. The fee budget applies to the total fees of all paths.
+pub fn get_multi_path_route_with_params<L: Deref, S: Score + ?Sized>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, first_hops: Option<&[channelmanager::ChannelDetails]>,
+	last_hops: &[RouteHint], final_value_msat: u64, final_cltv: u32, params: &RouteParameters, logger: L, scorer: &S) -> Result<Route, LightningError> where L::Target: Logger {
+	if final_value_msat == 0 {
+		return Err(LightningError{err: "Cannot send a payment of 0 msat", action: ErrorAction::IgnoreError});
+	}
+	if final_value_msat > 21_000_000 * 1_0000_0000 * 1000 {
+		return Err(LightningError{err: "Cannot generate a route of more value than all existing satoshis", action: ErrorAction::IgnoreError});
+	}
+	if params.max_path_count == 0 {
+		return Err(LightningError{err: "Cannot generate a route with a maximum path count of zero", action: ErrorAction::IgnoreError});
+	}
+	if params.max_path_count > MAX_PATH_COUNT {
+		return Err(LightningError{err: "Cannot generate a route with a maximum path count above 10 paths", action: ErrorAction::IgnoreError});
+	}
+
+	// Liquidity already allocated to previously-selected paths, by short_channel_id and the node
+	// at the sending end of the channel.
+	let mut used_liquidity = HashMap::new();
+	let mut paths = Vec::new();
+	let mut remaining_value_msat = final_value_msat;
+	let mut remaining_fee_msat = params.max_total_fee_msat;
+	let mut path_value_msat = final_value_msat;
+	while remaining_value_msat > 0 {
+		if paths.len() >= params.max_path_count {
+			return Err(LightningError{err: "Failed to find a set of paths with enough liquidity within the maximum path count", action: ErrorAction::IgnoreError});
+		}
+		let path = match find_path(our_node_id, network, target, first_hops, last_hops, path_value_msat, final_cltv, Some(&used_liquidity), params, remaining_fee_msat, scorer) {
+			Ok(path) => path,
+			Err(e) => {
+				// Look for a path carrying a smaller part of the remaining value, as long as the
+				// paths we have left could still carry all of it in parts of that size.
+				let smaller_value_msat = path_value_msat / 2;
+				let paths_left = (params.max_path_count - paths.len()) as u64;
+				if smaller_value_msat == 0 || smaller_value_msat.saturating_mul(paths_left) < remaining_value_msat {
+					// Errors other than a plain lack of paths (eg violating the route parameters)
+					// say more about why we failed than a lack of liquidity does.
+					if (paths.is_empty() && path_value_msat == final_value_msat) || e.err != NO_PATH_ERR {
+						return Err(e);
+					}
+					return Err(LightningError{err: "Failed to find a set of paths with enough liquidity to the given destination", action: ErrorAction::IgnoreError});
+				}
+				path_value_msat = smaller_value_msat;
+				continue;
+			}
+		};
+
+		let value_msat = path.max_value_msat(path_value_msat, remaining_value_msat, remaining_fee_msat);
+		let hops = path.into_route_hops(value_msat);
+		let mut amount_msat = 0;
+		for (idx, hop) in hops.iter().enumerate().rev() {
+			amount_msat += hop.fee_msat;
+			let src_node_id = if idx == 0 { *our_node_id } else { hops[idx - 1].pubkey };
+			*used_liquidity.entry((hop.short_channel_id, src_node_id)).or_insert(0) += amount_msat;
+		}
+		paths.push(hops);
+		remaining_fee_msat -= amount_msat - value_msat;
+		remaining_value_msat -= value_msat;
+		path_value_msat = remaining_value_msat;
+	}
+
+	let route = Route { paths };
+	log_trace!(logger, "Got route: {}", log_route!(route));
+	Ok(route)
+}
+
+/// The error find_path returns if no path to the target exists, as opposed to none satisfying the
+/// route parameters.
+const NO_PATH_ERR: &str = "Failed to find a path to the given destination";
+
+/// Finds the cheapest path which can carry final_value_msat to the target. If used_liquidity is
+/// provided, channels are only used if the liquidity not yet used by other paths is enough to
+/// carry the value we'd send over them. The path must satisfy params, paying no more than
+/// max_fee_msat in fees.
+fn find_path<S: Score + ?Sized>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, first_hops: Option<&[channelmanager::ChannelDetails]>,
+	last_hops: &[RouteHint], final_value_msat: u64, final_cltv: u32, used_liquidity: Option<&HashMap<(u64, PublicKey), u64>>,
+	params: &RouteParameters, max_fee_msat: u64, scorer: &S) -> Result<PathCandidate, LightningError> {
+	// Paths are weighted by their total fee plus the penalties the scorer assigns to each channel
+	// along them, letting the scorer account for our past success in using them.
 	if *target == *our_node_id {
 		return Err(LightningError{err: "Cannot generate a route to ourselves", action: ErrorAction::IgnoreError});
 	}
@@ -172,6 +439,26 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 		return Err(LightningError{err: "Cannot generate a route of more value than all existing satoshis", action: ErrorAction::IgnoreError});
 	}
 
+	if params.excluded_nodes.contains(target) {
+		return Err(LightningError{err: "Cannot generate a route to an excluded node", action: ErrorAction::IgnoreError});
+	}
+
+	if final_cltv > params.max_total_cltv_expiry_delta {
+		return Err(LightningError{err: "Cannot generate a route with a final CLTV delta above the maximum total CLTV delta", action: ErrorAction::IgnoreError});
+	}
+
+	if params.max_path_length == 0 {
+		return Err(LightningError{err: "Cannot generate a route with a maximum path length of zero", action: ErrorAction::IgnoreError});
+	}
+	if params.max_path_length > MAX_PATH_LENGTH {
+		return Err(LightningError{err: "Cannot generate a route with a maximum path length above 20 hops", action: ErrorAction::IgnoreError});
+	}
+
+	let excluded_channels = &params.excluded_channels;
+	// The last route parameter a path was rejected for violating, if any, so that we can say why
+	// we failed to find a path.
+	let mut violated_param_err = None;
+
 	// We do a dest-to-source Dijkstra's sorting by each node's distance from the destination
 	// plus the minimum per-HTLC fee to get from it to another node (aka "shitty A*").
 	// TODO: There are a few tweaks we could do, including possibly pre-calculating more stuff
@@ -187,6 +474,22 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 		}
 	};
 
+	// Gets the liquidity in a channel which hasn't been used by previously-selected paths.
+	let available_liquidity = |short_channel_id: u64, src_node_id: &PublicKey, capacity_msat: u64| -> u64 {
+		match used_liquidity {
+			Some(used) => capacity_msat.saturating_sub(*used.get(&(short_channel_id, *src_node_id)).unwrap_or(&0)),
+			None => capacity_msat,
+		}
+	};
+
+	// Gets the most we can send over a channel in the network graph in a single HTLC, given the
+	// liquidity left in it and the limit its last update set.
+	let graph_channel_limit = |short_channel_id: u64, src_node_id: &PublicKey, chan: &ChannelInfo, directional_info: &DirectionalChannelInfo| -> u64 {
+		let capacity_msat = chan.capacity_sats.map(|capacity_sats| capacity_sats.saturating_mul(1000)).unwrap_or(u64::max_value());
+		cmp::min(available_liquidity(short_channel_id, src_node_id, capacity_msat),
+			directional_info.htlc_maximum_msat.unwrap_or(u64::max_value()))
+	};
+
 	let mut targets = BinaryHeap::new(); //TODO: Do we care about switching to eg Fibbonaci heap?
 	let mut dist = HashMap::with_capacity(network.get_nodes().len());
 
@@ -194,19 +497,24 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 	if let Some(hops) = first_hops {
 		for chan in hops {
 			let short_channel_id = chan.short_channel_id.expect("first_hops should be filled in with usable channels, not pending ones");
-			if chan.remote_network_id == *target {
-				return Ok(Route {
-					paths: vec![vec![RouteHop {
+			if excluded_channels.contains(&short_channel_id) {
+				continue;
+			}
+			if chan.remote_network_id == *target && used_liquidity.is_none() {
+				return Ok(PathCandidate {
+					hops: vec![RouteHop {
 						pubkey: chan.remote_network_id,
 						node_features: chan.counterparty_features.to_context(),
 						short_channel_id,
 						channel_features: chan.counterparty_features.to_context(),
 						fee_msat: final_value_msat,
 						cltv_expiry_delta: final_cltv,
-					}]],
+					}],
+					inbound_channel_fees: vec![dummy_directional_info.fees],
+					available_liquidity_msat: vec![u64::max_value()],
 				});
 			}
-			first_hop_targets.insert(chan.remote_network_id, (short_channel_id, chan.counterparty_features.clone()));
+			first_hop_targets.entry(chan.remote_network_id).or_insert_with(|| Vec::new()).push(chan);
 		}
 		if first_hop_targets.is_empty() {
 			return Err(LightningError{err: "Cannot route when there are no outbound routes away from us", action: ErrorAction::IgnoreError});
@@ -216,10 +524,15 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 	macro_rules! add_entry {
 		// Adds entry which goes from $src_node_id to $dest_node_id
 		// over the channel with id $chan_id with fees described in
-		// $directional_info.
-		( $chan_id: expr, $src_node_id: expr, $dest_node_id: expr, $directional_info: expr, $chan_features: expr, $starting_fee_msat: expr ) => {
+		// $directional_info, if the value we'd send over it is no more than
+		// $available_liquidity_msat (its liquidity left, or the most it will relay in one HTLC).
+		// $path_penalty_msat is the sum of the scorer's penalties from $dest_node_id to the target,
+		// $cltv_to_target the total CLTV delta and $path_length the number of hops from it.
+		( $chan_id: expr, $src_node_id: expr, $dest_node_id: expr, $directional_info: expr, $chan_features: expr, $starting_fee_msat: expr, $available_liquidity_msat: expr, $path_penalty_msat: expr, $cltv_to_target: expr, $path_length: expr ) => {
 			//TODO: Explore simply adding fee to hit htlc_minimum_msat
-			if $starting_fee_msat as u64 + final_value_msat >= $directional_info.htlc_minimum_msat {
+			if !excluded_channels.contains(&$chan_id.clone()) && !params.excluded_nodes.contains(&$src_node_id) &&
+					$starting_fee_msat as u64 + final_value_msat >= $directional_info.htlc_minimum_msat &&
+					$starting_fee_msat as u64 + final_value_msat <= $available_liquidity_msat {
 				let proportional_fee_millions = ($starting_fee_msat + final_value_msat).checked_mul($directional_info.fees.proportional_millionths as u64);
 				if let Some(new_fee) = proportional_fee_millions.and_then(|part| {
 						($directional_info.fees.base_msat as u64).checked_add(part / 1000000) })
@@ -227,64 +540,116 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 					let mut total_fee = $starting_fee_msat as u64;
 					let hm_entry = dist.entry(&$src_node_id);
 					let old_entry = hm_entry.or_insert_with(|| {
-						let node = network.get_nodes().get(&$src_node_id).unwrap();
 						let mut fee_base_msat = u32::max_value();
 						let mut fee_proportional_millionths = u32::max_value();
-						if let Some(fees) = node.lowest_inbound_channel_fees {
+						if let Some(fees) = network.get_nodes().get(&$src_node_id).and_then(|node| node.lowest_inbound_channel_fees) {
 							fee_base_msat = fees.base_msat;
 							fee_proportional_millionths = fees.proportional_millionths;
 						};
-						(u64::max_value(),
-							fee_base_msat,
-							fee_proportional_millionths,
-							RouteHop {
+						PathBuildingHop {
+							lowest_fee_to_peer_through_node: u64::max_value(),
+							lowest_inbound_channel_fees: RoutingFees {
+								base_msat: fee_base_msat,
+								proportional_millionths: fee_proportional_millionths,
+							},
+							route_hop: RouteHop {
 								pubkey: $dest_node_id.clone(),
 								node_features: NodeFeatures::empty(),
 								short_channel_id: 0,
 								channel_features: $chan_features.clone(),
 								fee_msat: 0,
 								cltv_expiry_delta: 0,
-						})
+							},
+							channel_fees: $directional_info.fees,
+							available_liquidity_msat: 0,
+						}
 					});
 					if $src_node_id != *our_node_id {
 						// Ignore new_fee for channel-from-us as we assume all channels-from-us
 						// will have the same effective-fee
 						total_fee += new_fee;
-						if let Some(fee_inc) = final_value_msat.checked_add(total_fee).and_then(|inc| { (old_entry.2 as u64).checked_mul(inc) }) {
-							total_fee += fee_inc / 1000000 + (old_entry.1 as u64);
+						if let Some(fee_inc) = final_value_msat.checked_add(total_fee).and_then(|inc| { (old_entry.lowest_inbound_channel_fees.proportional_millionths as u64).checked_mul(inc) }) {
+							total_fee += fee_inc / 1000000 + (old_entry.lowest_inbound_channel_fees.base_msat as u64);
 						} else {
-							// max_value means we'll always fail the old_entry.0 > total_fee check
+							// max_value means we'll always fail the old_entry.lowest_fee_to_peer_through_node > total_fee check
 							total_fee = u64::max_value();
 						}
 					}
+					// Penalties only affect which path we select, they aren't actually paid.
+					let path_penalty_msat = ($path_penalty_msat as u64).saturating_add(scorer.channel_penalty_msat($chan_id.clone(), &$src_node_id, &$dest_node_id));
+					total_fee = total_fee.saturating_add(path_penalty_msat);
+
+					// As with fees, the CLTV delta of channels from us isn't actually used. Paths
+					// through other nodes need room left for at least our hop to them.
+					let (fee_to_src_msat, cltv_to_src, max_path_length_to_src) = if $src_node_id != *our_node_id {
+						(($starting_fee_msat as u64).saturating_add(new_fee),
+							($cltv_to_target as u32).saturating_add($directional_info.cltv_expiry_delta as u32), params.max_path_length - 1)
+					} else {
+						($starting_fee_msat as u64, $cltv_to_target as u32, params.max_path_length)
+					};
+					let path_length_to_src = ($path_length as u8).saturating_add(1);
+					if fee_to_src_msat > max_fee_msat {
+						violated_param_err = Some("Failed to find a path to the given destination within the maximum total fee");
+					} else if cltv_to_src > params.max_total_cltv_expiry_delta {
+						violated_param_err = Some("Failed to find a path to the given destination within the maximum total CLTV delta");
+					} else if path_length_to_src > max_path_length_to_src {
+						violated_param_err = Some("Failed to find a path to the given destination within the maximum path length");
+					}
+
 					let new_graph_node = RouteGraphNode {
 						pubkey: $src_node_id,
 						lowest_fee_to_peer_through_node: total_fee,
 						lowest_fee_to_node: $starting_fee_msat as u64 + new_fee,
+						path_penalty_msat,
+						cltv_to_target: cltv_to_src,
+						path_length: path_length_to_src,
 					};
-					if old_entry.0 > total_fee {
+					if fee_to_src_msat <= max_fee_msat && cltv_to_src <= params.max_total_cltv_expiry_delta &&
+							path_length_to_src <= max_path_length_to_src &&
+							old_entry.lowest_fee_to_peer_through_node > total_fee {
 						targets.push(new_graph_node);
-						old_entry.0 = total_fee;
-						old_entry.3 = RouteHop {
+						old_entry.lowest_fee_to_peer_through_node = total_fee;
+						old_entry.route_hop = RouteHop {
 							pubkey: $dest_node_id.clone(),
 							node_features: NodeFeatures::empty(),
 							short_channel_id: $chan_id.clone(),
 							channel_features: $chan_features.clone(),
 							fee_msat: new_fee, // This field is ignored on the last-hop anyway
 							cltv_expiry_delta: $directional_info.cltv_expiry_delta as u32,
-						}
+						};
+						old_entry.channel_fees = $directional_info.fees;
+						old_entry.available_liquidity_msat = $available_liquidity_msat;
 					}
 				}
 			}
 		};
 	}
 
+	macro_rules! add_first_hop_entry {
+		( $node_id: expr, $fee_to_target_msat: expr, $path_penalty_msat: expr, $cltv_to_target: expr, $path_length: expr ) => {
+			if let Some(chans) = first_hop_targets.get(&$node_id) {
+				// If we have several channels to the same peer, use the one with the most liquidity
+				// left (or the last one, if we aren't tracking liquidity).
+				let (first_hop, liquidity_msat) = chans.iter()
+					.map(|chan| {
+						let short_channel_id = chan.short_channel_id.unwrap();
+						let capacity_msat = if used_liquidity.is_some() { chan.outbound_capacity_msat } else { u64::max_value() };
+						(short_channel_id, available_liquidity(short_channel_id, our_node_id, capacity_msat))
+					}).max_by_key(|&(_, liquidity_msat)| liquidity_msat).unwrap();
+				// Currently there are no channel-context features defined, so we are a
+				// bit lazy here. In the future, we should pull them out via our
+				// ChannelManager, but there's no reason to waste the space until we
+				// need them.
+				let features = &chans[0].counterparty_features;
+				add_entry!(first_hop, *our_node_id, $node_id, dummy_directional_info, features.to_context(), $fee_to_target_msat, liquidity_msat, $path_penalty_msat, $cltv_to_target, $path_length);
+			}
+		};
+	}
+
 	macro_rules! add_entries_to_cheapest_to_target_node {
-		( $node: expr, $node_id: expr, $fee_to_target_msat: expr ) => {
+		( $node: expr, $node_id: expr, $fee_to_target_msat: expr, $path_penalty_msat: expr, $cltv_to_target: expr, $path_length: expr ) => {
 			if first_hops.is_some() {
-				if let Some(&(ref first_hop, ref features)) = first_hop_targets.get(&$node_id) {
-					add_entry!(first_hop, *our_node_id, $node_id, dummy_directional_info, features.to_context(), $fee_to_target_msat);
-				}
+				add_first_hop_entry!($node_id, $fee_to_target_msat, $path_penalty_msat, $cltv_to_target, $path_length);
 			}
 
 			let features;
@@ -303,7 +668,8 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 							if first_hops.is_none() || chan.node_two != *our_node_id {
 								if let Some(two_to_one) = chan.two_to_one.as_ref() {
 									if two_to_one.enabled {
-										add_entry!(chan_id, chan.node_two, chan.node_one, two_to_one, chan.features, $fee_to_target_msat);
+										add_entry!(chan_id, chan.node_two, chan.node_one, two_to_one, chan.features, $fee_to_target_msat,
+											graph_channel_limit(*chan_id, &chan.node_two, chan, two_to_one), $path_penalty_msat, $cltv_to_target, $path_length);
 									}
 								}
 							}
@@ -311,7 +677,8 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 							if first_hops.is_none() || chan.node_one != *our_node_id {
 								if let Some(one_to_two) = chan.one_to_two.as_ref() {
 									if one_to_two.enabled {
-										add_entry!(chan_id, chan.node_one, chan.node_two, one_to_two, chan.features, $fee_to_target_msat);
+										add_entry!(chan_id, chan.node_one, chan.node_two, one_to_two, chan.features, $fee_to_target_msat,
+											graph_channel_limit(*chan_id, &chan.node_one, chan, one_to_two), $path_penalty_msat, $cltv_to_target, $path_length);
 									}
 								}
 
@@ -324,9 +691,12 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 	}
 
 	match network.get_nodes().get(target) {
-		None => {},
+		None => {
+			// We may still have direct channels to the target if we're tracking liquidity.
+			add_first_hop_entry!(target, 0, 0, final_cltv, 0);
+		},
 		Some(node) => {
-			add_entries_to_cheapest_to_target_node!(node, target, 0);
+			add_entries_to_cheapest_to_target_node!(node, target, 0, 0, final_cltv, 0);
 		},
 	}
 
@@ -334,27 +704,25 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 		if first_hops.is_none() || hop.src_node_id != *our_node_id { // first_hop overrules last_hops
 			if network.get_nodes().get(&hop.src_node_id).is_some() {
 				if first_hops.is_some() {
-					if let Some(&(ref first_hop, ref features)) = first_hop_targets.get(&hop.src_node_id) {
-						// Currently there are no channel-context features defined, so we are a
-						// bit lazy here. In the future, we should pull them out via our
-						// ChannelManager, but there's no reason to waste the space until we
-						// need them.
-						add_entry!(first_hop, *our_node_id , hop.src_node_id, dummy_directional_info, features.to_context(), 0);
-					}
+					add_first_hop_entry!(hop.src_node_id, 0, 0, final_cltv, 0);
 				}
 				// BOLT 11 doesn't allow inclusion of features for the last hop hints, which
 				// really sucks, cause we're gonna need that eventually.
-				add_entry!(hop.short_channel_id, hop.src_node_id, target, hop, ChannelFeatures::empty(), 0);
+				add_entry!(hop.short_channel_id, hop.src_node_id, target, hop, ChannelFeatures::empty(), 0,
+					available_liquidity(hop.short_channel_id, &hop.src_node_id, u64::max_value()), 0, final_cltv, 0);
 			}
 		}
 	}
 
-	while let Some(RouteGraphNode { pubkey, lowest_fee_to_node, .. }) = targets.pop() {
+	while let Some(RouteGraphNode { pubkey, lowest_fee_to_node, path_penalty_msat, cltv_to_target, path_length, .. }) = targets.pop() {
 		if pubkey == *our_node_id {
-			let mut res = vec!(dist.remove(&our_node_id).unwrap().3);
+			let first_hop = dist.remove(&our_node_id).unwrap();
+			let mut res = vec!(first_hop.route_hop);
+			let mut inbound_channel_fees = vec!(first_hop.channel_fees);
+			let mut available_liquidity_msat = vec!(first_hop.available_liquidity_msat);
 			loop {
-				if let Some(&(_, ref features)) = first_hop_targets.get(&res.last().unwrap().pubkey) {
-					res.last_mut().unwrap().node_features = features.to_context();
+				if let Some(chans) = first_hop_targets.get(&res.last().unwrap().pubkey) {
+					res.last_mut().unwrap().node_features = chans[0].counterparty_features.to_context();
 				} else if let Some(node) = network.get_nodes().get(&res.last().unwrap().pubkey) {
 					if let Some(node_info) = node.announcement_info.as_ref() {
 						res.last_mut().unwrap().node_features = node_info.features.clone();
@@ -373,39 +741,40 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 				}
 
 				let new_entry = match dist.remove(&res.last().unwrap().pubkey) {
-					Some(hop) => hop.3,
+					Some(hop) => hop,
 					None => return Err(LightningError{err: "Failed to find a non-fee-overflowing path to the given destination", action: ErrorAction::IgnoreError}),
 				};
-				res.last_mut().unwrap().fee_msat = new_entry.fee_msat;
-				res.last_mut().unwrap().cltv_expiry_delta = new_entry.cltv_expiry_delta;
-				res.push(new_entry);
+				res.last_mut().unwrap().fee_msat = new_entry.route_hop.fee_msat;
+				res.last_mut().unwrap().cltv_expiry_delta = new_entry.route_hop.cltv_expiry_delta;
+				inbound_channel_fees.push(new_entry.channel_fees);
+				available_liquidity_msat.push(new_entry.available_liquidity_msat);
+				res.push(new_entry.route_hop);
 			}
 			res.last_mut().unwrap().fee_msat = final_value_msat;
 			res.last_mut().unwrap().cltv_expiry_delta = final_cltv;
-			let route = Route { paths: vec![res] };
-			log_trace!(logger, "Got route: {}", log_route!(route));
-			return Ok(route);
+			return Ok(PathCandidate { hops: res, inbound_channel_fees, available_liquidity_msat });
 		}
 
 		match network.get_nodes().get(&pubkey) {
 			None => {},
 			Some(node) => {
-				add_entries_to_cheapest_to_target_node!(node, &pubkey, lowest_fee_to_node);
+				add_entries_to_cheapest_to_target_node!(node, &pubkey, lowest_fee_to_node, path_penalty_msat, cltv_to_target, path_length);
 			},
 		}
 	}
 
-	Err(LightningError{err: "Failed to find a path to the given destination", action: ErrorAction::IgnoreError})
+	Err(LightningError{err: violated_param_err.unwrap_or(NO_PATH_ERR), action: ErrorAction::IgnoreError})
 }
 
 #[cfg(test)]
 mod tests {
 	use chain::chaininterface;
-	use routing::router::{get_route, RouteHint, RoutingFees};
+	use routing::router::{get_route, get_multi_path_route, get_route_with_params, get_multi_path_route_with_params, RouteHint, RouteParameters, RoutingFees};
 	use routing::network_graph::NetGraphMsgHandler;
+	use routing::scorer::{Score, Scorer, ScoringParameters};
 	use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
 	use ln::msgs::{ErrorAction, LightningError, UnsignedChannelAnnouncement, ChannelAnnouncement, RoutingMessageHandler,
-	   NodeAnnouncement, UnsignedNodeAnnouncement, ChannelUpdate, UnsignedChannelUpdate};
+	   NodeAnnouncement, UnsignedNodeAnnouncement, ChannelUpdate, UnsignedChannelUpdate, HTLCFailChannelUpdate, OptionalField};
 	use ln::channelmanager;
 	use util::test_utils;
 	use util::ser::Writeable;
@@ -500,8 +869,9 @@ mod tests {
 		let our_privkey = &SecretKey::from_slice(&hex::decode("0101010101010101010101010101010101010101010101010101010101010101").unwrap()[..]).unwrap();
 		let our_id = PublicKey::from_secret_key(&secp_ctx, our_privkey);
 		let logger = Arc::new(test_utils::TestLogger::new());
+		let scorer = test_utils::TestScorer::new();
 		let chain_monitor = Arc::new(chaininterface::ChainWatchInterfaceUtil::new(Network::Testnet));
-		let net_graph_msg_handler = NetGraphMsgHandler::new(chain_monitor, Arc::clone(&logger));
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.bitcoin_hash(), chain_monitor, Arc::clone(&logger));
 		// Build network from our_id to node8:
 		//
 		//        -1(1)2-  node1  -1(3)2-
@@ -605,6 +975,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		add_or_update_node(&net_graph_msg_handler, &secp_ctx, node1_privkey, NodeFeatures::from_le_bytes(id_to_feature_flags!(1)), 0);
@@ -619,6 +990,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: u32::max_value(),
 			fee_proportional_millionths: u32::max_value(),
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		update_channel(&net_graph_msg_handler, &secp_ctx, node2_privkey, UnsignedChannelUpdate {
@@ -630,6 +1002,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 
@@ -645,6 +1018,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: u32::max_value(),
 			fee_proportional_millionths: u32::max_value(),
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		update_channel(&net_graph_msg_handler, &secp_ctx, node8_privkey, UnsignedChannelUpdate {
@@ -656,6 +1030,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 
@@ -672,6 +1047,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		update_channel(&net_graph_msg_handler, &secp_ctx, node3_privkey, UnsignedChannelUpdate {
@@ -683,6 +1059,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 100,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 
@@ -697,6 +1074,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 1000000,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		update_channel(&net_graph_msg_handler, &secp_ctx, node3_privkey, UnsignedChannelUpdate {
@@ -708,6 +1086,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 
@@ -721,6 +1100,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 2000000,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		update_channel(&net_graph_msg_handler, &secp_ctx, node3_privkey, UnsignedChannelUpdate {
@@ -732,6 +1112,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		add_or_update_node(&net_graph_msg_handler, &secp_ctx, node3_privkey, NodeFeatures::from_le_bytes(id_to_feature_flags!(3)), 0);
@@ -746,6 +1127,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		update_channel(&net_graph_msg_handler, &secp_ctx, node5_privkey, UnsignedChannelUpdate {
@@ -757,6 +1139,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 
@@ -770,6 +1153,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		update_channel(&net_graph_msg_handler, &secp_ctx, node4_privkey, UnsignedChannelUpdate {
@@ -781,6 +1165,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		add_or_update_node(&net_graph_msg_handler, &secp_ctx, node5_privkey, NodeFeatures::from_le_bytes(id_to_feature_flags!(5)), 0);
@@ -797,6 +1182,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 1000000,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		update_channel(&net_graph_msg_handler, &secp_ctx, node6_privkey, UnsignedChannelUpdate {
@@ -808,13 +1194,14 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 
 		add_or_update_node(&net_graph_msg_handler, &secp_ctx, node6_privkey, NodeFeatures::from_le_bytes(id_to_feature_flags!(6)), 0);
 
 		// Simple route to 3 via 2
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, None, &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, node2);
@@ -842,6 +1229,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		update_channel(&net_graph_msg_handler, &secp_ctx, our_privkey, UnsignedChannelUpdate {
@@ -853,11 +1241,12 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 
 		// If all the channels require some features we don't understand, route should fail
-		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, None, &Vec::new(), 100, 42, Arc::clone(&logger)) {
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer) {
 			assert_eq!(err, "Failed to find a path to the given destination");
 		} else { panic!(); }
 
@@ -873,7 +1262,7 @@ mod tests {
 			inbound_capacity_msat: 0,
 			is_live: true,
 		}];
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans),  &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans),  &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, node8);
@@ -900,6 +1289,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 1000000,
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		update_channel(&net_graph_msg_handler, &secp_ctx, our_privkey, UnsignedChannelUpdate {
@@ -911,6 +1301,7 @@ mod tests {
 			htlc_minimum_msat: 0,
 			fee_base_msat: u32::max_value(),
 			fee_proportional_millionths: u32::max_value(),
+			htlc_maximum_msat: OptionalField::Absent,
 			excess_data: Vec::new()
 		});
 		// Disable nodes 1, 2, and 8 by requiring unknown feature bits
@@ -921,7 +1312,7 @@ mod tests {
 		add_or_update_node(&net_graph_msg_handler, &secp_ctx, node8_privkey, unknown_features.clone(), 1);
 
 		// // If all nodes require some features we don't understand, route should fail
-		// if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler, &node3, None, &Vec::new(), 100, 42, Arc::clone(&logger)) {
+		// if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler, &node3, None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer) {
 		// 	assert_eq!(err, "Failed to find a path to the given destination");
 		// } else { panic!(); }
 
@@ -937,7 +1328,7 @@ mod tests {
 			inbound_capacity_msat: 0,
 			is_live: true,
 		}];
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, node8);
@@ -964,7 +1355,7 @@ mod tests {
 		// the node_announcement.
 
 		// Route to 1 via 2 and 3 because our channel to 1 is disabled
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node1, None, &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node1, None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 3);
 
 		assert_eq!(route.paths[0][0].pubkey, node2);
@@ -1000,7 +1391,7 @@ mod tests {
 			inbound_capacity_msat: 0,
 			is_live: true,
 		}];
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, node8);
@@ -1045,7 +1436,7 @@ mod tests {
 			});
 
 		// Simple test across 2, 3, 5, and 4 via a last_hop channel
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node7, None, &last_hops, 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node7, None, &last_hops, 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 5);
 
 		assert_eq!(route.paths[0][0].pubkey, node2);
@@ -1097,7 +1488,7 @@ mod tests {
 			inbound_capacity_msat: 0,
 			is_live: true,
 		}];
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node7, Some(&our_chans), &last_hops, 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node7, Some(&our_chans), &last_hops, 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, node4);
@@ -1117,7 +1508,7 @@ mod tests {
 		last_hops[0].fees.base_msat = 1000;
 
 		// Revert to via 6 as the fee on 8 goes up
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node7, None, &last_hops, 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node7, None, &last_hops, 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 4);
 
 		assert_eq!(route.paths[0][0].pubkey, node2);
@@ -1151,7 +1542,7 @@ mod tests {
 		assert_eq!(route.paths[0][3].channel_features.le_flags(), &Vec::new()); // We can't learn any flags from invoices, sadly
 
 		// ...but still use 8 for larger payments as 6 has a variable feerate
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node7, None, &last_hops, 2000, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node7, None, &last_hops, 2000, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 5);
 
 		assert_eq!(route.paths[0][0].pubkey, node2);
@@ -1191,4 +1582,374 @@ mod tests {
 		assert_eq!(route.paths[0][4].node_features.le_flags(), &Vec::new()); // We dont pass flags in from invoices yet
 		assert_eq!(route.paths[0][4].channel_features.le_flags(), &Vec::new()); // We can't learn any flags from invoices, sadly
 	}
+
+	#[test]
+	fn multi_path_route_test() {
+		let secp_ctx = Secp256k1::new();
+		let our_privkey = &SecretKey::from_slice(&[1; 32]).unwrap();
+		let our_id = PublicKey::from_secret_key(&secp_ctx, our_privkey);
+		let logger = Arc::new(test_utils::TestLogger::new());
+		let scorer = test_utils::TestScorer::new();
+		let chain_monitor = Arc::new(chaininterface::ChainWatchInterfaceUtil::new(Network::Testnet));
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.bitcoin_hash(), chain_monitor, Arc::clone(&logger));
+		// Build network from our_id to node3, with node4 only reachable via our own channels:
+		//
+		//        -(1)- node1 -1(3)2-
+		//       /                   \
+		// our_id                     node3
+		//      |\                   /
+		//      | -(2)- node2 -1(4)2-
+		//      |
+		//       -(5, 6)- node4 (not in global route map)
+		//
+		// chan3 1-to-2: enabled, 0 fee
+		// chan4 1-to-2: enabled, 1000 msat fee
+		let node1_privkey = &SecretKey::from_slice(&[2; 32]).unwrap();
+		let node2_privkey = &SecretKey::from_slice(&[3; 32]).unwrap();
+		let node3_privkey = &SecretKey::from_slice(&[4; 32]).unwrap();
+		let node1 = PublicKey::from_secret_key(&secp_ctx, node1_privkey);
+		let node2 = PublicKey::from_secret_key(&secp_ctx, node2_privkey);
+		let node3 = PublicKey::from_secret_key(&secp_ctx, node3_privkey);
+		let node4 = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[5; 32]).unwrap());
+
+		add_channel(&net_graph_msg_handler, &secp_ctx, node1_privkey, node3_privkey, ChannelFeatures::empty(), 3);
+		update_channel(&net_graph_msg_handler, &secp_ctx, node1_privkey, UnsignedChannelUpdate {
+			chain_hash: genesis_block(Network::Testnet).header.bitcoin_hash(),
+			short_channel_id: 3,
+			timestamp: 1,
+			flags: 0,
+			cltv_expiry_delta: 0,
+			htlc_minimum_msat: 0,
+			fee_base_msat: 0,
+			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
+			excess_data: Vec::new()
+		});
+		add_channel(&net_graph_msg_handler, &secp_ctx, node2_privkey, node3_privkey, ChannelFeatures::empty(), 4);
+		update_channel(&net_graph_msg_handler, &secp_ctx, node2_privkey, UnsignedChannelUpdate {
+			chain_hash: genesis_block(Network::Testnet).header.bitcoin_hash(),
+			short_channel_id: 4,
+			timestamp: 1,
+			flags: 0,
+			cltv_expiry_delta: 0,
+			htlc_minimum_msat: 0,
+			fee_base_msat: 1000,
+			fee_proportional_millionths: 0,
+			htlc_maximum_msat: OptionalField::Absent,
+			excess_data: Vec::new()
+		});
+
+		macro_rules! our_chan {
+			($short_channel_id: expr, $node_id: expr, $outbound_capacity_msat: expr) => {
+				channelmanager::ChannelDetails {
+					channel_id: [$short_channel_id; 32],
+					short_channel_id: Some($short_channel_id as u64),
+					remote_network_id: $node_id.clone(),
+					counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
+					channel_value_satoshis: 0,
+					user_id: 0,
+					outbound_capacity_msat: $outbound_capacity_msat,
+					inbound_capacity_msat: 0,
+					is_live: true,
+				}
+			}
+		}
+		let our_chans = vec![our_chan!(1, node1, 60_000), our_chan!(2, node2, 50_000), our_chan!(5, node4, 30_000), our_chan!(6, node4, 30_000)];
+
+		// A payment which fits in one channel uses only the cheapest path
+		let route = get_multi_path_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 50_000, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths.len(), 1);
+		assert_eq!(route.paths[0].len(), 2);
+		assert_eq!(route.paths[0][0].short_channel_id, 1);
+		assert_eq!(route.paths[0][1].fee_msat, 50_000);
+
+		// A larger payment fills up the cheapest path and sends the rest over the more expensive one
+		let route = get_multi_path_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 100_000, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths.len(), 2);
+
+		assert_eq!(route.paths[0][0].pubkey, node1);
+		assert_eq!(route.paths[0][0].short_channel_id, 1);
+		assert_eq!(route.paths[0][0].fee_msat, 0);
+		assert_eq!(route.paths[0][1].pubkey, node3);
+		assert_eq!(route.paths[0][1].short_channel_id, 3);
+		assert_eq!(route.paths[0][1].fee_msat, 60_000);
+		assert_eq!(route.paths[0][1].cltv_expiry_delta, 42);
+
+		assert_eq!(route.paths[1][0].pubkey, node2);
+		assert_eq!(route.paths[1][0].short_channel_id, 2);
+		assert_eq!(route.paths[1][0].fee_msat, 1000);
+		assert_eq!(route.paths[1][1].pubkey, node3);
+		assert_eq!(route.paths[1][1].short_channel_id, 4);
+		assert_eq!(route.paths[1][1].fee_msat, 40_000);
+		assert_eq!(route.paths[1][1].cltv_expiry_delta, 42);
+
+		// The fee on chan4 means we can only send 109_000 msat in total
+		let route = get_multi_path_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 109_000, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths.len(), 2);
+		assert_eq!(route.paths[0][1].fee_msat, 60_000);
+		assert_eq!(route.paths[1][0].fee_msat, 1000);
+		assert_eq!(route.paths[1][1].fee_msat, 49_000);
+
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_multi_path_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 109_001, 42, Arc::clone(&logger), &scorer) {
+			assert_eq!(err, "Failed to find a set of paths with enough liquidity to the given destination");
+		} else { panic!(); }
+
+		// ...while get_route ignores liquidity entirely
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 109_001, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths.len(), 1);
+
+		// Several direct channels to a node we have no other information about are all used
+		let route = get_multi_path_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node4, Some(&our_chans), &Vec::new(), 50_000, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths.len(), 2);
+		assert_eq!(route.paths[0].len(), 1);
+		assert_eq!(route.paths[0][0].pubkey, node4);
+		assert_eq!(route.paths[0][0].fee_msat, 30_000);
+		assert_eq!(route.paths[0][0].node_features.le_flags(), &vec![0b11]);
+		assert_eq!(route.paths[1].len(), 1);
+		assert_eq!(route.paths[1][0].pubkey, node4);
+		assert_eq!(route.paths[1][0].fee_msat, 20_000);
+		assert_ne!(route.paths[0][0].short_channel_id, route.paths[1][0].short_channel_id);
+
+		// Once chan3 has failed, the scorer's penalty makes the path over chan4 cheaper despite its
+		// fee, though only the fee is actually paid
+		let scorer = Scorer::new(ScoringParameters { base_penalty_msat: 0, failure_penalty_msat: 10_000_000, ..Default::default() });
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 10_000, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0][1].short_channel_id, 3);
+		scorer.payment_path_failed(&route.paths[0], Some(&HTLCFailChannelUpdate::ChannelClosed { short_channel_id: 3, is_permanent: false }));
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 10_000, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0][0].short_channel_id, 2);
+		assert_eq!(route.paths[0][0].fee_msat, 1000);
+		assert_eq!(route.paths[0][1].short_channel_id, 4);
+		assert_eq!(route.paths[0][1].fee_msat, 10_000);
+	}
+
+	#[test]
+	fn htlc_maximum_msat_route_test() {
+		let secp_ctx = Secp256k1::new();
+		let our_privkey = &SecretKey::from_slice(&[1; 32]).unwrap();
+		let our_id = PublicKey::from_secret_key(&secp_ctx, our_privkey);
+		let logger = Arc::new(test_utils::TestLogger::new());
+		let scorer = test_utils::TestScorer::new();
+		let chain_monitor = Arc::new(chaininterface::ChainWatchInterfaceUtil::new(Network::Testnet));
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.bitcoin_hash(), chain_monitor, Arc::clone(&logger));
+		// Build network from our_id to node3:
+		//
+		//        -1(1)2- node1 -1(3)2-
+		//       /                     \
+		// our_id                       node3
+		//       \                     /
+		//        -1(2)2- node2 -1(4)2-
+		//
+		// chan3 1-to-2: enabled, 0 fee, htlc_maximum_msat 30_000
+		// chan4 1-to-2: enabled, 1000 msat fee, htlc_maximum_msat 100_000
+		let node1_privkey = &SecretKey::from_slice(&[2; 32]).unwrap();
+		let node2_privkey = &SecretKey::from_slice(&[3; 32]).unwrap();
+		let node3_privkey = &SecretKey::from_slice(&[4; 32]).unwrap();
+		let node1 = PublicKey::from_secret_key(&secp_ctx, node1_privkey);
+		let node2 = PublicKey::from_secret_key(&secp_ctx, node2_privkey);
+		let node3 = PublicKey::from_secret_key(&secp_ctx, node3_privkey);
+
+		for &(node_privkey, short_channel_id, fee_base_msat, htlc_maximum_msat) in [(node1_privkey, 3, 0, 30_000), (node2_privkey, 4, 1000, 100_000)].iter() {
+			add_channel(&net_graph_msg_handler, &secp_ctx, node_privkey, node3_privkey, ChannelFeatures::empty(), short_channel_id);
+			update_channel(&net_graph_msg_handler, &secp_ctx, node_privkey, UnsignedChannelUpdate {
+				chain_hash: genesis_block(Network::Testnet).header.bitcoin_hash(),
+				short_channel_id,
+				timestamp: 1,
+				flags: 1 << 8,
+				cltv_expiry_delta: 0,
+				htlc_minimum_msat: 0,
+				fee_base_msat,
+				fee_proportional_millionths: 0,
+				htlc_maximum_msat: OptionalField::Present(htlc_maximum_msat),
+				excess_data: Vec::new()
+			});
+		}
+		assert_eq!(net_graph_msg_handler.network_graph.read().unwrap().get_channels().get(&3).unwrap().one_to_two.as_ref().unwrap().htlc_maximum_msat, Some(30_000));
+
+		macro_rules! our_chan {
+			($short_channel_id: expr, $node_id: expr) => {
+				channelmanager::ChannelDetails {
+					channel_id: [$short_channel_id; 32],
+					short_channel_id: Some($short_channel_id as u64),
+					remote_network_id: $node_id.clone(),
+					counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
+					channel_value_satoshis: 0,
+					user_id: 0,
+					outbound_capacity_msat: 1_000_000,
+					inbound_capacity_msat: 0,
+					is_live: true,
+				}
+			}
+		}
+		let our_chans = vec![our_chan!(1, node1), our_chan!(2, node2)];
+
+		// A payment within chan3's limit takes the cheapest path...
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 30_000, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0][0].pubkey, node1);
+		assert_eq!(route.paths[0][1].short_channel_id, 3);
+
+		// ...while a larger one has to pay the fee on chan4
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 30_001, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0][0].pubkey, node2);
+		assert_eq!(route.paths[0][0].fee_msat, 1000);
+		assert_eq!(route.paths[0][1].short_channel_id, 4);
+
+		// No single path can carry more than chan4's limit
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 100_001, 42, Arc::clone(&logger), &scorer) {
+			assert_eq!(err, "Failed to find a path to the given destination");
+		} else { panic!(); }
+
+		// ...though a payment can be split, sending as much as chan4 allows over it and the rest
+		// over chan3
+		let route = get_multi_path_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 120_000, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths.len(), 2);
+		assert_eq!(route.paths[0][1].short_channel_id, 4);
+		assert_eq!(route.paths[0][1].fee_msat, 100_000);
+		assert_eq!(route.paths[1][1].short_channel_id, 3);
+		assert_eq!(route.paths[1][1].fee_msat, 20_000);
+	}
+
+	#[test]
+	fn route_parameters_test() {
+		let secp_ctx = Secp256k1::new();
+		let our_privkey = &SecretKey::from_slice(&[1; 32]).unwrap();
+		let our_id = PublicKey::from_secret_key(&secp_ctx, our_privkey);
+		let logger = Arc::new(test_utils::TestLogger::new());
+		let scorer = test_utils::TestScorer::new();
+		let chain_monitor = Arc::new(chaininterface::ChainWatchInterfaceUtil::new(Network::Testnet));
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.bitcoin_hash(), chain_monitor, Arc::clone(&logger));
+		// Build network from our_id to node3:
+		//
+		//        -1(1)2- node1 -1(3)2-
+		//       /                     \
+		// our_id                       node3
+		//       \                     /
+		//        -1(2)2- node2 -1(4)2-
+		//
+		// chan3 1-to-2: enabled, 0 fee, CLTV delta 100
+		// chan4 1-to-2: enabled, 1000 msat fee, CLTV delta 10
+		let node1_privkey = &SecretKey::from_slice(&[2; 32]).unwrap();
+		let node2_privkey = &SecretKey::from_slice(&[3; 32]).unwrap();
+		let node3_privkey = &SecretKey::from_slice(&[4; 32]).unwrap();
+		let node1 = PublicKey::from_secret_key(&secp_ctx, node1_privkey);
+		let node2 = PublicKey::from_secret_key(&secp_ctx, node2_privkey);
+		let node3 = PublicKey::from_secret_key(&secp_ctx, node3_privkey);
+
+		for &(node_privkey, short_channel_id, fee_base_msat, cltv_expiry_delta) in [(node1_privkey, 3, 0, 100), (node2_privkey, 4, 1000, 10)].iter() {
+			add_channel(&net_graph_msg_handler, &secp_ctx, node_privkey, node3_privkey, ChannelFeatures::empty(), short_channel_id);
+			update_channel(&net_graph_msg_handler, &secp_ctx, node_privkey, UnsignedChannelUpdate {
+				chain_hash: genesis_block(Network::Testnet).header.bitcoin_hash(),
+				short_channel_id,
+				timestamp: 1,
+				flags: 0,
+				cltv_expiry_delta,
+				htlc_minimum_msat: 0,
+				fee_base_msat,
+				fee_proportional_millionths: 0,
+				htlc_maximum_msat: OptionalField::Absent,
+				excess_data: Vec::new()
+			});
+		}
+
+		macro_rules! our_chan {
+			($short_channel_id: expr, $node_id: expr) => {
+				channelmanager::ChannelDetails {
+					channel_id: [$short_channel_id; 32],
+					short_channel_id: Some($short_channel_id as u64),
+					remote_network_id: $node_id.clone(),
+					counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
+					channel_value_satoshis: 0,
+					user_id: 0,
+					outbound_capacity_msat: 60_000,
+					inbound_capacity_msat: 0,
+					is_live: true,
+				}
+			}
+		}
+		let our_chans = vec![our_chan!(1, node1), our_chan!(2, node2)];
+
+		macro_rules! expect_route_err {
+			($params: expr, $final_cltv: expr, $err: expr) => {
+				match get_route_with_params(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 10_000, $final_cltv, &$params, Arc::clone(&logger), &scorer) {
+					Err(LightningError{err, action: ErrorAction::IgnoreError}) => assert_eq!(err, $err),
+					_ => panic!(),
+				}
+			}
+		}
+
+		// Without constraints, the cheapest path is used
+		let route = get_route_with_params(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 10_000, 42, &RouteParameters::default(), Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0][0].pubkey, node1);
+		assert_eq!(route.paths[0][0].cltv_expiry_delta, 100);
+
+		// Limiting the total CLTV delta forces us to pay the fee on chan4...
+		let params = RouteParameters { max_total_cltv_expiry_delta: 42 + 99, ..Default::default() };
+		let route = get_route_with_params(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 10_000, 42, &params, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0][0].pubkey, node2);
+		assert_eq!(route.paths[0][0].fee_msat, 1000);
+		assert_eq!(route.paths[0][0].cltv_expiry_delta, 10);
+
+		// ...unless the fee budget doesn't allow for it
+		let params = RouteParameters { max_total_cltv_expiry_delta: 42 + 99, max_total_fee_msat: 999, ..Default::default() };
+		expect_route_err!(params, 42, "Failed to find a path to the given destination within the maximum total fee");
+		let params = RouteParameters { max_total_cltv_expiry_delta: 42 + 9, ..Default::default() };
+		expect_route_err!(params, 42, "Failed to find a path to the given destination within the maximum total CLTV delta");
+		expect_route_err!(params, 52, "Cannot generate a route with a final CLTV delta above the maximum total CLTV delta");
+
+		// Both paths have two hops
+		let params = RouteParameters { max_path_length: 1, ..Default::default() };
+		expect_route_err!(params, 42, "Failed to find a path to the given destination within the maximum path length");
+		let params = RouteParameters { max_path_length: 0, ..Default::default() };
+		expect_route_err!(params, 42, "Cannot generate a route with a maximum path length of zero");
+		let params = RouteParameters { max_path_length: 21, ..Default::default() };
+		expect_route_err!(params, 42, "Cannot generate a route with a maximum path length above 20 hops");
+		let params = RouteParameters { max_path_length: 255, ..Default::default() };
+		expect_route_err!(params, 42, "Cannot generate a route with a maximum path length above 20 hops");
+
+		// Excluded nodes and channels are never used
+		let mut params = RouteParameters::default();
+		params.excluded_nodes.insert(node1);
+		let route = get_route_with_params(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 10_000, 42, &params, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0][0].pubkey, node2);
+		params.excluded_channels.insert(4);
+		expect_route_err!(params, 42, "Failed to find a path to the given destination");
+		params.excluded_nodes.insert(node3);
+		expect_route_err!(params, 42, "Cannot generate a route to an excluded node");
+
+		// The fee budget applies to all paths together
+		let params = RouteParameters { max_total_fee_msat: 999, ..Default::default() };
+		match get_multi_path_route_with_params(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 100_000, 42, &params, Arc::clone(&logger), &scorer) {
+			Err(LightningError{err, action: ErrorAction::IgnoreError}) => assert_eq!(err, "Failed to find a path to the given destination within the maximum total fee"),
+			_ => panic!(),
+		}
+		let params = RouteParameters { max_total_fee_msat: 1000, ..Default::default() };
+		let route = get_multi_path_route_with_params(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 100_000, 42, &params, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths.len(), 2);
+		assert_eq!(route.paths[1][0].fee_msat, 1000);
+
+		// The path count must be one which ChannelManager::send_payment accepts
+		let params = RouteParameters { max_path_count: 1, ..Default::default() };
+		match get_multi_path_route_with_params(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 100_000, 42, &params, Arc::clone(&logger), &scorer) {
+			Err(LightningError{err, action: ErrorAction::IgnoreError}) => assert_eq!(err, "Failed to find a path to the given destination"),
+			_ => panic!(),
+		}
+		for (max_path_count, expected_err) in [(0, "Cannot generate a route with a maximum path count of zero"),
+				(11, "Cannot generate a route with a maximum path count above 10 paths"),
+				(usize::max_value(), "Cannot generate a route with a maximum path count above 10 paths")].iter() {
+			let params = RouteParameters { max_path_count: *max_path_count, ..Default::default() };
+			match get_multi_path_route_with_params(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), 10_000, 42, &params, Arc::clone(&logger), &scorer) {
+				Err(LightningError{err, action: ErrorAction::IgnoreError}) => assert_eq!(err, *expected_err),
+				_ => panic!(),
+			}
+		}
+
+		// As must the value to send
+		for (final_value_msat, expected_err) in [(0, "Cannot send a payment of 0 msat"),
+				(21_000_000 * 1_0000_0000 * 1000 + 1, "Cannot generate a route of more value than all existing satoshis")].iter() {
+			match get_multi_path_route_with_params(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &node3, Some(&our_chans), &Vec::new(), *final_value_msat, 42, &RouteParameters::default(), Arc::clone(&logger), &scorer) {
+				Err(LightningError{err, action: ErrorAction::IgnoreError}) => assert_eq!(err, *expected_err),
+				_ => panic!(),
+			}
+		}
+	}
 }
@@ -0,0 +1,283 @@
+//! Utilities for scoring payment channels, allowing get_route to avoid channels which have failed
+//! to relay our payments in the past.
+//!
+//! The Score trait is consumed by get_route, which adds the penalty it returns for each channel
+//! to the cost of any path using that channel. Scorer is a default implementation which
+//! penalizes channels for each observed payment failure, reduces the penalty when payments
+//! through a channel succeed, and decays penalties over time so that channels are eventually
+//! retried.
+
+use bitcoin::secp256k1::key::PublicKey;
+
+use ln::msgs::{DecodeError, HTLCFailChannelUpdate};
+use routing::router::RouteHop;
+use util::ser::{Readable, Writeable, Writer};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// An interface used to score payment channels for path finding.
+///
+/// Scoring is in terms of fees willing to be paid in order to avoid routing through a channel.
+/// The update methods should be called as the results of payments become known, i.e. with the
+/// path and network_update of each Event::PaymentFailed and the path of each Event::PaymentSent.
+pub trait Score {
+	/// Returns the fee in msat willing to be paid to avoid routing through the given channel in
+	/// the direction from source to target.
+	fn channel_penalty_msat(&self, short_channel_id: u64, source: &PublicKey, target: &PublicKey) -> u64;
+
+	/// Handles updating channel penalties after a payment failed to be relayed along the given
+	/// path, with the network_update provided in the Event::PaymentFailed, if any.
+	fn payment_path_failed(&self, path: &[RouteHop], network_update: Option<&HTLCFailChannelUpdate>);
+
+	/// Handles updating channel penalties after a payment was successfully relayed along the
+	/// given path.
+	fn payment_path_successful(&self, path: &[RouteHop]);
+}
+
+/// Gets the channels along a failed payment path which the failure blamed, given the
+/// network_update provided in the Event::PaymentFailed.
+pub(crate) fn failed_channels(path: &[RouteHop], network_update: Option<&HTLCFailChannelUpdate>) -> Vec<u64> {
+	match network_update {
+		Some(&HTLCFailChannelUpdate::ChannelUpdateMessage { ref msg }) => vec![msg.contents.short_channel_id],
+		Some(&HTLCFailChannelUpdate::ChannelClosed { short_channel_id, .. }) => vec![short_channel_id],
+		Some(&HTLCFailChannelUpdate::NodeFailure { ref node_id, .. }) => {
+			// Blame the channels into and out of the failed node along the path.
+			let mut channels = Vec::new();
+			for (idx, hop) in path.iter().enumerate() {
+				if hop.pubkey == *node_id {
+					channels.push(hop.short_channel_id);
+					if let Some(next_hop) = path.get(idx + 1) {
+						channels.push(next_hop.short_channel_id);
+					}
+				}
+			}
+			channels
+		},
+		// Failures which didn't come with an update originated at our first hop.
+		None => path.first().map(|hop| vec![hop.short_channel_id]).unwrap_or(Vec::new()),
+	}
+}
+
+/// Parameters for configuring Scorer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoringParameters {
+	/// A fixed penalty in msat to apply to each channel, causing shorter paths to be preferred
+	/// over longer ones with similar fees.
+	///
+	/// Default value: 500 msat
+	pub base_penalty_msat: u64,
+	/// A penalty in msat to apply to a channel each time it fails to relay a payment. Penalties
+	/// accumulate over failures and are halved every failure_penalty_half_life_ticks.
+	///
+	/// Default value: 1,024,000 msat
+	pub failure_penalty_msat: u64,
+	/// The number of calls to Scorer::decay_penalties after which a channel's accumulated failure
+	/// penalty is halved. If decay_penalties is called once a minute, this is the half-life of
+	/// the penalty in minutes.
+	///
+	/// Default value: 60 ticks
+	pub failure_penalty_half_life_ticks: u16,
+}
+
+impl Default for ScoringParameters {
+	fn default() -> Self {
+		ScoringParameters {
+			base_penalty_msat: 500,
+			failure_penalty_msat: 1024 * 1000,
+			failure_penalty_half_life_ticks: 60,
+		}
+	}
+}
+
+impl_writeable!(ScoringParameters, 8 + 8 + 2, {
+	base_penalty_msat,
+	failure_penalty_msat,
+	failure_penalty_half_life_ticks
+});
+
+/// The accumulated failure penalty for a channel.
+struct ChannelFailure {
+	penalty_msat: u64,
+	/// The number of calls to decay_penalties since the penalty was last halved.
+	ticks_since_decay: u16,
+}
+
+impl_writeable!(ChannelFailure, 8 + 2, {
+	penalty_msat,
+	ticks_since_decay
+});
+
+/// A Score implementation which penalizes channels which have failed to relay payments, with the
+/// penalty decaying over time and being reduced by successful payments.
+///
+/// Channels are scored regardless of direction, as a failure in one direction often implies the
+/// channel (or one of its nodes) is unreliable in general.
+pub struct Scorer {
+	params: ScoringParameters,
+	channel_failures: Mutex<HashMap<u64, ChannelFailure>>,
+}
+
+impl Scorer {
+	/// Creates a new scorer using the given scoring parameters.
+	pub fn new(params: ScoringParameters) -> Self {
+		Scorer {
+			params,
+			channel_failures: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Decays the accumulated failure penalties of all channels. This should be called roughly
+	/// once per minute, eg alongside ChannelManager::timer_chan_freshness_every_min.
+	pub fn decay_penalties(&self) {
+		let half_life_ticks = self.params.failure_penalty_half_life_ticks;
+		self.channel_failures.lock().unwrap().retain(|_, failure| {
+			failure.ticks_since_decay += 1;
+			if failure.ticks_since_decay >= half_life_ticks {
+				failure.penalty_msat /= 2;
+				failure.ticks_since_decay = 0;
+			}
+			failure.penalty_msat != 0
+		});
+	}
+}
+
+impl Default for Scorer {
+	fn default() -> Self {
+		Scorer::new(ScoringParameters::default())
+	}
+}
+
+impl Score for Scorer {
+	fn channel_penalty_msat(&self, short_channel_id: u64, _source: &PublicKey, _target: &PublicKey) -> u64 {
+		let failure_penalty_msat = self.channel_failures.lock().unwrap().get(&short_channel_id)
+			.map(|failure| failure.penalty_msat).unwrap_or(0);
+		self.params.base_penalty_msat.saturating_add(failure_penalty_msat)
+	}
+
+	fn payment_path_failed(&self, path: &[RouteHop], network_update: Option<&HTLCFailChannelUpdate>) {
+		let mut channel_failures = self.channel_failures.lock().unwrap();
+		for short_channel_id in failed_channels(path, network_update) {
+			let failure = channel_failures.entry(short_channel_id)
+				.or_insert(ChannelFailure { penalty_msat: 0, ticks_since_decay: 0 });
+			failure.penalty_msat = failure.penalty_msat.saturating_add(self.params.failure_penalty_msat);
+		}
+	}
+
+	fn payment_path_successful(&self, path: &[RouteHop]) {
+		// Each channel which relayed the payment has its failure penalty halved.
+		let mut channel_failures = self.channel_failures.lock().unwrap();
+		for hop in path.iter() {
+			let remove = match channel_failures.get_mut(&hop.short_channel_id) {
+				Some(failure) => {
+					failure.penalty_msat /= 2;
+					failure.penalty_msat == 0
+				},
+				None => false,
+			};
+			if remove {
+				channel_failures.remove(&hop.short_channel_id);
+			}
+		}
+	}
+}
+
+impl Writeable for Scorer {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		self.params.write(writer)?;
+		let channel_failures = self.channel_failures.lock().unwrap();
+		(channel_failures.len() as u64).write(writer)?;
+		for (short_channel_id, failure) in channel_failures.iter() {
+			short_channel_id.write(writer)?;
+			failure.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for Scorer {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Scorer, DecodeError> {
+		let params = Readable::read(reader)?;
+		let failures_count: u64 = Readable::read(reader)?;
+		let mut channel_failures = HashMap::new();
+		for _ in 0..failures_count {
+			let short_channel_id: u64 = Readable::read(reader)?;
+			let failure = Readable::read(reader)?;
+			channel_failures.insert(short_channel_id, failure);
+		}
+		Ok(Scorer {
+			params,
+			channel_failures: Mutex::new(channel_failures),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use routing::router::RouteHop;
+	use routing::scorer::{Score, Scorer, ScoringParameters};
+	use ln::features::{ChannelFeatures, NodeFeatures};
+	use ln::msgs::HTLCFailChannelUpdate;
+	use util::ser::{Readable, Writeable};
+
+	use bitcoin::secp256k1::key::{PublicKey, SecretKey};
+	use bitcoin::secp256k1::Secp256k1;
+
+	use std::io::Cursor;
+
+	fn path(node_ids: &[PublicKey]) -> Vec<RouteHop> {
+		node_ids.iter().enumerate().map(|(idx, node_id)| RouteHop {
+			pubkey: *node_id,
+			node_features: NodeFeatures::empty(),
+			short_channel_id: idx as u64 + 1,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat: 1000,
+			cltv_expiry_delta: 40,
+		}).collect()
+	}
+
+	#[test]
+	fn scorer_penalizes_and_decays_failures() {
+		let secp_ctx = Secp256k1::new();
+		let node_ids: Vec<PublicKey> = (1..4u8).map(|idx| PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[idx; 32]).unwrap())).collect();
+		let scorer = Scorer::new(ScoringParameters {
+			base_penalty_msat: 500,
+			failure_penalty_msat: 1000,
+			failure_penalty_half_life_ticks: 2,
+		});
+		let path = path(&node_ids);
+		assert_eq!(scorer.channel_penalty_msat(2, &node_ids[0], &node_ids[1]), 500);
+
+		// A node failure blames the channels on either side of the node.
+		scorer.payment_path_failed(&path, Some(&HTLCFailChannelUpdate::NodeFailure { node_id: node_ids[1], is_permanent: false }));
+		assert_eq!(scorer.channel_penalty_msat(1, &node_ids[0], &node_ids[1]), 500);
+		assert_eq!(scorer.channel_penalty_msat(2, &node_ids[0], &node_ids[1]), 1500);
+		assert_eq!(scorer.channel_penalty_msat(3, &node_ids[1], &node_ids[2]), 1500);
+
+		// Failures accumulate, while successes halve the penalty.
+		scorer.payment_path_failed(&path, Some(&HTLCFailChannelUpdate::ChannelClosed { short_channel_id: 2, is_permanent: false }));
+		assert_eq!(scorer.channel_penalty_msat(2, &node_ids[0], &node_ids[1]), 2500);
+		scorer.payment_path_successful(&path);
+		assert_eq!(scorer.channel_penalty_msat(2, &node_ids[0], &node_ids[1]), 1500);
+		assert_eq!(scorer.channel_penalty_msat(3, &node_ids[1], &node_ids[2]), 1000);
+
+		// Failures without a network update blame our first hop.
+		scorer.payment_path_failed(&path, None);
+		assert_eq!(scorer.channel_penalty_msat(1, &node_ids[0], &node_ids[1]), 1500);
+
+		// Penalties are halved every failure_penalty_half_life_ticks.
+		scorer.decay_penalties();
+		assert_eq!(scorer.channel_penalty_msat(2, &node_ids[0], &node_ids[1]), 1500);
+		scorer.decay_penalties();
+		assert_eq!(scorer.channel_penalty_msat(2, &node_ids[0], &node_ids[1]), 1000);
+		assert_eq!(scorer.channel_penalty_msat(3, &node_ids[1], &node_ids[2]), 750);
+
+		// The accumulated penalties round-trip through serialization.
+		let read_scorer: Scorer = Readable::read(&mut Cursor::new(&scorer.encode())).unwrap();
+		assert_eq!(read_scorer.params, scorer.params);
+		for short_channel_id in 1..4 {
+			assert_eq!(read_scorer.channel_penalty_msat(short_channel_id, &node_ids[0], &node_ids[1]),
+				scorer.channel_penalty_msat(short_channel_id, &node_ids[0], &node_ids[1]));
+		}
+	}
+}
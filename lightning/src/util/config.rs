@@ -1,7 +1,7 @@
 //! Various user-configurable channel limits and settings which ChannelManager
 //! applies for you.
 
-use ln::channelmanager::{BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT};
+use ln::channelmanager::{BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT, MIN_CLTV_EXPIRY_DELTA};
 
 /// Top-level config which holds ChannelHandshakeLimits and ChannelConfig.
 ///
@@ -15,6 +15,60 @@ pub struct UserConfig {
 	pub peer_channel_config_limits: ChannelHandshakeLimits,
 	/// Channel config which affects behavior during channel lifetime.
 	pub channel_options: ChannelConfig,
+	/// The number of times ChannelManager::timer_chan_freshness_every_min may be called (ie roughly
+	/// the number of minutes) after we receive the first part of a multi-path payment before we
+	/// give up on receiving the rest and fail the parts we have back with mpp_timeout.
+	///
+	/// Multi-path payments which have been received in full never time out, even if they have not
+	/// yet been claimed with ChannelManager::claim_funds.
+	///
+	/// Default value: 3, giving the sender at least the 60 seconds BOLT 4 recommends.
+	pub mpp_timeout_ticks: u8,
+	/// If this is set to true, we accept spontaneous ("keysend") payments, ie payments which
+	/// carry their own preimage in the onion rather than paying one of our invoices. Such payments
+	/// are surfaced as an Event::PaymentReceived with payment_preimage set.
+	///
+	/// If this is set to false, spontaneous payments are failed back as if their payment hash
+	/// were unknown.
+	///
+	/// Default value: false.
+	pub accept_spontaneous_payments: bool,
+	/// If this is set to true, inbound channel requests which pass our ChannelHandshakeLimits are
+	/// not accepted automatically. Instead, an Event::OpenChannelRequest is generated for each and
+	/// the channel is only accepted once ChannelManager::accept_inbound_channel is called. Requests
+	/// which are neither accepted nor rejected within two calls to
+	/// ChannelManager::timer_chan_freshness_every_min are rejected. At most two requests from any
+	/// one peer, and 100 in total, may be awaiting an answer at once, any further requests being
+	/// rejected.
+	///
+	/// Requests to open dual-funded channels always await an
+	/// Event::FundingContributionRequired response, and are subject to the same timeout and
+	/// limits, whether or not this is set.
+	///
+	/// Default value: false.
+	pub manually_accept_inbound_channels: bool,
+	/// The percentage by which our fee estimate for ConfirmationTarget::Normal must exceed the
+	/// feerate of the commitment transactions of a channel we funded before
+	/// ChannelManager::timer_chan_freshness_every_min sends an update_fee raising it to our
+	/// estimate.
+	///
+	/// Default value: 10.
+	pub update_fee_increase_threshold_percent: u16,
+	/// The percentage by which our fee estimate for ConfirmationTarget::Normal must fall below the
+	/// feerate of the commitment transactions of a channel we funded before
+	/// ChannelManager::timer_chan_freshness_every_min sends an update_fee lowering it to our
+	/// estimate. Values of 100 or more disable lowering the feerate.
+	///
+	/// Default value: 50.
+	pub update_fee_decrease_threshold_percent: u16,
+	/// The percentage by which the feerate in an update_fee sent by the funder of a channel may
+	/// fall below our fee estimate for ConfirmationTarget::Background, or exceed twice our fee
+	/// estimate for ConfirmationTarget::HighPriority, before we close the channel. Feerates in
+	/// open_channel are held to these bounds without any deviation, eg a value of 100 accepts
+	/// feerates between half our Background estimate and four times our HighPriority estimate.
+	///
+	/// Default value: 0.
+	pub max_inbound_update_fee_deviation_percent: u16,
 }
 
 impl Default for UserConfig {
@@ -23,6 +77,12 @@ impl Default for UserConfig {
 			own_channel_config: ChannelHandshakeConfig::default(),
 			peer_channel_config_limits: ChannelHandshakeLimits::default(),
 			channel_options: ChannelConfig::default(),
+			mpp_timeout_ticks: 3,
+			accept_spontaneous_payments: false,
+			manually_accept_inbound_channels: false,
+			update_fee_increase_threshold_percent: 10,
+			update_fee_decrease_threshold_percent: 50,
+			max_inbound_update_fee_deviation_percent: 0,
 		}
 	}
 }
@@ -59,6 +119,16 @@ pub struct ChannelHandshakeConfig {
 	/// Default value: 1. If the value is less than 1, it is ignored and set to 1, as is required
 	/// by the protocol.
 	pub our_htlc_minimum_msat: u64,
+	/// If set, we signal support for option_anchor_outputs to our peers, and channels with peers
+	/// which also support it will include anchor outputs in their commitment transactions. This
+	/// allows us to bump the fee of our commitment transactions at broadcast time, using inputs
+	/// from the UtxoPool provided to our SimpleManyChannelMonitor.
+	///
+	/// As this changes the features we advertise to peers, only the value in the ChannelManager's
+	/// default configuration is used, including for channels opened with an override config.
+	///
+	/// Default value: false.
+	pub negotiate_anchor_outputs: bool,
 }
 
 impl Default for ChannelHandshakeConfig {
@@ -67,6 +137,7 @@ impl Default for ChannelHandshakeConfig {
 			minimum_depth: 6,
 			our_to_self_delay: BREAKDOWN_TIMEOUT,
 			our_htlc_minimum_msat: 1,
+			negotiate_anchor_outputs: false,
 		}
 	}
 }
@@ -89,6 +160,13 @@ pub struct ChannelHandshakeLimits {
 	///
 	/// Default value: 0.
 	pub min_funding_satoshis: u64,
+	/// Maximum allowed satoshis when a channel is funded, this is supplied by the sender and so
+	/// only applies to inbound channels. Channels larger than 2^24 - 1 satoshis are only accepted
+	/// from peers which signal support for large channels, regardless of this limit.
+	///
+	/// Default value: 2^24 - 1, the largest channel peers which don't support large channels can
+	/// open.
+	pub max_funding_satoshis: u64,
 	/// The remote node sets a limit on the minimum size of HTLCs we can send to them. This allows
 	/// you to limit the maximum minimum-size they can require.
 	///
@@ -153,6 +231,7 @@ impl Default for ChannelHandshakeLimits {
 	fn default() -> Self {
 		ChannelHandshakeLimits {
 			min_funding_satoshis: 0,
+			max_funding_satoshis: (1 << 24) - 1,
 			max_htlc_minimum_msat: <u64>::max_value(),
 			min_max_htlc_value_in_flight_msat: 0,
 			max_channel_reserve_satoshis: <u64>::max_value(),
@@ -171,11 +250,37 @@ impl Default for ChannelHandshakeLimits {
 #[derive(Copy, Clone, Debug)]
 pub struct ChannelConfig {
 	/// Amount (in millionths of a satoshi) the channel will charge per transferred satoshi.
-	/// This may be allowed to change at runtime in a later update, however doing so must result in
-	/// update messages sent to notify all nodes of our updated relay fee.
+	/// This may be changed at runtime with ChannelManager::update_channel_config, which notifies
+	/// all nodes of our updated relay fee.
 	///
 	/// Default value: 0.
 	pub fee_proportional_millionths: u32,
+	/// Amount (in millisatoshis) the channel will charge for each forwarded HTLC, in addition to
+	/// fee_proportional_millionths. If unset, we charge roughly what it would cost us to claim the
+	/// HTLC on-chain at the current feerate.
+	/// This may be changed at runtime with ChannelManager::update_channel_config.
+	///
+	/// Default value: None.
+	pub fee_base_msat: Option<u32>,
+	/// The difference in CLTV expiry we require between an HTLC we receive and the HTLC we forward
+	/// over the channel in its place. This gives us time to claim the inbound HTLC on-chain if the
+	/// outbound HTLC is claimed late.
+	/// This may be changed at runtime with ChannelManager::update_channel_config.
+	///
+	/// Default value: MIN_CLTV_EXPIRY_DELTA (currently 72), which we also enforce as a minimum.
+	pub cltv_expiry_delta: u16,
+	/// The smallest HTLC (in millisatoshis) we will forward over the channel. We never forward
+	/// HTLCs smaller than the minimum our counterparty will accept, regardless of this value.
+	/// This may be changed at runtime with ChannelManager::update_channel_config.
+	///
+	/// Default value: 0.
+	pub forwarding_htlc_minimum_msat: u64,
+	/// The largest HTLC (in millisatoshis) we will forward over the channel, if any. This is
+	/// capped at the channel's value when announced.
+	/// This may be changed at runtime with ChannelManager::update_channel_config.
+	///
+	/// Default value: None.
+	pub forwarding_htlc_maximum_msat: Option<u64>,
 	/// Set to announce the channel publicly and notify all nodes that they can route via this
 	/// channel.
 	///
@@ -207,6 +312,10 @@ impl Default for ChannelConfig {
 	fn default() -> Self {
 		ChannelConfig {
 			fee_proportional_millionths: 0,
+			fee_base_msat: None,
+			cltv_expiry_delta: MIN_CLTV_EXPIRY_DELTA,
+			forwarding_htlc_minimum_msat: 0,
+			forwarding_htlc_maximum_msat: None,
 			announced_channel: false,
 			commit_upfront_shutdown_pubkey: true,
 		}
@@ -214,8 +323,12 @@ impl Default for ChannelConfig {
 }
 
 //Add write and readable traits to channelconfig
-impl_writeable!(ChannelConfig, 8+1+1, {
+impl_writeable!(ChannelConfig, 8+1+1+5+2+8+9, {
 	fee_proportional_millionths,
+	fee_base_msat,
+	cltv_expiry_delta,
+	forwarding_htlc_minimum_msat,
+	forwarding_htlc_maximum_msat,
 	announced_channel,
 	commit_upfront_shutdown_pubkey
 });
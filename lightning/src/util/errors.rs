@@ -50,6 +50,145 @@ impl fmt::Debug for APIError {
 	}
 }
 
+/// The BOLT 4 failure code flag indicating an unparsable onion.
+pub(crate) const BADONION: u16 = 0x8000;
+/// The BOLT 4 failure code flag indicating a permanent failure.
+pub(crate) const PERM: u16 = 0x4000;
+/// The BOLT 4 failure code flag indicating a node failure.
+pub(crate) const NODE: u16 = 0x2000;
+/// The BOLT 4 failure code flag indicating a channel_update is enclosed.
+pub(crate) const UPDATE: u16 = 0x1000;
+
+/// A BOLT 4 failure code, indicating why a node along a payment's path failed the HTLC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnionFailureCode {
+	/// invalid_realm: the realm byte was not understood by the processing node.
+	InvalidRealm,
+	/// temporary_node_failure: a general temporary failure of the processing node.
+	TemporaryNodeFailure,
+	/// permanent_node_failure: a general permanent failure of the processing node.
+	PermanentNodeFailure,
+	/// required_node_feature_missing: the processing node requires a feature not present in the
+	/// onion.
+	RequiredNodeFeatureMissing,
+	/// invalid_onion_version: the version byte of the onion was not understood.
+	InvalidOnionVersion,
+	/// invalid_onion_hmac: the HMAC of the onion was incorrect when it reached the processing node.
+	InvalidOnionHmac,
+	/// invalid_onion_key: the ephemeral key of the onion was unparseable by the processing node.
+	InvalidOnionKey,
+	/// temporary_channel_failure: the outgoing channel was unable to handle the HTLC temporarily.
+	TemporaryChannelFailure,
+	/// permanent_channel_failure: the outgoing channel was unable to handle the HTLC permanently.
+	PermanentChannelFailure,
+	/// required_channel_feature_missing: the outgoing channel requires a feature not present in
+	/// the onion.
+	RequiredChannelFeatureMissing,
+	/// unknown_next_peer: the onion specified a short_channel_id which doesn't match any leading
+	/// from the processing node.
+	UnknownNextPeer,
+	/// amount_below_minimum: the HTLC amount was below the outgoing channel's htlc_minimum_msat.
+	AmountBelowMinimum,
+	/// fee_insufficient: the fee paid did not meet the outgoing channel's requirements.
+	FeeInsufficient,
+	/// incorrect_cltv_expiry: the cltv_expiry did not comply with the outgoing channel's
+	/// cltv_expiry_delta.
+	IncorrectCltvExpiry,
+	/// expiry_too_soon: the CLTV expiry was too close to the current block height for safe
+	/// handling by the processing node.
+	ExpiryTooSoon,
+	/// incorrect_or_unknown_payment_details: the recipient didn't know the payment hash, the
+	/// payment secret didn't match, or the amount or CLTV expiry was incorrect.
+	IncorrectOrUnknownPaymentDetails,
+	/// incorrect_payment_amount: the recipient indicated the amount was incorrect. Deprecated in
+	/// favor of IncorrectOrUnknownPaymentDetails.
+	IncorrectPaymentAmount,
+	/// final_expiry_too_soon: the recipient indicated the CLTV expiry was too close to the current
+	/// block height.
+	FinalExpiryTooSoon,
+	/// final_incorrect_cltv_expiry: the CLTV expiry in the HTLC didn't match the one in the onion.
+	FinalIncorrectCltvExpiry,
+	/// final_incorrect_htlc_amount: the amount of the HTLC didn't match the one in the onion.
+	FinalIncorrectHtlcAmount,
+	/// channel_disabled: the outgoing channel has been disabled.
+	ChannelDisabled,
+	/// expiry_too_far: the CLTV expiry in the HTLC was too far in the future.
+	ExpiryTooFar,
+	/// mpp_timeout: the recipient didn't receive the full amount of a multi-part payment within
+	/// a reasonable time.
+	MppTimeout,
+	/// A failure code which we don't understand, carrying the raw value.
+	Unknown(u16),
+}
+
+impl OnionFailureCode {
+	/// Converts a raw failure code, as found on the wire, into an OnionFailureCode.
+	pub fn from_u16(code: u16) -> Self {
+		match code {
+			_c if _c == PERM|1 => OnionFailureCode::InvalidRealm,
+			_c if _c == NODE|2 => OnionFailureCode::TemporaryNodeFailure,
+			_c if _c == PERM|NODE|2 => OnionFailureCode::PermanentNodeFailure,
+			_c if _c == PERM|NODE|3 => OnionFailureCode::RequiredNodeFeatureMissing,
+			_c if _c == BADONION|PERM|4 => OnionFailureCode::InvalidOnionVersion,
+			_c if _c == BADONION|PERM|5 => OnionFailureCode::InvalidOnionHmac,
+			_c if _c == BADONION|PERM|6 => OnionFailureCode::InvalidOnionKey,
+			_c if _c == UPDATE|7 => OnionFailureCode::TemporaryChannelFailure,
+			_c if _c == PERM|8 => OnionFailureCode::PermanentChannelFailure,
+			_c if _c == PERM|9 => OnionFailureCode::RequiredChannelFeatureMissing,
+			_c if _c == PERM|10 => OnionFailureCode::UnknownNextPeer,
+			_c if _c == UPDATE|11 => OnionFailureCode::AmountBelowMinimum,
+			_c if _c == UPDATE|12 => OnionFailureCode::FeeInsufficient,
+			_c if _c == UPDATE|13 => OnionFailureCode::IncorrectCltvExpiry,
+			_c if _c == UPDATE|14 => OnionFailureCode::ExpiryTooSoon,
+			_c if _c == PERM|15 => OnionFailureCode::IncorrectOrUnknownPaymentDetails,
+			_c if _c == PERM|16 => OnionFailureCode::IncorrectPaymentAmount,
+			17 => OnionFailureCode::FinalExpiryTooSoon,
+			18 => OnionFailureCode::FinalIncorrectCltvExpiry,
+			19 => OnionFailureCode::FinalIncorrectHtlcAmount,
+			_c if _c == UPDATE|20 => OnionFailureCode::ChannelDisabled,
+			21 => OnionFailureCode::ExpiryTooFar,
+			23 => OnionFailureCode::MppTimeout,
+			_ => OnionFailureCode::Unknown(code),
+		}
+	}
+
+	/// Gets the raw failure code, as found on the wire.
+	pub fn to_u16(&self) -> u16 {
+		match self {
+			&OnionFailureCode::InvalidRealm => PERM|1,
+			&OnionFailureCode::TemporaryNodeFailure => NODE|2,
+			&OnionFailureCode::PermanentNodeFailure => PERM|NODE|2,
+			&OnionFailureCode::RequiredNodeFeatureMissing => PERM|NODE|3,
+			&OnionFailureCode::InvalidOnionVersion => BADONION|PERM|4,
+			&OnionFailureCode::InvalidOnionHmac => BADONION|PERM|5,
+			&OnionFailureCode::InvalidOnionKey => BADONION|PERM|6,
+			&OnionFailureCode::TemporaryChannelFailure => UPDATE|7,
+			&OnionFailureCode::PermanentChannelFailure => PERM|8,
+			&OnionFailureCode::RequiredChannelFeatureMissing => PERM|9,
+			&OnionFailureCode::UnknownNextPeer => PERM|10,
+			&OnionFailureCode::AmountBelowMinimum => UPDATE|11,
+			&OnionFailureCode::FeeInsufficient => UPDATE|12,
+			&OnionFailureCode::IncorrectCltvExpiry => UPDATE|13,
+			&OnionFailureCode::ExpiryTooSoon => UPDATE|14,
+			&OnionFailureCode::IncorrectOrUnknownPaymentDetails => PERM|15,
+			&OnionFailureCode::IncorrectPaymentAmount => PERM|16,
+			&OnionFailureCode::FinalExpiryTooSoon => 17,
+			&OnionFailureCode::FinalIncorrectCltvExpiry => 18,
+			&OnionFailureCode::FinalIncorrectHtlcAmount => 19,
+			&OnionFailureCode::ChannelDisabled => UPDATE|20,
+			&OnionFailureCode::ExpiryTooFar => 21,
+			&OnionFailureCode::MppTimeout => 23,
+			&OnionFailureCode::Unknown(code) => code,
+		}
+	}
+
+	/// Whether the PERM bit is set, indicating that retrying over the same path is not expected
+	/// to succeed.
+	pub fn is_permanent(&self) -> bool {
+		self.to_u16() & PERM == PERM
+	}
+}
+
 #[inline]
 pub(crate) fn get_onion_debug_field(error_code: u16) -> (&'static str, usize) {
 	match error_code & 0xff {
@@ -64,10 +203,6 @@ pub(crate) fn get_onion_debug_field(error_code: u16) -> (&'static str, usize) {
 
 #[inline]
 pub(crate) fn get_onion_error_description(error_code: u16) -> (&'static str, &'static str) {
-	const BADONION: u16 = 0x8000;
-	const PERM: u16 = 0x4000;
-	const NODE: u16 = 0x2000;
-	const UPDATE: u16 = 0x1000;
 	match error_code {
 		_c if _c == PERM|1 => ("The realm byte was not understood by the processing node", "invalid_realm"),
 		_c if _c == NODE|2 => ("Node indicated temporary node failure", "temporary_node_failure"),
@@ -91,6 +226,28 @@ pub(crate) fn get_onion_error_description(error_code: u16) -> (&'static str, &'s
 		_c if _c == 19 => ("The final node indicated the amount in the HTLC does not match the value in the onion", "final_incorrect_htlc_amount"),
 		_c if _c == UPDATE|20 => ("Node indicated the outbound channel has been disabled", "channel_disabled"),
 		_c if _c == 21 => ("Node indicated the CLTV expiry in the HTLC is too far in the future", "expiry_too_far"),
+		_c if _c == 23 => ("The final node indicated the complete amount of the multi-part payment was not received within a reasonable time", "mpp_timeout"),
 		_ => ("Unknown", ""),
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use util::errors::{OnionFailureCode, PERM, UPDATE};
+
+	#[test]
+	fn onion_failure_code_round_trip() {
+		// Every code, known or not, survives conversion to an OnionFailureCode and back
+		for code in 0..=u16::max_value() {
+			let failure_code = OnionFailureCode::from_u16(code);
+			assert_eq!(failure_code.to_u16(), code);
+			assert_eq!(failure_code.is_permanent(), code & PERM == PERM);
+			if let OnionFailureCode::Unknown(_) = failure_code {} else {
+				assert_eq!(OnionFailureCode::from_u16(failure_code.to_u16()), failure_code);
+			}
+		}
+		assert_eq!(OnionFailureCode::from_u16(PERM|15), OnionFailureCode::IncorrectOrUnknownPaymentDetails);
+		assert_eq!(OnionFailureCode::from_u16(UPDATE|7), OnionFailureCode::TemporaryChannelFailure);
+		assert_eq!(OnionFailureCode::from_u16(UPDATE|22), OnionFailureCode::Unknown(UPDATE|22));
+	}
+}
@@ -6,12 +6,17 @@
 //! few other things.
 
 use ln::msgs;
-use ln::channelmanager::{PaymentPreimage, PaymentHash, PaymentSecret};
+use ln::features::InitFeatures;
+use ln::channelmanager::{PaymentPreimage, PaymentHash, PaymentSecret, InterceptId};
+use ln::payment_retry::PaymentAttempt;
 use chain::transaction::OutPoint;
 use chain::keysinterface::SpendableOutputDescriptor;
+use routing::router::RouteHop;
+use util::errors::OnionFailureCode;
 use util::ser::{Writeable, Writer, MaybeReadable, Readable};
 
 use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
 
 use bitcoin::secp256k1::key::PublicKey;
 
@@ -72,6 +77,12 @@ pub enum Event {
 		/// Note that, in any case, this value must be passed as-is to any fail or claim calls as
 		/// the HTLC index includes this value.
 		payment_secret: Option<PaymentSecret>,
+		/// The preimage included in the onion by the sender if this is a spontaneous ("keysend")
+		/// payment, which should be passed to ChannelManager::claim_funds to claim it. Only
+		/// provided if UserConfig::accept_spontaneous_payments is set.
+		/// Also provided for payments to a payment hash from
+		/// ChannelManager::create_inbound_payment, which we derive the preimage of.
+		payment_preimage: Option<PaymentPreimage>,
 		/// The value, in thousandths of a satoshi, that this payment is for. Note that you must
 		/// compare this to the expected value before accepting the payment (as otherwise you are
 		/// providing proof-of-payment for less than the value you expected!).
@@ -86,6 +97,9 @@ pub enum Event {
 		/// Note that this serves as a payment receipt, if you wish to have such a thing, you must
 		/// store it somehow!
 		payment_preimage: PaymentPreimage,
+		/// The path which succeeded. As each path of a multi-path payment generates its own
+		/// PaymentSent event, this is useful to learn which channels relayed the payment.
+		path: Vec<RouteHop>,
 	},
 	/// Indicates an outbound payment we made failed. Probably some intermediary node dropped
 	/// something. You may wish to retry with a different route.
@@ -98,10 +112,16 @@ pub enum Event {
 		/// the payment has failed, not just the route in question. If this is not set, you may
 		/// retry the payment via a different route.
 		rejected_by_dest: bool,
-#[cfg(test)]
-		error_code: Option<u16>,
-#[cfg(test)]
-		error_data: Option<Vec<u8>>,
+		/// The path which failed. Its last hop's fee_msat is the value which failed to reach the
+		/// recipient, which you may wish to retry via ChannelManager::retry_payment.
+		path: Vec<RouteHop>,
+		/// Any update to our view of the network graph which the failure implies, which may be
+		/// useful to exclude the failing channel or node when retrying. This is also provided to
+		/// the network graph via a MessageSendEvent::PaymentFailureNetworkUpdate.
+		network_update: Option<msgs::HTLCFailChannelUpdate>,
+		/// The failure reported for the HTLC, if we were able to decode one. This is None if a node
+		/// along the path returned a failure we could not decrypt or parse.
+		failure: Option<PaymentFailureDetails>,
 	},
 	/// Used to indicate that ChannelManager::process_pending_htlc_forwards should be called at a
 	/// time in the future.
@@ -120,6 +140,134 @@ pub enum Event {
 		/// The outputs which you should store as spendable by you.
 		outputs: Vec<SpendableOutputDescriptor>,
 	},
+	/// Indicates a payment sent via a PaymentRetryManager has been resolved, either because it
+	/// succeeded or because it failed and will not be retried any further. This is generated by
+	/// PaymentRetryManager::process_events in place of the PaymentSent and PaymentFailed events
+	/// for the payment.
+	PaymentResolved {
+		/// The hash which was given to PaymentRetryManager::send_payment.
+		payment_hash: PaymentHash,
+		/// The preimage to the payment_hash if the payment succeeded, or None if it failed.
+		payment_preimage: Option<PaymentPreimage>,
+		/// Each path a part of the payment was sent over, in the order they were attempted.
+		attempts: Vec<PaymentAttempt>,
+	},
+	/// Used to indicate that a peer wishes to open a dual-funded channel with us, and that the
+	/// client should call ChannelManager::contribute_channel_funding with the funds (if any) it
+	/// wishes to add to the channel, or ChannelManager::reject_inbound_channel. Requests which are
+	/// answered by neither in time are rejected, as for Event::OpenChannelRequest.
+	/// Generated in ChannelManager message handling.
+	FundingContributionRequired {
+		/// The temporary_channel_id which you'll need to pass into
+		/// ChannelManager::contribute_channel_funding.
+		temporary_channel_id: [u8; 32],
+		/// The node_id of the peer opening the channel.
+		counterparty_node_id: PublicKey,
+		/// The value, in satoshis, the peer is contributing to the channel.
+		counterparty_funding_satoshis: u64,
+		/// The feerate, in satoshis per 1000 weight units, at which our inputs and outputs must pay
+		/// for themselves in the funding transaction.
+		funding_feerate_per_kw: u32,
+	},
+	/// Used to indicate that the funding transaction of a dual-funded channel, or a splice
+	/// transaction, has been negotiated and that the client should sign the inputs it contributed
+	/// and pass the result to ChannelManager::funding_transaction_signed. The transaction will be
+	/// broadcast once our counterparty has signed its own inputs.
+	FundingTransactionReadyForSigning {
+		/// The channel_id which you'll need to pass into ChannelManager::funding_transaction_signed.
+		channel_id: [u8; 32],
+		/// The value passed in to ChannelManager::create_dual_funded_channel, or 0 for inbound
+		/// channels.
+		user_channel_id: u64,
+		/// The funding transaction, whose inputs spending our outputs need their witnesses filled in.
+		unsigned_transaction: Transaction,
+	},
+	/// Used to indicate that a peer wishes to open a channel with us, when
+	/// UserConfig::manually_accept_inbound_channels is set. The request has already been checked
+	/// against our ChannelHandshakeLimits, and the client should call either
+	/// ChannelManager::accept_inbound_channel or ChannelManager::reject_inbound_channel. Requests
+	/// which are not answered in time are rejected, see
+	/// UserConfig::manually_accept_inbound_channels.
+	/// Generated in ChannelManager message handling.
+	OpenChannelRequest {
+		/// The temporary_channel_id which you'll need to pass into
+		/// ChannelManager::accept_inbound_channel or ChannelManager::reject_inbound_channel.
+		temporary_channel_id: [u8; 32],
+		/// The node_id of the peer requesting the channel.
+		counterparty_node_id: PublicKey,
+		/// The features the peer sent us in its Init message.
+		counterparty_features: InitFeatures,
+		/// The value, in satoshis, of the channel, all of which is funded by the peer.
+		funding_satoshis: u64,
+		/// The amount, in millisatoshis, the peer pushes to us upon opening the channel, which is
+		/// our initial balance in it.
+		push_msat: u64,
+		/// The number of blocks we'd have to wait to claim our funds were we to broadcast our
+		/// commitment transaction.
+		to_self_delay: u16,
+		/// The balance, in satoshis, the peer requires us to keep in the channel once our balance
+		/// exceeds it.
+		channel_reserve_satoshis: u64,
+		/// The smallest HTLC, in millisatoshis, the peer will accept.
+		htlc_minimum_msat: u64,
+		/// The most HTLCs the peer will accept from us at once.
+		max_accepted_htlcs: u16,
+		/// The initial feerate, in satoshis per 1000 weight units, of the commitment transactions.
+		feerate_per_kw: u32,
+		/// Whether the peer wishes to announce the channel to the rest of the network.
+		announce_channel: bool,
+	},
+	/// Indicates an HTLC we forwarded was claimed by the next hop and that we have claimed the
+	/// corresponding inbound HTLC from the previous hop, earning the difference between the two.
+	/// Generated when we claim the inbound HTLC, whether the outbound HTLC was claimed in a
+	/// channel update or on-chain.
+	PaymentForwarded {
+		/// The channel we received the inbound HTLC over.
+		prev_channel_id: [u8; 32],
+		/// The channel we forwarded the HTLC over.
+		next_channel_id: [u8; 32],
+		/// The value, in millisatoshis, of the inbound HTLC.
+		amt_in_msat: u64,
+		/// The value, in millisatoshis, of the outbound HTLC.
+		amt_out_msat: u64,
+		/// The fee, in millisatoshis, we earned for forwarding the HTLC, ie amt_in_msat less
+		/// amt_out_msat.
+		fee_earned_msat: u64,
+		/// Whether the next hop claimed the outbound HTLC on-chain, in which case the on-chain fees
+		/// we paid for the HTLC are not accounted for in fee_earned_msat.
+		claim_from_onchain_tx: bool,
+	},
+	/// Indicates a channel has been closed and removed from ChannelManager::list_channels. Any
+	/// funds remaining in it will be claimed on-chain and provided via SpendableOutputs events.
+	ChannelClosed {
+		/// The channel_id of the channel which was closed. Note that this may be the temporary
+		/// channel_id if the channel was closed before its funding transaction was created.
+		channel_id: [u8; 32],
+		/// The value passed in to ChannelManager::create_channel, or 0 for inbound channels.
+		user_id: u64,
+		/// Why the channel was closed.
+		reason: ClosureReason,
+	},
+	/// Indicates we were asked to forward an HTLC over a short channel id passed to
+	/// ChannelManager::add_intercept_scid. You must call either
+	/// ChannelManager::forward_intercepted_htlc or ChannelManager::fail_intercepted_htlc, or the
+	/// HTLC will be failed backwards once it gets close to expiring.
+	/// Generated when the inbound HTLC is irrevocably committed to.
+	HTLCIntercepted {
+		/// The id to pass to ChannelManager::forward_intercepted_htlc or
+		/// ChannelManager::fail_intercepted_htlc.
+		intercept_id: InterceptId,
+		/// The short channel id the sender asked us to forward the HTLC over.
+		requested_next_hop_scid: u64,
+		/// The payment hash of the HTLC.
+		payment_hash: PaymentHash,
+		/// The value, in millisatoshis, of the inbound HTLC.
+		inbound_amount_msat: u64,
+		/// The value, in millisatoshis, the sender asked us to forward to the next hop.
+		expected_outbound_amount_msat: u64,
+		/// The CLTV expiry the sender asked the outbound HTLC to have.
+		outgoing_cltv_value: u32,
+	},
 }
 
 impl Writeable for Event {
@@ -135,29 +283,25 @@ impl Writeable for Event {
 				funding_txo.write(writer)?;
 				user_channel_id.write(writer)?;
 			},
-			&Event::PaymentReceived { ref payment_hash, ref payment_secret, ref amt } => {
+			&Event::PaymentReceived { ref payment_hash, ref payment_secret, ref payment_preimage, ref amt } => {
 				2u8.write(writer)?;
 				payment_hash.write(writer)?;
 				payment_secret.write(writer)?;
+				payment_preimage.write(writer)?;
 				amt.write(writer)?;
 			},
-			&Event::PaymentSent { ref payment_preimage } => {
+			&Event::PaymentSent { ref payment_preimage, ref path } => {
 				3u8.write(writer)?;
 				payment_preimage.write(writer)?;
+				path.write(writer)?;
 			},
-			&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest,
-				#[cfg(test)]
-				ref error_code,
-				#[cfg(test)]
-				ref error_data,
-			} => {
+			&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest, ref path, ref network_update, ref failure } => {
 				4u8.write(writer)?;
 				payment_hash.write(writer)?;
 				rejected_by_dest.write(writer)?;
-				#[cfg(test)]
-				error_code.write(writer)?;
-				#[cfg(test)]
-				error_data.write(writer)?;
+				path.write(writer)?;
+				network_update.write(writer)?;
+				failure.write(writer)?;
 			},
 			&Event::PendingHTLCsForwardable { time_forwardable: _ } => {
 				5u8.write(writer)?;
@@ -171,6 +315,55 @@ impl Writeable for Event {
 					output.write(writer)?;
 				}
 			},
+			&Event::PaymentResolved { ref payment_hash, ref payment_preimage, ref attempts } => {
+				7u8.write(writer)?;
+				payment_hash.write(writer)?;
+				payment_preimage.write(writer)?;
+				(attempts.len() as u64).write(writer)?;
+				for attempt in attempts.iter() {
+					attempt.write(writer)?;
+				}
+			},
+			&Event::FundingContributionRequired { .. } => {
+				0u8.write(writer)?;
+				// As with FundingGenerationReady, the channel is forgotten upon disconnection so we
+				// never write out FundingContributionRequired events.
+			},
+			&Event::FundingTransactionReadyForSigning { ref channel_id, ref user_channel_id, ref unsigned_transaction } => {
+				8u8.write(writer)?;
+				channel_id.write(writer)?;
+				user_channel_id.write(writer)?;
+				unsigned_transaction.write(writer)?;
+			},
+			&Event::OpenChannelRequest { .. } => {
+				0u8.write(writer)?;
+				// The request is forgotten upon disconnection, so we never write out
+				// OpenChannelRequest events.
+			},
+			&Event::PaymentForwarded { ref prev_channel_id, ref next_channel_id, ref amt_in_msat, ref amt_out_msat, ref fee_earned_msat, ref claim_from_onchain_tx } => {
+				9u8.write(writer)?;
+				prev_channel_id.write(writer)?;
+				next_channel_id.write(writer)?;
+				amt_in_msat.write(writer)?;
+				amt_out_msat.write(writer)?;
+				fee_earned_msat.write(writer)?;
+				claim_from_onchain_tx.write(writer)?;
+			},
+			&Event::ChannelClosed { ref channel_id, ref user_id, ref reason } => {
+				10u8.write(writer)?;
+				channel_id.write(writer)?;
+				user_id.write(writer)?;
+				reason.write(writer)?;
+			},
+			&Event::HTLCIntercepted { ref intercept_id, ref requested_next_hop_scid, ref payment_hash, ref inbound_amount_msat, ref expected_outbound_amount_msat, ref outgoing_cltv_value } => {
+				11u8.write(writer)?;
+				intercept_id.write(writer)?;
+				requested_next_hop_scid.write(writer)?;
+				payment_hash.write(writer)?;
+				inbound_amount_msat.write(writer)?;
+				expected_outbound_amount_msat.write(writer)?;
+				outgoing_cltv_value.write(writer)?;
+			},
 		}
 		Ok(())
 	}
@@ -186,18 +379,19 @@ impl MaybeReadable for Event {
 			2u8 => Ok(Some(Event::PaymentReceived {
 					payment_hash: Readable::read(reader)?,
 					payment_secret: Readable::read(reader)?,
+					payment_preimage: Readable::read(reader)?,
 					amt: Readable::read(reader)?,
 				})),
 			3u8 => Ok(Some(Event::PaymentSent {
 					payment_preimage: Readable::read(reader)?,
+					path: Readable::read(reader)?,
 				})),
 			4u8 => Ok(Some(Event::PaymentFailed {
 					payment_hash: Readable::read(reader)?,
 					rejected_by_dest: Readable::read(reader)?,
-					#[cfg(test)]
-					error_code: Readable::read(reader)?,
-					#[cfg(test)]
-					error_data: Readable::read(reader)?,
+					path: Readable::read(reader)?,
+					network_update: Readable::read(reader)?,
+					failure: Readable::read(reader)?,
 				})),
 			5u8 => Ok(Some(Event::PendingHTLCsForwardable {
 					time_forwardable: Duration::from_secs(0)
@@ -210,11 +404,194 @@ impl MaybeReadable for Event {
 				}
 				Ok(Some(Event::SpendableOutputs { outputs }))
 			},
+			7u8 => {
+				let payment_hash = Readable::read(reader)?;
+				let payment_preimage = Readable::read(reader)?;
+				let attempts_len: u64 = Readable::read(reader)?;
+				let mut attempts = Vec::new();
+				for _ in 0..attempts_len {
+					attempts.push(Readable::read(reader)?);
+				}
+				Ok(Some(Event::PaymentResolved { payment_hash, payment_preimage, attempts }))
+			},
+			8u8 => Ok(Some(Event::FundingTransactionReadyForSigning {
+					channel_id: Readable::read(reader)?,
+					user_channel_id: Readable::read(reader)?,
+					unsigned_transaction: Readable::read(reader)?,
+				})),
+			9u8 => Ok(Some(Event::PaymentForwarded {
+					prev_channel_id: Readable::read(reader)?,
+					next_channel_id: Readable::read(reader)?,
+					amt_in_msat: Readable::read(reader)?,
+					amt_out_msat: Readable::read(reader)?,
+					fee_earned_msat: Readable::read(reader)?,
+					claim_from_onchain_tx: Readable::read(reader)?,
+				})),
+			10u8 => Ok(Some(Event::ChannelClosed {
+					channel_id: Readable::read(reader)?,
+					user_id: Readable::read(reader)?,
+					reason: Readable::read(reader)?,
+				})),
+			11u8 => Ok(Some(Event::HTLCIntercepted {
+					intercept_id: Readable::read(reader)?,
+					requested_next_hop_scid: Readable::read(reader)?,
+					payment_hash: Readable::read(reader)?,
+					inbound_amount_msat: Readable::read(reader)?,
+					expected_outbound_amount_msat: Readable::read(reader)?,
+					outgoing_cltv_value: Readable::read(reader)?,
+				})),
 			_ => Err(msgs::DecodeError::InvalidValue)
 		}
 	}
 }
 
+impl Event {
+	/// Reads an Event written by a version 1 ChannelManager, which didn't include the fields since
+	/// added to some events.
+	pub(crate) fn read_legacy<R: ::std::io::Read>(reader: &mut R) -> Result<Option<Self>, msgs::DecodeError> {
+		match Readable::read(reader)? {
+			2u8 => Ok(Some(Event::PaymentReceived {
+					payment_hash: Readable::read(reader)?,
+					payment_secret: Readable::read(reader)?,
+					payment_preimage: None,
+					amt: Readable::read(reader)?,
+				})),
+			3u8 => Ok(Some(Event::PaymentSent {
+					payment_preimage: Readable::read(reader)?,
+					path: Vec::new(),
+				})),
+			4u8 => Ok(Some(Event::PaymentFailed {
+					payment_hash: Readable::read(reader)?,
+					rejected_by_dest: Readable::read(reader)?,
+					path: Vec::new(),
+					network_update: None,
+					failure: None,
+				})),
+			event_type => MaybeReadable::read(&mut ::std::io::Read::chain(&[event_type][..], reader)),
+		}
+	}
+}
+
+/// The reason a channel was closed, provided in Event::ChannelClosed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClosureReason {
+	/// Our counterparty sent us an error message, after which we broadcasted our latest commitment
+	/// transaction.
+	CounterpartyForceClosed {
+		/// The error message our counterparty sent us. Note that this is untrusted data and should
+		/// be sanitized before being logged or displayed.
+		peer_msg: String,
+	},
+	/// We force-closed the channel via ChannelManager::force_close_channel.
+	HolderForceClosed,
+	/// Both sides agreed to close the channel, and either the closing transaction has been
+	/// broadcast or the channel was never funded. The shutdown may have been initiated by either
+	/// side.
+	CooperativeClosure,
+	/// A transaction spending the funding output, most likely a commitment transaction broadcast
+	/// by our counterparty, was confirmed on-chain.
+	CommitmentTxConfirmed,
+	/// The funding transaction of an inbound channel didn't confirm within
+	/// FUNDING_CONF_DEADLINE_BLOCKS blocks of our receiving open_channel.
+	FundingTimedOut,
+	/// We hit an error processing a message or event for the channel which required closing it.
+	ProcessingError {
+		/// A developer-readable description of the error.
+		err: String,
+	},
+	/// We disconnected from our counterparty and either were told we're unlikely to be able to
+	/// reconnect to them or the channel's funding hadn't yet been negotiated, in which case it
+	/// can't be resumed.
+	DisconnectedPeer,
+}
+
+impl Writeable for ClosureReason {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		match self {
+			&ClosureReason::CounterpartyForceClosed { ref peer_msg } => {
+				0u8.write(writer)?;
+				peer_msg.as_bytes().to_vec().write(writer)?;
+			},
+			&ClosureReason::HolderForceClosed => 1u8.write(writer)?,
+			&ClosureReason::CooperativeClosure => 2u8.write(writer)?,
+			&ClosureReason::CommitmentTxConfirmed => 3u8.write(writer)?,
+			&ClosureReason::FundingTimedOut => 4u8.write(writer)?,
+			&ClosureReason::ProcessingError { ref err } => {
+				5u8.write(writer)?;
+				err.as_bytes().to_vec().write(writer)?;
+			},
+			&ClosureReason::DisconnectedPeer => 6u8.write(writer)?,
+		}
+		Ok(())
+	}
+}
+impl Readable for ClosureReason {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, msgs::DecodeError> {
+		macro_rules! read_string { () => {
+			String::from_utf8(Readable::read(reader)?).map_err(|_| msgs::DecodeError::InvalidValue)?
+		} }
+		match <u8 as Readable>::read(reader)? {
+			0 => Ok(ClosureReason::CounterpartyForceClosed { peer_msg: read_string!() }),
+			1 => Ok(ClosureReason::HolderForceClosed),
+			2 => Ok(ClosureReason::CooperativeClosure),
+			3 => Ok(ClosureReason::CommitmentTxConfirmed),
+			4 => Ok(ClosureReason::FundingTimedOut),
+			5 => Ok(ClosureReason::ProcessingError { err: read_string!() }),
+			6 => Ok(ClosureReason::DisconnectedPeer),
+			_ => Err(msgs::DecodeError::InvalidValue),
+		}
+	}
+}
+
+/// The decoded failure for an HTLC we sent, provided in Event::PaymentFailed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaymentFailureDetails {
+	/// The node which failed the HTLC. This is None if the HTLC was failed by us, or by our direct
+	/// counterparty without an onion-encrypted failure (eg via update_fail_malformed_htlc).
+	pub failing_node_id: Option<PublicKey>,
+	/// The short_channel_id of the channel the failing node was asked to forward the HTLC over,
+	/// or None if the failing node was the recipient. If failing_node_id is None this is our own
+	/// channel with the first hop.
+	pub short_channel_id: Option<u64>,
+	/// The BOLT 4 failure code.
+	pub failure_code: OnionFailureCode,
+	/// The data which accompanied the failure code, eg the channel_update for update failures or
+	/// the amount the recipient received for final_incorrect_htlc_amount.
+	pub failure_data: Vec<u8>,
+	/// The channel_update the failing node included with the failure, if any. Note that this has
+	/// not been checked against the network graph.
+	pub channel_update: Option<msgs::ChannelUpdate>,
+}
+
+impl PaymentFailureDetails {
+	/// Whether the failure is permanent, ie retrying over the same path is not expected to succeed.
+	pub fn is_permanent(&self) -> bool {
+		self.failure_code.is_permanent()
+	}
+}
+
+impl Writeable for PaymentFailureDetails {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		self.failing_node_id.write(writer)?;
+		self.short_channel_id.write(writer)?;
+		self.failure_code.to_u16().write(writer)?;
+		self.failure_data.write(writer)?;
+		self.channel_update.write(writer)?;
+		Ok(())
+	}
+}
+impl Readable for PaymentFailureDetails {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, msgs::DecodeError> {
+		Ok(PaymentFailureDetails {
+			failing_node_id: Readable::read(reader)?,
+			short_channel_id: Readable::read(reader)?,
+			failure_code: OnionFailureCode::from_u16(Readable::read(reader)?),
+			failure_data: Readable::read(reader)?,
+			channel_update: Readable::read(reader)?,
+		})
+	}
+}
+
 /// An event generated by ChannelManager which indicates a message should be sent to a peer (or
 /// broadcast to most peers).
 /// These events are handled by PeerManager::process_events if you are using a PeerManager.
@@ -236,6 +613,78 @@ pub enum MessageSendEvent {
 		/// The message which should be sent.
 		msg: msgs::OpenChannel,
 	},
+	/// Used to indicate that we've initiated a dual-funded channel open and should send the
+	/// open_channel2 message provided to the given peer.
+	SendOpenChannelV2 {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::OpenChannelV2,
+	},
+	/// Used to indicate that we've accepted a dual-funded channel open and should send the
+	/// accept_channel2 message provided to the given peer.
+	SendAcceptChannelV2 {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::AcceptChannelV2,
+	},
+	/// Used to indicate that a tx_add_input message should be sent to the peer with the given node_id.
+	SendTxAddInput {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxAddInput,
+	},
+	/// Used to indicate that a tx_add_output message should be sent to the peer with the given node_id.
+	SendTxAddOutput {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxAddOutput,
+	},
+	/// Used to indicate that a tx_complete message should be sent to the peer with the given node_id.
+	SendTxComplete {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxComplete,
+	},
+	/// Used to indicate that a tx_signatures message should be sent to the peer with the given node_id.
+	SendTxSignatures {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxSignatures,
+	},
+	/// Used to indicate that a tx_abort message should be sent to the peer with the given node_id.
+	SendTxAbort {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxAbort,
+	},
+	/// Used to indicate that a splice_init message should be sent to the peer with the given node_id.
+	SendSpliceInit {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::SpliceInit,
+	},
+	/// Used to indicate that a splice_ack message should be sent to the peer with the given node_id.
+	SendSpliceAck {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::SpliceAck,
+	},
+	/// Used to indicate that a splice_locked message should be sent to the peer with the given node_id.
+	SendSpliceLocked {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::SpliceLocked,
+	},
 	/// Used to indicate that a funding_created message should be sent to the peer with the given node_id.
 	SendFundingCreated {
 		/// The node_id of the node which should receive this message
@@ -336,7 +785,53 @@ pub enum MessageSendEvent {
 	PaymentFailureNetworkUpdate {
 		/// The channel/node update which should be sent to NetGraphMsgHandler
 		update: msgs::HTLCFailChannelUpdate,
-	}
+	},
+	/// Used to indicate that a query_channel_range message should be sent to the peer with the
+	/// given node_id.
+	SendChannelRangeQuery {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The query_channel_range which should be sent.
+		msg: msgs::QueryChannelRange,
+	},
+	/// Used to indicate that a reply_channel_range message should be sent to the peer with the
+	/// given node_id.
+	SendReplyChannelRange {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The reply_channel_range which should be sent.
+		msg: msgs::ReplyChannelRange,
+	},
+	/// Used to indicate that a query_short_channel_ids message should be sent to the peer with the
+	/// given node_id.
+	SendShortIdsQuery {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The query_short_channel_ids which should be sent.
+		msg: msgs::QueryShortChannelIds,
+	},
+	/// Used to indicate that the gossip requested in a query_short_channel_ids, followed by a
+	/// reply_short_channel_ids_end message, should be sent to the peer with the given node_id.
+	SendShortIdsReply {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The channel_announcements (each followed by its channel_updates, if any) which should
+		/// be sent.
+		channel_announcements: Vec<(msgs::ChannelAnnouncement, Option<msgs::ChannelUpdate>, Option<msgs::ChannelUpdate>)>,
+		/// The node_announcements which should be sent after the channel_announcements.
+		node_announcements: Vec<msgs::NodeAnnouncement>,
+		/// Finally, the reply_short_channel_ids_end which should be sent. ALL of the above
+		/// messages must be sent before it!
+		msg: msgs::ReplyShortChannelIdsEnd,
+	},
+	/// Used to indicate that a gossip_timestamp_filter message should be sent to the peer with
+	/// the given node_id.
+	SendGossipTimestampFilter {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The gossip_timestamp_filter which should be sent.
+		msg: msgs::GossipTimestampFilter,
+	},
 }
 
 /// A trait indicating an object may generate message send events
@@ -94,17 +94,17 @@ impl<'a> std::fmt::Display for DebugTx<'a> {
 				write!(f, "commitment tx")?;
 			} else if self.0.input.len() == 1 && self.0.input[0].witness.last().unwrap().len() == 71 {
 				write!(f, "closing tx")?;
-			} else if self.0.input.len() == 1 && HTLCType::scriptlen_to_htlctype(self.0.input[0].witness.last().unwrap().len()) == Some(HTLCType::OfferedHTLC) &&
+			} else if self.0.input.len() == 1 && HTLCType::witness_script_to_htlctype(self.0.input[0].witness.last().unwrap()) == Some(HTLCType::OfferedHTLC) &&
 					self.0.input[0].witness.len() == 5 {
 				write!(f, "HTLC-timeout tx")?;
-			} else if self.0.input.len() == 1 && HTLCType::scriptlen_to_htlctype(self.0.input[0].witness.last().unwrap().len()) == Some(HTLCType::AcceptedHTLC) &&
+			} else if self.0.input.len() == 1 && HTLCType::witness_script_to_htlctype(self.0.input[0].witness.last().unwrap()) == Some(HTLCType::AcceptedHTLC) &&
 					self.0.input[0].witness.len() == 5 {
 				write!(f, "HTLC-success tx")?;
 			} else {
 				for inp in &self.0.input {
 					if !inp.witness.is_empty() {
-						if HTLCType::scriptlen_to_htlctype(inp.witness.last().unwrap().len()) == Some(HTLCType::OfferedHTLC) { write!(f, "preimage-")?; break }
-						else if HTLCType::scriptlen_to_htlctype(inp.witness.last().unwrap().len()) == Some(HTLCType::AcceptedHTLC) { write!(f, "timeout-")?; break }
+						if HTLCType::witness_script_to_htlctype(inp.witness.last().unwrap()) == Some(HTLCType::OfferedHTLC) { write!(f, "preimage-")?; break }
+						else if HTLCType::witness_script_to_htlctype(inp.witness.last().unwrap()) == Some(HTLCType::AcceptedHTLC) { write!(f, "timeout-")?; break }
 					}
 				}
 				write!(f, "tx")?;
@@ -135,6 +135,9 @@ impl<'a> std::fmt::Display for DebugSpendable<'a> {
 			&SpendableOutputDescriptor::StaticOutputRemotePayment { ref outpoint, .. } => {
 				write!(f, "DynamicOutputP2WPKH {}:{} marked for spending", outpoint.txid, outpoint.vout)?;
 			}
+			&SpendableOutputDescriptor::StaticOutputRemotePaymentWithAnchors { ref outpoint, .. } => {
+				write!(f, "StaticOutputRemotePaymentWithAnchors {}:{} marked for spending", outpoint.txid, outpoint.vout)?;
+			}
 		}
 		Ok(())
 	}
@@ -17,7 +17,7 @@ use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 use bitcoin::hash_types::{Txid, BlockHash};
 use std::marker::Sized;
 use ln::msgs::DecodeError;
-use ln::channelmanager::{PaymentPreimage, PaymentHash, PaymentSecret};
+use ln::channelmanager::{PaymentPreimage, PaymentHash, PaymentSecret, InterceptId};
 use util::byte_utils;
 
 use util::byte_utils::{be64_to_array, be48_to_array, be32_to_array, be16_to_array, slice_to_be16, slice_to_be32, slice_to_be48, slice_to_be64};
@@ -604,6 +604,19 @@ impl Readable for PaymentSecret {
 	}
 }
 
+impl Writeable for InterceptId {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		self.0.write(w)
+	}
+}
+
+impl Readable for InterceptId {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let buf: [u8; 32] = Readable::read(r)?;
+		Ok(InterceptId(buf))
+	}
+}
+
 impl<T: Writeable> Writeable for Option<T> {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
 		match *self {
@@ -632,6 +645,19 @@ impl<T: Readable> Readable for Option<T>
 	}
 }
 
+impl<P, T: ReadableArgs<P>> ReadableArgs<P> for Option<T>
+{
+	fn read<R: Read>(r: &mut R, params: P) -> Result<Self, DecodeError> {
+		match BigSize::read(r)?.0 {
+			0 => Ok(None),
+			len => {
+				let mut reader = FixedLengthReader::new(r, len - 1);
+				Ok(Some(ReadableArgs::read(&mut reader, params)?))
+			}
+		}
+	}
+}
+
 impl Writeable for Txid {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
 		w.write_all(&self[..])
@@ -6,6 +6,8 @@ use ln::channelmonitor;
 use ln::features::{ChannelFeatures, InitFeatures};
 use ln::msgs;
 use ln::channelmonitor::HTLCUpdate;
+use routing::router::RouteHop;
+use routing::scorer::Score;
 use util::enforcing_trait_impls::EnforcingChannelKeys;
 use util::events;
 use util::logger::{Logger, Level, Record};
@@ -13,7 +15,8 @@ use util::ser::{Readable, Writer, Writeable};
 
 use bitcoin::BitcoinHash;
 use bitcoin::blockdata::constants::genesis_block;
-use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::blockdata::transaction::{Transaction, TxOut};
+use bitcoin::blockdata::transaction::OutPoint as BitcoinOutPoint;
 use bitcoin::blockdata::script::{Builder, Script};
 use bitcoin::blockdata::block::Block;
 use bitcoin::blockdata::opcodes;
@@ -23,7 +26,7 @@ use bitcoin::hash_types::{Txid, BlockHash};
 use bitcoin::secp256k1::{SecretKey, PublicKey, Secp256k1, Signature};
 
 use std::time::Duration;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::{cmp, mem};
 use std::collections::HashMap;
@@ -48,10 +51,55 @@ impl chaininterface::FeeEstimator for TestFeeEstimator {
 	}
 }
 
+/// A UtxoPool which spends a single large OP_TRUE P2WSH output of a fake wallet transaction.
+pub struct TestUtxoPool {
+	pub wallet_tx: Transaction,
+	pub selections: AtomicUsize,
+	/// If set, no UTXOs are returned, as if the wallet were empty.
+	pub empty: AtomicBool,
+}
+impl TestUtxoPool {
+	pub fn new() -> Self {
+		let op_true_script = Builder::new().push_opcode(opcodes::OP_TRUE).into_script();
+		Self {
+			wallet_tx: Transaction { version: 2, lock_time: 0, input: Vec::new(), output: vec![TxOut {
+				script_pubkey: op_true_script.to_v0_p2wsh(), value: 100_000_000,
+			}]},
+			selections: AtomicUsize::new(0),
+			empty: AtomicBool::new(false),
+		}
+	}
+}
+impl chaininterface::UtxoPool for TestUtxoPool {
+	fn select_utxos(&self, target_value_sat: u64) -> Vec<chaininterface::Utxo> {
+		self.selections.fetch_add(1, Ordering::AcqRel);
+		if self.empty.load(Ordering::Acquire) || target_value_sat > self.wallet_tx.output[0].value { return Vec::new(); }
+		vec![chaininterface::Utxo {
+			outpoint: BitcoinOutPoint { txid: self.wallet_tx.txid(), vout: 0 },
+			output: self.wallet_tx.output[0].clone(),
+			// number_of_witness_elements + witness_script_length + witness_script
+			satisfaction_weight: 1 + 1 + 1,
+		}]
+	}
+	fn get_change_script(&self) -> Script {
+		Builder::new().push_opcode(opcodes::OP_TRUE).into_script().to_v0_p2wsh()
+	}
+	fn sign_tx(&self, tx: &mut Transaction) -> Result<(), ()> {
+		let wallet_txid = self.wallet_tx.txid();
+		for input in tx.input.iter_mut() {
+			if input.previous_output.txid == wallet_txid {
+				input.witness = vec![Builder::new().push_opcode(opcodes::OP_TRUE).into_script().into_bytes()];
+			}
+		}
+		Ok(())
+	}
+}
+
 pub struct TestChannelMonitor<'a> {
 	pub added_monitors: Mutex<Vec<(OutPoint, channelmonitor::ChannelMonitor<EnforcingChannelKeys>)>>,
 	pub latest_monitor_update_id: Mutex<HashMap<[u8; 32], (OutPoint, u64)>>,
-	pub simple_monitor: channelmonitor::SimpleManyChannelMonitor<OutPoint, EnforcingChannelKeys, &'a chaininterface::BroadcasterInterface, &'a TestFeeEstimator, &'a TestLogger, &'a ChainWatchInterface>,
+	pub simple_monitor: channelmonitor::SimpleManyChannelMonitor<OutPoint, EnforcingChannelKeys, &'a chaininterface::BroadcasterInterface, &'a TestFeeEstimator, &'a TestLogger, &'a ChainWatchInterface, Arc<TestUtxoPool>>,
+	pub utxo_pool: Arc<TestUtxoPool>,
 	pub update_ret: Mutex<Result<(), channelmonitor::ChannelMonitorUpdateErr>>,
 	// If this is set to Some(), after the next return, we'll always return this until update_ret
 	// is changed:
@@ -59,10 +107,12 @@ pub struct TestChannelMonitor<'a> {
 }
 impl<'a> TestChannelMonitor<'a> {
 	pub fn new(chain_monitor: &'a chaininterface::ChainWatchInterface, broadcaster: &'a chaininterface::BroadcasterInterface, logger: &'a TestLogger, fee_estimator: &'a TestFeeEstimator) -> Self {
+		let utxo_pool = Arc::new(TestUtxoPool::new());
 		Self {
 			added_monitors: Mutex::new(Vec::new()),
 			latest_monitor_update_id: Mutex::new(HashMap::new()),
-			simple_monitor: channelmonitor::SimpleManyChannelMonitor::new(chain_monitor, broadcaster, logger, fee_estimator),
+			simple_monitor: channelmonitor::SimpleManyChannelMonitor::new(chain_monitor, broadcaster, logger, fee_estimator, utxo_pool.clone()),
+			utxo_pool,
 			update_ret: Mutex::new(Ok(())),
 			next_update_ret: Mutex::new(None),
 		}
@@ -98,11 +148,13 @@ impl<'a> channelmonitor::ManyChannelMonitor for TestChannelMonitor<'a> {
 				&mut ::std::io::Cursor::new(&w.0)).unwrap() == update);
 
 		self.latest_monitor_update_id.lock().unwrap().insert(funding_txo.to_channel_id(), (funding_txo, update.update_id));
+		let spliced_funding_txo = self.simple_monitor.monitors.lock().unwrap().get(&funding_txo).and_then(|monitor| monitor.get_spliced_funding_txo());
 		assert!(self.simple_monitor.update_monitor(funding_txo, update).is_ok());
 		// At every point where we get a monitor update, we should be able to send a useful monitor
 		// to a watchtower and disk...
 		let monitors = self.simple_monitor.monitors.lock().unwrap();
-		let monitor = monitors.get(&funding_txo).unwrap();
+		// Once a splice is locked, the monitor is keyed by the spliced funding outpoint instead.
+		let monitor = monitors.get(&funding_txo).or_else(|| monitors.get(&spliced_funding_txo.unwrap())).unwrap();
 		w.0.clear();
 		monitor.write_for_disk(&mut w).unwrap();
 		let new_monitor = <(BlockHash, channelmonitor::ChannelMonitor<EnforcingChannelKeys>)>::read(
@@ -149,6 +201,16 @@ impl msgs::ChannelMessageHandler for TestChannelMessageHandler {
 	fn handle_funding_created(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingCreated) {}
 	fn handle_funding_signed(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingSigned) {}
 	fn handle_funding_locked(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingLocked) {}
+	fn handle_open_channel_v2(&self, _their_node_id: &PublicKey, _their_features: InitFeatures, _msg: &msgs::OpenChannelV2) {}
+	fn handle_accept_channel_v2(&self, _their_node_id: &PublicKey, _their_features: InitFeatures, _msg: &msgs::AcceptChannelV2) {}
+	fn handle_tx_add_input(&self, _their_node_id: &PublicKey, _msg: &msgs::TxAddInput) {}
+	fn handle_tx_add_output(&self, _their_node_id: &PublicKey, _msg: &msgs::TxAddOutput) {}
+	fn handle_tx_complete(&self, _their_node_id: &PublicKey, _msg: &msgs::TxComplete) {}
+	fn handle_tx_signatures(&self, _their_node_id: &PublicKey, _msg: &msgs::TxSignatures) {}
+	fn handle_tx_abort(&self, _their_node_id: &PublicKey, _msg: &msgs::TxAbort) {}
+	fn handle_splice_init(&self, _their_node_id: &PublicKey, _msg: &msgs::SpliceInit) {}
+	fn handle_splice_ack(&self, _their_node_id: &PublicKey, _msg: &msgs::SpliceAck) {}
+	fn handle_splice_locked(&self, _their_node_id: &PublicKey, _msg: &msgs::SpliceLocked) {}
 	fn handle_shutdown(&self, _their_node_id: &PublicKey, _msg: &msgs::Shutdown) {}
 	fn handle_closing_signed(&self, _their_node_id: &PublicKey, _msg: &msgs::ClosingSigned) {}
 	fn handle_update_add_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateAddHTLC) {}
@@ -161,6 +223,7 @@ impl msgs::ChannelMessageHandler for TestChannelMessageHandler {
 	fn handle_announcement_signatures(&self, _their_node_id: &PublicKey, _msg: &msgs::AnnouncementSignatures) {}
 	fn handle_channel_reestablish(&self, _their_node_id: &PublicKey, _msg: &msgs::ChannelReestablish) {}
 	fn peer_disconnected(&self, _their_node_id: &PublicKey, _no_connection_possible: bool) {}
+	fn provided_init_features(&self) -> InitFeatures { InitFeatures::known() }
 	fn peer_connected(&self, _their_node_id: &PublicKey, _msg: &msgs::Init) {}
 	fn handle_error(&self, _their_node_id: &PublicKey, _msg: &msgs::ErrorMessage) {}
 }
@@ -216,6 +279,7 @@ fn get_dummy_channel_update(short_chan_id: u64) -> msgs::ChannelUpdate {
 			htlc_minimum_msat: 0,
 			fee_base_msat: 0,
 			fee_proportional_millionths: 0,
+			htlc_maximum_msat: msgs::OptionalField::Absent,
 			excess_data: vec![],
 		}
 	}
@@ -226,6 +290,7 @@ pub struct TestRoutingMessageHandler {
 	pub chan_anns_recvd: AtomicUsize,
 	pub chan_anns_sent: AtomicUsize,
 	pub request_full_sync: AtomicBool,
+	pub pending_events: Mutex<Vec<events::MessageSendEvent>>,
 }
 
 impl TestRoutingMessageHandler {
@@ -235,6 +300,7 @@ impl TestRoutingMessageHandler {
 			chan_anns_recvd: AtomicUsize::new(0),
 			chan_anns_sent: AtomicUsize::new(0),
 			request_full_sync: AtomicBool::new(false),
+			pending_events: Mutex::new(Vec::new()),
 		}
 	}
 }
@@ -274,6 +340,64 @@ impl msgs::RoutingMessageHandler for TestRoutingMessageHandler {
 	fn should_request_full_sync(&self, _node_id: &PublicKey) -> bool {
 		self.request_full_sync.load(Ordering::Acquire)
 	}
+
+	fn sync_routing_table(&self, their_node_id: &PublicKey, init_msg: &msgs::Init) {
+		if !init_msg.features.supports_gossip_queries() {
+			return;
+		}
+		// Request all historical gossip via the timestamp filter if we want a full sync.
+		let first_timestamp = if self.request_full_sync.load(Ordering::Acquire) { 0 } else { 0xffff_ffff };
+		self.pending_events.lock().unwrap().push(events::MessageSendEvent::SendGossipTimestampFilter {
+			node_id: their_node_id.clone(),
+			msg: msgs::GossipTimestampFilter {
+				chain_hash: genesis_block(Network::Testnet).header.bitcoin_hash(),
+				first_timestamp,
+				timestamp_range: 0xffff_ffff,
+			},
+		});
+	}
+
+	fn peer_disconnected(&self, _their_node_id: &PublicKey) {}
+
+	fn handle_reply_channel_range(&self, _their_node_id: &PublicKey, _msg: &msgs::ReplyChannelRange) -> Result<(), msgs::LightningError> {
+		Ok(())
+	}
+
+	fn handle_reply_short_channel_ids_end(&self, _their_node_id: &PublicKey, _msg: &msgs::ReplyShortChannelIdsEnd) -> Result<(), msgs::LightningError> {
+		Ok(())
+	}
+
+	fn handle_query_channel_range(&self, _their_node_id: &PublicKey, _msg: &msgs::QueryChannelRange) -> Result<(), msgs::LightningError> {
+		Ok(())
+	}
+
+	fn handle_query_short_channel_ids(&self, _their_node_id: &PublicKey, _msg: &msgs::QueryShortChannelIds) -> Result<(), msgs::LightningError> {
+		Ok(())
+	}
+}
+
+impl events::MessageSendEventsProvider for TestRoutingMessageHandler {
+	fn get_and_clear_pending_msg_events(&self) -> Vec<events::MessageSendEvent> {
+		let mut ret = Vec::new();
+		let mut pending_events = self.pending_events.lock().unwrap();
+		mem::swap(&mut ret, &mut pending_events);
+		ret
+	}
+}
+
+/// A Score which assigns no penalties, so routes are selected on fees alone.
+pub struct TestScorer {}
+
+impl TestScorer {
+	pub fn new() -> TestScorer {
+		TestScorer {}
+	}
+}
+
+impl Score for TestScorer {
+	fn channel_penalty_msat(&self, _short_channel_id: u64, _source: &PublicKey, _target: &PublicKey) -> u64 { 0 }
+	fn payment_path_failed(&self, _path: &[RouteHop], _network_update: Option<&msgs::HTLCFailChannelUpdate>) {}
+	fn payment_path_successful(&self, _path: &[RouteHop]) {}
 }
 
 pub struct TestLogger {
@@ -340,6 +464,9 @@ impl keysinterface::KeysInterface for TestKeysInterface {
 			None => self.backing.get_channel_id()
 		}
 	}
+
+	fn get_secure_random_bytes(&self) -> [u8; 32] { self.backing.get_secure_random_bytes() }
+	fn get_inbound_payment_key_material(&self) -> [u8; 32] { self.backing.get_inbound_payment_key_material() }
 }
 
 impl TestKeysInterface {
@@ -357,26 +484,29 @@ impl TestKeysInterface {
 }
 
 pub struct TestChainWatcher {
-	pub utxo_ret: Mutex<Result<(Script, u64), ChainError>>,
+	pub utxo_ret: Mutex<Result<(Txid, Script, u64), ChainError>>,
+	pub watched_outpoints: Mutex<Vec<(Txid, u32)>>,
 }
 
 impl TestChainWatcher {
 	pub fn new() -> Self {
 		let script = Builder::new().push_opcode(opcodes::OP_TRUE).into_script();
-		Self { utxo_ret: Mutex::new(Ok((script, u64::max_value()))) }
+		Self { utxo_ret: Mutex::new(Ok((Txid::default(), script, u64::max_value()))), watched_outpoints: Mutex::new(Vec::new()) }
 	}
 }
 
 impl ChainWatchInterface for TestChainWatcher {
 	fn install_watch_tx(&self, _txid: &Txid, _script_pub_key: &Script) { }
-	fn install_watch_outpoint(&self, _outpoint: (Txid, u32), _out_script: &Script) { }
+	fn install_watch_outpoint(&self, outpoint: (Txid, u32), _out_script: &Script) {
+		self.watched_outpoints.lock().unwrap().push(outpoint);
+	}
 	fn watch_all_txn(&self) { }
 	fn filter_block<'a>(&self, _block: &'a Block) -> Vec<usize> {
 		Vec::new()
 	}
 	fn reentered(&self) -> usize { 0 }
 
-	fn get_chain_utxo(&self, _genesis_hash: BlockHash, _unspent_tx_output_identifier: u64) -> Result<(Script, u64), ChainError> {
+	fn get_chain_utxo(&self, _genesis_hash: BlockHash, _unspent_tx_output_identifier: u64) -> Result<(Txid, Script, u64), ChainError> {
 		self.utxo_ret.lock().unwrap().clone()
 	}
 }